@@ -0,0 +1,64 @@
+//! What registries advertise at `/v1/api-info` about how clients
+//! authenticate, used to tell people how to get credentials when they're
+//! missing or refused.
+
+use reqwest::Url;
+use serde::Deserialize;
+
+use crate::http_client;
+
+#[derive(Deserialize)]
+struct ApiInfo {
+    auth: ApiInfoAuth,
+}
+
+#[derive(Deserialize)]
+struct ApiInfoAuth {
+    /// Registries from before reads could need authentication don't say how
+    /// readers authenticate.
+    #[serde(default)]
+    read: Option<ApiInfoAuthMode>,
+    write: ApiInfoAuthMode,
+}
+
+/// How a registry says clients authenticate to read from or write to it.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub(crate) enum ApiInfoAuthMode {
+    ApiKey {},
+    DoubleApiKey {},
+    #[serde(rename_all = "kebab-case")]
+    GithubOauth {
+        client_id: String,
+    },
+    #[serde(rename_all = "kebab-case")]
+    GithubOauthPrivate {
+        client_id: String,
+    },
+    Gitlab {},
+    Unauthenticated {},
+    #[serde(other)]
+    Other,
+}
+
+fn advertised_auth(api: &Url) -> Option<ApiInfoAuth> {
+    let client = http_client::blocking_client().ok()?;
+    let response = client.get(api.join("/v1/api-info").ok()?).send().ok()?;
+
+    if !response.status().is_success() {
+        return None;
+    }
+
+    Some(response.json::<ApiInfo>().ok()?.auth)
+}
+
+/// The write auth mode the registry at `api` advertises, if it can be read.
+/// Older registries don't have `/v1/api-info`.
+pub(crate) fn advertised_write_auth(api: &Url) -> Option<ApiInfoAuthMode> {
+    Some(advertised_auth(api)?.write)
+}
+
+/// The read auth mode the registry at `api` advertises, if it can be read.
+pub(crate) fn advertised_read_auth(api: &Url) -> Option<ApiInfoAuthMode> {
+    advertised_auth(api)?.read
+}
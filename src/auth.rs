@@ -1,13 +1,21 @@
 //! Defines storage of authentication information when interacting with
 //! registries.
+//!
+//! Tokens are keyed by the API URL of the registry they're for. When Wally is
+//! built with the `keychain` feature, tokens are kept in the OS keychain if
+//! there is one. Otherwise they're kept in `~/.wally/auth.toml`, which only
+//! the current user can read. A token can also be given in an environment
+//! variable named after the registry's API host, which wins over a stored one.
 
 use std::collections::HashMap;
+use std::env;
 use std::io;
 use std::path::{Path, PathBuf};
 
 use anyhow::Context;
 use serde::{Deserialize, Serialize};
 use toml_edit::{table, value, Document, Item};
+use url::Url;
 
 const DEFAULT_AUTH_TOML: &str = r#"
 # This is where Wally stores details for authenticating with registries.
@@ -37,16 +45,48 @@ impl AuthStore {
         Ok(auth)
     }
 
-    /// Simplifies the usecase of AuthStore::load()?.tokens.get(key)
-    /// If multiple tokens are needed you should use AuthStore::load()?.tokens instead
+    /// The token for a registry, from its environment variable if that's
+    /// set, then from the keychain if it's there, and from the auth file
+    /// otherwise.
     pub fn get_token(key: &str) -> anyhow::Result<Option<String>> {
+        if let Some(token) = env::var(token_env_var(key))
+            .ok()
+            .filter(|token| !token.is_empty())
+        {
+            return Ok(Some(token));
+        }
+
+        if let Some(token) = keychain::get(key) {
+            return Ok(Some(token));
+        }
+
         // As this auth store will only live as long as this function we can just remove the value
         // to give ownership to whatever needs it
         Ok(Self::load()?.tokens.remove(key))
     }
 
+    /// Stores the token for a registry, or removes it if `token` is `None`.
+    ///
+    /// A token that makes it into the keychain is removed from the auth file,
+    /// so that there's never a stale copy left behind in plain text.
     pub fn set_token(key: &str, token: Option<&str>) -> anyhow::Result<()> {
+        match token {
+            Some(token) if keychain::set(key, token) => Self::set_file_token(key, None),
+            Some(token) => Self::set_file_token(key, Some(token)),
+            None => {
+                keychain::delete(key);
+                Self::set_file_token(key, None)
+            }
+        }
+    }
+
+    fn set_file_token(key: &str, token: Option<&str>) -> anyhow::Result<()> {
         let path = file_path()?;
+
+        if token.is_none() && !path.exists() {
+            return Ok(());
+        }
+
         let contents = Self::contents(&path)?;
 
         let mut auth: Document = contents.parse().unwrap();
@@ -64,7 +104,7 @@ impl AuthStore {
         }
 
         fs_err::create_dir_all(path.parent().unwrap())?;
-        fs_err::write(&path, auth.to_string())?;
+        write_private(&path, &auth.to_string())?;
 
         Ok(())
     }
@@ -83,9 +123,129 @@ impl AuthStore {
     }
 }
 
+/// The environment variable that can hold the token for the registry whose API
+/// is at `api`, like `WALLY_TOKEN_API_EXAMPLE_COM` for `https://api.example.com`.
+/// It's named after the host, and port if there is one, so that a token meant
+/// for one registry is never sent to another.
+pub fn token_env_var(api: &str) -> String {
+    let host = match Url::parse(api) {
+        Ok(url) => match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}_{}", host, port),
+            (Some(host), None) => host.to_owned(),
+            (None, _) => api.to_owned(),
+        },
+        Err(_) => api.to_owned(),
+    };
+
+    let host: String = host
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect();
+
+    format!("WALLY_TOKEN_{}", host)
+}
+
+/// Writes the auth file so that only the current user can read it, including
+/// when it was created by an older version of Wally that didn't.
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &str) -> anyhow::Result<()> {
+    use std::fs::{OpenOptions, Permissions};
+    use std::io::Write;
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+
+    file.set_permissions(Permissions::from_mode(0o600))?;
+    file.write_all(contents.as_bytes())?;
+
+    Ok(())
+}
+
+/// On Windows, files in the user's home directory are already private to them.
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &str) -> anyhow::Result<()> {
+    fs_err::write(path, contents)?;
+    Ok(())
+}
+
+#[cfg(feature = "keychain")]
+mod keychain {
+    const SERVICE: &str = "wally";
+
+    fn entry(key: &str) -> Option<keyring::Entry> {
+        keyring::Entry::new(SERVICE, key).ok()
+    }
+
+    pub fn get(key: &str) -> Option<String> {
+        entry(key)?.get_password().ok()
+    }
+
+    /// Returns whether the token was stored. Machines without a keychain, like
+    /// most CI runners, fall back to the auth file.
+    pub fn set(key: &str, token: &str) -> bool {
+        match entry(key).map(|entry| entry.set_password(token)) {
+            Some(Ok(())) => true,
+            Some(Err(err)) => {
+                log::warn!("Could not store token in the keychain: {}", err);
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub fn delete(key: &str) {
+        if let Some(entry) = entry(key) {
+            entry.delete_password().ok();
+        }
+    }
+}
+
+#[cfg(not(feature = "keychain"))]
+mod keychain {
+    pub fn get(_key: &str) -> Option<String> {
+        None
+    }
+
+    pub fn set(_key: &str, _token: &str) -> bool {
+        false
+    }
+
+    pub fn delete(_key: &str) {}
+}
+
 fn file_path() -> anyhow::Result<PathBuf> {
     let mut path = dirs::home_dir().context("Failed to find home directory")?;
     path.push(".wally");
     path.push("auth.toml");
     Ok(path)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn token_env_vars() {
+        assert_eq!(
+            token_env_var("https://api.example.com/"),
+            "WALLY_TOKEN_API_EXAMPLE_COM"
+        );
+        assert_eq!(
+            token_env_var("http://localhost:8000"),
+            "WALLY_TOKEN_LOCALHOST_8000"
+        );
+        assert_eq!(
+            token_env_var("https://my-registry.dev/wally/"),
+            "WALLY_TOKEN_MY_REGISTRY_DEV"
+        );
+    }
+}
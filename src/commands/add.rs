@@ -0,0 +1,641 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, format_err, Context};
+use semver::VersionReq;
+use structopt::StructOpt;
+use toml_edit::{decorated, value, Document, Item, Value};
+
+use crate::manifest::{Manifest, Realm, MANIFEST_FILE_NAME};
+use crate::package_name::PackageName;
+use crate::package_req::PackageReq;
+use crate::package_source::{
+    PackageSource, PackageSourceMap, PackageSourceProvider, Registry, TestRegistry,
+};
+
+use super::{GlobalOptions, InstallSubcommand};
+
+/// The manifest section that holds each realm's dependencies.
+const SECTIONS: [(Realm, &str); 3] = [
+    (Realm::Shared, "dependencies"),
+    (Realm::Server, "server-dependencies"),
+    (Realm::Dev, "dev-dependencies"),
+];
+
+/// Add a dependency to the project's manifest, or change the version
+/// requirement of one it already has.
+#[derive(Debug, StructOpt)]
+pub struct AddSubcommand {
+    /// Path to the project to add the dependency to.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// The package to add, as `scope/name` or `scope/name@version-req`.
+    /// Without a version requirement, the newest version is used.
+    pub package: String,
+
+    /// The alias to give the dependency. Defaults to the package's name in
+    /// PascalCase, like `RoactHooks` for `roblox/roact-hooks`.
+    #[structopt(long = "alias")]
+    pub alias: Option<String>,
+
+    /// The realm to add the dependency to: shared, server, or dev. Defaults
+    /// to the realm it's already in, or else the package's own realm.
+    #[structopt(long = "realm")]
+    pub realm: Option<Realm>,
+
+    /// Install the project's dependencies afterwards.
+    #[structopt(long = "install")]
+    pub install: bool,
+}
+
+impl AddSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let manifest = Manifest::load(&self.project_path)?;
+        let (name, version_req) = parse_package(&self.package)?;
+
+        let default_registry: Box<PackageSource> = if global.test_registry {
+            Box::new(PackageSource::TestRegistry(TestRegistry::new(
+                &manifest.package.registry,
+            )))
+        } else {
+            Box::new(PackageSource::Registry(Registry::from_registry_spec(
+                &manifest.package.registry,
+            )?))
+        };
+
+        let mut package_sources = PackageSourceMap::new(default_registry);
+        package_sources.add_fallbacks()?;
+
+        let newest = newest_version(
+            &name,
+            version_req.as_ref().map(|(_, req)| req),
+            &package_sources,
+        )?;
+
+        // A version requirement that was given is written the way it was
+        // given. Otherwise the newest version is written on its own, which
+        // Wally reads as a caret requirement.
+        let requirement = match &version_req {
+            Some((text, _)) => format!("{}@{}", name, text),
+            None => format!("{}@{}", name, newest.package.version),
+        };
+
+        let mut document = read_document(&self.project_path)?;
+        let added = add_dependency(
+            &mut document,
+            &name,
+            self.alias.as_deref(),
+            self.realm,
+            newest.package.realm,
+            &requirement,
+        )?;
+        write_document(&self.project_path, &document)?;
+
+        match &added.previous {
+            Some(previous) if previous == &requirement => println!(
+                "{} is already a dependency in [{}]",
+                added.alias,
+                section_name(added.realm)
+            ),
+            Some(previous) => println!(
+                "Changed {} in [{}] from {} to {}",
+                added.alias,
+                section_name(added.realm),
+                previous,
+                requirement
+            ),
+            None => println!(
+                "Added {} = \"{}\" to [{}]",
+                added.alias,
+                requirement,
+                section_name(added.realm)
+            ),
+        }
+
+        if self.install {
+            install(self.project_path).run(global)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Remove dependencies from the project's manifest.
+#[derive(Debug, StructOpt)]
+pub struct RemoveSubcommand {
+    /// Path to the project to remove the dependencies from.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// The dependencies to remove, each by its alias or by its package's
+    /// `scope/name`.
+    #[structopt(required = true)]
+    pub dependencies: Vec<String>,
+
+    /// Install the project's dependencies afterwards.
+    #[structopt(long = "install")]
+    pub install: bool,
+}
+
+impl RemoveSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let manifest = Manifest::load(&self.project_path)?;
+        let mut document = read_document(&self.project_path)?;
+
+        for dependency in &self.dependencies {
+            for (realm, alias) in remove_dependency(&mut document, &manifest, dependency)? {
+                println!("Removed {} from [{}]", alias, section_name(realm));
+            }
+        }
+
+        write_document(&self.project_path, &document)?;
+
+        if self.install {
+            install(self.project_path).run(global)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A dependency that `wally add` wrote to the manifest.
+#[derive(Debug, PartialEq, Eq)]
+struct AddedDependency {
+    alias: String,
+    realm: Realm,
+
+    /// What the dependency was before, if the manifest already had it.
+    previous: Option<String>,
+}
+
+/// A registry dependency the manifest already has.
+struct ExistingDependency {
+    alias: String,
+    realm: Realm,
+    requirement: String,
+}
+
+fn install(project_path: PathBuf) -> InstallSubcommand {
+    InstallSubcommand {
+        project_path,
+        locked: false,
+        preferences: None,
+        verify_integrity: false,
+        mirrors: Vec::new(),
+        search_mirrors: false,
+        jobs: None,
+        retries: None,
+        offline: false,
+        frozen: false,
+        no_hooks: false,
+    }
+}
+
+fn section_name(realm: Realm) -> &'static str {
+    SECTIONS
+        .iter()
+        .find(|(section_realm, _)| *section_realm == realm)
+        .map(|(_, section)| *section)
+        .unwrap()
+}
+
+/// Splits `scope/name@version-req` into the package name and, if it was
+/// given, the version requirement, along with how it was written.
+fn parse_package(package: &str) -> anyhow::Result<(PackageName, Option<(String, VersionReq)>)> {
+    match package.split_once('@') {
+        Some((name, version_req)) => {
+            let version_req_parsed = VersionReq::parse(version_req).with_context(|| {
+                format!("\"{}\" is not a valid version requirement", version_req)
+            })?;
+
+            Ok((
+                name.parse()?,
+                Some((version_req.to_owned(), version_req_parsed)),
+            ))
+        }
+        None => Ok((package.parse()?, None)),
+    }
+}
+
+/// The newest version of a package matching `version_req`, from the highest
+/// priority source that has the package. Yanked versions are never picked.
+/// Without a version requirement, prereleases are only picked when there's
+/// nothing else.
+fn newest_version(
+    name: &PackageName,
+    version_req: Option<&VersionReq>,
+    package_sources: &PackageSourceMap,
+) -> anyhow::Result<Manifest> {
+    let package_req = PackageReq::new(
+        name.clone(),
+        version_req.cloned().unwrap_or_else(VersionReq::any),
+    );
+
+    let (source, manifests) = package_sources
+        .source_order()
+        .iter()
+        .find_map(|source_id| {
+            let source = package_sources.get(source_id).unwrap();
+
+            match source.query(&package_req) {
+                Ok(manifests) if !manifests.is_empty() => Some((source, manifests)),
+                _ => None,
+            }
+        })
+        .ok_or_else(|| format_err!("Couldn't find any versions of {}", package_req))?;
+
+    let candidates: Vec<Manifest> = manifests
+        .into_iter()
+        .filter(|manifest| !source.is_yanked(&manifest.package_id()).unwrap_or(false))
+        .collect();
+
+    let newest = |stable_only: bool| {
+        candidates
+            .iter()
+            .filter(|manifest| !stable_only || !manifest.package.version.is_prerelease())
+            .max_by(|a, b| a.package.version.cmp(&b.package.version))
+            .cloned()
+    };
+
+    newest(version_req.is_none())
+        .or_else(|| newest(false))
+        .ok_or_else(|| format_err!("Every version of {} has been yanked", package_req))
+}
+
+/// `roblox/roact-hooks` becomes `RoactHooks`.
+fn default_alias(name: &PackageName) -> String {
+    name.name()
+        .split(|char| char == '-' || char == '_')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+fn read_document(project_path: &Path) -> anyhow::Result<Document> {
+    let manifest_path = project_path.join(MANIFEST_FILE_NAME);
+    let contents = fs_err::read_to_string(&manifest_path)?;
+
+    contents
+        .parse()
+        .with_context(|| format!("Could not parse {}", manifest_path.display()))
+}
+
+/// Writes the edited manifest back, as long as it's still a valid manifest.
+fn write_document(project_path: &Path, document: &Document) -> anyhow::Result<()> {
+    let contents = document.to_string();
+    Manifest::from_slice(contents.as_bytes()).context("The edited manifest would be invalid")?;
+
+    fs_err::write(project_path.join(MANIFEST_FILE_NAME), contents)?;
+    Ok(())
+}
+
+/// The first registry dependency with the given alias, or if there's no
+/// alias, on the given package.
+fn find_dependency(
+    document: &Document,
+    name: &PackageName,
+    alias: Option<&str>,
+) -> anyhow::Result<Option<ExistingDependency>> {
+    for &(realm, section) in SECTIONS.iter() {
+        if !document.as_table().contains_table(section) {
+            continue;
+        }
+
+        let table = document[section].as_table().unwrap();
+
+        for (key, item) in table.iter() {
+            let package_req = item
+                .as_str()
+                .and_then(|requirement| requirement.parse::<PackageReq>().ok());
+
+            let found = match (alias, &package_req) {
+                (Some(alias), _) => key == alias,
+                (None, Some(package_req)) => package_req.name() == name,
+                (None, None) => false,
+            };
+
+            if !found {
+                continue;
+            }
+
+            match package_req {
+                Some(package_req) if package_req.name() == name => {
+                    return Ok(Some(ExistingDependency {
+                        alias: key.to_owned(),
+                        realm,
+                        requirement: item.as_str().unwrap().to_owned(),
+                    }))
+                }
+                Some(package_req) => bail!(
+                    "{} is already a dependency on {}. Remove it first with `wally remove {}`.",
+                    key,
+                    package_req.name(),
+                    key
+                ),
+                None => bail!("{} is already a path or Git dependency", key),
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Sets a dependency on `name` to `requirement`, keeping the alias and realm
+/// it already has unless others are given. A new dependency goes in the realm
+/// of the package it's on, unless another is given.
+fn add_dependency(
+    document: &mut Document,
+    name: &PackageName,
+    alias: Option<&str>,
+    realm: Option<Realm>,
+    package_realm: Realm,
+    requirement: &str,
+) -> anyhow::Result<AddedDependency> {
+    let added = match find_dependency(document, name, alias)? {
+        Some(existing) => {
+            let added = AddedDependency {
+                alias: existing.alias,
+                realm: realm.unwrap_or(existing.realm),
+                previous: Some(existing.requirement),
+            };
+
+            if added.realm != existing.realm {
+                document[section_name(existing.realm)]
+                    .as_table_mut()
+                    .unwrap()
+                    .remove(&added.alias);
+            }
+
+            added
+        }
+        None => AddedDependency {
+            alias: alias
+                .map(ToOwned::to_owned)
+                .unwrap_or_else(|| default_alias(name)),
+            realm: realm.unwrap_or(package_realm),
+            previous: None,
+        },
+    };
+
+    let item = &mut document[section_name(added.realm)][added.alias.as_str()];
+
+    // Changing a requirement in place keeps the whitespace and comment around
+    // it.
+    *item = match item.as_value() {
+        Some(previous) => Item::Value(decorated(
+            Value::from(requirement),
+            previous.decor().prefix(),
+            previous.decor().suffix(),
+        )),
+        None => value(requirement),
+    };
+
+    Ok(added)
+}
+
+/// Removes every dependency with `target` as its alias, or on the package
+/// `target` names, along with the features turned on in it. Dependencies
+/// that a feature lists have to be taken out of the feature first.
+fn remove_dependency(
+    document: &mut Document,
+    manifest: &Manifest,
+    target: &str,
+) -> anyhow::Result<Vec<(Realm, String)>> {
+    let name = if target.contains('/') {
+        Some(target.parse::<PackageName>()?)
+    } else {
+        None
+    };
+
+    let mut removed = Vec::new();
+
+    for &(realm, section) in SECTIONS.iter() {
+        if !document.as_table().contains_table(section) {
+            continue;
+        }
+
+        let table = document[section].as_table_mut().unwrap();
+
+        let aliases: Vec<String> = table
+            .iter()
+            .filter(|(key, item)| match &name {
+                Some(name) => item
+                    .as_str()
+                    .and_then(|requirement| requirement.parse::<PackageReq>().ok())
+                    .map_or(false, |package_req| package_req.name() == name),
+                None => *key == target,
+            })
+            .map(|(key, _)| key.to_owned())
+            .collect();
+
+        for alias in aliases {
+            if let Some((feature, _)) = manifest
+                .features
+                .iter()
+                .find(|(_, members)| members.contains(&alias))
+            {
+                bail!(
+                    "{} is part of the feature {}. Take it out of the feature first.",
+                    alias,
+                    feature
+                );
+            }
+
+            table.remove(&alias);
+            removed.push((realm, alias));
+        }
+    }
+
+    if removed.is_empty() {
+        bail!("The project has no dependency {}", target);
+    }
+
+    if document.as_table().contains_table("dependency-features") {
+        let dependency_features = document["dependency-features"].as_table_mut().unwrap();
+
+        for (_, alias) in &removed {
+            dependency_features.remove(alias);
+        }
+    }
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::package_source::InMemoryRegistry;
+    use crate::test_package::PackageBuilder;
+
+    const MANIFEST: &str = r#"[package]
+name = "biff/root"
+version = "0.1.0"
+registry = "test-registry"
+realm = "shared"
+
+# Things the game needs.
+[dependencies]
+Minimal = "biff/minimal@0.1.0" # keep this one old
+Local = { path = "../local" }
+
+[dependency-features]
+Minimal = ["extra"]
+"#;
+
+    fn name(name: &str) -> PackageName {
+        name.parse().unwrap()
+    }
+
+    #[test]
+    fn picks_newest_stable_version() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/minimal@0.1.0"));
+        registry.publish(PackageBuilder::new("biff/minimal@0.2.0"));
+        registry.publish(PackageBuilder::new("biff/minimal@0.3.0"));
+        registry.publish(PackageBuilder::new("biff/minimal@0.4.0-beta"));
+        registry.yank(&"biff/minimal@0.3.0".parse().unwrap());
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let newest = |version_req: Option<&str>| {
+            let version_req = version_req.map(|req| VersionReq::parse(req).unwrap());
+            newest_version(
+                &name("biff/minimal"),
+                version_req.as_ref(),
+                &package_sources,
+            )
+            .map(|manifest| manifest.package.version.to_string())
+        };
+
+        assert_eq!(newest(None).unwrap(), "0.2.0");
+        assert_eq!(newest(Some("0.1")).unwrap(), "0.1.0");
+        assert_eq!(newest(Some(">=0.4.0-beta")).unwrap(), "0.4.0-beta");
+        assert!(newest(Some("2.0")).is_err());
+    }
+
+    #[test]
+    fn adds_and_updates_dependencies() {
+        let mut document: Document = MANIFEST.parse().unwrap();
+
+        let added = add_dependency(
+            &mut document,
+            &name("roblox/roact-hooks"),
+            None,
+            None,
+            Realm::Shared,
+            "roblox/roact-hooks@0.4.0",
+        )
+        .unwrap();
+        assert_eq!(
+            added,
+            AddedDependency {
+                alias: "RoactHooks".to_owned(),
+                realm: Realm::Shared,
+                previous: None,
+            }
+        );
+
+        add_dependency(
+            &mut document,
+            &name("biff/minimal"),
+            None,
+            None,
+            Realm::Shared,
+            "biff/minimal@0.2.0",
+        )
+        .unwrap();
+
+        add_dependency(
+            &mut document,
+            &name("biff/server"),
+            None,
+            None,
+            Realm::Server,
+            "biff/server@1.0.0",
+        )
+        .unwrap();
+
+        let contents = document.to_string();
+        assert!(contents.contains("# Things the game needs."));
+        assert!(contents.contains("Minimal = \"biff/minimal@0.2.0\" # keep this one old"));
+        assert!(contents.contains("RoactHooks = \"roblox/roact-hooks@0.4.0\""));
+        assert!(contents.contains("[server-dependencies]\nServer = \"biff/server@1.0.0\""));
+
+        let manifest = Manifest::from_slice(contents.as_bytes()).unwrap();
+        assert_eq!(manifest.dependencies.len(), 3);
+        assert_eq!(manifest.server_dependencies.len(), 1);
+    }
+
+    #[test]
+    fn moves_dependencies_between_realms() {
+        let mut document: Document = MANIFEST.parse().unwrap();
+
+        let added = add_dependency(
+            &mut document,
+            &name("biff/minimal"),
+            None,
+            Some(Realm::Dev),
+            Realm::Shared,
+            "biff/minimal@0.1.0",
+        )
+        .unwrap();
+        assert_eq!(added.previous.as_deref(), Some("biff/minimal@0.1.0"));
+
+        let manifest = Manifest::from_slice(document.to_string().as_bytes()).unwrap();
+        assert!(!manifest.dependencies.contains_key("Minimal"));
+        assert!(manifest.dev_dependencies.contains_key("Minimal"));
+    }
+
+    #[test]
+    fn refuses_to_replace_other_dependencies() {
+        let mut document: Document = MANIFEST.parse().unwrap();
+
+        let add = |document: &mut Document, alias| {
+            add_dependency(
+                document,
+                &name("biff/other"),
+                Some(alias),
+                None,
+                Realm::Shared,
+                "biff/other@1.0.0",
+            )
+        };
+
+        assert!(add(&mut document, "Minimal").is_err());
+        assert!(add(&mut document, "Local").is_err());
+        assert_eq!(document.to_string(), MANIFEST);
+    }
+
+    #[test]
+    fn removes_dependencies() {
+        let manifest = Manifest::from_slice(MANIFEST.as_bytes()).unwrap();
+
+        let mut document: Document = MANIFEST.parse().unwrap();
+        let removed = remove_dependency(&mut document, &manifest, "biff/minimal").unwrap();
+        assert_eq!(removed, vec![(Realm::Shared, "Minimal".to_owned())]);
+
+        let contents = document.to_string();
+        assert!(contents.contains("# Things the game needs."));
+        assert!(!contents.contains("Minimal"));
+        assert!(contents.contains("Local = { path = \"../local\" }"));
+
+        remove_dependency(&mut document, &manifest, "Local").unwrap();
+        assert!(remove_dependency(&mut document, &manifest, "Local").is_err());
+    }
+
+    #[test]
+    fn keeps_dependencies_features_need() {
+        let mut manifest = Manifest::from_slice(MANIFEST.as_bytes()).unwrap();
+        manifest
+            .features
+            .insert("minimal".to_owned(), vec!["Minimal".to_owned()]);
+
+        let mut document: Document = MANIFEST.parse().unwrap();
+        assert!(remove_dependency(&mut document, &manifest, "Minimal").is_err());
+    }
+}
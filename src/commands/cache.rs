@@ -0,0 +1,185 @@
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{bail, format_err, Context};
+use fs_err as fs;
+use structopt::StructOpt;
+use ubyte::ToByteUnit;
+use walkdir::WalkDir;
+
+use crate::package_cache::{cache_root, PackageCache};
+
+/// Inspect or clean up Wally's cache of downloaded packages and indexes.
+#[derive(Debug, StructOpt)]
+pub struct CacheSubcommand {
+    #[structopt(subcommand)]
+    pub command: CacheCommand,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum CacheCommand {
+    /// Print the directory the cache is kept in.
+    Path,
+
+    /// Show how much space the cache takes up, split into package archives
+    /// and each registry's index.
+    Size,
+
+    /// Remove everything from the cache.
+    Clean,
+
+    /// Remove package archives that haven't been installed from recently.
+    Prune {
+        /// How long an archive has to go unused before it's removed, like
+        /// `30d`. Takes `s`, `m`, `h`, `d`, or `w`.
+        #[structopt(long = "older-than", parse(try_from_str = parse_age))]
+        older_than: Duration,
+    },
+}
+
+impl CacheSubcommand {
+    pub fn run(self) -> anyhow::Result<()> {
+        let root = cache_root()?;
+
+        match self.command {
+            CacheCommand::Path => println!("{}", root.display()),
+            CacheCommand::Size => print_size(&root)?,
+            CacheCommand::Clean => {
+                if root.exists() {
+                    fs::remove_dir_all(&root).with_context(|| {
+                        format!(
+                            "could not remove the cache. Is Wally still running somewhere? \
+                             Cache: {}",
+                            root.display()
+                        )
+                    })?;
+                }
+
+                println!("Removed the cache at {}", root.display());
+            }
+            CacheCommand::Prune { older_than } => {
+                let removed = PackageCache::at(root.join("packages")).prune(older_than)?;
+                let bytes: u64 = removed.iter().map(|entry| entry.size).sum();
+
+                println!(
+                    "Removed {} cached package(s), {}",
+                    removed.len(),
+                    bytes.bytes()
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn print_size(root: &Path) -> anyhow::Result<()> {
+    let cache = PackageCache::at(root.join("packages"));
+    let entries = cache.entries()?;
+    let archives: u64 = entries.iter().map(|entry| entry.size).sum();
+    let mut total = archives;
+
+    println!(
+        "Package archives: {} ({} package(s))",
+        archives.bytes(),
+        entries.iter().filter(|entry| !entry.partial).count()
+    );
+
+    // Each registry's index is cloned into a directory of its own, named
+    // after the registry's host.
+    let index_dir = root.join("index");
+
+    if index_dir.exists() {
+        let mut indexes: Vec<_> = fs::read_dir(&index_dir)?.collect::<Result<_, _>>()?;
+        indexes.sort_by_key(|entry| entry.file_name());
+
+        for index in indexes {
+            let size = dir_size(&index.path())?;
+            total += size;
+
+            println!(
+                "Index {}: {}",
+                index.file_name().to_string_lossy(),
+                size.bytes()
+            );
+        }
+    }
+
+    // Git dependencies are checked out next to the indexes.
+    let git_dir = root.join("git");
+
+    if git_dir.exists() {
+        let size = dir_size(&git_dir)?;
+        total += size;
+
+        println!("Git checkouts: {}", size.bytes());
+    }
+
+    println!("Total: {} ({} bytes)", total.bytes(), total);
+
+    Ok(())
+}
+
+fn dir_size(path: &Path) -> anyhow::Result<u64> {
+    let mut size = 0;
+
+    for entry in WalkDir::new(path) {
+        let entry = entry?;
+
+        if entry.file_type().is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+
+    Ok(size)
+}
+
+/// Reads a duration like `30d` or `12h`.
+fn parse_age(value: &str) -> anyhow::Result<Duration> {
+    let split = value
+        .find(|char: char| !char.is_ascii_digit())
+        .ok_or_else(|| format_err!("{} needs a unit, like {}d", value, value))?;
+    let (amount, unit) = value.split_at(split);
+
+    let amount: u64 = amount
+        .parse()
+        .with_context(|| format!("{} doesn't start with a number", value))?;
+
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => bail!(
+            "unknown unit \"{}\" in {}, expected s, m, h, d, or w",
+            unit,
+            value
+        ),
+    };
+
+    Ok(Duration::from_secs(amount * seconds))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ages() {
+        assert_eq!(parse_age("45s").unwrap(), Duration::from_secs(45));
+        assert_eq!(parse_age("12h").unwrap(), Duration::from_secs(12 * 60 * 60));
+        assert_eq!(
+            parse_age("30d").unwrap(),
+            Duration::from_secs(30 * 24 * 60 * 60)
+        );
+        assert_eq!(
+            parse_age("2w").unwrap(),
+            Duration::from_secs(14 * 24 * 60 * 60)
+        );
+
+        assert!(parse_age("30").is_err());
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("30y").is_err());
+    }
+}
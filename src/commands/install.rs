@@ -4,20 +4,27 @@ use std::io::Write;
 use std::path::PathBuf;
 use std::time::Duration;
 
+use anyhow::{bail, format_err};
 use crossterm::style::{Attribute, Color, SetAttribute, SetForegroundColor};
 use indicatif::{ProgressBar, ProgressStyle};
-
+use serde::Serialize;
 use structopt::StructOpt;
 
-use crate::installation::InstallationContext;
+use crate::hooks::run_post_install_hooks;
+use crate::installation::{InstallationContext, DEFAULT_CONCURRENCY, DEFAULT_RETRIES};
 use crate::lockfile::Lockfile;
 use crate::manifest::Manifest;
+use crate::package_cache::{cache_root, PackageCache};
 use crate::package_id::PackageId;
-use crate::package_source::{PackageSource, PackageSourceMap, Registry, TestRegistry};
-use crate::resolution::resolve;
+use crate::package_source::{
+    add_local_dependencies, OfflineRegistry, PackageSource, PackageSourceMap,
+    PackageSourceProvider, Registry, TestRegistry,
+};
+use crate::preferences::VersionPreferences;
+use crate::resolution::{resolve_with_preferences, Resolve};
 
 use super::utils::{generate_dependency_changes, render_update_difference};
-use super::GlobalOptions;
+use super::{GlobalOptions, OutputFormat};
 
 /// Install all of the dependencies of this project.
 #[derive(Debug, StructOpt)]
@@ -29,27 +36,150 @@ pub struct InstallSubcommand {
     /// Flag to error if the lockfile does not match with the latest dependencies.
     #[structopt(long = "locked")]
     pub locked: bool,
+
+    /// Path to a TOML file of preferred package versions, used when more than
+    /// one version of a package would work.
+    #[structopt(long = "preferences")]
+    pub preferences: Option<PathBuf>,
+
+    /// Check downloaded packages against the integrity documents served by
+    /// the registry.
+    #[structopt(long = "verify-integrity")]
+    pub verify_integrity: bool,
+
+    /// A mirror of the registry to download packages from when it can't be
+    /// reached. Can be given more than once, and is tried before the mirrors
+    /// listed in the manifest.
+    #[structopt(long = "mirror")]
+    pub mirrors: Vec<String>,
+
+    /// Also look for packages on mirrors when the registry doesn't have them.
+    #[structopt(long = "search-mirrors")]
+    pub search_mirrors: bool,
+
+    /// How many packages to download at once. Defaults to 8.
+    #[structopt(long = "jobs", short = "j")]
+    pub jobs: Option<usize>,
+
+    /// How many times to retry a package download that fails, picking up
+    /// where it left off. Defaults to 3.
+    #[structopt(long = "retries")]
+    pub retries: Option<usize>,
+
+    /// Install exactly what's in the lockfile from the local package cache,
+    /// without reaching any registry. Path dependencies are read from disk, and
+    /// Git dependencies from earlier checkouts. Fails if the lockfile is out of
+    /// date or a locked package hasn't been cached or checked out by an
+    /// earlier install.
+    #[structopt(long = "offline", conflicts_with = "frozen")]
+    pub offline: bool,
+
+    /// Fail instead of changing the lockfile. Unlike `--offline`, packages
+    /// are still downloaded from the registry.
+    #[structopt(long = "frozen")]
+    pub frozen: bool,
+
+    /// Don't run the project's post-install hooks.
+    #[structopt(long = "no-hooks")]
+    pub no_hooks: bool,
+}
+
+/// What `wally install --format json` prints once it's done.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct InstallOutput {
+    root: PackageId,
+
+    /// Every package that was installed, not counting the project itself.
+    packages: Vec<PackageId>,
+
+    /// Whether the resolved packages differ from the ones the lockfile had
+    /// before.
+    lockfile_changed: bool,
+
+    /// Installed versions that their owners have deprecated.
+    deprecated: Vec<Deprecation>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct Deprecation {
+    package: PackageId,
+    message: String,
 }
 
 impl InstallSubcommand {
     pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let format = global.format;
+        let output = self.install(global)?;
+
+        if format == OutputFormat::Json {
+            println!("{}", serde_json::to_string_pretty(&output)?);
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn install(self, global: GlobalOptions) -> anyhow::Result<InstallOutput> {
         let manifest = Manifest::load(&self.project_path)?;
+        let preferences = VersionPreferences::load_optional(self.preferences.as_deref())?;
 
-        let lockfile = Lockfile::load(&self.project_path)?
-            .unwrap_or_else(|| Lockfile::from_manifest(&manifest));
+        let lockfile_is_fixed = self.offline || self.frozen;
+
+        let lockfile = match Lockfile::load(&self.project_path)? {
+            Some(lockfile) => lockfile,
+            None if lockfile_is_fixed => bail!(
+                "There's no lockfile in {} to install from. Run `wally install` to create one.",
+                self.project_path.display()
+            ),
+            None => Lockfile::from_manifest(&manifest),
+        };
 
-        let default_registry: Box<PackageSource> = if global.test_registry {
-            Box::new(PackageSource::TestRegistry(TestRegistry::new(
-                &manifest.package.registry,
-            )))
+        // Tests use their own registries, whose packages shouldn't end up in
+        // the user's cache.
+        let package_cache = if global.test_registry {
+            None
         } else {
-            Box::new(PackageSource::Registry(Registry::from_registry_spec(
-                &manifest.package.registry,
-            )?))
+            Some(PackageCache::new()?)
         };
 
-        let mut package_sources = PackageSourceMap::new(default_registry);
-        package_sources.add_fallbacks()?;
+        let mut package_sources = if self.offline {
+            let offline = OfflineRegistry::from_lockfile(
+                &lockfile,
+                &manifest.package_id(),
+                package_cache.as_ref(),
+            )?;
+
+            PackageSourceMap::new(Box::new(PackageSource::Offline(offline)))
+        } else {
+            let default_registry: Box<PackageSource> = if global.test_registry {
+                Box::new(PackageSource::TestRegistry(TestRegistry::new(
+                    &manifest.package.registry,
+                )))
+            } else {
+                Box::new(PackageSource::Registry(
+                    Registry::from_registry_spec(&manifest.package.registry)?
+                        .with_mirrors(&self.mirrors)?
+                        .with_mirrors(&manifest.package.mirrors)?
+                        .search_mirrors(self.search_mirrors),
+                ))
+            };
+
+            let mut package_sources = PackageSourceMap::new(default_registry);
+            package_sources.add_fallbacks()?;
+            package_sources
+        };
+
+        // Path and Git dependencies are turned into dependencies on the exact
+        // versions found, so it's the rewritten manifest that's resolved. They
+        // aren't in the package cache, so they're loaded like this offline too.
+        let manifest = add_local_dependencies(
+            &manifest,
+            &self.project_path,
+            &lockfile,
+            &cache_root()?.join("git"),
+            self.offline,
+            &mut package_sources,
+        )?;
 
         let try_to_use = lockfile.as_ids().collect();
 
@@ -66,37 +196,20 @@ impl InstallSubcommand {
                 SetForegroundColor(Color::Reset)
             ));
 
-            let latest_graph = resolve(&manifest, &BTreeSet::new(), &package_sources)?;
+            let latest_graph = resolve_with_preferences(
+                &manifest,
+                &BTreeSet::new(),
+                &package_sources,
+                &preferences,
+            )?;
 
             if try_to_use != latest_graph.activated {
                 progress.finish_and_clear();
-
-                let old_dependencies = &try_to_use;
-
-                let changes =
-                    generate_dependency_changes(old_dependencies, &latest_graph.activated);
-                let mut error_output = Vec::new();
-
-                writeln!(
-                    error_output,
-                    "{} The Lockfile is out of date and wasn't changed due to --locked{}",
-                    SetForegroundColor(Color::Yellow),
-                    SetForegroundColor(Color::Reset)
-                )?;
-
-                render_update_difference(&changes, &mut error_output)?;
-
-                writeln!(
-                    error_output,
-                    "{}{} Suggestion{}{} try running wally update",
-                    SetAttribute(Attribute::Bold),
-                    SetForegroundColor(Color::DarkGreen),
-                    SetForegroundColor(Color::Reset),
-                    SetAttribute(Attribute::Reset)
-                )?;
-
-                anyhow::bail!(String::from_utf8(error_output)
-                    .expect("output from render_update_difference should always be utf-8"));
+                return Err(lockfile_out_of_date(
+                    "--locked",
+                    &try_to_use,
+                    &latest_graph.activated,
+                )?);
             }
 
             progress.println(format!(
@@ -113,7 +226,8 @@ impl InstallSubcommand {
             SetForegroundColor(Color::Reset)
         ));
 
-        let resolved = resolve(&manifest, &try_to_use, &package_sources)?;
+        let resolved =
+            resolve_with_preferences(&manifest, &try_to_use, &package_sources, &preferences)?;
 
         progress.println(format!(
             "{}   Resolved {}{} dependencies",
@@ -122,14 +236,47 @@ impl InstallSubcommand {
             resolved.activated.len() - 1
         ));
 
-        let new_lockfile = Lockfile::from_resolve(&resolved);
-        new_lockfile.save(&self.project_path)?;
+        let deprecated = find_deprecations(&manifest.package_id(), &resolved, &package_sources);
 
-        progress.println(format!(
-            "{}  Generated {}lockfile",
-            SetForegroundColor(Color::DarkGreen),
-            SetForegroundColor(Color::Reset)
-        ));
+        for deprecation in &deprecated {
+            progress.println(format!(
+                "{} Deprecated {}{}: {}",
+                SetForegroundColor(Color::Yellow),
+                SetForegroundColor(Color::Reset),
+                deprecation.package,
+                deprecation.message
+            ));
+        }
+
+        let checksums = if lockfile_is_fixed {
+            if try_to_use != resolved.activated {
+                progress.finish_and_clear();
+                let flag = if self.offline {
+                    "--offline"
+                } else {
+                    "--frozen"
+                };
+                return Err(lockfile_out_of_date(
+                    flag,
+                    &try_to_use,
+                    &resolved.activated,
+                )?);
+            }
+
+            lockfile.checksums()
+        } else {
+            let mut new_lockfile = Lockfile::from_resolve(&resolved);
+            new_lockfile.keep_checksums(&lockfile);
+            new_lockfile.save(&self.project_path)?;
+
+            progress.println(format!(
+                "{}  Generated {}lockfile",
+                SetForegroundColor(Color::DarkGreen),
+                SetForegroundColor(Color::Reset)
+            ));
+
+            new_lockfile.checksums()
+        };
 
         progress.set_message(format!(
             "{}  Cleaning {}package destination...",
@@ -141,7 +288,12 @@ impl InstallSubcommand {
             &self.project_path,
             manifest.place.shared_packages,
             manifest.place.server_packages,
-        );
+        )
+        .with_integrity_verification(self.verify_integrity)
+        .with_package_cache(package_cache)
+        .with_checksums(checksums)
+        .with_concurrency(self.jobs.unwrap_or(DEFAULT_CONCURRENCY))
+        .with_retries(self.retries.unwrap_or(DEFAULT_RETRIES));
 
         installation.clean()?;
         progress.println(format!(
@@ -151,8 +303,122 @@ impl InstallSubcommand {
         ));
         progress.finish_and_clear();
 
+        let output = InstallOutput {
+            root: root_package_id.clone(),
+            packages: resolved
+                .activated
+                .iter()
+                .filter(|package_id| **package_id != root_package_id)
+                .cloned()
+                .collect(),
+            lockfile_changed: try_to_use != resolved.activated,
+            deprecated,
+        };
+
         installation.install(package_sources, root_package_id, resolved)?;
 
-        Ok(())
+        if !self.no_hooks {
+            run_post_install_hooks(
+                &manifest.hooks.post_install,
+                &self.project_path,
+                &installation,
+            )?;
+        }
+
+        Ok(output)
+    }
+}
+
+/// The deprecation messages of any packages resolved for `root`. Deprecation
+/// is only a warning, so a source that can't say whether a package is
+/// deprecated is taken to mean it isn't.
+fn find_deprecations(
+    root: &PackageId,
+    resolved: &Resolve,
+    package_sources: &PackageSourceMap,
+) -> Vec<Deprecation> {
+    resolved
+        .metadata
+        .iter()
+        .filter(|(package_id, _)| *package_id != root)
+        .filter_map(|(package_id, metadata)| {
+            let source = package_sources.get(&metadata.source_registry)?;
+            let message = source.deprecation(package_id).ok()??;
+
+            Some(Deprecation {
+                package: package_id.clone(),
+                message,
+            })
+        })
+        .collect()
+}
+
+/// The error for when the lockfile would have to change, but `flag` says it
+/// mustn't, listing what would change.
+fn lockfile_out_of_date(
+    flag: &str,
+    locked: &BTreeSet<PackageId>,
+    resolved: &BTreeSet<PackageId>,
+) -> anyhow::Result<anyhow::Error> {
+    let changes = generate_dependency_changes(locked, resolved);
+    let mut error_output = Vec::new();
+
+    writeln!(
+        error_output,
+        "{} The Lockfile is out of date and wasn't changed due to {}{}",
+        SetForegroundColor(Color::Yellow),
+        flag,
+        SetForegroundColor(Color::Reset)
+    )?;
+
+    render_update_difference(&changes, &mut error_output)?;
+
+    writeln!(
+        error_output,
+        "{}{} Suggestion{}{} try running wally update",
+        SetAttribute(Attribute::Bold),
+        SetForegroundColor(Color::DarkGreen),
+        SetForegroundColor(Color::Reset),
+        SetAttribute(Attribute::Reset)
+    )?;
+
+    Ok(format_err!(String::from_utf8(error_output).expect(
+        "output from render_update_difference should always be utf-8"
+    )))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::package_source::InMemoryRegistry;
+    use crate::resolution::resolve;
+    use crate::test_package::PackageBuilder;
+
+    #[test]
+    fn finds_deprecated_versions() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/old@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/current@1.0.0"));
+        registry.deprecate(
+            &"biff/old@1.0.0".parse().unwrap(),
+            "Use biff/current instead",
+        );
+
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("Old", "biff/old@1.0.0")
+            .with_dep("Current", "biff/current@1.0.0")
+            .into_manifest();
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolved = resolve(&root, &BTreeSet::new(), &package_sources).unwrap();
+
+        assert_eq!(
+            find_deprecations(&root.package_id(), &resolved, &package_sources),
+            vec![Deprecation {
+                package: "biff/old@1.0.0".parse().unwrap(),
+                message: "Use biff/current instead".to_owned(),
+            }]
+        );
     }
 }
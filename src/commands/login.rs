@@ -4,13 +4,14 @@ use std::time::Duration;
 
 use anyhow::format_err;
 use opener;
-use reqwest::blocking::Client;
 use reqwest::Url;
 use serde::Deserialize;
 use structopt::StructOpt;
 
 use crate::{
+    api_info::{advertised_write_auth, ApiInfoAuthMode},
     auth::AuthStore,
+    http_client,
     manifest::Manifest,
     package_index::{PackageIndex, PackageIndexConfig},
 };
@@ -18,6 +19,9 @@ use crate::{
 /// Log into a registry.
 #[derive(Debug, StructOpt)]
 pub struct LoginSubcommand {
+    /// URL of the registry's index to log into. Defaults to the registry of
+    /// the project at `--project-path`.
+    pub registry: Option<String>,
     /// Path to a project to decide how to login
     #[structopt(long = "project-path", default_value = ".")]
     pub project_path: PathBuf,
@@ -55,7 +59,7 @@ fn wait_for_github_auth(
 ) -> anyhow::Result<DeviceCodeAuth> {
     sleep(Duration::from_secs(device_code_response.interval));
 
-    let client = Client::new();
+    let client = http_client::blocking_client()?;
     let response = client
         .post("https://github.com/login/oauth/access_token")
         .header("accept", "application/json")
@@ -85,13 +89,13 @@ fn prompt_api_key(api: url::Url) -> anyhow::Result<()> {
 }
 
 fn prompt_github_auth(api: url::Url, github_oauth_id: &str) -> anyhow::Result<()> {
-    let client = Client::new();
+    let client = http_client::blocking_client()?;
     let device_code_response = client
         .post("https://github.com/login/device/code")
         .header("accept", "application/json")
         .json(&serde_json::json!({
             "client_id": github_oauth_id,
-            "scope": "read:user",
+            "scope": "read:user user:email",
         }))
         .send()?
         .json::<DeviceCodeResponse>()?;
@@ -110,26 +114,51 @@ fn prompt_github_auth(api: url::Url, github_oauth_id: &str) -> anyhow::Result<()
     AuthStore::set_token(api.as_str(), Some(&auth.access_token))
 }
 
-fn fetch_package_index_config(project_path: &Path) -> anyhow::Result<PackageIndexConfig> {
-    let manifest = Manifest::load(project_path)?;
-    let registry = Url::parse(&manifest.package.registry)?;
+/// The index config of the given registry, or of the project's registry if
+/// none was given.
+pub(crate) fn fetch_package_index_config(
+    registry: Option<&str>,
+    project_path: &Path,
+) -> anyhow::Result<PackageIndexConfig> {
+    let registry = match registry {
+        Some(registry) => Url::parse(registry)?,
+        None => {
+            let manifest = Manifest::load(project_path)?;
+            Url::parse(&manifest.package.registry)?
+        }
+    };
+
     let package_index = PackageIndex::new(&registry, None)?;
     package_index.config()
 }
 
+/// The GitHub OAuth app the registry itself says to log in with. When the
+/// registry doesn't say, we fall back to the client ID in the index config.
+fn advertised_github_oauth_id(api: &Url) -> Option<String> {
+    match advertised_write_auth(api)? {
+        ApiInfoAuthMode::GithubOauth { client_id }
+        | ApiInfoAuthMode::GithubOauthPrivate { client_id } => Some(client_id),
+        _ => None,
+    }
+}
+
 impl LoginSubcommand {
     pub fn run(self) -> anyhow::Result<()> {
+        let registry = self.registry.as_deref();
+
         match (self.token, self.api) {
             (Some(token), Some(api)) => AuthStore::set_token(&api, Some(&token)),
             (Some(token), None) => {
-                let config = fetch_package_index_config(&self.project_path)?;
+                let config = fetch_package_index_config(registry, &self.project_path)?;
 
                 AuthStore::set_token(config.api.as_str(), Some(&token))
             }
             (None, _) => {
-                let config = fetch_package_index_config(&self.project_path)?;
+                let config = fetch_package_index_config(registry, &self.project_path)?;
+                let github_oauth_id =
+                    advertised_github_oauth_id(&config.api).or(config.github_oauth_id);
 
-                match config.github_oauth_id {
+                match github_oauth_id {
                     None => prompt_api_key(config.api),
                     Some(github_oauth_id) => prompt_github_auth(config.api, &github_oauth_id),
                 }
@@ -2,11 +2,16 @@ use std::path::PathBuf;
 
 use structopt::StructOpt;
 
-use crate::{auth::AuthStore, manifest::Manifest, package_index::PackageIndex};
+use crate::auth::AuthStore;
+
+use super::login::fetch_package_index_config;
 
 /// Log out of a registry.
 #[derive(Debug, StructOpt)]
 pub struct LogoutSubcommand {
+    /// URL of the registry's index to log out of. Defaults to the registry of
+    /// the project at `--project-path`.
+    pub registry: Option<String>,
     /// Path to a project to decide how to logout
     #[structopt(long = "project-path", default_value = ".")]
     pub project_path: PathBuf,
@@ -14,10 +19,7 @@ pub struct LogoutSubcommand {
 
 impl LogoutSubcommand {
     pub fn run(self) -> anyhow::Result<()> {
-        let manifest = Manifest::load(&self.project_path)?;
-        let registry = url::Url::parse(&manifest.package.registry)?;
-        let package_index = PackageIndex::new(&registry, None)?;
-        let api = package_index.config()?.api;
+        let api = fetch_package_index_config(self.registry.as_deref(), &self.project_path)?.api;
 
         AuthStore::set_token(api.as_str(), None)?;
 
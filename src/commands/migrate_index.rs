@@ -0,0 +1,39 @@
+use structopt::StructOpt;
+use url::Url;
+
+use crate::package_index::{IndexLayout, PackageIndex};
+
+/// Move the packages in a registry's index to a different directory layout.
+/// Intended for registry maintainers.
+#[derive(Debug, StructOpt)]
+pub struct MigrateIndexSubcommand {
+    /// URL of the index repository to migrate.
+    #[structopt(long = "index")]
+    pub index: Url,
+
+    /// The layout to move the index to, either `flat` or `sharded`.
+    #[structopt(long = "layout", default_value = "sharded")]
+    pub layout: IndexLayout,
+
+    /// A GitHub token to push the migration with. If not given, the machine's
+    /// Git credential helper is used.
+    #[structopt(long = "token")]
+    pub token: Option<String>,
+}
+
+impl MigrateIndexSubcommand {
+    pub fn run(self) -> anyhow::Result<()> {
+        // Work on a fresh clone so that the user's cached copy of the index
+        // isn't left half-migrated if something goes wrong.
+        let package_index = PackageIndex::new_temp(&self.index, self.token)?;
+        let moved = package_index.migrate_layout(self.layout)?;
+
+        if moved == 0 {
+            println!("Index already uses the {} layout", self.layout);
+        } else {
+            println!("Moved {} scopes to the {} layout", moved, self.layout);
+        }
+
+        Ok(())
+    }
+}
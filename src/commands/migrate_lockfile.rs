@@ -0,0 +1,126 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, format_err};
+use semver::VersionReq;
+use structopt::StructOpt;
+
+use crate::lockfile::{LockPackage, Lockfile};
+use crate::manifest::Manifest;
+use crate::package_id::PackageId;
+use crate::package_integrity::archive_hash;
+use crate::package_req::PackageReq;
+use crate::package_source::{
+    PackageSource, PackageSourceMap, PackageSourceProvider, Registry, TestRegistry,
+};
+
+use super::GlobalOptions;
+
+/// Upgrade a lockfile written by an older version of Wally to the current
+/// format, without changing which versions of packages are locked.
+#[derive(Debug, StructOpt)]
+pub struct MigrateLockfileSubcommand {
+    /// Path to the project whose lockfile should be migrated.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+}
+
+impl MigrateLockfileSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let manifest = Manifest::load(&self.project_path)?;
+        let mut lockfile = Lockfile::load(&self.project_path)?.ok_or_else(|| {
+            format_err!(
+                "There's no lockfile in {} to migrate. Run `wally install` to create one.",
+                self.project_path.display()
+            )
+        })?;
+
+        let default_registry: Box<PackageSource> = if global.test_registry {
+            Box::new(PackageSource::TestRegistry(TestRegistry::new(
+                &manifest.package.registry,
+            )))
+        } else {
+            Box::new(PackageSource::Registry(Registry::from_registry_spec(
+                &manifest.package.registry,
+            )?))
+        };
+
+        let mut package_sources = PackageSourceMap::new(default_registry);
+        package_sources.add_fallbacks()?;
+
+        let root_package_id = manifest.package_id();
+        lockfile.registry = manifest.package.registry.clone();
+
+        let mut checksums_added = 0;
+
+        for lock_package in &mut lockfile.packages {
+            let lock_package = match lock_package {
+                LockPackage::Registry(lock_package) => lock_package,
+                LockPackage::Git(lock_package) => bail!(
+                    "{} is a Git dependency, which can't be migrated. \
+                     Run `wally update` to re-resolve your dependencies instead.",
+                    lock_package.name
+                ),
+                LockPackage::Path(lock_package) => bail!(
+                    "{} is a path dependency, which can't be migrated. \
+                     Run `wally update` to re-resolve your dependencies instead.",
+                    lock_package.name
+                ),
+            };
+
+            let package_id =
+                PackageId::new(lock_package.name.clone(), lock_package.version.clone());
+
+            if package_id == root_package_id || lock_package.checksum.is_some() {
+                continue;
+            }
+
+            let source = find_source(&package_sources, &package_id).ok_or_else(|| {
+                format_err!(
+                    "{} is no longer available from any registry, so its checksum can't be \
+                     filled in. Run `wally update` to re-resolve your dependencies instead.",
+                    package_id
+                )
+            })?;
+
+            // Registries that serve integrity documents already know the hash,
+            // which saves downloading the whole package.
+            let checksum = match source.download_integrity(&package_id)? {
+                Some(integrity) => integrity.archive,
+                None => archive_hash(&source.download_package(&package_id)?),
+            };
+
+            lock_package.checksum = Some(checksum);
+            checksums_added += 1;
+        }
+
+        lockfile.save(&self.project_path)?;
+
+        println!(
+            "Migrated lockfile, adding checksums for {} package(s)",
+            checksums_added
+        );
+
+        Ok(())
+    }
+}
+
+/// Find the highest priority source that still has the given version of a
+/// package.
+fn find_source<'a>(
+    package_sources: &'a PackageSourceMap,
+    package_id: &PackageId,
+) -> Option<&'a PackageSource> {
+    let package_req = PackageReq::new(
+        package_id.name().clone(),
+        VersionReq::exact(package_id.version()),
+    );
+
+    package_sources
+        .source_order()
+        .iter()
+        .map(|source| package_sources.get(source).unwrap())
+        .find(|source| match source.query(&package_req) {
+            Ok(manifests) => !manifests.is_empty(),
+            Err(_) => false,
+        })
+}
@@ -1,26 +1,49 @@
+mod add;
+mod cache;
 mod init;
 mod install;
 mod login;
 mod logout;
 mod manifest_to_json;
+mod migrate_index;
+mod migrate_lockfile;
+mod outdated;
 mod package;
 mod publish;
 mod search;
+mod tree;
 mod update;
 mod utils;
+mod verify;
+mod yank;
 
+pub use add::{AddSubcommand, RemoveSubcommand};
+pub use cache::CacheSubcommand;
 pub use init::InitSubcommand;
 pub use install::InstallSubcommand;
 pub use login::LoginSubcommand;
 pub use logout::LogoutSubcommand;
 pub use manifest_to_json::ManifestToJsonSubcommand;
+pub use migrate_index::MigrateIndexSubcommand;
+pub use migrate_lockfile::MigrateLockfileSubcommand;
+pub use outdated::OutdatedSubcommand;
 pub use package::PackageSubcommand;
 pub use publish::PublishSubcommand;
 pub use search::SearchSubcommand;
+pub use tree::{TreeSubcommand, WhySubcommand};
 pub use update::{PackageSpec, UpdateSubcommand};
+pub use verify::VerifySubcommand;
+pub use yank::{UnyankSubcommand, YankSubcommand};
 
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::bail;
 use structopt::StructOpt;
 
+use crate::http_client;
+
 #[derive(Debug, StructOpt)]
 #[structopt(about = env!("CARGO_PKG_DESCRIPTION"))]
 pub struct Args {
@@ -33,6 +56,18 @@ pub struct Args {
 
 impl Args {
     pub fn run(self) -> anyhow::Result<()> {
+        let mut timeouts = http_client::Timeouts::from_env()?;
+
+        if let Some(connect_timeout) = self.global.connect_timeout {
+            timeouts.connect = Duration::from_secs(connect_timeout);
+        }
+
+        if let Some(timeout) = self.global.timeout {
+            timeouts.total = Duration::from_secs(timeout);
+        }
+
+        timeouts.set();
+
         match self.subcommand {
             Subcommand::Publish(subcommand) => subcommand.run(self.global),
             Subcommand::Init(subcommand) => subcommand.run(),
@@ -43,6 +78,17 @@ impl Args {
             Subcommand::Package(subcommand) => subcommand.run(),
             Subcommand::Install(subcommand) => subcommand.run(self.global),
             Subcommand::ManifestToJson(subcommand) => subcommand.run(),
+            Subcommand::MigrateIndex(subcommand) => subcommand.run(),
+            Subcommand::MigrateLockfile(subcommand) => subcommand.run(self.global),
+            Subcommand::Yank(subcommand) => subcommand.run(),
+            Subcommand::Unyank(subcommand) => subcommand.run(),
+            Subcommand::Outdated(subcommand) => subcommand.run(self.global),
+            Subcommand::Tree(subcommand) => subcommand.run(self.global),
+            Subcommand::Why(subcommand) => subcommand.run(),
+            Subcommand::Verify(subcommand) => subcommand.run(self.global),
+            Subcommand::Cache(subcommand) => subcommand.run(),
+            Subcommand::Add(subcommand) => subcommand.run(self.global),
+            Subcommand::Remove(subcommand) => subcommand.run(self.global),
         }
     }
 }
@@ -55,6 +101,22 @@ pub struct GlobalOptions {
     #[structopt(global = true, parse(from_occurrences), long = "verbose", short)]
     pub verbosity: u8,
 
+    /// Seconds to wait for a connection to a registry or GitHub before giving
+    /// up. Defaults to 30, or WALLY_CONNECT_TIMEOUT.
+    #[structopt(global = true, long = "connect-timeout")]
+    pub connect_timeout: Option<u64>,
+
+    /// Seconds a single request can take, including reading the response,
+    /// before giving up. Defaults to 120, or WALLY_TIMEOUT.
+    #[structopt(global = true, long = "timeout")]
+    pub timeout: Option<u64>,
+
+    /// How to print results: `human`, or `json` for scripts. With `json`,
+    /// only JSON is printed to stdout, including errors, and everything else
+    /// goes to stderr. Supported by install, outdated, tree, and verify.
+    #[structopt(global = true, long = "format", default_value = "human")]
+    pub format: OutputFormat,
+
     /// Flag to indidate if we will be using a test registry. Usable only by tests.
     #[structopt(skip)]
     pub test_registry: bool,
@@ -72,6 +134,9 @@ impl Default for GlobalOptions {
     fn default() -> Self {
         Self {
             verbosity: 0,
+            connect_timeout: None,
+            timeout: None,
+            format: OutputFormat::Human,
             test_registry: false,
             use_temp_index: false,
             check_token: None,
@@ -79,6 +144,38 @@ impl Default for GlobalOptions {
     }
 }
 
+/// How commands print their results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Human,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "human" => Ok(OutputFormat::Human),
+            "json" => Ok(OutputFormat::Json),
+            _ => bail!("unknown format \"{}\", expected human or json", value),
+        }
+    }
+}
+
+/// The error a command returns when it's failed but has already printed
+/// why as part of its JSON output, so nothing more should be printed.
+#[derive(Debug)]
+pub struct ReportedFailure;
+
+impl fmt::Display for ReportedFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the command failed, as reported in its output")
+    }
+}
+
+impl std::error::Error for ReportedFailure {}
+
 #[derive(Debug, StructOpt)]
 pub enum Subcommand {
     Init(InitSubcommand),
@@ -90,4 +187,15 @@ pub enum Subcommand {
     Search(SearchSubcommand),
     Package(PackageSubcommand),
     ManifestToJson(ManifestToJsonSubcommand),
+    MigrateIndex(MigrateIndexSubcommand),
+    MigrateLockfile(MigrateLockfileSubcommand),
+    Yank(YankSubcommand),
+    Unyank(UnyankSubcommand),
+    Outdated(OutdatedSubcommand),
+    Tree(TreeSubcommand),
+    Why(WhySubcommand),
+    Verify(VerifySubcommand),
+    Cache(CacheSubcommand),
+    Add(AddSubcommand),
+    Remove(RemoveSubcommand),
 }
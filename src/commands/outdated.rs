@@ -0,0 +1,408 @@
+use std::path::PathBuf;
+
+use anyhow::format_err;
+use crossterm::style::{Color, SetForegroundColor};
+use semver::{Version, VersionReq};
+use serde::Serialize;
+use structopt::StructOpt;
+
+use crate::lockfile::{LockPackage, Lockfile};
+use crate::manifest::{Manifest, Realm};
+use crate::package_name::PackageName;
+use crate::package_req::PackageReq;
+use crate::package_source::{
+    PackageSource, PackageSourceMap, PackageSourceProvider, Registry, TestRegistry,
+};
+
+use super::{GlobalOptions, OutputFormat};
+
+/// List the project's dependencies that have newer versions available.
+#[derive(Debug, StructOpt)]
+pub struct OutdatedSubcommand {
+    /// Path to the project to check the dependencies of.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// Print the dependencies as JSON instead of a table. The same as
+    /// `--format json`.
+    #[structopt(long = "json")]
+    pub json: bool,
+}
+
+impl OutdatedSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let manifest = Manifest::load(&self.project_path)?;
+        let lockfile = Lockfile::load(&self.project_path)?.ok_or_else(|| {
+            format_err!(
+                "There's no lockfile in {}. Run `wally install` to create one.",
+                self.project_path.display()
+            )
+        })?;
+
+        let default_registry: Box<PackageSource> = if global.test_registry {
+            Box::new(PackageSource::TestRegistry(TestRegistry::new(
+                &manifest.package.registry,
+            )))
+        } else {
+            Box::new(PackageSource::Registry(Registry::from_registry_spec(
+                &manifest.package.registry,
+            )?))
+        };
+
+        let mut package_sources = PackageSourceMap::new(default_registry);
+        package_sources.add_fallbacks()?;
+
+        let dependencies = outdated_dependencies(&manifest, &lockfile, &package_sources)?;
+
+        if self.json || global.format == OutputFormat::Json {
+            println!("{}", serde_json::to_string_pretty(&dependencies)?);
+        } else {
+            print_table(&dependencies);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct OutdatedDependency {
+    alias: String,
+    name: PackageName,
+    realm: Realm,
+    req: VersionReq,
+
+    /// The locked version, if the lockfile has one for this dependency.
+    current: Option<Version>,
+
+    /// The newest version that the manifest's version requirement allows.
+    compatible: Option<Version>,
+
+    /// The newest version there is, not counting prereleases.
+    latest: Option<Version>,
+
+    status: UpdateStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum UpdateStatus {
+    UpToDate,
+
+    /// A newer version is allowed by the manifest, so `wally update` will
+    /// pick it up.
+    InRange,
+
+    /// The only newer versions are outside the manifest's version
+    /// requirement, which has to be changed to use them.
+    NeedsRangeChange,
+
+    /// The lockfile doesn't have this dependency yet.
+    NotLocked,
+}
+
+impl UpdateStatus {
+    fn description(self) -> &'static str {
+        match self {
+            UpdateStatus::UpToDate => "up to date",
+            UpdateStatus::InRange => "update in range",
+            UpdateStatus::NeedsRangeChange => "needs range change",
+            UpdateStatus::NotLocked => "not locked",
+        }
+    }
+
+    fn color(self) -> Color {
+        match self {
+            UpdateStatus::UpToDate => Color::DarkGreen,
+            UpdateStatus::InRange => Color::Yellow,
+            UpdateStatus::NeedsRangeChange => Color::Red,
+            UpdateStatus::NotLocked => Color::DarkGrey,
+        }
+    }
+}
+
+/// Compares each of the root package's direct dependencies to the versions its
+/// registry has.
+fn outdated_dependencies(
+    manifest: &Manifest,
+    lockfile: &Lockfile,
+    package_sources: &PackageSourceMap,
+) -> anyhow::Result<Vec<OutdatedDependency>> {
+    let root_id = manifest.package_id();
+    let locked: Vec<_> = lockfile
+        .packages
+        .iter()
+        .find_map(|lock_package| match lock_package {
+            LockPackage::Registry(lock_package)
+                if lock_package.name == manifest.package.name
+                    && lock_package.version == manifest.package.version =>
+            {
+                Some(lock_package.dependencies.clone())
+            }
+            _ => None,
+        })
+        .unwrap_or_default();
+
+    let realms = [
+        (Realm::Shared, &manifest.dependencies),
+        (Realm::Server, &manifest.server_dependencies),
+        (Realm::Dev, &manifest.dev_dependencies),
+    ];
+
+    let mut dependencies = Vec::new();
+
+    for (realm, realm_dependencies) in realms.iter() {
+        // Path and Git dependencies have no registry to check for newer
+        // versions.
+        let registry_dependencies = realm_dependencies
+            .iter()
+            .filter_map(|(alias, dependency)| Some((alias, dependency.registry()?)));
+
+        for (alias, package_req) in registry_dependencies {
+            let current = locked
+                .iter()
+                .find(|(locked_alias, package_id)| {
+                    locked_alias == alias && package_id.name() == package_req.name()
+                })
+                .map(|(_, package_id)| package_id.version().clone());
+
+            let versions =
+                available_versions(package_req.name(), current.as_ref(), package_sources).map_err(
+                    |err| format_err!("could not check {} for {}: {:#}", alias, root_id, err),
+                )?;
+
+            let compatible = versions
+                .iter()
+                .filter(|version| package_req.version_req().matches(version))
+                .max()
+                .cloned();
+
+            let latest = versions
+                .iter()
+                .filter(|version| !version.is_prerelease())
+                .max()
+                .or_else(|| versions.iter().max())
+                .cloned();
+
+            let status = match &current {
+                None => UpdateStatus::NotLocked,
+                Some(current) if compatible.as_ref().map_or(false, |v| v > current) => {
+                    UpdateStatus::InRange
+                }
+                Some(current) if latest.as_ref().map_or(false, |v| v > current) => {
+                    UpdateStatus::NeedsRangeChange
+                }
+                Some(_) => UpdateStatus::UpToDate,
+            };
+
+            dependencies.push(OutdatedDependency {
+                alias: alias.clone(),
+                name: package_req.name().clone(),
+                realm: *realm,
+                req: package_req.version_req().clone(),
+                current,
+                compatible,
+                latest,
+                status,
+            });
+        }
+    }
+
+    Ok(dependencies)
+}
+
+/// Every version of a package from the highest priority source that has it,
+/// the way resolution finds packages. Yanked versions are left out, unless
+/// it's the version already in use.
+fn available_versions(
+    name: &PackageName,
+    current: Option<&Version>,
+    package_sources: &PackageSourceMap,
+) -> anyhow::Result<Vec<Version>> {
+    let package_req = PackageReq::new(name.clone(), VersionReq::any());
+
+    let (source, manifests) = package_sources
+        .source_order()
+        .iter()
+        .find_map(|source_id| {
+            let source = package_sources.get(source_id).unwrap();
+
+            match source.query(&package_req) {
+                Ok(manifests) => Some((source, manifests)),
+                Err(_) => None,
+            }
+        })
+        .ok_or_else(|| format_err!("Failed to find a source for {}", name))?;
+
+    let versions = manifests
+        .into_iter()
+        .filter(|manifest| {
+            Some(&manifest.package.version) == current
+                || !source.is_yanked(&manifest.package_id()).unwrap_or(false)
+        })
+        .map(|manifest| manifest.package.version)
+        .collect();
+
+    Ok(versions)
+}
+
+fn print_table(dependencies: &[OutdatedDependency]) {
+    let show = |version: &Option<Version>| match version {
+        Some(version) => version.to_string(),
+        None => "-".to_owned(),
+    };
+
+    let rows: Vec<[String; 5]> = dependencies
+        .iter()
+        .map(|dependency| {
+            [
+                format!("{} ({})", dependency.alias, dependency.name),
+                show(&dependency.current),
+                show(&dependency.compatible),
+                show(&dependency.latest),
+                dependency.status.description().to_owned(),
+            ]
+        })
+        .collect();
+
+    let header = [
+        "Dependency".to_owned(),
+        "Current".to_owned(),
+        "Compatible".to_owned(),
+        "Latest".to_owned(),
+        "Status".to_owned(),
+    ];
+
+    let mut widths = [0; 5];
+    for row in std::iter::once(&header).chain(&rows) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let pad = |row: &[String; 5]| {
+        row[..4]
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}  ", cell, width = *width))
+            .collect::<String>()
+    };
+
+    println!("{}{}", pad(&header), header[4]);
+
+    for (row, dependency) in rows.iter().zip(dependencies) {
+        println!(
+            "{}{}{}{}",
+            pad(row),
+            SetForegroundColor(dependency.status.color()),
+            row[4],
+            SetForegroundColor(Color::Reset)
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::lockfile::RegistryLockPackage;
+    use crate::package_id::PackageId;
+    use crate::package_source::InMemoryRegistry;
+    use crate::test_package::PackageBuilder;
+
+    fn lock_root(manifest: &Manifest, dependencies: &[(&str, &str)]) -> Lockfile {
+        let mut lockfile = Lockfile::from_manifest(manifest);
+
+        lockfile
+            .packages
+            .push(LockPackage::Registry(RegistryLockPackage {
+                name: manifest.package.name.clone(),
+                version: manifest.package.version.clone(),
+                checksum: None,
+                features: Vec::new(),
+                dependencies: dependencies
+                    .iter()
+                    .map(|(alias, id)| (alias.to_string(), id.parse::<PackageId>().unwrap()))
+                    .collect(),
+            }));
+
+        lockfile
+    }
+
+    #[test]
+    fn compares_to_range_and_latest() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/minimal@0.1.0"));
+        registry.publish(PackageBuilder::new("biff/minimal@0.1.1"));
+        registry.publish(PackageBuilder::new("biff/minimal@0.2.0"));
+        registry.publish(PackageBuilder::new("biff/minimal@0.3.0-beta"));
+        registry.publish(PackageBuilder::new("biff/pinned@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/pinned@1.2.0"));
+        registry.publish(PackageBuilder::new("biff/current@2.0.0"));
+        registry.publish(PackageBuilder::new("biff/current@2.1.0"));
+        registry.yank(&"biff/current@2.1.0".parse().unwrap());
+
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("Minimal", "biff/minimal@0.1.0")
+            .with_dep("Pinned", "biff/pinned@=1.0.0")
+            .with_server_dep("Current", "biff/current@2.0.0")
+            .with_dep("New", "biff/pinned@1.0.0")
+            .into_manifest();
+
+        let lockfile = lock_root(
+            &root,
+            &[
+                ("Minimal", "biff/minimal@0.1.0"),
+                ("Pinned", "biff/pinned@1.0.0"),
+                ("Current", "biff/current@2.0.0"),
+            ],
+        );
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let dependencies = outdated_dependencies(&root, &lockfile, &package_sources).unwrap();
+
+        let summary: Vec<_> = dependencies
+            .iter()
+            .map(|dependency| {
+                (
+                    dependency.alias.as_str(),
+                    dependency.compatible.as_ref().map(ToString::to_string),
+                    dependency.latest.as_ref().map(ToString::to_string),
+                    dependency.status,
+                )
+            })
+            .collect();
+
+        let version = |version: &str| Some(version.to_owned());
+
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    "Minimal",
+                    version("0.1.1"),
+                    version("0.2.0"),
+                    UpdateStatus::InRange
+                ),
+                (
+                    "New",
+                    version("1.2.0"),
+                    version("1.2.0"),
+                    UpdateStatus::NotLocked
+                ),
+                (
+                    "Pinned",
+                    version("1.0.0"),
+                    version("1.2.0"),
+                    UpdateStatus::NeedsRangeChange
+                ),
+                (
+                    "Current",
+                    version("2.0.0"),
+                    version("2.0.0"),
+                    UpdateStatus::UpToDate
+                ),
+            ]
+        );
+    }
+}
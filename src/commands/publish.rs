@@ -1,17 +1,21 @@
+use std::io::Read;
 use std::path::{Path, PathBuf};
 
-use anyhow::{bail, Context};
+use anyhow::{bail, format_err, Context};
 use structopt::StructOpt;
 use ubyte::ToByteUnit;
 use url::Url;
 
 use crate::{
-    auth::AuthStore, manifest::Manifest, package_contents::PackageContents,
-    package_index::PackageIndex, GlobalOptions,
+    api_info::{advertised_write_auth, ApiInfoAuthMode},
+    auth::AuthStore,
+    http_client,
+    manifest::Manifest,
+    package_contents::PackageContents,
+    package_index::PackageIndex,
+    GlobalOptions,
 };
 
-const VERSION: &str = env!("CARGO_PKG_VERSION");
-
 /// Publish this project to a registry.
 #[derive(Debug, StructOpt)]
 pub struct PublishSubcommand {
@@ -22,24 +26,60 @@ pub struct PublishSubcommand {
     /// Auth token to use
     #[structopt(long = "token")]
     pub token: Option<String>,
+
+    /// URL of the registry's index to publish to, instead of the registry in
+    /// the manifest. The token stored for that registry is used.
+    #[structopt(long = "registry")]
+    pub registry: Option<String>,
+
+    /// A package archive that was already built with `wally package`, or `-`
+    /// to read one from stdin. The archive's own manifest is used instead of
+    /// the project's, and the project is not packaged.
+    pub archive: Option<PathBuf>,
 }
 
 impl PublishSubcommand {
     pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
-        let manifest = Manifest::load(&self.project_path)?;
+        let prepared = match &self.archive {
+            Some(archive) => Some(read_archive(archive)?),
+            None => None,
+        };
+
+        let manifest = match &prepared {
+            Some(contents) => contents
+                .manifest()
+                .context("The package archive is not a valid Wally package")?,
+            None => Manifest::load(&self.project_path)?,
+        };
 
         if manifest.package.private {
             bail!("Cannot publish private package.");
         }
 
+        if let Some((alias, dependency)) = manifest
+            .all_dependencies()
+            .find(|(_, dependency)| dependency.registry().is_none())
+        {
+            bail!(
+                "Cannot publish a package with path or Git dependencies. \
+                 Dependency {} is {}; depend on a published version instead.",
+                alias,
+                dependency
+            );
+        }
+
+        let registry = self
+            .registry
+            .as_deref()
+            .unwrap_or(&manifest.package.registry);
+
         let index_url = if global.test_registry {
-            let index_path = Path::new(&manifest.package.registry)
-                .join("index")
-                .canonicalize()?;
+            let index_path = Path::new(registry).join("index").canonicalize()?;
 
             Url::from_directory_path(index_path).unwrap()
         } else {
-            Url::parse(&manifest.package.registry)?
+            Url::parse(registry)
+                .with_context(|| format!("{} isn't a valid registry URL", registry))?
         };
 
         let package_index = if global.use_temp_index {
@@ -49,7 +89,10 @@ impl PublishSubcommand {
         };
 
         let api = package_index.config()?.api;
-        let contents = PackageContents::pack_from_path(&self.project_path)?;
+        let contents = match prepared {
+            Some(contents) => contents,
+            None => PackageContents::pack_from_path(&self.project_path)?,
+        };
 
         if contents.data().len() > 2.mebibytes() {
             bail!("Package size exceeds 2MB. Reduce package size and try again.");
@@ -57,8 +100,10 @@ impl PublishSubcommand {
 
         let auth = match self.token {
             Some(token) => token,
-            None => AuthStore::get_token(api.as_str())?
-                .with_context(|| "Authentication is required to publish, use `wally login`")?,
+            None => match AuthStore::get_token(api.as_str())? {
+                Some(token) => token,
+                None => return Err(missing_credentials(&api, self.registry.as_deref())),
+            },
         };
 
         println!(
@@ -73,11 +118,10 @@ impl PublishSubcommand {
             return Ok(());
         }
 
-        let client = reqwest::blocking::Client::new();
+        let client = http_client::blocking_client()?;
         let response = client
             .post(api.join("/v1/publish")?)
             .header("accept", "application/json")
-            .header("Wally-Version", VERSION)
             .bearer_auth(auth)
             .body(contents.data().to_owned())
             .send()?;
@@ -92,3 +136,59 @@ impl PublishSubcommand {
         Ok(())
     }
 }
+
+/// Explains how to get credentials for the registry at `api`, going by how the
+/// registry says it authenticates publishers. `registry` is the registry the
+/// command was pointed at, if it isn't the manifest's.
+fn missing_credentials(api: &Url, registry: Option<&str>) -> anyhow::Error {
+    let login = match registry {
+        Some(registry) => format!("wally login {}", registry),
+        None => String::from("wally login"),
+    };
+
+    match advertised_write_auth(api) {
+        Some(ApiInfoAuthMode::GithubOauth { .. })
+        | Some(ApiInfoAuthMode::GithubOauthPrivate { .. }) => format_err!(
+            "No credentials are stored for {}. This registry authenticates publishers with \
+             GitHub, so log in with `{}`.",
+            api,
+            login
+        ),
+        Some(ApiInfoAuthMode::ApiKey {}) | Some(ApiInfoAuthMode::DoubleApiKey {}) => format_err!(
+            "No credentials are stored for {}. This registry takes an API key to publish: \
+             store one with `{} --token <key>`, or pass it with `--token`.",
+            api,
+            login
+        ),
+        Some(ApiInfoAuthMode::Gitlab {}) => format_err!(
+            "No credentials are stored for {}. This registry authenticates publishers with \
+             GitLab: store a GitLab access token with `{} --token <token>`, or pass it with \
+             `--token`.",
+            api,
+            login
+        ),
+        Some(ApiInfoAuthMode::Unauthenticated {}) => {
+            format_err!("The registry at {} doesn't accept publishes", api)
+        }
+        Some(ApiInfoAuthMode::Other) | None => format_err!(
+            "No credentials are stored for {}. Authentication is required to publish, use \
+             `{}`, or pass a token with `--token`.",
+            api,
+            login
+        ),
+    }
+}
+
+fn read_archive(path: &Path) -> anyhow::Result<PackageContents> {
+    let data = if path == Path::new("-") {
+        let mut data = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut data)
+            .context("Could not read package archive from stdin")?;
+        data
+    } else {
+        fs_err::read(path)?
+    };
+
+    Ok(PackageContents::from_buffer(data))
+}
@@ -3,11 +3,11 @@ use std::path::PathBuf;
 use anyhow::bail;
 use crossterm::style::Color;
 use crossterm::style::SetForegroundColor;
-use reqwest::{blocking::Client, header::AUTHORIZATION};
+use reqwest::header::AUTHORIZATION;
 use serde::Deserialize;
 use structopt::StructOpt;
 
-use crate::{auth::AuthStore, manifest::Manifest, package_index::PackageIndex};
+use crate::{auth::AuthStore, http_client, manifest::Manifest, package_index::PackageIndex};
 
 /// Search a registry for packages matching a query.
 #[derive(Debug, StructOpt)]
@@ -24,13 +24,12 @@ impl SearchSubcommand {
     pub fn run(self) -> anyhow::Result<()> {
         let manifest = Manifest::load(&self.project_path)?;
         let registry = url::Url::parse(&manifest.package.registry)?;
-        let auth_store = AuthStore::load()?;
         let package_index = PackageIndex::new(&registry, None)?;
         let api = package_index.config()?.api;
 
-        let auth = auth_store.tokens.get(api.as_str());
+        let auth = AuthStore::get_token(api.as_str())?;
 
-        let client = Client::new();
+        let client = http_client::blocking_client()?;
         let mut request = client
             .get(api.join("/v1/package-search/")?)
             .query(&[("query", &self.query)]);
@@ -0,0 +1,496 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, format_err};
+use serde::Serialize;
+use structopt::StructOpt;
+
+use crate::lockfile::{LockPackage, Lockfile};
+use crate::manifest::{Manifest, Realm};
+use crate::package_id::PackageId;
+use crate::package_name::PackageName;
+
+use super::{GlobalOptions, OutputFormat};
+
+/// Print the project's resolved dependency graph from its lockfile.
+#[derive(Debug, StructOpt)]
+pub struct TreeSubcommand {
+    /// Path to the project to print the dependencies of.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// Only show dependencies of this realm: shared, server, or dev.
+    #[structopt(long = "realm")]
+    pub realm: Option<Realm>,
+}
+
+impl TreeSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let graph = DependencyGraph::load(&self.project_path)?;
+
+        if global.format == OutputFormat::Json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&graph.output(self.realm))?
+            );
+            return Ok(());
+        }
+
+        for line in graph.tree(self.realm) {
+            println!("{}", line);
+        }
+
+        Ok(())
+    }
+}
+
+/// Show every path from the project's direct dependencies to a package.
+#[derive(Debug, StructOpt)]
+pub struct WhySubcommand {
+    /// The package to explain, like `scope/name`.
+    pub package: PackageName,
+
+    /// Path to the project to look in.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// Only show paths through dependencies of this realm: shared, server,
+    /// or dev.
+    #[structopt(long = "realm")]
+    pub realm: Option<Realm>,
+}
+
+impl WhySubcommand {
+    pub fn run(self) -> anyhow::Result<()> {
+        let graph = DependencyGraph::load(&self.project_path)?;
+
+        if !graph.contains(&self.package) {
+            bail!("{} isn't in the lockfile", self.package);
+        }
+
+        let paths = graph.paths_to(&self.package, self.realm);
+
+        if paths.is_empty() {
+            match self.realm {
+                Some(realm) => println!(
+                    "{} isn't depended on through any {} dependencies",
+                    self.package,
+                    realm_name(realm)
+                ),
+                None => println!("{} isn't depended on by the project", self.package),
+            }
+        }
+
+        for (realm, path) in paths {
+            let path: Vec<_> = path.iter().map(ToString::to_string).collect();
+            println!("[{}] {}", realm_name(realm), path.join(" -> "));
+        }
+
+        Ok(())
+    }
+}
+
+fn realm_name(realm: Realm) -> &'static str {
+    match realm {
+        Realm::Shared => "shared",
+        Realm::Server => "server",
+        Realm::Dev => "dev",
+    }
+}
+
+/// The graph as `wally tree --format json` prints it. Rather than nesting,
+/// every package reachable from the listed direct dependencies appears once,
+/// so repeats and cycles need no special handling.
+#[derive(Debug, Serialize)]
+struct TreeOutput {
+    root: PackageId,
+    dependencies: Vec<DirectDependency>,
+    packages: Vec<TreePackage>,
+}
+
+#[derive(Debug, Serialize)]
+struct DirectDependency {
+    alias: String,
+    package: PackageId,
+    realm: Realm,
+}
+
+#[derive(Debug, Serialize)]
+struct TreePackage {
+    package: PackageId,
+    dependencies: Vec<Dependency>,
+}
+
+#[derive(Debug, Serialize)]
+struct Dependency {
+    alias: String,
+    package: PackageId,
+}
+
+/// The graph of registry packages in a lockfile. Git packages are left out,
+/// since the lockfile doesn't identify them by package ID.
+struct DependencyGraph {
+    root: PackageId,
+
+    /// The root package's direct dependencies, with the realm the manifest
+    /// puts them in. The lockfile alone doesn't record realms.
+    direct: Vec<(Realm, String, PackageId)>,
+
+    dependencies: BTreeMap<PackageId, Vec<(String, PackageId)>>,
+}
+
+impl DependencyGraph {
+    fn load(project_path: &Path) -> anyhow::Result<Self> {
+        let manifest = Manifest::load(project_path)?;
+        let lockfile = Lockfile::load(project_path)?.ok_or_else(|| {
+            format_err!(
+                "There's no lockfile in {}. Run `wally install` to create one.",
+                project_path.display()
+            )
+        })?;
+
+        Ok(Self::new(&manifest, &lockfile))
+    }
+
+    fn new(manifest: &Manifest, lockfile: &Lockfile) -> Self {
+        let root = manifest.package_id();
+
+        let dependencies: BTreeMap<_, _> = lockfile
+            .packages
+            .iter()
+            .map(|lock_package| {
+                (
+                    lock_package.package_id(),
+                    lock_package.dependencies().to_vec(),
+                )
+            })
+            .collect();
+
+        let realm_of = |alias: &str| {
+            if manifest.server_dependencies.contains_key(alias) {
+                Realm::Server
+            } else if manifest.dev_dependencies.contains_key(alias) {
+                Realm::Dev
+            } else {
+                Realm::Shared
+            }
+        };
+
+        let mut direct: Vec<_> = dependencies
+            .get(&root)
+            .into_iter()
+            .flatten()
+            .map(|(alias, package_id)| (realm_of(alias), alias.clone(), package_id.clone()))
+            .collect();
+        direct.sort_by_key(|(realm, _, _)| match realm {
+            Realm::Shared => 0,
+            Realm::Server => 1,
+            Realm::Dev => 2,
+        });
+
+        Self {
+            root,
+            direct,
+            dependencies,
+        }
+    }
+
+    fn contains(&self, name: &PackageName) -> bool {
+        self.dependencies
+            .keys()
+            .any(|package_id| package_id.name() == name)
+    }
+
+    fn direct_in(&self, realm: Option<Realm>) -> impl Iterator<Item = &(Realm, String, PackageId)> {
+        self.direct
+            .iter()
+            .filter(move |(dependency_realm, _, _)| realm.map_or(true, |r| r == *dependency_realm))
+    }
+
+    /// The graph as lines of a tree. A package that was already printed is
+    /// marked with `(*)` instead of being expanded again, and a dependency
+    /// back on one of its own ancestors is marked with `(cycle)`.
+    fn tree(&self, realm: Option<Realm>) -> Vec<String> {
+        let mut printer = TreePrinter {
+            graph: self,
+            lines: vec![self.root.to_string()],
+            expanded: BTreeSet::new(),
+            ancestors: vec![self.root.clone()],
+        };
+
+        let direct: Vec<_> = self.direct_in(realm).collect();
+
+        for (i, (realm, alias, package_id)) in direct.iter().enumerate() {
+            let label = format!("{} {} ({})", alias, package_id, realm_name(*realm));
+            printer.push(package_id, label, "", i + 1 == direct.len());
+        }
+
+        printer.lines
+    }
+
+    fn output(&self, realm: Option<Realm>) -> TreeOutput {
+        let dependencies: Vec<_> = self
+            .direct_in(realm)
+            .map(|(realm, alias, package_id)| DirectDependency {
+                alias: alias.clone(),
+                package: package_id.clone(),
+                realm: *realm,
+            })
+            .collect();
+
+        let mut reachable = BTreeSet::new();
+        let mut queue: Vec<_> = dependencies
+            .iter()
+            .map(|dependency| dependency.package.clone())
+            .collect();
+
+        while let Some(package_id) = queue.pop() {
+            if package_id == self.root || !reachable.insert(package_id.clone()) {
+                continue;
+            }
+
+            for (_, child_id) in self.dependencies.get(&package_id).into_iter().flatten() {
+                queue.push(child_id.clone());
+            }
+        }
+
+        let packages = reachable
+            .into_iter()
+            .map(|package_id| TreePackage {
+                dependencies: self
+                    .dependencies
+                    .get(&package_id)
+                    .into_iter()
+                    .flatten()
+                    .map(|(alias, child_id)| Dependency {
+                        alias: alias.clone(),
+                        package: child_id.clone(),
+                    })
+                    .collect(),
+                package: package_id,
+            })
+            .collect();
+
+        TreeOutput {
+            root: self.root.clone(),
+            dependencies,
+            packages,
+        }
+    }
+
+    /// Every path from a direct dependency down to any version of `name`. A
+    /// path never visits the same package twice, so cycles end the path.
+    fn paths_to(&self, name: &PackageName, realm: Option<Realm>) -> Vec<(Realm, Vec<PackageId>)> {
+        let mut paths = Vec::new();
+
+        for (realm, _, package_id) in self.direct_in(realm) {
+            let mut path = vec![package_id.clone()];
+            self.collect_paths(name, &mut path, &mut |path| {
+                paths.push((*realm, path.to_vec()));
+            });
+        }
+
+        paths
+    }
+
+    fn collect_paths(
+        &self,
+        name: &PackageName,
+        path: &mut Vec<PackageId>,
+        found: &mut dyn FnMut(&[PackageId]),
+    ) {
+        let package_id = path.last().unwrap().clone();
+
+        if package_id.name() == name {
+            found(path);
+            return;
+        }
+
+        for (_, child_id) in self.dependencies.get(&package_id).into_iter().flatten() {
+            if path.contains(child_id) || *child_id == self.root {
+                continue;
+            }
+
+            path.push(child_id.clone());
+            self.collect_paths(name, path, found);
+            path.pop();
+        }
+    }
+}
+
+struct TreePrinter<'a> {
+    graph: &'a DependencyGraph,
+    lines: Vec<String>,
+    expanded: BTreeSet<PackageId>,
+    ancestors: Vec<PackageId>,
+}
+
+impl TreePrinter<'_> {
+    fn push(&mut self, package_id: &PackageId, label: String, prefix: &str, last: bool) {
+        let branch = if last { "└── " } else { "├── " };
+
+        if self.ancestors.contains(package_id) {
+            self.lines
+                .push(format!("{}{}{} (cycle)", prefix, branch, label));
+            return;
+        }
+
+        if !self.expanded.insert(package_id.clone()) {
+            self.lines
+                .push(format!("{}{}{} (*)", prefix, branch, label));
+            return;
+        }
+
+        self.lines.push(format!("{}{}{}", prefix, branch, label));
+
+        let child_prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+        let graph = self.graph;
+        let children = graph.dependencies.get(package_id).into_iter().flatten();
+        let count = graph.dependencies.get(package_id).map_or(0, Vec::len);
+
+        self.ancestors.push(package_id.clone());
+
+        for (i, (alias, child_id)) in children.enumerate() {
+            let label = format!("{} {}", alias, child_id);
+            self.push(child_id, label, &child_prefix, i + 1 == count);
+        }
+
+        self.ancestors.pop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::lockfile::RegistryLockPackage;
+    use crate::test_package::PackageBuilder;
+
+    fn lockfile(manifest: &Manifest, packages: &[(&str, &[(&str, &str)])]) -> Lockfile {
+        let mut lockfile = Lockfile::from_manifest(manifest);
+
+        for (id, dependencies) in packages {
+            let (name, version) = id.parse::<PackageId>().unwrap().into_parts();
+
+            lockfile
+                .packages
+                .push(LockPackage::Registry(RegistryLockPackage {
+                    name,
+                    version,
+                    checksum: None,
+                    features: Vec::new(),
+                    dependencies: dependencies
+                        .iter()
+                        .map(|(alias, id)| (alias.to_string(), id.parse().unwrap()))
+                        .collect(),
+                }));
+        }
+
+        lockfile
+    }
+
+    /// The root depends on a and b, which both depend on c, and c depends
+    /// back on a.
+    fn diamond_with_cycle() -> DependencyGraph {
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("A", "biff/a@1.0.0")
+            .with_server_dep("B", "biff/b@1.0.0")
+            .into_manifest();
+
+        let lockfile = lockfile(
+            &root,
+            &[
+                (
+                    "biff/root@0.1.0",
+                    &[("A", "biff/a@1.0.0"), ("B", "biff/b@1.0.0")],
+                ),
+                ("biff/a@1.0.0", &[("C", "biff/c@1.0.0")]),
+                ("biff/b@1.0.0", &[("C", "biff/c@1.0.0")]),
+                ("biff/c@1.0.0", &[("A", "biff/a@1.0.0")]),
+            ],
+        );
+
+        DependencyGraph::new(&root, &lockfile)
+    }
+
+    #[test]
+    fn tree_marks_repeats_and_cycles() {
+        let graph = diamond_with_cycle();
+
+        assert_eq!(
+            graph.tree(None),
+            vec![
+                "biff/root@0.1.0",
+                "├── A biff/a@1.0.0 (shared)",
+                "│   └── C biff/c@1.0.0",
+                "│       └── A biff/a@1.0.0 (cycle)",
+                "└── B biff/b@1.0.0 (server)",
+                "    └── C biff/c@1.0.0 (*)",
+            ]
+        );
+
+        assert_eq!(
+            graph.tree(Some(Realm::Server)),
+            vec![
+                "biff/root@0.1.0",
+                "└── B biff/b@1.0.0 (server)",
+                "    └── C biff/c@1.0.0",
+                "        └── A biff/a@1.0.0",
+                "            └── C biff/c@1.0.0 (cycle)",
+            ]
+        );
+    }
+
+    #[test]
+    fn json_lists_each_reachable_package_once() {
+        let graph = diamond_with_cycle();
+        let output = serde_json::to_value(graph.output(Some(Realm::Server))).unwrap();
+
+        assert_eq!(
+            output,
+            serde_json::json!({
+                "root": "biff/root@0.1.0",
+                "dependencies": [
+                    { "alias": "B", "package": "biff/b@1.0.0", "realm": "server" },
+                ],
+                "packages": [
+                    {
+                        "package": "biff/a@1.0.0",
+                        "dependencies": [{ "alias": "C", "package": "biff/c@1.0.0" }],
+                    },
+                    {
+                        "package": "biff/b@1.0.0",
+                        "dependencies": [{ "alias": "C", "package": "biff/c@1.0.0" }],
+                    },
+                    {
+                        "package": "biff/c@1.0.0",
+                        "dependencies": [{ "alias": "A", "package": "biff/a@1.0.0" }],
+                    },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn why_finds_every_path() {
+        let graph = diamond_with_cycle();
+        let ids =
+            |ids: &[&str]| -> Vec<PackageId> { ids.iter().map(|id| id.parse().unwrap()).collect() };
+
+        assert_eq!(
+            graph.paths_to(&"biff/c".parse().unwrap(), None),
+            vec![
+                (Realm::Shared, ids(&["biff/a@1.0.0", "biff/c@1.0.0"])),
+                (Realm::Server, ids(&["biff/b@1.0.0", "biff/c@1.0.0"])),
+            ]
+        );
+
+        assert_eq!(
+            graph.paths_to(&"biff/a".parse().unwrap(), Some(Realm::Server)),
+            vec![(
+                Realm::Server,
+                ids(&["biff/b@1.0.0", "biff/c@1.0.0", "biff/a@1.0.0"])
+            )]
+        );
+    }
+}
@@ -4,13 +4,18 @@ use std::path::PathBuf;
 use std::str::FromStr;
 use std::time::Duration;
 
-use crate::installation::InstallationContext;
+use crate::hooks::run_post_install_hooks;
+use crate::installation::{InstallationContext, DEFAULT_CONCURRENCY, DEFAULT_RETRIES};
 use crate::lockfile::Lockfile;
 use crate::manifest::Manifest;
+use crate::package_cache::cache_root;
 use crate::package_id::PackageId;
 use crate::package_name::PackageName;
 use crate::package_req::PackageReq;
-use crate::package_source::{PackageSource, PackageSourceMap, Registry, TestRegistry};
+use crate::package_source::{
+    add_local_dependencies, PackageSource, PackageSourceMap, Registry, TestRegistry,
+};
+use crate::preferences::VersionPreferences;
 use crate::{resolution, GlobalOptions};
 use crossterm::style::{Attribute, Color, SetAttribute, SetForegroundColor};
 use indicatif::{ProgressBar, ProgressStyle};
@@ -28,11 +33,45 @@ pub struct UpdateSubcommand {
     /// An optional list of dependencies to update.
     /// They must be valid package name with an optional version requirement.
     pub package_specs: Vec<PackageSpec>,
+
+    /// Path to a TOML file of preferred package versions, used when more than
+    /// one version of a package would work.
+    #[structopt(long = "preferences")]
+    pub preferences: Option<PathBuf>,
+
+    /// Check downloaded packages against the integrity documents served by
+    /// the registry.
+    #[structopt(long = "verify-integrity")]
+    pub verify_integrity: bool,
+
+    /// A mirror of the registry to download packages from when it can't be
+    /// reached. Can be given more than once, and is tried before the mirrors
+    /// listed in the manifest.
+    #[structopt(long = "mirror")]
+    pub mirrors: Vec<String>,
+
+    /// Also look for packages on mirrors when the registry doesn't have them.
+    #[structopt(long = "search-mirrors")]
+    pub search_mirrors: bool,
+
+    /// How many packages to download at once. Defaults to 8.
+    #[structopt(long = "jobs", short = "j")]
+    pub jobs: Option<usize>,
+
+    /// How many times to retry a package download that fails, picking up
+    /// where it left off. Defaults to 3.
+    #[structopt(long = "retries")]
+    pub retries: Option<usize>,
+
+    /// Don't run the project's post-install hooks.
+    #[structopt(long = "no-hooks")]
+    pub no_hooks: bool,
 }
 
 impl UpdateSubcommand {
     pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
         let manifest = Manifest::load(&self.project_path)?;
+        let preferences = VersionPreferences::load_optional(self.preferences.as_deref())?;
 
         let lockfile = match Lockfile::load(&self.project_path)? {
             Some(lockfile) => lockfile,
@@ -44,14 +83,37 @@ impl UpdateSubcommand {
                 &manifest.package.registry,
             )))
         } else {
-            Box::new(PackageSource::Registry(Registry::from_registry_spec(
-                &manifest.package.registry,
-            )?))
+            Box::new(PackageSource::Registry(
+                Registry::from_registry_spec(&manifest.package.registry)?
+                    .with_mirrors(&self.mirrors)?
+                    .with_mirrors(&manifest.package.mirrors)?
+                    .search_mirrors(self.search_mirrors),
+            ))
         };
 
         let mut package_sources = PackageSourceMap::new(default_registry);
         package_sources.add_fallbacks()?;
 
+        // Git dependencies that are being updated are fetched again, instead
+        // of being checked out at the commit they were locked to.
+        let mut pins = lockfile.clone();
+        if self.package_specs.is_empty() {
+            pins.packages.clear();
+        } else {
+            pins.packages.retain(|lock_package| {
+                !self.given_package_id_satisifies_targets(&lock_package.package_id())
+            });
+        }
+
+        let manifest = add_local_dependencies(
+            &manifest,
+            &self.project_path,
+            &pins,
+            &cache_root()?.join("git"),
+            false,
+            &mut package_sources,
+        )?;
+
         // If the user didn't specify any targets, then update all of the packages.
         // Otherwise, find the target packages to update.
         let try_to_use = if self.package_specs.is_empty() {
@@ -89,7 +151,12 @@ impl UpdateSubcommand {
                 SetForegroundColor(Color::Reset)
             ));
 
-        let resolved_graph = resolution::resolve(&manifest, &try_to_use, &package_sources)?;
+        let resolved_graph = resolution::resolve_with_preferences(
+            &manifest,
+            &try_to_use,
+            &package_sources,
+            &preferences,
+        )?;
 
         progress.println(format!(
             "{}   Resolved {}{} total dependencies",
@@ -107,7 +174,9 @@ impl UpdateSubcommand {
             render_update_difference(&dependency_changes, &mut std::io::stdout()).unwrap();
         });
 
-        Lockfile::from_resolve(&resolved_graph).save(&self.project_path)?;
+        let mut new_lockfile = Lockfile::from_resolve(&resolved_graph);
+        new_lockfile.keep_checksums(&lockfile);
+        new_lockfile.save(&self.project_path)?;
 
         progress.println(format!(
             "{}    Updated {}lockfile",
@@ -120,7 +189,11 @@ impl UpdateSubcommand {
             &self.project_path,
             manifest.place.shared_packages,
             manifest.place.server_packages,
-        );
+        )
+        .with_integrity_verification(self.verify_integrity)
+        .with_checksums(new_lockfile.checksums())
+        .with_concurrency(self.jobs.unwrap_or(DEFAULT_CONCURRENCY))
+        .with_retries(self.retries.unwrap_or(DEFAULT_RETRIES));
 
         progress.set_message(format!(
             "{}  Cleaning {}package destination...",
@@ -145,6 +218,14 @@ impl UpdateSubcommand {
 
         installation_context.install(package_sources, root_package_id, resolved_graph)?;
 
+        if !self.no_hooks {
+            run_post_install_hooks(
+                &manifest.hooks.post_install,
+                &self.project_path,
+                &installation_context,
+            )?;
+        }
+
         Ok(())
     }
 
@@ -0,0 +1,584 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, format_err};
+use crossterm::style::{Color, SetForegroundColor};
+use fs_err as fs;
+use serde::Serialize;
+use structopt::StructOpt;
+use walkdir::WalkDir;
+
+use crate::installation::package_id_file_name;
+use crate::lockfile::{LockPackage, Lockfile};
+use crate::manifest::Manifest;
+use crate::package_cache::PackageCache;
+use crate::package_contents::PackageContents;
+use crate::package_id::PackageId;
+use crate::package_integrity::{archive_hash, PackageIntegrity};
+use crate::package_source::{
+    PackageSource, PackageSourceMap, PackageSourceProvider, Registry, TestRegistry,
+};
+
+use super::install::InstallOutput;
+use super::{GlobalOptions, InstallSubcommand, OutputFormat, ReportedFailure};
+
+/// Check that the installed packages match the lockfile, without changing
+/// anything.
+#[derive(Debug, StructOpt)]
+pub struct VerifySubcommand {
+    /// Path to the project to verify the packages of.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// Reinstall the packages from the lockfile if any of them don't match.
+    #[structopt(long = "fix")]
+    pub fix: bool,
+}
+
+impl VerifySubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let manifest = Manifest::load(&self.project_path)?;
+        let lockfile = Lockfile::load(&self.project_path)?.ok_or_else(|| {
+            format_err!(
+                "There's no lockfile in {}. Run `wally install` to create one.",
+                self.project_path.display()
+            )
+        })?;
+
+        let package_cache = if global.test_registry {
+            None
+        } else {
+            Some(PackageCache::new()?)
+        };
+
+        // Registries are only set up once a package turns out not to be
+        // cached, so that verifying a project installed from the cache
+        // doesn't need the network.
+        let mut package_sources = None;
+        let mut fetch = |package_id: &PackageId| -> anyhow::Result<PackageContents> {
+            if let Some(cache) = &package_cache {
+                if let Some(contents) = cache.get(package_id)? {
+                    return Ok(contents);
+                }
+            }
+
+            if package_sources.is_none() {
+                package_sources = Some(registry_sources(&manifest, &global)?);
+            }
+
+            download(package_sources.as_ref().unwrap(), package_id)
+        };
+
+        let discrepancies = audit(
+            &self.project_path,
+            &manifest.package_id(),
+            &lockfile,
+            &mut fetch,
+        )?;
+
+        // An unchecked package is only a warning. Reinstalling it wouldn't
+        // give it a checksum.
+        let drifted = discrepancies
+            .iter()
+            .filter(|discrepancy| !discrepancy.is_warning())
+            .count();
+
+        if global.format == OutputFormat::Json {
+            let mut output = VerifyOutput {
+                verified: drifted == 0,
+                discrepancies: discrepancies.iter().map(DiscrepancyOutput::new).collect(),
+                reinstalled: None,
+            };
+
+            let fix = self.fix;
+
+            if drifted > 0 && fix {
+                output.reinstalled = Some(self.reinstall().install(global)?);
+            }
+
+            println!("{}", serde_json::to_string_pretty(&output)?);
+
+            return if drifted > 0 && !fix {
+                Err(ReportedFailure.into())
+            } else {
+                Ok(())
+            };
+        }
+
+        for discrepancy in &discrepancies {
+            println!(
+                "{}{:>11} {}{}",
+                SetForegroundColor(Color::Yellow),
+                discrepancy.label(),
+                SetForegroundColor(Color::Reset),
+                discrepancy
+            );
+        }
+
+        if drifted == 0 {
+            println!(
+                "{}   Verified {}installed packages match the lockfile",
+                SetForegroundColor(Color::DarkGreen),
+                SetForegroundColor(Color::Reset)
+            );
+            return Ok(());
+        }
+
+        if !self.fix {
+            bail!(
+                "{} installed package(s) don't match the lockfile. Run `wally verify --fix` to reinstall them.",
+                drifted
+            );
+        }
+
+        self.reinstall().run(global)
+    }
+
+    /// Only the lockfile is trusted here, so the packages are reinstalled
+    /// exactly as it has them.
+    fn reinstall(self) -> InstallSubcommand {
+        InstallSubcommand {
+            project_path: self.project_path,
+            locked: false,
+            preferences: None,
+            verify_integrity: false,
+            mirrors: Vec::new(),
+            search_mirrors: false,
+            jobs: None,
+            retries: None,
+            offline: false,
+            frozen: true,
+            no_hooks: false,
+        }
+    }
+}
+
+/// What `wally verify --format json` prints.
+#[derive(Debug, Serialize)]
+struct VerifyOutput {
+    /// Whether the installed packages match the lockfile, warnings aside.
+    verified: bool,
+    discrepancies: Vec<DiscrepancyOutput>,
+
+    /// What was installed by `--fix`, if anything had to be.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reinstalled: Option<InstallOutput>,
+}
+
+#[derive(Debug, Serialize)]
+struct DiscrepancyOutput {
+    kind: &'static str,
+
+    /// The package's ID, or for an extra package, the name of its directory.
+    package: String,
+
+    /// `warning` or `error`.
+    severity: &'static str,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    files: Vec<String>,
+
+    message: String,
+}
+
+impl DiscrepancyOutput {
+    fn new(discrepancy: &Discrepancy) -> Self {
+        let (kind, package, files) = match discrepancy {
+            Discrepancy::Missing(package_id) => ("missing", package_id.to_string(), Vec::new()),
+            Discrepancy::ChecksumMismatch(package_id) => {
+                ("checksum-mismatch", package_id.to_string(), Vec::new())
+            }
+            Discrepancy::Unchecked(package_id) => ("unchecked", package_id.to_string(), Vec::new()),
+            Discrepancy::Modified(package_id, files) => {
+                ("modified", package_id.to_string(), files.clone())
+            }
+            Discrepancy::Extra(name) => ("extra", name.clone(), Vec::new()),
+        };
+
+        Self {
+            kind,
+            package,
+            severity: if discrepancy.is_warning() {
+                "warning"
+            } else {
+                "error"
+            },
+            files,
+            message: discrepancy.to_string(),
+        }
+    }
+}
+
+/// A way that the installed packages differ from the lockfile.
+#[derive(Debug, PartialEq, Eq)]
+enum Discrepancy {
+    /// A locked package that isn't installed at all.
+    Missing(PackageId),
+
+    /// A package whose archive no longer matches the checksum it was locked
+    /// with.
+    ChecksumMismatch(PackageId),
+
+    /// A package with no checksum in the lockfile, so its archive can't be
+    /// checked. Its files are still checked against the archive.
+    Unchecked(PackageId),
+
+    /// A package with files that were changed, removed, or added since it
+    /// was installed.
+    Modified(PackageId, Vec<String>),
+
+    /// A package that's installed but not in the lockfile.
+    Extra(String),
+}
+
+impl Discrepancy {
+    fn is_warning(&self) -> bool {
+        matches!(self, Discrepancy::Unchecked(_))
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Discrepancy::Missing(_) => "Missing",
+            Discrepancy::ChecksumMismatch(_) => "Checksum",
+            Discrepancy::Unchecked(_) => "Unchecked",
+            Discrepancy::Modified(_, _) => "Modified",
+            Discrepancy::Extra(_) => "Extra",
+        }
+    }
+}
+
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Discrepancy::Missing(package_id) => write!(f, "{} isn't installed", package_id),
+            Discrepancy::ChecksumMismatch(package_id) => write!(
+                f,
+                "{} doesn't match the checksum in the lockfile",
+                package_id
+            ),
+            Discrepancy::Unchecked(package_id) => write!(
+                f,
+                "{} has no checksum in the lockfile. Run `wally migrate-lockfile` to add one.",
+                package_id
+            ),
+            Discrepancy::Modified(package_id, files) => {
+                write!(f, "{} has changed files: {}", package_id, files.join(", "))
+            }
+            Discrepancy::Extra(name) => write!(f, "{} isn't in the lockfile", name),
+        }
+    }
+}
+
+/// Compares the packages installed in the project to the ones in its
+/// lockfile, using `fetch` to get the archive each one was installed from.
+fn audit(
+    project_path: &Path,
+    root_id: &PackageId,
+    lockfile: &Lockfile,
+    fetch: &mut dyn FnMut(&PackageId) -> anyhow::Result<PackageContents>,
+) -> anyhow::Result<Vec<Discrepancy>> {
+    let index_dirs = ["Packages", "ServerPackages", "DevPackages"]
+        .iter()
+        .map(|dir| project_path.join(dir).join("_Index"));
+
+    // Every package directory in each index, by name, with the index it's in.
+    let mut installed = BTreeMap::new();
+
+    for index_dir in index_dirs {
+        if !index_dir.is_dir() {
+            continue;
+        }
+
+        for entry in fs::read_dir(&index_dir)? {
+            let entry = entry?;
+
+            if entry.file_type()?.is_dir() {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                installed.entry(name).or_insert_with(|| index_dir.clone());
+            }
+        }
+    }
+
+    let mut expected = BTreeSet::new();
+    let mut discrepancies = Vec::new();
+
+    for lock_package in &lockfile.packages {
+        let lock_package = match lock_package {
+            LockPackage::Registry(lock_package) => lock_package,
+
+            // Packages from a path or Git have no registry to check them
+            // against, so all that's checked is that they're installed.
+            local => {
+                let package_id = local.package_id();
+                let file_name = package_id_file_name(&package_id);
+
+                if installed.contains_key(&file_name) {
+                    expected.insert(file_name);
+                } else {
+                    discrepancies.push(Discrepancy::Missing(package_id));
+                }
+
+                continue;
+            }
+        };
+
+        let package_id = PackageId::new(lock_package.name.clone(), lock_package.version.clone());
+
+        if &package_id == root_id {
+            continue;
+        }
+
+        let file_name = package_id_file_name(&package_id);
+        let index_dir = match installed.get(&file_name) {
+            Some(index_dir) => index_dir,
+            None => {
+                discrepancies.push(Discrepancy::Missing(package_id));
+                continue;
+            }
+        };
+        expected.insert(file_name.clone());
+
+        let contents = fetch(&package_id)
+            .map_err(|err| format_err!("could not get {} to verify: {:#}", package_id, err))?;
+
+        match &lock_package.checksum {
+            Some(checksum) if *checksum != archive_hash(&contents) => {
+                discrepancies.push(Discrepancy::ChecksumMismatch(package_id));
+                continue;
+            }
+            Some(_) => {}
+            None => discrepancies.push(Discrepancy::Unchecked(package_id.clone())),
+        }
+
+        let package_dir = index_dir.join(&file_name).join(package_id.name().name());
+        let changed = changed_files(&contents, &package_dir)?;
+
+        if !changed.is_empty() {
+            discrepancies.push(Discrepancy::Modified(package_id, changed));
+        }
+    }
+
+    for name in installed.keys() {
+        if !expected.contains(name) {
+            discrepancies.push(Discrepancy::Extra(name.clone()));
+        }
+    }
+
+    Ok(discrepancies)
+}
+
+/// The files in `package_dir` that differ from the archive it was unpacked
+/// from, including files that aren't in the archive at all.
+fn changed_files(contents: &PackageContents, package_dir: &Path) -> anyhow::Result<Vec<String>> {
+    let integrity = PackageIntegrity::from_contents(contents)?;
+    let mut changed: BTreeSet<_> = integrity
+        .mismatched_files(package_dir)?
+        .into_iter()
+        .collect();
+
+    for entry in WalkDir::new(package_dir).min_depth(1) {
+        let entry = entry?;
+
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let relative = entry.path().strip_prefix(package_dir)?;
+        let name = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        if !integrity.files.contains_key(&name) {
+            changed.insert(name);
+        }
+    }
+
+    Ok(changed.into_iter().collect())
+}
+
+fn registry_sources(
+    manifest: &Manifest,
+    global: &GlobalOptions,
+) -> anyhow::Result<PackageSourceMap> {
+    let default_registry: Box<PackageSource> = if global.test_registry {
+        Box::new(PackageSource::TestRegistry(TestRegistry::new(
+            &manifest.package.registry,
+        )))
+    } else {
+        Box::new(PackageSource::Registry(Registry::from_registry_spec(
+            &manifest.package.registry,
+        )?))
+    };
+
+    let mut package_sources = PackageSourceMap::new(default_registry);
+    package_sources.add_fallbacks()?;
+
+    Ok(package_sources)
+}
+
+/// Downloads a package from the first source that has it.
+fn download(
+    package_sources: &PackageSourceMap,
+    package_id: &PackageId,
+) -> anyhow::Result<PackageContents> {
+    let mut last_error = None;
+
+    for source_id in package_sources.source_order() {
+        let source = package_sources.get(source_id).unwrap();
+
+        match source.download_package(package_id) {
+            Ok(contents) => return Ok(contents),
+            Err(err) => last_error = Some(err),
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| format_err!("Failed to find a source for {}", package_id)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::lockfile::RegistryLockPackage;
+    use crate::test_package::PackageBuilder;
+
+    fn install(project: &Path, package: &PackageBuilder) -> PathBuf {
+        let package_id = package.manifest().package_id();
+        let path = project
+            .join("Packages/_Index")
+            .join(package_id_file_name(&package_id))
+            .join(package_id.name().name());
+
+        fs::create_dir_all(&path).unwrap();
+        package.contents().unpack_into_path(&path).unwrap();
+
+        path
+    }
+
+    fn lock(lockfile: &mut Lockfile, package: &PackageBuilder, checksum: Option<String>) {
+        let package_id = package.manifest().package_id();
+        let (name, version) = package_id.into_parts();
+
+        lockfile
+            .packages
+            .push(LockPackage::Registry(RegistryLockPackage {
+                name,
+                version,
+                checksum,
+                features: Vec::new(),
+                dependencies: Vec::new(),
+            }));
+    }
+
+    #[test]
+    fn reports_drift() {
+        let project = tempfile::tempdir().unwrap();
+        let root = PackageBuilder::new("biff/root@0.1.0").into_manifest();
+
+        let intact = PackageBuilder::new("biff/intact@1.0.0").with_file("src/init.lua", "return 1");
+        let tampered =
+            PackageBuilder::new("biff/tampered@1.0.0").with_file("src/init.lua", "return 2");
+        let relocked = PackageBuilder::new("biff/relocked@1.0.0");
+        let unlocked = PackageBuilder::new("biff/unlocked@1.0.0");
+        let missing = PackageBuilder::new("biff/missing@1.0.0");
+        let packages = [&intact, &tampered, &relocked, &unlocked, &missing];
+
+        let mut lockfile = Lockfile::from_manifest(&root);
+
+        for package in &packages {
+            let checksum = match package.manifest().package.name.name() {
+                "relocked" => Some("not the checksum".to_owned()),
+                "unlocked" => None,
+                _ => Some(archive_hash(&package.contents())),
+            };
+
+            lock(&mut lockfile, package, checksum);
+        }
+
+        install(project.path(), &intact);
+        install(project.path(), &relocked);
+        install(project.path(), &unlocked);
+        install(project.path(), &PackageBuilder::new("biff/stray@1.0.0"));
+
+        let tampered_path = install(project.path(), &tampered);
+        fs::write(tampered_path.join("src/init.lua"), "return 3").unwrap();
+        fs::write(tampered_path.join("src/extra.lua"), "return 4").unwrap();
+
+        let mut fetch = |package_id: &PackageId| {
+            packages
+                .iter()
+                .find(|package| package.manifest().package_id() == *package_id)
+                .map(|package| package.contents())
+                .ok_or_else(|| format_err!("no such package"))
+        };
+
+        let discrepancies =
+            audit(project.path(), &root.package_id(), &lockfile, &mut fetch).unwrap();
+
+        let id = |id: &str| id.parse::<PackageId>().unwrap();
+
+        assert_eq!(
+            discrepancies,
+            vec![
+                Discrepancy::Modified(
+                    id("biff/tampered@1.0.0"),
+                    vec!["src/extra.lua".to_owned(), "src/init.lua".to_owned()]
+                ),
+                Discrepancy::ChecksumMismatch(id("biff/relocked@1.0.0")),
+                Discrepancy::Unchecked(id("biff/unlocked@1.0.0")),
+                Discrepancy::Missing(id("biff/missing@1.0.0")),
+                Discrepancy::Extra("biff_stray@1.0.0".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn clean_install_has_no_discrepancies() {
+        let project = tempfile::tempdir().unwrap();
+        let root = PackageBuilder::new("biff/root@0.1.0").into_manifest();
+        let package = PackageBuilder::new("biff/minimal@0.1.0");
+
+        let mut lockfile = Lockfile::from_manifest(&root);
+        lock(
+            &mut lockfile,
+            &package,
+            Some(archive_hash(&package.contents())),
+        );
+        install(project.path(), &package);
+
+        let mut fetch = |_: &PackageId| Ok::<_, anyhow::Error>(package.contents());
+        let discrepancies =
+            audit(project.path(), &root.package_id(), &lockfile, &mut fetch).unwrap();
+
+        assert_eq!(discrepancies, Vec::new());
+    }
+
+    #[test]
+    fn discrepancies_as_json() {
+        let package_id: PackageId = "biff/tampered@1.0.0".parse().unwrap();
+
+        assert_eq!(
+            serde_json::to_value(DiscrepancyOutput::new(&Discrepancy::Modified(
+                package_id.clone(),
+                vec!["src/init.lua".to_owned()]
+            )))
+            .unwrap(),
+            serde_json::json!({
+                "kind": "modified",
+                "package": "biff/tampered@1.0.0",
+                "severity": "error",
+                "files": ["src/init.lua"],
+                "message": "biff/tampered@1.0.0 has changed files: src/init.lua",
+            })
+        );
+
+        assert_eq!(
+            serde_json::to_value(DiscrepancyOutput::new(&Discrepancy::Unchecked(package_id)))
+                .unwrap()["severity"],
+            "warning"
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use anyhow::{bail, Context};
+use reqwest::StatusCode;
+use serde_json::json;
+use structopt::StructOpt;
+use url::Url;
+
+use crate::{
+    auth::AuthStore, http_client, manifest::Manifest, package_id::PackageId,
+    package_index::PackageIndex,
+};
+
+/// Yank a version of a package so that new installs won't pick it. Projects
+/// that already have it in their lockfile can still install it.
+#[derive(Debug, StructOpt)]
+pub struct YankSubcommand {
+    #[structopt(flatten)]
+    pub target: YankTarget,
+}
+
+impl YankSubcommand {
+    pub fn run(self) -> anyhow::Result<()> {
+        self.target.run(true)
+    }
+}
+
+/// Undo a yank, letting new installs pick the version again.
+#[derive(Debug, StructOpt)]
+pub struct UnyankSubcommand {
+    #[structopt(flatten)]
+    pub target: YankTarget,
+}
+
+impl UnyankSubcommand {
+    pub fn run(self) -> anyhow::Result<()> {
+        self.target.run(false)
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct YankTarget {
+    /// The package version to change, like `scope/name@1.0.0`.
+    pub package_id: PackageId,
+
+    /// Path to a project whose registry should be used.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// The registry index to use instead of the project's registry.
+    #[structopt(long = "registry")]
+    pub registry: Option<Url>,
+
+    /// Auth token to use
+    #[structopt(long = "token")]
+    pub token: Option<String>,
+
+    /// Don't ask for confirmation.
+    #[structopt(long = "yes", short = "y")]
+    pub yes: bool,
+}
+
+impl YankTarget {
+    fn run(self, yanked: bool) -> anyhow::Result<()> {
+        let (action, changed_key) = match yanked {
+            true => ("yank", "yanked"),
+            false => ("unyank", "unyanked"),
+        };
+
+        let registry = match self.registry {
+            Some(registry) => registry,
+            None => Url::parse(&Manifest::load(&self.project_path)?.package.registry)?,
+        };
+
+        let package_index = PackageIndex::new(&registry, None)?;
+        let api = package_index.config()?.api;
+
+        let auth = match self.token {
+            Some(token) => token,
+            None => AuthStore::get_token(api.as_str())?.with_context(|| {
+                format!(
+                    "Authentication is required to {}, use `wally login`",
+                    action
+                )
+            })?,
+        };
+
+        if !self.yes && !confirm(&format!("Really {} {}?", action, self.package_id))? {
+            println!("Nothing was changed. Pass --yes to skip this prompt.");
+            return Ok(());
+        }
+
+        let name = self.package_id.name();
+        let endpoint = format!("/v1/package-{}/{}/{}", action, name.scope(), name.name());
+
+        // The user named the exact version they want, so it can be yanked even
+        // if it's the only version of the package.
+        let client = http_client::blocking_client()?;
+        let response = client
+            .post(api.join(&endpoint)?)
+            .header("accept", "application/json")
+            .bearer_auth(auth)
+            .json(&json!({
+                "versions": [self.package_id.version()],
+                "force": true,
+            }))
+            .send()?;
+
+        let status = response.status();
+        let body: serde_json::Value = response.json().unwrap_or_default();
+        let message = body["message"]
+            .as_str()
+            .unwrap_or("no message from registry");
+
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => bail!(
+                "You can't {} {}: {}\nOnly owners of the {} scope can {} its packages. If you \
+                 are an owner, try logging in again with `wally login`.",
+                action,
+                self.package_id,
+                message,
+                name.scope(),
+                action
+            ),
+            status if !status.is_success() => bail!(
+                "Could not {} {} ({}): {}",
+                action,
+                self.package_id,
+                status,
+                message
+            ),
+            _ => {}
+        }
+
+        let changed = body[changed_key]
+            .as_array()
+            .map_or(false, |versions| !versions.is_empty());
+
+        if changed {
+            println!("{} is now {}", self.package_id, changed_key);
+        } else {
+            println!("{} was already {}", self.package_id, changed_key);
+        }
+
+        Ok(())
+    }
+}
+
+fn confirm(prompt: &str) -> anyhow::Result<bool> {
+    print!("{} [y/N] ", prompt);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
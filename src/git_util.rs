@@ -5,16 +5,62 @@
 //! https://github.com/rust-lang/cargo/blob/master/src/cargo/sources/git/utils.rs
 
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use anyhow::{bail, format_err, Context};
 use git2::build::RepoBuilder;
 use git2::{
-    Cred, CredentialType, FetchOptions, RemoteCallbacks, Repository, RepositoryInitOptions,
+    Cred, CredentialType, FetchOptions, Oid, ProxyOptions, RemoteCallbacks, Repository,
+    RepositoryInitOptions,
 };
 use url::Url;
 use walkdir::WalkDir;
 
+use crate::http_client::ProxyConfig;
+
+/// The proxy to fetch a repository through. Unlike reqwest, libgit2 only uses
+/// a proxy when it's told to.
+enum GitProxy {
+    /// Whatever Git's own config and the standard environment variables say.
+    Auto,
+    Direct,
+    Url(String),
+}
+
+impl GitProxy {
+    fn for_url(url: &str) -> anyhow::Result<Self> {
+        let proxy = match ProxyConfig::load()? {
+            Some(proxy) => proxy,
+            None => return Ok(GitProxy::Auto),
+        };
+
+        let host = Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_owned));
+
+        match host {
+            Some(host) if proxy.bypasses(&host) => Ok(GitProxy::Direct),
+            _ => Ok(GitProxy::Url(proxy.url_with_credentials()?)),
+        }
+    }
+
+    fn options(&self) -> ProxyOptions<'_> {
+        let mut options = ProxyOptions::new();
+
+        match self {
+            GitProxy::Auto => {
+                options.auto();
+            }
+            GitProxy::Direct => {}
+            GitProxy::Url(url) => {
+                options.url(url);
+            }
+        }
+
+        options
+    }
+}
+
 /// Based roughly on Cargo's approach to handling authentication, but very pared
 /// down.
 ///
@@ -122,8 +168,11 @@ pub fn clone(access_token: Option<String>, url: &Url, into: &Path) -> anyhow::Re
     let mut callbacks = RemoteCallbacks::new();
     callbacks.credentials(make_credentials_callback(access_token, &git_config));
 
+    let proxy = GitProxy::for_url(url.as_str())?;
+
     let mut fetch_options = FetchOptions::new();
     fetch_options.remote_callbacks(callbacks);
+    fetch_options.proxy_options(proxy.options());
 
     let mut builder = RepoBuilder::new();
     builder.fetch_options(fetch_options);
@@ -139,8 +188,6 @@ pub fn commit_and_push(
     index_path: &Path,
     modified_file: &Path,
 ) -> anyhow::Result<()> {
-    let git_config = git2::Config::open_default()?;
-
     // libgit2 only accepts a relative path
     let relative_path = modified_file.strip_prefix(&index_path).with_context(|| {
         format!(
@@ -153,6 +200,33 @@ pub fn commit_and_push(
     // git add $file
     let mut index = repository.index()?;
     index.add_path(relative_path)?;
+
+    commit_index_and_push(repository, access_token, message, &mut index)
+}
+
+/// Like `commit_and_push`, but commits every change in the working tree,
+/// including files that were moved or deleted.
+pub fn commit_all_and_push(
+    repository: &Repository,
+    access_token: Option<String>,
+    message: &str,
+) -> anyhow::Result<()> {
+    // git add --all
+    let mut index = repository.index()?;
+    index.add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None)?;
+    index.update_all(["*"].iter(), None)?;
+
+    commit_index_and_push(repository, access_token, message, &mut index)
+}
+
+fn commit_index_and_push(
+    repository: &Repository,
+    access_token: Option<String>,
+    message: &str,
+    index: &mut git2::Index,
+) -> anyhow::Result<()> {
+    let git_config = git2::Config::open_default()?;
+
     index.write()?;
     let tree_id = index.write_tree()?;
     let tree = repository.find_tree(tree_id)?;
@@ -191,24 +265,62 @@ pub fn commit_and_push(
 }
 
 pub fn update_index(access_token: Option<String>, repository: &Repository) -> anyhow::Result<()> {
+    let commit = fetch_index(access_token, repository)?;
+    reset_index(repository, commit)
+}
+
+/// Fetches the latest commit of the remote index without touching the
+/// working tree, returning its ID.
+pub fn fetch_index(access_token: Option<String>, repository: &Repository) -> anyhow::Result<Oid> {
+    fetch(access_token, repository, &["main"])?;
+
+    let commit = repository.find_reference("FETCH_HEAD")?.peel_to_commit()?;
+    Ok(commit.id())
+}
+
+/// Fetches every branch and tag of a repository without touching the working
+/// tree. Branches end up under `refs/remotes/origin`.
+pub fn fetch_all(access_token: Option<String>, repository: &Repository) -> anyhow::Result<()> {
+    fetch(
+        access_token,
+        repository,
+        &[
+            "+refs/heads/*:refs/remotes/origin/*",
+            "+refs/tags/*:refs/tags/*",
+        ],
+    )
+}
+
+fn fetch(
+    access_token: Option<String>,
+    repository: &Repository,
+    refspecs: &[&str],
+) -> anyhow::Result<()> {
     let git_config = git2::Config::open_default()?;
 
     let mut callbacks = RemoteCallbacks::new();
     callbacks.credentials(make_credentials_callback(access_token, &git_config));
 
+    let mut origin = repository.find_remote("origin")?;
+    let proxy = GitProxy::for_url(origin.url().unwrap_or_default())?;
+
     let mut fetch_options = FetchOptions::new();
     fetch_options.remote_callbacks(callbacks);
+    fetch_options.proxy_options(proxy.options());
 
-    repository
-        .find_remote("origin")?
-        .fetch(&["main"], Some(&mut fetch_options), None)
+    origin
+        .fetch(refspecs, Some(&mut fetch_options), None)
         .with_context(|| format!("could not fetch Git repository"))?;
 
+    Ok(())
+}
+
+/// "git reset --hard" to a commit, like one returned by `fetch_index`.
+pub fn reset_index(repository: &Repository, commit: Oid) -> anyhow::Result<()> {
     let mut options = git2::build::CheckoutBuilder::new();
     options.force();
 
-    // "git reset --hard" to the latest commit in the remote repo
-    let commit = repository.find_reference("FETCH_HEAD")?.peel_to_commit()?;
+    let commit = repository.find_commit(commit)?;
     repository
         .reset(
             &commit.into_object(),
@@ -219,3 +331,30 @@ pub fn update_index(access_token: Option<String>, repository: &Repository) -> an
 
     Ok(())
 }
+
+/// The commit the working tree is checked out at.
+pub fn head_commit(repository: &Repository) -> anyhow::Result<Oid> {
+    Ok(repository.head()?.peel_to_commit()?.id())
+}
+
+/// Every file added, changed, or removed between two commits, relative to the
+/// root of the repository. A renamed file is listed under both names.
+pub fn changed_files(repository: &Repository, from: Oid, to: Oid) -> anyhow::Result<Vec<PathBuf>> {
+    let from = repository.find_commit(from)?.tree()?;
+    let to = repository.find_commit(to)?.tree()?;
+    let diff = repository.diff_tree_to_tree(Some(&from), Some(&to), None)?;
+
+    let mut files = Vec::new();
+
+    for delta in diff.deltas() {
+        for file in &[delta.old_file(), delta.new_file()] {
+            if let Some(path) = file.path() {
+                if !files.iter().any(|listed| listed == path) {
+                    files.push(path.to_owned());
+                }
+            }
+        }
+    }
+
+    Ok(files)
+}
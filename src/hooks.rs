@@ -0,0 +1,117 @@
+//! Commands a project asks to have run once its packages are installed, like
+//! regenerating a sourcemap for Luau tooling.
+//!
+//! Only the root project's hooks are ever run. Hooks in the manifests of its
+//! dependencies are ignored, so installing a package can never run anything
+//! its author chose.
+
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+use anyhow::{bail, Context};
+use crossterm::style::{Color, SetForegroundColor};
+
+use crate::installation::InstallationContext;
+
+/// The directory the project itself is in.
+pub const PROJECT_PATH_VAR: &str = "WALLY_PROJECT_PATH";
+
+/// Where shared packages were installed, like `Packages`.
+pub const PACKAGES_PATH_VAR: &str = "WALLY_PACKAGES_PATH";
+
+/// Where server packages were installed, like `ServerPackages`.
+pub const SERVER_PACKAGES_PATH_VAR: &str = "WALLY_SERVER_PACKAGES_PATH";
+
+/// Where dev packages were installed, like `DevPackages`.
+pub const DEV_PACKAGES_PATH_VAR: &str = "WALLY_DEV_PACKAGES_PATH";
+
+/// Runs each of `hooks` in turn from the project's directory, with the
+/// install's output paths in the environment. Hooks are run by the system
+/// shell, `sh` or `cmd`, with nothing on their standard input.
+///
+/// The first hook to fail stops the rest and fails the install, with what the
+/// hook printed in the error.
+pub fn run_post_install_hooks(
+    hooks: &[String],
+    project_path: &Path,
+    installation: &InstallationContext,
+) -> anyhow::Result<()> {
+    if hooks.is_empty() {
+        return Ok(());
+    }
+
+    // Hooks run from the project's directory, so every path they're given
+    // has to work from there.
+    let current_dir = std::env::current_dir()?;
+    let project_path = current_dir.join(project_path);
+
+    for hook in hooks {
+        eprintln!(
+            "{}    Running {}post-install hook `{}`",
+            SetForegroundColor(Color::DarkGreen),
+            SetForegroundColor(Color::Reset),
+            hook
+        );
+
+        let output = shell(hook)
+            .current_dir(&project_path)
+            .env(PROJECT_PATH_VAR, &project_path)
+            .env(
+                PACKAGES_PATH_VAR,
+                current_dir.join(installation.shared_dir()),
+            )
+            .env(
+                SERVER_PACKAGES_PATH_VAR,
+                current_dir.join(installation.server_dir()),
+            )
+            .env(
+                DEV_PACKAGES_PATH_VAR,
+                current_dir.join(installation.dev_dir()),
+            )
+            .stdin(Stdio::null())
+            .output()
+            .with_context(|| format!("could not run post-install hook `{}`", hook))?;
+
+        if !output.status.success() {
+            bail!(
+                "post-install hook `{}` failed ({}){}",
+                hook,
+                output.status,
+                printed(&output)
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(windows)]
+fn shell(hook: &str) -> Command {
+    let mut command = Command::new("cmd");
+    command.arg("/C").arg(hook);
+    command
+}
+
+#[cfg(not(windows))]
+fn shell(hook: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(hook);
+    command
+}
+
+/// Everything a hook printed, to go after the error saying it failed.
+fn printed(output: &Output) -> String {
+    let mut printed = String::new();
+
+    for stream in [&output.stdout, &output.stderr] {
+        let text = String::from_utf8_lossy(stream);
+        let text = text.trim_end();
+
+        if !text.is_empty() {
+            printed.push('\n');
+            printed.push_str(text);
+        }
+    }
+
+    printed
+}
@@ -0,0 +1,218 @@
+//! A lock file that keeps several processes from writing to the same package
+//! index at once, like multiple registry instances sharing a file system.
+
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context};
+use fs_err::OpenOptions;
+
+/// How often to check whether a held lock has been released.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub struct IndexLock {
+    /// The path of the lock file. It should be on a file system shared by every
+    /// process writing to the index.
+    path: PathBuf,
+
+    /// How long to wait for the lock before giving up.
+    timeout: Duration,
+
+    /// How old a lock file can get before we assume the process holding it
+    /// crashed, and break it.
+    stale_after: Duration,
+}
+
+impl IndexLock {
+    pub fn new(path: impl Into<PathBuf>, timeout: Duration, stale_after: Duration) -> Self {
+        Self {
+            path: path.into(),
+            timeout,
+            stale_after,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Wait for the lock to become free and take it. The lock is released when
+    /// the returned guard is dropped.
+    pub fn acquire(&self) -> anyhow::Result<IndexLockGuard> {
+        let start = Instant::now();
+
+        loop {
+            let result = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&self.path);
+
+            match result {
+                Ok(mut file) => {
+                    // Record who holds the lock to make debugging easier.
+                    writeln!(file, "{}", std::process::id())?;
+
+                    return Ok(IndexLockGuard {
+                        path: self.path.clone(),
+                    });
+                }
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                    if is_stale(&self.path, self.stale_after)? {
+                        self.break_stale_lock()?;
+                        continue;
+                    }
+
+                    if start.elapsed() >= self.timeout {
+                        bail!(
+                            "timed out after {:?} waiting for index lock {}",
+                            self.timeout,
+                            self.path.display()
+                        );
+                    }
+
+                    thread::sleep(POLL_INTERVAL);
+                }
+                Err(err) => {
+                    return Err(err).with_context(|| {
+                        format!("could not create index lock {}", self.path.display())
+                    });
+                }
+            }
+        }
+    }
+
+    /// Several processes can find the same stale lock, so it's moved aside
+    /// under a name of our own rather than deleted, which only one of them can
+    /// do. Whoever moves it checks again that what they moved is stale, since
+    /// another process may have broken the lock and taken a fresh one since we
+    /// looked, and puts a fresh lock back.
+    fn break_stale_lock(&self) -> anyhow::Result<()> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let mut broken = self.path.clone().into_os_string();
+        broken.push(format!(".broken-{}-{}", std::process::id(), nanos));
+        let broken = PathBuf::from(broken);
+
+        match fs_err::rename(&self.path, &broken) {
+            Ok(()) => {}
+
+            // Someone else broke it first.
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!("could not break index lock {}", self.path.display())
+                });
+            }
+        }
+
+        if !is_stale(&broken, self.stale_after)? {
+            // Linking fails if the path is taken, so this can't replace a lock
+            // someone else has taken in the meantime.
+            if let Err(err) = fs_err::hard_link(&broken, &self.path) {
+                log::error!("Could not restore index lock: {:?}", err);
+            }
+
+            return remove_lock_file(&broken);
+        }
+
+        log::warn!("Breaking stale index lock {}", self.path.display());
+        remove_lock_file(&broken)
+    }
+}
+
+fn is_stale(path: &Path, stale_after: Duration) -> anyhow::Result<bool> {
+    let modified = match fs_err::metadata(path) {
+        Ok(metadata) => metadata.modified()?,
+
+        // The lock was released while we were looking at it.
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err.into()),
+    };
+
+    let age = SystemTime::now()
+        .duration_since(modified)
+        .unwrap_or_default();
+
+    Ok(age >= stale_after)
+}
+
+/// Proof that the index lock is held. Dropping it releases the lock.
+pub struct IndexLockGuard {
+    path: PathBuf,
+}
+
+impl Drop for IndexLockGuard {
+    fn drop(&mut self) {
+        if let Err(err) = remove_lock_file(&self.path) {
+            log::error!("Could not release index lock: {:?}", err);
+        }
+    }
+}
+
+fn remove_lock_file(path: &Path) -> anyhow::Result<()> {
+    match fs_err::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn held_lock_times_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock = IndexLock::new(
+            dir.path().join("index.lock"),
+            Duration::from_millis(200),
+            Duration::from_secs(60),
+        );
+
+        let guard = lock.acquire().unwrap();
+        assert!(lock.acquire().is_err());
+
+        drop(guard);
+        assert!(!lock.path().exists());
+        lock.acquire().unwrap();
+    }
+
+    #[test]
+    fn stale_lock_is_broken() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock = IndexLock::new(
+            dir.path().join("index.lock"),
+            Duration::from_millis(200),
+            Duration::from_secs(0),
+        );
+
+        fs_err::write(lock.path(), "12345").unwrap();
+        lock.acquire().unwrap();
+
+        // Nothing is left behind by breaking it.
+        assert_eq!(fs_err::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn fresh_lock_is_put_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock = IndexLock::new(
+            dir.path().join("index.lock"),
+            Duration::from_millis(200),
+            Duration::from_secs(60),
+        );
+
+        // As if another process had taken the lock between us finding it
+        // stale and moving it aside.
+        fs_err::write(lock.path(), "12345").unwrap();
+        lock.break_stale_lock().unwrap();
+
+        assert_eq!(fs_err::read_to_string(lock.path()).unwrap(), "12345");
+        assert_eq!(fs_err::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -1,11 +1,16 @@
 use std::{
+    collections::BTreeMap,
     fmt::Display,
     io,
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     time::Duration,
 };
 
-use anyhow::{bail, format_err};
+use anyhow::{bail, format_err, Context};
 use crossterm::style::{Color, SetForegroundColor};
 use fs_err as fs;
 use indicatif::{ProgressBar, ProgressStyle};
@@ -13,12 +18,22 @@ use indoc::{formatdoc, indoc};
 
 use crate::{
     manifest::Realm,
+    package_cache::{PackageCache, PartialDownload},
     package_contents::PackageContents,
     package_id::PackageId,
+    package_integrity::archive_hash,
     package_source::{PackageSourceMap, PackageSourceProvider},
     resolution::Resolve,
 };
 
+/// How many packages are downloaded at once by default. Kept small so that a
+/// large install doesn't trip a registry's rate limit.
+pub const DEFAULT_CONCURRENCY: usize = 8;
+
+/// How many times a package download that fails is retried by default before
+/// the install gives up.
+pub const DEFAULT_RETRIES: usize = 3;
+
 #[derive(Clone)]
 pub struct InstallationContext {
     shared_dir: PathBuf,
@@ -29,6 +44,11 @@ pub struct InstallationContext {
     server_path: Option<String>,
     dev_dir: PathBuf,
     dev_index_dir: PathBuf,
+    verify_integrity: bool,
+    package_cache: Option<PackageCache>,
+    concurrency: usize,
+    retries: usize,
+    checksums: Arc<BTreeMap<PackageId, String>>,
 }
 
 impl InstallationContext {
@@ -55,9 +75,63 @@ impl InstallationContext {
             server_path,
             dev_dir,
             dev_index_dir,
+            verify_integrity: false,
+            package_cache: None,
+            concurrency: DEFAULT_CONCURRENCY,
+            retries: DEFAULT_RETRIES,
+            checksums: Arc::default(),
         }
     }
 
+    /// Check downloaded packages against the integrity documents served by
+    /// their registry, failing the installation if any file doesn't match.
+    pub fn with_integrity_verification(mut self, verify_integrity: bool) -> Self {
+        self.verify_integrity = verify_integrity;
+        self
+    }
+
+    /// Keep a copy of every installed package in `package_cache`, so that it
+    /// can be installed again offline.
+    pub fn with_package_cache(mut self, package_cache: Option<PackageCache>) -> Self {
+        self.package_cache = package_cache;
+        self
+    }
+
+    /// Download and unpack at most `concurrency` packages at once.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Retry each package download that fails up to `retries` times, picking
+    /// up from what was already downloaded where the source allows it.
+    pub fn with_retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Check each downloaded package against the checksum it was locked with,
+    /// failing the installation if it doesn't match.
+    pub fn with_checksums(mut self, checksums: BTreeMap<PackageId, String>) -> Self {
+        self.checksums = Arc::new(checksums);
+        self
+    }
+
+    /// Where shared packages are installed.
+    pub fn shared_dir(&self) -> &Path {
+        &self.shared_dir
+    }
+
+    /// Where server packages are installed.
+    pub fn server_dir(&self) -> &Path {
+        &self.server_dir
+    }
+
+    /// Where dev packages are installed.
+    pub fn dev_dir(&self) -> &Path {
+        &self.dev_dir
+    }
+
     /// Delete the existing index, if it exists.
     pub fn clean(&self) -> anyhow::Result<()> {
         fn remove_ignore_not_found(path: &Path) -> io::Result<()> {
@@ -97,12 +171,19 @@ impl InstallationContext {
         );
         bar.enable_steady_tick(Duration::from_millis(100));
 
+        // Every package is unpacked into its own directory, so packages don't
+        // need to wait for their dependencies and can all be installed at
+        // once, bounded by the size of the blocking pool.
         let runtime = tokio::runtime::Builder::new_multi_thread()
-            .worker_threads(50)
+            .max_blocking_threads(self.concurrency)
             .enable_all()
             .build()
             .unwrap();
 
+        // Set once any package fails, so that packages that haven't started
+        // yet are skipped instead of installed for nothing.
+        let failed = Arc::new(AtomicBool::new(false));
+
         for package_id in resolved_copy.activated {
             log::debug!("Installing {}...", package_id);
 
@@ -141,13 +222,36 @@ impl InstallationContext {
                 }
 
                 let source_registry = resolved_copy.metadata[&package_id].source_registry.clone();
+                let source_checksum = resolved_copy.metadata[&package_id].checksum.clone();
                 let source_copy = sources.clone();
                 let context = self.clone();
                 let b = bar.clone();
+                let failed = Arc::clone(&failed);
 
-                let handle = runtime.spawn_blocking(move || {
+                let install_package = move || {
                     let package_source = source_copy.get(&source_registry).unwrap();
-                    let contents = package_source.download_package(&package_id)?;
+
+                    // Packages from a path or Git can change without their
+                    // version changing, so they're never cached.
+                    let package_cache = context
+                        .package_cache
+                        .as_ref()
+                        .filter(|_| !source_registry.is_local());
+
+                    // Downloads into the cache survive a failed install, so
+                    // that the next one can resume them.
+                    let mut download = match package_cache {
+                        Some(cache) => cache.partial(&package_id)?,
+                        None => PartialDownload::temporary()?,
+                    };
+
+                    download_with_retries(
+                        package_source,
+                        &package_id,
+                        &mut download,
+                        context.retries,
+                    )?;
+                    let contents = download.contents()?;
                     b.println(format!(
                         "{} Downloaded {}{}",
                         SetForegroundColor(Color::DarkGreen),
@@ -155,7 +259,81 @@ impl InstallationContext {
                         package_id,
                     ));
                     b.inc(1);
-                    context.write_contents(&package_id, &contents, package_realm)
+
+                    // The locked checksum catches archives that changed since
+                    // they were locked, the registry's catches archives that
+                    // were corrupted or tampered with on the way here.
+                    let actual = archive_hash(&contents);
+                    let expected_checksums =
+                        [context.checksums.get(&package_id), source_checksum.as_ref()];
+
+                    let mismatch = expected_checksums
+                        .iter()
+                        .flatten()
+                        .find(|expected| &actual != **expected);
+
+                    // A corrupt download is thrown away, so that the next
+                    // install downloads it again instead of resuming it.
+                    if let Some(expected) = mismatch {
+                        download.discard()?;
+                        bail!(
+                            "Checksum mismatch for {}: expected {}, got {}",
+                            package_id,
+                            expected,
+                            actual
+                        );
+                    }
+
+                    let integrity = if context.verify_integrity && !source_registry.is_local() {
+                        let integrity = package_source.download_integrity(&package_id)?;
+
+                        match &integrity {
+                            Some(integrity) => integrity
+                                .verify_archive(&contents)
+                                .with_context(|| format!("could not verify {}", package_id))?,
+                            None => log::warn!(
+                                "No integrity document is available for {}, skipping verification",
+                                package_id
+                            ),
+                        }
+
+                        integrity
+                    } else {
+                        None
+                    };
+
+                    let path = context.write_contents(&package_id, &contents, package_realm)?;
+
+                    if let Some(integrity) = integrity {
+                        integrity
+                            .verify_directory(&path)
+                            .with_context(|| format!("could not verify {}", package_id))?;
+                    }
+
+                    // Packages are only cached once they've installed cleanly.
+                    // A cache that can't be written to shouldn't stop the
+                    // install, though.
+                    if let Some(cache) = package_cache {
+                        if let Err(err) = cache.commit(&package_id, download) {
+                            log::warn!("Could not cache {}: {:#}", package_id, err);
+                        }
+                    }
+
+                    Ok::<_, anyhow::Error>(())
+                };
+
+                let handle = runtime.spawn_blocking(move || {
+                    if failed.load(Ordering::SeqCst) {
+                        return Ok(());
+                    }
+
+                    let result = install_package();
+
+                    if result.is_err() {
+                        failed.store(true, Ordering::SeqCst);
+                    }
+
+                    result
                 });
 
                 handles.push(handle);
@@ -164,13 +342,28 @@ impl InstallationContext {
 
         let num_packages = handles.len();
 
+        // Every task is waited on, even after one fails, so that nothing is
+        // still writing packages once this returns. Skipped packages succeed,
+        // so the first error is from a package that really failed.
+        let mut first_error = None;
+
         for handle in handles {
-            runtime
-                .block_on(handle)
-                .expect("Package failed to be installed.")?;
+            let result = match runtime.block_on(handle) {
+                Ok(result) => result,
+                Err(err) => Err(anyhow::Error::new(err).context("Package failed to be installed.")),
+            };
+
+            if let Err(err) = result {
+                first_error.get_or_insert(err);
+            }
         }
 
         bar.finish_and_clear();
+
+        if let Some(err) = first_error {
+            return Err(err);
+        }
+
         log::info!("Downloaded {} packages!", num_packages);
 
         Ok(())
@@ -330,7 +523,7 @@ impl InstallationContext {
         package_id: &PackageId,
         contents: &PackageContents,
         realm: Realm,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<PathBuf> {
         let mut path = match realm {
             Realm::Shared => self.shared_index_dir.clone(),
             Realm::Server => self.server_index_dir.clone(),
@@ -343,12 +536,44 @@ impl InstallationContext {
         fs::create_dir_all(&path)?;
         contents.unpack_into_path(&path)?;
 
-        Ok(())
+        Ok(path)
+    }
+}
+
+/// Downloads a package into `download`, retrying up to `retries` times with a
+/// growing delay between attempts.
+fn download_with_retries<S: PackageSourceProvider>(
+    source: &S,
+    package_id: &PackageId,
+    download: &mut PartialDownload,
+    retries: usize,
+) -> anyhow::Result<()> {
+    let mut attempt = 0;
+
+    loop {
+        match source.download_package_into(package_id, download) {
+            Ok(()) => return Ok(()),
+            Err(err) if attempt < retries => {
+                attempt += 1;
+                let delay = Duration::from_millis(500 << (attempt - 1).min(5));
+
+                log::warn!(
+                    "Downloading {} failed, retrying in {:?} ({} of {}): {:#}",
+                    package_id,
+                    delay,
+                    attempt,
+                    retries,
+                    err
+                );
+                std::thread::sleep(delay);
+            }
+            Err(err) => return Err(err),
+        }
     }
 }
 
 /// Creates a suitable name for use in file paths that refer to this package.
-fn package_id_file_name(id: &PackageId) -> String {
+pub(crate) fn package_id_file_name(id: &PackageId) -> String {
     format!(
         "{}_{}@{}",
         id.name().scope(),
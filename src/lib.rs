@@ -1,15 +1,22 @@
+pub mod api_info;
 pub mod auth;
 pub mod commands;
 pub mod git_util;
+pub mod hooks;
+pub mod http_client;
+pub mod index_lock;
 pub mod installation;
 pub mod lockfile;
 pub mod manifest;
+pub mod package_cache;
 pub mod package_contents;
 pub mod package_id;
 pub mod package_index;
+pub mod package_integrity;
 pub mod package_name;
 pub mod package_req;
 pub mod package_source;
+pub mod preferences;
 pub mod resolution;
 pub mod test_package;
 
@@ -1,5 +1,5 @@
 use std::collections::BTreeMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::{
     fs::read_to_string,
     io::{self, BufWriter, Write},
@@ -10,16 +10,21 @@ use semver::Version;
 use serde::{Deserialize, Serialize};
 
 use crate::package_id;
+use crate::package_source::PackageSourceId;
 use crate::{
     manifest::Manifest, package_id::PackageId, package_name::PackageName, resolution::Resolve,
 };
 
 pub const LOCKFILE_NAME: &str = "wally.lock";
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Lockfile {
     pub registry: String,
 
+    /// Overrides from the root manifest that were applied when resolving.
+    #[serde(default)]
+    pub overrides: BTreeMap<PackageName, Version>,
+
     #[serde(rename = "package")]
     pub packages: Vec<LockPackage>,
 }
@@ -46,6 +51,7 @@ impl Lockfile {
     pub fn from_manifest(manifest: &Manifest) -> Self {
         Self {
             registry: manifest.package.registry.clone(),
+            overrides: BTreeMap::new(),
             packages: Vec::new(),
         }
     }
@@ -61,16 +67,50 @@ impl Lockfile {
             ]
             .concat();
 
+            let metadata = resolve.metadata.get(package_id);
+            let name = package_id.name().clone();
+            let version = package_id.version().clone();
+
+            match metadata.map(|metadata| &metadata.source_registry) {
+                Some(PackageSourceId::PathDependency(path)) => {
+                    packages.push(LockPackage::Path(PathLockPackage {
+                        name,
+                        version,
+                        path: path.clone(),
+                        dependencies,
+                    }));
+                    continue;
+                }
+                Some(PackageSourceId::GitDependency { url, rev, commit }) => {
+                    packages.push(LockPackage::Git(GitLockPackage {
+                        name,
+                        version,
+                        git: url.clone(),
+                        rev: rev.clone(),
+                        commit: commit.clone(),
+                        dependencies,
+                    }));
+                    continue;
+                }
+                _ => {}
+            }
+
             packages.push(LockPackage::Registry(RegistryLockPackage {
-                name: package_id.name().clone(),
-                version: package_id.version().clone(),
-                checksum: None,
+                name,
+                version,
+                checksum: metadata.and_then(|metadata| metadata.checksum.clone()),
+                features: resolve
+                    .features
+                    .get(package_id)
+                    .map(|features| features.iter().cloned().collect())
+                    .unwrap_or_default(),
                 dependencies,
             }));
         }
 
         Self {
             registry: "test".to_owned(),
+            overrides: resolve.overrides.clone(),
             packages,
         }
     }
@@ -99,6 +139,14 @@ impl Lockfile {
         writeln!(file, "registry = \"{}\"", self.registry)?;
         writeln!(file, "")?;
 
+        if !self.overrides.is_empty() {
+            writeln!(file, "[overrides]")?;
+            for (name, version) in &self.overrides {
+                writeln!(file, "\"{}\" = \"{}\"", name, version)?;
+            }
+            writeln!(file, "")?;
+        }
+
         for lock_package in self.packages.iter() {
             writeln!(file, "[[package]]")?;
 
@@ -111,31 +159,47 @@ impl Lockfile {
                         writeln!(file, "checksum = \"{}\"", checksum)?;
                     }
 
-                    if registry_lock_package.dependencies.len() == 0 {
-                        writeln!(file, "dependencies = []")?;
-                    } else {
-                        writeln!(file, "dependencies = [")?;
-                        for dependency in registry_lock_package.dependencies.iter() {
-                            writeln!(file, "\t[\"{}\", \"{}\"],", dependency.0, dependency.1)?;
-                        }
-                        writeln!(file, "]")?;
+                    if !registry_lock_package.features.is_empty() {
+                        let features: Vec<_> = registry_lock_package
+                            .features
+                            .iter()
+                            .map(|feature| format!("\"{}\"", feature))
+                            .collect();
+                        writeln!(file, "features = [{}]", features.join(", "))?;
                     }
                 }
                 LockPackage::Git(git_lock_package) => {
                     writeln!(file, "name = \"{}\"", git_lock_package.name)?;
-                    writeln!(file, "rev = \"{}\"", git_lock_package.rev)?;
-                    writeln!(file, "commit = \"{}\"", git_lock_package.commit)?;
+                    writeln!(file, "version = \"{}\"", git_lock_package.version)?;
+                    writeln!(file, "git = {}", toml_string(&git_lock_package.git))?;
 
-                    if git_lock_package.dependencies.len() == 0 {
-                        writeln!(file, "dependencies = []")?;
-                    } else {
-                        writeln!(file, "dependencies = [")?;
-                        for dependency in git_lock_package.dependencies.iter() {
-                            writeln!(file, "\t\"{}\",", dependency)?;
-                        }
-                        writeln!(file, "]")?;
+                    if let Some(rev) = &git_lock_package.rev {
+                        writeln!(file, "rev = {}", toml_string(rev))?;
                     }
+
+                    writeln!(file, "commit = \"{}\"", git_lock_package.commit)?;
+                }
+                LockPackage::Path(path_lock_package) => {
+                    // Always with forward slashes, so that the lockfile
+                    // doesn't change between platforms.
+                    let path = path_lock_package.path.to_string_lossy().replace('\\', "/");
+
+                    writeln!(file, "name = \"{}\"", path_lock_package.name)?;
+                    writeln!(file, "version = \"{}\"", path_lock_package.version)?;
+                    writeln!(file, "path = {}", toml_string(&path))?;
+                }
+            }
+
+            let dependencies = lock_package.dependencies();
+
+            if dependencies.len() == 0 {
+                writeln!(file, "dependencies = []")?;
+            } else {
+                writeln!(file, "dependencies = [")?;
+                for dependency in dependencies.iter() {
+                    writeln!(file, "\t[\"{}\", \"{}\"],", dependency.0, dependency.1)?;
                 }
+                writeln!(file, "]")?;
             }
 
             writeln!(file, "")?;
@@ -146,39 +210,193 @@ impl Lockfile {
         Ok(())
     }
 
-    pub fn as_ids(&self) -> impl Iterator<Item = PackageId> + '_ {
-        self.packages.iter().map(|lock_package| match lock_package {
-            LockPackage::Registry(lock_package) => {
-                PackageId::new(lock_package.name.clone(), lock_package.version.clone())
+    /// Keeps the checksums that `previous` locked packages with, so that a
+    /// package whose archive changes on the registry is caught instead of
+    /// being locked again with its new checksum.
+    pub fn keep_checksums(&mut self, previous: &Lockfile) {
+        let previous = previous.checksums();
+
+        for lock_package in &mut self.packages {
+            if let LockPackage::Registry(lock_package) = lock_package {
+                let package_id =
+                    PackageId::new(lock_package.name.clone(), lock_package.version.clone());
+
+                if let Some(checksum) = previous.get(&package_id) {
+                    lock_package.checksum = Some(checksum.clone());
+                }
             }
-            LockPackage::Git(_) => todo!(),
-        })
+        }
+    }
+
+    /// The checksum of every package that was locked with one.
+    pub fn checksums(&self) -> BTreeMap<PackageId, String> {
+        self.packages
+            .iter()
+            .filter_map(|lock_package| match lock_package {
+                LockPackage::Registry(lock_package) => {
+                    let checksum = lock_package.checksum.clone()?;
+                    let package_id =
+                        PackageId::new(lock_package.name.clone(), lock_package.version.clone());
+                    Some((package_id, checksum))
+                }
+                LockPackage::Git(_) | LockPackage::Path(_) => None,
+            })
+            .collect()
+    }
+
+    pub fn as_ids(&self) -> impl Iterator<Item = PackageId> + '_ {
+        self.packages.iter().map(LockPackage::package_id)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Quotes a string for TOML, for values that could hold quotes or
+/// backslashes, like URLs and paths.
+fn toml_string(value: &str) -> String {
+    toml::Value::String(value.to_owned()).to_string()
+}
+
+// Registry packages have to come last: every other kind of package has all
+// of the fields a registry package needs, so an untagged enum would read them
+// all as registry packages otherwise.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum LockPackage {
-    Registry(RegistryLockPackage),
     Git(GitLockPackage),
+    Path(PathLockPackage),
+    Registry(RegistryLockPackage),
+}
+
+impl LockPackage {
+    pub fn package_id(&self) -> PackageId {
+        let (name, version) = match self {
+            LockPackage::Registry(lock_package) => (&lock_package.name, &lock_package.version),
+            LockPackage::Git(lock_package) => (&lock_package.name, &lock_package.version),
+            LockPackage::Path(lock_package) => (&lock_package.name, &lock_package.version),
+        };
+
+        PackageId::new(name.clone(), version.clone())
+    }
+
+    pub fn dependencies(&self) -> &[(String, PackageId)] {
+        match self {
+            LockPackage::Registry(lock_package) => &lock_package.dependencies,
+            LockPackage::Git(lock_package) => &lock_package.dependencies,
+            LockPackage::Path(lock_package) => &lock_package.dependencies,
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RegistryLockPackage {
     pub name: PackageName,
     pub version: Version,
     pub checksum: Option<String>,
 
+    /// The features that were turned on in this package when it was locked.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub features: Vec<String>,
+
     #[serde(default)]
     pub dependencies: Vec<(String, PackageId)>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A package depended on from a Git repository, pinned to the commit it was
+/// checked out at.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GitLockPackage {
-    pub name: String,
-    pub rev: String,
+    pub name: PackageName,
+    pub version: Version,
+    pub git: String,
+
+    /// The branch, tag, or commit the dependency asked for, if any.
+    #[serde(default)]
+    pub rev: Option<String>,
+
     pub commit: String,
 
     #[serde(default)]
-    pub dependencies: Vec<PackageId>,
+    pub dependencies: Vec<(String, PackageId)>,
+}
+
+/// A package depended on by path, relative to the project.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PathLockPackage {
+    pub name: PackageName,
+    pub version: Version,
+    pub path: PathBuf,
+
+    #[serde(default)]
+    pub dependencies: Vec<(String, PackageId)>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lock_package(id: &str, checksum: Option<&str>) -> LockPackage {
+        let (name, version) = id.parse::<PackageId>().unwrap().into_parts();
+
+        LockPackage::Registry(RegistryLockPackage {
+            name,
+            version,
+            checksum: checksum.map(str::to_owned),
+            features: Vec::new(),
+            dependencies: Vec::new(),
+        })
+    }
+
+    fn lockfile(packages: Vec<LockPackage>) -> Lockfile {
+        Lockfile {
+            registry: "test".to_owned(),
+            overrides: BTreeMap::new(),
+            packages,
+        }
+    }
+
+    #[test]
+    fn features_survive_a_round_trip() {
+        let mut package = lock_package("biff/knit@1.0.0", None);
+        if let LockPackage::Registry(package) = &mut package {
+            package.features = vec!["roact".to_owned(), "testez".to_owned()];
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        lockfile(vec![package, lock_package("biff/minimal@0.1.0", None)])
+            .save(dir.path())
+            .unwrap();
+
+        let loaded = Lockfile::load(dir.path()).unwrap().unwrap();
+        let features: Vec<_> = loaded
+            .packages
+            .iter()
+            .map(|package| match package {
+                LockPackage::Registry(package) => package.features.clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+
+        assert_eq!(features, vec![vec!["roact", "testez"], vec![]]);
+    }
+
+    #[test]
+    fn keeps_previously_locked_checksums() {
+        let previous = lockfile(vec![
+            lock_package("biff/minimal@0.1.0", Some("locked")),
+            lock_package("biff/other@0.1.0", None),
+        ]);
+
+        let mut lockfile = lockfile(vec![
+            lock_package("biff/minimal@0.1.0", Some("from-registry")),
+            lock_package("biff/other@0.1.0", Some("from-registry")),
+            lock_package("biff/new@0.1.0", None),
+        ]);
+        lockfile.keep_checksums(&previous);
+
+        let checksums = lockfile.checksums();
+        let checksum = |id: &str| checksums.get(&id.parse().unwrap()).map(String::as_str);
+
+        assert_eq!(checksum("biff/minimal@0.1.0"), Some("locked"));
+        assert_eq!(checksum("biff/other@0.1.0"), Some("from-registry"));
+        assert_eq!(checksum("biff/new@0.1.0"), None);
+    }
 }
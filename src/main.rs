@@ -2,7 +2,7 @@ use std::process::exit;
 
 use structopt::StructOpt;
 
-use libwally::Args;
+use libwally::{resolution::ResolveConflict, Args, OutputFormat, ReportedFailure};
 
 fn main() {
     let args = Args::from_args();
@@ -23,8 +23,35 @@ fn main() {
         .format_indent(Some(8))
         .init();
 
+    let format = args.global.format;
+
     if let Err(err) = args.run() {
-        eprintln!("{:?}", err);
+        match format {
+            _ if err.is::<ReportedFailure>() => {}
+            OutputFormat::Human => eprintln!("{:?}", err),
+            OutputFormat::Json => {
+                let causes: Vec<_> = err.chain().skip(1).map(ToString::to_string).collect();
+                let mut error = serde_json::json!({
+                    "error": {
+                        "message": err.to_string(),
+                        "causes": causes,
+                    }
+                });
+
+                // Conflicts between version requirements are spelled out, so
+                // that scripts don't have to pick apart the message.
+                let conflict = err
+                    .chain()
+                    .find_map(|cause| cause.downcast_ref::<ResolveConflict>());
+
+                if let Some(conflict) = conflict {
+                    error["error"]["conflict"] = serde_json::json!(conflict);
+                }
+
+                println!("{:#}", error);
+            }
+        }
+
         exit(1);
     }
 }
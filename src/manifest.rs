@@ -1,8 +1,12 @@
-use std::collections::BTreeMap;
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use semver::Version;
+use serde::de::{Deserializer, Error as _};
+use serde::ser::Serializer;
 use serde::{Deserialize, Serialize};
 
 use crate::package_id::PackageId;
@@ -21,13 +25,42 @@ pub struct Manifest {
     pub place: PlaceInfo,
 
     #[serde(default)]
-    pub dependencies: BTreeMap<String, PackageReq>,
+    pub dependencies: BTreeMap<String, Dependency>,
 
     #[serde(default)]
-    pub server_dependencies: BTreeMap<String, PackageReq>,
+    pub server_dependencies: BTreeMap<String, Dependency>,
 
     #[serde(default)]
-    pub dev_dependencies: BTreeMap<String, PackageReq>,
+    pub dev_dependencies: BTreeMap<String, Dependency>,
+
+    /// Named sets of optional dependencies, which are only installed when a
+    /// package depending on this one turns the feature on. Any dependency
+    /// listed in a feature is optional. Features can also list other
+    /// features, which are turned on with them.
+    ///
+    /// Example: `roact = ["Roact", "RoactHooks"]`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub features: BTreeMap<String, Vec<String>>,
+
+    /// Features to turn on in this package's dependencies, by alias.
+    ///
+    /// Example: `Knit = ["roact"]`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub dependency_features: BTreeMap<String, Vec<String>>,
+
+    /// Versions to force packages to during resolution, regardless of the
+    /// versions that packages depending on them ask for. Only the overrides of
+    /// the root package are used.
+    ///
+    /// Example: `"biff/minimal" = "1.0.2"`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub overrides: BTreeMap<PackageName, Version>,
+
+    /// Commands to run when installing this package's dependencies. Only the
+    /// hooks of the project being installed are run, never those of its
+    /// dependencies.
+    #[serde(default, skip_serializing_if = "Hooks::is_empty")]
+    pub hooks: Hooks,
 }
 
 impl Manifest {
@@ -52,6 +85,210 @@ impl Manifest {
     pub fn package_id(&self) -> PackageId {
         PackageId::new(self.package.name.clone(), self.package.version.clone())
     }
+
+    /// Every dependency in every realm, with its alias.
+    pub fn all_dependencies(&self) -> impl Iterator<Item = (&String, &Dependency)> {
+        self.dependencies
+            .iter()
+            .chain(&self.server_dependencies)
+            .chain(&self.dev_dependencies)
+    }
+
+    fn has_dependency(&self, alias: &str) -> bool {
+        self.dependencies.contains_key(alias)
+            || self.server_dependencies.contains_key(alias)
+            || self.dev_dependencies.contains_key(alias)
+    }
+
+    /// The aliases of dependencies that are only installed when one of the
+    /// package's features is turned on.
+    pub fn optional_dependencies(&self) -> BTreeSet<&str> {
+        self.features
+            .values()
+            .flatten()
+            .map(String::as_str)
+            .filter(|entry| self.has_dependency(entry))
+            .collect()
+    }
+
+    /// Every feature that turning on `requested` turns on, including the
+    /// features they list.
+    pub fn expand_features<'a, I>(&self, requested: I) -> anyhow::Result<BTreeSet<String>>
+    where
+        I: IntoIterator<Item = &'a String>,
+    {
+        let mut enabled = BTreeSet::new();
+        let mut to_visit: Vec<&String> = requested.into_iter().collect();
+
+        while let Some(feature) = to_visit.pop() {
+            if enabled.contains(feature) {
+                continue;
+            }
+
+            let entries = match self.features.get(feature) {
+                Some(entries) => entries,
+                None => bail!("{} has no feature named \"{}\"", self.package_id(), feature),
+            };
+
+            for entry in entries {
+                if self.features.contains_key(entry) {
+                    to_visit.push(entry);
+                } else if !self.has_dependency(entry) {
+                    bail!(
+                        "feature \"{}\" of {} lists \"{}\", which is neither one of its \
+                         dependencies nor another feature",
+                        feature,
+                        self.package_id(),
+                        entry
+                    );
+                }
+            }
+
+            enabled.insert(feature.clone());
+        }
+
+        Ok(enabled)
+    }
+}
+
+/// Where a dependency comes from. Most dependencies are asked for by version
+/// from the registry, but while working on several packages at once, one can
+/// be used straight from a directory or a Git repository instead. Packages
+/// with path or Git dependencies can't be published.
+///
+/// Examples:
+/// * `"roblox/roact@1.4.2"`
+/// * `{ path = "../my-package" }`
+/// * `{ git = "https://github.com/biff/my-package.git", rev = "main" }`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Dependency {
+    Registry(PackageReq),
+
+    /// A package in a directory, relative to the manifest depending on it.
+    Path(PathBuf),
+
+    /// A package at the root of a Git repository. `rev` is a branch, tag, or
+    /// commit, or the repository's default branch if it isn't given.
+    Git {
+        url: String,
+        rev: Option<String>,
+    },
+}
+
+impl Dependency {
+    /// The requirement a registry dependency has, or `None` for path and Git
+    /// dependencies.
+    pub fn registry(&self) -> Option<&PackageReq> {
+        match self {
+            Dependency::Registry(req) => Some(req),
+            _ => None,
+        }
+    }
+}
+
+impl From<PackageReq> for Dependency {
+    fn from(req: PackageReq) -> Self {
+        Dependency::Registry(req)
+    }
+}
+
+impl fmt::Display for Dependency {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Dependency::Registry(req) => write!(formatter, "{}", req),
+            Dependency::Path(path) => write!(formatter, "path {}", path.display()),
+            Dependency::Git {
+                url,
+                rev: Some(rev),
+            } => write!(formatter, "{}#{}", url, rev),
+            Dependency::Git { url, rev: None } => write!(formatter, "{}", url),
+        }
+    }
+}
+
+/// How a dependency is written in a manifest: a package requirement, or a
+/// table for path and Git dependencies.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum DependencySpec {
+    Req(String),
+    Table(DependencyTable),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DependencyTable {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    path: Option<PathBuf>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    git: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rev: Option<String>,
+}
+
+impl Serialize for Dependency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Dependency::Registry(req) => req.serialize(serializer),
+            Dependency::Path(path) => DependencySpec::Table(DependencyTable {
+                path: Some(path.clone()),
+                git: None,
+                rev: None,
+            })
+            .serialize(serializer),
+            Dependency::Git { url, rev } => DependencySpec::Table(DependencyTable {
+                path: None,
+                git: Some(url.clone()),
+                rev: rev.clone(),
+            })
+            .serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Dependency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let table = match DependencySpec::deserialize(deserializer).map_err(|_| {
+            D::Error::custom(
+                "a dependency is either a package requirement of the form \
+                 SCOPE/NAME@VERSION_REQ, { path = \"...\" }, or { git = \"...\" }",
+            )
+        })? {
+            DependencySpec::Req(req) => {
+                return req
+                    .parse()
+                    .map(Dependency::Registry)
+                    .map_err(D::Error::custom)
+            }
+            DependencySpec::Table(table) => table,
+        };
+
+        match table {
+            DependencyTable {
+                path: Some(path),
+                git: None,
+                rev: None,
+            } => Ok(Dependency::Path(path)),
+            DependencyTable {
+                path: None,
+                git: Some(url),
+                rev,
+            } => Ok(Dependency::Git { url, rev }),
+            DependencyTable {
+                path: None,
+                git: None,
+                ..
+            } => Err(D::Error::custom(
+                "a dependency table needs either a path or a git URL",
+            )),
+            _ => Err(D::Error::custom(
+                "a dependency can come from a path or from Git, but not both, and only Git \
+                 dependencies take a rev",
+            )),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -71,6 +308,13 @@ pub struct Package {
     /// Example: `https://github.com/UpliftGames/wally-test-index`
     pub registry: String,
 
+    /// Mirrors of `registry` to download packages from, in order, when it
+    /// can't be reached. Packages are still locked to `registry`.
+    ///
+    /// Example: ["https://github.com/UpliftGames/wally-test-index-mirror"]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<String>,
+
     /// The realms (`shared`, `server`, etc) that this package can be used in.
     ///
     /// Packages in the `shared` realm can only depend on other `shared`
@@ -96,11 +340,13 @@ pub struct Package {
     #[serde(default)]
     pub authors: Vec<String>,
 
-    /// A list of paths to include in the package. Glob patterns are supported.
+    /// A list of paths to include in the package. Glob patterns are supported,
+    /// and work like `.gitignore` patterns.
     ///
     /// By default all directories and files are included except files generated
     /// by wally and hidden files/directories. If include is specified then only
-    /// files matching patterns in the include list will be included.
+    /// files matching patterns in the include list will be included. The
+    /// manifest and license file are always included.
     ///
     /// If include is unspecified and a .gitignore file exists then those patterns
     /// will be respected and wally will also ignore those files.
@@ -167,6 +413,24 @@ impl Default for PlaceInfo {
     }
 }
 
+/// Commands a project runs around installs, from its `[hooks]` table.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Hooks {
+    /// Commands run by the system shell, in order, after every successful
+    /// install.
+    ///
+    /// Example: `post-install = ["rojo sourcemap default.project.json -o sourcemap.json"]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_install: Vec<String>,
+}
+
+impl Hooks {
+    pub fn is_empty(&self) -> bool {
+        self.post_install.is_empty()
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Realm {
@@ -175,6 +439,22 @@ pub enum Realm {
     Dev,
 }
 
+impl FromStr for Realm {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "shared" => Ok(Realm::Shared),
+            "server" => Ok(Realm::Server),
+            "dev" => Ok(Realm::Dev),
+            _ => anyhow::bail!(
+                "unknown realm \"{}\", expected shared, server, or dev",
+                value
+            ),
+        }
+    }
+}
+
 impl Realm {
     pub fn is_dependency_valid(dep_type: Self, dep_realm: Self) -> bool {
         use Realm::*;
@@ -185,3 +465,62 @@ impl Realm {
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(dependencies: &str) -> anyhow::Result<Manifest> {
+        let manifest = format!(
+            "[package]\nname = \"biff/game\"\nversion = \"0.1.0\"\nregistry = \"test\"\n\
+             realm = \"shared\"\n\n[dependencies]\n{}",
+            dependencies
+        );
+
+        Manifest::from_slice(manifest.as_bytes())
+    }
+
+    #[test]
+    fn dependency_kinds() {
+        let manifest = parse(
+            r#"
+            Minimal = "biff/minimal@1.0.0"
+            Utils = { path = "../utils" }
+            Signal = { git = "https://github.com/biff/signal.git", rev = "main" }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            manifest.dependencies["Minimal"],
+            Dependency::Registry("biff/minimal@1.0.0".parse().unwrap())
+        );
+        assert_eq!(
+            manifest.dependencies["Utils"],
+            Dependency::Path(PathBuf::from("../utils"))
+        );
+        assert_eq!(
+            manifest.dependencies["Signal"],
+            Dependency::Git {
+                url: "https://github.com/biff/signal.git".to_owned(),
+                rev: Some("main".to_owned()),
+            }
+        );
+
+        let round_trip = Manifest::from_slice(
+            toml::Value::try_from(&manifest)
+                .unwrap()
+                .to_string()
+                .as_bytes(),
+        );
+        assert_eq!(round_trip.unwrap().dependencies, manifest.dependencies);
+    }
+
+    #[test]
+    fn invalid_dependency_tables() {
+        assert!(parse(r#"Utils = { path = "../utils", git = "https://example.com" }"#).is_err());
+        assert!(parse(r#"Utils = { path = "../utils", rev = "main" }"#).is_err());
+        assert!(parse(r#"Utils = { version = "1.0.0" }"#).is_err());
+        assert!(parse(r#"Utils = {}"#).is_err());
+    }
+}
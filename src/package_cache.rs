@@ -0,0 +1,439 @@
+//! Keeps a copy of every package archive Wally installs, so that a project can
+//! be installed again later without the network.
+//!
+//! Published package versions never change, so archives are looked up by
+//! package ID alone and never expire. Archives that haven't been used in a
+//! while can be pruned, since they can always be downloaded again.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Context};
+use fs_err as fs;
+use tempfile::NamedTempFile;
+use walkdir::WalkDir;
+
+use crate::package_contents::PackageContents;
+use crate::package_id::PackageId;
+
+#[derive(Debug, Clone)]
+pub struct PackageCache {
+    path: PathBuf,
+}
+
+impl PackageCache {
+    /// The cache in the user's cache directory, next to cached indexes.
+    pub fn new() -> anyhow::Result<Self> {
+        Ok(Self::at(cache_root()?.join("packages")))
+    }
+
+    /// A cache kept in the given directory.
+    pub fn at<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The cached archive of a package, if there is one. Archives are marked
+    /// as used whenever they're read, so that pruning keeps them.
+    pub fn get(&self, package_id: &PackageId) -> anyhow::Result<Option<PackageContents>> {
+        let path = self.package_path(package_id);
+
+        match fs::read(&path) {
+            Ok(data) => {
+                mark_used(&path);
+                Ok(Some(PackageContents::from_buffer(data)))
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => {
+                Err(err).with_context(|| format!("could not read {} from cache", package_id))
+            }
+        }
+    }
+
+    /// Adds a package's archive to the cache, unless it's already there.
+    ///
+    /// The archive is written to a temporary file first, so that an install
+    /// that's interrupted can't leave a partial archive behind.
+    pub fn insert(&self, package_id: &PackageId, contents: &PackageContents) -> anyhow::Result<()> {
+        let path = self.package_path(package_id);
+
+        if path.exists() {
+            return Ok(());
+        }
+
+        let dir = path
+            .parent()
+            .expect("cached packages are always in a directory");
+        fs::create_dir_all(dir)?;
+
+        let mut file = NamedTempFile::new_in(dir)?;
+        file.write_all(contents.data())?;
+        file.persist(&path)
+            .with_context(|| format!("could not add {} to cache", package_id))?;
+
+        Ok(())
+    }
+
+    /// Opens the download of a package's archive, picking up whatever an
+    /// earlier, interrupted install already downloaded.
+    pub fn partial(&self, package_id: &PackageId) -> anyhow::Result<PartialDownload> {
+        let path = self.partial_path(package_id);
+
+        let dir = path
+            .parent()
+            .expect("cached packages are always in a directory");
+        fs::create_dir_all(dir)?;
+
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&path)
+            .with_context(|| format!("could not open {}", path.display()))?;
+        let len = file.seek(SeekFrom::End(0))?;
+
+        Ok(PartialDownload {
+            file,
+            path: Some(path),
+            len,
+        })
+    }
+
+    /// Moves a finished download into the cache. This should only be called
+    /// once the archive has been checked, so that a download that was cut
+    /// short or corrupted never ends up in the cache.
+    pub fn commit(&self, package_id: &PackageId, download: PartialDownload) -> anyhow::Result<()> {
+        let path = self.package_path(package_id);
+
+        let PartialDownload {
+            mut file,
+            path: partial_path,
+            ..
+        } = download;
+
+        let partial_path = match partial_path {
+            Some(partial_path) => partial_path,
+            None => {
+                let mut data = Vec::new();
+                file.seek(SeekFrom::Start(0))?;
+                file.read_to_end(&mut data)?;
+                return self.insert(package_id, &PackageContents::from_buffer(data));
+            }
+        };
+
+        // The file has to be closed before it can be renamed on Windows.
+        drop(file);
+
+        if path.exists() {
+            fs::remove_file(partial_path)?;
+        } else {
+            fs::rename(partial_path, &path)
+                .with_context(|| format!("could not add {} to cache", package_id))?;
+        }
+
+        Ok(())
+    }
+
+    /// Every archive in the cache, along with downloads that haven't finished.
+    pub fn entries(&self) -> anyhow::Result<Vec<CacheEntry>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut entries = Vec::new();
+
+        for entry in WalkDir::new(&self.path).min_depth(3).max_depth(3) {
+            let entry = entry?;
+            let file_name = entry.file_name().to_string_lossy();
+
+            let partial = file_name.ends_with(".zip.partial");
+            if !partial && !file_name.ends_with(".zip") {
+                continue;
+            }
+
+            let metadata = entry.metadata()?;
+            entries.push(CacheEntry {
+                path: entry.path().to_owned(),
+                size: metadata.len(),
+                last_used: metadata.modified()?,
+                partial,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    /// Removes archives and unfinished downloads that haven't been used for
+    /// at least `older_than`.
+    ///
+    /// Installs mark archives as used as they read them, and keep writing to
+    /// their downloads, so the ones they're working with are never old enough
+    /// to be pruned. An archive that an install opens just before it's
+    /// removed can still be read on Unix, and can't be removed at all on
+    /// Windows, so it's skipped.
+    pub fn prune(&self, older_than: Duration) -> anyhow::Result<Vec<CacheEntry>> {
+        let now = SystemTime::now();
+        let mut removed = Vec::new();
+
+        for entry in self.entries()? {
+            let age = now.duration_since(entry.last_used).unwrap_or_default();
+            if age < older_than {
+                continue;
+            }
+
+            match fs::remove_file(&entry.path) {
+                Ok(()) => removed.push(entry),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => log::warn!("Could not prune {}: {}", entry.path.display(), err),
+            }
+        }
+
+        Ok(removed)
+    }
+
+    fn package_path(&self, package_id: &PackageId) -> PathBuf {
+        self.path
+            .join(package_id.name().scope())
+            .join(package_id.name().name())
+            .join(format!("{}.zip", package_id.version()))
+    }
+
+    fn partial_path(&self, package_id: &PackageId) -> PathBuf {
+        self.path
+            .join(package_id.name().scope())
+            .join(package_id.name().name())
+            .join(format!("{}.zip.partial", package_id.version()))
+    }
+}
+
+/// Where Wally keeps everything it caches: package archives, and a copy of
+/// each registry's index.
+pub fn cache_root() -> anyhow::Result<PathBuf> {
+    Ok(dirs::cache_dir()
+        .ok_or_else(|| anyhow!("could not find cache directory"))?
+        .join("wally"))
+}
+
+/// Records that an archive was just used, by moving its modification time
+/// forward. Access times aren't used, since many systems don't keep them up
+/// to date. Failing to record it only means the archive might be pruned
+/// sooner.
+fn mark_used(path: &Path) {
+    let result = std::fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .and_then(|file| file.set_modified(SystemTime::now()));
+
+    if let Err(err) = result {
+        log::debug!("Could not mark {} as used: {}", path.display(), err);
+    }
+}
+
+/// An archive in the package cache, or a download that hasn't finished.
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub path: PathBuf,
+    pub size: u64,
+
+    /// When the archive was last installed from, or the download was last
+    /// written to.
+    pub last_used: SystemTime,
+
+    pub partial: bool,
+}
+
+/// A package archive that's being downloaded. It's written to a file as it
+/// arrives, so a download that fails partway through can be resumed instead
+/// of started over.
+pub struct PartialDownload {
+    file: std::fs::File,
+
+    /// Where the download is kept in the package cache, if it's kept there.
+    /// Downloads outside the cache are lost once they're dropped.
+    path: Option<PathBuf>,
+
+    len: u64,
+}
+
+impl PartialDownload {
+    /// A download that isn't kept anywhere, for installs without a package
+    /// cache.
+    pub fn temporary() -> anyhow::Result<Self> {
+        Ok(Self {
+            file: tempfile::tempfile()?,
+            path: None,
+            len: 0,
+        })
+    }
+
+    /// How many bytes have been downloaded so far.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Throws away everything downloaded so far.
+    pub fn restart(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.len = 0;
+        Ok(())
+    }
+
+    /// Deletes the download, because it doesn't match what was expected and
+    /// resuming it would only make it longer.
+    pub fn discard(self) -> anyhow::Result<()> {
+        let Self { file, path, .. } = self;
+        drop(file);
+
+        if let Some(path) = path {
+            fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Everything downloaded so far.
+    pub fn contents(&mut self) -> anyhow::Result<PackageContents> {
+        let mut data = Vec::with_capacity(self.len as usize);
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_to_end(&mut data)?;
+        self.file.seek(SeekFrom::End(0))?;
+
+        Ok(PackageContents::from_buffer(data))
+    }
+}
+
+impl Write for PartialDownload {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.len += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = PackageCache::at(dir.path());
+        let package_id: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+
+        assert!(cache.get(&package_id).unwrap().is_none());
+
+        let contents = PackageContents::from_buffer(b"archive".to_vec());
+        cache.insert(&package_id, &contents).unwrap();
+
+        let cached = cache.get(&package_id).unwrap().unwrap();
+        assert_eq!(cached.data(), b"archive");
+    }
+
+    #[test]
+    fn insert_keeps_existing_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = PackageCache::at(dir.path());
+        let package_id: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+
+        cache
+            .insert(
+                &package_id,
+                &PackageContents::from_buffer(b"first".to_vec()),
+            )
+            .unwrap();
+        cache
+            .insert(
+                &package_id,
+                &PackageContents::from_buffer(b"second".to_vec()),
+            )
+            .unwrap();
+
+        assert_eq!(cache.get(&package_id).unwrap().unwrap().data(), b"first");
+    }
+
+    #[test]
+    fn partial_downloads_resume() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = PackageCache::at(dir.path());
+        let package_id: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+
+        let mut download = cache.partial(&package_id).unwrap();
+        download.write_all(b"arch").unwrap();
+        drop(download);
+
+        let mut download = cache.partial(&package_id).unwrap();
+        assert_eq!(download.len(), 4);
+        download.write_all(b"ive").unwrap();
+        assert_eq!(download.contents().unwrap().data(), b"archive");
+
+        // Nothing is cached until the download is committed.
+        assert!(cache.get(&package_id).unwrap().is_none());
+
+        cache.commit(&package_id, download).unwrap();
+        assert_eq!(cache.get(&package_id).unwrap().unwrap().data(), b"archive");
+        assert!(cache.partial(&package_id).unwrap().is_empty());
+    }
+
+    #[test]
+    fn prune_keeps_recently_used_archives() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = PackageCache::at(dir.path());
+        let old: PackageId = "biff/old@0.1.0".parse().unwrap();
+        let used: PackageId = "biff/used@0.1.0".parse().unwrap();
+        let recent: PackageId = "biff/recent@0.1.0".parse().unwrap();
+
+        let contents = PackageContents::from_buffer(b"archive".to_vec());
+        for package_id in &[&old, &used, &recent] {
+            cache.insert(package_id, &contents).unwrap();
+        }
+
+        let month_ago = SystemTime::now() - Duration::from_secs(30 * 24 * 60 * 60);
+        for package_id in &[&old, &used] {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .open(cache.package_path(package_id))
+                .unwrap()
+                .set_modified(month_ago)
+                .unwrap();
+        }
+
+        // Installing from the cache counts as using it.
+        cache.get(&used).unwrap().unwrap();
+
+        let removed = cache.prune(Duration::from_secs(7 * 24 * 60 * 60)).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].size, 7);
+
+        assert!(cache.get(&old).unwrap().is_none());
+        assert!(cache.get(&used).unwrap().is_some());
+        assert!(cache.get(&recent).unwrap().is_some());
+        assert_eq!(cache.entries().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn discarded_downloads_start_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = PackageCache::at(dir.path());
+        let package_id: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+
+        let mut download = cache.partial(&package_id).unwrap();
+        download.write_all(b"corrupt").unwrap();
+        download.discard().unwrap();
+
+        assert!(cache.partial(&package_id).unwrap().is_empty());
+        assert!(cache.get(&package_id).unwrap().is_none());
+    }
+}
@@ -1,22 +1,26 @@
-use std::io::{self, BufRead, BufReader, Cursor};
+use std::io::{self, BufRead, BufReader, Cursor, Read};
 use std::path::{Path, PathBuf};
 
-use anyhow::format_err;
+use anyhow::{format_err, Context};
 use fs_err::File;
-use globset::{Glob, GlobSet, GlobSetBuilder};
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
 use serde_json::json;
 use walkdir::WalkDir;
 use zip::{write::FileOptions, ZipArchive, ZipWriter};
 
-use crate::manifest::Manifest;
+use crate::manifest::{Manifest, MANIFEST_FILE_NAME};
 
-static EXCLUDED_GLOBS: &[&str] = &[
-    ".*",
-    "wally.lock",
-    "Packages",
-    "ServerPackages",
-    "DevPackages",
-];
+/// Wally's own files and install output, which never belong in a package, even
+/// if the manifest includes them.
+static ALWAYS_EXCLUDED_GLOBS: &[&str] =
+    &["/wally.lock", "Packages", "ServerPackages", "DevPackages"];
+
+/// Excluded unless the manifest includes them.
+static DEFAULT_EXCLUDED_GLOBS: &[&str] = &["/.*"];
+
+/// The manifest and license are part of every package, whatever the manifest's
+/// include and exclude lists say. These are matched case-insensitively.
+static ALWAYS_INCLUDED_GLOBS: &[&str] = &[MANIFEST_FILE_NAME, "license*", "licence*"];
 
 /// Container for the contents of a package that have been downloaded.
 #[derive(Clone)]
@@ -93,29 +97,51 @@ impl PackageContents {
         Ok(())
     }
 
+    /// The files and directories in a project that go into its package.
+    ///
+    /// Without an include list, everything but hidden files, Wally's own files,
+    /// and whatever the exclude list and `.gitignore` name goes in. With one,
+    /// only files it matches go in, though the exclude list still applies
+    /// to files it doesn't explicitly match.
     pub fn filtered_contents(input: &Path) -> anyhow::Result<Vec<PathBuf>> {
         let manifest = Manifest::load(input)?;
         let includes = manifest.package.include;
         let mut excludes = manifest.package.exclude;
 
-        if includes.is_empty() && Path::new(".gitignore").exists() {
-            let gitignore = File::open(Path::new(".gitignore"))?;
+        let gitignore_path = input.join(".gitignore");
+        if includes.is_empty() && gitignore_path.exists() {
+            let gitignore = File::open(&gitignore_path)?;
+
+            for line in BufReader::new(gitignore).lines() {
+                let line = line?;
+                let pattern = line.trim();
+
+                if pattern.is_empty() || pattern.starts_with('#') {
+                    continue;
+                }
+
+                if pattern.starts_with('!') {
+                    log::debug!("Ignoring negated .gitignore pattern {}", pattern);
+                    continue;
+                }
 
-            BufReader::new(gitignore)
-                .lines()
-                .flatten()
-                .for_each(|pattern| {
-                    excludes.push(pattern);
-                });
+                excludes.push(pattern.to_owned());
+            }
         }
 
-        EXCLUDED_GLOBS
-            .iter()
-            .map(|pattern| pattern.to_string())
-            .for_each(|pattern| excludes.push(pattern));
+        excludes.extend(
+            DEFAULT_EXCLUDED_GLOBS
+                .iter()
+                .map(|pattern| pattern.to_string()),
+        );
 
         let include = build_glob_set(&includes)?;
         let exclude = build_glob_set(&excludes)?;
+        let always_exclude = build_glob_set(ALWAYS_EXCLUDED_GLOBS)?;
+        let always_include = build_root_glob_set(ALWAYS_INCLUDED_GLOBS)?;
+
+        let is_included =
+            |relative: &Path| always_include.is_match(relative) || include.is_match(relative);
 
         Ok(WalkDir::new(input)
             .min_depth(1)
@@ -123,17 +149,38 @@ impl PackageContents {
             .filter_entry(|entry| {
                 let relative = entry.path().strip_prefix(input).unwrap();
 
-                if !includes.is_empty() && !include.matches(relative).is_empty() {
-                    return true;
-                };
+                if always_exclude.is_match(relative) {
+                    return false;
+                }
 
-                exclude.matches(relative).is_empty()
+                is_included(relative) || !exclude.is_match(relative)
             })
             .flatten()
+            .filter(|entry| {
+                let relative = entry.path().strip_prefix(input).unwrap();
+
+                // Directories that only lead to included files are created
+                // when the package is unpacked, so they don't need entries.
+                includes.is_empty() || is_included(relative)
+            })
             .map(|entry| entry.path().to_path_buf())
             .collect())
     }
 
+    /// Read the manifest contained in the package.
+    pub fn manifest(&self) -> anyhow::Result<Manifest> {
+        let mut archive = ZipArchive::new(Cursor::new(self.data.as_slice()))
+            .context("could not read ZIP archive")?;
+        let mut manifest_file = archive
+            .by_name(MANIFEST_FILE_NAME)
+            .context("could not find manifest file")?;
+
+        let mut contents = Vec::new();
+        manifest_file.read_to_end(&mut contents)?;
+
+        Manifest::from_slice(&contents)
+    }
+
     pub fn data(&self) -> &[u8] {
         &self.data
     }
@@ -144,12 +191,142 @@ impl PackageContents {
     }
 }
 
-fn build_glob_set(patterns: &[String]) -> anyhow::Result<GlobSet> {
+/// Builds a set from `.gitignore`-style patterns: a pattern starting with `/`
+/// only matches from the root of the package, others match at any depth, and
+/// a pattern matching a directory also matches everything in it.
+fn build_glob_set<S: AsRef<str>>(patterns: &[S]) -> anyhow::Result<GlobSet> {
     let mut builder = GlobSetBuilder::new();
 
     for pattern in patterns {
-        builder.add(Glob::new(pattern)?);
+        let pattern = pattern.as_ref().trim_end_matches('/');
+        let anchored = pattern.starts_with('/') || pattern.contains('/');
+        let pattern = pattern.trim_start_matches('/');
+
+        if pattern.is_empty() {
+            continue;
+        }
+
+        let pattern = if anchored {
+            pattern.to_owned()
+        } else {
+            format!("**/{}", pattern)
+        };
+
+        builder.add(Glob::new(&pattern)?);
+        builder.add(Glob::new(&format!("{}/**", pattern))?);
     }
 
     Ok(builder.build()?)
 }
+
+/// Builds a set of case-insensitive patterns that only match files in the root
+/// of the package.
+fn build_root_glob_set(patterns: &[&str]) -> anyhow::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+
+    for pattern in patterns {
+        builder.add(
+            GlobBuilder::new(pattern)
+                .case_insensitive(true)
+                .literal_separator(true)
+                .build()?,
+        );
+    }
+
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::BTreeSet;
+
+    use crate::test_package::PackageBuilder;
+
+    fn project(include: &[&str], exclude: &[&str], files: &[&str]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut manifest = PackageBuilder::new("biff/assets@0.1.0").into_manifest();
+        manifest.package.include = include.iter().map(|s| s.to_string()).collect();
+        manifest.package.exclude = exclude.iter().map(|s| s.to_string()).collect();
+        fs_err::write(
+            dir.path().join(MANIFEST_FILE_NAME),
+            toml::to_string_pretty(&manifest).unwrap(),
+        )
+        .unwrap();
+
+        for file in files {
+            let path = dir.path().join(file);
+            fs_err::create_dir_all(path.parent().unwrap()).unwrap();
+            fs_err::write(path, file).unwrap();
+        }
+
+        dir
+    }
+
+    fn contents(dir: &tempfile::TempDir) -> BTreeSet<String> {
+        PackageContents::filtered_contents(dir.path())
+            .unwrap()
+            .iter()
+            .filter(|path| path.is_file())
+            .map(|path| {
+                let relative = path.strip_prefix(dir.path()).unwrap();
+                relative.to_str().unwrap().replace('\\', "/")
+            })
+            .collect()
+    }
+
+    fn set(files: &[&str]) -> BTreeSet<String> {
+        files.iter().map(|s| s.to_string()).collect()
+    }
+
+    const FILES: &[&str] = &[
+        "LICENSE.md",
+        "src/init.lua",
+        "assets/logo.png",
+        "assets/data.json",
+        "assets/.DS_Store",
+        ".env",
+        "wally.lock",
+        "Packages/biff_dep@1.0.0/init.lua",
+        "docs/index.md",
+        "build/out.lua",
+    ];
+
+    #[test]
+    fn excludes_by_default_and_from_gitignore() {
+        let dir = project(&[], &["/docs"], FILES);
+        fs_err::write(
+            dir.path().join(".gitignore"),
+            "# Build output\nbuild/\n*.DS_Store\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            contents(&dir),
+            set(&[
+                "LICENSE.md",
+                "assets/data.json",
+                "assets/logo.png",
+                "src/init.lua",
+                "wally.toml",
+            ])
+        );
+    }
+
+    #[test]
+    fn include_keeps_only_matches_and_required_files() {
+        let dir = project(&["/src", "assets/*.png", "Packages"], &[], FILES);
+
+        assert_eq!(
+            contents(&dir),
+            set(&[
+                "LICENSE.md",
+                "assets/logo.png",
+                "src/init.lua",
+                "wally.toml"
+            ])
+        );
+    }
+}
@@ -1,18 +1,30 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
 use std::io::{BufReader, ErrorKind, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use anyhow::{anyhow, Context};
+use anyhow::{anyhow, bail, Context};
 use fs_err::{create_dir_all, File, OpenOptions};
-use git2::Repository;
+use git2::{Oid, Repository};
+use semver::Version;
 use serde::{Deserialize, Serialize};
 use tempfile::TempDir;
 use url::Url;
+use walkdir::WalkDir;
 
 use crate::git_util;
+use crate::index_lock::{IndexLock, IndexLockGuard};
 use crate::manifest::Manifest;
-use crate::package_name::PackageName;
+use crate::package_id::PackageId;
+use crate::package_name::{canonical_scope, PackageName};
+
+const CONFIG_FILE_NAME: &str = "config.json";
+const OWNERS_FILE_NAME: &str = "owners.json";
+const TEAMS_FILE_NAME: &str = "teams.json";
+const SIGNING_KEYS_FILE_NAME: &str = "signing-keys.json";
 
 /// Configuration contained in the index's `config.json` file.
 #[derive(Debug, Serialize, Deserialize)]
@@ -22,6 +34,213 @@ pub struct PackageIndexConfig {
 
     #[serde(default)]
     pub fallback_registries: Vec<String>,
+
+    /// How packages are laid out in the index's directories.
+    #[serde(default)]
+    pub layout: IndexLayout,
+}
+
+/// How the files in an index are arranged. Every tool reading the index has
+/// to agree on this, so it's stored in the index's `config.json`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IndexLayout {
+    /// Each scope is a directory in the root of the index.
+    Flat,
+
+    /// Scopes are grouped into directories by prefix, like crates.io does, so
+    /// that no single directory gets too big:
+    ///
+    /// * `1/a`
+    /// * `2/ab`
+    /// * `3/a/abc`
+    /// * `ab/cd/abcdef`
+    Sharded,
+}
+
+impl IndexLayout {
+    /// The directory holding a scope's packages and owners file, relative to
+    /// the root of the index.
+    pub fn scope_dir(&self, scope: &str) -> PathBuf {
+        match self {
+            IndexLayout::Flat => PathBuf::from(scope),
+            IndexLayout::Sharded => {
+                let chars: Vec<char> = scope.chars().collect();
+                let prefix =
+                    |range: std::ops::Range<usize>| -> String { chars[range].iter().collect() };
+
+                let mut path = PathBuf::new();
+                match chars.len() {
+                    1 => path.push("1"),
+                    2 => path.push("2"),
+                    3 => {
+                        path.push("3");
+                        path.push(prefix(0..1));
+                    }
+                    _ => {
+                        path.push(prefix(0..2));
+                        path.push(prefix(2..4));
+                    }
+                }
+
+                path.push(scope);
+                path
+            }
+        }
+    }
+
+    /// The file holding a package's entries, relative to the root of the
+    /// index.
+    pub fn package_file(&self, name: &PackageName) -> PathBuf {
+        self.scope_dir(name.scope()).join(name.name())
+    }
+}
+
+impl Default for IndexLayout {
+    fn default() -> Self {
+        IndexLayout::Flat
+    }
+}
+
+impl fmt::Display for IndexLayout {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IndexLayout::Flat => write!(formatter, "flat"),
+            IndexLayout::Sharded => write!(formatter, "sharded"),
+        }
+    }
+}
+
+impl FromStr for IndexLayout {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "flat" => Ok(IndexLayout::Flat),
+            "sharded" => Ok(IndexLayout::Sharded),
+            _ => bail!(
+                "index layout must be one of flat or sharded, got '{}'",
+                value
+            ),
+        }
+    }
+}
+
+/// A scope owner, by user id. Registries can let people sign in with more
+/// than one GitHub host, and user ids are only unique on one host, so owners
+/// from any host but the registry's main one are namespaced by the name the
+/// registry gives that host.
+///
+/// In `owners.json`, owners from the main host are plain numbers, as they've
+/// always been, and other owners are strings like `"enterprise:1234"`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OwnerId {
+    provider: Option<String>,
+    id: u64,
+}
+
+impl OwnerId {
+    /// An owner from the registry's main host.
+    pub fn new(id: u64) -> Self {
+        Self { provider: None, id }
+    }
+
+    /// An owner from the host the registry calls `provider`.
+    pub fn with_provider(provider: &str, id: u64) -> Self {
+        Self {
+            provider: Some(provider.to_owned()),
+            id,
+        }
+    }
+
+    /// The name of the host the owner is from, or `None` for the main host.
+    pub fn provider(&self) -> Option<&str> {
+        self.provider.as_deref()
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+impl From<u64> for OwnerId {
+    fn from(id: u64) -> Self {
+        Self::new(id)
+    }
+}
+
+impl fmt::Display for OwnerId {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match &self.provider {
+            Some(provider) => write!(formatter, "{}:{}", provider, self.id),
+            None => write!(formatter, "{}", self.id),
+        }
+    }
+}
+
+impl FromStr for OwnerId {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        let (provider, id) = match value.rsplit_once(':') {
+            Some((provider, id)) => (Some(provider), id),
+            None => (None, value),
+        };
+
+        let id = id
+            .parse()
+            .with_context(|| format!("invalid owner id '{}'", value))?;
+
+        match provider {
+            Some("") => bail!("invalid owner id '{}', the provider is empty", value),
+            Some(provider) => Ok(Self::with_provider(provider, id)),
+            None => Ok(Self::new(id)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum OwnerIdSpec {
+    Id(u64),
+    Namespaced(String),
+}
+
+impl Serialize for OwnerId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match &self.provider {
+            Some(_) => OwnerIdSpec::Namespaced(self.to_string()).serialize(serializer),
+            None => OwnerIdSpec::Id(self.id).serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for OwnerId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        match OwnerIdSpec::deserialize(deserializer).map_err(|_| {
+            D::Error::custom("an owner id is either a number or a string like \"provider:1234\"")
+        })? {
+            OwnerIdSpec::Id(id) => Ok(Self::new(id)),
+            OwnerIdSpec::Namespaced(id) => id.parse().map_err(D::Error::custom),
+        }
+    }
+}
+
+/// What a refresh of the index found.
+#[derive(Debug, Serialize)]
+pub struct IndexRefresh {
+    /// The commit the index is now at.
+    pub head: String,
+
+    /// Whether every package was forgotten, rather than only the ones that
+    /// changed.
+    pub full: bool,
+
+    /// The packages that were read again because they changed. Empty for a
+    /// full refresh.
+    pub updated: Vec<PackageName>,
 }
 
 pub struct PackageIndex {
@@ -36,13 +255,17 @@ pub struct PackageIndex {
     repository: Mutex<Repository>,
 
     /// A cache that contains all of the packages we've queried so far. This
-    /// cache is never emptied.
+    /// cache is only emptied by a full refresh.
     package_cache: Mutex<HashMap<PackageName, Arc<PackageMetadata>>>,
 
     /// A GitHub Personal Access Token to use before trying the machine's local
     /// configuration.
     access_token: Option<String>,
 
+    /// A lock shared with other processes that write to this index. When set,
+    /// it's held around every change we make to the index.
+    write_lock: Option<IndexLock>,
+
     /// If this index is contained in a temporary location, like when running
     /// tests or a registry server, hold onto it here so that it'll be dropped
     /// at the right time.
@@ -61,6 +284,7 @@ impl PackageIndex {
             repository: Mutex::new(repository),
             package_cache: Mutex::new(HashMap::new()),
             access_token,
+            write_lock: None,
             temp_dir: None,
         };
 
@@ -87,6 +311,7 @@ impl PackageIndex {
             repository: Mutex::new(repository),
             package_cache: Mutex::new(HashMap::new()),
             access_token,
+            write_lock: None,
             temp_dir: Some(temp_dir),
         };
 
@@ -107,8 +332,118 @@ impl PackageIndex {
         Ok(())
     }
 
+    /// Catch up with changes made to the remote index by anyone else, like
+    /// owners edited by hand, and forget any packages read before them.
+    ///
+    /// The package cache stays locked until the refresh is done, so package
+    /// reads wait for it rather than seeing a mix of old and new files. If the
+    /// fetch fails, the local copy is left as it was.
+    pub fn refresh(&self) -> anyhow::Result<IndexRefresh> {
+        let repository = self.repository.lock().unwrap();
+        let mut package_cache = self.package_cache.lock().unwrap();
+
+        git_util::update_index(self.access_token.clone(), &repository)
+            .context("could not refresh package index")?;
+        package_cache.clear();
+
+        Ok(IndexRefresh {
+            head: git_util::head_commit(&repository)?.to_string(),
+            full: true,
+            updated: Vec::new(),
+        })
+    }
+
+    /// Like `refresh`, but only the packages whose files changed between the
+    /// old and new commits are read again, and the rest of the cache is kept.
+    /// The package cache is only locked once the new commit has been fetched,
+    /// so reads carry on while the network is in use.
+    ///
+    /// If `expected_head` is already checked out, like when a webhook reports
+    /// a push that this registry made itself, nothing is fetched. If the
+    /// changes can't be worked out, or the index's config changed, this falls
+    /// back to forgetting every package.
+    pub fn refresh_incremental(&self, expected_head: Option<&str>) -> anyhow::Result<IndexRefresh> {
+        let repository = self.repository.lock().unwrap();
+        let old_head = git_util::head_commit(&repository)?;
+
+        if expected_head == Some(old_head.to_string().as_str()) {
+            return Ok(IndexRefresh {
+                head: old_head.to_string(),
+                full: false,
+                updated: Vec::new(),
+            });
+        }
+
+        let new_head = git_util::fetch_index(self.access_token.clone(), &repository)
+            .context("could not refresh package index")?;
+        let changed = git_util::changed_files(&repository, old_head, new_head);
+
+        let mut package_cache = self.package_cache.lock().unwrap();
+        git_util::reset_index(&repository, new_head).context("could not refresh package index")?;
+
+        let changed = match changed {
+            Ok(changed)
+                if !changed
+                    .iter()
+                    .any(|path| path == Path::new(CONFIG_FILE_NAME)) =>
+            {
+                changed
+            }
+            result => {
+                if let Err(err) = result {
+                    log::warn!(
+                        "Could not find what changed in the package index, forgetting every \
+                         package instead: {:?}",
+                        err
+                    );
+                }
+
+                package_cache.clear();
+
+                return Ok(IndexRefresh {
+                    head: new_head.to_string(),
+                    full: true,
+                    updated: Vec::new(),
+                });
+            }
+        };
+
+        let layout = self.layout()?;
+        let mut updated: Vec<_> = changed
+            .iter()
+            .filter_map(|path| package_name_from_path(layout, path))
+            .collect();
+        updated.sort();
+
+        for name in &updated {
+            // A package that was deleted, or can't be read any more, is
+            // dropped so that reading it gives the same error it would after
+            // a restart.
+            match self.read_package_metadata(name) {
+                Ok(metadata) => {
+                    package_cache.insert(name.clone(), Arc::new(metadata));
+                }
+                Err(_) => {
+                    package_cache.remove(name);
+                }
+            }
+        }
+
+        Ok(IndexRefresh {
+            head: new_head.to_string(),
+            full: false,
+            updated,
+        })
+    }
+
+    /// Require holding `lock` while making changes to the index, for when other
+    /// processes might be writing to the same remote index.
+    pub fn set_write_lock(&mut self, lock: IndexLock) {
+        self.write_lock = Some(lock);
+    }
+
     pub fn config(&self) -> anyhow::Result<PackageIndexConfig> {
-        let config_path = self.path.join("config.json");
+        let config_path = self.path.join(CONFIG_FILE_NAME);
         let contents = fs_err::read_to_string(config_path)?;
         Ok(serde_json::from_str(&contents)?)
     }
@@ -120,39 +455,323 @@ impl PackageIndex {
     /// servers; it's intended for use with local registries or in the
     /// implementation of the registry server itself.
     pub fn publish(&self, manifest: &Manifest) -> anyhow::Result<()> {
+        self.publish_with_checksums(manifest, &ArchiveChecksums::default())
+    }
+
+    /// Publish a package to the index, recording the hashes of its archive
+    /// next to its manifest.
+    pub fn publish_with_checksums(
+        &self,
+        manifest: &Manifest,
+        checksums: &ArchiveChecksums,
+    ) -> anyhow::Result<()> {
+        self.publish_with_owner(manifest, checksums, None, None)
+    }
+
+    /// Publish a package to the index on behalf of `publisher`, adding
+    /// `new_owner` to its scope's owners in the same commit.
+    ///
+    /// Either every change makes it to the remote index or none of them do.
+    /// If anything fails along the way, the local copy of the index is put
+    /// back to match the remote, so a half-finished publish is never read
+    /// from it or pushed along with somebody else's change.
+    pub fn publish_with_owner(
+        &self,
+        manifest: &Manifest,
+        checksums: &ArchiveChecksums,
+        publisher: Option<&str>,
+        new_owner: Option<&OwnerId>,
+    ) -> anyhow::Result<()> {
+        self.publish_and_prune(manifest, checksums, publisher, new_owner, &[])
+    }
+
+    /// Like [`publish_with_owner`](Self::publish_with_owner), but also removes
+    /// the `pruned` versions of the package in the same commit, to make room
+    /// for the new one. If the publish fails, the pruned versions are left
+    /// where they were.
+    pub fn publish_and_prune(
+        &self,
+        manifest: &Manifest,
+        checksums: &ArchiveChecksums,
+        publisher: Option<&str>,
+        new_owner: Option<&OwnerId>,
+        pruned: &[Version],
+    ) -> anyhow::Result<()> {
         let repo = self.repository.lock().unwrap();
-        let package_path = self.package_path(&manifest.package.name);
+        let _write_lock = self.lock_for_write(&repo)?;
+        let name = &manifest.package.name;
+        let package_id = manifest.package_id();
 
-        // This package might not exist yet, so create its containing directory.
-        create_dir_all(package_path.parent().unwrap())?;
+        // Work out every change before touching any files, so that most
+        // failures leave nothing to clean up.
+        let package_path = self.package_path(name)?;
 
-        {
-            let mut file = OpenOptions::new()
-                .append(true)
-                .create(true)
-                .open(&package_path)?;
-
-            // Package entries are newline-delimited JSON files. We assume here
-            // that the file is empty or already ends in a newline.
-            let mut entry = serde_json::to_string(&manifest)?;
+        let new_entry = IndexEntry {
+            manifest: manifest.clone(),
+            yanked: false,
+            checksum: checksums.blake3.clone(),
+            sha256: checksums.sha256.clone(),
+            published_at: Some(unix_time()),
+            published_by: publisher.map(str::to_owned),
+            deprecated: None,
+            signature: checksums.signature.clone(),
+        };
+
+        // Package entries are newline-delimited JSON files. Without anything
+        // to prune, we assume here that the file is empty or already ends in a
+        // newline, and append to it.
+        let (entry, entries) = if pruned.is_empty() {
+            let mut entry = serde_json::to_string(&new_entry)?;
             entry.push('\n');
-            file.write_all(entry.as_bytes())?;
+            (Some(entry), Vec::new())
+        } else {
+            let mut entries = read_index_entries(&package_path)
+                .with_context(|| format!("could not open package {} from index", name))?;
+            entries.retain(|entry| !pruned.contains(&entry.manifest.package.version));
+            entries.push(new_entry);
+            (None, entries)
+        };
+
+        let owners = match new_owner {
+            Some(new_owner) => {
+                let mut owners = self.get_scope_owners(name.scope())?;
+
+                if owners.contains(new_owner) {
+                    None
+                } else {
+                    owners.push(new_owner.clone());
+                    Some((
+                        self.scope_path(name.scope())?.join(OWNERS_FILE_NAME),
+                        owners,
+                    ))
+                }
+            }
+            None => None,
+        };
+
+        let head = git_util::head_commit(&repo)?;
+        let mut created = Vec::new();
+
+        let result = (|| -> anyhow::Result<()> {
+            if !package_path.exists() {
+                created.push(package_path.clone());
+            }
+
+            // This package might not exist yet, so create its containing directory.
+            create_dir_all(package_path.parent().unwrap())?;
+
+            match &entry {
+                Some(entry) => OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(&package_path)?
+                    .write_all(entry.as_bytes())?,
+                None => write_index_entries(&package_path, &entries)?,
+            }
+
+            if let Some((owners_path, owners)) = &owners {
+                if !owners_path.exists() {
+                    created.push(owners_path.clone());
+                }
+
+                create_dir_all(owners_path.parent().unwrap())?;
+                fs_err::write(owners_path, serde_json::to_string(owners)?)?;
+            }
+
+            let message = match pruned {
+                [] => format!("Publish {}", package_id),
+                _ => {
+                    let pruned: Vec<String> =
+                        pruned.iter().map(|version| version.to_string()).collect();
+                    format!("Publish {}, pruning {}", package_id, pruned.join(", "))
+                }
+            };
+
+            git_util::commit_all_and_push(&repo, self.access_token.clone(), &message)
+        })();
+
+        // Blow away the cache for this package, since we've modified the
+        // underlying file, whether or not the publish made it.
+        self.package_cache.lock().unwrap().remove(name);
+
+        if let Err(err) = result {
+            self.roll_back(&repo, head, &created);
+            return Err(err.context(format!("could not publish {}", package_id)));
+        }
+
+        Ok(())
+    }
+
+    /// Mark or unmark the given versions of a package as yanked, committing
+    /// all of the changes to the index at once.
+    ///
+    /// Returns the versions whose yanked state actually changed. Versions that
+    /// are not present in the index are ignored.
+    pub fn set_yanked(
+        &self,
+        name: &PackageName,
+        versions: &[Version],
+        yanked: bool,
+    ) -> anyhow::Result<Vec<Version>> {
+        let repo = self.repository.lock().unwrap();
+        let _write_lock = self.lock_for_write(&repo)?;
+        let package_path = self.package_path(name)?;
+
+        let mut entries = read_index_entries(&package_path)
+            .with_context(|| format!("could not open package {} from index", name))?;
+
+        let mut changed = Vec::new();
+        for entry in &mut entries {
+            let version = &entry.manifest.package.version;
+
+            if entry.yanked != yanked && versions.contains(version) {
+                entry.yanked = yanked;
+                changed.push(version.clone());
+            }
+        }
+
+        if changed.is_empty() {
+            return Ok(changed);
         }
 
+        write_index_entries(&package_path, &entries)?;
+
+        let changed_list: Vec<String> = changed.iter().map(|version| version.to_string()).collect();
+        let action = if yanked { "Yank" } else { "Unyank" };
+
         git_util::commit_and_push(
             &repo,
             self.access_token.clone(),
-            &format!("Publish {}", manifest.package_id()),
+            &format!("{} {} {}", action, name, changed_list.join(", ")),
             &self.path,
             &package_path,
         )?;
 
-        // Blow away the cache for this package, since we've now modified the
-        // underlying file.
         let mut package_cache = self.package_cache.lock().unwrap();
-        package_cache.remove(&manifest.package.name);
+        package_cache.remove(name);
 
-        Ok(())
+        Ok(changed)
+    }
+
+    /// Set or clear the deprecation message of the given versions of a
+    /// package, committing all of the changes to the index at once. A
+    /// deprecated version can still be installed, but installs warn about it.
+    ///
+    /// Returns the versions whose message actually changed. Versions that are
+    /// not present in the index are ignored.
+    pub fn set_deprecated(
+        &self,
+        name: &PackageName,
+        versions: &[Version],
+        message: Option<&str>,
+    ) -> anyhow::Result<Vec<Version>> {
+        let repo = self.repository.lock().unwrap();
+        let _write_lock = self.lock_for_write(&repo)?;
+        let package_path = self.package_path(name)?;
+
+        let mut entries = read_index_entries(&package_path)
+            .with_context(|| format!("could not open package {} from index", name))?;
+
+        let mut changed = Vec::new();
+        for entry in &mut entries {
+            let version = &entry.manifest.package.version;
+
+            if entry.deprecated.as_deref() != message && versions.contains(version) {
+                entry.deprecated = message.map(str::to_owned);
+                changed.push(version.clone());
+            }
+        }
+
+        if changed.is_empty() {
+            return Ok(changed);
+        }
+
+        write_index_entries(&package_path, &entries)?;
+
+        let changed_list: Vec<String> = changed.iter().map(|version| version.to_string()).collect();
+        let action = if message.is_some() {
+            "Deprecate"
+        } else {
+            "Undeprecate"
+        };
+
+        git_util::commit_and_push(
+            &repo,
+            self.access_token.clone(),
+            &format!("{} {} {}", action, name, changed_list.join(", ")),
+            &self.path,
+            &package_path,
+        )?;
+
+        let mut package_cache = self.package_cache.lock().unwrap();
+        package_cache.remove(name);
+
+        Ok(changed)
+    }
+
+    /// Remove a version of a package from the index altogether, as if it had
+    /// never been published. Unlike yanking, this breaks anything that depends
+    /// on the version, so it should only be used for mistakes caught early.
+    ///
+    /// Returns whether the version was in the index. If the change can't be
+    /// pushed, the local copy of the index is put back to match the remote.
+    pub fn unpublish(&self, package_id: &PackageId) -> anyhow::Result<bool> {
+        let repo = self.repository.lock().unwrap();
+        let _write_lock = self.lock_for_write(&repo)?;
+        let name = package_id.name();
+        let package_path = self.package_path(name)?;
+
+        let mut entries = read_index_entries(&package_path)
+            .with_context(|| format!("could not open package {} from index", name))?;
+
+        let count = entries.len();
+        entries.retain(|entry| &entry.manifest.package.version != package_id.version());
+
+        if entries.len() == count {
+            return Ok(false);
+        }
+
+        // A package with no versions left shouldn't look like it still exists.
+        if entries.is_empty() {
+            fs_err::remove_file(&package_path)?;
+        } else {
+            write_index_entries(&package_path, &entries)?;
+        }
+
+        let result = git_util::commit_all_and_push(
+            &repo,
+            self.access_token.clone(),
+            &format!("Unpublish {}", package_id),
+        );
+
+        self.package_cache.lock().unwrap().remove(name);
+
+        if let Err(err) = result {
+            if let Err(reset_err) = git_util::update_index(self.access_token.clone(), &repo) {
+                log::error!(
+                    "Could not reset package index after failed unpublish: {:?}",
+                    reset_err
+                );
+            }
+
+            return Err(err.context(format!("could not unpublish {}", package_id)));
+        }
+
+        Ok(true)
+    }
+
+    /// How many versions of a package are in the index, counting yanked ones.
+    pub fn version_count(&self, name: &PackageName) -> anyhow::Result<usize> {
+        if !self.package_exists(name)? {
+            return Ok(0);
+        }
+
+        Ok(self.get_package_metadata(name)?.versions.len())
+    }
+
+    /// Whether any version of a package has been published to the index.
+    pub fn package_exists(&self, name: &PackageName) -> anyhow::Result<bool> {
+        Ok(self.package_path(name)?.is_file())
     }
 
     /// Read the list of versions for a package from the index.
@@ -162,42 +781,82 @@ impl PackageIndex {
         if package_cache.contains_key(name) {
             Ok(Arc::clone(&package_cache[name]))
         } else {
-            let package_path = self.package_path(name);
+            let metadata = Arc::new(self.read_package_metadata(name)?);
+            package_cache.insert(name.clone(), Arc::clone(&metadata));
 
-            // Construct a buffered file reader, with a nice error message in the
-            // event of failure. We might want to return a structured error from
-            // this method in the future to distinguish between general I/O errors
-            // and a package not existing.
-            let file = File::open(&package_path)
-                .with_context(|| format!("could not open package {} from index", name))?;
-            let file = BufReader::new(file);
+            Ok(metadata)
+        }
+    }
 
-            // Read all of the manifests from the package file.
-            //
-            // Entries into the index are stored as JSON Lines. This block will
-            // either parse all of the entries, or fail with a single error.
-            let manifest_stream: Result<Vec<Manifest>, serde_json::Error> =
-                serde_json::Deserializer::from_reader(file)
-                    .into_iter::<Manifest>()
-                    .collect();
+    /// Read a package's versions from its file, without the cache.
+    fn read_package_metadata(&self, name: &PackageName) -> anyhow::Result<PackageMetadata> {
+        let package_path = self.package_path(name)?;
 
-            let mut versions = manifest_stream
-                .with_context(|| format!("could not parse package index entry for {}", name))?;
+        // Read with a nice error message in the event of failure. We might
+        // want to return a structured error from this method in the future
+        // to distinguish between general I/O errors and a package not
+        // existing.
+        let entries = read_index_entries(&package_path)
+            .with_context(|| format!("could not open package {} from index", name))?;
 
-            versions.sort_by(|a, b| b.package.version.cmp(&a.package.version));
+        let mut versions = Vec::with_capacity(entries.len());
+        let mut yanked = BTreeSet::new();
+        let mut checksums = BTreeMap::new();
+        let mut sha256 = BTreeMap::new();
+        let mut published = BTreeMap::new();
+        let mut publishers = BTreeMap::new();
+        let mut deprecated = BTreeMap::new();
+        let mut signatures = BTreeMap::new();
 
-            let metadata = Arc::new(PackageMetadata { versions });
-            package_cache.insert(name.clone(), Arc::clone(&metadata));
+        for entry in entries {
+            if entry.yanked {
+                yanked.insert(entry.manifest.package.version.clone());
+            }
 
-            Ok(metadata)
+            if let Some(checksum) = entry.checksum {
+                checksums.insert(entry.manifest.package.version.clone(), checksum);
+            }
+
+            if let Some(hash) = entry.sha256 {
+                sha256.insert(entry.manifest.package.version.clone(), hash);
+            }
+
+            if let Some(published_at) = entry.published_at {
+                published.insert(entry.manifest.package.version.clone(), published_at);
+            }
+
+            if let Some(publisher) = entry.published_by {
+                publishers.insert(entry.manifest.package.version.clone(), publisher);
+            }
+
+            if let Some(message) = entry.deprecated {
+                deprecated.insert(entry.manifest.package.version.clone(), message);
+            }
+
+            if let Some(signature) = entry.signature {
+                signatures.insert(entry.manifest.package.version.clone(), signature);
+            }
+
+            versions.push(entry.manifest);
         }
+
+        versions.sort_by(|a, b| b.package.version.cmp(&a.package.version));
+
+        Ok(PackageMetadata {
+            versions,
+            yanked,
+            checksums,
+            sha256,
+            published,
+            publishers,
+            deprecated,
+            signatures,
+        })
     }
 
     /// Read the list of owners for a scope from the index
-    pub fn get_scope_owners(&self, scope: &str) -> anyhow::Result<Vec<u64>> {
-        let mut path = self.path.clone();
-        path.push(scope);
-        path.push("owners.json");
+    pub fn get_scope_owners(&self, scope: &str) -> anyhow::Result<Vec<OwnerId>> {
+        let path = self.scope_path(scope)?.join(OWNERS_FILE_NAME);
 
         match File::open(path) {
             Ok(file) => serde_json::from_reader(file)
@@ -212,7 +871,7 @@ impl PackageIndex {
     }
 
     /// Check if a user id is present in the owners.json file for a scope
-    pub fn is_scope_owner(&self, scope: &str, user_id: &u64) -> anyhow::Result<bool> {
+    pub fn is_scope_owner(&self, scope: &str, user_id: &OwnerId) -> anyhow::Result<bool> {
         let owners = self.get_scope_owners(scope)?;
         Ok(owners.iter().any(|owner| owner == user_id))
     }
@@ -220,20 +879,20 @@ impl PackageIndex {
     /// Add an owner to a scope's owner file
     /// Similar to publish, this first applies the change to our local copy
     /// and then attempts to push it to the remote index
-    pub fn add_scope_owner(&self, scope: &str, owner_id: &u64) -> anyhow::Result<()> {
+    pub fn add_scope_owner(&self, scope: &str, owner_id: &OwnerId) -> anyhow::Result<()> {
         let repo = self.repository.lock().unwrap();
-        let mut path = self.path.clone();
+        let _write_lock = self.lock_for_write(&repo)?;
+        let mut path = self.scope_path(scope)?;
 
         // This scope might not exist yet
-        path.push(scope);
         create_dir_all(&path)?;
-        path.push("owners.json");
+        path.push(OWNERS_FILE_NAME);
 
         {
             let mut owners = self.get_scope_owners(&scope)?;
             let mut file = OpenOptions::new().write(true).create(true).open(&path)?;
 
-            owners.push(*owner_id);
+            owners.push(owner_id.clone());
             file.write_all(serde_json::to_string(&owners)?.as_bytes())?;
         }
 
@@ -248,19 +907,540 @@ impl PackageIndex {
         Ok(())
     }
 
-    fn package_path(&self, name: &PackageName) -> PathBuf {
-        // Each package has all of its versions stored in a folder based on its
-        // scope and name.
-        let mut package_path = self.path.clone();
-        package_path.push(name.scope());
-        package_path.push(name.name());
-        package_path
+    /// Replace the list of owners for a scope. `update` is given the current
+    /// owners and returns the new ones, and runs while holding the index's
+    /// write lock so that nobody else can change the owners in between.
+    ///
+    /// Returns the new owners.
+    pub fn set_scope_owners<F, E>(&self, scope: &str, update: F) -> Result<Vec<OwnerId>, E>
+    where
+        F: FnOnce(Vec<OwnerId>) -> Result<Vec<OwnerId>, E>,
+        E: From<anyhow::Error>,
+    {
+        let repo = self.repository.lock().unwrap();
+        let _write_lock = self.lock_for_write(&repo)?;
+
+        let owners = update(self.get_scope_owners(scope)?)?;
+        self.write_scope_owners(&repo, scope, &owners)?;
+
+        Ok(owners)
+    }
+
+    fn write_scope_owners(
+        &self,
+        repo: &Repository,
+        scope: &str,
+        owners: &[OwnerId],
+    ) -> anyhow::Result<()> {
+        let mut path = self.scope_path(scope)?;
+
+        create_dir_all(&path)?;
+        path.push(OWNERS_FILE_NAME);
+        fs_err::write(&path, serde_json::to_string(owners)?)?;
+
+        git_util::commit_and_push(
+            repo,
+            self.access_token.clone(),
+            &format!("Update owners for {}/*", scope),
+            &self.path,
+            &path,
+        )
+    }
+
+    /// Read the list of GitHub teams, as `org/team-slug`, whose members own a
+    /// scope alongside its individual owners.
+    pub fn get_scope_teams(&self, scope: &str) -> anyhow::Result<Vec<String>> {
+        let path = self.scope_path(scope)?.join(TEAMS_FILE_NAME);
+
+        match File::open(path) {
+            Ok(file) => serde_json::from_reader(file)
+                .with_context(|| format!("could not parse teams file for scope {}", scope)),
+
+            Err(error) => match error.kind() {
+                ErrorKind::NotFound => Ok(Vec::new()),
+                _ => Err(error)
+                    .with_context(|| format!("failed to read teams file for scope {}", scope)),
+            },
+        }
+    }
+
+    /// Add a GitHub team to a scope's teams file, making its members owners
+    /// of the scope.
+    pub fn add_scope_team(&self, scope: &str, team: &str) -> anyhow::Result<()> {
+        let repo = self.repository.lock().unwrap();
+        let _write_lock = self.lock_for_write(&repo)?;
+        let mut path = self.scope_path(scope)?;
+
+        create_dir_all(&path)?;
+        path.push(TEAMS_FILE_NAME);
+
+        let mut teams = self.get_scope_teams(scope)?;
+
+        if teams.iter().any(|existing| existing == team) {
+            return Ok(());
+        }
+
+        teams.push(team.to_owned());
+        fs_err::write(&path, serde_json::to_string(&teams)?)?;
+
+        git_util::commit_and_push(
+            &repo,
+            self.access_token.clone(),
+            &format!("Add team {} for {}/*", team, scope),
+            &self.path,
+            &path,
+        )?;
+
+        Ok(())
+    }
+
+    /// Read the minisign public keys that packages published to a scope can be
+    /// signed with.
+    pub fn get_scope_signing_keys(&self, scope: &str) -> anyhow::Result<Vec<String>> {
+        let path = self.scope_path(scope)?.join(SIGNING_KEYS_FILE_NAME);
+
+        match File::open(path) {
+            Ok(file) => serde_json::from_reader(file)
+                .with_context(|| format!("could not parse signing keys file for scope {}", scope)),
+
+            Err(error) => match error.kind() {
+                ErrorKind::NotFound => Ok(Vec::new()),
+                _ => Err(error).with_context(|| {
+                    format!("failed to read signing keys file for scope {}", scope)
+                }),
+            },
+        }
+    }
+
+    /// Replace the signing keys of a scope. Like `set_scope_owners`, `check`
+    /// runs while holding the index's write lock, so that whoever is allowed
+    /// to change the keys can't change in between.
+    pub fn set_scope_signing_keys<F, E>(
+        &self,
+        scope: &str,
+        keys: &[String],
+        check: F,
+    ) -> Result<(), E>
+    where
+        F: FnOnce() -> Result<(), E>,
+        E: From<anyhow::Error>,
+    {
+        let repo = self.repository.lock().unwrap();
+        let _write_lock = self.lock_for_write(&repo)?;
+
+        check()?;
+        self.write_scope_signing_keys(&repo, scope, keys)?;
+
+        Ok(())
+    }
+
+    fn write_scope_signing_keys(
+        &self,
+        repo: &Repository,
+        scope: &str,
+        keys: &[String],
+    ) -> anyhow::Result<()> {
+        let mut path = self.scope_path(scope)?;
+
+        create_dir_all(&path)?;
+        path.push(SIGNING_KEYS_FILE_NAME);
+        fs_err::write(&path, serde_json::to_string(keys)?)?;
+
+        git_util::commit_and_push(
+            repo,
+            self.access_token.clone(),
+            &format!("Update signing keys for {}/*", scope),
+            &self.path,
+            &path,
+        )
+    }
+
+    /// Put the local copy of the index back the way it was before a change
+    /// that failed: at the remote's latest commit if it can be fetched, and
+    /// otherwise at `head`. Files the change created are removed, since a
+    /// reset leaves behind files that were never committed.
+    fn roll_back(&self, repo: &Repository, head: Oid, created: &[PathBuf]) {
+        let reset = git_util::update_index(self.access_token.clone(), repo)
+            .or_else(|_| git_util::reset_index(repo, head));
+
+        if let Err(err) = reset {
+            log::error!(
+                "Could not reset package index after failed change: {:?}",
+                err
+            );
+        }
+
+        for path in created {
+            if path.exists() {
+                if let Err(err) = fs_err::remove_file(path) {
+                    log::error!("Could not clean up package index: {:?}", err);
+                }
+            }
+        }
+
+        if let Err(err) = remove_empty_dirs(&self.path) {
+            log::error!("Could not clean up package index: {:?}", err);
+        }
+    }
+
+    /// Take the write lock, if there is one, and then catch our copy of the
+    /// index up with anything other processes pushed while we were waiting.
+    fn lock_for_write(&self, repo: &Repository) -> anyhow::Result<Option<IndexLockGuard>> {
+        let lock = match &self.write_lock {
+            Some(lock) => lock,
+            None => return Ok(None),
+        };
+
+        let guard = lock.acquire()?;
+
+        git_util::update_index(self.access_token.clone(), repo)
+            .context("could not update package index")?;
+
+        // Packages we've cached may have changed underneath us.
+        self.package_cache.lock().unwrap().clear();
+
+        Ok(Some(guard))
+    }
+
+    /// List every package in the index.
+    pub fn package_names(&self) -> anyhow::Result<Vec<PackageName>> {
+        let mut names = Vec::new();
+
+        for (scope, scope_dir) in self.scope_dirs()? {
+            names.extend(read_package_names(&scope, &scope_dir)?);
+        }
+
+        names.sort();
+        Ok(names)
+    }
+
+    /// List every scope in the index, whether it has packages or only owners.
+    pub fn scope_names(&self) -> anyhow::Result<Vec<String>> {
+        Ok(self.scope_dirs()?.keys().cloned().collect())
+    }
+
+    /// Whether anything has ever been published to, or owned in, a scope.
+    pub fn scope_exists(&self, scope: &str) -> anyhow::Result<bool> {
+        Ok(self.scope_path(scope)?.is_dir())
+    }
+
+    /// List every package in a scope. A scope that doesn't exist has none.
+    pub fn scope_package_names(&self, scope: &str) -> anyhow::Result<Vec<PackageName>> {
+        let scope = canonical_scope(scope)?;
+        let scope_dir = self.scope_path(&scope)?;
+
+        if !scope_dir.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut names = read_package_names(&scope, &scope_dir)?;
+        names.sort();
+        Ok(names)
+    }
+
+    /// Reads how this index is laid out from its config. Indexes without a
+    /// config, like ones that were just created, use the flat layout.
+    pub fn layout(&self) -> anyhow::Result<IndexLayout> {
+        if !self.path.join(CONFIG_FILE_NAME).exists() {
+            return Ok(IndexLayout::Flat);
+        }
+
+        Ok(self.config()?.layout)
+    }
+
+    /// Move every scope in the index to match `layout`, record the new layout
+    /// in the index's config, and push all of it as a single commit.
+    ///
+    /// Returns the number of scopes that were moved.
+    pub fn migrate_layout(&self, layout: IndexLayout) -> anyhow::Result<usize> {
+        let repo = self.repository.lock().unwrap();
+        let _write_lock = self.lock_for_write(&repo)?;
+
+        if self.layout()? == layout {
+            return Ok(0);
+        }
+
+        let scope_dirs = self.scope_dirs()?;
+
+        // Scope directories in one layout can be the parents of directories in
+        // the other, like scope `ab` and the `ab/cd` shard. Moving everything
+        // out of the way first keeps us from moving scopes into each other.
+        let staging_dir = self.path.join(".wally-migrate");
+        create_dir_all(&staging_dir)?;
+
+        for (scope, scope_dir) in &scope_dirs {
+            fs_err::rename(scope_dir, staging_dir.join(scope))?;
+        }
+
+        remove_empty_dirs(&self.path)?;
+
+        for scope in scope_dirs.keys() {
+            let destination = self.path.join(layout.scope_dir(scope));
+            create_dir_all(destination.parent().unwrap())?;
+            fs_err::rename(staging_dir.join(scope), destination)?;
+        }
+
+        fs_err::remove_dir(&staging_dir)?;
+
+        // Edit the config as plain JSON so that any fields we don't know about
+        // are left alone.
+        let config_path = self.path.join(CONFIG_FILE_NAME);
+        let mut config: serde_json::Value =
+            serde_json::from_str(&fs_err::read_to_string(&config_path)?)?;
+        config["layout"] = serde_json::to_value(layout)?;
+        fs_err::write(&config_path, serde_json::to_string_pretty(&config)? + "\n")?;
+
+        git_util::commit_all_and_push(
+            &repo,
+            self.access_token.clone(),
+            &format!("Migrate index to {} layout", layout),
+        )?;
+
+        self.package_cache.lock().unwrap().clear();
+
+        Ok(scope_dirs.len())
+    }
+
+    /// Find the directory of every scope in the index, keyed by scope name.
+    /// Scope directories are the ones that directly contain files, since
+    /// every package and owners file lives in its scope's directory.
+    fn scope_dirs(&self) -> anyhow::Result<BTreeMap<String, PathBuf>> {
+        let mut scopes = BTreeMap::new();
+
+        let entries = WalkDir::new(&self.path)
+            .min_depth(2)
+            .into_iter()
+            .filter_entry(|entry| !entry.file_name().to_string_lossy().starts_with('.'));
+
+        for entry in entries {
+            let entry = entry?;
+
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            let scope_dir = entry.path().parent().unwrap();
+            if let Some(scope) = scope_dir.file_name().and_then(|name| name.to_str()) {
+                scopes.insert(scope.to_owned(), scope_dir.to_owned());
+            }
+        }
+
+        Ok(scopes)
+    }
+
+    fn scope_path(&self, scope: &str) -> anyhow::Result<PathBuf> {
+        // Scopes can come straight from a request, so make sure they can't
+        // point outside of the index, and that scopes differing only in case
+        // share the same owners.
+        let scope = canonical_scope(scope)?;
+
+        Ok(self.path.join(self.layout()?.scope_dir(&scope)))
     }
+
+    fn package_path(&self, name: &PackageName) -> anyhow::Result<PathBuf> {
+        // Each package has all of its versions stored in a file based on its
+        // scope and name. Where that file lives depends on the index layout.
+        Ok(self.path.join(self.layout()?.package_file(name)))
+    }
+}
+
+/// The package whose file is at `path` in an index with `layout`, if it's a
+/// package file at all rather than something like a scope's owners.
+fn package_name_from_path(layout: IndexLayout, path: &Path) -> Option<PackageName> {
+    let name = path.file_name()?.to_str()?;
+    let scope = path.parent()?.file_name()?.to_str()?;
+    let name = PackageName::new(scope, name).ok()?;
+
+    if layout.package_file(&name) == path {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+fn read_package_names(scope: &str, scope_dir: &Path) -> anyhow::Result<Vec<PackageName>> {
+    let mut names = Vec::new();
+
+    for entry in fs_err::read_dir(scope_dir)? {
+        let entry = entry?;
+
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+
+        // Anything that can't be a package name, like the owners file, isn't a
+        // package.
+        let name = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| PackageName::new(scope, name).ok());
+
+        if let Some(name) = name {
+            names.push(name);
+        }
+    }
+
+    Ok(names)
 }
 
 #[derive(Default, Serialize)]
 pub struct PackageMetadata {
     pub versions: Vec<Manifest>,
+
+    /// Versions that have been yanked from the index.
+    pub yanked: BTreeSet<Version>,
+
+    /// Hashes of the published archive of each version. Versions published
+    /// before checksums were recorded don't have one.
+    pub checksums: BTreeMap<Version, String>,
+
+    /// Hex-encoded SHA-256 hashes of the published archive of each version,
+    /// for clients checking what they downloaded with standard tools.
+    /// Versions published before these were recorded don't have one.
+    pub sha256: BTreeMap<Version, String>,
+
+    /// When each version was published, in seconds since the Unix epoch.
+    /// Versions published before this was recorded don't have a time.
+    pub published: BTreeMap<Version, u64>,
+
+    /// Who published each version, like a GitHub login. Versions published
+    /// before this was recorded don't have a publisher.
+    pub publishers: BTreeMap<Version, String>,
+
+    /// Messages left by the package's owners on versions they've deprecated,
+    /// like which package to use instead.
+    pub deprecated: BTreeMap<Version, String>,
+
+    /// The minisign signature each signed version was published with, as the
+    /// text of a `.minisig` file. The registry checked it against its scope's
+    /// signing keys when it was published.
+    pub signatures: BTreeMap<Version, String>,
+}
+
+impl PackageMetadata {
+    pub fn is_yanked(&self, version: &Version) -> bool {
+        self.yanked.contains(version)
+    }
+
+    pub fn checksum(&self, version: &Version) -> Option<&str> {
+        self.checksums.get(version).map(String::as_str)
+    }
+
+    pub fn sha256(&self, version: &Version) -> Option<&str> {
+        self.sha256.get(version).map(String::as_str)
+    }
+
+    pub fn published_at(&self, version: &Version) -> Option<u64> {
+        self.published.get(version).copied()
+    }
+
+    pub fn publisher(&self, version: &Version) -> Option<&str> {
+        self.publishers.get(version).map(String::as_str)
+    }
+
+    pub fn deprecation(&self, version: &Version) -> Option<&str> {
+        self.deprecated.get(version).map(String::as_str)
+    }
+
+    pub fn signature(&self, version: &Version) -> Option<&str> {
+        self.signatures.get(version).map(String::as_str)
+    }
+}
+
+/// Hashes of a package's archive, and its signature if it has one, recorded
+/// in the index when it's published.
+#[derive(Debug, Clone, Default)]
+pub struct ArchiveChecksums {
+    /// The BLAKE3 hash given by `package_integrity::archive_hash`.
+    pub blake3: Option<String>,
+
+    /// The hex-encoded SHA-256 hash of the archive.
+    pub sha256: Option<String>,
+
+    /// A minisign signature of the archive, as the text of a `.minisig` file.
+    pub signature: Option<String>,
+}
+
+/// A single line of a package's file in the index. Entries are the published
+/// manifest, plus any state about that version that the registry manages.
+#[derive(Serialize, Deserialize)]
+struct IndexEntry {
+    #[serde(flatten)]
+    manifest: Manifest,
+
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    yanked: bool,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    checksum: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+
+    /// When the version was published, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    published_at: Option<u64>,
+
+    /// Who published the version, as the registry knows them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    published_by: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deprecated: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
+}
+
+fn write_index_entries(package_path: &Path, entries: &[IndexEntry]) -> anyhow::Result<()> {
+    let mut file = File::create(package_path)?;
+
+    for entry in entries {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        file.write_all(line.as_bytes())?;
+    }
+
+    Ok(())
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+fn read_index_entries(package_path: &Path) -> anyhow::Result<Vec<IndexEntry>> {
+    let file = BufReader::new(File::open(package_path)?);
+
+    // Entries into the index are stored as JSON Lines. This will either parse
+    // all of the entries, or fail with a single error.
+    let entries = serde_json::Deserializer::from_reader(file)
+        .into_iter::<IndexEntry>()
+        .collect::<Result<Vec<_>, _>>()
+        .context("could not parse package index entry")?;
+
+    Ok(entries)
+}
+
+/// Remove every empty directory under `root`, leaving `root` itself.
+fn remove_empty_dirs(root: &Path) -> anyhow::Result<()> {
+    let dirs = WalkDir::new(root)
+        .min_depth(1)
+        .contents_first(true)
+        .into_iter()
+        .filter_entry(|entry| !entry.file_name().to_string_lossy().starts_with('.'));
+
+    for entry in dirs {
+        let entry = entry?;
+
+        if entry.file_type().is_dir() && fs_err::read_dir(entry.path())?.next().is_none() {
+            fs_err::remove_dir(entry.path())?;
+        }
+    }
+
+    Ok(())
 }
 
 fn index_path(index_url: &Url) -> anyhow::Result<PathBuf> {
@@ -282,3 +1462,94 @@ fn index_path(index_url: &Url) -> anyhow::Result<PathBuf> {
 
     Ok(path)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn scope_dir(layout: IndexLayout, scope: &str) -> String {
+        layout.scope_dir(scope).to_str().unwrap().replace('\\', "/")
+    }
+
+    #[test]
+    fn flat_layout() {
+        assert_eq!(scope_dir(IndexLayout::Flat, "biff"), "biff");
+    }
+
+    #[test]
+    fn sharded_layout() {
+        assert_eq!(scope_dir(IndexLayout::Sharded, "a"), "1/a");
+        assert_eq!(scope_dir(IndexLayout::Sharded, "ab"), "2/ab");
+        assert_eq!(scope_dir(IndexLayout::Sharded, "abc"), "3/a/abc");
+        assert_eq!(scope_dir(IndexLayout::Sharded, "biff"), "bi/ff/biff");
+        assert_eq!(scope_dir(IndexLayout::Sharded, "roblox"), "ro/bl/roblox");
+    }
+
+    #[test]
+    fn sharded_package_file() {
+        let name: PackageName = "biff/minimal".parse().unwrap();
+        let path = IndexLayout::Sharded.package_file(&name);
+        assert_eq!(path, Path::new("bi/ff/biff/minimal"));
+    }
+
+    #[test]
+    fn package_names_from_changed_paths() {
+        let name: PackageName = "biff/minimal".parse().unwrap();
+
+        assert_eq!(
+            package_name_from_path(IndexLayout::Flat, Path::new("biff/minimal")),
+            Some(name.clone())
+        );
+        assert_eq!(
+            package_name_from_path(IndexLayout::Sharded, Path::new("bi/ff/biff/minimal")),
+            Some(name)
+        );
+
+        // Scope files, and package files where the layout doesn't put them,
+        // aren't packages.
+        assert_eq!(
+            package_name_from_path(IndexLayout::Flat, Path::new("biff/owners.json")),
+            None
+        );
+        assert_eq!(
+            package_name_from_path(IndexLayout::Sharded, Path::new("biff/minimal")),
+            None
+        );
+        assert_eq!(
+            package_name_from_path(IndexLayout::Flat, Path::new("config.json")),
+            None
+        );
+    }
+
+    #[test]
+    fn layout_in_config() {
+        let config: PackageIndexConfig =
+            serde_json::from_str(r#"{ "api": "https://example.com", "layout": "sharded" }"#)
+                .unwrap();
+        assert_eq!(config.layout, IndexLayout::Sharded);
+
+        let config: PackageIndexConfig =
+            serde_json::from_str(r#"{ "api": "https://example.com" }"#).unwrap();
+        assert_eq!(config.layout, IndexLayout::Flat);
+    }
+
+    #[test]
+    fn owner_ids() {
+        let owners: Vec<OwnerId> = serde_json::from_str(r#"[1, "enterprise:1"]"#).unwrap();
+        assert_eq!(
+            owners,
+            vec![OwnerId::new(1), OwnerId::with_provider("enterprise", 1)]
+        );
+        assert_ne!(owners[0], owners[1]);
+
+        // Owners from the main host are written the way they always were.
+        assert_eq!(
+            serde_json::to_string(&owners).unwrap(),
+            r#"[1,"enterprise:1"]"#
+        );
+
+        assert_eq!("1".parse::<OwnerId>().unwrap(), OwnerId::new(1));
+        assert!(":1".parse::<OwnerId>().is_err());
+        assert!("enterprise:biff".parse::<OwnerId>().is_err());
+    }
+}
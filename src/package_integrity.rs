@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+use std::io::{self, Cursor};
+use std::path::Path;
+
+use anyhow::{bail, Context};
+use fs_err::File;
+use serde::{Deserialize, Serialize};
+use zip::ZipArchive;
+
+use crate::package_contents::PackageContents;
+
+/// The hash algorithm used for every hash in an integrity document.
+pub const INTEGRITY_ALGORITHM: &str = "blake3";
+
+/// Lists the hash of a package archive and of every file inside it, so that a
+/// client can check individual files after the package has been extracted.
+///
+/// Integrity documents are generated by the registry when a package is
+/// published and served alongside the package contents.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PackageIntegrity {
+    /// The algorithm used for the hashes in this document.
+    pub algorithm: String,
+
+    /// Hash of the package archive as it was published.
+    pub archive: String,
+
+    /// Hashes of each file in the archive, keyed by their path relative to the
+    /// root of the package.
+    pub files: BTreeMap<String, String>,
+}
+
+impl PackageIntegrity {
+    /// Generate the integrity document for the given package archive.
+    pub fn from_contents(contents: &PackageContents) -> anyhow::Result<Self> {
+        let mut archive =
+            ZipArchive::new(Cursor::new(contents.data())).context("could not read ZIP archive")?;
+        let mut files = BTreeMap::new();
+
+        for index in 0..archive.len() {
+            let mut file = archive.by_index(index)?;
+
+            if file.is_dir() {
+                continue;
+            }
+
+            if file.enclosed_name().is_none() {
+                bail!(
+                    "archive contains a file with an unsafe path: {}",
+                    file.name()
+                );
+            }
+
+            let name = file.name().to_owned();
+            let mut hasher = blake3::Hasher::new();
+            io::copy(&mut file, &mut hasher)?;
+
+            files.insert(name, hasher.finalize().to_hex().to_string());
+        }
+
+        Ok(Self {
+            algorithm: INTEGRITY_ALGORITHM.to_owned(),
+            archive: archive_hash(contents),
+            files,
+        })
+    }
+
+    /// Check that the given package archive is the one this document was
+    /// generated from.
+    pub fn verify_archive(&self, contents: &PackageContents) -> anyhow::Result<()> {
+        self.check_algorithm()?;
+
+        if archive_hash(contents) != self.archive {
+            bail!("package archive does not match its integrity document");
+        }
+
+        Ok(())
+    }
+
+    /// Check every file listed in this document against the files extracted
+    /// into the given directory.
+    pub fn verify_directory(&self, root: &Path) -> anyhow::Result<()> {
+        let mismatched = self.mismatched_files(root)?;
+
+        if !mismatched.is_empty() {
+            bail!(
+                "{} file(s) do not match the integrity document: {}",
+                mismatched.len(),
+                mismatched.join(", ")
+            );
+        }
+
+        Ok(())
+    }
+
+    /// The files listed in this document that are missing from the given
+    /// directory or don't match their hash, in order.
+    pub fn mismatched_files(&self, root: &Path) -> anyhow::Result<Vec<String>> {
+        self.check_algorithm()?;
+
+        let mut mismatched = Vec::new();
+
+        for (name, expected) in &self.files {
+            if Path::new(name).is_absolute() || name.split('/').any(|part| part == "..") {
+                bail!("integrity document contains an unsafe path: {}", name);
+            }
+
+            let path = root.join(name);
+            let actual = match File::open(&path) {
+                Ok(mut file) => {
+                    let mut hasher = blake3::Hasher::new();
+                    io::copy(&mut file, &mut hasher)?;
+                    Some(hasher.finalize().to_hex().to_string())
+                }
+                Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+                Err(err) => return Err(err.into()),
+            };
+
+            if actual.as_ref() != Some(expected) {
+                mismatched.push(name.clone());
+            }
+        }
+
+        Ok(mismatched)
+    }
+
+    fn check_algorithm(&self) -> anyhow::Result<()> {
+        if self.algorithm != INTEGRITY_ALGORITHM {
+            bail!(
+                "unsupported integrity algorithm '{}', expected '{}'",
+                self.algorithm,
+                INTEGRITY_ALGORITHM
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Hash a package archive the same way integrity documents and lockfile
+/// checksums do.
+pub fn archive_hash(contents: &PackageContents) -> String {
+    hash(contents.data())
+}
+
+fn hash(data: &[u8]) -> String {
+    blake3::hash(data).to_hex().to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use zip::{write::FileOptions, ZipWriter};
+
+    use super::*;
+
+    fn test_contents() -> PackageContents {
+        let mut data = Vec::new();
+        let mut archive = ZipWriter::new(Cursor::new(&mut data));
+
+        archive
+            .add_directory("src", FileOptions::default())
+            .unwrap();
+        archive
+            .start_file("src/init.lua", FileOptions::default())
+            .unwrap();
+        archive.write_all(b"return {}").unwrap();
+        archive
+            .start_file("wally.toml", FileOptions::default())
+            .unwrap();
+        archive.write_all(b"[package]").unwrap();
+        archive.finish().unwrap();
+        drop(archive);
+
+        PackageContents::from_buffer(data)
+    }
+
+    #[test]
+    fn lists_files() {
+        let integrity = PackageIntegrity::from_contents(&test_contents()).unwrap();
+
+        let names: Vec<_> = integrity.files.keys().map(String::as_str).collect();
+        assert_eq!(names, vec!["src/init.lua", "wally.toml"]);
+        assert_eq!(integrity.files["src/init.lua"], hash(b"return {}"));
+    }
+
+    #[test]
+    fn verify_archive() {
+        let contents = test_contents();
+        let integrity = PackageIntegrity::from_contents(&contents).unwrap();
+
+        integrity.verify_archive(&contents).unwrap();
+        integrity
+            .verify_archive(&PackageContents::from_buffer(b"not a zip".to_vec()))
+            .unwrap_err();
+    }
+
+    #[test]
+    fn verify_directory() {
+        let contents = test_contents();
+        let integrity = PackageIntegrity::from_contents(&contents).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        contents.unpack_into_path(dir.path()).unwrap();
+        integrity.verify_directory(dir.path()).unwrap();
+
+        fs_err::write(dir.path().join("src/init.lua"), "return nil").unwrap();
+        let err = integrity.verify_directory(dir.path()).unwrap_err();
+        assert!(err.to_string().contains("src/init.lua"));
+    }
+
+    #[test]
+    fn unsupported_algorithm() {
+        let contents = test_contents();
+        let mut integrity = PackageIntegrity::from_contents(&contents).unwrap();
+        integrity.algorithm = "md5".to_owned();
+
+        integrity.verify_archive(&contents).unwrap_err();
+    }
+}
@@ -37,6 +37,21 @@ impl PackageName {
         Ok(PackageName { scope, name })
     }
 
+    /// Like `new`, but first puts the scope and name in their canonical form,
+    /// so that `MyOrg/Pkg` and `myorg/pkg` refer to the same package. Use this
+    /// for names coming from outside, like from a request.
+    pub fn canonical<S, N>(scope: S, name: N) -> anyhow::Result<Self>
+    where
+        S: AsRef<str>,
+        N: AsRef<str>,
+    {
+        let scope = canonical_scope(scope.as_ref())?;
+        let name = name.as_ref().to_ascii_lowercase();
+        validate_name(&name)?;
+
+        Ok(PackageName { scope, name })
+    }
+
     pub fn scope(&self) -> &str {
         &self.scope
     }
@@ -46,7 +61,11 @@ impl PackageName {
     }
 }
 
-fn validate_scope(scope: &str) -> anyhow::Result<()> {
+/// Checks that `scope` is a valid package scope. Anything accepting a scope on
+/// its own, without a package name, should use this before turning the scope
+/// into a path. Since only lowercase letters, digits, and dashes are valid,
+/// this rules out path separators, `..`, and control characters.
+pub fn validate_scope(scope: &str) -> anyhow::Result<()> {
     let only_valid_chars = scope
         .chars()
         .all(|char| char.is_ascii_lowercase() || char.is_ascii_digit() || char == '-');
@@ -66,6 +85,16 @@ fn validate_scope(scope: &str) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Puts a scope in the form it's stored under in the index. Scopes are case
+/// insensitive, so they're lowercased, and they're then checked like
+/// `validate_scope` does.
+pub fn canonical_scope(scope: &str) -> anyhow::Result<String> {
+    let scope = scope.to_ascii_lowercase();
+    validate_scope(&scope)?;
+
+    Ok(scope)
+}
+
 fn validate_name(name: &str) -> anyhow::Result<()> {
     let only_valid_chars = name
         .chars()
@@ -137,6 +166,20 @@ impl<'de> Visitor<'de> for PackageNameVisitor {
 mod test {
     use super::*;
 
+    #[test]
+    fn canonical() {
+        let package = PackageName::canonical("Flub-Flab", "Sisyphus-Simulator-2").unwrap();
+        assert_eq!(
+            package,
+            PackageName::new("flub-flab", "sisyphus-simulator-2").unwrap()
+        );
+
+        assert_eq!(canonical_scope("FooBar").unwrap(), "foobar");
+        canonical_scope("foo/../bar").unwrap_err();
+        PackageName::canonical("foo", "b@r").unwrap_err();
+        PackageName::new("Foo", "bar").unwrap_err();
+    }
+
     #[test]
     fn new() {
         let package = PackageName::new("flub-flab", "sisyphus-simulator-2").unwrap();
@@ -159,6 +202,21 @@ mod test {
         assert!(PackageName::new("", "").is_err());
     }
 
+    #[test]
+    fn new_path_traversal() {
+        assert!(PackageName::new("..", "foo").is_err());
+        assert!(PackageName::new("biff", "..").is_err());
+        assert!(PackageName::new("../biff", "foo").is_err());
+        assert!(PackageName::new("biff", "..\\foo").is_err());
+        assert!(PackageName::new("biff", "%2e%2e%2ffoo").is_err());
+        assert!(PackageName::new("bi\0ff", "foo").is_err());
+        assert!(PackageName::new("biff", "foo\n").is_err());
+
+        assert!(validate_scope("../../etc").is_err());
+        assert!(validate_scope("bi\0ff").is_err());
+        assert!(validate_scope("biff").is_ok());
+    }
+
     #[test]
     fn parse() {
         let adopt_me: PackageName = "flub-flab/sisyphus-simulator".parse().unwrap();
@@ -1,20 +1,27 @@
 mod in_memory;
+mod local;
+mod offline;
 mod registry;
 mod test_registry;
 
 pub use self::in_memory::InMemoryRegistry;
 use self::in_memory::InMemoryRegistrySource;
+pub use self::local::{add_local_dependencies, LocalPackage};
+pub use self::offline::OfflineRegistry;
 pub use self::registry::Registry;
 pub use self::test_registry::TestRegistry;
 
 use std::collections::HashMap;
+use std::io::Write;
 use std::path::PathBuf;
 
 use serde::Serialize;
 
 use crate::manifest::Manifest;
+use crate::package_cache::PartialDownload;
 use crate::package_contents::PackageContents;
 use crate::package_id::PackageId;
+use crate::package_integrity::PackageIntegrity;
 use crate::package_req::PackageReq;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
@@ -22,6 +29,28 @@ pub enum PackageSourceId {
     DefaultRegistry,
     Git(String),
     Path(PathBuf),
+
+    /// A path dependency, by its path relative to the project.
+    PathDependency(PathBuf),
+
+    /// A Git dependency, checked out at `commit`.
+    GitDependency {
+        url: String,
+        rev: Option<String>,
+        commit: String,
+    },
+}
+
+impl PackageSourceId {
+    /// Whether this is a single package depended on by path or from Git,
+    /// rather than a registry. Their contents can change without their
+    /// version changing, so they're never cached.
+    pub fn is_local(&self) -> bool {
+        matches!(
+            self,
+            PackageSourceId::PathDependency(_) | PackageSourceId::GitDependency { .. }
+        )
+    }
 }
 
 #[derive(Clone)]
@@ -71,6 +100,10 @@ impl PackageSourceMap {
                         PackageSourceId::DefaultRegistry => {
                             panic!("Default registry should never be added as a fallback source!")
                         }
+                        PackageSourceId::PathDependency(_)
+                        | PackageSourceId::GitDependency { .. } => {
+                            panic!("Local packages should never be added as a fallback source!")
+                        }
                     };
 
                     self.sources.insert(fallback.clone(), source);
@@ -83,6 +116,19 @@ impl PackageSourceMap {
 
         Ok(())
     }
+
+    /// Adds a package depended on by path or from Git. It's checked before
+    /// every registry, so that it's used in place of any published versions
+    /// of the same package.
+    pub fn add_local(&mut self, id: PackageSourceId, package: LocalPackage) {
+        if self.sources.contains_key(&id) {
+            return;
+        }
+
+        self.sources
+            .insert(id.clone(), Box::new(PackageSource::Local(package)));
+        self.source_order.insert(0, id);
+    }
 }
 
 pub trait PackageSourceProvider: Sync + Send + Clone {
@@ -97,6 +143,47 @@ pub trait PackageSourceProvider: Sync + Send + Clone {
     /// `PackageId`.
     fn download_package(&self, package_id: &PackageId) -> anyhow::Result<PackageContents>;
 
+    /// Downloads the contents of a package into `download`, writing them as
+    /// they arrive. Sources that can resume a download carry on from what's
+    /// already in it; by default, it's started over.
+    fn download_package_into(
+        &self,
+        package_id: &PackageId,
+        download: &mut PartialDownload,
+    ) -> anyhow::Result<()> {
+        let contents = self.download_package(package_id)?;
+        download.restart()?;
+        download.write_all(contents.data())?;
+        Ok(())
+    }
+
+    /// Downloads the integrity document of a package, if this source serves
+    /// them and has one for the package.
+    fn download_integrity(
+        &self,
+        _package_id: &PackageId,
+    ) -> anyhow::Result<Option<PackageIntegrity>> {
+        Ok(None)
+    }
+
+    /// The hash the source recorded for a package's archive when it was
+    /// published, as given by `package_integrity::archive_hash`, if it has one.
+    fn checksum(&self, _package_id: &PackageId) -> anyhow::Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// Whether a package version has been yanked. Yanked versions can still be
+    /// downloaded, but aren't picked for new installs.
+    fn is_yanked(&self, _package_id: &PackageId) -> anyhow::Result<bool> {
+        Ok(false)
+    }
+
+    /// The message a package version was deprecated with, if it has been.
+    /// Deprecated versions are still picked for installs, with a warning.
+    fn deprecation(&self, _package_id: &PackageId) -> anyhow::Result<Option<String>> {
+        Ok(None)
+    }
+
     /// Provide a list of fallback sources to search if this source can't provide a package
     fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>>;
 }
@@ -104,6 +191,8 @@ pub trait PackageSourceProvider: Sync + Send + Clone {
 #[derive(Clone)]
 pub enum PackageSource {
     InMemory(InMemoryRegistrySource),
+    Local(LocalPackage),
+    Offline(OfflineRegistry),
     Registry(Registry),
     TestRegistry(TestRegistry),
 }
@@ -112,6 +201,8 @@ impl PackageSourceProvider for PackageSource {
     fn update(&self) -> anyhow::Result<()> {
         match self {
             PackageSource::InMemory(source) => source.update(),
+            PackageSource::Local(source) => source.update(),
+            PackageSource::Offline(source) => source.update(),
             PackageSource::Registry(source) => source.update(),
             PackageSource::TestRegistry(source) => source.update(),
         }
@@ -120,6 +211,8 @@ impl PackageSourceProvider for PackageSource {
     fn query(&self, package_req: &PackageReq) -> anyhow::Result<Vec<Manifest>> {
         match self {
             PackageSource::InMemory(source) => source.query(package_req),
+            PackageSource::Local(source) => source.query(package_req),
+            PackageSource::Offline(source) => source.query(package_req),
             PackageSource::Registry(source) => source.query(package_req),
             PackageSource::TestRegistry(source) => source.query(package_req),
         }
@@ -128,14 +221,77 @@ impl PackageSourceProvider for PackageSource {
     fn download_package(&self, package_id: &PackageId) -> anyhow::Result<PackageContents> {
         match self {
             PackageSource::InMemory(source) => source.download_package(package_id),
+            PackageSource::Local(source) => source.download_package(package_id),
+            PackageSource::Offline(source) => source.download_package(package_id),
             PackageSource::Registry(source) => source.download_package(package_id),
             PackageSource::TestRegistry(source) => source.download_package(package_id),
         }
     }
 
+    fn download_package_into(
+        &self,
+        package_id: &PackageId,
+        download: &mut PartialDownload,
+    ) -> anyhow::Result<()> {
+        match self {
+            PackageSource::InMemory(source) => source.download_package_into(package_id, download),
+            PackageSource::Local(source) => source.download_package_into(package_id, download),
+            PackageSource::Offline(source) => source.download_package_into(package_id, download),
+            PackageSource::Registry(source) => source.download_package_into(package_id, download),
+            PackageSource::TestRegistry(source) => {
+                source.download_package_into(package_id, download)
+            }
+        }
+    }
+
+    fn download_integrity(
+        &self,
+        package_id: &PackageId,
+    ) -> anyhow::Result<Option<PackageIntegrity>> {
+        match self {
+            PackageSource::InMemory(source) => source.download_integrity(package_id),
+            PackageSource::Local(source) => source.download_integrity(package_id),
+            PackageSource::Offline(source) => source.download_integrity(package_id),
+            PackageSource::Registry(source) => source.download_integrity(package_id),
+            PackageSource::TestRegistry(source) => source.download_integrity(package_id),
+        }
+    }
+
+    fn checksum(&self, package_id: &PackageId) -> anyhow::Result<Option<String>> {
+        match self {
+            PackageSource::InMemory(source) => source.checksum(package_id),
+            PackageSource::Local(source) => source.checksum(package_id),
+            PackageSource::Offline(source) => source.checksum(package_id),
+            PackageSource::Registry(source) => source.checksum(package_id),
+            PackageSource::TestRegistry(source) => source.checksum(package_id),
+        }
+    }
+
+    fn is_yanked(&self, package_id: &PackageId) -> anyhow::Result<bool> {
+        match self {
+            PackageSource::InMemory(source) => source.is_yanked(package_id),
+            PackageSource::Local(source) => source.is_yanked(package_id),
+            PackageSource::Offline(source) => source.is_yanked(package_id),
+            PackageSource::Registry(source) => source.is_yanked(package_id),
+            PackageSource::TestRegistry(source) => source.is_yanked(package_id),
+        }
+    }
+
+    fn deprecation(&self, package_id: &PackageId) -> anyhow::Result<Option<String>> {
+        match self {
+            PackageSource::InMemory(source) => source.deprecation(package_id),
+            PackageSource::Local(source) => source.deprecation(package_id),
+            PackageSource::Offline(source) => source.deprecation(package_id),
+            PackageSource::Registry(source) => source.deprecation(package_id),
+            PackageSource::TestRegistry(source) => source.deprecation(package_id),
+        }
+    }
+
     fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>> {
         match self {
             PackageSource::InMemory(source) => source.fallback_sources(),
+            PackageSource::Local(source) => source.fallback_sources(),
+            PackageSource::Offline(source) => source.fallback_sources(),
             PackageSource::Registry(source) => source.fallback_sources(),
             PackageSource::TestRegistry(source) => source.fallback_sources(),
         }
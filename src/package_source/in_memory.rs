@@ -43,7 +43,46 @@ impl InMemoryRegistry {
             .entry(manifest.package.name.name().to_owned())
             .or_default();
 
-        entries.push(PackageEntry { manifest, contents });
+        entries.push(PackageEntry {
+            manifest,
+            contents,
+            yanked: false,
+            deprecated: None,
+        });
+    }
+
+    /// Yank a package version that has already been published.
+    pub fn yank(&self, package_id: &PackageId) {
+        let mut storage = self.storage.contents.write().unwrap();
+
+        let entry = storage
+            .get_mut(package_id.name().scope())
+            .and_then(|scope| scope.get_mut(package_id.name().name()))
+            .and_then(|entries| {
+                entries
+                    .iter_mut()
+                    .find(|entry| &entry.manifest.package.version == package_id.version())
+            })
+            .expect("package to yank was not published");
+
+        entry.yanked = true;
+    }
+
+    /// Deprecate a package version that has already been published.
+    pub fn deprecate(&self, package_id: &PackageId, message: &str) {
+        let mut storage = self.storage.contents.write().unwrap();
+
+        let entry = storage
+            .get_mut(package_id.name().scope())
+            .and_then(|scope| scope.get_mut(package_id.name().name()))
+            .and_then(|entries| {
+                entries
+                    .iter_mut()
+                    .find(|entry| &entry.manifest.package.version == package_id.version())
+            })
+            .expect("package to deprecate was not published");
+
+        entry.deprecated = Some(message.to_owned());
     }
 
     /// Returns a handle to an object that can be used as a `PackageSource`.
@@ -110,6 +149,38 @@ impl PackageSourceProvider for InMemoryRegistrySource {
         Ok(entry.contents.clone())
     }
 
+    fn is_yanked(&self, package_id: &PackageId) -> anyhow::Result<bool> {
+        let storage = self.storage.contents.read().unwrap();
+
+        let yanked = storage
+            .get(package_id.name().scope())
+            .and_then(|scope| scope.get(package_id.name().name()))
+            .and_then(|entries| {
+                entries
+                    .iter()
+                    .find(|entry| &entry.manifest.package.version == package_id.version())
+            })
+            .map_or(false, |entry| entry.yanked);
+
+        Ok(yanked)
+    }
+
+    fn deprecation(&self, package_id: &PackageId) -> anyhow::Result<Option<String>> {
+        let storage = self.storage.contents.read().unwrap();
+
+        let deprecated = storage
+            .get(package_id.name().scope())
+            .and_then(|scope| scope.get(package_id.name().name()))
+            .and_then(|entries| {
+                entries
+                    .iter()
+                    .find(|entry| &entry.manifest.package.version == package_id.version())
+            })
+            .and_then(|entry| entry.deprecated.clone());
+
+        Ok(deprecated)
+    }
+
     fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>> {
         todo!("Implement in-memory fallback sources");
     }
@@ -118,6 +189,8 @@ impl PackageSourceProvider for InMemoryRegistrySource {
 struct PackageEntry {
     manifest: Manifest,
     contents: PackageContents,
+    yanked: bool,
+    deprecated: Option<String>,
 }
 
 #[derive(Clone, Default)]
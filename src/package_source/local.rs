@@ -0,0 +1,515 @@
+//! Defines a package source for a single package that's depended on by path
+//! or from a Git repository instead of from a registry. It's how packages get
+//! worked on together before any of them are published.
+//!
+//! Each one is loaded before resolving, and the dependencies on it are turned
+//! into dependencies on its exact version, so the resolver treats it like any
+//! other package.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, format_err, Context};
+use git2::{build::CheckoutBuilder, Oid, Repository};
+use semver::VersionReq;
+use url::Url;
+
+use crate::git_util;
+use crate::lockfile::{LockPackage, Lockfile};
+use crate::manifest::{Dependency, Manifest};
+use crate::package_id::PackageId;
+use crate::package_name::PackageName;
+use crate::package_req::PackageReq;
+
+use super::{PackageContents, PackageSourceId, PackageSourceMap, PackageSourceProvider};
+
+#[derive(Clone)]
+pub struct LocalPackage {
+    /// The package's manifest, with its own path and Git dependencies already
+    /// turned into exact version requirements.
+    manifest: Manifest,
+
+    /// Where the package is on disk.
+    dir: PathBuf,
+}
+
+impl PackageSourceProvider for LocalPackage {
+    fn update(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn query(&self, package_req: &PackageReq) -> anyhow::Result<Vec<Manifest>> {
+        if package_req.name() != &self.manifest.package.name {
+            bail!(
+                "{} only has {}",
+                self.dir.display(),
+                self.manifest.package.name
+            );
+        }
+
+        if package_req.matches(&self.manifest.package.name, &self.manifest.package.version) {
+            Ok(vec![self.manifest.clone()])
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    fn download_package(&self, _package_id: &PackageId) -> anyhow::Result<PackageContents> {
+        PackageContents::pack_from_path(&self.dir)
+    }
+
+    fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>> {
+        Ok(Vec::new())
+    }
+}
+
+/// A package that's been loaded, but whose own path and Git dependencies
+/// still have to be.
+struct Pending {
+    manifest: Manifest,
+    dir: PathBuf,
+
+    /// The package's path relative to the project, if it's the root package
+    /// or a path dependency. Packages from Git have none, since a path
+    /// dependency inside a checkout would point somewhere different on every
+    /// machine.
+    relative: Option<PathBuf>,
+
+    /// The source the package is added as, or `None` for the root package.
+    source: Option<PackageSourceId>,
+}
+
+/// Loads every package that `root`, or any package it depends on by path or
+/// from Git, depends on by path or from Git, and adds each one to
+/// `package_sources`. Returns `root` with those dependencies turned into
+/// dependencies on the exact versions that were loaded, ready to resolve.
+///
+/// Git dependencies are checked out under `checkouts`, at the commit locked in
+/// `lockfile` if there is one. When `offline`, nothing is fetched, so every
+/// Git dependency has to be locked to a commit that's already there.
+pub fn add_local_dependencies(
+    root: &Manifest,
+    project_path: &Path,
+    lockfile: &Lockfile,
+    checkouts: &Path,
+    offline: bool,
+    package_sources: &mut PackageSourceMap,
+) -> anyhow::Result<Manifest> {
+    let mut loaded: HashMap<PackageSourceId, PackageId> = HashMap::new();
+    let mut origins: BTreeMap<PackageName, PackageSourceId> = BTreeMap::new();
+    let mut rewritten_root = None;
+
+    let mut to_visit = vec![Pending {
+        manifest: root.clone(),
+        dir: project_path.to_owned(),
+        relative: Some(PathBuf::new()),
+        source: None,
+    }];
+
+    while let Some(pending) = to_visit.pop() {
+        let mut manifest = pending.manifest;
+        let package_id = manifest.package_id();
+        let is_root = pending.source.is_none();
+
+        // Only the root package's dev dependencies are ever installed.
+        let mut realms = vec![
+            &mut manifest.dependencies,
+            &mut manifest.server_dependencies,
+        ];
+        if is_root {
+            realms.push(&mut manifest.dev_dependencies);
+        }
+
+        for dependencies in realms {
+            for dependency in dependencies.values_mut() {
+                let (source, dir, relative) = match &*dependency {
+                    Dependency::Registry(_) => continue,
+                    Dependency::Path(path) => {
+                        let relative = match &pending.relative {
+                            Some(relative) => normalize(&relative.join(path)),
+                            None => bail!(
+                                "{} depends on {}, but packages from Git can't have path \
+                                 dependencies",
+                                package_id,
+                                dependency
+                            ),
+                        };
+
+                        (
+                            PackageSourceId::PathDependency(relative.clone()),
+                            pending.dir.join(path),
+                            Some(relative),
+                        )
+                    }
+                    Dependency::Git { url, rev } => {
+                        let locked = locked_commit(lockfile, url, rev.as_deref());
+                        let (commit, dir) =
+                            check_out(url, rev.as_deref(), locked, checkouts, offline)
+                                .with_context(|| {
+                                    format!("could not check out {} for {}", dependency, package_id)
+                                })?;
+
+                        let source = PackageSourceId::GitDependency {
+                            url: url.clone(),
+                            rev: rev.clone(),
+                            commit,
+                        };
+
+                        (source, dir, None)
+                    }
+                };
+
+                let target = match loaded.get(&source) {
+                    Some(target) => target.clone(),
+                    None => {
+                        let target_manifest = Manifest::load(&dir).with_context(|| {
+                            format!(
+                                "could not load {}, a dependency of {}",
+                                dependency, package_id
+                            )
+                        })?;
+                        let target = target_manifest.package_id();
+
+                        if let Some(other) = origins.insert(target.name().clone(), source.clone()) {
+                            bail!(
+                                "{} is depended on from both {} and {}. \
+                                 A package can only come from one place.",
+                                target.name(),
+                                describe(&other),
+                                describe(&source)
+                            );
+                        }
+
+                        loaded.insert(source.clone(), target.clone());
+                        to_visit.push(Pending {
+                            manifest: target_manifest,
+                            dir,
+                            relative,
+                            source: Some(source),
+                        });
+
+                        target
+                    }
+                };
+
+                *dependency = Dependency::Registry(PackageReq::new(
+                    target.name().clone(),
+                    VersionReq::exact(target.version()),
+                ));
+            }
+        }
+
+        match pending.source {
+            Some(source) => package_sources.add_local(
+                source,
+                LocalPackage {
+                    manifest,
+                    dir: pending.dir,
+                },
+            ),
+            None => rewritten_root = Some(manifest),
+        }
+    }
+
+    // The root package is always the first one visited.
+    Ok(rewritten_root.unwrap())
+}
+
+/// The commit `lockfile` pinned a Git dependency to, if it was locked with the
+/// same repository and revision.
+fn locked_commit<'a>(lockfile: &'a Lockfile, url: &str, rev: Option<&str>) -> Option<&'a str> {
+    lockfile
+        .packages
+        .iter()
+        .find_map(|lock_package| match lock_package {
+            LockPackage::Git(lock_package)
+                if lock_package.git == url && lock_package.rev.as_deref() == rev =>
+            {
+                Some(lock_package.commit.as_str())
+            }
+            _ => None,
+        })
+}
+
+/// Checks out a Git dependency under `checkouts`, returning the commit it was
+/// checked out at and where. Every repository is cloned once, and every commit
+/// used from it is checked out into a directory of its own. When `offline`,
+/// only the `locked` commit can be used, and only if it was fetched before.
+fn check_out(
+    url: &str,
+    rev: Option<&str>,
+    locked: Option<&str>,
+    checkouts: &Path,
+    offline: bool,
+) -> anyhow::Result<(String, PathBuf)> {
+    let parsed = Url::parse(url).with_context(|| format!("invalid Git URL {}", url))?;
+    let hash = blake3::hash(url.as_bytes()).to_hex();
+    let root = checkouts.join(&hash.as_str()[..16]);
+    let locked = locked.map(Oid::from_str).transpose()?;
+
+    let (repository, commit) = if offline {
+        let not_checked_out = || {
+            format_err!(
+                "Git dependency {} isn't checked out; run without --offline",
+                url
+            )
+        };
+
+        let commit = locked.ok_or_else(not_checked_out)?;
+        let dir = root.join(commit.to_string());
+
+        if dir.exists() {
+            return Ok((commit.to_string(), dir));
+        }
+
+        let repository =
+            Repository::open(root.join("repository")).map_err(|_| not_checked_out())?;
+
+        if repository.find_commit(commit).is_err() {
+            return Err(not_checked_out());
+        }
+
+        (repository, commit)
+    } else {
+        let repository = git_util::open_or_clone(None, &parsed, &root.join("repository"))?;
+
+        // A locked commit that was fetched before doesn't need fetching again.
+        let commit = match locked {
+            Some(commit) if repository.find_commit(commit).is_ok() => commit,
+            _ => {
+                git_util::fetch_all(None, &repository)?;
+
+                match locked {
+                    Some(commit) => commit,
+                    None => find_rev(&repository, rev)?,
+                }
+            }
+        };
+
+        (repository, commit)
+    };
+
+    let dir = root.join(commit.to_string());
+
+    if !dir.exists() {
+        // Checked out somewhere else first, so that a checkout that fails
+        // halfway isn't mistaken for a finished one.
+        let staging = tempfile::tempdir_in(&root)?;
+
+        let mut options = CheckoutBuilder::new();
+        options
+            .target_dir(staging.path())
+            .force()
+            .update_index(false);
+
+        let tree = repository.find_commit(commit)?.into_object();
+        repository
+            .checkout_tree(&tree, Some(&mut options))
+            .with_context(|| format!("could not check out commit {}", commit))?;
+
+        fs_err::rename(staging.into_path(), &dir)?;
+    }
+
+    Ok((commit.to_string(), dir))
+}
+
+/// The commit a branch, tag, or commit hash points to, or the commit the
+/// repository's default branch points to if no revision is given.
+fn find_rev(repository: &Repository, rev: Option<&str>) -> anyhow::Result<Oid> {
+    // Branches are looked up among the remote's first, since the local ones
+    // only move when they're checked out.
+    let candidates = match rev {
+        Some(rev) => vec![format!("refs/remotes/origin/{}", rev), rev.to_owned()],
+        None => vec!["refs/remotes/origin/HEAD".to_owned(), "HEAD".to_owned()],
+    };
+
+    for candidate in &candidates {
+        if let Ok(object) = repository.revparse_single(candidate) {
+            return Ok(object.peel_to_commit()?.id());
+        }
+    }
+
+    bail!(
+        "could not find {} in the repository",
+        rev.unwrap_or("the default branch")
+    )
+}
+
+fn describe(source: &PackageSourceId) -> String {
+    match source {
+        PackageSourceId::PathDependency(path) => format!("path {}", path.display()),
+        PackageSourceId::GitDependency { url, commit, .. } => format!("{}#{}", url, commit),
+        other => format!("{:?}", other),
+    }
+}
+
+/// Removes `.` and `..` from a path where it can, without touching the file
+/// system, so that the same directory reached two ways is the same package.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::package_source::InMemoryRegistry;
+    use crate::resolution::resolve;
+    use crate::test_package::PackageBuilder;
+
+    fn write_manifest(dir: &Path, contents: &str) {
+        fs_err::create_dir_all(dir).unwrap();
+        fs_err::write(dir.join("wally.toml"), contents).unwrap();
+    }
+
+    #[test]
+    fn path_dependencies() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/minimal@1.0.0"));
+
+        let project = tempfile::tempdir().unwrap();
+        let root_dir = project.path().join("game");
+        write_manifest(
+            &root_dir,
+            r#"
+            [package]
+            name = "biff/game"
+            version = "0.1.0"
+            registry = "test"
+            realm = "shared"
+
+            [dependencies]
+            Lib = { path = "../lib" }
+            "#,
+        );
+        write_manifest(
+            &project.path().join("lib"),
+            r#"
+            [package]
+            name = "biff/lib"
+            version = "0.3.0"
+            registry = "test"
+            realm = "shared"
+
+            [dependencies]
+            Minimal = "biff/minimal@1.0.0"
+            Util = { path = "./../util" }
+            "#,
+        );
+        write_manifest(
+            &project.path().join("util"),
+            r#"
+            [package]
+            name = "biff/util"
+            version = "0.2.0"
+            registry = "test"
+            realm = "shared"
+            "#,
+        );
+
+        let root = Manifest::load(&root_dir).unwrap();
+        let mut package_sources = PackageSourceMap::new(Box::new(registry.source()));
+
+        let root = add_local_dependencies(
+            &root,
+            &root_dir,
+            &Lockfile::from_manifest(&root),
+            &project.path().join("checkouts"),
+            false,
+            &mut package_sources,
+        )
+        .unwrap();
+
+        assert_eq!(
+            root.dependencies["Lib"],
+            Dependency::Registry(PackageReq::new(
+                "biff/lib".parse().unwrap(),
+                VersionReq::exact(&"0.3.0".parse().unwrap())
+            ))
+        );
+
+        let resolve = resolve(&root, &Default::default(), &package_sources).unwrap();
+        let activated: Vec<_> = resolve.activated.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            activated,
+            vec![
+                "biff/game@0.1.0",
+                "biff/lib@0.3.0",
+                "biff/minimal@1.0.0",
+                "biff/util@0.2.0"
+            ]
+        );
+
+        let util = "biff/util@0.2.0".parse().unwrap();
+        assert_eq!(
+            resolve.metadata[&util].source_registry,
+            PackageSourceId::PathDependency(PathBuf::from("../util"))
+        );
+    }
+
+    #[test]
+    fn offline_git_dependency_must_be_checked_out() {
+        let registry = InMemoryRegistry::new();
+        let project = tempfile::tempdir().unwrap();
+        write_manifest(
+            project.path(),
+            r#"
+            [package]
+            name = "biff/game"
+            version = "0.1.0"
+            registry = "test"
+            realm = "shared"
+
+            [dependencies]
+            Lib = { git = "https://example.com/biff/lib.git" }
+            "#,
+        );
+
+        let root = Manifest::load(project.path()).unwrap();
+        let mut package_sources = PackageSourceMap::new(Box::new(registry.source()));
+
+        let err = add_local_dependencies(
+            &root,
+            project.path(),
+            &Lockfile::from_manifest(&root),
+            &project.path().join("checkouts"),
+            true,
+            &mut package_sources,
+        )
+        .unwrap_err();
+
+        let message = format!("{:#}", err);
+        assert!(
+            message.contains(
+                "Git dependency https://example.com/biff/lib.git isn't checked out; run without \
+                 --offline"
+            ),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn normalizes_paths() {
+        assert_eq!(normalize(Path::new("a/./b/../c")), PathBuf::from("a/c"));
+        assert_eq!(normalize(Path::new("../x/../y")), PathBuf::from("../y"));
+        assert_eq!(normalize(Path::new("../../z")), PathBuf::from("../../z"));
+    }
+}
@@ -0,0 +1,196 @@
+//! Defines a package source that only has the packages locked in a lockfile,
+//! served from the local package cache. Resolving against it reproduces the
+//! lockfile without ever reaching a registry.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::{bail, format_err};
+
+use crate::lockfile::{LockPackage, Lockfile};
+use crate::manifest::Manifest;
+use crate::package_cache::PackageCache;
+use crate::package_id::PackageId;
+use crate::package_integrity::archive_hash;
+use crate::package_req::PackageReq;
+
+use super::{PackageContents, PackageSourceId, PackageSourceProvider};
+
+#[derive(Clone)]
+pub struct OfflineRegistry {
+    packages: Arc<BTreeMap<PackageId, (Manifest, PackageContents)>>,
+}
+
+impl OfflineRegistry {
+    /// Loads every package locked in `lockfile` from a registry, except the
+    /// root package, from `cache`. Fails if any of them aren't cached, or
+    /// don't match the checksum they were locked with. Path and Git
+    /// dependencies aren't cached, and are loaded from where they are instead.
+    pub fn from_lockfile(
+        lockfile: &Lockfile,
+        root_package_id: &PackageId,
+        cache: Option<&PackageCache>,
+    ) -> anyhow::Result<Self> {
+        let mut packages = BTreeMap::new();
+        let mut missing = Vec::new();
+
+        for lock_package in &lockfile.packages {
+            let lock_package = match lock_package {
+                LockPackage::Registry(lock_package) => lock_package,
+                LockPackage::Git(_) | LockPackage::Path(_) => continue,
+            };
+
+            let package_id =
+                PackageId::new(lock_package.name.clone(), lock_package.version.clone());
+
+            if &package_id == root_package_id {
+                continue;
+            }
+
+            // Tests install from their own registries without a cache, so
+            // nothing is cached for them.
+            let cached = match cache {
+                Some(cache) => cache.get(&package_id)?,
+                None => None,
+            };
+
+            let contents = match cached {
+                Some(contents) => contents,
+                None => {
+                    missing.push(package_id);
+                    continue;
+                }
+            };
+
+            if let Some(checksum) = &lock_package.checksum {
+                if &archive_hash(&contents) != checksum {
+                    bail!(
+                        "The cached copy of {} doesn't match the checksum in the lockfile",
+                        package_id
+                    );
+                }
+            }
+
+            let manifest = contents
+                .manifest()
+                .map_err(|err| format_err!("invalid cached package {}: {:#}", package_id, err))?;
+
+            packages.insert(package_id, (manifest, contents));
+        }
+
+        if !missing.is_empty() {
+            let missing: Vec<_> = missing.iter().map(ToString::to_string).collect();
+
+            bail!(
+                "These locked packages aren't in the package cache, so they can't be \
+                 installed offline: {}\nRun `wally install` with network access to cache them.",
+                missing.join(", ")
+            );
+        }
+
+        Ok(Self {
+            packages: Arc::new(packages),
+        })
+    }
+}
+
+impl PackageSourceProvider for OfflineRegistry {
+    fn update(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn query(&self, package_req: &PackageReq) -> anyhow::Result<Vec<Manifest>> {
+        let versions = self
+            .packages
+            .values()
+            .map(|(manifest, _)| manifest)
+            .filter(|manifest| {
+                package_req.matches(&manifest.package.name, &manifest.package.version)
+            })
+            .cloned()
+            .collect();
+
+        Ok(versions)
+    }
+
+    fn download_package(&self, package_id: &PackageId) -> anyhow::Result<PackageContents> {
+        self.packages
+            .get(package_id)
+            .map(|(_, contents)| contents.clone())
+            .ok_or_else(|| format_err!("Package {} isn't locked, so it isn't cached", package_id))
+    }
+
+    /// Everything this source could fall back to was already resolved into
+    /// the lockfile.
+    fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>> {
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    use crate::package_source::{PackageSource, PackageSourceMap};
+    use crate::resolution::resolve;
+    use crate::test_package::PackageBuilder;
+
+    fn lockfile(root: &Manifest, lock_packages: &[&str]) -> Lockfile {
+        let mut lockfile = Lockfile::from_manifest(root);
+
+        for id in lock_packages {
+            let package_id: PackageId = id.parse().unwrap();
+            let (name, version) = package_id.into_parts();
+
+            lockfile.packages.push(LockPackage::Registry(
+                crate::lockfile::RegistryLockPackage {
+                    name,
+                    version,
+                    checksum: None,
+                    features: Vec::new(),
+                    dependencies: Vec::new(),
+                },
+            ));
+        }
+
+        lockfile
+    }
+
+    #[test]
+    fn resolves_locked_packages_from_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = PackageCache::at(dir.path());
+
+        let (minimal, contents) = PackageBuilder::new("biff/minimal@0.1.0").package();
+        cache.insert(&minimal.package_id(), &contents).unwrap();
+
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("Minimal", "biff/minimal@0.1.0")
+            .into_manifest();
+        let lockfile = lockfile(&root, &["biff/root@0.1.0", "biff/minimal@0.1.0"]);
+
+        let offline =
+            OfflineRegistry::from_lockfile(&lockfile, &root.package_id(), Some(&cache)).unwrap();
+        let package_sources = PackageSourceMap::new(Box::new(PackageSource::Offline(offline)));
+
+        let try_to_use: BTreeSet<_> = lockfile.as_ids().collect();
+        let resolved = resolve(&root, &try_to_use, &package_sources).unwrap();
+        assert_eq!(resolved.activated, try_to_use);
+    }
+
+    #[test]
+    fn missing_packages_fail() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = PackageCache::at(dir.path());
+
+        let root = PackageBuilder::new("biff/root@0.1.0").into_manifest();
+        let lockfile = lockfile(&root, &["biff/root@0.1.0", "biff/minimal@0.1.0"]);
+
+        let err = OfflineRegistry::from_lockfile(&lockfile, &root.package_id(), Some(&cache))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("biff/minimal@0.1.0"), "{}", err);
+    }
+}
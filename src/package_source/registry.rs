@@ -1,28 +1,42 @@
-use std::io::Read;
+use std::io;
 use std::sync::Arc;
 
-use anyhow::bail;
+use anyhow::{bail, format_err, Context};
 use once_cell::sync::OnceCell;
-use reqwest::{blocking::Client, header::AUTHORIZATION};
+use reqwest::{
+    blocking::{Client, Response},
+    header::{AUTHORIZATION, RANGE},
+    StatusCode,
+};
 use url::Url;
 
-use crate::auth::AuthStore;
+use crate::api_info::{advertised_read_auth, ApiInfoAuthMode};
+use crate::auth::{token_env_var, AuthStore};
+use crate::http_client;
 use crate::manifest::Manifest;
+use crate::package_cache::PartialDownload;
 use crate::package_id::PackageId;
 use crate::package_index::PackageIndex;
+use crate::package_integrity::PackageIntegrity;
 use crate::package_req::PackageReq;
 use crate::package_source::PackageContents;
 
 use super::{PackageSourceId, PackageSourceProvider};
 
-const VERSION: &str = env!("CARGO_PKG_VERSION");
-
 #[derive(Clone)]
 pub struct Registry {
     index_url: Url,
     auth_token: OnceCell<Option<Arc<str>>>,
     index: OnceCell<Arc<PackageIndex>>,
     client: Client,
+
+    /// Registries serving the same packages, tried in order when this one
+    /// can't be reached.
+    mirrors: Vec<Registry>,
+
+    /// Whether mirrors are also tried when this registry doesn't have a
+    /// package, instead of only when it can't be reached.
+    search_mirrors: bool,
 }
 
 impl Registry {
@@ -35,10 +49,32 @@ impl Registry {
             index_url,
             auth_token: OnceCell::new(),
             index: OnceCell::new(),
-            client: Client::new(),
+            client: http_client::blocking_client()?,
+            mirrors: Vec::new(),
+            search_mirrors: false,
         })
     }
 
+    /// Adds mirrors to download packages from, in order, when this registry
+    /// can't be reached or fails with a server error. Packages are still
+    /// identified by this registry, so lockfiles don't change depending on
+    /// which mirror was used.
+    pub fn with_mirrors<S: AsRef<str>>(mut self, specs: &[S]) -> anyhow::Result<Self> {
+        for spec in specs {
+            let mirror = Self::from_registry_spec(spec.as_ref())
+                .with_context(|| format!("invalid registry mirror {}", spec.as_ref()))?;
+            self.mirrors.push(mirror);
+        }
+
+        Ok(self)
+    }
+
+    /// Also try mirrors when this registry says a package doesn't exist.
+    pub fn search_mirrors(mut self, search_mirrors: bool) -> Self {
+        self.search_mirrors = search_mirrors;
+        self
+    }
+
     fn auth_token(&self) -> anyhow::Result<Option<Arc<str>>> {
         self.auth_token
             .get_or_try_init(|| match AuthStore::get_token(self.api_url()?.as_str())? {
@@ -57,6 +93,163 @@ impl Registry {
         let config = self.index()?.config()?;
         Ok(config.api)
     }
+
+    /// Sends a GET request to `path` on this registry's API. `range_start`
+    /// asks for the response to start that many bytes in, to resume a
+    /// download.
+    fn send(&self, path: &str, range_start: Option<u64>) -> anyhow::Result<(Url, Response)> {
+        let url = self.api_url()?.join(path)?;
+
+        let mut request = self.client.get(url.clone());
+
+        if let Some(token) = self.auth_token()? {
+            request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+
+        if let Some(start) = range_start {
+            request = request.header(RANGE, format!("bytes={}-", start));
+        }
+
+        let response = request
+            .send()
+            .map_err(|err| http_client::request_error(err, &url))?;
+
+        Ok((url, response))
+    }
+
+    /// Sends a GET request to `path` on this registry, and reads the whole
+    /// response, moving on to each of its mirrors in turn if it can't be
+    /// reached. Reading the response here means a registry that stalls
+    /// partway through one is failed over like one that can't be reached.
+    fn get_with_mirrors(&self, path: &str) -> anyhow::Result<(&Registry, RegistryResponse)> {
+        let (registry, status, body) = self.fetch_with_mirrors(path, None, |url, response| {
+            let body = response
+                .bytes()
+                .map_err(|err| http_client::request_error(err, url))?;
+            Ok(body.to_vec())
+        })?;
+
+        Ok((registry, RegistryResponse { status, body }))
+    }
+
+    /// Sends a GET request to `path` on this registry, and hands the response
+    /// to `read`, moving on to each of its mirrors in turn if it can't be
+    /// reached or `read` fails. The last registry tried is returned with what
+    /// was read of its response, whatever the response's status.
+    fn fetch_with_mirrors<T>(
+        &self,
+        path: &str,
+        range_start: Option<u64>,
+        mut read: impl FnMut(&Url, Response) -> anyhow::Result<T>,
+    ) -> anyhow::Result<(&Registry, StatusCode, T)> {
+        let mut registries = std::iter::once(self).chain(&self.mirrors).peekable();
+
+        while let Some(registry) = registries.next() {
+            let is_last = registries.peek().is_none();
+
+            let (url, response) = match registry.send(path, range_start) {
+                Ok(sent) => sent,
+                Err(err) if !is_last => {
+                    log::warn!(
+                        "Could not reach registry {}, trying the next mirror: {:#}",
+                        registry.index_url,
+                        err
+                    );
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+
+            let status = response.status();
+
+            if !is_last && self.should_fall_back(status) {
+                log::warn!(
+                    "Registry {} responded with {}, trying the next mirror",
+                    registry.index_url,
+                    status
+                );
+                continue;
+            }
+
+            match read(&url, response) {
+                Ok(value) => return Ok((registry, status, value)),
+                Err(err) if !is_last => {
+                    log::warn!(
+                        "Response from registry {} was interrupted, trying the next mirror: {:#}",
+                        registry.index_url,
+                        err
+                    );
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        unreachable!("a registry is always tried")
+    }
+
+    /// Explains the registry refusing to let us read a package, and how to
+    /// get credentials it takes, going by how it says readers authenticate.
+    fn not_authorized(&self, package_id: &PackageId, status: StatusCode) -> anyhow::Error {
+        let api = match self.api_url() {
+            Ok(api) => api,
+            Err(err) => return err,
+        };
+
+        let scope = package_id.name().scope();
+        let login = format!("wally login {}", self.index_url);
+        let env_var = token_env_var(api.as_str());
+
+        let credentials = match self.auth_token() {
+            Ok(Some(_)) => format!("The credentials stored for {} were refused.", api),
+            _ => format!("No credentials are stored for {}.", api),
+        };
+
+        let help = match advertised_read_auth(&api) {
+            Some(ApiInfoAuthMode::GithubOauth { .. })
+            | Some(ApiInfoAuthMode::GithubOauthPrivate { .. }) => format!(
+                "This registry authenticates readers with GitHub, so run `{}` as someone who \
+                 can read {}.",
+                login, scope
+            ),
+            Some(ApiInfoAuthMode::ApiKey {}) | Some(ApiInfoAuthMode::DoubleApiKey {}) => format!(
+                "This registry takes an API key to read: store one with `{} --token <key>`, or \
+                 set {}.",
+                login, env_var
+            ),
+            Some(ApiInfoAuthMode::Gitlab {}) => format!(
+                "This registry authenticates readers with GitLab: store a GitLab access token \
+                 with `{} --token <token>`, or set {}.",
+                login, env_var
+            ),
+            _ => format!("Run `{}`, or set {} to a token.", login, env_var),
+        };
+
+        format_err!(
+            "Not authorized to read {} from {} ({}). {} {}",
+            scope,
+            api,
+            status,
+            credentials,
+            help
+        )
+    }
+
+    /// A 404 means the package really isn't there, which mirrors of the same
+    /// registry won't change, unless they were asked to be searched anyway.
+    fn should_fall_back(&self, status: StatusCode) -> bool {
+        status.is_server_error() || (status == StatusCode::NOT_FOUND && self.search_mirrors)
+    }
+}
+
+struct RegistryResponse {
+    status: StatusCode,
+    body: Vec<u8>,
+}
+
+impl RegistryResponse {
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
 }
 
 impl PackageSourceProvider for Registry {
@@ -78,37 +271,142 @@ impl PackageSourceProvider for Registry {
         Ok(versions)
     }
 
+    fn checksum(&self, package_id: &PackageId) -> anyhow::Result<Option<String>> {
+        let metadata = self.index()?.get_package_metadata(package_id.name())?;
+        Ok(metadata.checksum(package_id.version()).map(str::to_owned))
+    }
+
+    fn is_yanked(&self, package_id: &PackageId) -> anyhow::Result<bool> {
+        let metadata = self.index()?.get_package_metadata(package_id.name())?;
+        Ok(metadata.is_yanked(package_id.version()))
+    }
+
+    fn deprecation(&self, package_id: &PackageId) -> anyhow::Result<Option<String>> {
+        let metadata = self.index()?.get_package_metadata(package_id.name())?;
+        Ok(metadata
+            .deprecation(package_id.version())
+            .map(str::to_owned))
+    }
+
     fn download_package(&self, package_id: &PackageId) -> anyhow::Result<PackageContents> {
+        let path = contents_path(package_id);
+
+        // Registries may redirect to a pre-signed storage URL, which the client
+        // follows without sending the auth token along. The archive is checked
+        // against its checksum once it's downloaded either way.
+        let (registry, response) = self
+            .get_with_mirrors(&path)
+            .with_context(|| format!("Failed to download package {}", package_id))?;
+
+        if is_unauthorized(response.status) {
+            return Err(registry.not_authorized(package_id, response.status));
+        }
+
+        if !response.status.is_success() {
+            bail!(
+                "Failed to download package {} from registry: {}\n{} {}",
+                package_id,
+                registry.api_url()?,
+                response.status,
+                response.text()
+            );
+        }
+
+        Ok(PackageContents::from_buffer(response.body))
+    }
+
+    /// Asks for only the bytes that aren't in `download` yet. Registries that
+    /// ignore the range send the whole archive, which starts the download
+    /// over.
+    fn download_package_into(
+        &self,
+        package_id: &PackageId,
+        download: &mut PartialDownload,
+    ) -> anyhow::Result<()> {
+        let path = contents_path(package_id);
+        let range_start = Some(download.len()).filter(|&len| len > 0);
+
+        // Failures writing the download are kept from `fetch_with_mirrors`,
+        // which would ask the next mirror for a range that's out of date.
+        // Retrying resumes from whatever was written instead.
+        let (registry, status, written) = self
+            .fetch_with_mirrors(&path, range_start, |url, mut response| {
+                let status = response.status();
+
+                Ok(match status {
+                    StatusCode::PARTIAL_CONTENT => copy_into(url, &mut response, download),
+                    _ if status.is_success() => download
+                        .restart()
+                        .map_err(Into::into)
+                        .and_then(|()| copy_into(url, &mut response, download)),
+                    _ => Err(format_err!(
+                        "{} {}",
+                        status,
+                        response.text().unwrap_or_default()
+                    )),
+                })
+            })
+            .with_context(|| format!("Failed to download package {}", package_id))?;
+
+        // What was downloaded before is longer than the archive, so it can't
+        // be part of it.
+        if status == StatusCode::RANGE_NOT_SATISFIABLE && range_start.is_some() {
+            download.restart()?;
+            return self.download_package_into(package_id, download);
+        }
+
+        if is_unauthorized(status) {
+            return Err(registry.not_authorized(package_id, status));
+        }
+
+        let api_url = registry.api_url()?;
+        written.with_context(|| {
+            format!(
+                "Failed to download package {} from registry: {}",
+                package_id, api_url
+            )
+        })
+    }
+
+    fn download_integrity(
+        &self,
+        package_id: &PackageId,
+    ) -> anyhow::Result<Option<PackageIntegrity>> {
         let path = format!(
-            "/v1/package-contents/{}/{}/{}",
+            "/v1/package-integrity/{}/{}/{}",
             package_id.name().scope(),
             package_id.name().name(),
             package_id.version()
         );
 
-        let url = self.api_url()?.join(&path)?;
+        let (registry, response) = self
+            .get_with_mirrors(&path)
+            .with_context(|| format!("Failed to download integrity document for {}", package_id))?;
 
-        let mut request = self.client.get(url).header("Wally-Version", VERSION);
+        // Registries that predate integrity documents, and packages published
+        // before they were introduced, simply don't have one.
+        if response.status == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
 
-        if let Some(token) = self.auth_token()? {
-            request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+        if is_unauthorized(response.status) {
+            return Err(registry.not_authorized(package_id, response.status));
         }
-        let mut response = request.send()?;
 
-        if !response.status().is_success() {
+        if !response.status.is_success() {
             bail!(
-                "Failed to download package {} from registry: {}\n{} {}",
+                "Failed to download integrity document for {} from registry: {}\n{} {}",
                 package_id,
-                self.api_url()?,
-                response.status(),
-                response.text()?
+                registry.api_url()?,
+                response.status,
+                response.text()
             );
         }
 
-        let mut data = Vec::new();
-        response.read_to_end(&mut data)?;
+        let integrity = serde_json::from_slice(&response.body)
+            .with_context(|| format!("could not parse integrity document for {}", package_id))?;
 
-        Ok(PackageContents::from_buffer(data))
+        Ok(Some(integrity))
     }
 
     fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>> {
@@ -122,3 +420,57 @@ impl PackageSourceProvider for Registry {
         Ok(sources)
     }
 }
+
+fn is_unauthorized(status: StatusCode) -> bool {
+    status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN
+}
+
+fn copy_into(
+    url: &Url,
+    response: &mut Response,
+    download: &mut PartialDownload,
+) -> anyhow::Result<()> {
+    io::copy(response, download)
+        .with_context(|| format!("Download from {} was interrupted", url))?;
+    Ok(())
+}
+
+fn contents_path(package_id: &PackageId) -> String {
+    format!(
+        "/v1/package-contents/{}/{}/{}",
+        package_id.name().scope(),
+        package_id.name().name(),
+        package_id.version()
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn registry(search_mirrors: bool) -> Registry {
+        Registry::from_registry_spec("https://github.com/UpliftGames/wally-index")
+            .unwrap()
+            .search_mirrors(search_mirrors)
+    }
+
+    #[test]
+    fn falls_back_on_server_errors() {
+        let registry = registry(false);
+        assert!(registry.should_fall_back(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(registry.should_fall_back(StatusCode::BAD_GATEWAY));
+        assert!(!registry.should_fall_back(StatusCode::OK));
+        assert!(!registry.should_fall_back(StatusCode::UNAUTHORIZED));
+    }
+
+    #[test]
+    fn falls_back_on_missing_packages_only_when_searching() {
+        assert!(!registry(false).should_fall_back(StatusCode::NOT_FOUND));
+        assert!(registry(true).should_fall_back(StatusCode::NOT_FOUND));
+    }
+
+    #[test]
+    fn mirrors_must_be_urls() {
+        assert!(registry(false).with_mirrors(&["not a url"]).is_err());
+    }
+}
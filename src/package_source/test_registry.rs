@@ -21,6 +21,12 @@ impl TestRegistry {
     pub fn new<P: Into<PathBuf>>(path: P) -> Self {
         Self { path: path.into() }
     }
+
+    fn config(&self) -> anyhow::Result<PackageIndexConfig> {
+        let config_path = self.path.join("index/config.json");
+        let contents = fs_err::read_to_string(config_path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
 }
 
 impl PackageSourceProvider for TestRegistry {
@@ -29,12 +35,13 @@ impl PackageSourceProvider for TestRegistry {
     }
 
     fn query(&self, package_req: &PackageReq) -> anyhow::Result<Vec<Manifest>> {
-        // Each package has all of its versions stored in a folder based on its
-        // scope and name.
-        let mut package_path = self.path.clone();
-        package_path.push("index");
-        package_path.push(package_req.name().scope());
-        package_path.push(package_req.name().name());
+        // Each package has all of its versions stored in a file based on its
+        // scope and name, laid out the way the index's config says.
+        let layout = self.config()?.layout;
+        let package_path = self
+            .path
+            .join("index")
+            .join(layout.package_file(package_req.name()));
 
         // Construct a buffered file reader, with a nice error message in the
         // event of failure. We might want to return a structured error from
@@ -82,11 +89,8 @@ impl PackageSourceProvider for TestRegistry {
     }
 
     fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>> {
-        let config_path = self.path.join("index/config.json");
-        let contents = fs_err::read_to_string(config_path)?;
-        let config: PackageIndexConfig = serde_json::from_str(&contents)?;
-
-        let sources = config
+        let sources = self
+            .config()?
             .fallback_registries
             .iter()
             .map(|source| self.path.join(source).canonicalize().unwrap())
@@ -0,0 +1,47 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::Context;
+use semver::Version;
+use serde::Deserialize;
+
+use crate::package_name::PackageName;
+
+/// Versions of packages that resolution should prefer when more than one
+/// version would satisfy a dependency. Preferences are advisory: a preferred
+/// version that doesn't satisfy a dependency is ignored.
+///
+/// These are loaded from a TOML file kept outside of any one project, which
+/// lets a team steer many projects towards the same versions:
+///
+/// ```toml
+/// [versions]
+/// "roblox/roact" = "1.4.0"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VersionPreferences {
+    #[serde(default)]
+    pub versions: BTreeMap<PackageName, Version>,
+}
+
+impl VersionPreferences {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs_err::read_to_string(path)?;
+
+        toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse version preferences at {}", path.display()))
+    }
+
+    /// Load preferences from `path` if one was given, or use no preferences.
+    pub fn load_optional(path: Option<&Path>) -> anyhow::Result<Self> {
+        match path {
+            Some(path) => Self::load(path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn get(&self, name: &PackageName) -> Option<&Version> {
+        self.versions.get(name)
+    }
+}
@@ -1,15 +1,18 @@
 use std::cmp::Ordering;
 use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
 
 use anyhow::bail;
 use anyhow::format_err;
-use semver::Version;
+use semver::{Version, VersionReq};
 use serde::Serialize;
 
 use crate::manifest::{Manifest, Realm};
 use crate::package_id::PackageId;
+use crate::package_name::PackageName;
 use crate::package_req::PackageReq;
 use crate::package_source::{PackageSourceId, PackageSourceMap, PackageSourceProvider};
+use crate::preferences::VersionPreferences;
 
 /// A completely resolved graph of packages returned by `resolve`.
 ///
@@ -33,6 +36,15 @@ pub struct Resolve {
 
     /// Graph of all dependencies originating from the "dev" dependency realm.
     pub dev_dependencies: BTreeMap<PackageId, BTreeMap<String, PackageId>>,
+
+    /// Overrides from the root manifest that were applied during resolution.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub overrides: BTreeMap<PackageName, Version>,
+
+    /// The features that packages depending on each package turned on, for
+    /// packages that had any turned on.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub features: BTreeMap<PackageId, BTreeSet<String>>,
 }
 
 impl Resolve {
@@ -57,6 +69,10 @@ pub struct ResolvePackageMetadata {
     pub realm: Realm,
     pub origin_realm: Realm,
     pub source_registry: PackageSourceId,
+
+    /// The hash of the package's archive, if its source recorded one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
 }
 
 pub fn resolve(
@@ -64,6 +80,60 @@ pub fn resolve(
     try_to_use: &BTreeSet<PackageId>,
     package_sources: &PackageSourceMap,
 ) -> anyhow::Result<Resolve> {
+    resolve_with_preferences(
+        root_manifest,
+        try_to_use,
+        package_sources,
+        &VersionPreferences::default(),
+    )
+}
+
+/// Like `resolve`, but when several versions of a package would work, the one
+/// named in `preferences` is picked.
+pub fn resolve_with_preferences(
+    root_manifest: &Manifest,
+    try_to_use: &BTreeSet<PackageId>,
+    package_sources: &PackageSourceMap,
+    preferences: &VersionPreferences,
+) -> anyhow::Result<Resolve> {
+    // Packages are picked greedily, so a package picked early on can turn out
+    // not to satisfy a requirement found later, even when another version
+    // would satisfy them all. When that happens, resolution starts over with
+    // that version hinted, and only fails if no version works.
+    let mut hints = BTreeSet::new();
+
+    loop {
+        match resolve_attempt(
+            root_manifest,
+            try_to_use,
+            package_sources,
+            preferences,
+            &hints,
+        )? {
+            Attempt::Resolved(resolve) => return Ok(resolve),
+            Attempt::Retry(hint) => {
+                log::debug!("Resolving again, trying {} first", hint);
+                hints.insert(hint);
+            }
+        }
+    }
+}
+
+enum Attempt {
+    Resolved(Resolve),
+
+    /// Picking this package ahead of any other version might avoid a
+    /// conflict.
+    Retry(PackageId),
+}
+
+fn resolve_attempt(
+    root_manifest: &Manifest,
+    try_to_use: &BTreeSet<PackageId>,
+    package_sources: &PackageSourceMap,
+    preferences: &VersionPreferences,
+    hints: &BTreeSet<PackageId>,
+) -> anyhow::Result<Attempt> {
     let mut resolve = Resolve::default();
 
     // Insert root project into graph and activated dependencies, as it'll
@@ -75,44 +145,55 @@ pub fn resolve(
             realm: root_manifest.package.realm,
             origin_realm: root_manifest.package.realm,
             source_registry: PackageSourceId::DefaultRegistry,
+            checksum: None,
         },
     );
 
     // Queue of all dependency requests that need to be resolved.
     let mut packages_to_visit = VecDeque::new();
 
-    for (alias, req) in &root_manifest.dependencies {
-        packages_to_visit.push_back(DependencyRequest {
-            request_source: root_manifest.package_id(),
-            request_realm: Realm::Shared,
-            origin_realm: Realm::Shared,
-            package_alias: alias.clone(),
-            package_req: req.clone(),
-        });
-    }
+    // The manifest of every activated package, so that features turned on
+    // after a package was activated can still find its optional dependencies.
+    let mut manifests = BTreeMap::new();
+    manifests.insert(root_manifest.package_id(), root_manifest.clone());
 
-    for (alias, req) in &root_manifest.server_dependencies {
-        packages_to_visit.push_back(DependencyRequest {
-            request_source: root_manifest.package_id(),
-            request_realm: Realm::Server,
-            origin_realm: Realm::Server,
-            package_alias: alias.clone(),
-            package_req: req.clone(),
-        });
-    }
+    // Every request for each package so far, and the version it got, to
+    // explain any conflict.
+    let mut requirements: BTreeMap<PackageName, Vec<ConflictingRequirement>> = BTreeMap::new();
 
-    for (alias, req) in &root_manifest.dev_dependencies {
-        packages_to_visit.push_back(DependencyRequest {
-            request_source: root_manifest.package_id(),
-            request_realm: Realm::Dev,
-            origin_realm: Realm::Dev,
-            package_alias: alias.clone(),
-            package_req: req.clone(),
-        });
-    }
+    // Nothing depends on the root package, so its optional dependencies are
+    // never turned on.
+    let optional = root_manifest.optional_dependencies();
+    queue_dependencies(&mut packages_to_visit, root_manifest, None, |alias| {
+        !optional.contains(alias)
+    })?;
 
     // Workhorse loop: resolve all dependencies, depth-first.
-    'outer: while let Some(dependency_request) = packages_to_visit.pop_front() {
+    'outer: while let Some(mut dependency_request) = packages_to_visit.pop_front() {
+        // Overrides in the root manifest replace whatever version was asked
+        // for. This is an escape hatch for patching transitive dependencies, so
+        // we only warn about constraints being violated.
+        let package_name = dependency_request.package_req.name().clone();
+
+        if let Some(version) = root_manifest.overrides.get(&package_name) {
+            let requested = &dependency_request.package_req;
+
+            if !requested.version_req().matches(version) {
+                log::warn!(
+                    "{} depends on {}, but it has been overridden to version {}",
+                    dependency_request.request_source,
+                    requested,
+                    version
+                );
+            }
+
+            dependency_request.package_req =
+                PackageReq::new(package_name.clone(), VersionReq::exact(version));
+            resolve
+                .overrides
+                .insert(package_name.clone(), version.clone());
+        }
+
         // Locate all already-activated packages that might match this
         // dependency request.
         let mut matching_activated: Vec<_> = resolve
@@ -153,6 +234,11 @@ pub fn resolve(
 
                 metadata.origin_realm = realm_match;
 
+                requirements
+                    .entry(package_id.name().clone())
+                    .or_default()
+                    .push(dependency_request.requirement(Some(package_id.version())));
+
                 resolve.activate(
                     dependency_request.request_source.clone(),
                     dependency_request.package_alias.clone(),
@@ -160,6 +246,15 @@ pub fn resolve(
                     package_id.clone(),
                 );
 
+                enable_features(
+                    &mut resolve,
+                    &manifests,
+                    &mut packages_to_visit,
+                    package_id,
+                    &dependency_request.features,
+                    realm_match,
+                )?;
+
                 continue 'outer;
             }
         }
@@ -185,25 +280,41 @@ pub fn resolve(
                 )
             })?;
 
+        let preferred = preferred_version(&dependency_request, &candidates, preferences);
+
         // Sort our candidate packages by descending version, so that we try the
         // highest versions first.
         //
         // Additionally, if there were any packages that were previously used by
         // our lockfile (in `try_to_use`), prioritize those first. This
-        // technique is the one used by Cargo.
+        // technique is the one used by Cargo. After those comes the version
+        // our preferences asked for, if any. Hints from an earlier attempt that
+        // ran into a conflict come before all of them.
         candidates.sort_by(|a, b| {
+            let hinted_a = hints.contains(&a.package_id());
+            let hinted_b = hints.contains(&b.package_id());
+
             let contains_a = try_to_use.contains(&a.package_id());
             let contains_b = try_to_use.contains(&b.package_id());
 
-            match (contains_a, contains_b) {
-                (true, false) => Ordering::Less,
-                (false, true) => Ordering::Greater,
-                _ => b.package.version.cmp(&a.package.version),
-            }
+            let prefers_a = preferred.as_ref() == Some(&a.package.version);
+            let prefers_b = preferred.as_ref() == Some(&b.package.version);
+
+            hinted_b
+                .cmp(&hinted_a)
+                .then(contains_b.cmp(&contains_a))
+                .then(prefers_b.cmp(&prefers_a))
+                .then_with(|| b.package.version.cmp(&a.package.version))
         });
 
+        let source = package_sources.get(source_registry).unwrap();
+
+        // Yanked versions are skipped unless we were already using them, so
+        // that existing lockfiles keep working.
         let filtered_candidates = candidates.iter().filter(|candidate| {
             Realm::is_dependency_valid(dependency_request.request_realm, candidate.package.realm)
+                && (try_to_use.contains(&candidate.package_id())
+                    || !source.is_yanked(&candidate.package_id()).unwrap_or(false))
         });
 
         let mut conflicting = Vec::new();
@@ -232,6 +343,11 @@ pub fn resolve(
                 candidate.package.version.clone(),
             );
 
+            requirements
+                .entry(candidate_id.name().clone())
+                .or_default()
+                .push(dependency_request.requirement(Some(candidate_id.version())));
+
             resolve.activate(
                 dependency_request.request_source.clone(),
                 dependency_request.package_alias.to_owned(),
@@ -245,28 +361,28 @@ pub fn resolve(
                     realm: candidate.package.realm,
                     origin_realm: dependency_request.origin_realm,
                     source_registry: source_registry.clone(),
+                    checksum: source.checksum(&candidate_id).unwrap_or(None),
                 },
             );
 
-            for (alias, req) in &candidate.dependencies {
-                packages_to_visit.push_back(DependencyRequest {
-                    request_source: candidate_id.clone(),
-                    request_realm: Realm::Shared,
-                    origin_realm: dependency_request.origin_realm,
-                    package_alias: alias.clone(),
-                    package_req: req.clone(),
-                })
-            }
-
-            for (alias, req) in &candidate.server_dependencies {
-                packages_to_visit.push_back(DependencyRequest {
-                    request_source: candidate_id.clone(),
-                    request_realm: Realm::Server,
-                    origin_realm: dependency_request.origin_realm,
-                    package_alias: alias.clone(),
-                    package_req: req.clone(),
-                })
-            }
+            let optional = candidate.optional_dependencies();
+            queue_dependencies(
+                &mut packages_to_visit,
+                candidate,
+                Some(dependency_request.origin_realm),
+                |alias| !optional.contains(alias),
+            )?;
+
+            manifests.insert(candidate_id.clone(), candidate.clone());
+
+            enable_features(
+                &mut resolve,
+                &manifests,
+                &mut packages_to_visit,
+                &candidate_id,
+                &dependency_request.features,
+                dependency_request.origin_realm,
+            )?;
 
             continue 'outer;
         }
@@ -278,24 +394,180 @@ pub fn resolve(
                 req_realm = dependency_request.request_realm,
                 req = dependency_request.package_req,
             );
-        } else {
-            let conflicting_debug: Vec<_> = conflicting
+        }
+
+        // Every package whose request got a version that the candidates
+        // conflicted with has a say in which version is picked, along with
+        // this one.
+        let mut conflict = ResolveConflict {
+            package: package_name.clone(),
+            requirements: requirements
+                .remove(&package_name)
+                .unwrap_or_default()
                 .into_iter()
-                .map(|id| format!("{:?}", id))
-                .collect();
+                .filter(|requirement| {
+                    conflicting.iter().any(|candidate| {
+                        matches!(&requirement.selected, Some(selected)
+                            if compatible(candidate.version(), selected))
+                    })
+                })
+                .collect(),
+        };
+        conflict
+            .requirements
+            .push(dependency_request.requirement(None));
 
-            bail!(
-                "All possible candidates for package {req} ({req_realm:?}) conflicted with other \
-                 packages that were already installed. These packages were previously selected: \
-                 {conflicting}",
-                req = dependency_request.package_req,
-                req_realm = dependency_request.request_realm,
-                conflicting = conflicting_debug.join(", "),
-            );
+        let satisfies_all = conflicting.into_iter().find(|candidate| {
+            conflict
+                .requirements
+                .iter()
+                .all(|requirement| requirement.version_req.matches(candidate.version()))
+        });
+
+        return match satisfies_all {
+            Some(hint) if !hints.contains(&hint) => Ok(Attempt::Retry(hint)),
+            _ => Err(conflict.into()),
+        };
+    }
+
+    Ok(Attempt::Resolved(resolve))
+}
+
+/// Queue up requests for the dependencies of `manifest` that `include` lets
+/// through. Dependencies of the root package, which has no `origin_realm`,
+/// originate from their own realm, and only the root's dev dependencies are
+/// installed.
+///
+/// Path and Git dependencies have to have been swapped for the packages they
+/// point to, with `add_local_dependencies`, before resolving.
+fn queue_dependencies(
+    packages_to_visit: &mut VecDeque<DependencyRequest>,
+    manifest: &Manifest,
+    origin_realm: Option<Realm>,
+    include: impl Fn(&str) -> bool,
+) -> anyhow::Result<()> {
+    let mut sections = vec![
+        (Realm::Shared, &manifest.dependencies),
+        (Realm::Server, &manifest.server_dependencies),
+    ];
+
+    if origin_realm.is_none() {
+        sections.push((Realm::Dev, &manifest.dev_dependencies));
+    }
+
+    for (realm, dependencies) in sections {
+        for (alias, dependency) in dependencies {
+            if !include(alias) {
+                continue;
+            }
+
+            let req = match dependency.registry() {
+                Some(req) => req,
+                None => bail!(
+                    "{} depends on {} from {}, but only packages installed from a path or Git \
+                     can have path and Git dependencies",
+                    manifest.package_id(),
+                    alias,
+                    dependency
+                ),
+            };
+
+            packages_to_visit.push_back(DependencyRequest {
+                request_source: manifest.package_id(),
+                request_realm: realm,
+                origin_realm: origin_realm.unwrap_or(realm),
+                package_alias: alias.clone(),
+                package_req: req.clone(),
+                features: manifest
+                    .dependency_features
+                    .get(alias)
+                    .map(|features| features.iter().cloned().collect())
+                    .unwrap_or_default(),
+            });
         }
     }
 
-    Ok(resolve)
+    Ok(())
+}
+
+/// Turn on `features` of an activated package, queueing up the optional
+/// dependencies of any that weren't already on. Features are unified, so a
+/// package has every feature that anything depending on it asked for.
+fn enable_features(
+    resolve: &mut Resolve,
+    manifests: &BTreeMap<PackageId, Manifest>,
+    packages_to_visit: &mut VecDeque<DependencyRequest>,
+    package_id: &PackageId,
+    features: &BTreeSet<String>,
+    origin_realm: Realm,
+) -> anyhow::Result<()> {
+    if features.is_empty() {
+        return Ok(());
+    }
+
+    let manifest = manifests
+        .get(package_id)
+        .expect("activated package was missing its manifest");
+
+    let enabled = resolve.features.entry(package_id.clone()).or_default();
+    let newly_enabled: Vec<_> = manifest
+        .expand_features(features)?
+        .into_iter()
+        .filter(|feature| !enabled.contains(feature))
+        .collect();
+
+    if newly_enabled.is_empty() {
+        return Ok(());
+    }
+
+    let aliases: BTreeSet<&str> = newly_enabled
+        .iter()
+        .flat_map(|feature| &manifest.features[feature])
+        .map(String::as_str)
+        .collect();
+    enabled.extend(newly_enabled.iter().cloned());
+
+    queue_dependencies(packages_to_visit, manifest, Some(origin_realm), |alias| {
+        aliases.contains(alias)
+    })?;
+
+    Ok(())
+}
+
+/// Find the version that the preferences ask for, if it's one this request can
+/// use. Preferences that can't be used are ignored with a warning.
+fn preferred_version(
+    request: &DependencyRequest,
+    candidates: &[Manifest],
+    preferences: &VersionPreferences,
+) -> Option<Version> {
+    let preferred = preferences.get(request.package_req.name())?;
+
+    if !request.package_req.version_req().matches(preferred) {
+        log::warn!(
+            "Ignoring preferred version {} of {} because {} depends on {}",
+            preferred,
+            request.package_req.name(),
+            request.request_source,
+            request.package_req
+        );
+        return None;
+    }
+
+    let available = candidates
+        .iter()
+        .any(|candidate| &candidate.package.version == preferred);
+
+    if !available {
+        log::warn!(
+            "Ignoring preferred version {} of {} because it could not be found",
+            preferred,
+            request.package_req.name()
+        );
+        return None;
+    }
+
+    Some(preferred.clone())
 }
 
 fn compatible(a: &Version, b: &Version) -> bool {
@@ -316,8 +588,71 @@ pub struct DependencyRequest {
     origin_realm: Realm,
     package_alias: String,
     package_req: PackageReq,
+
+    /// Features the requester asked to turn on in the package.
+    features: BTreeSet<String>,
+}
+
+impl DependencyRequest {
+    fn requirement(&self, selected: Option<&Version>) -> ConflictingRequirement {
+        ConflictingRequirement {
+            requested_by: self.request_source.clone(),
+            version_req: self.package_req.version_req().clone(),
+            selected: selected.cloned(),
+        }
+    }
+}
+
+/// The error returned when packages require versions of the same package
+/// that no single version satisfies. Only one SemVer-compatible version of a
+/// package can be installed, so, for example, `^1.2.0` and `=1.1.0` conflict.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ResolveConflict {
+    /// The package that the requirements disagree on.
+    pub package: PackageName,
+
+    /// Each request for the package that had to be satisfied, with the one
+    /// that couldn't be last.
+    pub requirements: Vec<ConflictingRequirement>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ConflictingRequirement {
+    pub requested_by: PackageId,
+    pub version_req: VersionReq,
+
+    /// The version that was picked for this request, if one was.
+    pub selected: Option<Version>,
 }
 
+impl fmt::Display for ResolveConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "No version of {} satisfies every package that depends on it:",
+            self.package
+        )?;
+
+        for requirement in &self.requirements {
+            write!(
+                f,
+                "\n    {} requires {} {}",
+                requirement.requested_by, self.package, requirement.version_req
+            )?;
+
+            if let Some(selected) = &requirement.selected {
+                write!(f, ", which selected {}", selected)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ResolveConflict {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -470,6 +805,37 @@ mod tests {
         Ok(())
     }
 
+    /// Overrides in the root manifest force a version onto transitive
+    /// dependencies, even if it violates what the dependent asked for.
+    #[test]
+    fn override_transitive_dependency() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/minimal@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/minimal@1.0.1"));
+        registry.publish(PackageBuilder::new("biff/minimal@2.0.0"));
+        registry.publish(
+            PackageBuilder::new("biff/one-dependency@1.0.0")
+                .with_dep("Minimal", "biff/minimal@1.0.0"),
+        );
+
+        let root = PackageBuilder::new("biff/root@1.0.0")
+            .with_dep("OneDependency", "biff/one-dependency@1.0.0")
+            .with_override("biff/minimal", "2.0.0");
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolved = resolve(root.manifest(), &Default::default(), &package_sources)?;
+
+        let minimal: PackageId = "biff/minimal@2.0.0".parse().unwrap();
+        assert!(resolved.activated.contains(&minimal));
+        assert_eq!(resolved.activated.len(), 3);
+        assert_eq!(
+            resolved.overrides.get(minimal.name()),
+            Some(minimal.version())
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn one_dependency_yes_upgrade() -> anyhow::Result<()> {
         let registry = InMemoryRegistry::new();
@@ -498,4 +864,216 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn preferred_version_is_used() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/minimal@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/minimal@1.1.0"));
+        registry.publish(PackageBuilder::new("biff/minimal@1.2.0"));
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let root = PackageBuilder::new("biff/root@1.0.0").with_dep("Minimal", "biff/minimal@1.0.0");
+
+        let mut preferences = VersionPreferences::default();
+        preferences
+            .versions
+            .insert("biff/minimal".parse()?, "1.1.0".parse()?);
+
+        let resolved = resolve_with_preferences(
+            root.manifest(),
+            &Default::default(),
+            &package_sources,
+            &preferences,
+        )?;
+
+        assert!(resolved.activated.contains(&"biff/minimal@1.1.0".parse()?));
+        Ok(())
+    }
+
+    #[test]
+    fn unsatisfiable_preferred_version_is_ignored() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/minimal@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/minimal@1.2.0"));
+        registry.publish(PackageBuilder::new("biff/minimal@2.0.0"));
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let root = PackageBuilder::new("biff/root@1.0.0").with_dep("Minimal", "biff/minimal@1.0.0");
+
+        let mut preferences = VersionPreferences::default();
+        preferences
+            .versions
+            .insert("biff/minimal".parse()?, "2.0.0".parse()?);
+
+        let resolved = resolve_with_preferences(
+            root.manifest(),
+            &Default::default(),
+            &package_sources,
+            &preferences,
+        )?;
+
+        assert!(resolved.activated.contains(&"biff/minimal@1.2.0".parse()?));
+        Ok(())
+    }
+
+    /// Optional dependencies are only installed when something depending on
+    /// their package turns on a feature listing them, and the features that
+    /// were turned on are recorded.
+    #[test]
+    fn optional_dependencies_follow_features() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/minimal@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/roact@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/hooks@1.0.0"));
+        registry.publish(
+            PackageBuilder::new("biff/knit@1.0.0")
+                .with_dep("Minimal", "biff/minimal@1.0.0")
+                .with_dep("Roact", "biff/roact@1.0.0")
+                .with_dep("Hooks", "biff/hooks@1.0.0")
+                .with_feature("roact", &["Roact"])
+                .with_feature("hooks", &["roact", "Hooks"]),
+        );
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let knit: PackageId = "biff/knit@1.0.0".parse()?;
+        let roact: PackageId = "biff/roact@1.0.0".parse()?;
+        let hooks: PackageId = "biff/hooks@1.0.0".parse()?;
+
+        let root = PackageBuilder::new("biff/root@1.0.0").with_dep("Knit", "biff/knit@1.0.0");
+        let resolved = resolve(root.manifest(), &Default::default(), &package_sources)?;
+        assert!(resolved.activated.contains(&"biff/minimal@1.0.0".parse()?));
+        assert!(!resolved.activated.contains(&roact));
+        assert!(resolved.features.is_empty());
+
+        let root = root.with_dependency_features("Knit", &["hooks"]);
+        let resolved = resolve(root.manifest(), &Default::default(), &package_sources)?;
+        assert!(resolved.activated.contains(&roact));
+        assert!(resolved.activated.contains(&hooks));
+
+        let features: Vec<_> = resolved.features[&knit]
+            .iter()
+            .map(String::as_str)
+            .collect();
+        assert_eq!(features, vec!["hooks", "roact"]);
+
+        let root = PackageBuilder::new("biff/root@1.0.0")
+            .with_dep("Knit", "biff/knit@1.0.0")
+            .with_dependency_features("Knit", &["vue"]);
+        let err = resolve(root.manifest(), &Default::default(), &package_sources).unwrap_err();
+        assert!(
+            err.to_string().contains("no feature named \"vue\""),
+            "{}",
+            err
+        );
+
+        Ok(())
+    }
+
+    /// A feature turned on by a later dependent still pulls in its optional
+    /// dependencies, even though the package was already activated by then.
+    #[test]
+    fn features_are_unified() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/roact@1.0.0"));
+        registry.publish(
+            PackageBuilder::new("biff/knit@1.0.0")
+                .with_dep("Roact", "biff/roact@1.0.0")
+                .with_feature("roact", &["Roact"]),
+        );
+        registry.publish(
+            PackageBuilder::new("biff/ui@1.0.0")
+                .with_dep("Knit", "biff/knit@1.0.0")
+                .with_dependency_features("Knit", &["roact"]),
+        );
+
+        let root = PackageBuilder::new("biff/root@1.0.0")
+            .with_dep("Knit", "biff/knit@1.0.0")
+            .with_dep("Ui", "biff/ui@1.0.0");
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolved = resolve(root.manifest(), &Default::default(), &package_sources)?;
+        assert!(resolved.activated.contains(&"biff/roact@1.0.0".parse()?));
+
+        Ok(())
+    }
+
+    /// B's requirement on D is seen first and picks 1.2.0, which C can't use.
+    /// 1.1.0 works for both, so it should be picked instead.
+    #[test]
+    fn avoidable_conflict_is_resolved() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/b@1.0.0").with_dep("D", "biff/d@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/c@1.0.0").with_dep("D", "biff/d@=1.1.0"));
+        registry.publish(PackageBuilder::new("biff/d@1.1.0"));
+        registry.publish(PackageBuilder::new("biff/d@1.2.0"));
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let root = PackageBuilder::new("biff/a@1.0.0")
+            .with_dep("B", "biff/b@1.0.0")
+            .with_dep("C", "biff/c@1.0.0");
+
+        let resolved = resolve(root.manifest(), &Default::default(), &package_sources)?;
+        assert!(resolved.activated.contains(&"biff/d@1.1.0".parse()?));
+        assert!(!resolved.activated.contains(&"biff/d@1.2.0".parse()?));
+
+        Ok(())
+    }
+
+    #[test]
+    fn unsatisfiable_conflict_is_reported() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/b@1.0.0").with_dep("D", "biff/d@1.2.0"));
+        registry.publish(PackageBuilder::new("biff/c@1.0.0").with_dep("D", "biff/d@=1.1.0"));
+        registry.publish(PackageBuilder::new("biff/d@1.1.0"));
+        registry.publish(PackageBuilder::new("biff/d@1.2.0"));
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let root = PackageBuilder::new("biff/a@1.0.0")
+            .with_dep("B", "biff/b@1.0.0")
+            .with_dep("C", "biff/c@1.0.0");
+
+        let err = resolve(root.manifest(), &Default::default(), &package_sources).unwrap_err();
+        let conflict = err
+            .downcast_ref::<ResolveConflict>()
+            .expect("resolution should fail with a conflict");
+
+        assert_eq!(conflict.package, "biff/d".parse()?);
+        assert_eq!(conflict.requirements.len(), 2);
+
+        let first = &conflict.requirements[0];
+        assert_eq!(first.requested_by, "biff/b@1.0.0".parse()?);
+        assert_eq!(first.version_req.to_string(), "^1.2.0");
+        assert_eq!(first.selected, Some("1.2.0".parse()?));
+
+        let second = &conflict.requirements[1];
+        assert_eq!(second.requested_by, "biff/c@1.0.0".parse()?);
+        assert_eq!(second.version_req.to_string(), "=1.1.0");
+        assert_eq!(second.selected, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn yanked_versions_are_skipped() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/minimal@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/minimal@1.1.0"));
+
+        let yanked: PackageId = "biff/minimal@1.1.0".parse()?;
+        registry.yank(&yanked);
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let root = PackageBuilder::new("biff/root@1.0.0").with_dep("Minimal", "biff/minimal@1.0.0");
+
+        let resolved = resolve(root.manifest(), &Default::default(), &package_sources)?;
+        assert!(resolved.activated.contains(&"biff/minimal@1.0.0".parse()?));
+
+        // A lockfile that already uses the yanked version keeps using it.
+        let try_to_use = vec![yanked.clone()].into_iter().collect();
+        let resolved = resolve(root.manifest(), &try_to_use, &package_sources)?;
+        assert!(resolved.activated.contains(&yanked));
+
+        Ok(())
+    }
 }
@@ -3,14 +3,17 @@
 use std::{
     collections::BTreeMap,
     io::{Cursor, Write},
+    path::PathBuf,
 };
 
+use semver::Version;
 use zip::write::{FileOptions, ZipWriter};
 
 use crate::{
-    manifest::{Manifest, Package, Realm},
+    manifest::{Dependency, Manifest, Package, Realm},
     package_contents::PackageContents,
     package_id::PackageId,
+    package_name::PackageName,
     package_req::PackageReq,
 };
 
@@ -32,6 +35,7 @@ impl PackageBuilder {
                 name,
                 version,
                 registry: String::new(),
+                mirrors: Vec::new(),
                 realm: Realm::Shared,
                 description: None,
                 license: None,
@@ -46,6 +50,10 @@ impl PackageBuilder {
             dependencies: Default::default(),
             server_dependencies: Default::default(),
             dev_dependencies: Default::default(),
+            features: Default::default(),
+            dependency_features: Default::default(),
+            overrides: Default::default(),
+            hooks: Default::default(),
         };
 
         Self {
@@ -59,6 +67,16 @@ impl PackageBuilder {
         self
     }
 
+    pub fn with_description<S: Into<String>>(mut self, description: S) -> Self {
+        self.manifest.package.description = Some(description.into());
+        self
+    }
+
+    pub fn with_license<S: Into<String>>(mut self, license: S) -> Self {
+        self.manifest.package.license = Some(license.into());
+        self
+    }
+
     pub fn with_dep<A, R>(mut self, alias: A, package_req: R) -> Self
     where
         A: Into<String>,
@@ -66,7 +84,18 @@ impl PackageBuilder {
     {
         let req: PackageReq = package_req.as_ref().parse().expect("invalid PackageReq");
 
-        self.manifest.dependencies.insert(alias.into(), req);
+        self.manifest.dependencies.insert(alias.into(), req.into());
+        self
+    }
+
+    pub fn with_path_dep<A, P>(mut self, alias: A, path: P) -> Self
+    where
+        A: Into<String>,
+        P: Into<PathBuf>,
+    {
+        self.manifest
+            .dependencies
+            .insert(alias.into(), Dependency::Path(path.into()));
         self
     }
 
@@ -77,7 +106,51 @@ impl PackageBuilder {
     {
         let req: PackageReq = package_req.as_ref().parse().expect("invalid PackageReq");
 
-        self.manifest.server_dependencies.insert(alias.into(), req);
+        self.manifest
+            .server_dependencies
+            .insert(alias.into(), req.into());
+        self
+    }
+
+    pub fn with_feature<N, E>(mut self, feature: N, entries: &[E]) -> Self
+    where
+        N: Into<String>,
+        E: AsRef<str>,
+    {
+        let entries = entries
+            .iter()
+            .map(|entry| entry.as_ref().to_owned())
+            .collect();
+
+        self.manifest.features.insert(feature.into(), entries);
+        self
+    }
+
+    pub fn with_dependency_features<A, F>(mut self, alias: A, features: &[F]) -> Self
+    where
+        A: Into<String>,
+        F: AsRef<str>,
+    {
+        let features = features
+            .iter()
+            .map(|feature| feature.as_ref().to_owned())
+            .collect();
+
+        self.manifest
+            .dependency_features
+            .insert(alias.into(), features);
+        self
+    }
+
+    pub fn with_override<N, V>(mut self, package_name: N, version: V) -> Self
+    where
+        N: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let name: PackageName = package_name.as_ref().parse().expect("invalid PackageName");
+        let version: Version = version.as_ref().parse().expect("invalid Version");
+
+        self.manifest.overrides.insert(name, version);
         self
     }
 
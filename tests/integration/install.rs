@@ -60,6 +60,136 @@ fn locked_catches_dated_packages() {
     assert!(result.is_err(), "Should fail!");
 }
 
+#[test]
+fn post_install_hooks() {
+    let (project, result) = run_install_with_hooks(&["echo installed > hook-output.txt"], false);
+    result.unwrap();
+
+    assert!(project.path().join("hook-output.txt").is_file());
+}
+
+#[test]
+fn no_hooks() {
+    let (project, result) = run_install_with_hooks(&["echo installed > hook-output.txt"], true);
+    result.unwrap();
+
+    assert!(!project.path().join("hook-output.txt").exists());
+}
+
+#[test]
+fn failing_post_install_hook() {
+    let (_project, result) = run_install_with_hooks(&["echo sourcemap broke && exit 3"], false);
+    let err = result.unwrap_err();
+
+    assert!(err.to_string().contains("sourcemap broke"), "{}", err);
+}
+
+#[test]
+fn offline_path_dependency() {
+    let project = tempfile::tempdir().unwrap();
+    let write_manifest = |dir: &str, contents: &str| {
+        let dir = project.path().join(dir);
+        fs_err::create_dir_all(&dir).unwrap();
+        fs_err::write(dir.join("wally.toml"), contents).unwrap();
+    };
+
+    write_manifest(
+        "game",
+        r#"
+        [package]
+        name = "biff/game"
+        version = "0.1.0"
+        license = "MIT"
+        realm = "shared"
+        registry = "test-registries/primary-registry"
+
+        [dependencies]
+        Lib = { path = "../lib" }
+        "#,
+    );
+    write_manifest(
+        "lib",
+        r#"
+        [package]
+        name = "biff/lib"
+        version = "0.3.0"
+        license = "MIT"
+        realm = "shared"
+        registry = "test-registries/primary-registry"
+        "#,
+    );
+
+    let game = project.path().join("game");
+    let install = |offline: bool| {
+        Args {
+            global: GlobalOptions {
+                test_registry: true,
+                ..Default::default()
+            },
+            subcommand: Subcommand::Install(InstallSubcommand {
+                project_path: game.clone(),
+                locked: false,
+                preferences: None,
+                verify_integrity: false,
+                mirrors: Vec::new(),
+                search_mirrors: false,
+                jobs: None,
+                retries: None,
+                offline,
+                frozen: false,
+                no_hooks: false,
+            }),
+        }
+        .run()
+    };
+
+    install(false).unwrap();
+    fs_err::remove_dir_all(game.join("Packages")).unwrap();
+
+    // Path dependencies need no network, so they install offline too.
+    install(true).unwrap();
+    assert!(game.join("Packages").join("Lib.lua").is_file());
+}
+
+/// Installs the minimal project with `hooks` as its post-install hooks.
+fn run_install_with_hooks(
+    hooks: &[&str],
+    no_hooks: bool,
+) -> (TempProject, Result<(), anyhow::Error>) {
+    let source_project =
+        Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects",)).join("minimal");
+
+    let project = TempProject::new(&source_project).unwrap();
+
+    let manifest_path = project.path().join("wally.toml");
+    let mut manifest = fs_err::read_to_string(&manifest_path).unwrap();
+    manifest.push_str(&format!("\n[hooks]\npost-install = {:?}\n", hooks));
+    fs_err::write(&manifest_path, manifest).unwrap();
+
+    let result = Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Install(InstallSubcommand {
+            project_path: project.path().to_owned(),
+            locked: false,
+            preferences: None,
+            verify_integrity: false,
+            mirrors: Vec::new(),
+            search_mirrors: false,
+            jobs: None,
+            retries: None,
+            offline: false,
+            frozen: false,
+            no_hooks,
+        }),
+    }
+    .run();
+
+    (project, result)
+}
+
 fn run_locked_install(name: &str) -> Result<(), anyhow::Error> {
     let source_project =
         Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects",)).join(name);
@@ -74,6 +204,15 @@ fn run_locked_install(name: &str) -> Result<(), anyhow::Error> {
         subcommand: Subcommand::Install(InstallSubcommand {
             project_path: project.path().to_owned(),
             locked: true,
+            preferences: None,
+            verify_integrity: false,
+            mirrors: Vec::new(),
+            search_mirrors: false,
+            jobs: None,
+            retries: None,
+            offline: false,
+            frozen: false,
+            no_hooks: false,
         }),
     }
     .run()
@@ -93,6 +232,15 @@ fn run_install_test(name: &str) -> TempProject {
         subcommand: Subcommand::Install(InstallSubcommand {
             project_path: project.path().to_owned(),
             locked: false,
+            preferences: None,
+            verify_integrity: false,
+            mirrors: Vec::new(),
+            search_mirrors: false,
+            jobs: None,
+            retries: None,
+            offline: false,
+            frozen: false,
+            no_hooks: false,
         }),
     };
 
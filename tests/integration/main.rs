@@ -3,6 +3,7 @@ mod util;
 mod temp_project;
 
 mod install;
+mod migrate_lockfile;
 mod publish;
 mod read_projects;
 mod update;
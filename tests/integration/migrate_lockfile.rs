@@ -0,0 +1,64 @@
+use crate::temp_project::TempProject;
+use libwally::{
+    lockfile::{LockPackage, Lockfile},
+    Args, GlobalOptions, MigrateLockfileSubcommand, Subcommand,
+};
+use std::path::Path;
+
+#[test]
+fn fills_in_checksums() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/diamond-graph/root/dated"
+    ));
+
+    let project = TempProject::new(&source_project).unwrap();
+    let before = Lockfile::load(project.path()).unwrap().unwrap();
+
+    run_migrate_lockfile(&project).unwrap();
+
+    let after = Lockfile::load(project.path()).unwrap().unwrap();
+    assert_eq!(after.registry, "test-registries/primary-registry");
+    assert_eq!(
+        before.as_ids().collect::<Vec<_>>(),
+        after.as_ids().collect::<Vec<_>>(),
+        "Migrating shouldn't change which versions are locked."
+    );
+
+    for package in &after.packages {
+        if let LockPackage::Registry(package) = package {
+            if package.name.name() != "root" {
+                assert!(
+                    package.checksum.is_some(),
+                    "{} should have a checksum",
+                    package.name
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn missing_lockfile() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/diamond-graph/root/fresh"
+    ));
+
+    let project = TempProject::new(&source_project).unwrap();
+
+    assert!(run_migrate_lockfile(&project).is_err());
+}
+
+fn run_migrate_lockfile(project: &TempProject) -> anyhow::Result<()> {
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::MigrateLockfile(MigrateLockfileSubcommand {
+            project_path: project.path().to_owned(),
+        }),
+    }
+    .run()
+}
@@ -29,6 +29,8 @@ fn check_prompts_auth() {
         subcommand: Subcommand::Publish(PublishSubcommand {
             project_path: test_projects.join("minimal"),
             token: None,
+            registry: None,
+            archive: None,
         }),
     };
 
@@ -79,6 +81,8 @@ fn check_private_field() {
         subcommand: Subcommand::Publish(PublishSubcommand {
             project_path: test_projects.join("private-package"),
             token: None,
+            registry: None,
+            archive: None,
         }),
     };
 
@@ -113,9 +117,83 @@ fn check_token_arg() {
         subcommand: Subcommand::Publish(PublishSubcommand {
             project_path: test_projects.join("minimal"),
             token: Some("token".to_owned()),
+            registry: None,
+            archive: None,
         }),
     };
 
     args.run()
         .expect("Publish did not use the provided token in the publish request");
 }
+
+/// A prepared archive should be published as-is, using its own manifest
+/// instead of the one in the project path
+#[test]
+#[serial]
+fn check_publish_archive() {
+    let test_projects = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects"));
+    let test_registry = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-registries/primary-registry"
+    ));
+
+    git_util::init_test_repo(&test_registry.join("index")).unwrap();
+
+    let contents = PackageContents::pack_from_path(&test_projects.join("minimal")).unwrap();
+    let archive_dir = tempdir().unwrap();
+    let archive_path = archive_dir.path().join("minimal.zip");
+    fs_err::write(&archive_path, contents.data()).unwrap();
+
+    let args = Args {
+        global: GlobalOptions {
+            test_registry: true,
+            use_temp_index: true,
+            check_token: Some("token".to_owned()),
+            ..Default::default()
+        },
+        subcommand: Subcommand::Publish(PublishSubcommand {
+            // This project is private, so publishing would fail if it were used
+            project_path: test_projects.join("private-package"),
+            token: Some("token".to_owned()),
+            registry: None,
+            archive: Some(archive_path),
+        }),
+    };
+
+    args.run()
+        .expect("Publish did not use the prepared archive");
+}
+
+/// `--registry` should publish to the given registry instead of the one in
+/// the manifest
+#[test]
+#[serial]
+fn check_registry_override() {
+    let test_projects = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects"));
+    let test_registries = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-registries"));
+
+    git_util::init_test_repo(&test_registries.join("tertiary-registry/index")).unwrap();
+
+    let publish = |registry: &Path| Args {
+        global: GlobalOptions {
+            test_registry: true,
+            use_temp_index: true,
+            check_token: Some("token".to_owned()),
+            ..Default::default()
+        },
+        subcommand: Subcommand::Publish(PublishSubcommand {
+            project_path: test_projects.join("minimal"),
+            token: Some("token".to_owned()),
+            registry: Some(registry.to_str().unwrap().to_owned()),
+            archive: None,
+        }),
+    };
+
+    publish(&test_registries.join("tertiary-registry"))
+        .run()
+        .expect("Publish did not use the given registry");
+
+    publish(&test_registries.join("missing-registry"))
+        .run()
+        .expect_err("Publish used the manifest's registry instead of the given one");
+}
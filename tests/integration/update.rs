@@ -146,6 +146,13 @@ fn run_update_with_specs(project: &TempProject, specs: Vec<PackageSpec>) -> anyh
         subcommand: Subcommand::Update(UpdateSubcommand {
             project_path: project.path().to_owned(),
             package_specs: specs,
+            preferences: None,
+            verify_integrity: false,
+            mirrors: Vec::new(),
+            search_mirrors: false,
+            jobs: None,
+            retries: None,
+            no_hooks: false,
         }),
     }
     .run()
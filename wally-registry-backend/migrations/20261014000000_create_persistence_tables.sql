@@ -0,0 +1,30 @@
+CREATE TABLE package_downloads (
+    scope TEXT NOT NULL,
+    name TEXT NOT NULL,
+    version TEXT NOT NULL,
+    downloads BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (scope, name, version)
+);
+
+-- Only ever appended to, like the audit log.
+CREATE TABLE audit_events (
+    id BIGSERIAL PRIMARY KEY,
+    timestamp BIGINT NOT NULL,
+    outcome TEXT NOT NULL,
+    actor TEXT NOT NULL,
+    user_id BIGINT,
+    package TEXT NOT NULL,
+    scope TEXT NOT NULL,
+    permission TEXT,
+    reason TEXT
+);
+
+CREATE INDEX audit_events_by_scope ON audit_events (scope, timestamp);
+
+CREATE TABLE blocked_users (
+    user_id BIGINT PRIMARY KEY
+);
+
+CREATE TABLE blocked_tokens (
+    token_hash TEXT PRIMARY KEY
+);
@@ -0,0 +1,8 @@
+-- Users from the registry's other GitHub hosts are told apart from users of
+-- the main host with the same id by the host's name. It's NULL for users of
+-- the main host, and for GitLab users.
+ALTER TABLE audit_events ADD COLUMN user_provider TEXT;
+
+ALTER TABLE blocked_users ADD COLUMN user_provider TEXT;
+ALTER TABLE blocked_users DROP CONSTRAINT blocked_users_pkey;
+CREATE UNIQUE INDEX blocked_users_by_id ON blocked_users (user_id, COALESCE(user_provider, ''));
@@ -0,0 +1,5 @@
+-- Yanks, unyanks, and unpublishes are audited too, and a scope's activity is
+-- read from here. Events from before then are all publishes.
+ALTER TABLE audit_events ADD COLUMN action TEXT NOT NULL DEFAULT 'publish';
+
+CREATE INDEX audit_events_by_scope_and_id ON audit_events (scope, id);
@@ -0,0 +1,276 @@
+//! Writes a structured record of every attempt to publish a package, whether
+//! it was allowed or not, and of every yank, unyank, and unpublish, so that
+//! there's a trail to follow when something unexpected shows up in the index.
+//!
+//! Each event is written as a single line of JSON, and kept in the registry's
+//! persistence, which is where a scope's activity is read from. The audit log
+//! is only ever appended to.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{format_err, Context};
+use fs_err::OpenOptions;
+use libwally::{package_id::PackageId, package_index::OwnerId};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::{WriteAccess, WritePermission};
+use crate::persistence::Persistence;
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum AuditSink {
+    Stdout,
+    File { path: PathBuf },
+}
+
+/// Events from before other actions were audited are all publishes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuditAction {
+    #[default]
+    Publish,
+    Yank,
+    Unyank,
+    Unpublish,
+}
+
+impl AuditAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuditAction::Publish => "publish",
+            AuditAction::Yank => "yank",
+            AuditAction::Unyank => "unyank",
+            AuditAction::Unpublish => "unpublish",
+        }
+    }
+
+    pub fn parse(action: &str) -> Option<Self> {
+        match action {
+            "publish" => Some(AuditAction::Publish),
+            "yank" => Some(AuditAction::Yank),
+            "unyank" => Some(AuditAction::Unyank),
+            "unpublish" => Some(AuditAction::Unpublish),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuditOutcome {
+    Allowed,
+    Denied,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AuditEvent {
+    /// When the attempt was made, in seconds since the Unix epoch.
+    pub timestamp: u64,
+
+    #[serde(default)]
+    pub action: AuditAction,
+
+    pub outcome: AuditOutcome,
+
+    /// Who made the attempt: a GitHub or GitLab login, or "api-key".
+    pub actor: String,
+
+    /// The id of the user who made the attempt, if it was a user.
+    pub user_id: Option<OwnerId>,
+
+    pub package: PackageId,
+
+    pub scope: String,
+
+    /// Why the attempt was allowed. Only set for allowed attempts.
+    pub permission: Option<WritePermission>,
+
+    /// Why the attempt was denied. Only set for denied attempts.
+    pub reason: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn allowed(
+        authorization: &WriteAccess,
+        package: &PackageId,
+        permission: WritePermission,
+    ) -> Self {
+        Self::new(
+            authorization,
+            package,
+            AuditAction::Publish,
+            AuditOutcome::Allowed,
+            Some(permission),
+            None,
+        )
+    }
+
+    pub fn denied(authorization: &WriteAccess, package: &PackageId, reason: &str) -> Self {
+        Self::new(
+            authorization,
+            package,
+            AuditAction::Publish,
+            AuditOutcome::Denied,
+            None,
+            Some(reason.to_owned()),
+        )
+    }
+
+    /// A yank, unyank, or unpublish that went through. Only those are
+    /// audited, since they're made by people already allowed to publish.
+    pub fn changed(authorization: &WriteAccess, package: &PackageId, action: AuditAction) -> Self {
+        Self::new(
+            authorization,
+            package,
+            action,
+            AuditOutcome::Allowed,
+            None,
+            None,
+        )
+    }
+
+    /// A change made with the registry's admin key, which doesn't belong to a
+    /// user.
+    pub fn by_admin(package: &PackageId, action: AuditAction) -> Self {
+        Self::with_actor(
+            String::from("admin-key"),
+            None,
+            package,
+            action,
+            AuditOutcome::Allowed,
+            None,
+            None,
+        )
+    }
+
+    fn new(
+        authorization: &WriteAccess,
+        package: &PackageId,
+        action: AuditAction,
+        outcome: AuditOutcome,
+        permission: Option<WritePermission>,
+        reason: Option<String>,
+    ) -> Self {
+        Self::with_actor(
+            authorization.actor().to_owned(),
+            authorization.user_id(),
+            package,
+            action,
+            outcome,
+            permission,
+            reason,
+        )
+    }
+
+    fn with_actor(
+        actor: String,
+        user_id: Option<OwnerId>,
+        package: &PackageId,
+        action: AuditAction,
+        outcome: AuditOutcome,
+        permission: Option<WritePermission>,
+        reason: Option<String>,
+    ) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+
+        Self {
+            timestamp,
+            action,
+            outcome,
+            actor,
+            user_id,
+            package: package.clone(),
+            scope: package.name().scope().to_owned(),
+            permission,
+            reason,
+        }
+    }
+}
+
+/// An audit event as it was kept, with the id it was given there, which goes
+/// up with every event and is used to page through them.
+#[derive(Debug, Clone)]
+pub struct StoredAuditEvent {
+    pub id: u64,
+    pub event: AuditEvent,
+}
+
+pub struct AuditLog {
+    output: Option<Mutex<Box<dyn Write + Send>>>,
+    persistence: Option<Arc<dyn Persistence>>,
+}
+
+impl AuditLog {
+    /// An audit log that doesn't write anywhere.
+    pub fn disabled() -> Self {
+        Self {
+            output: None,
+            persistence: None,
+        }
+    }
+
+    pub fn new(sink: &AuditSink) -> anyhow::Result<Self> {
+        let output: Box<dyn Write + Send> = match sink {
+            AuditSink::Stdout => Box::new(std::io::stdout()),
+            AuditSink::File { path } => Box::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .context("could not open audit log")?,
+            ),
+        };
+
+        Ok(Self {
+            output: Some(Mutex::new(output)),
+            persistence: None,
+        })
+    }
+
+    /// Also keep every event in `persistence`, whether or not the log writes
+    /// anywhere itself.
+    pub fn persist_to(mut self, persistence: Arc<dyn Persistence>) -> Self {
+        self.persistence = Some(persistence);
+        self
+    }
+
+    /// Keeps an event in persistence, then writes it to the log. The event is
+    /// persisted before this returns, so that it shows up in the activity of
+    /// its scope right away.
+    pub async fn record(&self, event: &AuditEvent) -> anyhow::Result<()> {
+        if let Some(persistence) = &self.persistence {
+            // Persistence is how activity is shown rather than the record of
+            // the event, so failing to persist it doesn't stop it from being
+            // written to the log.
+            if let Err(err) = persistence.record_audit(event).await {
+                tracing::warn!(error = ?err, "could not persist audit event");
+            }
+        }
+
+        let output = match &self.output {
+            Some(output) => output,
+            None => return Ok(()),
+        };
+
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+
+        let mut output = output
+            .lock()
+            .map_err(|_| format_err!("audit log is unavailable"))?;
+
+        // Write the whole line at once, so that lines from concurrent
+        // requests never end up interleaved.
+        output.write_all(&line)?;
+        output.flush()?;
+
+        Ok(())
+    }
+}
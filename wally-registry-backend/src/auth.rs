@@ -1,26 +1,52 @@
-use std::{collections::HashMap, fmt};
+use std::{collections::HashMap, fmt, future::Future, sync::Arc};
 
 use anyhow::{anyhow, format_err};
 use constant_time_eq::constant_time_eq;
-use libwally::{package_id::PackageId, package_index::PackageIndex};
-use reqwest::{Client, StatusCode};
+use libwally::{
+    package_id::PackageId,
+    package_index::{OwnerId, PackageIndex},
+};
+use reqwest::StatusCode;
 use rocket::{
     http::Status,
     request::{FromRequest, Outcome},
     Request, State,
 };
 use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use url::Url;
 
+use crate::auth_throttle::AuthThrottle;
+use crate::blocklist::Blocklist;
 use crate::error::Error;
-use crate::{config::Config, error::ApiErrorStatus};
+use crate::github_app::GithubApp;
+use crate::github_client::{GithubClient, TimedClient};
+use crate::github_rate_limit::{observe_github_rate_limit, secondary_rate_limit, GithubRateLimit};
+use crate::maintenance::MaintenanceMode;
+use crate::metrics::{time_github_call, Metrics};
+use crate::rate_limit::{rate_limit, AccessKind, Identity};
+use crate::read_tokens::{self, ReadTokenClaims, ReadTokenError};
+use crate::request_id::RequestId;
+use crate::retry::GithubRetry;
+use crate::teams::TeamMembership;
+use crate::token_cache::{CachedToken, CachedUser, TokenCache};
+use crate::trusted_publishing::{self, IssuerKeys, TrustedPublisherInfo};
+use crate::{
+    config::{BootstrapPolicy, Config},
+    error::ApiErrorStatus,
+};
 
 #[derive(Deserialize, Serialize)]
 #[serde(tag = "type", content = "value", rename_all = "kebab-case")]
 pub enum AuthMode {
-    ApiKey(String),
+    ApiKey(ApiKeys),
     DoubleApiKey {
-        read: Option<String>,
-        write: String,
+        read: Option<ApiKeys>,
+        write: ApiKeys,
+        /// The only scopes the read keys can read from. If not set, read keys
+        /// can read everything.
+        #[serde(default, rename = "read-scopes")]
+        read_scopes: Option<Vec<String>>,
     },
     GithubOAuth {
         #[serde(rename = "client-id")]
@@ -34,13 +60,83 @@ pub enum AuthMode {
         #[serde(rename = "client-secret")]
         client_secret: String,
     },
+    /// OAuth through a GitLab instance, either gitlab.com or a self-hosted one.
+    #[serde(rename = "gitlab")]
+    GitLab {
+        #[serde(rename = "client-id")]
+        client_id: String,
+        #[serde(rename = "client-secret")]
+        client_secret: String,
+        #[serde(rename = "instance-url")]
+        instance_url: Url,
+        /// Like `github-oauth-private`, require everyone to be a member of the
+        /// index project, even to read.
+        #[serde(default)]
+        private: bool,
+    },
+    /// Writes from CI jobs with an OIDC token from one of the
+    /// `trusted_publishers`, so that CI doesn't need a stored secret. Reads
+    /// are public.
+    TrustedPublishing {
+        /// The audience tokens have to be issued for.
+        #[serde(default = "default_oidc_audience")]
+        audience: String,
+        /// Keys that can still write to every scope, for publishing by hand.
+        #[serde(default, rename = "api-key")]
+        api_key: Option<ApiKeys>,
+    },
     Unauthenticated,
 }
 
-#[derive(Deserialize)]
+fn default_oidc_audience() -> String {
+    String::from("wally")
+}
+
+/// One or more API keys, any of which is accepted. Allowing several at once
+/// means a key can be rotated without downtime: add the new key, move clients
+/// over to it, then remove the old one.
+///
+/// Configured as either a single string or an array of strings.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ApiKeys {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl ApiKeys {
+    pub fn as_slice(&self) -> &[String] {
+        match self {
+            ApiKeys::One(key) => std::slice::from_ref(key),
+            ApiKeys::Many(keys) => keys,
+        }
+    }
+}
+
+impl From<&str> for ApiKeys {
+    fn from(key: &str) -> Self {
+        ApiKeys::One(key.to_owned())
+    }
+}
+
+#[derive(Clone, Deserialize)]
 pub struct GithubInfo {
     login: String,
     id: u64,
+
+    /// When the account was created, as an RFC 3339 timestamp.
+    #[serde(default)]
+    created_at: Option<String>,
+
+    /// Whether the account has a verified email address. This doesn't come
+    /// from GitHub's user endpoint, so it's only set if it was checked.
+    #[serde(default)]
+    verified_email: Option<bool>,
+
+    /// Which of `github_providers` the account is on, or `None` for the main
+    /// GitHub host. This doesn't come from GitHub either.
+    #[serde(skip)]
+    provider: Option<String>,
 }
 
 impl GithubInfo {
@@ -51,30 +147,201 @@ impl GithubInfo {
     pub fn id(&self) -> &u64 {
         &self.id
     }
+
+    /// Which of `github_providers` the account is on, or `None` for the main
+    /// GitHub host.
+    pub fn provider(&self) -> Option<&str> {
+        self.provider.as_deref()
+    }
+
+    /// The account's id, namespaced by its provider so that it can't be
+    /// mistaken for an account with the same id on another host.
+    pub fn owner_id(&self) -> OwnerId {
+        match &self.provider {
+            Some(provider) => OwnerId::with_provider(provider, self.id),
+            None => OwnerId::new(self.id),
+        }
+    }
+
+    /// How many whole days ago the account was created, if GitHub said when.
+    pub fn account_age_days(&self) -> Option<i64> {
+        let created_at = OffsetDateTime::parse(self.created_at.as_deref()?, &Rfc3339).ok()?;
+        Some((OffsetDateTime::now_utc() - created_at).whole_days())
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct GitLabInfo {
+    username: String,
+    id: u64,
+}
+
+impl GitLabInfo {
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn id(&self) -> &u64 {
+        &self.id
+    }
+}
+
+#[derive(Deserialize)]
+struct GitLabTokenInfo {
+    application: Option<GitLabApplication>,
+}
+
+#[derive(Deserialize)]
+struct GitLabApplication {
+    uid: String,
+}
+
+#[derive(Deserialize)]
+struct GitLabMember {
+    access_level: u64,
 }
 
+/// GitLab's access level for the Reporter role, the lowest that can read a
+/// private project's repository.
+const GITLAB_REPORTER_ACCESS: u64 = 20;
+
 #[derive(Deserialize)]
-#[allow(unused)] // Variables are (currently) not accessed but ensure they are present during json parsing
-struct ValidatedGithubApp {
-    client_id: String,
+pub(crate) struct ValidatedGithubApp {
+    #[serde(default)]
+    client_id: Option<String>,
 }
 
+/// GitHub's answer to checking a token. OAuth app tokens and GitHub App
+/// user-to-server tokens are answered with slightly different bodies, with
+/// fields that can be `null` or missing in one but not the other, so only
+/// `id` is required and everything unknown is ignored.
 #[derive(Deserialize)]
-#[allow(unused)] // Variables are (currently) not accessed but ensure they are present during json parsing
-struct ValidatedGithubInfo {
+pub(crate) struct ValidatedGithubInfo {
+    #[allow(unused)] // Not accessed but ensures it's present during json parsing
     id: u64,
-    app: ValidatedGithubApp,
+
+    #[serde(default)]
+    app: Option<ValidatedGithubApp>,
+}
+
+impl ValidatedGithubInfo {
+    /// GitHub will happily validate a token issued to any OAuth app, so check
+    /// that it was issued to ours. Otherwise a token from another registry
+    /// could be replayed against this one.
+    pub(crate) fn check_app(&self, client_id: &str) -> Result<(), Error> {
+        let token_client_id = match self.app.as_ref().and_then(|app| app.client_id.as_ref()) {
+            Some(token_client_id) => token_client_id,
+            None => {
+                return Err(format_err!(
+                    "GitHub's token check didn't say which application the token was issued for"
+                )
+                .status(Status::BadGateway)
+                .code("github_unexpected_response"))
+            }
+        };
+
+        if constant_time_eq(token_client_id.as_bytes(), client_id.as_bytes()) {
+            Ok(())
+        } else {
+            Err(
+                format_err!("GitHub token was issued for a different application")
+                    .status(Status::Unauthorized)
+                    .code("github_auth_failed"),
+            )
+        }
+    }
 }
 
 #[derive(Deserialize)]
-struct GithubPermissionInfo {
+struct GithubEmail {
+    email: String,
+    verified: bool,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct GithubPermissionInfo {
+    /// One of `admin`, `write`, `read`, or `none`, with `maintain` and
+    /// `triage` folded into `write` and `read`.
     permission: String,
+
+    /// The user's role, which tells `maintain` and `triage` apart. Custom
+    /// roles have their own names, which aren't understood here.
+    role_name: Option<String>,
 }
 
 impl GithubPermissionInfo {
     pub fn permission(&self) -> &str {
         &self.permission
     }
+
+    /// How much access this is, from the role if it's one of GitHub's own,
+    /// or else from the permission it's based on.
+    pub(crate) fn level(&self) -> Option<GithubPermission> {
+        self.role_name
+            .as_deref()
+            .and_then(GithubPermission::from_name)
+            .or_else(|| GithubPermission::from_name(&self.permission))
+    }
+}
+
+/// How much access a GitHub user has to a repository, in GitHub's order, so
+/// that a minimum can be required.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GithubPermission {
+    Read,
+    Triage,
+    Write,
+    Maintain,
+    Admin,
+}
+
+impl GithubPermission {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "read" => Some(GithubPermission::Read),
+            "triage" => Some(GithubPermission::Triage),
+            "write" => Some(GithubPermission::Write),
+            "maintain" => Some(GithubPermission::Maintain),
+            "admin" => Some(GithubPermission::Admin),
+            _ => None,
+        }
+    }
+}
+
+impl Default for GithubPermission {
+    fn default() -> Self {
+        GithubPermission::Read
+    }
+}
+
+impl fmt::Display for GithubPermission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            GithubPermission::Read => "read",
+            GithubPermission::Triage => "triage",
+            GithubPermission::Write => "write",
+            GithubPermission::Maintain => "maintain",
+            GithubPermission::Admin => "admin",
+        };
+
+        f.write_str(name)
+    }
+}
+
+impl AuthMode {
+    /// A short name for the kind of auth mode, for labelling metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AuthMode::ApiKey(_) => "api-key",
+            AuthMode::DoubleApiKey { .. } => "double-api-key",
+            AuthMode::GithubOAuth { .. } => "github-oauth",
+            AuthMode::GithubOAuthPrivate { .. } => "github-oauth-private",
+            AuthMode::GitLab { .. } => "gitlab",
+            AuthMode::TrustedPublishing { .. } => "trusted-publishing",
+            AuthMode::Unauthenticated => "unauthenticated",
+        }
+    }
 }
 
 impl fmt::Debug for AuthMode {
@@ -84,52 +351,263 @@ impl fmt::Debug for AuthMode {
             AuthMode::DoubleApiKey { .. } => write!(formatter, "double API key"),
             AuthMode::GithubOAuth { .. } => write!(formatter, "Github OAuth"),
             AuthMode::GithubOAuthPrivate { .. } => write!(formatter, "Github OAuth (private)"),
+            AuthMode::GitLab {
+                instance_url,
+                private,
+                ..
+            } => {
+                write!(formatter, "GitLab OAuth via {}", instance_url)?;
+
+                if *private {
+                    write!(formatter, " (private)")?;
+                }
+
+                Ok(())
+            }
+            AuthMode::TrustedPublishing { .. } => write!(formatter, "trusted publishing"),
             AuthMode::Unauthenticated => write!(formatter, "no authentication"),
         }
     }
 }
 
-fn match_api_key<T>(request: &Request<'_>, key: &str, result: T) -> Outcome<T, Error> {
-    let input_api_key: String = match request.headers().get_one("authorization") {
-        Some(key) if key.starts_with("Bearer ") => (key[6..].trim()).to_owned(),
-        _ => {
+impl AuthMode {
+    /// What a client needs to know to authenticate, like which OAuth app to
+    /// log in with. Like `Debug`, this never includes keys or secrets.
+    pub fn public_info(&self) -> serde_json::Value {
+        match self {
+            AuthMode::ApiKey(_) => serde_json::json!({ "type": "api-key" }),
+            AuthMode::DoubleApiKey { read, .. } => serde_json::json!({
+                "type": "double-api-key",
+                "read-key-required": read.is_some(),
+            }),
+            AuthMode::GithubOAuth { client_id, .. } => serde_json::json!({
+                "type": "github-oauth",
+                "client-id": client_id,
+            }),
+            AuthMode::GithubOAuthPrivate { client_id, .. } => serde_json::json!({
+                "type": "github-oauth-private",
+                "client-id": client_id,
+            }),
+            AuthMode::GitLab {
+                client_id,
+                instance_url,
+                private,
+                ..
+            } => serde_json::json!({
+                "type": "gitlab",
+                "client-id": client_id,
+                "instance-url": instance_url,
+                "private": private,
+            }),
+            AuthMode::TrustedPublishing { audience, api_key } => serde_json::json!({
+                "type": "trusted-publishing",
+                "audience": audience,
+                "api-key-accepted": api_key.is_some(),
+            }),
+            AuthMode::Unauthenticated => serde_json::json!({ "type": "unauthenticated" }),
+        }
+    }
+}
+
+fn match_api_key<T>(request: &Request<'_>, keys: &[String], result: T) -> Outcome<T, Error> {
+    if let Some(key) = bearer_token(request) {
+        if let Err(err) = check_token_blocklist(request, key) {
+            return err.into();
+        }
+    }
+
+    compare_api_key(request, keys, result)
+}
+
+/// Checks the API key without consulting the blocklist. Used for the admin
+/// key, so that the blocklist can't lock admins out of changing it.
+fn compare_api_key<T>(request: &Request<'_>, keys: &[String], result: T) -> Outcome<T, Error> {
+    let input_api_key = match bearer_token(request) {
+        Some(key) => key,
+        None => {
             return format_err!("API key required")
                 .status(Status::Unauthorized)
+                .code("auth_required")
                 .into();
         }
     };
 
-    if constant_time_eq(key.as_bytes(), input_api_key.as_bytes()) {
+    if key_matches(keys, input_api_key) {
         Outcome::Success(result)
     } else {
         format_err!("Invalid API key for read access")
             .status(Status::Unauthorized)
+            .code("invalid_api_key")
             .into()
     }
 }
 
-fn extract_github_owner_repo(url: &str) -> Option<(String, String)> {
-    // Remove "https://" or "http://"
-    let url = url.strip_prefix("https://")
-        .or_else(|| url.strip_prefix("http://"))
-        .unwrap_or(url);
+fn key_matches(keys: &[String], input_api_key: &str) -> bool {
+    // Compare against every key, even after finding a match, so the time
+    // taken doesn't reveal which key matched.
+    keys.iter().fold(false, |matched, key| {
+        constant_time_eq(key.as_bytes(), input_api_key.as_bytes()) | matched
+    })
+}
+
+fn bearer_token<'r>(request: &'r Request<'_>) -> Option<&'r str> {
+    match request.headers().get_one("authorization") {
+        Some(key) if key.starts_with("Bearer ") => Some(key[6..].trim()),
+        _ => None,
+    }
+}
+
+/// Turns away requests from IP addresses and token prefixes that have failed
+/// to authenticate too often, before their token is checked with anyone, and
+/// counts how `authenticate` went. `authenticated` says whether a successful
+/// outcome actually checked the token, rather than letting anyone in.
+async fn throttle_auth<T>(
+    request: &Request<'_>,
+    authenticate: impl Future<Output = Outcome<T, Error>>,
+    authenticated: impl Fn(&T) -> bool,
+) -> Outcome<T, Error> {
+    let token = match bearer_token(request) {
+        Some(token) => token,
+        // Without a token, there's nothing being guessed.
+        None => return authenticate.await,
+    };
+
+    let throttle = request
+        .guard::<&State<AuthThrottle>>()
+        .await
+        .expect("AuthThrottle was not configured");
+    let ip = request.client_ip();
+
+    if let Err(wait) = throttle.check(ip, token) {
+        let retry_after = wait.as_secs_f64().ceil() as u64;
+
+        return format_err!(
+            "Too many failed attempts to authenticate. Try again in {} second(s).",
+            retry_after
+        )
+        .status(Status::TooManyRequests)
+        .code("auth_throttled")
+        .retry_after(retry_after)
+        .into();
+    }
+
+    let outcome = authenticate.await;
+
+    match &outcome {
+        Outcome::Success(access) if authenticated(access) => throttle.record_success(ip, token),
+        Outcome::Failure((_, err)) if err.http_status() == Status::Unauthorized => {
+            throttle.record_failure(ip, token)
+        }
+        _ => {}
+    }
+
+    outcome
+}
+
+fn check_token_blocklist(request: &Request<'_>, token: &str) -> Result<(), Error> {
+    match request.rocket().state::<Blocklist>() {
+        Some(blocklist) if blocklist.is_token_blocked(token) => {
+            Err(format_err!("This token has been blocked from the registry")
+                .status(Status::Forbidden)
+                .code("blocked"))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn check_gitlab_user_blocklist(request: &Request<'_>, info: &GitLabInfo) -> Result<(), Error> {
+    match request.rocket().state::<Blocklist>() {
+        Some(blocklist) if blocklist.is_user_blocked(&OwnerId::new(info.id)) => Err(format_err!(
+            "The GitLab account {} has been blocked from the registry",
+            info.username
+        )
+        .status(Status::Forbidden)
+        .code("blocked")),
+        _ => Ok(()),
+    }
+}
+
+/// Accounts are checked by their id, which stays the same when they're renamed.
+fn check_user_blocklist(request: &Request<'_>, info: &GithubInfo) -> Result<(), Error> {
+    match request.rocket().state::<Blocklist>() {
+        Some(blocklist) if blocklist.is_user_blocked(&info.owner_id()) => Err(format_err!(
+            "The GitHub account {} has been blocked from the registry",
+            info.login
+        )
+        .status(Status::Forbidden)
+        .code("blocked")),
+        _ => Ok(()),
+    }
+}
+
+/// Work out the host, owner, and name of the GitHub repository an index URL
+/// points to. Handles `https://`, `http://`, and `ssh://` URLs as well as the
+/// SCP-like `git@host:owner/repo.git`, on github.com or an Enterprise host.
+pub(crate) fn extract_github_owner_repo(url: &str) -> Option<(String, String, String)> {
+    let (host, path) = match ["https://", "http://", "ssh://"]
+        .iter()
+        .find_map(|scheme| url.strip_prefix(scheme))
+    {
+        Some(rest) => {
+            let (host, path) = rest.split_once('/')?;
+
+            // SSH URLs can name a port, which is no use for the HTTP API.
+            match url.starts_with("ssh://") {
+                true => (host.split(':').next()?, path),
+                false => (host, path),
+            }
+        }
+        None => url.split_once(':')?,
+    };
+
+    // Drop any user, like the `git@` in SSH URLs.
+    let host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+
+    let path = path.trim_end_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+
+    let mut parts = path.split('/');
+    let owner = parts.next()?;
+    let repo = parts.next()?;
+
+    if host.is_empty() || owner.is_empty() || repo.is_empty() {
+        return None;
+    }
+
+    Some((host.to_owned(), owner.to_owned(), repo.to_owned()))
+}
+
+/// The API base of the host the index is on, and the index repository's API
+/// URL under it. The index always lives on the main host, whichever host a
+/// user signed in through, and the registry's token is only good there, so
+/// permission checks always go to it.
+pub(crate) fn index_repo_api(config: &Config) -> Option<(String, String)> {
+    let (_, owner, repo) = extract_github_owner_repo(config.index_url.as_str())?;
+    let api_base = config.github_api_base();
+    let repo_api = format!("{api_base}/repos/{owner}/{repo}");
 
-    // Remove trailing ".git" or "/"
-    let url = url.trim_end_matches(".git").trim_end_matches('/');
+    Some((api_base, repo_api))
+}
 
-    // Now expect: github.com/org/repo
-    let parts: Vec<&str> = url.split('/').collect();
-    if parts.len() >= 3 && parts[0] == "github.com" {
-        let org = parts[1].to_string();
-        let repo = parts[2].to_string();
-        Some((org, repo))
+/// The base of GitHub's REST API for repositories on the given host.
+/// Enterprise Server hosts it under `/api/v3` on the same host.
+pub(crate) fn github_api_base(host: &str) -> String {
+    if host.eq_ignore_ascii_case("github.com") || host.eq_ignore_ascii_case("www.github.com") {
+        "https://api.github.com".to_owned()
     } else {
-        None
+        format!("https://{}/api/v3", host)
     }
 }
 
-trait GithubAccessor {
-    fn construct(info: GithubInfo) -> Self;
+trait OAuthAccessor {
+    /// Whether this kind of access allows modifying the registry.
+    const WRITE: bool;
+
+    /// `permission` is the user's permission on the index repository, like
+    /// `admin` or `write`, if it was checked.
+    fn construct(info: GithubInfo, permission: Option<String>) -> Self;
+
+    fn construct_gitlab(info: GitLabInfo) -> Self;
 }
 
 #[derive(PartialEq, Eq)]
@@ -138,7 +616,16 @@ enum IndexAccessPolicy {
     Required,
 }
 
-async fn verify_github<AccessType: GithubAccessor>(
+// The token is never recorded on the span, only who it turned out to belong to.
+#[tracing::instrument(
+    skip_all,
+    fields(
+        request_id = %RequestId::of(request),
+        login = tracing::field::Empty,
+        id = tracing::field::Empty,
+    )
+)]
+async fn verify_github<AccessType: OAuthAccessor>(
     request: &Request<'_>,
     client_id: &str,
     client_secret: &str,
@@ -149,139 +636,946 @@ async fn verify_github<AccessType: GithubAccessor>(
         _ => {
             return format_err!("Github auth required")
                 .status(Status::Unauthorized)
+                .code("auth_required")
                 .into();
         }
     };
 
-    let client = Client::new();
-    let response = client
-        .get("https://api.github.com/user")
-        .header("accept", "application/json")
-        .header("user-agent", "wally")
-        .bearer_auth(&token)
-        .send()
+    if let Err(err) = check_token_blocklist(request, &token) {
+        return err.into();
+    }
+
+    let config = request
+        .guard::<&State<Config>>()
+        .await
+        .expect("Failed to load config");
+
+    let token_cache = request
+        .guard::<&State<TokenCache>>()
+        .await
+        .expect("TokenCache was not configured");
+
+    let index_access_required = index_access_policy == IndexAccessPolicy::Required;
+
+    if let Some(CachedToken {
+        user: CachedUser::Github(info),
+        permission,
+    }) = token_cache.get(&token, AccessType::WRITE, index_access_required)
+    {
+        if let Err(err) = check_user_blocklist(request, &info) {
+            return err.into();
+        }
+
+        if !index_access_required || permission.is_some() {
+            record_github_user(&info);
+            return Outcome::Success(AccessType::construct(info, permission));
+        }
+    }
+
+    let github = request
+        .guard::<&State<GithubClient>>()
+        .await
+        .expect("GithubClient was not configured");
+    let client = github.identity();
+
+    // Tokens that are already cached keep working while the registry waits
+    // for its rate limit to reset; only new ones would need GitHub.
+    let rate_limit = request
+        .guard::<&State<GithubRateLimit>>()
+        .await
+        .expect("GithubRateLimit was not configured");
+
+    if let Err(err) = rate_limit.check() {
+        return err.into();
+    }
+
+    let retry = GithubRetry::new(config.github_retries);
+
+    let main_host = GithubHost {
+        provider: None,
+        api_base: config.github_api_base(),
+        client_id,
+        client_secret,
+    };
+    let hosts = std::iter::once(main_host).chain(config.github_providers.iter().map(|provider| {
+        GithubHost {
+            provider: Some(provider.name.as_str()),
+            api_base: provider.api_base(),
+            client_id: &provider.client_id,
+            client_secret: &provider.client_secret,
+        }
+    }));
+
+    // The token is tried on each host in turn. Being turned away by one only
+    // means it might belong to another, but anything else, like a host being
+    // down, stops the search so that it can't be mistaken for a bad token.
+    let mut identified = None;
+    let mut auth_failure = None;
+
+    for host in hosts {
+        match identify_github_user(request, &client, &retry, &host, &token).await {
+            Ok(github_info) => {
+                identified = Some((github_info, host.api_base));
+                break;
+            }
+            Err(err) if err.http_status() == Status::Unauthorized => {
+                // The main host's answer is the one worth reporting.
+                auth_failure.get_or_insert(err);
+            }
+            Err(err) => return err.into(),
+        }
+    }
+
+    let (mut github_info, api_base) = match (identified, auth_failure) {
+        (Some(identified), _) => identified,
+        (None, Some(err)) => return err.into(),
+        (None, None) => unreachable!("the main GitHub host is always tried"),
+    };
+
+    record_github_user(&github_info);
+
+    if let Err(err) = check_user_blocklist(request, &github_info) {
+        return err.into();
+    }
+
+    if AccessType::WRITE {
+        if let Some(allowed_domains) = &config.allowed_email_domains {
+            if let Err(err) = verify_email_domain(&client, &api_base, &token, allowed_domains).await
+            {
+                return err.into();
+            }
+        }
+
+        // Only needed if the user claims a scope, but checked up front so
+        // that it's remembered with the token.
+        if config.bootstrap.require_verified_email {
+            match github_emails(&client, &api_base, &token).await {
+                Ok(emails) => {
+                    let verified = emails
+                        .map(|emails| emails.iter().any(|email| email.verified))
+                        .unwrap_or(false);
+                    github_info.verified_email = Some(verified);
+                }
+                Err(err) => return err.into(),
+            }
+        }
+    }
+
+    let mut permission = None;
+
+    if index_access_required {
+        let username = github_info.login();
+
+        let (index_api_base, repo_api) = match index_repo_api(config) {
+            Some(index_api) => index_api,
+            None => {
+                return format_err!(
+                    "The registry's index URL isn't a GitHub repository, so GitHub permissions \
+                     can't be checked"
+                )
+                .status(Status::InternalServerError)
+                .code("registry_misconfigured")
+                .into();
+            }
+        };
+
+        // This check is given its own, usually shorter, timeouts so a slow
+        // GitHub can't hold on to a worker for long.
+        let client = github.permission();
+
+        let github_app = request
+            .guard::<&State<Option<GithubApp>>>()
+            .await
+            .expect("GithubApp was not configured");
+
+        // A GitHub App's installation tokens are preferred over the static
+        // token when both are configured.
+        let token = match (github_app.inner(), &config.github_token) {
+            (Some(github_app), _) => {
+                match github_app
+                    .installation_token(request, &client, &retry, &index_api_base, &repo_api)
+                    .await
+                {
+                    Ok(token) => token,
+                    Err(err) => return err.into(),
+                }
+            }
+            (None, Some(token)) => token.clone(),
+            (None, None) => {
+                return format_err!(
+                    "The registry needs a GitHub token or GitHub App to check GitHub permissions"
+                )
+                .status(Status::InternalServerError)
+                .code("registry_misconfigured")
+                .into();
+            }
+        };
+
+        let index_permission = github_index_permission(
+            request,
+            &client,
+            &retry,
+            &repo_api,
+            username,
+            &token,
+            config.minimum_index_permission(AccessType::WRITE),
+        )
+        .await;
+
+        permission = match index_permission {
+            Ok(index_permission) => Some(index_permission),
+            Err(err) => return err.into(),
+        };
+    }
+
+    token_cache.insert(
+        &token,
+        AccessType::WRITE,
+        index_access_required,
+        CachedToken {
+            user: CachedUser::Github(github_info.clone()),
+            permission: permission.clone(),
+        },
+    );
+
+    Outcome::Success(AccessType::construct(github_info, permission))
+}
+
+/// A GitHub host tokens can be checked against, with the OAuth app the
+/// registry has registered there.
+struct GithubHost<'a> {
+    /// The name of one of `github_providers`, or `None` for the main host.
+    provider: Option<&'a str>,
+    api_base: String,
+    client_id: &'a str,
+    client_secret: &'a str,
+}
+
+/// Finds who `token` belongs to on `host`, and checks that it was issued to
+/// the registry's OAuth app there.
+async fn identify_github_user(
+    request: &Request<'_>,
+    client: &TimedClient,
+    retry: &GithubRetry,
+    host: &GithubHost<'_>,
+    token: &str,
+) -> Result<GithubInfo, Error> {
+    let response = retry
+        .send(|| {
+            let response = client
+                .get(format!("{}/user", host.api_base))
+                .header("accept", "application/json")
+                .bearer_auth(token)
+                .send();
+            time_github_call(request, "user", response)
+        })
         .await;
 
-    let github_info = match response {
+    let mut github_info = match response {
         Err(err) => {
-            return format_err!(err).status(Status::InternalServerError).into();
+            return Err(format_err!(err).status(Status::InternalServerError));
+        }
+        Ok(response) if !response.status().is_success() => {
+            let status = response.status();
+
+            if let Some(err) = secondary_rate_limit(response).await {
+                return Err(err);
+            }
+
+            return Err(format_err!("Github auth failed because: {}", status)
+                .status(Status::Unauthorized)
+                .code("github_auth_failed"));
         }
         Ok(response) => match response.json::<GithubInfo>().await {
             Err(err) => {
-                return format_err!("Github auth failed: {}", err)
+                return Err(format_err!("Github auth failed: {}", err)
                     .status(Status::Unauthorized)
-                    .into();
+                    .code("github_auth_failed"));
             }
             Ok(github_info) => github_info,
         },
     };
 
     let mut body = HashMap::new();
-    body.insert("access_token", &token);
+    body.insert("access_token", token);
 
-    let response = client
-        .post(format!(
-            "https://api.github.com/applications/{}/token",
-            client_id
-        ))
-        .header("accept", "application/json")
-        .header("user-agent", "wally")
-        .basic_auth(client_id, Some(client_secret))
-        .json(&body)
-        .send()
+    let response = retry
+        .send(|| {
+            let response = client
+                .post(format!(
+                    "{}/applications/{}/token",
+                    host.api_base, host.client_id
+                ))
+                .header("accept", "application/json")
+                .basic_auth(host.client_id, Some(host.client_secret))
+                .json(&body)
+                .send();
+            time_github_call(request, "check-token", response)
+        })
         .await;
 
     let validated_github_info = match response {
         Err(err) => {
-            return format_err!(err).status(Status::InternalServerError).into();
+            return Err(format_err!(err).status(Status::InternalServerError));
         }
         Ok(response) => {
+            observe_github_rate_limit(request, "check-token", &response);
+
             // If a code 422 (unprocessable entity) is returned, it's a sign of
             // auth failure. Otherwise, we don't know what happened!
             // https://docs.github.com/en/rest/apps/oauth-applications#check-a-token--status-codes
             match response.status() {
                 StatusCode::OK => response.json::<ValidatedGithubInfo>().await,
                 StatusCode::UNPROCESSABLE_ENTITY => {
-                    return anyhow!("GitHub auth was invalid")
+                    return Err(anyhow!("GitHub auth was invalid")
                         .status(Status::Unauthorized)
-                        .into();
+                        .code("github_auth_failed"));
                 }
                 status => {
-                    return format_err!("Github auth failed because: {}", status)
+                    if let Some(err) = secondary_rate_limit(response).await {
+                        return Err(err);
+                    }
+
+                    return Err(format_err!("Github auth failed because: {}", status)
                         .status(Status::UnprocessableEntity)
-                        .into()
+                        .code("github_auth_failed"));
                 }
             }
         }
     };
 
-    if let Err(err) = validated_github_info {
-        return format_err!("Github auth failed: {}", err)
-            .status(Status::Unauthorized)
-            .into()
-    }
+    // GitHub said the token is valid, so a body that can't be read is GitHub
+    // changing its API rather than the user's fault.
+    let validated_github_info = match validated_github_info {
+        Ok(validated_github_info) => validated_github_info,
+        Err(err) => {
+            return Err(
+                format_err!("GitHub's token check gave an unexpected response: {}", err)
+                    .status(Status::BadGateway)
+                    .code("github_unexpected_response"),
+            );
+        }
+    };
 
-    if index_access_policy == IndexAccessPolicy::Required {
-        let config = request
-            .guard::<&State<Config>>()
-            .await
-            .expect("Failed to load config");
+    validated_github_info.check_app(host.client_id)?;
 
-        let username = github_info.login();
+    github_info.provider = host.provider.map(str::to_owned);
+    Ok(github_info)
+}
 
-        // These two lines will panic if the backend config isn't setup correctly
-        let (owner, repo) = extract_github_owner_repo(config.index_url.as_str()).unwrap();
-        let token = config.github_token.clone().unwrap();
-
-        let response = client
-            .get(format!(
-                "https://api.github.com/repos/{owner}/{repo}/collaborators/{username}/permission"
-            ))
-            .header("accept", "application/json")
-            .header("user-agent", "wally")
-            .bearer_auth(token)
-            .send()
-            .await;
+fn record_github_user(info: &GithubInfo) {
+    let span = tracing::Span::current();
+    span.record("login", &info.login());
+    span.record("id", info.id());
+}
 
-        let permission_info = match response {
-            Err(err) => {
-                return format_err!(err).status(Status::InternalServerError).into();
-            }
-            Ok(response) => match response.json::<GithubPermissionInfo>().await {
-                Err(err) => {
-                    return format_err!("Github auth failed: {}", err)
-                        .status(Status::Unauthorized)
-                        .into();
-                }
-                Ok(permission_info) => permission_info,
-            },
-        };
+/// Finds how much access `username` has to the index repository, refusing
+/// them if it's less than `minimum`.
+///
+/// Fine-grained personal access tokens can be refused the permission endpoint
+/// even when they're valid, so a 403 from it falls back to asking whether the
+/// user is a collaborator at all. That doesn't say how much access they have,
+/// so it's only enough when any collaborator is let in.
+async fn github_index_permission(
+    request: &Request<'_>,
+    client: &TimedClient,
+    retry: &GithubRetry,
+    repo_api: &str,
+    username: &str,
+    token: &str,
+    minimum: GithubPermission,
+) -> Result<String, Error> {
+    let response = retry
+        .send(|| {
+            let response = client
+                .get(format!("{repo_api}/collaborators/{username}/permission"))
+                .header("accept", "application/json")
+                .bearer_auth(token)
+                .send();
+            time_github_call(request, "permission", response)
+        })
+        .await
+        .map_err(|err| github_permission_error(username, err))?;
+    observe_github_rate_limit(request, "permission", &response);
 
-        match permission_info.permission() {
-            "admin" | "write" | "read" => {}
-            _ => {
-                return anyhow!("GitHub auth was invalid")
-                    .status(Status::Unauthorized)
-                    .into();
-            }
+    // A secondary rate limit also comes as a 403, and mustn't be taken for a
+    // token that can't use this endpoint. It's always a 429 otherwise.
+    if matches!(
+        response.status(),
+        StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS
+    ) {
+        if let Some(err) = secondary_rate_limit(response).await {
+            return Err(err);
         }
-    }
 
-    Outcome::Success(AccessType::construct(github_info))
-}
+        let response = retry
+            .send(|| {
+                let response = client
+                    .get(format!("{repo_api}/collaborators/{username}"))
+                    .header("accept", "application/json")
+                    .bearer_auth(token)
+                    .send();
+                time_github_call(request, "collaborator", response)
+            })
+            .await
+            .map_err(|err| github_permission_error(username, err))?;
+        observe_github_rate_limit(request, "collaborator", &response);
+
+        let status = response.status();
+
+        if let Some(err) = secondary_rate_limit(response).await {
+            return Err(err);
+        }
+
+        return match is_github_collaborator(status)? {
+            true if minimum == GithubPermission::Read => Ok(COLLABORATOR_PERMISSION.to_owned()),
+            true => Err(format_err!(
+                "GitHub only said whether {} is a collaborator on the index repository, not \
+                 whether they have the {} permission this registry requires",
+                username,
+                minimum
+            )
+            .status(Status::Forbidden)
+            .code("insufficient_permission")),
+            false => Err(not_github_collaborator(username)),
+        };
+    }
+
+    let permission_info = response
+        .json::<GithubPermissionInfo>()
+        .await
+        .map_err(|err| github_permission_error(username, err))?;
+
+    match permission_info.level() {
+        Some(level) if level >= minimum => Ok(permission_info.permission),
+        Some(level) => Err(format_err!(
+            "GitHub user {} has the {} permission on the index repository, but this registry \
+             requires {}",
+            username,
+            level,
+            minimum
+        )
+        .status(Status::Forbidden)
+        .code("insufficient_permission")),
+        None => Err(not_github_collaborator(username)),
+    }
+}
+
+/// The permission recorded for users whose access was only checked with the
+/// collaborator endpoint, which doesn't say how much access they have.
+const COLLABORATOR_PERMISSION: &str = "collaborator";
+
+/// Reads the answer from GitHub's collaborator endpoint, which is 204 for
+/// collaborators and 404 for everyone else. Other answers mean the registry's
+/// own token is the problem, so they aren't blamed on the user.
+pub(crate) fn is_github_collaborator(status: StatusCode) -> Result<bool, Error> {
+    match status {
+        StatusCode::NO_CONTENT => Ok(true),
+        StatusCode::NOT_FOUND => Ok(false),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(format_err!(
+            "The registry's GitHub token isn't allowed to check collaborators on the index \
+             repository. Fine-grained tokens need read access to the repository's metadata."
+        )
+        .status(Status::InternalServerError)
+        .code("registry_misconfigured")),
+        status => Err(
+            format_err!("GitHub collaborator check failed because: {}", status)
+                .status(Status::InternalServerError)
+                .code("github_auth_failed"),
+        ),
+    }
+}
+
+fn not_github_collaborator(username: &str) -> Error {
+    format_err!(
+        "GitHub user {} isn't a collaborator on the index repository",
+        username
+    )
+    .status(Status::Forbidden)
+    .code("not_collaborator")
+}
+
+fn github_permission_error(username: &str, err: reqwest::Error) -> Error {
+    if err.is_timeout() {
+        tracing::warn!(%username, error = %err, "GitHub permission check timed out");
+
+        format_err!("Timed out checking GitHub permissions, try again later")
+            .status(Status::GatewayTimeout)
+            .code("github_timeout")
+    } else if err.is_decode() {
+        format_err!("Github auth failed: {}", err)
+            .status(Status::Unauthorized)
+            .code("github_auth_failed")
+    } else {
+        format_err!(err).status(Status::InternalServerError)
+    }
+}
+
+async fn verify_gitlab<AccessType: OAuthAccessor>(
+    request: &Request<'_>,
+    client_id: &str,
+    instance_url: &Url,
+    index_access_policy: IndexAccessPolicy,
+) -> Outcome<AccessType, Error> {
+    let token: String = match request.headers().get_one("authorization") {
+        Some(key) if key.starts_with("Bearer ") => (key[6..].trim()).to_owned(),
+        _ => {
+            return format_err!("GitLab auth required")
+                .status(Status::Unauthorized)
+                .code("auth_required")
+                .into();
+        }
+    };
+
+    if let Err(err) = check_token_blocklist(request, &token) {
+        return err.into();
+    }
+
+    let config = request
+        .guard::<&State<Config>>()
+        .await
+        .expect("Failed to load config");
+
+    let token_cache = request
+        .guard::<&State<TokenCache>>()
+        .await
+        .expect("TokenCache was not configured");
 
+    let index_access_required = index_access_policy == IndexAccessPolicy::Required;
+
+    if let Some(CachedToken {
+        user: CachedUser::GitLab(gitlab_info),
+        ..
+    }) = token_cache.get(&token, AccessType::WRITE, index_access_required)
+    {
+        if let Err(err) = check_gitlab_user_blocklist(request, &gitlab_info) {
+            return err.into();
+        }
+
+        return Outcome::Success(AccessType::construct_gitlab(gitlab_info));
+    }
+
+    let project = match index_access_required {
+        true => match gitlab_project_id(instance_url, &config.index_url) {
+            Some(project) => Some(project),
+            None => {
+                return format_err!(
+                    "The registry's index URL isn't a project on {}, so GitLab permissions \
+                     can't be checked",
+                    instance_url
+                )
+                .status(Status::InternalServerError)
+                .code("registry_misconfigured")
+                .into();
+            }
+        },
+        false => None,
+    };
+
+    let github = request
+        .guard::<&State<GithubClient>>()
+        .await
+        .expect("GithubClient was not configured");
+    let client = github.identity();
+
+    let retry = GithubRetry::new(config.github_retries);
+
+    let response = retry
+        .send(|| {
+            client
+                .get(gitlab_url(instance_url, "api/v4/user"))
+                .header("accept", "application/json")
+                .bearer_auth(&token)
+                .send()
+        })
+        .await;
+
+    let gitlab_info = match response {
+        Err(err) => {
+            return format_err!(err).status(Status::InternalServerError).into();
+        }
+        Ok(response) => match response.error_for_status() {
+            Err(err) => {
+                return format_err!("GitLab auth failed: {}", err)
+                    .status(Status::Unauthorized)
+                    .code("gitlab_auth_failed")
+                    .into();
+            }
+            Ok(response) => match response.json::<GitLabInfo>().await {
+                Err(err) => {
+                    return format_err!("GitLab auth failed: {}", err)
+                        .status(Status::Unauthorized)
+                        .code("gitlab_auth_failed")
+                        .into();
+                }
+                Ok(gitlab_info) => gitlab_info,
+            },
+        },
+    };
+
+    if let Err(err) = check_gitlab_user_blocklist(request, &gitlab_info) {
+        return err.into();
+    }
+
+    // Make sure the token was issued to this registry's OAuth application,
+    // rather than being any token for the user.
+    let response = retry
+        .send(|| {
+            client
+                .get(gitlab_url(instance_url, "oauth/token/info"))
+                .header("accept", "application/json")
+                .bearer_auth(&token)
+                .send()
+        })
+        .await;
+
+    let token_info = match response {
+        Err(err) => {
+            return format_err!(err).status(Status::InternalServerError).into();
+        }
+        Ok(response) => match response.status() {
+            StatusCode::OK => response.json::<GitLabTokenInfo>().await,
+            StatusCode::UNAUTHORIZED => {
+                return anyhow!("GitLab auth was invalid")
+                    .status(Status::Unauthorized)
+                    .code("gitlab_auth_failed")
+                    .into();
+            }
+            status => {
+                return format_err!("GitLab auth failed because: {}", status)
+                    .status(Status::UnprocessableEntity)
+                    .code("gitlab_auth_failed")
+                    .into()
+            }
+        },
+    };
+
+    match token_info {
+        Ok(GitLabTokenInfo {
+            application: Some(application),
+        }) if constant_time_eq(application.uid.as_bytes(), client_id.as_bytes()) => {}
+        Ok(_) => {
+            return anyhow!("GitLab token was not issued to this registry")
+                .status(Status::Unauthorized)
+                .code("gitlab_auth_failed")
+                .into();
+        }
+        Err(err) => {
+            return format_err!("GitLab auth failed: {}", err)
+                .status(Status::Unauthorized)
+                .code("gitlab_auth_failed")
+                .into();
+        }
+    }
+
+    if let Some(project) = project {
+        let client = github.permission();
+
+        // Asked with the user's own token, which can only see the members of
+        // projects the user can see.
+        let response = retry
+            .send(|| {
+                client
+                    .get(gitlab_url(
+                        instance_url,
+                        &format!(
+                            "api/v4/projects/{}/members/all/{}",
+                            project,
+                            gitlab_info.id()
+                        ),
+                    ))
+                    .header("accept", "application/json")
+                    .bearer_auth(&token)
+                    .send()
+            })
+            .await;
+
+        let member = match response {
+            Ok(response) => match gitlab_membership_error(response.status()) {
+                Some(err) => return err.into(),
+                None => response.json::<GitLabMember>().await,
+            },
+            Err(err) => Err(err),
+        };
+
+        let member = match member {
+            Ok(member) => member,
+            Err(err) if err.is_timeout() => {
+                tracing::warn!(
+                    username = %gitlab_info.username(),
+                    error = %err,
+                    "GitLab membership check timed out"
+                );
+
+                return format_err!("Timed out checking GitLab permissions, try again later")
+                    .status(Status::GatewayTimeout)
+                    .code("gitlab_timeout")
+                    .into();
+            }
+            Err(err) if err.is_decode() => {
+                return format_err!("GitLab auth failed: {}", err)
+                    .status(Status::Unauthorized)
+                    .code("gitlab_auth_failed")
+                    .into();
+            }
+            Err(err) => {
+                return format_err!(err).status(Status::InternalServerError).into();
+            }
+        };
+
+        if member.access_level < GITLAB_REPORTER_ACCESS {
+            return anyhow!("GitLab auth was invalid")
+                .status(Status::Forbidden)
+                .code("gitlab_auth_failed")
+                .into();
+        }
+    }
+
+    token_cache.insert(
+        &token,
+        AccessType::WRITE,
+        index_access_required,
+        CachedToken {
+            user: CachedUser::GitLab(gitlab_info.clone()),
+            permission: None,
+        },
+    );
+
+    Outcome::Success(AccessType::construct_gitlab(gitlab_info))
+}
+
+/// Reads the status of GitLab's project member endpoint, giving the error to
+/// refuse with for anything but a member. The check is made with the user's
+/// own token, which has already identified them, so being turned away means
+/// the registry's OAuth application doesn't ask for enough scope.
+pub(crate) fn gitlab_membership_error(status: StatusCode) -> Option<Error> {
+    match status {
+        StatusCode::OK => None,
+        StatusCode::NOT_FOUND => Some(
+            anyhow!("You are not a member of this registry's GitLab project")
+                .status(Status::Forbidden)
+                .code("not_project_member"),
+        ),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Some(
+            format_err!(
+                "The registry's GitLab application isn't allowed to check project members. It \
+                 needs the read_api scope."
+            )
+            .status(Status::InternalServerError)
+            .code("registry_misconfigured"),
+        ),
+        status => Some(
+            format_err!("GitLab membership check failed because: {}", status)
+                .status(Status::InternalServerError)
+                .code("gitlab_auth_failed"),
+        ),
+    }
+}
+
+/// Build a URL on a GitLab instance, which may be hosted under a path.
+fn gitlab_url(instance_url: &Url, path: &str) -> String {
+    format!("{}/{}", instance_url.as_str().trim_end_matches('/'), path)
+}
+
+/// GitLab accepts a project's URL-encoded path wherever it wants a project
+/// id, so work that out from the index URL.
+pub(crate) fn gitlab_project_id(instance_url: &Url, index_url: &Url) -> Option<String> {
+    if instance_url.host_str() != index_url.host_str() {
+        return None;
+    }
+
+    let path = index_url
+        .path()
+        .strip_prefix(instance_url.path().trim_end_matches('/'))?
+        .trim_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+
+    if path.split('/').count() < 2 {
+        return None;
+    }
+
+    Some(path.replace('/', "%2F"))
+}
+
+/// Lists the email addresses on the GitHub account owning `token`, or `None`
+/// if the token isn't allowed to read them.
+async fn github_emails(
+    client: &TimedClient,
+    api_base: &str,
+    token: &str,
+) -> Result<Option<Vec<GithubEmail>>, Error> {
+    let response = client
+        .get(format!("{}/user/emails", api_base))
+        .header("accept", "application/json")
+        .bearer_auth(token)
+        .send()
+        .await
+        .map_err(|err| format_err!(err).status(Status::InternalServerError))?;
+
+    // Tokens created before this registry required emails may not have the
+    // `user:email` scope, in which case GitHub refuses to list them.
+    if matches!(
+        response.status(),
+        StatusCode::FORBIDDEN | StatusCode::NOT_FOUND
+    ) {
+        return Ok(None);
+    }
+
+    let emails = response.json::<Vec<GithubEmail>>().await.map_err(|err| {
+        format_err!("Github auth failed: {}", err)
+            .status(Status::Unauthorized)
+            .code("github_auth_failed")
+    })?;
+
+    Ok(Some(emails))
+}
+
+/// Checks that the GitHub account owning `token` has at least one verified
+/// email address on one of the allowed domains.
+async fn verify_email_domain(
+    client: &TimedClient,
+    api_base: &str,
+    token: &str,
+    allowed_domains: &[String],
+) -> Result<(), Error> {
+    let emails = match github_emails(client, api_base, token).await? {
+        Some(emails) => emails,
+        None => {
+            return Err(format_err!(
+                "Could not read the email addresses on your GitHub account. This registry \
+                 requires a verified email address from one of these domains: {}. Try logging \
+                 in again with `wally login`.",
+                allowed_domains.join(", ")
+            )
+            .status(Status::Unauthorized)
+            .code("email_unreadable"))
+        }
+    };
+
+    let has_allowed_email = emails
+        .iter()
+        .filter(|email| email.verified)
+        .any(|email| email_has_domain(&email.email, allowed_domains));
+
+    if has_allowed_email {
+        Ok(())
+    } else {
+        Err(format_err!(
+            "Publishing to this registry requires a verified email address from one of these \
+             domains: {}. Add and verify one on your GitHub account.",
+            allowed_domains.join(", ")
+        )
+        .status(Status::Forbidden)
+        .code("email_not_allowed"))
+    }
+}
+
+pub(crate) fn email_has_domain(email: &str, allowed_domains: &[String]) -> bool {
+    match email.rsplit_once('@') {
+        Some((_, domain)) => allowed_domains
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(domain)),
+        None => false,
+    }
+}
+
+fn record_auth(request: &Request<'_>, auth: &AuthMode, result: &'static str) {
+    if let Some(metrics) = request.rocket().state::<Arc<Metrics>>() {
+        metrics.record_auth(auth.kind(), result);
+    }
+}
 
 pub enum ReadAccess {
     Public,
-    ApiKey,
-    #[allow(dead_code)]
+    /// Access with an API key, limited to `scopes` if the key is only allowed
+    /// to read some of them.
+    ApiKey {
+        scopes: Option<Vec<String>>,
+    },
     Github(GithubInfo),
+    GitLab(GitLabInfo),
+    /// Access with a read token issued by this registry, limited to the
+    /// scopes it claims.
+    Token(ReadTokenClaims),
 }
 
-impl GithubAccessor for ReadAccess {
-    fn construct(info: GithubInfo) -> Self {
+impl ReadAccess {
+    pub fn can_read_scope(&self, scope: &str) -> bool {
+        match self {
+            ReadAccess::ApiKey {
+                scopes: Some(scopes),
+            }
+            | ReadAccess::Token(ReadTokenClaims { scopes, .. }) => {
+                scopes.iter().any(|allowed| allowed == scope)
+            }
+            _ => true,
+        }
+    }
+
+    pub fn check_scope(&self, scope: &str) -> Result<(), Error> {
+        if self.can_read_scope(scope) {
+            return Ok(());
+        }
+
+        let credential = match self {
+            ReadAccess::Token(_) => "read token",
+            _ => "API key",
+        };
+
+        Err(
+            format_err!("this {} can't read packages in scope {}", credential, scope)
+                .status(Status::Forbidden)
+                .code("scope_not_readable"),
+        )
+    }
+}
+
+/// Checks a request's bearer token as a read token, if the registry issues
+/// them. Tokens that aren't JWTs are left for the registry's auth mode.
+fn verify_read_token(request: &Request<'_>, config: &Config) -> Option<Outcome<ReadAccess, Error>> {
+    let secret = &config.read_tokens.as_ref()?.secret;
+    let token = bearer_token(request)?;
+
+    let claims = match read_tokens::verify(secret, token) {
+        Ok(claims) => claims,
+        Err(ReadTokenError::NotAToken) => return None,
+        Err(ReadTokenError::Expired) => {
+            return Some(
+                format_err!("This read token has expired. Ask for a new one.")
+                    .status(Status::Unauthorized)
+                    .code("read_token_expired")
+                    .into(),
+            )
+        }
+        Err(ReadTokenError::Invalid) => {
+            return Some(
+                format_err!("Invalid read token")
+                    .status(Status::Unauthorized)
+                    .code("invalid_read_token")
+                    .into(),
+            )
+        }
+    };
+
+    // Tokens can't be recalled once they're issued, so leaked ones are
+    // revoked by blocking them.
+    if let Err(err) = check_token_blocklist(request, token) {
+        return Some(err.into());
+    }
+
+    Some(Outcome::Success(ReadAccess::Token(claims)))
+}
+
+impl OAuthAccessor for ReadAccess {
+    const WRITE: bool = false;
+
+    fn construct(info: GithubInfo, _permission: Option<String>) -> Self {
         ReadAccess::Github(info)
     }
+
+    fn construct_gitlab(info: GitLabInfo) -> Self {
+        ReadAccess::GitLab(info)
+    }
 }
 
 #[rocket::async_trait]
@@ -294,57 +1588,322 @@ impl<'r> FromRequest<'r> for ReadAccess {
             .await
             .expect("AuthMode was not configured");
 
-        match &config.auth {
-            AuthMode::Unauthenticated => Outcome::Success(ReadAccess::Public),
-            AuthMode::GithubOAuth { .. } => Outcome::Success(ReadAccess::Public),
-            AuthMode::GithubOAuthPrivate {
+        let authenticate = async {
+            match verify_read_token(request, config) {
+                Some(outcome) => outcome,
+                None => read_auth_mode(request, config).await,
+            }
+        };
+
+        let outcome = throttle_auth(request, authenticate, |access| {
+            !matches!(access, ReadAccess::Public)
+        })
+        .await;
+
+        let result = match &outcome {
+            Outcome::Success(ReadAccess::Public) => "anonymous",
+            Outcome::Success(_) => "success",
+            _ => "failure",
+        };
+        record_auth(request, config.read_auth(), result);
+
+        rate_limit(request, outcome, AccessKind::Read, |access| match access {
+            ReadAccess::Public => Identity::ip(request),
+            ReadAccess::ApiKey { .. } | ReadAccess::Token(_) => Identity::api_key(request),
+            ReadAccess::Github(github_info) => Identity::User(github_info.owner_id()),
+            ReadAccess::GitLab(gitlab_info) => Identity::User(OwnerId::new(*gitlab_info.id())),
+        })
+        .await
+    }
+}
+
+/// Checks a request for read access the way the registry's auth mode says to.
+async fn read_auth_mode(request: &Request<'_>, config: &Config) -> Outcome<ReadAccess, Error> {
+    match config.read_auth() {
+        AuthMode::Unauthenticated => Outcome::Success(ReadAccess::Public),
+        AuthMode::GithubOAuth { .. } => Outcome::Success(ReadAccess::Public),
+        AuthMode::GithubOAuthPrivate {
+            client_id,
+            client_secret,
+        } => {
+            verify_github::<ReadAccess>(
+                request,
                 client_id,
                 client_secret,
-            } => verify_github::<ReadAccess>(request, client_id, client_secret, IndexAccessPolicy::Required).await,
-            AuthMode::ApiKey(key) => match_api_key(request, key, ReadAccess::ApiKey),
-            AuthMode::DoubleApiKey { read, .. } => match read {
-                None => Outcome::Success(ReadAccess::Public),
-                Some(key) => match_api_key(request, key, ReadAccess::ApiKey),
-            },
+                IndexAccessPolicy::Required,
+            )
+            .await
         }
+        AuthMode::GitLab { private: false, .. } => Outcome::Success(ReadAccess::Public),
+        AuthMode::TrustedPublishing { .. } => Outcome::Success(ReadAccess::Public),
+        AuthMode::GitLab {
+            client_id,
+            instance_url,
+            private: true,
+            ..
+        } => {
+            verify_gitlab::<ReadAccess>(
+                request,
+                client_id,
+                instance_url,
+                IndexAccessPolicy::Required,
+            )
+            .await
+        }
+        AuthMode::ApiKey(keys) => match_api_key(
+            request,
+            keys.as_slice(),
+            ReadAccess::ApiKey { scopes: None },
+        ),
+        AuthMode::DoubleApiKey {
+            read, read_scopes, ..
+        } => match read {
+            None => Outcome::Success(ReadAccess::Public),
+            Some(keys) => match_api_key(
+                request,
+                keys.as_slice(),
+                ReadAccess::ApiKey {
+                    scopes: read_scopes.clone(),
+                },
+            ),
+        },
     }
 }
 
 pub enum WriteAccess {
     ApiKey,
-    Github(GithubInfo),
+    /// Access with a GitHub token. `permission` is the user's permission on
+    /// the index repository (`admin`, `write`, or `read`), which is only
+    /// checked when the registry is private.
+    Github {
+        info: GithubInfo,
+        permission: Option<String>,
+    },
+    GitLab(GitLabInfo),
+    /// Access from a CI job with an OIDC token, limited to the scopes its
+    /// repository is trusted with.
+    TrustedPublisher(TrustedPublisherInfo),
 }
 
-impl GithubAccessor for WriteAccess {
-    fn construct(info: GithubInfo) -> Self {
-        WriteAccess::Github(info)
+impl OAuthAccessor for WriteAccess {
+    const WRITE: bool = true;
+
+    fn construct(info: GithubInfo, permission: Option<String>) -> Self {
+        WriteAccess::Github { info, permission }
+    }
+
+    fn construct_gitlab(info: GitLabInfo) -> Self {
+        WriteAccess::GitLab(info)
     }
 }
 
 impl WriteAccess {
-    pub fn can_write_package(
+    /// Who was granted access, for recording what they did.
+    pub fn actor(&self) -> &str {
+        match self {
+            WriteAccess::ApiKey => "api-key",
+            WriteAccess::Github { info, .. } => info.login(),
+            WriteAccess::GitLab(gitlab_info) => gitlab_info.username(),
+            WriteAccess::TrustedPublisher(info) => &info.repository,
+        }
+    }
+
+    /// The GitHub user's permission on the index repository, like `admin` or
+    /// `write`, for routes that need more than write access to a scope. Only
+    /// known for GitHub users of private registries.
+    pub fn github_permission(&self) -> Option<&str> {
+        match self {
+            WriteAccess::Github { permission, .. } => permission.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// The id of the user who was granted access, which is what scope owners
+    /// are recorded by. API keys and CI jobs don't belong to a user.
+    pub fn user_id(&self) -> Option<OwnerId> {
+        match self {
+            WriteAccess::ApiKey | WriteAccess::TrustedPublisher(_) => None,
+            WriteAccess::Github { info, .. } => Some(info.owner_id()),
+            WriteAccess::GitLab(gitlab_info) => Some(OwnerId::new(*gitlab_info.id())),
+        }
+    }
+
+    pub async fn can_write_package(
         &self,
         package_id: &PackageId,
         index: &PackageIndex,
-    ) -> anyhow::Result<bool> {
-        let scope = package_id.name().scope();
-
-        let has_permission = match self {
-            WriteAccess::ApiKey => true,
-            WriteAccess::Github(github_info) => {
-                match index.is_scope_owner(scope, github_info.id())? {
-                    true => true,
-                    // Only grant write access if the username matches the scope AND the scope has no existing owners
-                    false => {
-                        github_info.login().to_lowercase() == scope
-                            && index.get_scope_owners(scope)?.is_empty()
-                    }
+        teams: &dyn TeamMembership,
+        bootstrap: &BootstrapPolicy,
+    ) -> Result<bool, Error> {
+        self.can_write_scope(package_id.name().scope(), index, teams, bootstrap)
+            .await
+    }
+
+    pub async fn can_write_scope(
+        &self,
+        scope: &str,
+        index: &PackageIndex,
+        teams: &dyn TeamMembership,
+        bootstrap: &BootstrapPolicy,
+    ) -> Result<bool, Error> {
+        Ok(self
+            .write_permission(scope, index, teams, bootstrap)
+            .await?
+            .is_some())
+    }
+
+    /// Works out why this user can write to `scope`, if they can at all. A
+    /// GitHub user claiming a scope who doesn't meet the `bootstrap` policy
+    /// gets a 403 saying why, rather than just not being allowed.
+    pub async fn write_permission(
+        &self,
+        scope: &str,
+        index: &PackageIndex,
+        teams: &dyn TeamMembership,
+        bootstrap: &BootstrapPolicy,
+    ) -> Result<Option<WritePermission>, Error> {
+        // CI jobs can only ever write to the scopes they're trusted with, and
+        // never claim one.
+        if let WriteAccess::TrustedPublisher(info) = self {
+            return Ok(info
+                .can_write_scope(scope)
+                .then(|| WritePermission::TrustedPublisher));
+        }
+
+        let user_id = match self.user_id() {
+            None => return Ok(Some(WritePermission::ApiKey)),
+            Some(user_id) => user_id,
+        };
+
+        if index.is_scope_owner(scope, &user_id)? {
+            return Ok(Some(WritePermission::Owner));
+        }
+
+        // Teams and scope names go by login, and logins are only unique on
+        // one host, so users from the registry's other GitHub hosts can only
+        // write to scopes they've been made an owner of.
+        if matches!(self, WriteAccess::Github { info, .. } if info.provider().is_some()) {
+            return Ok(None);
+        }
+
+        let scope_teams = index.get_scope_teams(scope)?;
+
+        // Teams are a GitHub feature, so only GitHub users can be in one.
+        if let WriteAccess::Github { info, .. } = self {
+            for team in &scope_teams {
+                if teams.is_member(team, info.login()).await? {
+                    return Ok(Some(WritePermission::Team(team.clone())));
                 }
             }
+        }
+
+        // Only grant write access if the username matches the scope AND the scope has no existing owners
+        let can_bootstrap = self.actor().eq_ignore_ascii_case(scope)
+            && index.get_scope_owners(scope)?.is_empty()
+            && scope_teams.is_empty();
+
+        if !can_bootstrap {
+            return Ok(None);
+        }
+
+        if let WriteAccess::Github { info, .. } = self {
+            check_bootstrap_policy(bootstrap, info, scope)?;
+        }
+
+        Ok(Some(WritePermission::Bootstrap))
+    }
+}
+
+/// Checks that a GitHub account is allowed to claim the unowned scope matching
+/// its login, so that throwaway accounts can't squat on scopes.
+fn check_bootstrap_policy(
+    policy: &BootstrapPolicy,
+    info: &GithubInfo,
+    scope: &str,
+) -> Result<(), Error> {
+    if let Some(min_age) = policy.min_account_age_days {
+        let old_enough = match info.account_age_days() {
+            Some(age) => age >= 0 && age as u64 >= min_age,
+            None => false,
         };
 
-        Ok(has_permission)
+        if !old_enough {
+            return Err(format_err!(
+                "Claiming the scope {} requires a GitHub account that's at least {} days old. \
+                 Ask an owner of the scope to add you instead.",
+                scope,
+                min_age
+            )
+            .status(Status::Forbidden)
+            .code("bootstrap_not_allowed"));
+        }
     }
+
+    if policy.require_verified_email && info.verified_email != Some(true) {
+        return Err(format_err!(
+            "Claiming the scope {} requires a verified email address on your GitHub account. \
+             If you have one, try logging in again with `wally login` so that the registry can \
+             see it.",
+            scope
+        )
+        .status(Status::Forbidden)
+        .code("bootstrap_not_allowed"));
+    }
+
+    Ok(())
+}
+
+/// Checks that the registry lets packages be published to `scope` at all,
+/// whoever is asking, using `allowed_scopes` and `denied_scopes`.
+pub fn check_scope_allowed(config: &Config, scope: &str) -> Result<(), Error> {
+    let listed = |scopes: &[String]| {
+        scopes
+            .iter()
+            .any(|listed| listed.eq_ignore_ascii_case(scope))
+    };
+
+    if listed(&config.denied_scopes) {
+        return Err(format_err!(
+            "the scope {} is reserved, so nothing can be published to it",
+            scope
+        )
+        .status(Status::Forbidden)
+        .code("scope_reserved"));
+    }
+
+    if !config.allowed_scopes.is_empty() && !listed(&config.allowed_scopes) {
+        return Err(format_err!(
+            "this registry only allows publishing to approved scopes, and {} isn't \
+             one of them",
+            scope
+        )
+        .status(Status::Forbidden)
+        .code("scope_not_allowed"));
+    }
+
+    Ok(())
+}
+
+/// Why a user was allowed to write to a scope.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "team", rename_all = "kebab-case")]
+pub enum WritePermission {
+    /// API keys can write to every scope.
+    ApiKey,
+
+    /// The user is one of the scope's owners.
+    Owner,
+
+    /// The user is a member of a team that owns the scope.
+    Team(String),
+
+    /// The scope has no owners yet and the user's login matches it, so they
+    /// get to claim it.
+    Bootstrap,
+
+    /// A CI job from a repository that `trusted_publishers` lets write to
+    /// the scope.
+    TrustedPublisher,
 }
 
 #[rocket::async_trait]
@@ -357,22 +1916,237 @@ impl<'r> FromRequest<'r> for WriteAccess {
             .await
             .expect("AuthMode was not configured");
 
-        match &config.auth {
-            AuthMode::Unauthenticated => format_err!("Invalid API key for write access")
-                .status(Status::Unauthorized)
-                .into(),
-            AuthMode::ApiKey(key) => match_api_key(request, key, WriteAccess::ApiKey),
-            AuthMode::DoubleApiKey { write, .. } => {
-                match_api_key(request, write, WriteAccess::ApiKey)
+        let outcome = throttle_auth(request, write_auth_mode(request, config), |_| true).await;
+
+        let result = match &outcome {
+            Outcome::Success(_) => "success",
+            _ => "failure",
+        };
+        record_auth(request, config.write_auth(), result);
+
+        rate_limit(request, outcome, AccessKind::Write, |access| {
+            match access.user_id() {
+                Some(user_id) => Identity::User(user_id),
+                None => Identity::api_key(request),
             }
-            AuthMode::GithubOAuth {
+        })
+        .await
+    }
+}
+
+/// Write access for a request that changes the registry, like publishing or
+/// yanking. These are turned away while the registry is in maintenance mode,
+/// while endpoints that only use write access to decide what to show, like a
+/// scope's activity, keep working.
+pub struct MutatingAccess(WriteAccess);
+
+impl MutatingAccess {
+    pub fn into_inner(self) -> WriteAccess {
+        self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for MutatingAccess {
+    type Error = Error;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Error> {
+        // Check this first so that we don't bother GitHub while writes are off.
+        if let Err(err) = check_maintenance(request).await {
+            return err.into();
+        }
+
+        request.guard::<WriteAccess>().await.map(MutatingAccess)
+    }
+}
+
+async fn check_maintenance(request: &Request<'_>) -> Result<(), Error> {
+    let maintenance = request
+        .guard::<&State<MaintenanceMode>>()
+        .await
+        .expect("MaintenanceMode was not configured");
+
+    if maintenance.is_enabled() {
+        return Err(
+            format_err!("The registry is in read-only maintenance mode. Try again later.")
+                .status(Status::ServiceUnavailable)
+                .code("maintenance"),
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks a request for write access the way the registry's auth mode says to.
+async fn write_auth_mode(request: &Request<'_>, config: &Config) -> Outcome<WriteAccess, Error> {
+    match config.write_auth() {
+        AuthMode::Unauthenticated => format_err!("Invalid API key for write access")
+            .status(Status::Unauthorized)
+            .code("invalid_api_key")
+            .into(),
+        AuthMode::ApiKey(keys) => match_api_key(request, keys.as_slice(), WriteAccess::ApiKey),
+        AuthMode::DoubleApiKey { read, write, .. } => {
+            // A read key is a valid key, it just isn't allowed to write.
+            let read_only = match (bearer_token(request), read) {
+                (Some(key), Some(read)) => {
+                    key_matches(read.as_slice(), key) && !key_matches(write.as_slice(), key)
+                }
+                _ => false,
+            };
+
+            match read_only {
+                true => format_err!("This API key can only read packages")
+                    .status(Status::Forbidden)
+                    .code("read_only_key")
+                    .into(),
+                false => match_api_key(request, write.as_slice(), WriteAccess::ApiKey),
+            }
+        }
+        AuthMode::GithubOAuth {
+            client_id,
+            client_secret,
+        } => {
+            verify_github::<WriteAccess>(
+                request,
                 client_id,
                 client_secret,
-            } => verify_github::<WriteAccess>(request, client_id, client_secret, IndexAccessPolicy::Optional).await,
-            AuthMode::GithubOAuthPrivate {
+                IndexAccessPolicy::Optional,
+            )
+            .await
+        }
+        AuthMode::GithubOAuthPrivate {
+            client_id,
+            client_secret,
+        } => {
+            verify_github::<WriteAccess>(
+                request,
                 client_id,
                 client_secret,
-            } => verify_github::<WriteAccess>(request, client_id, client_secret, IndexAccessPolicy::Required).await,
+                IndexAccessPolicy::Required,
+            )
+            .await
+        }
+        AuthMode::GitLab {
+            client_id,
+            instance_url,
+            private,
+            ..
+        } => {
+            let index_access_policy = match private {
+                true => IndexAccessPolicy::Required,
+                false => IndexAccessPolicy::Optional,
+            };
+
+            verify_gitlab::<WriteAccess>(request, client_id, instance_url, index_access_policy)
+                .await
+        }
+        AuthMode::TrustedPublishing { audience, api_key } => match bearer_token(request) {
+            Some(token) if trusted_publishing::is_oidc_token(token) => {
+                verify_trusted_publisher(request, config, audience, token).await
+            }
+            _ => match api_key {
+                Some(keys) => match_api_key(request, keys.as_slice(), WriteAccess::ApiKey),
+                None => format_err!("An OIDC token from a trusted publisher is required")
+                    .status(Status::Unauthorized)
+                    .code("auth_required")
+                    .into(),
+            },
+        },
+    }
+}
+
+async fn verify_trusted_publisher(
+    request: &Request<'_>,
+    config: &Config,
+    audience: &str,
+    token: &str,
+) -> Outcome<WriteAccess, Error> {
+    let github = request
+        .guard::<&State<GithubClient>>()
+        .await
+        .expect("GithubClient was not configured");
+    let keys = request
+        .guard::<&State<IssuerKeys>>()
+        .await
+        .expect("IssuerKeys was not configured");
+
+    match trusted_publishing::verify(config, github, keys, audience, token).await {
+        Ok(info) => Outcome::Success(WriteAccess::TrustedPublisher(info)),
+        Err(err) => err.into(),
+    }
+}
+
+/// Who a request was authenticated as, for `/v1/whoami`. Requests are checked
+/// for read access, or for write access with `?write=true`.
+pub enum Whoami {
+    Read(ReadAccess),
+    Write(WriteAccess),
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Whoami {
+    type Error = Error;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Error> {
+        let write = request
+            .query_value::<bool>("write")
+            .and_then(Result::ok)
+            .unwrap_or(false);
+
+        if write {
+            request.guard::<WriteAccess>().await.map(Whoami::Write)
+        } else {
+            request.guard::<ReadAccess>().await.map(Whoami::Read)
         }
     }
 }
+
+/// Access to the admin API, granted by the registry's admin key.
+pub struct AdminAccess;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminAccess {
+    type Error = Error;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Error> {
+        let config = request
+            .guard::<&State<Config>>()
+            .await
+            .expect("AuthMode was not configured");
+
+        match &config.admin_key {
+            Some(key) => {
+                throttle_auth(
+                    request,
+                    async { compare_api_key(request, std::slice::from_ref(key), AdminAccess) },
+                    |_| true,
+                )
+                .await
+            }
+            None => format_err!("The admin API is not enabled on this registry")
+                .status(Status::NotFound)
+                .code("admin_api_disabled")
+                .into(),
+        }
+    }
+}
+
+/// Admin access for a request that changes the registry, which is turned away
+/// in maintenance mode like [`MutatingAccess`].
+pub struct MutatingAdminAccess;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for MutatingAdminAccess {
+    type Error = Error;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Error> {
+        if let Err(err) = check_maintenance(request).await {
+            return err.into();
+        }
+
+        request
+            .guard::<AdminAccess>()
+            .await
+            .map(|_| MutatingAdminAccess)
+    }
+}
@@ -0,0 +1,196 @@
+//! Slows down guessing of API keys and tokens. Once an IP address, or tokens
+//! that start the same way, fail to authenticate too many times in a window,
+//! further attempts are turned away before they're checked, even with a valid
+//! credential, until enough of the failures have decayed.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::AuthThrottleConfig;
+use crate::token_cache::{Clock, SystemClock};
+
+/// Once this many IPs, prefixes, or tokens are being tracked, those that
+/// don't count any more are forgotten.
+const MAX_ENTRIES: usize = 10_000;
+
+/// How many characters of a token are counted together. Long enough that
+/// unrelated tokens rarely share a count, short enough that someone working
+/// through guesses for one key does.
+const TOKEN_PREFIX_LEN: usize = 8;
+
+/// How long a token that authenticated is exempt for, so that clients that
+/// were already signed in keep working while someone sharing their IP
+/// address is throttled.
+const TRUSTED_FOR: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum FailureKey {
+    Ip(Option<IpAddr>),
+
+    /// The hash of a token's first few characters, so that no part of a
+    /// token is kept as-is.
+    TokenPrefix([u8; 32]),
+}
+
+impl FailureKey {
+    fn for_attempt(ip: Option<IpAddr>, token: &str) -> [Self; 2] {
+        // JWTs, like read tokens and OIDC tokens from CI jobs, all start with
+        // much the same header, so they're told apart by their signature.
+        let counted = match jsonwebtoken::decode_header(token) {
+            Ok(_) => token.rsplit('.').next().unwrap_or(token),
+            Err(_) => token,
+        };
+        let prefix: String = counted.chars().take(TOKEN_PREFIX_LEN).collect();
+
+        [
+            FailureKey::Ip(ip),
+            FailureKey::TokenPrefix(*blake3::hash(prefix.as_bytes()).as_bytes()),
+        ]
+    }
+}
+
+struct Failures {
+    count: f64,
+    updated: Instant,
+}
+
+#[derive(Default)]
+struct State {
+    failures: HashMap<FailureKey, Failures>,
+
+    /// When each token, by its hash, last authenticated.
+    trusted: HashMap<[u8; 32], Instant>,
+}
+
+/// Counts failed authentication attempts by IP address and token prefix.
+/// Each count decays by `max_failures` per `window`, and is cleared when an
+/// attempt succeeds.
+pub struct AuthThrottle {
+    config: AuthThrottleConfig,
+    clock: Arc<dyn Clock>,
+    state: Mutex<State>,
+}
+
+impl AuthThrottle {
+    pub fn new(config: AuthThrottleConfig) -> Self {
+        Self::with_clock(config, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(config: AuthThrottleConfig, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            config,
+            clock,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Whether an attempt with `token` can be checked, or how long to wait
+    /// until one can be.
+    pub fn check(&self, ip: Option<IpAddr>, token: &str) -> Result<(), Duration> {
+        if self.config.max_failures == 0 {
+            return Ok(());
+        }
+
+        let now = self.clock.now();
+        let max_failures = f64::from(self.config.max_failures);
+        let decay_rate = self.decay_rate();
+
+        // Throttling shouldn't take the registry down with it.
+        let state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return Ok(()),
+        };
+
+        if let Some(trusted) = state.trusted.get(&token_hash(token)) {
+            if now.duration_since(*trusted) < TRUSTED_FOR {
+                return Ok(());
+            }
+        }
+
+        let wait = FailureKey::for_attempt(ip, token)
+            .iter()
+            .filter_map(|key| state.failures.get(key))
+            .map(|failures| self.decayed(failures, now))
+            .filter(|count| *count >= max_failures)
+            .map(|count| (count - max_failures + 1.0) / decay_rate)
+            .fold(None, |longest: Option<f64>, wait| {
+                Some(longest.map_or(wait, |longest| longest.max(wait)))
+            });
+
+        match wait {
+            Some(wait) => Err(Duration::from_secs_f64(wait)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn record_failure(&self, ip: Option<IpAddr>, token: &str) {
+        if self.config.max_failures == 0 {
+            return;
+        }
+
+        let now = self.clock.now();
+
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return,
+        };
+
+        if state.failures.len() >= MAX_ENTRIES {
+            state
+                .failures
+                .retain(|_, failures| self.decayed(failures, now) >= 1.0);
+        }
+
+        for key in FailureKey::for_attempt(ip, token) {
+            let failures = state.failures.entry(key).or_insert(Failures {
+                count: 0.0,
+                updated: now,
+            });
+
+            failures.count = self.decayed(failures, now) + 1.0;
+            failures.updated = now;
+        }
+    }
+
+    /// Clears the attempt's counts, and exempts its token from them from now
+    /// on.
+    pub fn record_success(&self, ip: Option<IpAddr>, token: &str) {
+        if self.config.max_failures == 0 {
+            return;
+        }
+
+        let now = self.clock.now();
+
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return,
+        };
+
+        for key in &FailureKey::for_attempt(ip, token) {
+            state.failures.remove(key);
+        }
+
+        if state.trusted.len() >= MAX_ENTRIES {
+            state
+                .trusted
+                .retain(|_, trusted| now.duration_since(*trusted) < TRUSTED_FOR);
+        }
+
+        state.trusted.insert(token_hash(token), now);
+    }
+
+    fn decay_rate(&self) -> f64 {
+        f64::from(self.config.max_failures) / self.config.window.max(1) as f64
+    }
+
+    fn decayed(&self, failures: &Failures, now: Instant) -> f64 {
+        let elapsed = now.duration_since(failures.updated).as_secs_f64();
+        (failures.count - elapsed * self.decay_rate()).max(0.0)
+    }
+}
+
+fn token_hash(token: &str) -> [u8; 32] {
+    *blake3::hash(token.as_bytes()).as_bytes()
+}
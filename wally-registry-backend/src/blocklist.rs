@@ -0,0 +1,127 @@
+//! GitHub accounts and tokens that are turned away no matter what they could
+//! otherwise do, for cutting off a leaked token or a compromised account right
+//! away. Entries are managed through the admin API.
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use anyhow::Context;
+use libwally::package_index::OwnerId;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BlocklistEntries {
+    /// GitHub user ids, namespaced like scope owners for accounts on the
+    /// registry's other GitHub hosts. Logins can be changed, so accounts are
+    /// never blocked by login.
+    #[serde(default)]
+    pub user_ids: BTreeSet<OwnerId>,
+
+    /// Hex-encoded SHA-256 hashes of tokens or API keys, so that the tokens
+    /// themselves are never stored.
+    #[serde(default)]
+    pub token_hashes: BTreeSet<String>,
+}
+
+/// The blocklist lives in Rocket's managed state so that it can be changed
+/// without restarting. With a path, it's saved there on every change and read
+/// back at startup, so that blocks outlive the process.
+pub struct Blocklist {
+    path: Option<PathBuf>,
+    entries: RwLock<BlocklistEntries>,
+}
+
+impl Blocklist {
+    /// A blocklist that's only kept in memory.
+    pub fn new() -> Self {
+        Self {
+            path: None,
+            entries: RwLock::new(BlocklistEntries::default()),
+        }
+    }
+
+    /// Loads the blocklist saved at `path`, or starts an empty one there if
+    /// the file doesn't exist yet.
+    pub fn load(path: PathBuf) -> anyhow::Result<Self> {
+        let entries = match fs_err::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .with_context(|| format!("could not parse blocklist {}", path.display()))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => BlocklistEntries::default(),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Self {
+            path: Some(path),
+            entries: RwLock::new(entries),
+        })
+    }
+
+    pub fn entries(&self) -> BlocklistEntries {
+        self.entries.read().unwrap().clone()
+    }
+
+    pub fn is_user_blocked(&self, id: &OwnerId) -> bool {
+        self.entries.read().unwrap().user_ids.contains(id)
+    }
+
+    pub fn is_token_blocked(&self, token: &str) -> bool {
+        let entries = self.entries.read().unwrap();
+
+        // Hashing isn't free, and almost every registry's blocklist is empty.
+        !entries.token_hashes.is_empty() && entries.token_hashes.contains(&token_hash(token))
+    }
+
+    /// Blocks everything in `added`, returning the whole blocklist.
+    pub fn add(&self, added: BlocklistEntries) -> anyhow::Result<BlocklistEntries> {
+        self.update(|entries| {
+            entries.user_ids.extend(added.user_ids);
+            entries.token_hashes.extend(added.token_hashes);
+        })
+    }
+
+    /// Unblocks everything in `removed`, returning the whole blocklist.
+    pub fn remove(&self, removed: &BlocklistEntries) -> anyhow::Result<BlocklistEntries> {
+        self.update(|entries| {
+            entries.user_ids.retain(|id| !removed.user_ids.contains(id));
+            entries
+                .token_hashes
+                .retain(|hash| !removed.token_hashes.contains(hash));
+        })
+    }
+
+    /// Changes are saved before they take effect, so a blocklist that
+    /// couldn't be saved is left as it was.
+    fn update(
+        &self,
+        change: impl FnOnce(&mut BlocklistEntries),
+    ) -> anyhow::Result<BlocklistEntries> {
+        let mut entries = self.entries.write().unwrap();
+        let mut updated = entries.clone();
+        change(&mut updated);
+
+        if let Some(path) = &self.path {
+            // Written next to the blocklist and moved over it, so that it's
+            // never left half-written.
+            let temp_path = path.with_extension("tmp");
+            fs_err::write(&temp_path, serde_json::to_vec_pretty(&updated)?)?;
+            fs_err::rename(&temp_path, path)?;
+        }
+
+        *entries = updated.clone();
+        Ok(updated)
+    }
+}
+
+impl Default for Blocklist {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How a token is written in the blocklist: the hex-encoded SHA-256 hash of
+/// the token, which is what `sha256sum` gives for it.
+pub fn token_hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
@@ -0,0 +1,193 @@
+//! Compresses JSON and MessagePack responses, like package metadata and
+//! search results, for clients that ask for it with `Accept-Encoding`. Package downloads are ZIP
+//! archives, which are already compressed, so they're always sent as-is.
+
+use std::io::{Cursor, Write};
+
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Header, Method};
+use rocket::{Request, Response};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Encoding {
+    Gzip,
+
+    /// What HTTP calls `deflate` is zlib-wrapped DEFLATE.
+    Deflate,
+}
+
+impl Encoding {
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    fn compress(self, body: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+            Encoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CompressionConfig {
+    /// Responses smaller than this many bytes aren't worth compressing.
+    #[serde(default = "default_min_size")]
+    pub min_size: usize,
+
+    /// The encodings the registry will use, most preferred first. Compression
+    /// is turned off if empty.
+    #[serde(default = "default_encodings")]
+    pub encodings: Vec<Encoding>,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            min_size: default_min_size(),
+            encodings: default_encodings(),
+        }
+    }
+}
+
+fn default_min_size() -> usize {
+    1024
+}
+
+fn default_encodings() -> Vec<Encoding> {
+    vec![Encoding::Gzip, Encoding::Deflate]
+}
+
+/// Picks the first of `encodings` that the `Accept-Encoding` header allows.
+/// Encodings with a quality of 0 are refused, and `*` allows any encoding the
+/// header doesn't refuse by name.
+pub(crate) fn negotiate(accept_encoding: &str, encodings: &[Encoding]) -> Option<Encoding> {
+    let mut accepted = Vec::new();
+
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';').map(str::trim);
+        let name = parts.next().unwrap_or_default().to_ascii_lowercase();
+        let quality = parts
+            .find_map(|param| param.strip_prefix("q="))
+            .and_then(|quality| quality.parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        accepted.push((name, quality > 0.0));
+    }
+
+    let allows = |name: &str| match accepted.iter().find(|(accepted, _)| accepted == name) {
+        Some(&(_, allowed)) => allowed,
+        None => accepted
+            .iter()
+            .any(|(accepted, allowed)| accepted == "*" && *allowed),
+    };
+
+    encodings
+        .iter()
+        .copied()
+        .find(|encoding| allows(encoding.name()))
+}
+
+pub struct ResponseCompression {
+    config: CompressionConfig,
+}
+
+impl ResponseCompression {
+    pub fn new(config: CompressionConfig) -> Self {
+        Self { config }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for ResponseCompression {
+    fn info(&self) -> Info {
+        Info {
+            name: "Compress JSON and MessagePack responses",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let is_structured = response
+            .content_type()
+            .map(|content_type| content_type.is_json() || content_type.is_msgpack())
+            .unwrap_or(false);
+
+        if !is_structured || request.method() == Method::Head || self.config.encodings.is_empty() {
+            return;
+        }
+
+        // Whether this response is compressed depends on the request, so
+        // caches mustn't hand one client's response to another.
+        response.adjoin_header(Header::new("Vary", "Accept-Encoding"));
+
+        if response.headers().contains("Content-Encoding") {
+            return;
+        }
+
+        let encoding = match request
+            .headers()
+            .get_one("Accept-Encoding")
+            .and_then(|accept| negotiate(accept, &self.config.encodings))
+        {
+            Some(encoding) => encoding,
+            None => return,
+        };
+
+        if let Some(size) = response.body().preset_size() {
+            if size < self.config.min_size {
+                return;
+            }
+        }
+
+        let body = match response.body_mut().to_bytes().await {
+            Ok(body) => body,
+            Err(err) => {
+                tracing::warn!(error = %err, "could not read response to compress it");
+                return;
+            }
+        };
+
+        if body.len() < self.config.min_size {
+            response.set_sized_body(body.len(), Cursor::new(body));
+            return;
+        }
+
+        let compressed = match encoding.compress(&body) {
+            Ok(compressed) => compressed,
+            Err(err) => {
+                tracing::warn!(error = %err, "could not compress response");
+                response.set_sized_body(body.len(), Cursor::new(body));
+                return;
+            }
+        };
+
+        response.set_sized_body(compressed.len(), Cursor::new(compressed));
+        response.set_header(Header::new("Content-Encoding", encoding.name()));
+
+        // ETags are made from the uncompressed content, so a compressed
+        // response only has the same content semantically. A weak tag still
+        // matches `If-None-Match`, whichever encoding the client got first.
+        if let Some(etag) = response.headers().get_one("ETag") {
+            if !etag.starts_with("W/") {
+                let weak = format!("W/{}", etag);
+                response.set_header(Header::new("ETag", weak));
+            }
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Conditional GET support, so that clients can skip downloading metadata and
+//! packages they already have.
+
+use std::convert::Infallible;
+
+use rocket::http::{Header, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::response::Responder;
+use rocket::{Request, Response};
+
+/// The `If-None-Match` header of a request, if it had one.
+pub struct IfNoneMatch(Option<String>);
+
+impl IfNoneMatch {
+    /// Whether the client already has the representation tagged `etag`.
+    /// Weak tags are compared the same as strong ones, as RFC 7232 asks for
+    /// `If-None-Match`.
+    pub fn matches(&self, etag: &str) -> bool {
+        let header = match &self.0 {
+            Some(header) => header,
+            None => return false,
+        };
+
+        header.split(',').map(str::trim).any(|candidate| {
+            candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfNoneMatch {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let header = request.headers().get_one("If-None-Match");
+        Outcome::Success(IfNoneMatch(header.map(str::to_owned)))
+    }
+}
+
+/// Quotes a hash so that it can be used as an `ETag`.
+pub fn etag(hash: &str) -> String {
+    format!("\"{}\"", hash)
+}
+
+/// A response tagged with an `ETag`, or an empty 304 Not Modified if the
+/// client already has it.
+pub enum Tagged<R> {
+    Modified { response: R, etag: Option<String> },
+    NotModified { etag: String },
+}
+
+impl<R> Tagged<R> {
+    /// Sends `response` unless the client's `If-None-Match` matches `etag`.
+    /// Without an `etag`, the response is always sent.
+    pub fn new(response: R, etag: Option<String>, if_none_match: &IfNoneMatch) -> Self {
+        match etag {
+            Some(etag) if if_none_match.matches(&etag) => Tagged::NotModified { etag },
+            etag => Tagged::Modified { response, etag },
+        }
+    }
+}
+
+impl<'r, R: Responder<'r, 'static>> Responder<'r, 'static> for Tagged<R> {
+    fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'static> {
+        match self {
+            Tagged::Modified { response, etag } => {
+                let mut response = response.respond_to(request)?;
+
+                if let Some(etag) = etag {
+                    response.set_header(Header::new("ETag", etag));
+                }
+
+                Ok(response)
+            }
+            Tagged::NotModified { etag } => Response::build()
+                .status(Status::NotModified)
+                .raw_header("ETag", etag)
+                .ok(),
+        }
+    }
+}
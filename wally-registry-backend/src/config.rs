@@ -1,25 +1,1104 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use libwally::{http_client::TlsVersion, package_index::OwnerId, package_name::validate_scope};
 use semver::Version;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
-use crate::{auth::AuthMode, storage::StorageMode};
+use anyhow::{bail, Context};
+use figment::Figment;
+
+use crate::{
+    audit::AuditSink,
+    auth::{
+        extract_github_owner_repo, github_api_base, gitlab_project_id, AuthMode, GithubPermission,
+    },
+    compression::CompressionConfig,
+    cors::ANY_ORIGIN,
+    github_app::GithubAppConfig,
+    indexes::ANY_SCOPE,
+    logging::LogConfig,
+    persistence::PersistenceMode,
+    read_tokens::ReadTokenConfig,
+    storage::StorageMode,
+    webhook::WebhookConfig,
+};
 
 #[derive(Deserialize, Serialize)]
 pub struct Config {
     /// The URL of the Git repository containing the registry's package index.
     pub index_url: Url,
 
+    /// The scopes served from `index_url`, or `*` for every scope that isn't
+    /// served from one of `federated_indexes`. Scopes no index serves are
+    /// 404s.
+    #[serde(default = "default_index_scopes")]
+    pub index_scopes: Vec<String>,
+
+    /// Other index repositories to serve some scopes from, like a public
+    /// community index next to a private one. A scope is served from the
+    /// first of these that lists it, before `index_url` is considered.
+    #[serde(default)]
+    pub federated_indexes: Vec<FederatedIndexConfig>,
+
     /// The token that should be used by the registry to communicate with
     /// GitHub. If not specified, will try to use the machine's Git credential
     /// helper.
     pub github_token: Option<String>,
 
+    /// Authenticate as a GitHub App when checking permissions on the index
+    /// repository, using short-lived installation tokens instead of
+    /// `github_token`.
+    pub github_app: Option<GithubAppConfig>,
+
+    /// The base of the GitHub REST API, like `https://github.example.com/api/v3`
+    /// for GitHub Enterprise Server. If not set, it's worked out from the host
+    /// of `index_url`, falling back to `https://api.github.com`.
+    pub github_api_url: Option<Url>,
+
+    /// Other GitHub hosts people can sign in with, like a GitHub Enterprise
+    /// Server next to github.com. Tokens are checked against the main host
+    /// first and then against these in order. Only used with `github-oauth`.
+    #[serde(default)]
+    pub github_providers: Vec<GithubProvider>,
+
+    /// Repositories whose CI jobs can write to some scopes with the OIDC
+    /// token their CI provider gives them. Only used with
+    /// `trusted-publishing`.
+    #[serde(default)]
+    pub trusted_publishers: Vec<TrustedPublisher>,
+
     /// What kind of authentication is required to access endpoints.
     pub auth: AuthMode,
 
+    /// Authentication for reading packages, if it should differ from `auth`.
+    pub read_auth: Option<AuthMode>,
+
+    /// Authentication for publishing and other writes, if it should differ
+    /// from `auth`. Combined with an unauthenticated `read_auth`, this makes a
+    /// registry anyone can install from but only some people can publish to.
+    pub write_auth: Option<AuthMode>,
+
     /// Which storage backend to use.
     pub storage: StorageMode,
 
-    /// The minimum wally cli version required to publish to the registry
+    /// Answer package downloads with a redirect to a pre-signed storage URL
+    /// that expires after this many seconds, so that clients download
+    /// straight from storage. Only storage backends that can pre-sign URLs,
+    /// like S3, are redirected to; others are streamed through the registry.
+    pub presigned_url_ttl: Option<u64>,
+
+    /// Write, read back, and delete a probe object in storage on startup, so
+    /// that a misconfigured backend stops the registry from launching
+    /// instead of failing the first publish. Set `WALLY_STORAGE_SELF_TEST`
+    /// to `false` to skip it.
+    #[serde(default = "default_storage_self_test")]
+    pub storage_self_test: bool,
+
+    /// The minimum wally cli version required to publish to the registry.
+    /// Older clients are turned away with 426 Upgrade Required.
     pub minimum_wally_version: Option<Version>,
+
+    /// Hold package downloads to `minimum_wally_version` too, not just
+    /// publishes.
+    #[serde(default = "default_minimum_wally_version_reads")]
+    pub minimum_wally_version_reads: bool,
+
+    /// Whether requests that don't say which version of Wally sent them, or
+    /// give a version that can't be read, are let through when
+    /// `minimum_wally_version` is set.
+    #[serde(default)]
+    pub unknown_wally_version: UnknownWallyVersion,
+
+    /// The least permission GitHub users need on the index repository when
+    /// it's checked, like `write`, in GitHub's order: `read`, `triage`,
+    /// `write`, `maintain`, `admin`. Anyone who can read it is let in by
+    /// default.
+    #[serde(default)]
+    pub minimum_index_permission: GithubPermission,
+
+    /// The least permission needed to read, if it should differ from
+    /// `minimum_index_permission`.
+    pub read_index_permission: Option<GithubPermission>,
+
+    /// The least permission needed to publish and make other writes, if it
+    /// should differ from `minimum_index_permission`.
+    pub write_index_permission: Option<GithubPermission>,
+
+    /// If set, publishing with GitHub authentication requires the account to
+    /// have a verified email address on one of these domains.
+    pub allowed_email_domains: Option<Vec<String>>,
+
+    /// The minimum TLS version used for outgoing connections, like those made
+    /// to GitHub. Defaults to TLS 1.2.
+    #[serde(default)]
+    pub min_tls_version: TlsVersion,
+
+    /// How long to wait on GitHub, or GitLab, when checking who a user is and
+    /// whether they can access the index.
+    #[serde(default)]
+    pub github_timeouts: GithubTimeouts,
+
+    /// How calls to GitHub are retried when GitHub has a temporary problem.
+    #[serde(default)]
+    pub github_retries: GithubRetries,
+
+    /// Requirements a GitHub account has to meet before it can claim the
+    /// unowned scope matching its login. Nothing is required by default.
+    #[serde(default)]
+    pub bootstrap: BootstrapPolicy,
+
+    /// How many seconds a validated GitHub token is remembered for before it's
+    /// checked with GitHub again. Set to 0 to check every request.
+    #[serde(default = "default_auth_cache_ttl")]
+    pub auth_cache_ttl: u64,
+
+    /// How many reads and writes each user, API key, or IP address can make.
+    /// Nothing is limited by default.
+    #[serde(default)]
+    pub rate_limits: RateLimits,
+
+    /// How many times an IP address, or tokens starting the same way, can
+    /// fail to authenticate before further attempts are turned away.
+    #[serde(default)]
+    pub auth_throttle: AuthThrottleConfig,
+
+    /// Where to write a record of every publish attempt, allowed or denied. If
+    /// not set, no audit log is kept.
+    pub audit_log: Option<AuditSink>,
+
+    /// Where to keep download stats, audit events, and the blocklist so that
+    /// they survive restarts. Kept in memory by default.
+    #[serde(default)]
+    pub persistence: PersistenceMode,
+
+    /// Serve `/metrics` on a separate address, like `127.0.0.1:9100`, instead
+    /// of next to the rest of the API, so that it doesn't have to be public.
+    pub metrics_address: Option<SocketAddr>,
+
+    /// A lock file to hold while writing to the package index. Needed when
+    /// several instances of the registry write to the same index, so that
+    /// they take turns instead of pushing conflicting commits.
+    pub index_lock: Option<IndexLockConfig>,
+
+    /// How many seconds to wait between refreshes of the package index from
+    /// its remote, so that changes made directly to the index are picked up.
+    /// If not set, the index is only refreshed when the registry writes to it
+    /// or through the admin API.
+    pub index_refresh_interval: Option<u64>,
+
+    /// How long a request can take before it's abandoned with a 504, so that
+    /// a hung index or storage backend can't tie up every worker.
+    #[serde(default)]
+    pub request_timeouts: RequestTimeouts,
+
+    /// How long encoded package metadata and manifests are kept, so that
+    /// popular packages aren't encoded from the index for every request.
+    #[serde(default)]
+    pub metadata_cache: MetadataCacheConfig,
+
+    /// How many seconds after publishing a version can still be deleted, for
+    /// when something is published by mistake. Older versions can only be
+    /// yanked. Set to 0 to never allow deleting.
+    #[serde(default = "default_unpublish_window")]
+    pub unpublish_window: u64,
+
+    /// How many seconds after publishing a version the exact same archive can
+    /// be published again, which succeeds without changing anything. This
+    /// lets clients retry a publish whose response they never got. Set to 0,
+    /// the default, to always refuse versions that already exist.
+    #[serde(default)]
+    pub republish_window: u64,
+
+    /// The largest package archive that can be published, in bytes. Uploads
+    /// are cut off as soon as they go over, instead of being read in full.
+    #[serde(default = "default_max_package_size")]
+    pub max_package_size: u64,
+
+    /// The most versions a package can have, counting yanked ones, so that a
+    /// CI job publishing every commit can't bloat the index. Publishing past
+    /// this is refused unless `prune_prereleases` is on. Unlimited if not set.
+    pub max_versions_per_package: Option<usize>,
+
+    /// Make room for a new version of a package that's at
+    /// `max_versions_per_package` by deleting its oldest prereleases that
+    /// haven't been yanked.
+    #[serde(default)]
+    pub prune_prereleases: bool,
+
+    /// Reject packages whose dependencies don't match any version published to
+    /// this registry. Turn off to allow depending on versions that haven't
+    /// been published yet.
+    #[serde(default = "default_check_dependencies")]
+    pub check_dependencies: bool,
+
+    /// Reject shared packages with dependencies that are only published as
+    /// server packages. Turn off for registries that keep track of realms
+    /// some other way.
+    #[serde(default = "default_check_dependency_realms")]
+    pub check_dependency_realms: bool,
+
+    /// Reject new packages whose name looks like an existing package's, like
+    /// `r0blox/rodux` next to `roblox/rodux`, to stop lookalike names from
+    /// being used to impersonate packages.
+    #[serde(default = "default_reject_confusable_names")]
+    pub reject_confusable_names: bool,
+
+    /// Reject packages without a description in their manifest.
+    #[serde(default = "default_require_description")]
+    pub require_description: bool,
+
+    /// Reject packages whose manifest has no license, or one that isn't an
+    /// SPDX license expression like `MIT` or `MIT OR Apache-2.0`.
+    #[serde(default = "default_require_license")]
+    pub require_license: bool,
+
+    /// Reject packages published without a minisign signature from one of
+    /// their scope's signing keys. Signatures that are sent are checked
+    /// either way.
+    #[serde(default)]
+    pub require_signatures: bool,
+
+    /// The only scopes packages can be published to, for registries that
+    /// only host approved organizations. If empty, any scope can be used.
+    #[serde(default)]
+    pub allowed_scopes: Vec<String>,
+
+    /// Scopes nobody can publish to or claim, like `wally`, so that names
+    /// can be reserved. This applies even to owners of the scope.
+    #[serde(default)]
+    pub denied_scopes: Vec<String>,
+
+    /// The most packages a client can look up in one metadata batch request.
+    #[serde(default = "default_max_metadata_batch")]
+    pub max_metadata_batch: usize,
+
+    /// How much to log, and whether to log human-readable lines or JSON.
+    #[serde(default)]
+    pub log: LogConfig,
+
+    /// Start the registry in read-only maintenance mode, where reads work but
+    /// all writes are rejected. Can be toggled at runtime with the admin API.
+    #[serde(default)]
+    pub maintenance: bool,
+
+    /// A secret that grants access to the admin API. If not set, the admin API
+    /// is disabled.
+    pub admin_key: Option<String>,
+
+    /// A file to save the blocklist of GitHub accounts and tokens to, so that
+    /// blocks made through the admin API survive restarts. If not set, the
+    /// blocklist is only kept in memory.
+    pub blocklist_path: Option<PathBuf>,
+
+    /// Origins, like `https://wally.example.com`, that browsers can call the
+    /// registry from. Listed origins can send credentials; `*` allows any
+    /// other origin, but only without credentials. CORS is disabled if empty.
+    #[serde(default)]
+    pub cors_origins: Vec<String>,
+
+    /// How JSON responses are compressed for clients that accept it.
+    #[serde(default)]
+    pub compression: CompressionConfig,
+
+    /// URLs to notify whenever a package is published. If not set, no
+    /// webhooks are sent.
+    pub webhooks: Option<WebhookConfig>,
+
+    /// Lets the registry issue its own read tokens, limited to some scopes and
+    /// expiring after a while. If not set, read tokens aren't accepted.
+    pub read_tokens: Option<ReadTokenConfig>,
+
+    /// The secret that listing cursors are signed with. If not set, a random
+    /// one is made at launch, so cursors stop working when the registry
+    /// restarts and can't be shared between instances behind a load
+    /// balancer.
+    pub cursor_secret: Option<String>,
+}
+
+/// The shapes an auth mode can take, shown when one can't be read, since the
+/// `type` and `value` keys aren't obvious from the field names alone.
+const AUTH_MODE_HELP: &str = r#"Auth modes are tables with a `type`, and a `value` for the modes that need one:
+    { type = "unauthenticated" }
+    { type = "api-key", value = "SECRET-KEY" }
+    { type = "api-key", value = ["OLD-SECRET-KEY", "NEW-SECRET-KEY"] }
+    { type = "double-api-key", value = { read = "READ-KEY", write = "WRITE-KEY" } }
+    { type = "github-oauth", value = { client-id = "APP-ID", client-secret = "APP-SECRET" } }
+    { type = "github-oauth-private", value = { client-id = "APP-ID", client-secret = "APP-SECRET" } }
+    { type = "gitlab", value = { client-id = "APP-ID", client-secret = "APP-SECRET", instance-url = "https://gitlab.com" } }
+    { type = "trusted-publishing", value = { audience = "wally", api-key = "SECRET-KEY" } }"#;
+
+impl Config {
+    /// Reads the config, saying which field and file a problem is in, with
+    /// examples of each auth mode when it's an auth mode that's wrong.
+    pub fn from_figment(figment: &Figment) -> anyhow::Result<Self> {
+        figment.extract().map_err(|err| {
+            let mut auth_help = false;
+            let messages: Vec<_> = err
+                .into_iter()
+                .map(|error| {
+                    if let Some(field) = error.path.first() {
+                        auth_help |= matches!(field.as_str(), "auth" | "read_auth" | "write_auth");
+                    }
+
+                    format!("  {}", error)
+                })
+                .collect();
+
+            let mut message = format!("the configuration is invalid:\n{}", messages.join("\n"));
+            if auth_help {
+                message = format!("{}\n\n{}", message, AUTH_MODE_HELP);
+            }
+
+            anyhow::Error::msg(message)
+        })
+    }
+
+    /// Settings that are set but do nothing with the chosen auth modes, so
+    /// that operators find out instead of wondering why they don't apply.
+    /// `github_token` isn't one, since it's also used to push to the index.
+    pub fn unused_settings(&self) -> Vec<&'static str> {
+        let mut unused = Vec::new();
+
+        if !self.uses_github_auth() {
+            if self.github_app.is_some() {
+                unused.push("github_app");
+            }
+
+            if self.github_api_url.is_some() {
+                unused.push("github_api_url");
+            }
+
+            if !self.github_providers.is_empty() {
+                unused.push("github_providers");
+            }
+
+            if self.allowed_email_domains.is_some() {
+                unused.push("allowed_email_domains");
+            }
+
+            if self.minimum_index_permission != GithubPermission::default()
+                || self.read_index_permission.is_some()
+                || self.write_index_permission.is_some()
+            {
+                unused.push("minimum_index_permission");
+            }
+
+            if self.bootstrap.min_account_age_days.is_some()
+                || self.bootstrap.require_verified_email
+            {
+                unused.push("bootstrap");
+            }
+        }
+
+        let trusted_publishing = [self.read_auth(), self.write_auth()]
+            .iter()
+            .any(|auth| matches!(auth, AuthMode::TrustedPublishing { .. }));
+
+        if !trusted_publishing && !self.trusted_publishers.is_empty() {
+            unused.push("trusted_publishers");
+        }
+
+        unused
+    }
+
+    /// How reads are authenticated.
+    pub fn read_auth(&self) -> &AuthMode {
+        self.read_auth.as_ref().unwrap_or(&self.auth)
+    }
+
+    /// How writes are authenticated.
+    pub fn write_auth(&self) -> &AuthMode {
+        self.write_auth.as_ref().unwrap_or(&self.auth)
+    }
+
+    /// The least permission on the index repository needed for writes, or
+    /// for reads when `write` is false.
+    pub fn minimum_index_permission(&self, write: bool) -> GithubPermission {
+        let permission = match write {
+            true => self.write_index_permission,
+            false => self.read_index_permission,
+        };
+
+        permission.unwrap_or(self.minimum_index_permission)
+    }
+
+    /// The base URL GitHub API paths like `/user` are added to, without a
+    /// trailing slash.
+    pub fn github_api_base(&self) -> String {
+        if let Some(url) = &self.github_api_url {
+            return url.as_str().trim_end_matches('/').to_owned();
+        }
+
+        match extract_github_owner_repo(self.index_url.as_str()) {
+            Some((host, _, _)) => github_api_base(&host),
+            None => github_api_base("github.com"),
+        }
+    }
+
+    /// The base URL of the API of the GitHub host an owner signed in with,
+    /// or `None` if the registry doesn't know that host anymore.
+    pub fn owner_api_base(&self, owner: &OwnerId) -> Option<String> {
+        match owner.provider() {
+            Some(name) => self
+                .github_providers
+                .iter()
+                .find(|provider| provider.name == name)
+                .map(GithubProvider::api_base),
+            None => Some(self.github_api_base()),
+        }
+    }
+
+    /// Whether users sign in with GitHub for reads or writes, which means the
+    /// user ids recorded in the index are GitHub ids.
+    pub fn uses_github_auth(&self) -> bool {
+        [self.read_auth(), self.write_auth()].iter().any(|auth| {
+            matches!(
+                auth,
+                AuthMode::GithubOAuth { .. } | AuthMode::GithubOAuthPrivate { .. }
+            )
+        })
+    }
+
+    /// Check that the configuration has everything the chosen auth modes
+    /// need, so that mistakes stop the registry from starting instead of
+    /// failing requests later.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.validate_auth(&self.auth)?;
+
+        if let Some(read_auth) = &self.read_auth {
+            self.validate_auth(read_auth).context("invalid read_auth")?;
+        }
+
+        if let Some(write_auth) = &self.write_auth {
+            self.validate_auth(write_auth)
+                .context("invalid write_auth")?;
+        }
+
+        self.log.validate()?;
+        self.validate_indexes()?;
+        self.validate_github_providers()?;
+        self.validate_trusted_publishers()?;
+
+        if self.max_package_size == 0 {
+            bail!("max_package_size must be at least 1 byte");
+        }
+
+        if self.max_versions_per_package == Some(0) {
+            bail!("max_versions_per_package must be at least 1");
+        }
+
+        if self.index_refresh_interval == Some(0) {
+            bail!("index_refresh_interval must be at least 1 second");
+        }
+
+        if self.request_timeouts.read == 0 || self.request_timeouts.write == 0 {
+            bail!("request_timeouts must be at least 1 second");
+        }
+
+        if self.github_retries.max_attempts == 0 {
+            bail!("github_retries.max_attempts must be at least 1");
+        }
+
+        for origin in &self.cors_origins {
+            if origin != ANY_ORIGIN && !is_origin(origin) {
+                bail!(
+                    "CORS origin {} should be a scheme, host, and optional port, \
+                     like https://wally.example.com, with no path or trailing slash",
+                    origin
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_indexes(&self) -> anyhow::Result<()> {
+        let validate_scopes = |scopes: &[String], setting: &str| -> anyhow::Result<()> {
+            for scope in scopes {
+                if scope != ANY_SCOPE {
+                    validate_scope(scope)
+                        .with_context(|| format!("invalid scope {} in {}", scope, setting))?;
+                }
+            }
+
+            Ok(())
+        };
+
+        validate_scopes(&self.index_scopes, "index_scopes")?;
+        let mut urls = vec![&self.index_url];
+
+        for federated in &self.federated_indexes {
+            if urls.contains(&&federated.url) {
+                bail!("the index {} is configured more than once", federated.url);
+            }
+
+            if federated.scopes.is_empty() {
+                bail!("federated index {} needs at least one scope", federated.url);
+            }
+
+            validate_scopes(&federated.scopes, "federated_indexes")?;
+            urls.push(&federated.url);
+        }
+
+        Ok(())
+    }
+
+    fn validate_github_providers(&self) -> anyhow::Result<()> {
+        let mut names = Vec::new();
+
+        for provider in &self.github_providers {
+            if provider.name.is_empty() || provider.name.contains(':') {
+                bail!(
+                    "GitHub provider names can't be empty or contain ':', got '{}'",
+                    provider.name
+                );
+            }
+
+            if names.contains(&&provider.name) {
+                bail!(
+                    "the GitHub provider {} is configured more than once",
+                    provider.name
+                );
+            }
+
+            names.push(&provider.name);
+        }
+
+        // Index permissions and teams are only ever checked on the main host,
+        // so nobody from another host could get through a private registry.
+        let private = [self.read_auth(), self.write_auth()]
+            .iter()
+            .any(|auth| matches!(auth, AuthMode::GithubOAuthPrivate { .. }));
+
+        if private && !self.github_providers.is_empty() {
+            bail!(
+                "github_providers can't be used with auth mode github-oauth-private, which \
+                 checks permissions on the index repository on the main GitHub host"
+            );
+        }
+
+        Ok(())
+    }
+
+    fn validate_trusted_publishers(&self) -> anyhow::Result<()> {
+        for publisher in &self.trusted_publishers {
+            if publisher.repository.is_empty() {
+                bail!(
+                    "trusted publisher from {} needs a repository",
+                    publisher.issuer
+                );
+            }
+
+            if publisher.scopes.is_empty() {
+                bail!(
+                    "trusted publisher {} needs at least one scope",
+                    publisher.repository
+                );
+            }
+
+            for scope in &publisher.scopes {
+                validate_scope(scope).with_context(|| {
+                    format!(
+                        "invalid scope {} for trusted publisher {}",
+                        scope, publisher.repository
+                    )
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_auth(&self, auth: &AuthMode) -> anyhow::Result<()> {
+        match auth {
+            AuthMode::GithubOAuthPrivate { .. } => {
+                if extract_github_owner_repo(self.index_url.as_str()).is_none() {
+                    bail!(
+                        "auth mode github-oauth-private checks permissions on the index \
+                         repository, so index_url must be a GitHub repository URL, not {}",
+                        self.index_url
+                    );
+                }
+
+                if self.github_token.is_none() && self.github_app.is_none() {
+                    bail!(
+                        "auth mode github-oauth-private needs github_token or github_app to be \
+                         set to check permissions on the index repository"
+                    );
+                }
+            }
+            AuthMode::GitLab {
+                instance_url,
+                private: true,
+                ..
+            } => {
+                if gitlab_project_id(instance_url, &self.index_url).is_none() {
+                    bail!(
+                        "private gitlab auth checks membership of the index project, so \
+                         index_url must be a project on {}, not {}",
+                        instance_url,
+                        self.index_url
+                    );
+                }
+            }
+            AuthMode::ApiKey(keys) if keys.as_slice().is_empty() => {
+                bail!("auth mode api-key needs at least one key");
+            }
+            AuthMode::DoubleApiKey {
+                read,
+                write,
+                read_scopes,
+            } => {
+                if write.as_slice().is_empty() {
+                    bail!("auth mode double-api-key needs at least one write key");
+                }
+
+                if matches!(read, Some(read) if read.as_slice().is_empty()) {
+                    bail!(
+                        "auth mode double-api-key needs at least one read key, or no read \
+                         keys at all to make reading public"
+                    );
+                }
+
+                if read.is_none() && read_scopes.is_some() {
+                    bail!(
+                        "auth mode double-api-key has read-scopes set, but reading is public \
+                         because there are no read keys"
+                    );
+                }
+
+                for scope in read_scopes.iter().flatten() {
+                    validate_scope(scope)
+                        .with_context(|| format!("invalid scope {} in read-scopes", scope))?;
+                }
+            }
+            AuthMode::TrustedPublishing { api_key, .. } => {
+                if self.trusted_publishers.is_empty() {
+                    bail!(
+                        "auth mode trusted-publishing needs trusted_publishers to say which \
+                         repositories can publish to which scopes"
+                    );
+                }
+
+                if matches!(api_key, Some(keys) if keys.as_slice().is_empty()) {
+                    bail!(
+                        "auth mode trusted-publishing needs at least one key in api-key, or \
+                         no api-key at all"
+                    );
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct BootstrapPolicy {
+    /// The account must have been created at least this many days ago.
+    pub min_account_age_days: Option<u64>,
+
+    /// The account must have a verified email address. This needs tokens
+    /// with the `user:email` scope.
+    #[serde(default)]
+    pub require_verified_email: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy)]
+pub struct RequestTimeouts {
+    /// How many seconds GET and HEAD requests get.
+    #[serde(default = "default_read_request_timeout")]
+    pub read: u64,
+
+    /// How many seconds every other request gets. Publishes spend most of
+    /// theirs uploading and holding their scope's lock, so this is longer.
+    #[serde(default = "default_write_request_timeout")]
+    pub write: u64,
+}
+
+impl RequestTimeouts {
+    pub fn read(&self) -> Duration {
+        Duration::from_secs(self.read)
+    }
+
+    pub fn write(&self) -> Duration {
+        Duration::from_secs(self.write)
+    }
+}
+
+impl Default for RequestTimeouts {
+    fn default() -> Self {
+        Self {
+            read: default_read_request_timeout(),
+            write: default_write_request_timeout(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy)]
+pub struct MetadataCacheConfig {
+    /// How many seconds a cached response is served before it's stale. Set to
+    /// 0 to turn the cache off.
+    #[serde(default = "default_metadata_cache_ttl")]
+    pub ttl: u64,
+
+    /// How many seconds after that a stale response is still served while
+    /// it's encoded again in the background.
+    #[serde(default = "default_metadata_cache_stale")]
+    pub stale: u64,
+}
+
+impl Default for MetadataCacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: default_metadata_cache_ttl(),
+            stale: default_metadata_cache_stale(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy)]
+pub struct GithubTimeouts {
+    /// Timeouts for looking up the user a token belongs to and checking that
+    /// it was issued to this registry's OAuth app.
+    #[serde(default = "default_identity_timeouts")]
+    pub identity: Timeouts,
+
+    /// Timeouts for checking the user's permission on the index repository.
+    #[serde(default = "default_permission_timeouts")]
+    pub permission: Timeouts,
+}
+
+impl Default for GithubTimeouts {
+    fn default() -> Self {
+        Self {
+            identity: default_identity_timeouts(),
+            permission: default_permission_timeouts(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy)]
+pub struct Timeouts {
+    /// How many seconds to wait for a connection to be established.
+    pub connect: u64,
+
+    /// How many seconds to wait for the full response once a request has been
+    /// sent.
+    pub read: u64,
+}
+
+impl Timeouts {
+    pub fn connect(&self) -> Duration {
+        Duration::from_secs(self.connect)
+    }
+
+    pub fn read(&self) -> Duration {
+        Duration::from_secs(self.read)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy)]
+pub struct GithubRetries {
+    /// How many times each call is tried, including the first. Set to 1 to
+    /// turn retries off.
+    #[serde(default = "default_retry_attempts")]
+    pub max_attempts: u32,
+
+    /// How many milliseconds to wait before the first retry. Each retry after
+    /// that waits twice as long as the one before.
+    #[serde(default = "default_retry_base_delay")]
+    pub base_delay_ms: u64,
+
+    /// How many seconds a request can spend on GitHub calls, across all of
+    /// them, before it stops retrying.
+    #[serde(default = "default_retry_budget")]
+    pub budget: u64,
+}
+
+impl GithubRetries {
+    pub fn base_delay(&self) -> Duration {
+        Duration::from_millis(self.base_delay_ms)
+    }
+
+    pub fn budget(&self) -> Duration {
+        Duration::from_secs(self.budget)
+    }
+}
+
+impl Default for GithubRetries {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_retry_attempts(),
+            base_delay_ms: default_retry_base_delay(),
+            budget: default_retry_budget(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default)]
+pub struct RateLimits {
+    /// The limit on requests that read from the registry, like downloads.
+    pub read: Option<RateLimit>,
+
+    /// The limit on requests that change the registry, like publishes.
+    pub write: Option<RateLimit>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy)]
+pub struct RateLimit {
+    /// How many requests can be made in each window.
+    pub requests: u32,
+
+    /// How long the window is, in seconds.
+    pub window: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy)]
+pub struct AuthThrottleConfig {
+    /// How many failed attempts are allowed in each window. Set to 0 to turn
+    /// throttling off.
+    #[serde(default = "default_auth_throttle_failures")]
+    pub max_failures: u32,
+
+    /// How long the window is, in seconds. Failures are forgotten gradually
+    /// over it, rather than all at once at its end.
+    #[serde(default = "default_auth_throttle_window")]
+    pub window: u64,
+}
+
+impl Default for AuthThrottleConfig {
+    fn default() -> Self {
+        Self {
+            max_failures: default_auth_throttle_failures(),
+            window: default_auth_throttle_window(),
+        }
+    }
+}
+
+fn default_auth_throttle_failures() -> u32 {
+    10
+}
+
+fn default_auth_throttle_window() -> u64 {
+    5 * 60
+}
+
+fn default_auth_cache_ttl() -> u64 {
+    60
+}
+
+fn default_metadata_cache_ttl() -> u64 {
+    30
+}
+
+fn default_metadata_cache_stale() -> u64 {
+    5 * 60
+}
+
+fn default_unpublish_window() -> u64 {
+    60 * 60
+}
+
+fn default_max_package_size() -> u64 {
+    50 * 1024 * 1024
+}
+
+fn default_check_dependencies() -> bool {
+    true
+}
+
+fn default_check_dependency_realms() -> bool {
+    true
+}
+
+fn default_reject_confusable_names() -> bool {
+    true
+}
+
+fn default_minimum_wally_version_reads() -> bool {
+    true
+}
+
+fn default_storage_self_test() -> bool {
+    true
+}
+
+fn default_require_description() -> bool {
+    true
+}
+
+fn default_require_license() -> bool {
+    true
+}
+
+fn default_max_metadata_batch() -> usize {
+    100
+}
+
+fn default_read_request_timeout() -> u64 {
+    30
+}
+
+fn default_write_request_timeout() -> u64 {
+    300
+}
+
+fn default_identity_timeouts() -> Timeouts {
+    Timeouts {
+        connect: 10,
+        read: 30,
+    }
+}
+
+fn default_retry_attempts() -> u32 {
+    3
+}
+
+fn default_retry_base_delay() -> u64 {
+    250
+}
+
+fn default_retry_budget() -> u64 {
+    15
+}
+
+fn default_permission_timeouts() -> Timeouts {
+    Timeouts {
+        connect: 5,
+        read: 10,
+    }
+}
+
+/// What to do with a request whose Wally version isn't known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnknownWallyVersion {
+    Allow,
+    Deny,
+}
+
+impl Default for UnknownWallyVersion {
+    fn default() -> Self {
+        UnknownWallyVersion::Deny
+    }
+}
+
+/// A GitHub host people can sign in with besides the registry's main one,
+/// with the OAuth app registered on it.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct GithubProvider {
+    /// What the registry calls this host. Users from it are recorded as
+    /// owners by this name and their id, like `enterprise:1234`, so it
+    /// shouldn't be changed once they own scopes.
+    pub name: String,
+
+    /// The base of the host's REST API, like
+    /// `https://github.example.com/api/v3`.
+    pub api_url: Url,
+
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+impl GithubProvider {
+    /// The base URL GitHub API paths like `/user` are added to, without a
+    /// trailing slash.
+    pub fn api_base(&self) -> String {
+        self.api_url.as_str().trim_end_matches('/').to_owned()
+    }
+}
+
+/// A repository whose CI jobs can write to `scopes` with the OIDC tokens
+/// their CI provider issues.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct TrustedPublisher {
+    /// Who issues the tokens, like `https://token.actions.githubusercontent.com`
+    /// for GitHub Actions or `https://gitlab.com` for GitLab CI.
+    pub issuer: Url,
+
+    /// Where the issuer publishes its signing keys. If not set, they're found
+    /// through the issuer's OpenID configuration.
+    #[serde(default)]
+    pub jwks_url: Option<Url>,
+
+    /// The repository, like `biff/hello`, or the project path on GitLab.
+    pub repository: String,
+
+    /// If set, only jobs for this Git ref are trusted, like
+    /// `refs/heads/main` on GitHub or `main` on GitLab.
+    #[serde(default, rename = "ref")]
+    pub git_ref: Option<String>,
+
+    pub scopes: Vec<String>,
+}
+
+impl TrustedPublisher {
+    pub fn issuer_matches(&self, issuer: &str) -> bool {
+        self.issuer.as_str().trim_end_matches('/') == issuer.trim_end_matches('/')
+    }
+
+    /// Whether a job for `repository`, on `git_ref`, is trusted.
+    pub fn trusts(&self, repository: &str, git_ref: Option<&str>) -> bool {
+        let ref_matches = match &self.git_ref {
+            Some(expected) => git_ref == Some(expected.as_str()),
+            None => true,
+        };
+
+        self.repository.eq_ignore_ascii_case(repository) && ref_matches
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct FederatedIndexConfig {
+    /// The URL of the index's Git repository.
+    pub url: Url,
+
+    /// The scopes served from this index, or `*` for every scope not served
+    /// from an index listed before it.
+    pub scopes: Vec<String>,
+
+    /// Like `index_lock`, but for writes to this index.
+    pub lock: Option<IndexLockConfig>,
+}
+
+fn default_index_scopes() -> Vec<String> {
+    vec![ANY_SCOPE.to_owned()]
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct IndexLockConfig {
+    /// Where the lock file lives. This should be on a file system that every
+    /// instance of the registry can see.
+    pub path: PathBuf,
+
+    /// How many seconds to wait for the lock before failing the request.
+    #[serde(default = "default_lock_timeout")]
+    pub timeout: u64,
+
+    /// How many seconds old a lock can be before it's assumed to be
+    /// abandoned by an instance that crashed.
+    #[serde(default = "default_lock_stale_after")]
+    pub stale_after: u64,
+}
+
+fn default_lock_timeout() -> u64 {
+    30
+}
+
+fn default_lock_stale_after() -> u64 {
+    300
+}
+
+/// Browsers compare origins exactly, so an origin with a path or a trailing
+/// slash would never match.
+fn is_origin(origin: &str) -> bool {
+    match Url::parse(origin) {
+        Ok(url) => url.origin().ascii_serialization() == origin,
+        Err(_) => false,
+    }
 }
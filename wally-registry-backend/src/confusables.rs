@@ -0,0 +1,59 @@
+//! Catches new package names that look like an existing package's without
+//! being the same, like `r0blox/rodux` next to `roblox/rodux`, so that a new
+//! package can't pass itself off as a popular one.
+//!
+//! Names can only contain lowercase ASCII letters, digits, and dashes, and
+//! are lowercased before they reach the index, so different capitalizations
+//! are already the same package and Unicode lookalikes can't get in at all.
+//! What's left are the ASCII characters that look alike.
+
+use libwally::{package_index::PackageIndex, package_name::PackageName};
+
+/// Characters that are drawn alike, mapped to the one they're mistaken for.
+const LOOKALIKES: &[(char, char)] = &[('0', 'o'), ('1', 'l'), ('i', 'l')];
+
+/// Pairs of characters that together look like another.
+const LOOKALIKE_PAIRS: &[(&str, &str)] = &[("rn", "m"), ("vv", "w"), ("cl", "d")];
+
+/// Reduces a scope or name to what it looks like, so that names that look
+/// alike have the same skeleton. Dashes are left out, since `foo-bar` is
+/// easily mistaken for `foobar`.
+pub fn skeleton(name: &str) -> String {
+    let mut skeleton: String = name
+        .chars()
+        .filter(|char| *char != '-')
+        .map(|char| {
+            LOOKALIKES
+                .iter()
+                .find(|(lookalike, _)| *lookalike == char)
+                .map_or(char, |(_, target)| *target)
+        })
+        .collect();
+
+    for (pair, target) in LOOKALIKE_PAIRS {
+        skeleton = skeleton.replace(pair, target);
+    }
+
+    skeleton
+}
+
+/// Finds an existing package that looks like `name` but is spelled
+/// differently. Only new packages are checked, since new versions of an
+/// existing package are published under the name it already has.
+pub fn find_confusable(
+    index: &PackageIndex,
+    name: &PackageName,
+) -> anyhow::Result<Option<PackageName>> {
+    if index.package_exists(name)? {
+        return Ok(None);
+    }
+
+    let scope = skeleton(name.scope());
+    let short_name = skeleton(name.name());
+
+    Ok(index.package_names()?.into_iter().find(|existing| {
+        existing != name
+            && skeleton(existing.name()) == short_name
+            && skeleton(existing.scope()) == scope
+    }))
+}
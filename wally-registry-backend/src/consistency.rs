@@ -0,0 +1,155 @@
+//! Cross-checks the package indexes against storage, to find what manual
+//! edits and half-finished publishes or unpublishes left behind: versions in an
+//! index whose archive is gone, and archives for versions no index lists.
+
+use std::collections::BTreeSet;
+
+use anyhow::Context;
+use futures::{stream, StreamExt};
+use libwally::package_id::PackageId;
+use serde::Serialize;
+
+use crate::indexes::Indexes;
+use crate::scope_lock::ScopeLocks;
+use crate::storage::StorageBackend;
+
+/// How many archives are looked up in storage at once. Kept small so that a
+/// check doesn't crowd out downloads.
+const CONCURRENCY: usize = 8;
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ConsistencyReport {
+    /// How many versions the indexes list.
+    pub indexed: usize,
+
+    /// Versions in the index with no archive in storage. These can't be
+    /// repaired from here, since there's nothing to restore them from.
+    pub missing_archives: Vec<PackageId>,
+
+    /// Archives in storage for versions the index doesn't list, or `None` if
+    /// the storage backend can't list what it holds.
+    pub orphaned_archives: Option<Vec<PackageId>>,
+
+    /// Orphaned archives that were deleted, when pruning was asked for.
+    pub pruned: Vec<PackageId>,
+}
+
+/// Checks every version in the indexes against storage. With `prune`,
+/// orphaned archives are deleted too.
+pub async fn check_consistency(
+    indexes: Indexes,
+    storage: &dyn StorageBackend,
+    scope_locks: &ScopeLocks,
+    prune: bool,
+) -> anyhow::Result<ConsistencyReport> {
+    let indexed = indexed_versions(indexes.clone()).await?;
+
+    // Looked up a few at a time rather than all at once or one after
+    // another, so the check neither floods storage nor takes forever.
+    let lookups: Vec<_> = stream::iter(&indexed)
+        .map(|id| async move { (id, storage.exists(id).await) })
+        .buffer_unordered(CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut missing_archives = Vec::new();
+
+    for (id, exists) in lookups {
+        let exists = exists.with_context(|| format!("could not look up {} in storage", id))?;
+
+        if !exists {
+            missing_archives.push(id.clone());
+        }
+    }
+
+    missing_archives.sort();
+
+    let orphaned_archives = storage
+        .list()
+        .await
+        .context("could not list archives in storage")?
+        .map(|stored| {
+            let mut orphans: Vec<_> = stored
+                .into_iter()
+                .filter(|id| !indexed.contains(id))
+                .collect();
+            orphans.sort();
+            orphans.dedup();
+            orphans
+        });
+
+    let mut pruned = Vec::new();
+
+    if prune {
+        for id in orphaned_archives.iter().flatten() {
+            // A publish writes the archive before the index, so an archive
+            // can look orphaned while it's being published. Holding the
+            // scope's lock and checking again means it's only deleted once
+            // the publish has finished and still didn't list it.
+            let _scope_lock = scope_locks.lock(id.name().scope()).await;
+
+            if is_indexed(indexes.clone(), id.clone()).await? {
+                continue;
+            }
+
+            storage
+                .delete(id)
+                .await
+                .with_context(|| format!("could not delete orphaned archive {}", id))?;
+            pruned.push(id.clone());
+        }
+    }
+
+    Ok(ConsistencyReport {
+        indexed: indexed.len(),
+        missing_archives,
+        orphaned_archives,
+        pruned,
+    })
+}
+
+/// Every version of every package the registry serves. Reading an index is
+/// blocking, so it's done on a thread of its own.
+async fn indexed_versions(indexes: Indexes) -> anyhow::Result<BTreeSet<PackageId>> {
+    rocket::tokio::task::spawn_blocking(move || {
+        let mut ids = BTreeSet::new();
+
+        for (name, index) in indexes.package_names()? {
+            let metadata = index.get_package_metadata(&name)?;
+
+            ids.extend(
+                metadata
+                    .versions
+                    .iter()
+                    .map(|manifest| manifest.package_id()),
+            );
+        }
+
+        Ok(ids)
+    })
+    .await
+    .context("index check was interrupted")?
+}
+
+async fn is_indexed(indexes: Indexes, id: PackageId) -> anyhow::Result<bool> {
+    rocket::tokio::task::spawn_blocking(move || {
+        let index = match indexes.lookup(id.name().scope()) {
+            Some(index) => index,
+            None => return Ok(false),
+        };
+
+        if !index.package_exists(id.name())? {
+            return Ok(false);
+        }
+
+        let metadata = index.get_package_metadata(id.name())?;
+
+        Ok(metadata
+            .versions
+            .iter()
+            .any(|manifest| manifest.package.version == *id.version()))
+    })
+    .await
+    .context("index check was interrupted")?
+}
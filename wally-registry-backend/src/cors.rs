@@ -0,0 +1,103 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Header, Status};
+use rocket::{Request, Response};
+
+/// Matches any origin. Browsers never send credentials to an origin allowed
+/// this way, so it's only useful for public reads.
+pub const ANY_ORIGIN: &str = "*";
+
+const ALLOWED_METHODS: &str = "GET, POST, PUT, DELETE, OPTIONS";
+const ALLOWED_HEADERS: &str = "Authorization, Accept, Content-Type, Wally-Version";
+const EXPOSED_HEADERS: &str =
+    "Content-Digest, Retry-After, Wally-Signature, Wally-Yanked, X-Request-Id, X-Wally-Checksum";
+
+/// How long, in seconds, browsers can reuse the answer to a preflight request.
+const PREFLIGHT_MAX_AGE: u32 = 3600;
+
+/// Adds CORS headers to responses for requests coming from an allowed origin,
+/// so that browser-based clients can talk to the registry. With no allowed
+/// origins, no headers are added and browsers block cross-origin requests.
+pub struct Cors {
+    origins: Vec<String>,
+}
+
+impl Cors {
+    pub fn new(origins: Vec<String>) -> Self {
+        Self { origins }
+    }
+
+    /// Origins listed exactly are echoed back and may send credentials. Other
+    /// origins only get in through `*`, which browsers won't send credentials
+    /// to. The `Origin` header is never reflected unless it's in the list.
+    fn allow_origin(&self, origin: &str) -> Option<AllowedOrigin> {
+        if self.origins.iter().any(|allowed| allowed == origin) {
+            Some(AllowedOrigin::Exact)
+        } else if self.origins.iter().any(|allowed| allowed == ANY_ORIGIN) {
+            Some(AllowedOrigin::Any)
+        } else {
+            None
+        }
+    }
+}
+
+enum AllowedOrigin {
+    Exact,
+    Any,
+}
+
+#[rocket::async_trait]
+impl Fairing for Cors {
+    fn info(&self) -> Info {
+        Info {
+            name: "Add CORS headers to responses",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if self.origins.is_empty() {
+            return;
+        }
+
+        // Which origin is allowed depends on the request, so caches mustn't
+        // hand one origin's response to another.
+        response.adjoin_header(Header::new("Vary", "Origin"));
+
+        let origin = match request.headers().get_one("Origin") {
+            Some(origin) => origin,
+            None => return,
+        };
+
+        match self.allow_origin(origin) {
+            Some(AllowedOrigin::Exact) => {
+                response.set_header(Header::new(
+                    "Access-Control-Allow-Origin",
+                    origin.to_owned(),
+                ));
+                response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
+            }
+            Some(AllowedOrigin::Any) => {
+                response.set_header(Header::new("Access-Control-Allow-Origin", ANY_ORIGIN));
+            }
+            None => return,
+        }
+
+        response.set_header(Header::new("Access-Control-Allow-Methods", ALLOWED_METHODS));
+        response.set_header(Header::new("Access-Control-Allow-Headers", ALLOWED_HEADERS));
+        response.set_header(Header::new(
+            "Access-Control-Expose-Headers",
+            EXPOSED_HEADERS,
+        ));
+        response.set_header(Header::new(
+            "Access-Control-Max-Age",
+            PREFLIGHT_MAX_AGE.to_string(),
+        ));
+    }
+}
+
+/// Answers CORS preflight requests. The headers themselves come from the
+/// `Cors` fairing.
+#[options("/<_..>")]
+pub fn cors_options() -> Status {
+    Status::NoContent
+}
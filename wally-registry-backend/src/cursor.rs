@@ -0,0 +1,117 @@
+//! Cursors for paging through listings. A cursor holds the last entry of the
+//! page it came with, so the next page starts after that entry wherever it
+//! ends up, and packages published or removed in between don't shift pages
+//! the way offsets would.
+//!
+//! Cursors are opaque to clients: the position is serialized and signed with
+//! an HMAC, so a cursor that's been edited or made up is rejected instead of
+//! being trusted.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use anyhow::format_err;
+use hmac::{Hmac, Mac, NewMac};
+use rocket::http::Status;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::error::{ApiErrorStatus, Error};
+
+#[derive(Serialize, Deserialize)]
+struct Payload<T> {
+    /// Which listing the cursor is for, like `search`.
+    kind: String,
+
+    /// What was listed, like the search query, so that a cursor can't be
+    /// carried over to a different listing.
+    listing: String,
+
+    after: T,
+}
+
+pub struct Cursors {
+    key: Vec<u8>,
+}
+
+impl Cursors {
+    /// Signs cursors with `secret`, or without one, with a key made up at
+    /// launch that every instance of the registry has its own of.
+    pub fn new(secret: Option<&str>) -> Self {
+        let key = match secret {
+            Some(secret) => secret.as_bytes().to_vec(),
+            None => random_key(),
+        };
+
+        Self { key }
+    }
+
+    /// A cursor for the entries of `listing` after `after`.
+    pub fn encode<T: Serialize + ?Sized>(&self, kind: &str, listing: &str, after: &T) -> String {
+        let payload = Payload {
+            kind: kind.to_owned(),
+            listing: listing.to_owned(),
+            after,
+        };
+        let payload = serde_json::to_vec(&payload).expect("cursors can be serialized");
+
+        let mut mac = self.mac();
+        mac.update(&payload);
+        let signature = mac.finalize().into_bytes();
+
+        format!(
+            "{}.{}",
+            base64::encode_config(&payload, base64::URL_SAFE_NO_PAD),
+            base64::encode_config(&signature, base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    /// The position a cursor made by `encode` holds, if it's for `listing`
+    /// and hasn't been changed.
+    pub fn decode<T: DeserializeOwned>(
+        &self,
+        kind: &str,
+        listing: &str,
+        cursor: &str,
+    ) -> Result<T, Error> {
+        let invalid = || {
+            format_err!("this cursor is invalid. Start again from the first page.")
+                .status(Status::BadRequest)
+                .code("invalid_cursor")
+        };
+
+        let (payload, signature) = cursor.split_once('.').ok_or_else(invalid)?;
+        let payload =
+            base64::decode_config(payload, base64::URL_SAFE_NO_PAD).map_err(|_| invalid())?;
+        let signature =
+            base64::decode_config(signature, base64::URL_SAFE_NO_PAD).map_err(|_| invalid())?;
+
+        let mut mac = self.mac();
+        mac.update(&payload);
+        mac.verify(&signature).map_err(|_| invalid())?;
+
+        let payload: Payload<T> = serde_json::from_slice(&payload).map_err(|_| invalid())?;
+
+        if payload.kind != kind || payload.listing != listing {
+            return Err(format_err!("this cursor is for a different listing")
+                .status(Status::BadRequest)
+                .code("invalid_cursor"));
+        }
+
+        Ok(payload.after)
+    }
+
+    fn mac(&self) -> Hmac<Sha256> {
+        Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length")
+    }
+}
+
+/// 32 random bytes. The standard library seeds every `RandomState` from the
+/// operating system's random numbers, which saves depending on a crate just
+/// for this.
+fn random_key() -> Vec<u8> {
+    (0..4)
+        .flat_map(|_| RandomState::new().build_hasher().finish().to_le_bytes())
+        .collect()
+}
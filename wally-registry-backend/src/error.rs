@@ -13,6 +13,7 @@ use serde::Serialize;
 
 pub trait ApiErrorContext<T> {
     fn status(self, status: Status) -> Result<T, Error>;
+    fn code(self, code: &'static str) -> Result<T, Error>;
 }
 
 impl<T, E> ApiErrorContext<T> for Result<T, E>
@@ -22,6 +23,10 @@ where
     fn status(self, status: Status) -> Result<T, Error> {
         self.map_err(|err| err.into().status(status))
     }
+
+    fn code(self, code: &'static str) -> Result<T, Error> {
+        self.map_err(|err| err.into().code(code))
+    }
 }
 
 pub trait ApiErrorStatus {
@@ -44,11 +49,15 @@ where
 pub struct Error {
     message: String,
     status: Status,
+    code: Option<&'static str>,
+    retry_after: Option<u64>,
+    headers: Vec<(&'static str, String)>,
 }
 
 #[derive(Serialize)]
 struct ErrorResponse {
     message: String,
+    code: &'static str,
 }
 
 impl Error {
@@ -56,6 +65,53 @@ impl Error {
         self.status = status;
         self
     }
+
+    /// Set the machine-readable code clients can match on to tell errors
+    /// apart, like `version_exists`. Codes are part of the API, so they
+    /// shouldn't change once they've been released.
+    pub fn code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    /// The status this error is sent with.
+    pub fn http_status(&self) -> Status {
+        self.status
+    }
+
+    /// The code of this error, falling back to one based on its status for
+    /// errors that weren't given one.
+    fn code_or_default(&self) -> &'static str {
+        self.code.unwrap_or_else(|| match self.status.code {
+            400 => "bad_request",
+            401 => "unauthorized",
+            403 => "forbidden",
+            404 => "not_found",
+            409 => "conflict",
+            413 => "payload_too_large",
+            416 => "range_not_satisfiable",
+            426 => "upgrade_required",
+            429 => "rate_limited",
+            422 => "unprocessable",
+            502 => "bad_gateway",
+            503 => "unavailable",
+            504 => "timeout",
+            _ => "internal_error",
+        })
+    }
+
+    /// Tell the client how many seconds to wait before trying again.
+    pub fn retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds);
+        self
+    }
+
+    /// Add a header to the response, for errors that need to tell the client
+    /// more than a message can, like the size of a package behind a 416.
+    pub fn header(mut self, name: &'static str, value: String) -> Self {
+        self.headers.push((name, value));
+        self
+    }
 }
 
 impl<E> From<E> for Error
@@ -66,6 +122,9 @@ where
         Self {
             message: format!("{:?}", error.into()),
             status: Status::InternalServerError,
+            code: None,
+            retry_after: None,
+            headers: Vec::new(),
         }
     }
 }
@@ -82,6 +141,7 @@ where
 impl<'r> Responder<'r, 'static> for Error {
     fn respond_to(self, _request: &'r Request<'_>) -> rocket::response::Result<'static> {
         let response = ErrorResponse {
+            code: self.code_or_default(),
             message: self.message,
         };
         let output = serde_json::to_string(&response).unwrap();
@@ -89,10 +149,20 @@ impl<'r> Responder<'r, 'static> for Error {
         // TODO: Look at request's `Accept` header and return a different
         // content type if applicable.
 
-        Response::build()
+        let mut response = Response::build();
+        response
             .sized_body(output.len(), Cursor::new(output))
             .header(ContentType::new("application", "json"))
-            .status(self.status)
-            .ok()
+            .status(self.status);
+
+        if let Some(seconds) = self.retry_after {
+            response.raw_header("Retry-After", seconds.to_string());
+        }
+
+        for (name, value) in self.headers {
+            response.raw_header(name, value);
+        }
+
+        response.ok()
     }
 }
@@ -0,0 +1,158 @@
+//! The versions published most recently, for "what's new" pages. They're
+//! served as JSON, or as an Atom feed for people subscribing in a feed reader.
+//!
+//! Versions are found through the publish times kept in the index, so
+//! versions published before the index recorded them never show up.
+
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use libwally::{package_id::PackageId, package_index::PackageIndex, package_name::PackageName};
+use serde::Serialize;
+use time::OffsetDateTime;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RecentVersion {
+    pub package: PackageId,
+
+    /// When the version was published, in seconds since the Unix epoch.
+    pub published_at: u64,
+
+    /// Who published the version, like a GitHub login, if the index knows.
+    pub publisher: Option<String>,
+
+    pub description: Option<String>,
+}
+
+/// The `limit` most recently published versions of `packages`, newest first.
+/// Yanked versions are left out.
+pub fn recent_versions(
+    packages: &[(PackageName, &Arc<PackageIndex>)],
+    limit: usize,
+) -> anyhow::Result<Vec<RecentVersion>> {
+    let mut versions = Vec::new();
+
+    for (name, index) in packages {
+        let metadata = index.get_package_metadata(name)?;
+
+        for manifest in &metadata.versions {
+            let version = &manifest.package.version;
+
+            if metadata.is_yanked(version) {
+                continue;
+            }
+
+            if let Some(published_at) = metadata.published_at(version) {
+                versions.push(RecentVersion {
+                    package: manifest.package_id(),
+                    published_at,
+                    publisher: metadata.publisher(version).map(str::to_owned),
+                    description: manifest.package.description.clone(),
+                });
+            }
+        }
+    }
+
+    // Versions published in the same second are ordered by name and then
+    // version, highest first, so that the feed doesn't shuffle between
+    // requests.
+    versions.sort_by(|a, b| {
+        b.published_at
+            .cmp(&a.published_at)
+            .then_with(|| b.package.cmp(&a.package))
+    });
+    versions.truncate(limit);
+
+    Ok(versions)
+}
+
+/// Renders `versions` as an Atom feed. `id` names the feed, and has to stay
+/// the same for readers to recognize it.
+pub fn atom_feed(title: &str, id: &str, versions: &[RecentVersion]) -> String {
+    let updated = versions
+        .first()
+        .map(|version| version.published_at)
+        .unwrap_or_else(unix_time);
+
+    let mut feed = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    feed.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    feed.push_str(&format!("  <title>{}</title>\n", escape(title)));
+    feed.push_str(&format!("  <id>{}</id>\n", escape(id)));
+    feed.push_str(&format!("  <updated>{}</updated>\n", rfc3339(updated)));
+    feed.push_str("  <author><name>Wally</name></author>\n");
+
+    for version in versions {
+        let package = version.package.to_string();
+
+        feed.push_str("  <entry>\n");
+        feed.push_str(&format!(
+            "    <title>{} {}</title>\n",
+            escape(&version.package.name().to_string()),
+            escape(&version.package.version().to_string())
+        ));
+        feed.push_str(&format!("    <id>urn:wally:{}</id>\n", escape(&package)));
+        feed.push_str(&format!(
+            "    <updated>{}</updated>\n",
+            rfc3339(version.published_at)
+        ));
+
+        if let Some(publisher) = &version.publisher {
+            feed.push_str(&format!(
+                "    <author><name>{}</name></author>\n",
+                escape(publisher)
+            ));
+        }
+
+        let summary = match &version.description {
+            Some(description) => description.clone(),
+            None => format!("{} was published", package),
+        };
+        feed.push_str(&format!("    <summary>{}</summary>\n", escape(&summary)));
+        feed.push_str("  </entry>\n");
+    }
+
+    feed.push_str("</feed>\n");
+    feed
+}
+
+/// Formats a time in seconds since the Unix epoch like
+/// `2021-03-04T05:06:07Z`.
+fn rfc3339(seconds: u64) -> String {
+    let time =
+        OffsetDateTime::from_unix_timestamp(seconds as i64).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        time.year(),
+        time.month() as u8,
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    )
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for char in text.chars() {
+        match char {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            char => escaped.push(char),
+        }
+    }
+
+    escaped
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
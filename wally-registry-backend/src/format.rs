@@ -0,0 +1,222 @@
+//! Serves metadata and search results as MessagePack to clients that ask for
+//! it with `Accept: application/msgpack`, since it's smaller and quicker to
+//! parse than JSON. Everyone else gets JSON, as before.
+
+use std::convert::Infallible;
+use std::io::Cursor;
+
+use futures::stream::{BoxStream, StreamExt};
+use rocket::http::{ContentType, Header};
+use rocket::request::{FromRequest, Outcome};
+use rocket::response::stream::ReaderStream;
+use rocket::response::Responder;
+use rocket::{Request, Response};
+use serde::Serialize;
+
+use crate::error::Error;
+
+const MSGPACK_TYPES: &[&str] = &["application/msgpack", "application/x-msgpack"];
+const TOML_TYPES: &[&str] = &["application/toml", "text/toml"];
+const ATOM_TYPES: &[&str] = &["application/atom+xml"];
+const NDJSON_TYPES: &[&str] = &["application/x-ndjson", "application/ndjson"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+    Json,
+    MsgPack,
+}
+
+impl Format {
+    /// Picks MessagePack if the `Accept` header prefers it to JSON, or lists
+    /// it without listing JSON. Anything else gets JSON.
+    pub fn negotiate(accept: &str) -> Self {
+        let msgpack = quality(accept, MSGPACK_TYPES);
+        let json = quality(accept, &["application/json"]);
+
+        match (msgpack, json) {
+            (Some(msgpack), Some(json)) if msgpack > 0.0 && msgpack > json => Format::MsgPack,
+            (Some(msgpack), None) if msgpack > 0.0 => Format::MsgPack,
+            _ => Format::Json,
+        }
+    }
+
+    /// Serializes `value` in this format, ready to be hashed for an `ETag`
+    /// and sent.
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Encoded, Error> {
+        let body = match self {
+            Format::Json => serde_json::to_vec(value)?,
+            Format::MsgPack => rmp_serde::to_vec_named(value)?,
+        };
+
+        Ok(Encoded { format: self, body })
+    }
+}
+
+/// The quality an `Accept` header gives to any of `media_types`, or `None` if
+/// it doesn't list them.
+fn quality(accept: &str, media_types: &[&str]) -> Option<f32> {
+    accept.split(',').find_map(|item| {
+        let mut parts = item.split(';').map(str::trim);
+        let media_type = parts.next().unwrap_or_default().to_ascii_lowercase();
+
+        if !media_types.contains(&media_type.as_str()) {
+            return None;
+        }
+
+        let quality = parts
+            .find_map(|param| param.strip_prefix("q="))
+            .and_then(|quality| quality.parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        Some(quality)
+    })
+}
+
+/// Whether the `Accept` header prefers TOML to JSON and MessagePack, for
+/// manifests, which can be sent as they were published.
+pub fn prefers_toml(accept: &str) -> bool {
+    prefers(accept, TOML_TYPES)
+}
+
+/// Whether the `Accept` header prefers an Atom feed to JSON and MessagePack.
+pub fn prefers_atom(accept: &str) -> bool {
+    prefers(accept, ATOM_TYPES)
+}
+
+/// Whether the `Accept` header prefers newline-delimited JSON, for listings
+/// that can be streamed.
+pub fn prefers_ndjson(accept: &str) -> bool {
+    prefers(accept, NDJSON_TYPES)
+}
+
+fn prefers(accept: &str, media_types: &[&str]) -> bool {
+    let preferred = match quality(accept, media_types) {
+        Some(preferred) if preferred > 0.0 => preferred,
+        _ => return false,
+    };
+
+    [
+        quality(accept, MSGPACK_TYPES),
+        quality(accept, &["application/json"]),
+    ]
+    .iter()
+    .flatten()
+    .all(|other| preferred > *other)
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Format {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let format = match request.headers().get_one("Accept") {
+            Some(accept) => Format::negotiate(accept),
+            None => Format::Json,
+        };
+
+        Outcome::Success(format)
+    }
+}
+
+/// Whether a request prefers TOML, as `prefers_toml` decides.
+pub struct WantsToml(pub bool);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for WantsToml {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let wants_toml = request
+            .headers()
+            .get_one("Accept")
+            .map_or(false, prefers_toml);
+
+        Outcome::Success(WantsToml(wants_toml))
+    }
+}
+
+/// Whether a request prefers an Atom feed, as `prefers_atom` decides.
+pub struct WantsAtom(pub bool);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for WantsAtom {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let wants_atom = request
+            .headers()
+            .get_one("Accept")
+            .map_or(false, prefers_atom);
+
+        Outcome::Success(WantsAtom(wants_atom))
+    }
+}
+
+/// Whether a request prefers newline-delimited JSON, as `prefers_ndjson`
+/// decides.
+pub struct WantsNdjson(pub bool);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for WantsNdjson {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let wants_ndjson = request
+            .headers()
+            .get_one("Accept")
+            .map_or(false, prefers_ndjson);
+
+        Outcome::Success(WantsNdjson(wants_ndjson))
+    }
+}
+
+/// A listing sent as newline-delimited JSON, one entry per line, written out
+/// as each entry is read rather than all at once.
+pub struct Ndjson(pub BoxStream<'static, serde_json::Value>);
+
+impl<'r> Responder<'r, 'static> for Ndjson {
+    fn respond_to(self, _request: &'r Request<'_>) -> rocket::response::Result<'static> {
+        let lines = self.0.map(|entry| {
+            let mut line = entry.to_string();
+            line.push('\n');
+            Cursor::new(line)
+        });
+
+        Response::build()
+            .header(ContentType::new("application", "x-ndjson"))
+            .header(Header::new("Vary", "Accept"))
+            .streamed_body(ReaderStream::from(lines))
+            .ok()
+    }
+}
+
+/// A response body that's already been serialized in the format the client
+/// asked for.
+#[derive(Clone)]
+pub struct Encoded {
+    format: Format,
+    body: Vec<u8>,
+}
+
+impl Encoded {
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Encoded {
+    fn respond_to(self, _request: &'r Request<'_>) -> rocket::response::Result<'static> {
+        let content_type = match self.format {
+            Format::Json => ContentType::JSON,
+            Format::MsgPack => ContentType::MsgPack,
+        };
+
+        // Which format is sent depends on the request, so caches mustn't hand
+        // one client's response to another.
+        Response::build()
+            .header(content_type)
+            .header(Header::new("Vary", "Accept"))
+            .sized_body(self.body.len(), Cursor::new(self.body))
+            .ok()
+    }
+}
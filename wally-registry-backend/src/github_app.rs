@@ -0,0 +1,205 @@
+//! Authenticates the registry as a GitHub App, so that permission checks on
+//! the index repository can use short-lived installation tokens instead of a
+//! long-lived personal access token.
+
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{format_err, Context};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use reqwest::Response;
+use rocket::http::Status;
+use rocket::tokio::sync::Mutex;
+use rocket::Request;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::error::{ApiErrorContext, ApiErrorStatus, Error};
+use crate::github_client::TimedClient;
+use crate::metrics::time_github_call;
+use crate::retry::GithubRetry;
+
+/// GitHub gives installation tokens an hour to live. Ours are replaced a
+/// little before that, so that one never expires in the middle of a request.
+const TOKEN_LIFETIME: Duration = Duration::from_secs(60 * 60);
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+/// How many seconds the JWTs used to mint installation tokens are valid for.
+/// GitHub refuses any that are valid for more than ten minutes.
+const JWT_LIFETIME: u64 = 9 * 60;
+
+/// JWTs are backdated by this many seconds in case GitHub's clock is a little
+/// behind ours.
+const JWT_CLOCK_SKEW: u64 = 60;
+
+#[derive(Deserialize, Serialize)]
+pub struct GithubAppConfig {
+    /// The app's numeric id, shown on its settings page.
+    pub app_id: u64,
+
+    /// A PEM file holding one of the app's private keys.
+    pub private_key_path: PathBuf,
+
+    /// The id of the app's installation on the owner of the index repository.
+    /// If not set, it's looked up from the index repository.
+    pub installation_id: Option<u64>,
+}
+
+/// The claims GitHub expects in the JWT an app authenticates with.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AppClaims {
+    pub iat: u64,
+    pub exp: u64,
+    pub iss: String,
+}
+
+pub struct InstallationToken {
+    token: String,
+    minted: Instant,
+}
+
+impl InstallationToken {
+    pub fn new(token: String, minted: Instant) -> Self {
+        Self { token, minted }
+    }
+
+    /// Whether the token can still be used at `now`, without getting too close
+    /// to expiring.
+    pub fn is_fresh(&self, now: Instant) -> bool {
+        now + TOKEN_REFRESH_MARGIN < self.minted + TOKEN_LIFETIME
+    }
+}
+
+#[derive(Deserialize)]
+struct Installation {
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct AccessToken {
+    token: String,
+}
+
+pub struct GithubApp {
+    app_id: u64,
+    installation_id: Option<u64>,
+    key: EncodingKey,
+    token: Mutex<Option<InstallationToken>>,
+}
+
+impl GithubApp {
+    pub fn new(config: &GithubAppConfig) -> anyhow::Result<Self> {
+        let pem = fs_err::read(&config.private_key_path)?;
+        let key = EncodingKey::from_rsa_pem(&pem)
+            .context("the GitHub App private key should be an RSA key in PEM format")?;
+
+        Ok(Self {
+            app_id: config.app_id,
+            installation_id: config.installation_id,
+            key,
+            token: Mutex::new(None),
+        })
+    }
+
+    /// A JWT signed with the app's private key, which GitHub accepts in
+    /// exchange for installation tokens.
+    pub fn jwt(&self) -> anyhow::Result<String> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let claims = AppClaims {
+            iat: now - JWT_CLOCK_SKEW,
+            exp: now + JWT_LIFETIME,
+            iss: self.app_id.to_string(),
+        };
+
+        Ok(jsonwebtoken::encode(
+            &Header::new(Algorithm::RS256),
+            &claims,
+            &self.key,
+        )?)
+    }
+
+    /// A token for the app's installation, which can check permissions on the
+    /// index repository at `repo_api`. Tokens are reused until they're close
+    /// to expiring, and requests that need a new one at the same time wait for
+    /// a single one to be minted.
+    pub async fn installation_token(
+        &self,
+        request: &Request<'_>,
+        client: &TimedClient,
+        retry: &GithubRetry,
+        api_base: &str,
+        repo_api: &str,
+    ) -> Result<String, Error> {
+        let mut cached = self.token.lock().await;
+
+        if let Some(token) = cached
+            .as_ref()
+            .filter(|token| token.is_fresh(Instant::now()))
+        {
+            return Ok(token.token.clone());
+        }
+
+        let jwt = self
+            .jwt()
+            .status(Status::InternalServerError)
+            .code("registry_misconfigured")?;
+
+        let installation_id = match self.installation_id {
+            Some(id) => id,
+            None => {
+                let response = retry
+                    .send(|| {
+                        let response = client
+                            .get(format!("{repo_api}/installation"))
+                            .header("accept", "application/json")
+                            .bearer_auth(&jwt)
+                            .send();
+                        time_github_call(request, "app-installation", response)
+                    })
+                    .await;
+
+                app_response::<Installation>(response).await?.id
+            }
+        };
+
+        let response = retry
+            .send(|| {
+                let response = client
+                    .post(format!(
+                        "{api_base}/app/installations/{installation_id}/access_tokens"
+                    ))
+                    .header("accept", "application/json")
+                    .bearer_auth(&jwt)
+                    .send();
+                time_github_call(request, "app-token", response)
+            })
+            .await;
+
+        let token = app_response::<AccessToken>(response).await?.token;
+        *cached = Some(InstallationToken::new(token.clone(), Instant::now()));
+
+        Ok(token)
+    }
+}
+
+async fn app_response<T: DeserializeOwned>(
+    response: reqwest::Result<Response>,
+) -> Result<T, Error> {
+    response
+        .and_then(Response::error_for_status)
+        .map_err(app_error)?
+        .json::<T>()
+        .await
+        .map_err(app_error)
+}
+
+/// Failing to authenticate as the app is the registry's problem, not the
+/// user's, so it isn't reported as an auth failure.
+fn app_error(err: impl Display) -> Error {
+    format_err!(
+        "Could not authenticate as the registry's GitHub App: {}",
+        err
+    )
+    .status(Status::InternalServerError)
+    .code("github_app_failed")
+}
@@ -0,0 +1,76 @@
+//! The HTTP client used for every call the registry makes to GitHub, or to
+//! GitLab. It's built once at startup so that connections are pooled and
+//! reused, instead of a new pool being set up for each request.
+
+use std::time::Duration;
+
+use reqwest::{Client, IntoUrl, RequestBuilder};
+
+use crate::config::{Config, GithubTimeouts, Timeouts};
+
+const USER_AGENT: &str = concat!("wally-registry/", env!("CARGO_PKG_VERSION"));
+
+/// How long an unused pooled connection is kept open.
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+pub struct GithubClient {
+    client: Client,
+    timeouts: GithubTimeouts,
+}
+
+impl GithubClient {
+    /// Connect timeouts belong to the whole client, so the longer of the two
+    /// configured ones is used. Each request's own timeout still bounds how
+    /// long a call can take, connecting included.
+    pub fn new(config: &Config) -> reqwest::Result<Self> {
+        let timeouts = config.github_timeouts;
+        let connect = timeouts
+            .identity
+            .connect()
+            .max(timeouts.permission.connect());
+
+        let client = Client::builder()
+            .min_tls_version(config.min_tls_version.into())
+            .connect_timeout(connect)
+            .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+            .user_agent(USER_AGENT)
+            .build()?;
+
+        Ok(Self { client, timeouts })
+    }
+
+    /// For checking who a user is and that their token was issued to us.
+    pub fn identity(&self) -> TimedClient {
+        self.with_timeouts(self.timeouts.identity)
+    }
+
+    /// For checking permissions, like on the index repository or in a team.
+    pub fn permission(&self) -> TimedClient {
+        self.with_timeouts(self.timeouts.permission)
+    }
+
+    fn with_timeouts(&self, timeouts: Timeouts) -> TimedClient {
+        TimedClient {
+            client: self.client.clone(),
+            timeout: timeouts.connect() + timeouts.read(),
+        }
+    }
+}
+
+/// The shared client, with every request given the same timeout. Cloning it
+/// shares the connection pool.
+#[derive(Clone)]
+pub struct TimedClient {
+    client: Client,
+    timeout: Duration,
+}
+
+impl TimedClient {
+    pub fn get<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.get(url).timeout(self.timeout)
+    }
+
+    pub fn post<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.post(url).timeout(self.timeout)
+    }
+}
@@ -0,0 +1,158 @@
+//! Keeps track of how much of the registry's own GitHub rate limit is left, so
+//! that once it's nearly used up the registry stops calling GitHub until the
+//! limit resets, instead of making calls that are bound to fail.
+//!
+//! Only calls made with the registry's credentials are tracked. Calls made with
+//! a user's token count against that user's limit, not the registry's.
+//!
+//! GitHub also has secondary rate limits, which aren't reported in headers
+//! ahead of time. Calls refused because of one are told apart from real
+//! refusals, so that clients are asked to try again instead of being denied.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::format_err;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Response, StatusCode};
+use rocket::http::Status;
+use rocket::Request;
+
+use crate::error::{ApiErrorStatus, Error};
+use crate::metrics::Metrics;
+
+/// Once a budget is down to this many calls, new calls are held back until it
+/// resets, leaving what's left for requests that are already under way.
+const RESERVE: u64 = 10;
+
+/// How long to wait after a secondary rate limit that didn't say, which is
+/// what GitHub recommends.
+const SECONDARY_RETRY_AFTER: u64 = 60;
+
+/// Phrases GitHub uses in the message of a secondary rate limit refusal.
+const SECONDARY_MESSAGES: &[&str] = &["secondary rate limit", "abuse detection"];
+
+#[derive(Debug, Clone, Copy)]
+struct Budget {
+    remaining: u64,
+
+    /// When the budget resets, in seconds since the Unix epoch.
+    reset: u64,
+}
+
+/// Each kind of call always uses the same credentials, so each gets its own
+/// budget, named like the call is in metrics.
+#[derive(Default)]
+pub struct GithubRateLimit {
+    budgets: Mutex<BTreeMap<&'static str, Budget>>,
+}
+
+impl GithubRateLimit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remembers the budget GitHub reported in the headers of a response to
+    /// `call`, giving how many calls are left.
+    pub fn observe(&self, call: &'static str, headers: &HeaderMap) -> Option<u64> {
+        let header = |name: &str| headers.get(name)?.to_str().ok()?.trim().parse::<u64>().ok();
+
+        let budget = Budget {
+            remaining: header("x-ratelimit-remaining")?,
+            reset: header("x-ratelimit-reset")?,
+        };
+
+        self.budgets.lock().unwrap().insert(call, budget);
+        Some(budget.remaining)
+    }
+
+    /// Fails with a 503 saying when to try again if any budget is nearly used
+    /// up and hasn't reset yet.
+    pub fn check(&self) -> Result<(), Error> {
+        let now = unix_now();
+        let reset = self
+            .budgets
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|budget| budget.remaining <= RESERVE && budget.reset > now)
+            .map(|budget| budget.reset)
+            .max();
+
+        match reset {
+            Some(reset) => Err(format_err!(
+                "The registry has nearly used up its GitHub rate limit. Try again after it resets."
+            )
+            .status(Status::ServiceUnavailable)
+            .code("github_rate_limited")
+            .retry_after(reset - now)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Records the rate limit GitHub reported for a `call` made with the registry's
+/// own credentials, both for holding back later calls and for metrics.
+pub fn observe_github_rate_limit(
+    request: &Request<'_>,
+    call: &'static str,
+    response: &reqwest::Response,
+) {
+    let remaining = match request.rocket().state::<GithubRateLimit>() {
+        Some(rate_limit) => rate_limit.observe(call, response.headers()),
+        None => return,
+    };
+
+    if let (Some(remaining), Some(metrics)) = (remaining, request.rocket().state::<Arc<Metrics>>())
+    {
+        metrics.set_github_rate_limit_remaining(call, remaining);
+    }
+}
+
+/// Reads a response GitHub refused a call with, giving a 503 that says when to
+/// try again if it was refused because of a secondary rate limit. These come
+/// as a 403 with a `Retry-After` header or a message saying so, or as a 429.
+/// Anything else is a real refusal, and gives `None`.
+pub async fn secondary_rate_limit(response: Response) -> Option<Error> {
+    let status = response.status();
+
+    if status != StatusCode::FORBIDDEN && status != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+
+    let retry_after = response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok()?.trim().parse::<u64>().ok());
+
+    let limited = status == StatusCode::TOO_MANY_REQUESTS
+        || retry_after.is_some()
+        || match response.text().await {
+            Ok(body) => {
+                let body = body.to_lowercase();
+                SECONDARY_MESSAGES
+                    .iter()
+                    .any(|message| body.contains(message))
+            }
+            Err(_) => false,
+        };
+
+    if !limited {
+        return None;
+    }
+
+    Some(
+        format_err!("GitHub is limiting how quickly the registry can call it. Try again later.")
+            .status(Status::ServiceUnavailable)
+            .code("github_rate_limited")
+            .retry_after(retry_after.unwrap_or(SECONDARY_RETRY_AFTER)),
+    )
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,147 @@
+//! A readiness check that makes sure the services the registry depends on can
+//! be reached, for load balancers that should stop sending requests to an
+//! instance that can't serve them.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, format_err, Context};
+use serde::Serialize;
+
+use crate::auth::extract_github_owner_repo;
+use crate::config::Config;
+use crate::github_client::GithubClient;
+use crate::indexes::Indexes;
+use crate::token_cache::{Clock, SystemClock};
+
+/// How long the result of checking GitHub is reused for, so that frequent
+/// health checks don't use up the registry's GitHub rate limit.
+const GITHUB_CHECK_TTL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", content = "message", rename_all = "kebab-case")]
+pub enum CheckStatus {
+    Ok,
+
+    /// The check doesn't apply to how this registry is configured.
+    Skipped(String),
+
+    Failed(String),
+}
+
+impl CheckStatus {
+    pub fn is_healthy(&self) -> bool {
+        !matches!(self, CheckStatus::Failed(_))
+    }
+
+    fn from_result(result: anyhow::Result<()>) -> Self {
+        match result {
+            Ok(()) => CheckStatus::Ok,
+            Err(err) => CheckStatus::Failed(format!("{:#}", err)),
+        }
+    }
+}
+
+/// Remembers the last GitHub check for a short time.
+pub struct GithubHealth {
+    ttl: Duration,
+    clock: Arc<dyn Clock>,
+    last: Mutex<Option<(Instant, CheckStatus)>>,
+}
+
+impl Default for GithubHealth {
+    fn default() -> Self {
+        Self::with_clock(GITHUB_CHECK_TTL, Arc::new(SystemClock))
+    }
+}
+
+impl GithubHealth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_clock(ttl: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            ttl,
+            clock,
+            last: Mutex::new(None),
+        }
+    }
+
+    pub fn cached(&self) -> Option<CheckStatus> {
+        let last = self.last.lock().ok()?;
+
+        match &*last {
+            Some((checked, status)) if self.clock.now().duration_since(*checked) < self.ttl => {
+                Some(status.clone())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn store(&self, status: CheckStatus) {
+        if let Ok(mut last) = self.last.lock() {
+            *last = Some((self.clock.now(), status));
+        }
+    }
+
+    /// Check that the registry's GitHub token can reach the index repository's
+    /// collaborators, which is what GitHub auth needs, reusing a recent result
+    /// if there is one.
+    pub async fn check(&self, config: &Config, github: &GithubClient) -> CheckStatus {
+        if !config.uses_github_auth() {
+            return CheckStatus::Skipped(String::from("GitHub auth is not configured"));
+        }
+
+        let token = match &config.github_token {
+            Some(token) => token,
+            None => return CheckStatus::Skipped(String::from("no GitHub token is configured")),
+        };
+
+        if let Some(status) = self.cached() {
+            return status;
+        }
+
+        let status = CheckStatus::from_result(check_collaborators(config, github, token).await);
+        self.store(status.clone());
+
+        status
+    }
+}
+
+async fn check_collaborators(
+    config: &Config,
+    github: &GithubClient,
+    token: &str,
+) -> anyhow::Result<()> {
+    let (_, owner, repo) = extract_github_owner_repo(config.index_url.as_str())
+        .ok_or_else(|| format_err!("the index URL isn't a GitHub repository"))?;
+
+    let response = github
+        .permission()
+        .get(format!(
+            "{}/repos/{}/{}/collaborators?per_page=1",
+            config.github_api_base(),
+            owner,
+            repo
+        ))
+        .header("accept", "application/json")
+        .bearer_auth(token)
+        .send()
+        .await
+        .context("could not reach GitHub")?;
+
+    if !response.status().is_success() {
+        bail!(
+            "GitHub rejected the registry's token with {}",
+            response.status()
+        );
+    }
+
+    Ok(())
+}
+
+/// Check that every index can be fetched from its remote.
+pub fn check_index(indexes: &Indexes) -> CheckStatus {
+    CheckStatus::from_result(indexes.all().try_for_each(|index| index.update()))
+}
@@ -0,0 +1,75 @@
+//! Keeps the registry's copy of the package index up to date with changes made
+//! to the remote index directly, like owners edited by hand, which would
+//! otherwise only be seen after a restart.
+//!
+//! Refreshes are incremental: only the packages whose files changed between
+//! the old and new commits are read again, so a large index doesn't stall
+//! reads while every package is reparsed.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use libwally::package_index::{IndexRefresh, PackageIndex};
+use serde::Deserialize;
+
+use crate::indexes::Indexes;
+use crate::metadata_cache::MetadataCache;
+
+/// The parts of a Git host's push webhook payload that matter here, like
+/// GitHub's `push` event. Anything else in the payload is ignored.
+#[derive(Debug, Default, Deserialize)]
+pub struct PushEvent {
+    /// The branch that was pushed, like `refs/heads/main`.
+    #[serde(rename = "ref")]
+    pub git_ref: Option<String>,
+
+    /// The commit the branch was pushed to.
+    pub after: Option<String>,
+}
+
+impl PushEvent {
+    /// Whether the push was to a branch other than the index's own, and so
+    /// can't have changed it.
+    pub fn is_other_branch(&self) -> bool {
+        matches!(&self.git_ref, Some(git_ref) if git_ref != "refs/heads/main")
+    }
+}
+
+/// Fetches the latest index from its remote, re-reading only what changed
+/// unless `full` is set, and drops the cached metadata of what did. Git is
+/// blocking, so this runs on a thread of its own instead of holding up other
+/// requests.
+pub async fn refresh_index(
+    index: Arc<PackageIndex>,
+    metadata_cache: &MetadataCache,
+    full: bool,
+    expected_head: Option<String>,
+) -> anyhow::Result<IndexRefresh> {
+    let refresh = rocket::tokio::task::spawn_blocking(move || {
+        if full {
+            index.refresh()
+        } else {
+            index.refresh_incremental(expected_head.as_deref())
+        }
+    })
+    .await
+    .context("index refresh was interrupted")??;
+
+    metadata_cache.refreshed(&refresh);
+    Ok(refresh)
+}
+
+/// Refreshes every index every `interval`, forever. Failed refreshes are
+/// logged and the last good copy of the index keeps being served.
+pub async fn poll_index(indexes: Indexes, metadata_cache: Arc<MetadataCache>, interval: Duration) {
+    loop {
+        rocket::tokio::time::sleep(interval).await;
+
+        for index in indexes.all() {
+            if let Err(err) = refresh_index(Arc::clone(index), &metadata_cache, false, None).await {
+                tracing::warn!(index = %index.url(), error = ?err, "could not refresh package index");
+            }
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Serves scopes from more than one index repository, like a public community
+//! index next to a private one. Every scope is routed to exactly one index, so
+//! its packages, owners, and publishes all live in the same place. Archives
+//! for every index are kept in the registry's one storage backend.
+
+use std::sync::Arc;
+
+use anyhow::format_err;
+use libwally::{package_index::PackageIndex, package_name::PackageName};
+use rocket::http::Status;
+
+use crate::error::{ApiErrorStatus, Error};
+
+/// In a list of scopes an index serves, matches every scope.
+pub const ANY_SCOPE: &str = "*";
+
+#[derive(Clone)]
+struct Route {
+    scopes: Vec<String>,
+    index: Arc<PackageIndex>,
+}
+
+impl Route {
+    fn serves(&self, scope: &str) -> bool {
+        self.scopes
+            .iter()
+            .any(|served| served == ANY_SCOPE || served.eq_ignore_ascii_case(scope))
+    }
+}
+
+/// The indexes the registry serves, and which scopes each one serves.
+/// Cloning is cheap, since the indexes themselves are shared.
+#[derive(Clone)]
+pub struct Indexes {
+    /// Checked in order, before `main`.
+    federated: Vec<Route>,
+
+    /// The index at `index_url`.
+    main: Route,
+}
+
+impl Indexes {
+    /// Serves `scopes` from `index`, the registry's main index.
+    pub fn new(index: Arc<PackageIndex>, scopes: Vec<String>) -> Self {
+        Self {
+            federated: Vec::new(),
+            main: Route { scopes, index },
+        }
+    }
+
+    /// Serves `scopes` from another index, ahead of the main index and of
+    /// any federated index added after this one.
+    pub fn federate(&mut self, index: Arc<PackageIndex>, scopes: Vec<String>) {
+        self.federated.push(Route { scopes, index });
+    }
+
+    /// The index that serves `scope`, if any does.
+    pub fn lookup(&self, scope: &str) -> Option<&Arc<PackageIndex>> {
+        self.routes()
+            .find(|route| route.serves(scope))
+            .map(|route| &route.index)
+    }
+
+    /// Like `lookup`, but a scope no index serves is a 404.
+    pub fn for_scope(&self, scope: &str) -> Result<&Arc<PackageIndex>, Error> {
+        self.lookup(scope).ok_or_else(|| {
+            format_err!("scope {} isn't served by this registry", scope)
+                .status(Status::NotFound)
+                .code("scope_not_served")
+        })
+    }
+
+    /// The index at `index_url`.
+    pub fn main(&self) -> &Arc<PackageIndex> {
+        &self.main.index
+    }
+
+    /// The indexes other than the main one, in the order they're checked.
+    pub fn federated(&self) -> impl Iterator<Item = &Arc<PackageIndex>> {
+        self.federated.iter().map(|route| &route.index)
+    }
+
+    /// Every index, federated ones first.
+    pub fn all(&self) -> impl Iterator<Item = &Arc<PackageIndex>> {
+        self.routes().map(|route| &route.index)
+    }
+
+    /// Every package the registry serves, with the index it's served from,
+    /// in alphabetical order. Packages in an index whose scope is routed to
+    /// a different index are left out.
+    pub fn package_names(&self) -> anyhow::Result<Vec<(PackageName, &Arc<PackageIndex>)>> {
+        let mut names = Vec::new();
+
+        for index in self.all() {
+            for name in index.package_names()? {
+                if matches!(self.lookup(name.scope()), Some(served) if Arc::ptr_eq(served, index)) {
+                    names.push((name, index));
+                }
+            }
+        }
+
+        names.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(names)
+    }
+
+    fn routes(&self) -> impl Iterator<Item = &Route> {
+        self.federated.iter().chain(std::iter::once(&self.main))
+    }
+}
@@ -0,0 +1,184 @@
+//! Checks manifests for things that are allowed, but probably a mistake, so
+//! authors can hear about them before publishing. Nothing here stops a
+//! publish; publishing makes its own checks.
+
+use libwally::manifest::{Dependency, Manifest, Realm};
+use libwally::package_req::PackageReq;
+use semver::Version;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Worth fixing, but the package works as it is.
+    Warning,
+
+    /// The package can't be published as it is, or won't work the way its
+    /// author expects.
+    Error,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Diagnostic {
+    /// Which rule found the problem, to match on.
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// A check on a manifest, giving any problems it finds.
+type Rule = fn(&Manifest) -> Vec<Diagnostic>;
+
+/// Every rule, run in order. Add a rule by writing a function and listing it.
+const RULES: &[Rule] = &[
+    missing_description,
+    missing_license,
+    unbounded_dependencies,
+    dev_dependencies_shipped,
+    private_package,
+    local_dependencies,
+];
+
+/// Runs every rule against `manifest`.
+pub fn lint(manifest: &Manifest) -> Vec<Diagnostic> {
+    RULES.iter().flat_map(|rule| rule(manifest)).collect()
+}
+
+fn missing_description(manifest: &Manifest) -> Vec<Diagnostic> {
+    match &manifest.package.description {
+        Some(description) if !description.trim().is_empty() => Vec::new(),
+        _ => vec![Diagnostic {
+            rule: "missing_description",
+            severity: Severity::Warning,
+            message: String::from(
+                "the package has no description, so it's harder to find in search",
+            ),
+        }],
+    }
+}
+
+fn missing_license(manifest: &Manifest) -> Vec<Diagnostic> {
+    match &manifest.package.license {
+        Some(license) if !license.trim().is_empty() => Vec::new(),
+        _ => vec![Diagnostic {
+            rule: "missing_license",
+            severity: Severity::Warning,
+            message: String::from(
+                "the package has no license, so others may not be allowed to use it",
+            ),
+        }],
+    }
+}
+
+/// Requirements like `*` or `>=1.0.0` accept every future major version,
+/// which are allowed to break the package.
+fn unbounded_dependencies(manifest: &Manifest) -> Vec<Diagnostic> {
+    // A version no package will reach. Matching it means there's no upper
+    // bound, however the requirement was written.
+    let far_future = Version::new(u64::MAX, 0, 0);
+
+    all_dependencies(manifest)
+        .filter(|(_, _, req)| req.version_req().matches(&far_future))
+        .map(|(realm, alias, req)| Diagnostic {
+            rule: "unbounded_version_range",
+            severity: Severity::Warning,
+            message: format!(
+                "{} dependency {} = \"{}\" has no upper bound, so a breaking release of it \
+                 could be installed",
+                realm_name(realm),
+                alias,
+                req
+            ),
+        })
+        .collect()
+}
+
+/// Dev dependencies are only for working on the package itself. Listing one
+/// as a regular dependency too installs it for everyone who uses the package.
+fn dev_dependencies_shipped(manifest: &Manifest) -> Vec<Diagnostic> {
+    manifest
+        .dev_dependencies
+        .iter()
+        .filter_map(|(alias, dev_dependency)| Some((alias, dev_dependency.registry()?)))
+        .filter_map(|(alias, dev_req)| {
+            let (realm, shipped_alias, _) = all_dependencies(manifest)
+                .find(|(realm, _, req)| *realm != Realm::Dev && req.name() == dev_req.name())?;
+
+            Some(Diagnostic {
+                rule: "dev_dependency_shipped",
+                severity: Severity::Warning,
+                message: format!(
+                    "dev dependency {} ({}) is also the {} dependency {}, so it's installed \
+                     for everyone using this package",
+                    alias,
+                    dev_req.name(),
+                    realm_name(realm),
+                    shipped_alias
+                ),
+            })
+        })
+        .collect()
+}
+
+fn private_package(manifest: &Manifest) -> Vec<Diagnostic> {
+    match manifest.package.private {
+        true => vec![Diagnostic {
+            rule: "private_package",
+            severity: Severity::Error,
+            message: String::from(
+                "the package is marked private, so `wally publish` will refuse to publish it",
+            ),
+        }],
+        false => Vec::new(),
+    }
+}
+
+/// Path and Git dependencies only work on the author's machine, and anyone
+/// using the package would have to install them from the registry.
+fn local_dependencies(manifest: &Manifest) -> Vec<Diagnostic> {
+    all_sources(manifest)
+        .filter(|(_, _, dependency)| dependency.registry().is_none())
+        .map(|(realm, alias, dependency)| Diagnostic {
+            rule: "local_dependency",
+            severity: Severity::Error,
+            message: format!(
+                "{} dependency {} comes from {}, so the package can't be published until it \
+                 depends on a published version instead",
+                realm_name(realm),
+                alias,
+                dependency
+            ),
+        })
+        .collect()
+}
+
+/// Every registry dependency, since those are the ones with versions to check.
+fn all_dependencies(manifest: &Manifest) -> impl Iterator<Item = (Realm, &String, &PackageReq)> {
+    all_sources(manifest)
+        .filter_map(|(realm, alias, dependency)| Some((realm, alias, dependency.registry()?)))
+}
+
+fn all_sources(manifest: &Manifest) -> impl Iterator<Item = (Realm, &String, &Dependency)> {
+    let shared = manifest
+        .dependencies
+        .iter()
+        .map(|(alias, dependency)| (Realm::Shared, alias, dependency));
+    let server = manifest
+        .server_dependencies
+        .iter()
+        .map(|(alias, dependency)| (Realm::Server, alias, dependency));
+    let dev = manifest
+        .dev_dependencies
+        .iter()
+        .map(|(alias, dependency)| (Realm::Dev, alias, dependency));
+
+    shared.chain(server).chain(dev)
+}
+
+fn realm_name(realm: Realm) -> &'static str {
+    match realm {
+        Realm::Shared => "shared",
+        Realm::Server => "server",
+        Realm::Dev => "dev",
+    }
+}
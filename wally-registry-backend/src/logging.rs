@@ -0,0 +1,74 @@
+//! Sets up structured logging. Requests, authentication, and publishes are
+//! logged in spans that carry the request's id, so that everything logged
+//! while handling one request can be found together.
+
+use anyhow::{format_err, Context};
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    /// Human-readable lines, for reading in a terminal.
+    Pretty,
+
+    /// One JSON object per line, for log collectors.
+    Json,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        LogFormat::Pretty
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LogConfig {
+    #[serde(default)]
+    pub format: LogFormat,
+
+    /// Which logs to keep, like `info` or `wally_registry_backend=debug,info`,
+    /// in the same syntax as `RUST_LOG`.
+    #[serde(default = "default_level")]
+    pub level: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::default(),
+            level: default_level(),
+        }
+    }
+}
+
+impl LogConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.filter().map(|_| ())
+    }
+
+    fn filter(&self) -> anyhow::Result<EnvFilter> {
+        EnvFilter::try_new(&self.level).with_context(|| format!("invalid log level {}", self.level))
+    }
+}
+
+fn default_level() -> String {
+    "info".to_owned()
+}
+
+/// Installs the global logger. Logs from crates using `log`, like Rocket, are
+/// passed through it too. Each span is logged when it closes, with the fields
+/// recorded on it by then.
+pub fn init(config: &LogConfig) -> anyhow::Result<()> {
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(config.filter()?)
+        .with_span_events(FmtSpan::CLOSE);
+
+    let result = match config.format {
+        LogFormat::Pretty => builder.try_init(),
+        LogFormat::Json => builder.json().try_init(),
+    };
+
+    result.map_err(|err| format_err!(err))
+}
@@ -0,0 +1,94 @@
+//! Looks up the GitHub logins of scope owners, who are only recorded in the
+//! index by their numeric user ids. Owners from the registry's other GitHub
+//! hosts are looked up on their own host.
+
+use std::time::Duration;
+
+use libwally::package_index::OwnerId;
+use moka::sync::Cache;
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::github_client::GithubClient;
+
+/// Logins rarely change, so they're remembered for a while to keep listing
+/// owners from using up the registry's GitHub rate limit.
+const LOGIN_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+const LOGIN_CACHE_SIZE: u64 = 10_000;
+
+#[derive(Deserialize)]
+struct GithubUser {
+    login: String,
+}
+
+pub struct GithubLogins {
+    cache: Cache<OwnerId, String>,
+}
+
+impl GithubLogins {
+    pub fn new() -> Self {
+        Self {
+            cache: Cache::builder()
+                .max_capacity(LOGIN_CACHE_SIZE)
+                .time_to_live(LOGIN_CACHE_TTL)
+                .build(),
+        }
+    }
+
+    /// The login of the GitHub user with `id`, if GitHub can tell us. This is
+    /// only ever extra information, so failures are logged and give `None`.
+    pub async fn login(
+        &self,
+        config: &Config,
+        github: &GithubClient,
+        id: &OwnerId,
+    ) -> Option<String> {
+        if let Some(login) = self.cache.get(id) {
+            return Some(login);
+        }
+
+        let api_base = match config.owner_api_base(id) {
+            Some(api_base) => api_base,
+            None => {
+                tracing::warn!(%id, "owner is from a GitHub host that isn't configured");
+                return None;
+            }
+        };
+
+        let mut request = github
+            .identity()
+            .get(format!("{}/user/{}", api_base, id.id()))
+            .header("accept", "application/json");
+
+        // The registry's token is only good on the main host.
+        if let (None, Some(token)) = (id.provider(), &config.github_token) {
+            request = request.bearer_auth(token);
+        }
+
+        let user = match request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+        {
+            Ok(response) => response.json::<GithubUser>().await,
+            Err(err) => Err(err),
+        };
+
+        match user {
+            Ok(user) => {
+                self.cache.insert(id.clone(), user.login.clone());
+                Some(user.login)
+            }
+            Err(err) => {
+                tracing::warn!(%id, error = %err, "could not look up GitHub login");
+                None
+            }
+        }
+    }
+}
+
+impl Default for GithubLogins {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,209 +1,3495 @@
 #[macro_use]
 extern crate rocket;
 
+mod audit;
 mod auth;
+mod auth_throttle;
+mod blocklist;
+mod compression;
+mod conditional;
 mod config;
+mod confusables;
+mod consistency;
+mod cors;
+mod cursor;
 mod error;
+mod feed;
+mod format;
+mod github_app;
+mod github_client;
+mod github_rate_limit;
+mod health;
+mod index_refresh;
+mod indexes;
+mod lint;
+mod logging;
+mod logins;
+mod maintenance;
+mod metadata_cache;
+mod metrics;
+mod owner_audit;
+mod persistence;
+mod range;
+mod rate_limit;
+mod read_tokens;
+mod request_id;
+mod retry;
+mod scope_lock;
 mod search;
+mod signing;
+mod spdx;
+mod stats;
 mod storage;
+mod teams;
+mod timeout;
+mod token_cache;
+mod trusted_publishing;
+mod webhook;
 
 #[cfg(test)]
 mod tests;
 
 use std::convert::TryInto;
 use std::io::{Cursor, Read, Seek};
-use std::sync::RwLock;
+use std::net::SocketAddr;
+use std::path::{Component, Path};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::{format_err, Context};
+use anyhow::{bail, format_err, Context};
 use figment::{
-    providers::{Env, Format, Toml},
+    providers::{Env, Format as _, Toml},
     Figment,
 };
+use futures::stream::{self, StreamExt};
 use libwally::{
-    manifest::{Manifest, MANIFEST_FILE_NAME},
+    index_lock::IndexLock,
+    manifest::{Dependency, Manifest, Realm, MANIFEST_FILE_NAME},
+    package_contents::PackageContents,
     package_id::PackageId,
-    package_index::PackageIndex,
-    package_name::PackageName,
+    package_index::{ArchiveChecksums, OwnerId, PackageIndex, PackageMetadata},
+    package_integrity::PackageIntegrity,
+    package_name::{canonical_scope, PackageName},
 };
-use rocket::fairing::{Fairing, Info, Kind};
 use rocket::http::Header;
 use rocket::request::{FromRequest, Outcome};
-use rocket::response::stream::ReaderStream;
+use rocket::response::Responder;
 use rocket::serde::json::Json;
+use rocket::tokio::io::AsyncReadExt;
 use rocket::{
     data::{Data, ToByteUnit},
     fairing::AdHoc,
-    http::{ContentType, Status},
-    response::content,
+    http::{ContentType, Method, Status},
     State,
 };
 use rocket::{Build, Request, Response};
-use semver::Version;
+use semver::{Version, VersionReq};
+use serde::Deserialize;
 use serde_json::json;
+use sha2::{Digest, Sha256};
 use storage::StorageMode;
+use url::Url;
 use zip::ZipArchive;
 
-use crate::auth::{ReadAccess, WriteAccess};
-use crate::config::Config;
+use crate::audit::{AuditAction, AuditEvent, AuditLog};
+use crate::auth::{
+    check_scope_allowed, AdminAccess, MutatingAccess, MutatingAdminAccess, ReadAccess, Whoami,
+    WriteAccess, WritePermission,
+};
+use crate::auth_throttle::AuthThrottle;
+use crate::blocklist::{Blocklist, BlocklistEntries};
+use crate::compression::ResponseCompression;
+use crate::conditional::{etag, IfNoneMatch, Tagged};
+use crate::config::{Config, IndexLockConfig, UnknownWallyVersion};
+use crate::confusables::find_confusable;
+use crate::consistency::{check_consistency, ConsistencyReport};
+use crate::cors::{cors_options, Cors};
+use crate::cursor::Cursors;
 use crate::error::{ApiErrorContext, ApiErrorStatus, Error};
-use crate::search::SearchBackend;
-use crate::storage::{GcsStorage, LocalStorage, StorageBackend, StorageOutput};
+use crate::feed::{atom_feed, recent_versions};
+use crate::format::{Encoded, Format, Ndjson, WantsAtom, WantsNdjson, WantsToml};
+use crate::github_app::GithubApp;
+use crate::github_client::GithubClient;
+use crate::github_rate_limit::GithubRateLimit;
+use crate::health::{check_index, GithubHealth};
+use crate::index_refresh::{poll_index, refresh_index, PushEvent};
+use crate::indexes::Indexes;
+use crate::lint::lint;
+use crate::logins::GithubLogins;
+use crate::maintenance::MaintenanceMode;
+use crate::metadata_cache::{CacheKey, CachedResponse, MetadataCache};
+use crate::metrics::{Metrics, RequestMetrics};
+use crate::owner_audit::{audit_scope_owners, OwnerAudit};
+use crate::persistence::Persistence;
+use crate::range::RangeHeader;
+use crate::rate_limit::RateLimiter;
+use crate::read_tokens::ReadTokenClaims;
+use crate::request_id::{RequestId, RequestIds};
+use crate::scope_lock::ScopeLocks;
+use crate::search::{find_packages, latest_version, SearchBackend, SearchPosition};
+use crate::signing::{PublicKey, PublishSignature, SignedArchive};
+use crate::spdx::is_valid_license;
+use crate::stats::StatsStore;
+use crate::storage::{
+    self_test, ByteRange, GcsStorage, LocalStorage, StorageBackend, StoredPackage,
+};
+use crate::teams::GithubTeams;
+use crate::timeout::with_timeouts;
+use crate::token_cache::TokenCache;
+use crate::trusted_publishing::IssuerKeys;
+use crate::webhook::{PublishEvent, Webhooks};
+
+#[cfg(feature = "s3-storage")]
+use crate::storage::{s3_region, S3Credentials, S3Storage};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const DOCS_URL: &str = "https://github.com/UpliftGames/wally";
+
+/// The version of the registry API, bumped when it changes in a way that
+/// clients can't just ignore.
+const API_VERSION: u32 = 1;
+
+/// Everything this registry can do, so that clients can check before relying
+/// on something that older registries don't have.
+const CAPABILITIES: &[&str] = &[
+    "can-publish",
+    "lint",
+    "package-contents",
+    "package-deprecate",
+    "package-integrity",
+    "package-manifest",
+    "package-metadata",
+    "package-metadata-batch",
+    "package-range-requests",
+    "package-search",
+    "package-stats",
+    "package-unyank",
+    "package-yank",
+    "publish",
+    "read-tokens",
+    "recent",
+    "scope-activity",
+    "scope-owners",
+    "signing-keys",
+];
+
+/// How many activity events to return when a request doesn't ask for a
+/// specific number, and the most it can ask for.
+const DEFAULT_ACTIVITY_PAGE: usize = 50;
+const MAX_ACTIVITY_PAGE: usize = 200;
+
+/// How many search results to return when a request doesn't ask for a specific
+/// number, and the most it can ask for.
+const DEFAULT_SEARCH_PAGE: usize = 20;
+const MAX_SEARCH_PAGE: usize = 100;
+
+/// How many recently published versions to list when a request doesn't ask
+/// for a specific number, and the most it can ask for.
+const DEFAULT_RECENT_PAGE: usize = 20;
+const MAX_RECENT_PAGE: usize = 100;
+
+/// The longest deprecation message a version can be given. Messages are kept
+/// in the index, next to every version they're set on.
+const MAX_DEPRECATION_MESSAGE: usize = 1000;
+
+/// A JSON response that clients and proxies are allowed to cache.
+#[derive(Responder)]
+struct CacheableJson {
+    inner: Json<serde_json::Value>,
+    cache_control: Header<'static>,
+}
+
+impl CacheableJson {
+    fn new(value: serde_json::Value, max_age: u32) -> Self {
+        Self {
+            inner: Json(value),
+            cache_control: Header::new("Cache-Control", format!("public, max-age={}", max_age)),
+        }
+    }
+}
+
+/// The contents of a package, along with whether the version has been yanked.
+struct PackageDownload {
+    body: PackageBody,
+    yanked: bool,
+
+    /// The hex-encoded SHA-256 hash recorded when the version was published,
+    /// which clients can check what they downloaded against.
+    sha256: Option<String>,
+
+    /// The minisign signature the version was published with, if it was
+    /// signed.
+    signature: Option<String>,
+}
+
+enum PackageBody {
+    /// The package streamed from storage rather than read into memory first.
+    Stored {
+        package: StoredPackage,
+
+        /// The part of the package being sent, and the size of the whole
+        /// thing, when only part of it was asked for.
+        range: Option<(ByteRange, u64)>,
+    },
+
+    /// A short-lived URL to download the package from storage directly, so
+    /// that its bytes don't go through the registry.
+    Redirect(String),
+}
+
+impl<'r> Responder<'r, 'static> for PackageDownload {
+    fn respond_to(self, _request: &'r Request<'_>) -> rocket::response::Result<'static> {
+        let mut response = Response::build();
+        response.raw_header("Wally-Yanked", self.yanked.to_string());
+
+        let range = match self.body {
+            PackageBody::Stored { package, range } => {
+                response
+                    .header(ContentType::Binary)
+                    .raw_header("Accept-Ranges", "bytes")
+                    .streamed_body(package.contents);
+
+                if let Some(size) = package.size {
+                    response.raw_header("Content-Length", size.to_string());
+                }
+
+                if let Some((range, size)) = range {
+                    response.status(Status::PartialContent).raw_header(
+                        "Content-Range",
+                        format!("bytes {}-{}/{}", range.start, range.end - 1, size),
+                    );
+                }
+
+                range
+            }
+            PackageBody::Redirect(url) => {
+                response
+                    .status(Status::TemporaryRedirect)
+                    .raw_header("Location", url);
+
+                None
+            }
+        };
+
+        if let Some(sha256) = self.sha256 {
+            // Content-Digest, from RFC 9530, holds the hash in base64. It's
+            // the hash of what's sent, so it's left out of partial responses.
+            if let (Ok(hash), None) = (hex::decode(&sha256), range) {
+                response.raw_header(
+                    "Content-Digest",
+                    format!("sha-256=:{}:", base64::encode(hash)),
+                );
+            }
+
+            response.raw_header("X-Wally-Checksum", sha256);
+        }
+
+        // Header values can't hold the newlines of a `.minisig` file, so it's
+        // sent in base64, the same way it's sent when publishing.
+        if let Some(signature) = self.signature {
+            response.raw_header("Wally-Signature", base64::encode(signature));
+        }
+
+        response.ok()
+    }
+}
+
+/// Describes the service so that humans and tools poking at the registry can
+/// work out what it is.
+#[get("/")]
+fn root(config: &State<Config>) -> CacheableJson {
+    CacheableJson::new(
+        json!({
+            "message": "Wally Registry is up and running!",
+            "name": "wally-registry",
+            "version": VERSION,
+            "docs": DOCS_URL,
+            "index": config.index_url,
+            "capabilities": CAPABILITIES,
+        }),
+        300,
+    )
+}
+
+/// Tells clients what this registry supports and how to authenticate to it,
+/// so that they know whether to ask for a GitHub login or an API key. Needs no
+/// authentication, since clients ask before they have any.
+#[get("/v1/api-info")]
+fn api_info(config: &State<Config>) -> CacheableJson {
+    let mut features = CAPABILITIES.to_vec();
+
+    if config.unpublish_window > 0 {
+        features.push("unpublish");
+    }
+
+    let github_providers: Vec<_> = config
+        .github_providers
+        .iter()
+        .map(|provider| {
+            json!({
+                "name": provider.name,
+                "api-url": provider.api_url,
+                "client-id": provider.client_id,
+            })
+        })
+        .collect();
+
+    CacheableJson::new(
+        json!({
+            "api-version": API_VERSION,
+            "version": VERSION,
+            "auth": {
+                "read": config.read_auth().public_info(),
+                "write": config.write_auth().public_info(),
+                "github-providers": github_providers,
+            },
+            "features": features,
+        }),
+        300,
+    )
+}
+
+/// A cheap check that the registry is up, for load balancers and monitoring.
+#[get("/healthz")]
+fn healthz(maintenance: &State<MaintenanceMode>) -> Json<serde_json::Value> {
+    Json(json!({
+        "status": "ok",
+        "maintenance": maintenance.is_enabled(),
+    }))
+}
+
+/// Checks that the registry can reach every index, and GitHub when it's used
+/// for auth, answering 503 Service Unavailable if it can't.
+#[get("/health")]
+async fn health(
+    config: &State<Config>,
+    indexes: &State<Indexes>,
+    github: &State<GithubHealth>,
+    github_client: &State<GithubClient>,
+) -> (Status, Json<serde_json::Value>) {
+    let index_status = check_index(indexes);
+    let github_status = github.check(config, github_client).await;
+
+    let healthy = index_status.is_healthy() && github_status.is_healthy();
+    let status = match healthy {
+        true => Status::Ok,
+        false => Status::ServiceUnavailable,
+    };
+
+    (
+        status,
+        Json(json!({
+            "status": if healthy { "ok" } else { "unavailable" },
+            "checks": {
+                "index": index_status,
+                "github": github_status,
+            },
+        })),
+    )
+}
+
+#[get("/v1/package-contents/<scope>/<name>/<version>")]
+async fn package_contents(
+    config: &State<Config>,
+    storage: &State<Box<dyn StorageBackend>>,
+    indexes: &State<Indexes>,
+    metrics: &State<Arc<Metrics>>,
+    stats: &State<Arc<dyn StatsStore>>,
+    method: Method,
+    if_none_match: IfNoneMatch,
+    range: RangeHeader,
+    read: Result<ReadAccess, Error>,
+    scope: String,
+    name: String,
+    version: String,
+    cli_version: Result<ReadWallyVersion, Error>,
+) -> Result<Tagged<PackageDownload>, Error> {
+    let read = read?;
+    cli_version?;
+
+    let package_name = PackageName::canonical(scope, name)
+        .context("error parsing package name")
+        .status(Status::BadRequest)
+        .code("invalid_package_name")?;
+    read.check_scope(package_name.scope())?;
+    let version: Version = version
+        .parse()
+        .context("error parsing version")
+        .status(Status::BadRequest)
+        .code("invalid_version")?;
+    let package_id = PackageId::new(package_name, version);
+    let index = indexes.for_scope(package_id.name().scope())?;
+
+    let metadata = index.get_package_metadata(package_id.name()).ok();
+
+    // Published versions never change, so the hash recorded in the index
+    // when the version was published can be used as its ETag without reading
+    // the package from storage.
+    let package_etag = metadata
+        .as_ref()
+        .and_then(|metadata| metadata.checksum(package_id.version()))
+        .map(etag);
+
+    if let Some(package_etag) = package_etag.clone() {
+        if if_none_match.matches(&package_etag) {
+            return Ok(Tagged::NotModified { etag: package_etag });
+        }
+    }
+
+    // Yanked versions can still be downloaded so that lockfiles using them
+    // keep working, but clients can warn about them.
+    let yanked = metadata
+        .as_ref()
+        .map(|metadata| metadata.is_yanked(package_id.version()))
+        .unwrap_or(false);
+    let sha256 = metadata
+        .as_ref()
+        .and_then(|metadata| metadata.sha256(package_id.version()))
+        .map(str::to_owned);
+    let signature = metadata
+        .as_ref()
+        .and_then(|metadata| metadata.signature(package_id.version()))
+        .map(str::to_owned);
+
+    // Only versions in the index are redirected, so that a missing package
+    // still gets a 404 from the registry instead of an error from storage.
+    // Resumed downloads are streamed, since a pre-signed URL is for the whole
+    // archive.
+    let published = metadata.as_ref().map_or(false, |metadata| {
+        metadata
+            .versions
+            .iter()
+            .any(|manifest| &manifest.package.version == package_id.version())
+    });
+
+    if let (Some(ttl), true, false) = (config.presigned_url_ttl, published, range.is_requested()) {
+        match storage
+            .presigned_url(&package_id, Duration::from_secs(ttl))
+            .await
+        {
+            Ok(Some(url)) => {
+                if method != Method::Head {
+                    record_download(metrics, stats.inner(), &package_id);
+                }
+
+                return Ok(Tagged::Modified {
+                    response: PackageDownload {
+                        body: PackageBody::Redirect(url),
+                        yanked,
+                        sha256,
+                        signature,
+                    },
+                    etag: package_etag,
+                });
+            }
+            Ok(None) => {}
+            Err(err) => tracing::warn!(
+                %package_id,
+                error = ?err,
+                "could not pre-sign a download URL, streaming it instead"
+            ),
+        }
+    }
+
+    // The size is only needed to work out the range, so it's only looked up
+    // for range requests.
+    let package = match range.is_requested() {
+        true => match storage.size(&package_id).await {
+            Ok(size) => match range.resolve(size, package_etag.as_deref())? {
+                Some(byte_range) => storage
+                    .read_range(&package_id, byte_range)
+                    .await
+                    .map(|package| (package, Some((byte_range, size)))),
+                None => storage
+                    .read(&package_id)
+                    .await
+                    .map(|package| (package, None)),
+            },
+            Err(err) => Err(err),
+        },
+        false => storage
+            .read(&package_id)
+            .await
+            .map(|package| (package, None)),
+    };
+
+    match package {
+        Ok((package, range)) => {
+            // HEAD requests are answered by this route too, but don't fetch
+            // the package, so they aren't counted as downloads. Neither are
+            // resumed downloads, which were counted when they started.
+            let resumed = matches!(range, Some((byte_range, _)) if byte_range.start > 0);
+            if method != Method::Head && !resumed {
+                record_download(metrics, stats.inner(), &package_id);
+            }
+
+            Ok(Tagged::Modified {
+                response: PackageDownload {
+                    body: PackageBody::Stored { package, range },
+                    yanked,
+                    sha256,
+                    signature,
+                },
+                etag: package_etag,
+            })
+        }
+        // Only say the package is missing if it really is, so that storage
+        // outages don't look like packages disappearing.
+        Err(e) => match storage.exists(&package_id).await {
+            Ok(true) => Err(e)
+                .context("could not read package from storage backend")
+                .status(Status::InternalServerError),
+            _ => Err(e).status(Status::NotFound).code("package_not_found"),
+        },
+    }
+}
+
+fn record_download(metrics: &Metrics, stats: &Arc<dyn StatsStore>, package_id: &PackageId) {
+    metrics.record_download();
+    record_download_stats(stats, package_id);
+}
+
+/// Counts a download in the background, so that a slow stats store doesn't
+/// hold up the download itself.
+fn record_download_stats(stats: &Arc<dyn StatsStore>, package_id: &PackageId) {
+    let stats = Arc::clone(stats);
+    let package_id = package_id.clone();
+
+    rocket::tokio::spawn(async move {
+        if let Err(err) = stats.record_download(&package_id).await {
+            tracing::warn!(%package_id, error = ?err, "could not record download stats");
+        }
+    });
+}
+
+/// How many times each version of a package has been downloaded, along with
+/// the total across all versions.
+#[get("/v1/stats/<scope>/<name>")]
+async fn package_stats(
+    indexes: &State<Indexes>,
+    stats: &State<Arc<dyn StatsStore>>,
+    read: Result<ReadAccess, Error>,
+    scope: String,
+    name: String,
+) -> Result<Json<serde_json::Value>, Error> {
+    let read = read?;
+
+    let package_name = PackageName::canonical(scope, name)
+        .context("error parsing package name")
+        .status(Status::BadRequest)
+        .code("invalid_package_name")?;
+    read.check_scope(package_name.scope())?;
+    let index = indexes.for_scope(package_name.scope())?;
+
+    if !index.package_exists(&package_name)? {
+        return Err(format_err!("package {} does not exist", package_name)
+            .status(Status::NotFound)
+            .code("package_not_found"));
+    }
+
+    let metadata = index.get_package_metadata(&package_name)?;
+    let mut downloads = stats
+        .package_downloads(&package_name)
+        .await
+        .status(Status::ServiceUnavailable)
+        .code("stats_unavailable")?;
+
+    // Versions that have never been downloaded are still listed.
+    for manifest in &metadata.versions {
+        downloads
+            .entry(manifest.package.version.clone())
+            .or_default();
+    }
+
+    let total: u64 = downloads.values().sum();
+    let versions: serde_json::Map<_, _> = downloads
+        .into_iter()
+        .map(|(version, count)| (version.to_string(), json!(count)))
+        .collect();
+
+    Ok(Json(json!({
+        "package": package_name,
+        "total": total,
+        "versions": versions,
+    })))
+}
+
+/// Serves the integrity document generated when a package version was
+/// published. Packages published before integrity documents existed don't
+/// have one.
+#[get("/v1/package-integrity/<scope>/<name>/<version>")]
+async fn package_integrity(
+    storage: &State<Box<dyn StorageBackend>>,
+    read: Result<ReadAccess, Error>,
+    scope: String,
+    name: String,
+    version: String,
+) -> Result<Json<PackageIntegrity>, Error> {
+    let read = read?;
+
+    let package_name = PackageName::canonical(scope, name)
+        .context("error parsing package name")
+        .status(Status::BadRequest)
+        .code("invalid_package_name")?;
+    read.check_scope(package_name.scope())?;
+    let version: Version = version
+        .parse()
+        .context("error parsing version")
+        .status(Status::BadRequest)
+        .code("invalid_version")?;
+    let package_id = PackageId::new(package_name, version);
+
+    let contents = storage
+        .read_integrity(&package_id)
+        .await
+        .status(Status::NotFound)
+        .code("package_not_found")?;
+    let integrity =
+        serde_json::from_slice(&contents).context("could not parse stored integrity document")?;
+
+    Ok(Json(integrity))
+}
+
+/// A version's manifest, either parsed from the index or as the `wally.toml`
+/// it was published with.
+#[derive(Responder)]
+enum ManifestResponse {
+    Parsed(Encoded),
+    Raw(Vec<u8>, ContentType, Header<'static>),
+}
+
+/// Returns one version's manifest without its archive. It's parsed from the
+/// index, unless the request prefers TOML, when it's read from the archive
+/// exactly as it was published.
+#[get("/v1/package/<scope>/<name>/<version>/manifest")]
+#[allow(clippy::too_many_arguments)]
+async fn package_manifest(
+    storage: &State<Box<dyn StorageBackend>>,
+    indexes: &State<Indexes>,
+    metadata_cache: &State<Arc<MetadataCache>>,
+    format: Format,
+    wants_toml: WantsToml,
+    read: Result<ReadAccess, Error>,
+    scope: String,
+    name: String,
+    version: String,
+) -> Result<ManifestResponse, Error> {
+    let read = read?;
+    let package_id = parse_package_id(scope, name, version)?;
+    read.check_scope(package_id.name().scope())?;
+    let index = indexes.for_scope(package_id.name().scope())?;
+
+    let not_found = || {
+        format_err!("{} does not exist", package_id)
+            .status(Status::NotFound)
+            .code("package_not_found")
+    };
+
+    if !index.package_exists(package_id.name())? {
+        return Err(not_found());
+    }
+
+    if !wants_toml.0 {
+        let key = CacheKey::manifest(
+            package_id.name().clone(),
+            package_id.version().clone(),
+            format,
+        );
+        let index = Arc::clone(index);
+        let package_id = package_id.clone();
+
+        let cached = metadata_cache.get(key, move || {
+            let metadata = index.get_package_metadata(package_id.name())?;
+            let manifest = metadata
+                .versions
+                .iter()
+                .find(|manifest| &manifest.package.version == package_id.version())
+                .ok_or_else(|| {
+                    format_err!("{} does not exist", package_id)
+                        .status(Status::NotFound)
+                        .code("package_not_found")
+                })?;
+
+            Ok(CachedResponse {
+                body: format.encode(manifest)?,
+                etag: None,
+            })
+        })?;
+
+        return Ok(ManifestResponse::Parsed(cached.body));
+    }
+
+    let metadata = index.get_package_metadata(package_id.name())?;
+    if !metadata
+        .versions
+        .iter()
+        .any(|manifest| &manifest.package.version == package_id.version())
+    {
+        return Err(not_found());
+    }
+
+    let mut package = storage
+        .read(&package_id)
+        .await
+        .context("could not read package from storage backend")?;
+    let mut contents = Vec::new();
+    package
+        .contents
+        .read_to_end(&mut contents)
+        .await
+        .context("could not read package from storage backend")?;
+
+    let mut archive =
+        ZipArchive::new(Cursor::new(contents)).context("could not read stored package")?;
+    let manifest_contents = read_manifest_file(&mut archive)?;
+
+    Ok(ManifestResponse::Raw(
+        manifest_contents,
+        ContentType::new("text", "toml"),
+        Header::new("Vary", "Accept"),
+    ))
+}
+
+/// Lists every published version of a package, newest first, with its full
+/// manifest, along with the versions that have been yanked.
+#[get("/v1/package-metadata/<scope>/<name>")]
+async fn package_info(
+    indexes: &State<Indexes>,
+    metadata_cache: &State<Arc<MetadataCache>>,
+    if_none_match: IfNoneMatch,
+    format: Format,
+    read: Result<ReadAccess, Error>,
+    scope: String,
+    name: String,
+) -> Result<Tagged<Encoded>, Error> {
+    let read = read?;
+
+    let package_name = PackageName::canonical(scope, name)
+        .context("error parsing package name")
+        .status(Status::BadRequest)
+        .code("invalid_package_name")?;
+    read.check_scope(package_name.scope())?;
+    let index = indexes.for_scope(package_name.scope())?;
+
+    if !index.package_exists(&package_name)? {
+        return Err(format_err!("package {} does not exist", package_name)
+            .status(Status::NotFound)
+            .code("package_not_found"));
+    }
+
+    let key = CacheKey::metadata(package_name.clone(), format);
+    let index = Arc::clone(index);
+
+    let cached = metadata_cache.get(key, move || {
+        // The tag is taken from the encoded metadata, so JSON and MessagePack
+        // responses are tagged differently.
+        let metadata = format.encode(&*index.get_package_metadata(&package_name)?)?;
+        let metadata_etag = etag(&blake3::hash(metadata.body()).to_hex());
+
+        Ok(CachedResponse {
+            body: metadata,
+            etag: Some(metadata_etag),
+        })
+    })?;
+
+    Ok(Tagged::new(cached.body, cached.etag, &if_none_match))
+}
+
+/// Shows who the registry thinks a request comes from, to help debug auth.
+/// Only the identity a token resolved to is returned, never the token.
+#[get("/v1/whoami")]
+fn whoami(whoami: Result<Whoami, Error>) -> Result<Json<serde_json::Value>, Error> {
+    let identity = match whoami? {
+        Whoami::Read(ReadAccess::Public) => json!({ "type": "anonymous" }),
+        Whoami::Read(ReadAccess::ApiKey { scopes }) => json!({
+            "type": "api-key",
+            "scopes": scopes,
+        }),
+        Whoami::Read(ReadAccess::Github(info)) => json!({
+            "type": "github",
+            "login": info.login(),
+            "id": info.id(),
+            "provider": info.provider(),
+        }),
+        Whoami::Read(ReadAccess::Token(claims)) => json!({
+            "type": "read-token",
+            "subject": claims.sub,
+            "scopes": claims.scopes,
+            "expires": claims.exp,
+        }),
+        Whoami::Read(ReadAccess::GitLab(info)) | Whoami::Write(WriteAccess::GitLab(info)) => {
+            json!({
+                "type": "gitlab",
+                "username": info.username(),
+                "id": info.id(),
+            })
+        }
+        Whoami::Write(WriteAccess::ApiKey) => json!({ "type": "api-key" }),
+        Whoami::Write(WriteAccess::Github { info, permission }) => json!({
+            "type": "github",
+            "login": info.login(),
+            "id": info.id(),
+            "provider": info.provider(),
+            "permission": permission,
+        }),
+        Whoami::Write(WriteAccess::TrustedPublisher(info)) => json!({
+            "type": "trusted-publisher",
+            "issuer": info.issuer,
+            "repository": info.repository,
+            "subject": info.subject,
+            "scopes": info.scopes,
+        }),
+    };
+
+    Ok(Json(identity))
+}
+
+#[derive(Deserialize)]
+struct MetadataBatchEntry {
+    scope: String,
+    name: String,
+}
+
+/// Looks up the metadata of many packages at once, for clients resolving a
+/// whole dependency graph. Packages that don't exist, or that the client
+/// can't read, or in scopes the registry doesn't serve, are `null` instead of
+/// failing the batch.
+#[post("/v1/metadata-batch", data = "<packages>")]
+async fn package_info_batch(
+    config: &State<Config>,
+    indexes: &State<Indexes>,
+    format: Format,
+    read: Result<ReadAccess, Error>,
+    packages: Json<Vec<MetadataBatchEntry>>,
+) -> Result<Encoded, Error> {
+    let read = read?;
+
+    if packages.len() > config.max_metadata_batch {
+        return Err(format_err!(
+            "at most {} packages can be looked up at once, but {} were asked for",
+            config.max_metadata_batch,
+            packages.len()
+        )
+        .status(Status::BadRequest)
+        .code("batch_too_large"));
+    }
+
+    let mut results = serde_json::Map::new();
+
+    for entry in packages.into_inner() {
+        let package_name = PackageName::canonical(entry.scope, entry.name)
+            .context("error parsing package name")
+            .status(Status::BadRequest)
+            .code("invalid_package_name")?;
+
+        let metadata = match indexes.lookup(package_name.scope()) {
+            Some(index)
+                if read.can_read_scope(package_name.scope())
+                    && index.package_exists(&package_name)? =>
+            {
+                serde_json::to_value(&*index.get_package_metadata(&package_name)?)?
+            }
+            _ => serde_json::Value::Null,
+        };
+
+        results.insert(package_name.to_string(), metadata);
+    }
+
+    format.encode(&json!({ "packages": results }))
+}
+
+#[get("/v1/package-search?<query>")]
+async fn package_search(
+    search_backend: &State<RwLock<Option<SearchBackend>>>,
+    format: Format,
+    read: Result<ReadAccess, Error>,
+    query: String,
+) -> Result<Encoded, Error> {
+    let read = read?;
+
+    // Search is an optional extra, so when it's broken we report that it's
+    // unavailable instead of treating it as a server error.
+    let search_backend = search_backend.read().map_err(|_| {
+        format_err!("Search is unavailable. Try again later.")
+            .status(Status::ServiceUnavailable)
+            .code("search_unavailable")
+    })?;
+
+    let search_backend = search_backend.as_ref().ok_or_else(|| {
+        format_err!("Search is unavailable on this registry.")
+            .status(Status::ServiceUnavailable)
+            .code("search_unavailable")
+    })?;
+
+    let mut result = search_backend
+        .search(&query)
+        .status(Status::ServiceUnavailable)
+        .code("search_unavailable")?;
+    result.retain(|doc| read.can_read_scope(doc.scope()));
+
+    format.encode(&result)
+}
+
+/// A listing, either paged and encoded as JSON or MessagePack, or streamed as
+/// newline-delimited JSON to clients that ask for it.
+#[derive(Responder)]
+enum ListingResponse {
+    Encoded(Encoded),
+    Stream(Ndjson),
+}
+
+/// Checks that a listing is paged with either `cursor` or `offset`, since
+/// they'd disagree about where the page starts.
+fn check_page_start(cursor: &Option<String>, offset: Option<usize>) -> Result<(), Error> {
+    if cursor.is_some() && offset.is_some() {
+        return Err(format_err!("`cursor` and `offset` can't be used together")
+            .status(Status::BadRequest)
+            .code("invalid_cursor"));
+    }
+
+    Ok(())
+}
+
+/// Finds packages by name or description, reading the index directly. Results
+/// are paged with `limit` and the `next_cursor` of the page before, or with
+/// `offset`. Clients asking for `application/x-ndjson` get every result from
+/// the cursor on, one per line, unless they give a `limit`.
+#[allow(clippy::too_many_arguments)]
+#[get("/v1/search?<q>&<limit>&<offset>&<cursor>")]
+async fn search_packages(
+    indexes: &State<Indexes>,
+    cursors: &State<Cursors>,
+    format: Format,
+    wants_ndjson: WantsNdjson,
+    read: Result<ReadAccess, Error>,
+    q: Option<String>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+    cursor: Option<String>,
+) -> Result<ListingResponse, Error> {
+    let read = read?;
+    check_page_start(&cursor, offset)?;
+
+    let query = q.unwrap_or_default();
+    if query.trim().is_empty() {
+        return Err(format_err!("a search query must be given with `q`")
+            .status(Status::BadRequest)
+            .code("invalid_query"));
+    }
+
+    let after: Option<SearchPosition> = cursor
+        .map(|cursor| cursors.decode("search", &query, &cursor))
+        .transpose()?;
+
+    let mut found = find_packages(indexes, &query)?;
+    found.retain(|package| read.can_read_scope(&package.scope));
+    let total = found.len();
+
+    // Matches are sorted by their position, so the ones after the cursor are
+    // the ones after the last match that comes before or at it.
+    let start = match &after {
+        Some(after) => found.partition_point(|package| &package.position() <= after),
+        None => offset.unwrap_or(0).min(total),
+    };
+    let remaining = found.split_off(start);
+
+    if wants_ndjson.0 {
+        let results = remaining
+            .into_iter()
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|package| json!(package));
+
+        return Ok(ListingResponse::Stream(Ndjson(
+            stream::iter(results).boxed(),
+        )));
+    }
+
+    let limit = limit
+        .unwrap_or(DEFAULT_SEARCH_PAGE)
+        .clamp(1, MAX_SEARCH_PAGE);
+    let next_cursor = (remaining.len() > limit)
+        .then(|| cursors.encode("search", &query, &remaining[limit - 1].position()));
+    let results: Vec<_> = remaining.into_iter().take(limit).collect();
+
+    Ok(ListingResponse::Encoded(format.encode(&json!({
+        "total": total,
+        "results": results,
+        "next_cursor": next_cursor,
+    }))?))
+}
+
+/// Recently published versions, as JSON or MessagePack, or as an Atom feed.
+#[derive(Responder)]
+enum RecentResponse {
+    Encoded(Encoded),
+    Atom(String, ContentType, Header<'static>),
+}
+
+/// Lists the versions published most recently, newest first, from every
+/// scope the request can read, or from just `scope`. Clients asking for
+/// `application/atom+xml` get an Atom feed to subscribe to.
+#[get("/v1/recent?<limit>&<scope>")]
+fn recent(
+    indexes: &State<Indexes>,
+    format: Format,
+    wants_atom: WantsAtom,
+    read: Result<ReadAccess, Error>,
+    limit: Option<usize>,
+    scope: Option<String>,
+) -> Result<RecentResponse, Error> {
+    let read = read?;
+
+    let limit = limit
+        .unwrap_or(DEFAULT_RECENT_PAGE)
+        .clamp(1, MAX_RECENT_PAGE);
+
+    let (packages, title, id) = match scope {
+        Some(scope) => {
+            let scope = canonical_scope(&scope)
+                .context("error parsing scope")
+                .status(Status::BadRequest)
+                .code("invalid_scope")?;
+            read.check_scope(&scope)?;
+            let index = indexes.for_scope(&scope)?;
+
+            if !index.scope_exists(&scope)? {
+                return Err(format_err!("scope {} does not exist", scope)
+                    .status(Status::NotFound)
+                    .code("scope_not_found"));
+            }
+
+            let packages: Vec<_> = index
+                .scope_package_names(&scope)?
+                .into_iter()
+                .map(|name| (name, index))
+                .collect();
+
+            let title = format!("Recently published in {}", scope);
+            let id = format!("urn:wally:recent:{}", scope);
+            (packages, title, id)
+        }
+        None => {
+            let mut packages = indexes.package_names()?;
+            packages.retain(|(name, _)| read.can_read_scope(name.scope()));
+
+            let title = String::from("Recently published packages");
+            let id = String::from("urn:wally:recent");
+            (packages, title, id)
+        }
+    };
+
+    let versions = recent_versions(&packages, limit)?;
+
+    if wants_atom.0 {
+        return Ok(RecentResponse::Atom(
+            atom_feed(&title, &id, &versions),
+            ContentType::new("application", "atom+xml"),
+            Header::new("Vary", "Accept"),
+        ));
+    }
+
+    Ok(RecentResponse::Encoded(
+        format.encode(&json!({ "versions": versions }))?,
+    ))
+}
+
+#[derive(FromForm)]
+struct PublishOptions {
+    /// Check that the package could be published, without publishing it.
+    #[field(name = "dry-run")]
+    dry_run: bool,
+}
+
+#[post("/v1/publish?<options..>", data = "<data>")]
+#[allow(clippy::too_many_arguments)]
+async fn publish(
+    config: &State<Config>,
+    storage: &State<Box<dyn StorageBackend>>,
+    search_backend: &State<RwLock<Option<SearchBackend>>>,
+    indexes: &State<Indexes>,
+    metadata_cache: &State<Arc<MetadataCache>>,
+    audit: &State<AuditLog>,
+    metrics: &State<Arc<Metrics>>,
+    webhooks: &State<Webhooks>,
+    scope_locks: &State<ScopeLocks>,
+    github: &State<GithubClient>,
+    authorization: Result<MutatingAccess, Error>,
+    cli_version: Result<WallyVersion, Error>,
+    request_id: RequestId,
+    signature: PublishSignature,
+    options: PublishOptions,
+    data: Data<'_>,
+) -> Result<Json<serde_json::Value>, Error> {
+    cli_version?;
+
+    publish_contents(
+        &request_id,
+        config,
+        storage.inner().as_ref(),
+        search_backend,
+        indexes,
+        metadata_cache,
+        audit,
+        metrics,
+        webhooks,
+        scope_locks,
+        github,
+        authorization?.into_inner(),
+        None,
+        signature,
+        options,
+        data,
+    )
+    .await
+}
+
+/// Publishes a package, checking that the archive's manifest declares the
+/// package and version given in the URL.
+#[post("/v1/publish/<scope>/<name>/<version>?<options..>", data = "<data>")]
+#[allow(clippy::too_many_arguments)]
+async fn publish_version(
+    config: &State<Config>,
+    storage: &State<Box<dyn StorageBackend>>,
+    search_backend: &State<RwLock<Option<SearchBackend>>>,
+    indexes: &State<Indexes>,
+    metadata_cache: &State<Arc<MetadataCache>>,
+    audit: &State<AuditLog>,
+    metrics: &State<Arc<Metrics>>,
+    webhooks: &State<Webhooks>,
+    scope_locks: &State<ScopeLocks>,
+    github: &State<GithubClient>,
+    authorization: Result<MutatingAccess, Error>,
+    cli_version: Result<WallyVersion, Error>,
+    request_id: RequestId,
+    signature: PublishSignature,
+    scope: String,
+    name: String,
+    version: String,
+    options: PublishOptions,
+    data: Data<'_>,
+) -> Result<Json<serde_json::Value>, Error> {
+    cli_version?;
+    let authorization = authorization?.into_inner();
+    let package_id = parse_package_id(scope, name, version)?;
+
+    publish_contents(
+        &request_id,
+        config,
+        storage.inner().as_ref(),
+        search_backend,
+        indexes,
+        metadata_cache,
+        audit,
+        metrics,
+        webhooks,
+        scope_locks,
+        github,
+        authorization,
+        Some(package_id),
+        signature,
+        options,
+        data,
+    )
+    .await
+}
+
+/// Publishes the package archive in `data`. When `claimed` is given, the
+/// archive's manifest must declare exactly that package and version. A dry
+/// run makes every check a real publish would, then stops before writing
+/// anything.
+///
+/// Publishes are logged in a span with the package and how it went. Failures
+/// are logged with their error.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "publish",
+    skip_all,
+    fields(
+        request_id = %request_id,
+        package_id = tracing::field::Empty,
+        scope = tracing::field::Empty,
+        outcome = tracing::field::Empty,
+    ),
+    err(Debug)
+)]
+async fn publish_contents(
+    request_id: &RequestId,
+    config: &Config,
+    storage: &dyn StorageBackend,
+    search_backend: &RwLock<Option<SearchBackend>>,
+    indexes: &Indexes,
+    metadata_cache: &MetadataCache,
+    audit: &AuditLog,
+    metrics: &Metrics,
+    webhooks: &Webhooks,
+    scope_locks: &ScopeLocks,
+    github: &GithubClient,
+    authorization: WriteAccess,
+    claimed: Option<PackageId>,
+    signature: PublishSignature,
+    options: PublishOptions,
+    data: Data<'_>,
+) -> Result<Json<serde_json::Value>, Error> {
+    let contents = read_upload(config, data).await?;
+    let sha256 = hex::encode(Sha256::digest(&contents));
+    let signature = signature
+        .0
+        .map(|text| SignedArchive::new(text, &contents))
+        .transpose()?;
+    let (archive, manifest) = open_package_archive(contents)?;
+    let package_id = claimed.unwrap_or_else(|| manifest.package_id());
+    let index = indexes.for_scope(package_id.name().scope())?;
+
+    // Held until the publish is done, from checking the index through to
+    // committing to it, so that concurrent publishes to this scope take turns.
+    let _scope_lock = scope_locks.lock(package_id.name().scope()).await;
+
+    index.update()?;
+
+    let span = tracing::Span::current();
+    span.record("package_id", &tracing::field::display(&package_id));
+    span.record("scope", &package_id.name().scope());
+
+    let teams = GithubTeams::new(config, github);
+    let permission = authorization
+        .write_permission(package_id.name().scope(), index, &teams, &config.bootstrap)
+        .await?;
+
+    let permission = match permission {
+        Some(permission) => permission,
+        None => {
+            let message = format!(
+                "you do not have permission to write in scope {}",
+                package_id.name().scope()
+            );
+
+            record_audit(
+                audit,
+                AuditEvent::denied(&authorization, &package_id, &message),
+            )
+            .await;
+            return Err(format_err!(message)
+                .status(Status::Forbidden)
+                .code("scope_not_owned"));
+        }
+    };
+
+    check_scope_allowed(config, package_id.name().scope())?;
+    check_manifest_matches(&manifest, &package_id)?;
+    check_required_metadata(config, &manifest)?;
+    check_registry_dependencies(&manifest)?;
+    check_package_realm(&manifest)?;
+    check_signature(config, index, &package_id, signature.as_ref())?;
+
+    if let Ok(metadata) = index.get_package_metadata(package_id.name()) {
+        if metadata
+            .versions
+            .iter()
+            .any(|published_manifest| &published_manifest.package.version == package_id.version())
+        {
+            if is_retried_publish(config, &metadata, package_id.version(), &sha256) {
+                span.record("outcome", &"republished");
+                return Ok(Json(json!({
+                    "message": "Package was already published with the same contents",
+                    "republished": true,
+                    "prerelease": package_id.version().is_prerelease(),
+                    "pruned": [],
+                })));
+            }
+
+            return Err(format_err!("{} already exists in index", package_id)
+                .status(Status::Conflict)
+                .code("version_exists"));
+        }
+    }
+
+    if config.reject_confusable_names {
+        check_confusable_name(index, package_id.name())?;
+    }
+
+    if config.check_dependency_realms {
+        check_dependency_realms(indexes, &manifest)?;
+    }
+
+    if config.check_dependencies {
+        check_dependencies(indexes, &manifest)?;
+    }
+
+    let pruned = match config.max_versions_per_package {
+        Some(max_versions) => {
+            versions_to_prune(index, &package_id, max_versions, config.prune_prereleases)?
+        }
+        None => Vec::new(),
+    };
+
+    let contents = PackageContents::from_buffer(archive.into_inner().into_inner());
+    let integrity = PackageIntegrity::from_contents(&contents)
+        .context("could not generate integrity document")
+        .status(Status::BadRequest)
+        .code("invalid_archive")?;
+    let checksums = ArchiveChecksums {
+        blake3: Some(integrity.archive.clone()),
+        sha256: Some(sha256),
+        signature: signature.map(|signature| signature.text),
+    };
+
+    let scope = package_id.name().scope();
+    let new_owner = match authorization.user_id() {
+        Some(user_id) => !index.is_scope_owner(scope, &user_id)?,
+        None => false,
+    };
+
+    if options.dry_run {
+        span.record("outcome", &"dry-run");
+        return Ok(Json(json!({
+            "message": "Package would be published successfully",
+            "dry-run": true,
+            "package": package_id,
+            "permission": permission,
+            "new-scope-owner": new_owner,
+            "prerelease": package_id.version().is_prerelease(),
+            "pruned": pruned,
+        })));
+    }
+
+    // The archive goes into storage before the index lists it, so a version
+    // is never listed without being downloadable. If listing it fails, the
+    // archive is deleted again, so a publish either finishes or leaves nothing
+    // behind.
+    let stored = async {
+        storage
+            .write(&package_id, contents.data())
+            .await
+            .context("could not write package to storage backend")?;
+
+        storage
+            .write_integrity(&package_id, &serde_json::to_vec(&integrity)?)
+            .await
+            .context("could not write integrity document to storage backend")
+    }
+    .await;
+
+    // If a user can write but isn't in the scope owner file then we should
+    // add them, and prune any versions that are making room for this one, in
+    // the same commit as the new version.
+    let owner = authorization.user_id().filter(|_| new_owner);
+    let published = match stored {
+        Ok(()) => {
+            let manifest = manifest.clone();
+            let actor = authorization.actor().to_owned();
+            let pruned = pruned.clone();
+
+            write_index(index, move |index| {
+                index.publish_and_prune(
+                    &manifest,
+                    &checksums,
+                    Some(&actor),
+                    owner.as_ref(),
+                    &pruned,
+                )
+            })
+            .await
+            .context("could not publish package to index")
+        }
+        Err(err) => Err(err),
+    };
+
+    if let Err(err) = published {
+        if let Err(delete_err) = storage.delete(&package_id).await {
+            tracing::error!(
+                %package_id,
+                error = ?delete_err,
+                "could not delete package from storage after a failed publish"
+            );
+        }
+
+        return Err(err.into());
+    }
+
+    metadata_cache.invalidate(package_id.name());
+
+    for version in &pruned {
+        delete_pruned_version(
+            storage,
+            &PackageId::new(package_id.name().clone(), version.clone()),
+        )
+        .await;
+    }
+
+    record_audit(
+        audit,
+        AuditEvent::allowed(&authorization, &package_id, permission),
+    )
+    .await;
+    metrics.record_publish();
+    webhooks.notify(&PublishEvent::new(&authorization, &manifest));
+
+    // The package has been published by now, so problems updating search are
+    // logged instead of failing the request.
+    if let Ok(mut search_backend) = search_backend.try_write() {
+        if let Some(search_backend) = search_backend.as_mut() {
+            // TODO: Recrawling the whole index for each publish is very wasteful!
+            // Eventually this will get too expensive and we should only add the new package.
+            if let Err(err) = search_backend.crawl_packages(indexes) {
+                tracing::warn!(error = ?err, "could not update search after publish");
+            }
+        }
+    }
+
+    span.record("outcome", &"published");
+
+    Ok(Json(json!({
+        "message": "Package published successfully!",
+        "prerelease": package_id.version().is_prerelease(),
+        "pruned": pruned,
+    })))
+}
+
+/// Whether publishing a version that already exists is a retry of the publish
+/// that created it: the archive is byte for byte the one published, within
+/// `republish_window` seconds. Versions published before publish times or
+/// SHA-256 hashes were recorded can't be matched, so they're never retries.
+fn is_retried_publish(
+    config: &Config,
+    metadata: &PackageMetadata,
+    version: &Version,
+    sha256: &str,
+) -> bool {
+    if config.republish_window == 0 || metadata.sha256(version) != Some(sha256) {
+        return false;
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+
+    metadata.published_at(version).map_or(false, |published| {
+        now.saturating_sub(published) < config.republish_window
+    })
+}
+
+/// Rejects archives whose manifest doesn't declare the package being
+/// published, so that a bad upload can't end up in the index under a
+/// different name or version.
+fn check_manifest_matches(manifest: &Manifest, package_id: &PackageId) -> Result<(), Error> {
+    if &manifest.package.name != package_id.name() {
+        return Err(format_err!(
+            "archive manifest declares package {}, but {} is being published",
+            manifest.package.name,
+            package_id.name()
+        )
+        .status(Status::BadRequest)
+        .code("manifest_mismatch"));
+    }
+
+    if &manifest.package.version != package_id.version() {
+        return Err(format_err!(
+            "archive manifest declares version {} of {}, but version {} is being published",
+            manifest.package.version,
+            package_id.name(),
+            package_id.version()
+        )
+        .status(Status::BadRequest)
+        .code("manifest_mismatch"));
+    }
+
+    Ok(())
+}
+
+/// Path and Git dependencies only exist on the machine of whoever published
+/// the package, so nobody installing it could get them.
+fn check_registry_dependencies(manifest: &Manifest) -> Result<(), Error> {
+    let local: Vec<_> = manifest
+        .all_dependencies()
+        .filter(|(_, dependency)| dependency.registry().is_none())
+        .map(|(alias, dependency)| format!("{} ({})", alias, dependency))
+        .collect();
+
+    if local.is_empty() {
+        return Ok(());
+    }
+
+    Err(format_err!(
+        "packages can only depend on packages from a registry, but these dependencies come \
+         from a path or Git: {}",
+        local.join(", ")
+    )
+    .status(Status::BadRequest)
+    .code("local_dependency"))
+}
+
+fn check_required_metadata(config: &Config, manifest: &Manifest) -> Result<(), Error> {
+    let package = &manifest.package;
+
+    if config.require_description {
+        match &package.description {
+            Some(description) if !description.trim().is_empty() => {}
+            _ => {
+                return Err(format_err!(
+                    "this registry requires packages to have a description. Add one to the \
+                     [package] section of the manifest."
+                )
+                .status(Status::BadRequest)
+                .code("missing_description"))
+            }
+        }
+    }
+
+    if config.require_license {
+        match &package.license {
+            Some(license) if is_valid_license(license) => {}
+            Some(license) if !license.trim().is_empty() => {
+                return Err(format_err!(
+                    "{} isn't an SPDX license expression, like \"MIT\" or \"MIT OR \
+                     Apache-2.0\". Licenses that aren't on the SPDX License List can be given as \
+                     \"LicenseRef-\" followed by a name.",
+                    license
+                )
+                .status(Status::BadRequest)
+                .code("invalid_license"))
+            }
+            _ => {
+                return Err(format_err!(
+                    "this registry requires packages to have a license. Add an SPDX license \
+                     expression, like \"MIT\", to the [package] section of the manifest."
+                )
+                .status(Status::BadRequest)
+                .code("missing_license"))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Works out which versions to delete to make room for `package_id` in a
+/// package that's reached `max_versions`, or refuses the publish if room can't
+/// be made. Only prereleases that haven't been yanked are deleted, oldest
+/// first, since nothing should depend on them for long.
+fn versions_to_prune(
+    index: &PackageIndex,
+    package_id: &PackageId,
+    max_versions: usize,
+    prune_prereleases: bool,
+) -> Result<Vec<Version>, Error> {
+    let count = index.version_count(package_id.name())?;
+
+    if count < max_versions {
+        return Ok(Vec::new());
+    }
+
+    let needed = count + 1 - max_versions;
+    let too_many = |reason: &str| {
+        format_err!(
+            "{} already has {} versions, the most this registry allows{}",
+            package_id.name(),
+            count,
+            reason
+        )
+        .status(Status::Forbidden)
+        .code("too_many_versions")
+    };
+
+    if !prune_prereleases {
+        return Err(too_many(""));
+    }
+
+    let metadata = index.get_package_metadata(package_id.name())?;
+    let mut prereleases: Vec<&Version> = metadata
+        .versions
+        .iter()
+        .map(|manifest| &manifest.package.version)
+        .filter(|version| version.is_prerelease() && !metadata.is_yanked(version))
+        .collect();
+
+    if prereleases.len() < needed {
+        return Err(too_many(
+            ", and it doesn't have enough unyanked prereleases to make room",
+        ));
+    }
+
+    // Versions without a publish time were published before times were
+    // recorded, so they're older than any that have one.
+    prereleases.sort_by_key(|version| (metadata.published_at(version).unwrap_or(0), *version));
+
+    Ok(prereleases.into_iter().take(needed).cloned().collect())
+}
+
+/// Runs a change to `index` on a blocking thread. Writes can wait on the index
+/// lock and push to the remote, neither of which should hold up the worker
+/// that's serving other requests.
+async fn write_index<T, E, F>(index: &Arc<PackageIndex>, write: F) -> Result<T, E>
+where
+    T: Send + 'static,
+    E: From<anyhow::Error> + Send + 'static,
+    F: FnOnce(&PackageIndex) -> Result<T, E> + Send + 'static,
+{
+    let index = Arc::clone(index);
+
+    match rocket::tokio::task::spawn_blocking(move || write(&index)).await {
+        Ok(result) => result,
+        Err(err) => Err(anyhow::Error::new(err)
+            .context("index write was interrupted")
+            .into()),
+    }
+}
+
+/// Deletes a version from storage once the publish that pruned it has taken
+/// it out of the index. Nothing can find it by then, so failing to delete it
+/// only leaves an unused archive behind, which is logged instead of failing the
+/// publish.
+async fn delete_pruned_version(storage: &dyn StorageBackend, package_id: &PackageId) {
+    if let Err(err) = storage.delete(package_id).await {
+        tracing::warn!(%package_id, error = ?err, "could not delete pruned version from storage");
+    }
+}
+
+fn check_confusable_name(index: &PackageIndex, name: &PackageName) -> Result<(), Error> {
+    match find_confusable(index, name)? {
+        Some(existing) => Err(format_err!(
+            "{} looks too much like the existing package {}. Pick a name that's easier to tell \
+             apart.",
+            name,
+            existing
+        )
+        .status(Status::Conflict)
+        .code("confusable_name")),
+        None => Ok(()),
+    }
+}
+
+/// Dev is a realm for dependencies, so packages have to be either shared or
+/// server packages.
+fn check_package_realm(manifest: &Manifest) -> Result<(), Error> {
+    match manifest.package.realm {
+        Realm::Shared | Realm::Server => Ok(()),
+        Realm::Dev => Err(format_err!(
+            "packages can only be in the shared or server realm, but this one is in the dev realm"
+        )
+        .status(Status::BadRequest)
+        .code("invalid_realm")),
+    }
+}
+
+/// Checks a package's signature against its scope's signing keys, or with
+/// `require_signatures`, that it has one at all.
+fn check_signature(
+    config: &Config,
+    index: &PackageIndex,
+    package_id: &PackageId,
+    signature: Option<&SignedArchive>,
+) -> Result<(), Error> {
+    let scope = package_id.name().scope();
+
+    let signature = match signature {
+        Some(signature) => signature,
+        None if config.require_signatures => {
+            return Err(format_err!(
+                "this registry only accepts signed packages. Sign the archive of {} with \
+                 minisign and send the signature in the Wally-Signature header.",
+                package_id
+            )
+            .status(Status::BadRequest)
+            .code("signature_required"))
+        }
+        None => return Ok(()),
+    };
+
+    let keys = index
+        .get_scope_signing_keys(scope)?
+        .iter()
+        .map(|key| PublicKey::parse(key))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    if keys.is_empty() {
+        return Err(format_err!(
+            "scope {} has no signing keys to check the package's signature with. Its owners \
+             can add them at /v1/scope/{}/signing-keys.",
+            scope,
+            scope
+        )
+        .status(Status::BadRequest)
+        .code("unknown_signing_key"));
+    }
+
+    signature.verify(&keys)
+}
+
+/// Rejects shared dependencies that are only published as server packages,
+/// since a shared package that needs one would break when it's used outside
+/// the server. A dependency passes if any unyanked version it matches is a
+/// shared package. Dependencies matching nothing are left to
+/// `check_dependencies`.
+fn check_dependency_realms(indexes: &Indexes, manifest: &Manifest) -> Result<(), Error> {
+    let mut offending = Vec::new();
+
+    for (alias, dependency) in &manifest.dependencies {
+        let req = match dependency.registry() {
+            Some(req) => req,
+            None => continue,
+        };
+
+        let index = match indexes.lookup(req.name().scope()) {
+            Some(index) => index,
+            None => continue,
+        };
+
+        if !index.package_exists(req.name())? {
+            continue;
+        }
+
+        let metadata = index.get_package_metadata(req.name())?;
+        let realms: Vec<Realm> = metadata
+            .versions
+            .iter()
+            .map(|dependency| &dependency.package)
+            .filter(|package| {
+                req.matches(&package.name, &package.version)
+                    && !metadata.is_yanked(&package.version)
+            })
+            .map(|package| package.realm)
+            .collect();
+
+        if !realms.is_empty()
+            && !realms
+                .iter()
+                .any(|&realm| Realm::is_dependency_valid(Realm::Shared, realm))
+        {
+            offending.push(format!("{} ({})", alias, req));
+        }
+    }
+
+    if offending.is_empty() {
+        return Ok(());
+    }
+
+    Err(format_err!(
+        "shared dependencies can't be server packages. Move these to [server-dependencies], or \
+         make the package a server package: {}",
+        offending.join(", ")
+    )
+    .status(Status::BadRequest)
+    .code("cross_realm_dependencies"))
+}
+
+/// Rejects packages depending on something that nothing published to this
+/// registry can satisfy, since installing them would fail. Dependencies have
+/// to match a version that hasn't been yanked. Which realm that version is in
+/// is up to `check_dependency_realms`. Dev dependencies are only installed
+/// when working on the package itself, so they aren't checked.
+fn check_dependencies(indexes: &Indexes, manifest: &Manifest) -> Result<(), Error> {
+    let dependencies = manifest
+        .dependencies
+        .values()
+        .chain(manifest.server_dependencies.values())
+        .filter_map(Dependency::registry);
+
+    let mut unresolvable = Vec::new();
+
+    for req in dependencies {
+        let index = match indexes.lookup(req.name().scope()) {
+            Some(index) => index,
+            None => {
+                unresolvable.push(req.to_string());
+                continue;
+            }
+        };
+
+        let resolves = index.package_exists(req.name())? && {
+            let metadata = index.get_package_metadata(req.name())?;
+
+            metadata.versions.iter().any(|dependency| {
+                let package = &dependency.package;
+
+                req.matches(&package.name, &package.version)
+                    && !metadata.is_yanked(&package.version)
+            })
+        };
+
+        if !resolves {
+            unresolvable.push(req.to_string());
+        }
+    }
+
+    if unresolvable.is_empty() {
+        return Ok(());
+    }
+
+    Err(format_err!(
+        "no published version in this registry satisfies these dependencies: {}",
+        unresolvable.join(", ")
+    )
+    .status(Status::BadRequest)
+    .code("unresolvable_dependencies"))
+}
+
+/// Audit logging shouldn't fail a publish that has otherwise gone through, so
+/// problems writing to it are logged instead.
+async fn record_audit(audit: &AuditLog, event: AuditEvent) {
+    if let Err(err) = audit.record(&event).await {
+        tracing::error!(error = ?err, "could not write audit log");
+    }
+}
+
+/// Checks a manifest for likely mistakes, like a missing license, without
+/// publishing anything. The body is either a package archive or the contents
+/// of a `wally.toml`.
+#[post("/v1/lint", data = "<data>")]
+async fn lint_manifest(
+    config: &State<Config>,
+    read: Result<ReadAccess, Error>,
+    data: Data<'_>,
+) -> Result<Json<serde_json::Value>, Error> {
+    read?;
+
+    let contents = read_upload(config, data).await?;
+
+    // ZIP archives always start with the signature of their first entry.
+    let manifest = if contents.starts_with(b"PK\x03\x04") {
+        open_package_archive(contents)?.1
+    } else {
+        parse_manifest(&contents)?
+    };
+
+    Ok(Json(json!({
+        "package": manifest.package_id(),
+        "diagnostics": lint(&manifest),
+    })))
+}
+
+#[derive(Deserialize)]
+struct YankRequest {
+    /// A SemVer range selecting the versions to yank.
+    range: Option<VersionReq>,
+
+    /// An explicit list of versions to yank.
+    versions: Option<Vec<Version>>,
+
+    /// Allow yanking every version of the package.
+    #[serde(default)]
+    force: bool,
+}
+
+/// Yanks many versions of a package at once, for use when responding to a
+/// security issue. All matching versions are yanked in a single index commit.
+/// Like the rest of the admin API, this needs the admin key.
+#[post("/v1/package-yank/<scope>/<name>", data = "<yank_request>")]
+async fn yank_versions(
+    indexes: &State<Indexes>,
+    metadata_cache: &State<Arc<MetadataCache>>,
+    audit: &State<AuditLog>,
+    admin: Result<MutatingAdminAccess, Error>,
+    scope: String,
+    name: String,
+    yank_request: Json<YankRequest>,
+) -> Result<Json<serde_json::Value>, Error> {
+    admin?;
+    set_yanked(
+        indexes,
+        metadata_cache,
+        audit,
+        scope,
+        name,
+        &yank_request,
+        true,
+    )
+    .await
+}
+
+/// Undoes a yank, making versions available to resolution again.
+#[post("/v1/package-unyank/<scope>/<name>", data = "<yank_request>")]
+async fn unyank_versions(
+    indexes: &State<Indexes>,
+    metadata_cache: &State<Arc<MetadataCache>>,
+    audit: &State<AuditLog>,
+    admin: Result<MutatingAdminAccess, Error>,
+    scope: String,
+    name: String,
+    yank_request: Json<YankRequest>,
+) -> Result<Json<serde_json::Value>, Error> {
+    admin?;
+    set_yanked(
+        indexes,
+        metadata_cache,
+        audit,
+        scope,
+        name,
+        &yank_request,
+        false,
+    )
+    .await
+}
+
+/// Yanks a single version of a package.
+#[post("/v1/package-yank/<scope>/<name>/<version>")]
+#[allow(clippy::too_many_arguments)]
+async fn yank_version(
+    config: &State<Config>,
+    indexes: &State<Indexes>,
+    metadata_cache: &State<Arc<MetadataCache>>,
+    audit: &State<AuditLog>,
+    github: &State<GithubClient>,
+    authorization: Result<MutatingAccess, Error>,
+    scope: String,
+    name: String,
+    version: String,
+) -> Result<Json<serde_json::Value>, Error> {
+    let package_id = parse_package_id(scope, name, version)?;
+    set_version_yanked(
+        config,
+        indexes.for_scope(package_id.name().scope())?,
+        metadata_cache,
+        audit,
+        github,
+        authorization?.into_inner(),
+        package_id,
+        true,
+    )
+    .await
+}
+
+/// Undoes the yank of a single version of a package.
+#[post("/v1/package-unyank/<scope>/<name>/<version>")]
+#[allow(clippy::too_many_arguments)]
+async fn unyank_version(
+    config: &State<Config>,
+    indexes: &State<Indexes>,
+    metadata_cache: &State<Arc<MetadataCache>>,
+    audit: &State<AuditLog>,
+    github: &State<GithubClient>,
+    authorization: Result<MutatingAccess, Error>,
+    scope: String,
+    name: String,
+    version: String,
+) -> Result<Json<serde_json::Value>, Error> {
+    let package_id = parse_package_id(scope, name, version)?;
+    set_version_yanked(
+        config,
+        indexes.for_scope(package_id.name().scope())?,
+        metadata_cache,
+        audit,
+        github,
+        authorization?.into_inner(),
+        package_id,
+        false,
+    )
+    .await
+}
+
+fn parse_package_id(scope: String, name: String, version: String) -> Result<PackageId, Error> {
+    let package_name = PackageName::canonical(scope, name)
+        .context("error parsing package name")
+        .status(Status::BadRequest)
+        .code("invalid_package_name")?;
+    let version: Version = version
+        .parse()
+        .context("error parsing version")
+        .status(Status::BadRequest)
+        .code("invalid_version")?;
+
+    Ok(PackageId::new(package_name, version))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn set_version_yanked(
+    config: &Config,
+    index: &Arc<PackageIndex>,
+    metadata_cache: &MetadataCache,
+    audit: &AuditLog,
+    github: &GithubClient,
+    authorization: WriteAccess,
+    package_id: PackageId,
+    yanked: bool,
+) -> Result<Json<serde_json::Value>, Error> {
+    index.update()?;
+
+    let teams = GithubTeams::new(config, github);
+
+    if !authorization
+        .can_write_package(&package_id, index, &teams, &config.bootstrap)
+        .await?
+    {
+        return Err(format_err!(
+            "you do not have permission to write in scope {}",
+            package_id.name().scope()
+        )
+        .status(Status::Forbidden)
+        .code("scope_not_owned"));
+    }
+
+    let metadata = index
+        .get_package_metadata(package_id.name())
+        .status(Status::NotFound)
+        .code("package_not_found")?;
+
+    if !metadata
+        .versions
+        .iter()
+        .any(|manifest| &manifest.package.version == package_id.version())
+    {
+        return Err(format_err!("{} does not exist", package_id)
+            .status(Status::NotFound)
+            .code("version_not_found"));
+    }
+
+    let (action, key, kind) = match yanked {
+        true => ("Yanked", "yanked", AuditAction::Yank),
+        false => ("Unyanked", "unyanked", AuditAction::Unyank),
+    };
+
+    let name = package_id.name().clone();
+    let version = package_id.version().clone();
+    let changed = write_index(index, move |index| {
+        index.set_yanked(&name, std::slice::from_ref(&version), yanked)
+    })
+    .await
+    .context("could not update yanked versions in index")?;
+    metadata_cache.invalidate(package_id.name());
+
+    if !changed.is_empty() {
+        record_audit(
+            audit,
+            AuditEvent::changed(&authorization, &package_id, kind),
+        )
+        .await;
+    }
+
+    Ok(Json(json!({
+        "message": format!("{} {} version(s) of {}", action, changed.len(), package_id.name()),
+        key: changed,
+    })))
+}
+
+#[derive(Deserialize)]
+struct DeprecateRequest {
+    /// What installers are told, like which package to use instead. An
+    /// empty message clears the deprecation.
+    message: String,
+}
+
+/// Deprecates every published version of a package, leaving a message that
+/// installs show as a warning. Versions published later aren't deprecated.
+#[post("/v1/package-deprecate/<scope>/<name>", data = "<request>")]
+#[allow(clippy::too_many_arguments)]
+async fn deprecate_package(
+    config: &State<Config>,
+    indexes: &State<Indexes>,
+    metadata_cache: &State<Arc<MetadataCache>>,
+    github: &State<GithubClient>,
+    authorization: Result<MutatingAccess, Error>,
+    scope: String,
+    name: String,
+    request: Json<DeprecateRequest>,
+) -> Result<Json<serde_json::Value>, Error> {
+    let package_name = PackageName::canonical(scope, name)
+        .context("error parsing package name")
+        .status(Status::BadRequest)
+        .code("invalid_package_name")?;
+
+    set_deprecation(
+        config,
+        indexes.for_scope(package_name.scope())?,
+        metadata_cache,
+        github,
+        authorization?.into_inner(),
+        package_name,
+        None,
+        &request.message,
+    )
+    .await
+}
+
+/// Deprecates a single version of a package.
+#[post("/v1/package-deprecate/<scope>/<name>/<version>", data = "<request>")]
+#[allow(clippy::too_many_arguments)]
+async fn deprecate_version(
+    config: &State<Config>,
+    indexes: &State<Indexes>,
+    metadata_cache: &State<Arc<MetadataCache>>,
+    github: &State<GithubClient>,
+    authorization: Result<MutatingAccess, Error>,
+    scope: String,
+    name: String,
+    version: String,
+    request: Json<DeprecateRequest>,
+) -> Result<Json<serde_json::Value>, Error> {
+    let (package_name, version) = parse_package_id(scope, name, version)?.into_parts();
+
+    set_deprecation(
+        config,
+        indexes.for_scope(package_name.scope())?,
+        metadata_cache,
+        github,
+        authorization?.into_inner(),
+        package_name,
+        Some(version),
+        &request.message,
+    )
+    .await
+}
+
+/// Sets the deprecation message of one version of a package, or of all of
+/// them when `version` is `None`.
+#[allow(clippy::too_many_arguments)]
+async fn set_deprecation(
+    config: &Config,
+    index: &Arc<PackageIndex>,
+    metadata_cache: &MetadataCache,
+    github: &GithubClient,
+    authorization: WriteAccess,
+    package_name: PackageName,
+    version: Option<Version>,
+    message: &str,
+) -> Result<Json<serde_json::Value>, Error> {
+    let message = message.trim();
+
+    if message.chars().count() > MAX_DEPRECATION_MESSAGE {
+        return Err(format_err!(
+            "deprecation messages can be at most {} characters long",
+            MAX_DEPRECATION_MESSAGE
+        )
+        .status(Status::BadRequest)
+        .code("deprecation_message_too_long"));
+    }
+
+    index.update()?;
+
+    let metadata = index
+        .get_package_metadata(&package_name)
+        .status(Status::NotFound)
+        .code("package_not_found")?;
+
+    let versions = match version {
+        Some(version) => {
+            if !metadata
+                .versions
+                .iter()
+                .any(|manifest| manifest.package.version == version)
+            {
+                return Err(format_err!(
+                    "{} does not exist",
+                    PackageId::new(package_name, version)
+                )
+                .status(Status::NotFound)
+                .code("version_not_found"));
+            }
+
+            vec![version]
+        }
+        None => metadata
+            .versions
+            .iter()
+            .map(|manifest| manifest.package.version.clone())
+            .collect(),
+    };
+
+    let package_id = match versions.first() {
+        Some(version) => PackageId::new(package_name.clone(), version.clone()),
+        None => {
+            return Err(format_err!("package {} has no versions", package_name)
+                .status(Status::NotFound)
+                .code("package_not_found"))
+        }
+    };
+    let teams = GithubTeams::new(config, github);
+
+    if !authorization
+        .can_write_package(&package_id, index, &teams, &config.bootstrap)
+        .await?
+    {
+        return Err(format_err!(
+            "you do not have permission to write in scope {}",
+            package_name.scope()
+        )
+        .status(Status::Forbidden)
+        .code("scope_not_owned"));
+    }
+
+    let message = if message.is_empty() {
+        None
+    } else {
+        Some(message)
+    };
+
+    let name = package_name.clone();
+    let deprecation = message.map(str::to_owned);
+    let changed = write_index(index, move |index| {
+        index.set_deprecated(&name, &versions, deprecation.as_deref())
+    })
+    .await
+    .context("could not update deprecated versions in index")?;
+    metadata_cache.invalidate(&package_name);
+
+    let action = match message {
+        Some(_) => "Deprecated",
+        None => "Cleared the deprecation of",
+    };
+
+    Ok(Json(json!({
+        "message": format!("{} {} version(s) of {}", action, changed.len(), package_name),
+        "deprecation": message,
+        "versions": changed,
+    })))
+}
+
+/// Deletes a version that was published by mistake, removing it from both
+/// storage and the index. Only versions published within the last
+/// `unpublish_window` seconds can be deleted; older ones may already be
+/// depended on, so they have to be yanked instead.
+#[allow(clippy::too_many_arguments)]
+#[delete("/v1/package/<scope>/<name>/<version>")]
+async fn unpublish_version(
+    config: &State<Config>,
+    storage: &State<Box<dyn StorageBackend>>,
+    indexes: &State<Indexes>,
+    metadata_cache: &State<Arc<MetadataCache>>,
+    audit: &State<AuditLog>,
+    search_backend: &State<RwLock<Option<SearchBackend>>>,
+    github: &State<GithubClient>,
+    authorization: Result<MutatingAccess, Error>,
+    scope: String,
+    name: String,
+    version: String,
+) -> Result<Json<serde_json::Value>, Error> {
+    let authorization = authorization?.into_inner();
+    let package_id = parse_package_id(scope, name, version)?;
+    let index = indexes.for_scope(package_id.name().scope())?;
+
+    index.update()?;
+
+    let teams = GithubTeams::new(config, github);
+
+    if !authorization
+        .can_write_package(&package_id, index, &teams, &config.bootstrap)
+        .await?
+    {
+        return Err(format_err!(
+            "you do not have permission to write in scope {}",
+            package_id.name().scope()
+        )
+        .status(Status::Forbidden)
+        .code("scope_not_owned"));
+    }
+
+    let metadata = index
+        .get_package_metadata(package_id.name())
+        .status(Status::NotFound)
+        .code("package_not_found")?;
+
+    if !metadata
+        .versions
+        .iter()
+        .any(|manifest| &manifest.package.version == package_id.version())
+    {
+        return Err(format_err!("{} does not exist", package_id)
+            .status(Status::NotFound)
+            .code("version_not_found"));
+    }
+
+    // Versions published before publish times were recorded are always too
+    // old to delete.
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let recently_published = metadata
+        .published_at(package_id.version())
+        .map_or(false, |published| {
+            now.saturating_sub(published) < config.unpublish_window
+        });
+
+    if !recently_published {
+        return Err(format_err!(
+            "{} was published more than {} seconds ago, so it can no longer be deleted. \
+             Yank it instead with `wally yank`.",
+            package_id,
+            config.unpublish_window
+        )
+        .status(Status::Forbidden)
+        .code("unpublish_window_passed"));
+    }
+
+    // The version comes out of the index before its archive is deleted, so a
+    // version is never listed without being downloadable. Once it's out of the
+    // index nothing can find it, so failing to delete it from storage only
+    // leaves an unused archive behind, which is logged instead of failing.
+    let unpublished = package_id.clone();
+    write_index(index, move |index| index.unpublish(&unpublished))
+        .await
+        .context("could not remove package from index")?;
+    metadata_cache.invalidate(package_id.name());
+
+    if let Err(err) = storage.delete(&package_id).await {
+        tracing::warn!(%package_id, error = ?err, "could not delete unpublished version from storage");
+    }
+
+    record_audit(
+        audit,
+        AuditEvent::changed(&authorization, &package_id, AuditAction::Unpublish),
+    )
+    .await;
+
+    if let Ok(mut search_backend) = search_backend.try_write() {
+        if let Some(search_backend) = search_backend.as_mut() {
+            if let Err(err) = search_backend.crawl_packages(indexes) {
+                tracing::warn!(error = ?err, "could not update search after unpublish");
+            }
+        }
+    }
+
+    Ok(Json(json!({
+        "message": format!("Deleted {}", package_id),
+    })))
+}
+
+async fn set_yanked(
+    indexes: &Indexes,
+    metadata_cache: &MetadataCache,
+    audit: &AuditLog,
+    scope: String,
+    name: String,
+    yank_request: &YankRequest,
+    yanked: bool,
+) -> Result<Json<serde_json::Value>, Error> {
+    let package_name = PackageName::canonical(scope, name)
+        .context("error parsing package name")
+        .status(Status::BadRequest)
+        .code("invalid_package_name")?;
+    let index = indexes.for_scope(package_name.scope())?;
+
+    index.update()?;
+
+    let metadata = index
+        .get_package_metadata(&package_name)
+        .status(Status::NotFound)
+        .code("package_not_found")?;
+
+    let published: Vec<&Version> = metadata
+        .versions
+        .iter()
+        .map(|manifest| &manifest.package.version)
+        .collect();
+
+    let selected: Vec<Version> = match (&yank_request.range, &yank_request.versions) {
+        (Some(range), None) => published
+            .iter()
+            .filter(|version| range.matches(version))
+            .map(|version| (*version).clone())
+            .collect(),
+        (None, Some(versions)) => {
+            if let Some(missing) = versions.iter().find(|version| !published.contains(version)) {
+                return Err(format_err!("{}@{} does not exist", package_name, missing)
+                    .status(Status::NotFound)
+                    .code("version_not_found"));
+            }
+
+            versions.clone()
+        }
+        _ => {
+            return Err(
+                format_err!("exactly one of `range` or `versions` must be given")
+                    .status(Status::BadRequest)
+                    .code("invalid_yank_request"),
+            );
+        }
+    };
+
+    if selected.is_empty() {
+        return Err(
+            format_err!("no versions of {} matched the request", package_name)
+                .status(Status::NotFound)
+                .code("version_not_found"),
+        );
+    }
+
+    if yanked && selected.len() == published.len() && !yank_request.force {
+        return Err(format_err!(
+            "this would yank every version of {}, set `force` to do this anyway",
+            package_name
+        )
+        .status(Status::BadRequest)
+        .code("would_yank_all"));
+    }
+
+    let (action, key, kind) = match yanked {
+        true => ("Yanked", "yanked", AuditAction::Yank),
+        false => ("Unyanked", "unyanked", AuditAction::Unyank),
+    };
+
+    let name = package_name.clone();
+    let changed = write_index(index, move |index| {
+        index.set_yanked(&name, &selected, yanked)
+    })
+    .await
+    .context("could not update yanked versions in index")?;
+    metadata_cache.invalidate(&package_name);
+
+    for version in &changed {
+        let package_id = PackageId::new(package_name.clone(), version.clone());
+        record_audit(audit, AuditEvent::by_admin(&package_id, kind)).await;
+    }
+
+    Ok(Json(json!({
+        "message": format!("{} {} version(s) of {}", action, changed.len(), package_name),
+        key: changed,
+    })))
+}
+
+/// Recent publishes, yanks, and download counts for packages in a scope. Only
+/// people who can write to the scope can see its activity. Events are the
+/// scope's allowed audit events, and counts are its download stats, so they
+/// last as long as the configured persistence keeps them.
+#[allow(clippy::too_many_arguments)]
+#[get("/v1/scope/<scope>/activity?<before>&<limit>")]
+async fn scope_activity(
+    config: &State<Config>,
+    indexes: &State<Indexes>,
+    persistence: &State<Arc<dyn Persistence>>,
+    stats: &State<Arc<dyn StatsStore>>,
+    github: &State<GithubClient>,
+    authorization: Result<WriteAccess, Error>,
+    scope: String,
+    before: Option<u64>,
+    limit: Option<usize>,
+) -> Result<Json<serde_json::Value>, Error> {
+    let authorization = authorization?;
+
+    let scope = canonical_scope(&scope)
+        .context("error parsing scope")
+        .status(Status::BadRequest)
+        .code("invalid_scope")?;
+    let index = indexes.for_scope(&scope)?;
+
+    let teams = GithubTeams::new(config, github);
+
+    if !authorization
+        .can_write_scope(&scope, index, &teams, &config.bootstrap)
+        .await?
+    {
+        return Err(format_err!(
+            "you do not have permission to view activity in scope {}",
+            scope
+        )
+        .status(Status::Forbidden)
+        .code("scope_not_owned"));
+    }
+
+    let limit = limit
+        .unwrap_or(DEFAULT_ACTIVITY_PAGE)
+        .clamp(1, MAX_ACTIVITY_PAGE);
+    let events = persistence
+        .scope_audit_events(&scope, before, limit)
+        .await
+        .status(Status::ServiceUnavailable)
+        .code("activity_unavailable")?;
+    let downloads = stats
+        .scope_downloads(&scope)
+        .await
+        .status(Status::ServiceUnavailable)
+        .code("activity_unavailable")?;
+
+    // If we filled the page, there may be more events to fetch.
+    let next = match events.len() == limit {
+        true => events.last().map(|stored| stored.id),
+        false => None,
+    };
+
+    let events: Vec<_> = events
+        .iter()
+        .map(|stored| {
+            json!({
+                "id": stored.id,
+                "type": stored.event.action,
+                "package": stored.event.package,
+                "actor": stored.event.actor,
+                "timestamp": stored.event.timestamp,
+            })
+        })
+        .collect();
+
+    Ok(Json(json!({
+        "events": events,
+        "downloads": downloads,
+        "next": next,
+    })))
+}
+
+#[derive(Deserialize)]
+struct ScopeOwnersRequest {
+    scope: String,
+
+    /// GitHub user ids to make owners of the scope. Users of the registry's
+    /// other GitHub hosts are given like `"enterprise:1234"`.
+    #[serde(default)]
+    add: Vec<OwnerId>,
+
+    /// GitHub user ids to stop being owners of the scope.
+    #[serde(default)]
+    remove: Vec<OwnerId>,
+}
+
+/// Lists the packages in a scope, in alphabetical order, with the latest
+/// version of each. Packages whose every version has been yanked are listed
+/// with no version. Pages work like `search_packages`, and so does streaming,
+/// where each package's metadata is only read when its line is sent.
+#[allow(clippy::too_many_arguments)]
+#[get("/v1/scope/<scope>/packages?<limit>&<offset>&<cursor>")]
+fn scope_package_list(
+    indexes: &State<Indexes>,
+    cursors: &State<Cursors>,
+    format: Format,
+    wants_ndjson: WantsNdjson,
+    read: Result<ReadAccess, Error>,
+    scope: String,
+    limit: Option<usize>,
+    offset: Option<usize>,
+    cursor: Option<String>,
+) -> Result<ListingResponse, Error> {
+    let read = read?;
+    check_page_start(&cursor, offset)?;
+
+    let scope = canonical_scope(&scope)
+        .context("error parsing scope")
+        .status(Status::BadRequest)
+        .code("invalid_scope")?;
+    read.check_scope(&scope)?;
+    let index = indexes.for_scope(&scope)?;
+
+    if !index.scope_exists(&scope)? {
+        return Err(format_err!("scope {} does not exist", scope)
+            .status(Status::NotFound)
+            .code("scope_not_found"));
+    }
+
+    let after: Option<String> = cursor
+        .map(|cursor| cursors.decode("scope-packages", &scope, &cursor))
+        .transpose()?;
+
+    let mut names = index.scope_package_names(&scope)?;
+    let total = names.len();
+
+    let start = match &after {
+        Some(after) => names.partition_point(|name| name.name() <= after.as_str()),
+        None => offset.unwrap_or(0).min(total),
+    };
+    let remaining = names.split_off(start);
+
+    if wants_ndjson.0 {
+        let index = Arc::clone(index);
+        let packages = remaining
+            .into_iter()
+            .take(limit.unwrap_or(usize::MAX))
+            .map(move |name| match scope_package_entry(&index, &name) {
+                Ok(entry) => entry,
+
+                // The response has already started, so the error can only be
+                // reported in place of the package.
+                Err(err) => json!({
+                    "name": name.name(),
+                    "error": format!("{:#}", err),
+                }),
+            });
+
+        return Ok(ListingResponse::Stream(Ndjson(
+            stream::iter(packages).boxed(),
+        )));
+    }
+
+    let limit = limit
+        .unwrap_or(DEFAULT_SEARCH_PAGE)
+        .clamp(1, MAX_SEARCH_PAGE);
+    let next_cursor = (remaining.len() > limit)
+        .then(|| cursors.encode("scope-packages", &scope, remaining[limit - 1].name()));
+
+    let mut packages = Vec::new();
+
+    for name in remaining.iter().take(limit) {
+        packages.push(scope_package_entry(index, name)?);
+    }
+
+    Ok(ListingResponse::Encoded(format.encode(&json!({
+        "scope": scope,
+        "total": total,
+        "packages": packages,
+        "next_cursor": next_cursor,
+    }))?))
+}
+
+fn scope_package_entry(
+    index: &PackageIndex,
+    name: &PackageName,
+) -> anyhow::Result<serde_json::Value> {
+    let metadata = index.get_package_metadata(name)?;
+    let version = latest_version(&metadata).map(|latest| latest.package.version.to_string());
+
+    Ok(json!({
+        "name": name.name(),
+        "version": version,
+    }))
+}
+
+/// Lists the owners of a scope by user id. With GitHub auth, their logins are
+/// looked up too, as a best effort: a login is `null` if GitHub couldn't be
+/// asked. A scope nobody has claimed yet has no owners, rather than being a
+/// 404, since anyone whose login matches it can still claim it.
+#[get("/v1/scope/<scope>/owners")]
+async fn scope_owner_list(
+    config: &State<Config>,
+    indexes: &State<Indexes>,
+    logins: &State<GithubLogins>,
+    github: &State<GithubClient>,
+    read: Result<ReadAccess, Error>,
+    scope: String,
+) -> Result<Json<serde_json::Value>, Error> {
+    let read = read?;
+
+    let scope = canonical_scope(&scope)
+        .context("error parsing scope")
+        .status(Status::BadRequest)
+        .code("invalid_scope")?;
+    read.check_scope(&scope)?;
+    let index = indexes.for_scope(&scope)?;
+
+    let mut owners = Vec::new();
+
+    for id in index.get_scope_owners(&scope)? {
+        let login = match config.uses_github_auth() {
+            true => logins.login(config, github, &id).await,
+            false => None,
+        };
+
+        owners.push(json!({ "id": id, "login": login }));
+    }
+
+    Ok(Json(json!({
+        "scope": scope,
+        "owners": owners,
+    })))
+}
+
+/// Lists the minisign public keys that packages published to a scope can be
+/// signed with, so that anyone can check the signatures of its packages.
+#[get("/v1/scope/<scope>/signing-keys")]
+fn scope_signing_key_list(
+    indexes: &State<Indexes>,
+    read: Result<ReadAccess, Error>,
+    scope: String,
+) -> Result<Json<serde_json::Value>, Error> {
+    let read = read?;
+
+    let scope = canonical_scope(&scope)
+        .context("error parsing scope")
+        .status(Status::BadRequest)
+        .code("invalid_scope")?;
+    read.check_scope(&scope)?;
+    let index = indexes.for_scope(&scope)?;
+
+    Ok(Json(json!({
+        "scope": scope,
+        "keys": signing_key_list(&index.get_scope_signing_keys(&scope)?)?,
+    })))
+}
+
+#[derive(Deserialize)]
+struct SigningKeysRequest {
+    /// Minisign public keys, as the contents of a `.pub` file or just its
+    /// second line.
+    keys: Vec<String>,
+}
+
+/// Replaces the signing keys of a scope. Only its owners can do this, and
+/// like changing owners, it can't be done with a trusted publisher's token.
+#[put("/v1/scope/<scope>/signing-keys", data = "<keys_request>")]
+async fn set_scope_signing_keys(
+    config: &State<Config>,
+    indexes: &State<Indexes>,
+    github: &State<GithubClient>,
+    authorization: Result<MutatingAccess, Error>,
+    scope: String,
+    keys_request: Json<SigningKeysRequest>,
+) -> Result<Json<serde_json::Value>, Error> {
+    let authorization = authorization?.into_inner();
+
+    let scope = canonical_scope(&scope)
+        .context("error parsing scope")
+        .status(Status::BadRequest)
+        .code("invalid_scope")?;
+    let index = indexes.for_scope(&scope)?;
+
+    let mut keys = Vec::new();
+    for key in &keys_request.keys {
+        let key = PublicKey::parse(key)
+            .status(Status::BadRequest)
+            .code("invalid_signing_key")?;
+
+        if !keys.contains(&key.encode()) {
+            keys.push(key.encode());
+        }
+    }
+
+    index.update()?;
+
+    let teams = GithubTeams::new(config, github);
+    let permission = authorization
+        .write_permission(&scope, index, &teams, &config.bootstrap)
+        .await?;
+
+    // `permission` was checked before the index was locked for writing, so
+    // the owners are read again there.
+    let user_id = authorization.user_id();
+    let (locked_scope, locked_keys) = (scope.clone(), keys.clone());
+    write_index(index, move |index| {
+        index.set_scope_signing_keys(&locked_scope, &locked_keys, || -> Result<(), Error> {
+            let allowed = match (&permission, &user_id) {
+                (Some(WritePermission::ApiKey), _) | (Some(WritePermission::Team(_)), _) => true,
+                (Some(WritePermission::Owner), Some(user_id)) => {
+                    index.is_scope_owner(&locked_scope, user_id)?
+                }
+                _ => false,
+            };
+
+            match allowed {
+                true => Ok(()),
+                false => Err(format_err!(
+                    "you must be an owner of scope {} to change its signing keys",
+                    locked_scope
+                )
+                .status(Status::Forbidden)
+                .code("scope_not_owned")),
+            }
+        })
+    })
+    .await?;
+
+    Ok(Json(json!({
+        "scope": scope,
+        "keys": signing_key_list(&keys)?,
+    })))
+}
+
+/// Signing keys as they're listed, with the key id minisign shows for them.
+fn signing_key_list(keys: &[String]) -> Result<Vec<serde_json::Value>, Error> {
+    keys.iter()
+        .map(|key| {
+            let id = PublicKey::parse(key)?.key_id();
+            Ok(json!({ "id": id, "key": key }))
+        })
+        .collect()
+}
+
+/// Tells the caller whether they could publish to a scope, and why, so that
+/// clients can find out before building and uploading a package. This makes the
+/// same checks publishing does, minus anything about the package itself, so it
+/// says no more about the scope than a publish attempt would.
+#[get("/v1/can-publish/<scope>")]
+async fn can_publish(
+    config: &State<Config>,
+    indexes: &State<Indexes>,
+    github: &State<GithubClient>,
+    authorization: Result<WriteAccess, Error>,
+    scope: String,
+) -> Result<Json<serde_json::Value>, Error> {
+    let authorization = authorization?;
+
+    let scope = canonical_scope(&scope)
+        .context("error parsing scope")
+        .status(Status::BadRequest)
+        .code("invalid_scope")?;
+    let index = indexes.for_scope(&scope)?;
+
+    index.update()?;
+    check_scope_allowed(config, &scope)?;
+
+    // A user who could only claim the scope, but doesn't meet the bootstrap
+    // policy, gets the same 403 a publish would give them.
+    let teams = GithubTeams::new(config, github);
+    let permission = authorization
+        .write_permission(&scope, index, &teams, &config.bootstrap)
+        .await?;
+
+    let (reason, team) = match &permission {
+        Some(WritePermission::ApiKey) => ("api-key", None),
+        Some(WritePermission::Owner) => ("owner", None),
+        Some(WritePermission::Team(team)) => ("team", Some(team)),
+        Some(WritePermission::Bootstrap) => ("bootstrap", None),
+        Some(WritePermission::TrustedPublisher) => ("trusted-publisher", None),
+        None => ("denied", None),
+    };
+
+    Ok(Json(json!({
+        "scope": scope,
+        "allowed": permission.is_some(),
+        "reason": reason,
+        "team": team,
+    })))
+}
+
+/// Adds and removes owners of a scope. Only existing owners can do this, except
+/// for the user whose login matches a scope nobody owns yet, who can claim it.
+#[post("/v1/scope-owners", data = "<owners_request>")]
+async fn scope_owners(
+    config: &State<Config>,
+    indexes: &State<Indexes>,
+    github: &State<GithubClient>,
+    authorization: Result<MutatingAccess, Error>,
+    owners_request: Json<ScopeOwnersRequest>,
+) -> Result<Json<serde_json::Value>, Error> {
+    let authorization = authorization?.into_inner();
+    let ScopeOwnersRequest { scope, add, remove } = owners_request.into_inner();
+
+    let scope = canonical_scope(&scope)
+        .context("error parsing scope")
+        .status(Status::BadRequest)
+        .code("invalid_scope")?;
+    let index = indexes.for_scope(&scope)?;
+
+    for owner in &add {
+        if config.owner_api_base(owner).is_none() {
+            return Err(format_err!(
+                "{} isn't from a GitHub host this registry knows about",
+                owner
+            )
+            .status(Status::BadRequest)
+            .code("unknown_provider"));
+        }
+    }
+
+    index.update()?;
+
+    let teams = GithubTeams::new(config, github);
+    let permission = authorization
+        .write_permission(&scope, index, &teams, &config.bootstrap)
+        .await?;
+
+    // Claiming a scope creates it, so claims follow the same rules as
+    // publishing to it.
+    if permission == Some(WritePermission::Bootstrap) {
+        check_scope_allowed(config, &scope)?;
+    }
+
+    let user_id = authorization.user_id();
+    let locked_scope = scope.clone();
+    let owners = write_index(index, move |index| {
+        index.set_scope_owners(&locked_scope, |owners| {
+            change_scope_owners(
+                &locked_scope,
+                owners,
+                permission,
+                user_id.as_ref(),
+                &add,
+                &remove,
+            )
+        })
+    })
+    .await?;
+
+    Ok(Json(json!({
+        "scope": scope,
+        "owners": owners,
+    })))
+}
+
+/// Works out a scope's new owners. `permission` was checked before the owners
+/// were locked for writing, so it's checked again against `owners` here.
+fn change_scope_owners(
+    scope: &str,
+    mut owners: Vec<OwnerId>,
+    permission: Option<WritePermission>,
+    user_id: Option<&OwnerId>,
+    add: &[OwnerId],
+    remove: &[OwnerId],
+) -> Result<Vec<OwnerId>, Error> {
+    let allowed = match (&permission, user_id) {
+        (Some(WritePermission::ApiKey), _) => true,
+        (Some(WritePermission::Owner), Some(user_id)) => owners.contains(user_id),
+        (Some(WritePermission::Bootstrap), Some(user_id)) if owners.is_empty() => {
+            owners.push(user_id.clone());
+            true
+        }
+        _ => false,
+    };
+
+    if !allowed {
+        return Err(format_err!(
+            "you must be an owner of scope {} to change its owners",
+            scope
+        )
+        .status(Status::Forbidden)
+        .code("scope_not_owned"));
+    }
+
+    for owner in add {
+        if !owners.contains(owner) {
+            owners.push(owner.clone());
+        }
+    }
+
+    owners.retain(|owner| !remove.contains(owner));
+
+    if owners.is_empty() {
+        return Err(format_err!("scope {} must keep at least one owner", scope)
+            .status(Status::BadRequest)
+            .code("last_owner"));
+    }
+
+    Ok(owners)
+}
+
+#[derive(Deserialize)]
+struct ReadTokenRequest {
+    /// Who the token is for, shown by `/v1/whoami`.
+    subject: String,
+    scopes: Vec<String>,
+    /// How long the token lasts, in seconds. Defaults to the longest allowed.
+    ttl: Option<u64>,
+}
 
-#[cfg(feature = "s3-storage")]
-use crate::storage::S3Storage;
+/// Issues a read token that can only read `scopes`, and expires after `ttl`
+/// seconds. Anyone who can write to every one of the scopes can get one.
+#[post("/v1/read-tokens", data = "<token_request>")]
+async fn issue_read_token(
+    config: &State<Config>,
+    indexes: &State<Indexes>,
+    github: &State<GithubClient>,
+    authorization: Result<WriteAccess, Error>,
+    token_request: Json<ReadTokenRequest>,
+) -> Result<Json<serde_json::Value>, Error> {
+    let authorization = authorization?;
+    let ReadTokenRequest {
+        subject,
+        scopes,
+        ttl,
+    } = token_request.into_inner();
 
-#[get("/")]
-fn root() -> content::RawJson<serde_json::Value> {
-    content::RawJson(json!({
-        "message": "Wally Registry is up and running!",
-    }))
+    let read_tokens = config.read_tokens.as_ref().ok_or_else(|| {
+        format_err!("This registry doesn't issue read tokens")
+            .status(Status::NotFound)
+            .code("read_tokens_disabled")
+    })?;
+
+    if scopes.is_empty() {
+        return Err(format_err!("a read token needs at least one scope")
+            .status(Status::BadRequest)
+            .code("invalid_scope"));
+    }
+
+    let ttl = ttl.unwrap_or(read_tokens.max_ttl);
+    if ttl == 0 || ttl > read_tokens.max_ttl {
+        return Err(format_err!(
+            "read tokens can last between 1 and {} seconds",
+            read_tokens.max_ttl
+        )
+        .status(Status::BadRequest)
+        .code("invalid_ttl"));
+    }
+
+    let teams = GithubTeams::new(config, github);
+    let mut canonical_scopes = Vec::new();
+
+    for scope in &scopes {
+        let scope = canonical_scope(scope)
+            .context("error parsing scope")
+            .status(Status::BadRequest)
+            .code("invalid_scope")?;
+        let index = indexes.for_scope(&scope)?;
+        index.update()?;
+
+        // Claiming a scope isn't done by issuing tokens for it, so only
+        // existing owners and API keys count. CI jobs don't either, since
+        // they'd be handing out tokens outliving their own.
+        let permission = authorization
+            .write_permission(&scope, index, &teams, &config.bootstrap)
+            .await?;
+
+        if matches!(
+            permission,
+            None | Some(WritePermission::Bootstrap) | Some(WritePermission::TrustedPublisher)
+        ) {
+            return Err(format_err!(
+                "you must be able to write to scope {} to issue read tokens for it",
+                scope
+            )
+            .status(Status::Forbidden)
+            .code("scope_not_owned"));
+        }
+
+        canonical_scopes.push(scope);
+    }
+
+    let claims = ReadTokenClaims::new(subject, canonical_scopes, ttl)?;
+    let token = claims.encode(&read_tokens.secret)?;
+
+    Ok(Json(json!({
+        "token": token,
+        "subject": claims.sub,
+        "scopes": claims.scopes,
+        "expires": claims.exp,
+    })))
 }
 
-#[get("/v1/package-contents/<scope>/<name>/<version>")]
-async fn package_contents(
+/// Metrics in Prometheus' text format. This isn't authenticated, so registries
+/// that don't want it public should serve it on `metrics_address` instead.
+#[get("/metrics")]
+fn prometheus_metrics(metrics: &State<Arc<Metrics>>) -> (ContentType, String) {
+    (ContentType::Plain, metrics.render())
+}
+
+#[derive(Deserialize)]
+struct MaintenanceRequest {
+    enabled: bool,
+}
+
+/// Turns read-only maintenance mode on or off without restarting.
+#[put("/v1/admin/maintenance", data = "<maintenance_request>")]
+fn set_maintenance(
+    maintenance: &State<MaintenanceMode>,
+    admin: Result<AdminAccess, Error>,
+    maintenance_request: Json<MaintenanceRequest>,
+) -> Result<Json<serde_json::Value>, Error> {
+    admin?;
+
+    maintenance.set_enabled(maintenance_request.enabled);
+    tracing::info!(
+        enabled = maintenance_request.enabled,
+        "maintenance mode changed"
+    );
+
+    Ok(Json(json!({
+        "maintenance": maintenance.is_enabled(),
+    })))
+}
+
+/// Lists the GitHub accounts and tokens that are blocked.
+#[get("/v1/admin/blocklist")]
+fn list_blocklist(
+    blocklist: &State<Blocklist>,
+    admin: Result<AdminAccess, Error>,
+) -> Result<Json<BlocklistEntries>, Error> {
+    admin?;
+
+    Ok(Json(blocklist.entries()))
+}
+
+/// Blocks GitHub accounts by user id, and tokens or API keys by their SHA-256
+/// hash. Requests from either are refused with 403 Forbidden before anything
+/// else about them is checked.
+#[post("/v1/admin/blocklist", data = "<entries>")]
+async fn add_to_blocklist(
+    blocklist: &State<Blocklist>,
+    persistence: &State<Arc<dyn Persistence>>,
+    admin: Result<AdminAccess, Error>,
+    entries: Json<BlocklistEntries>,
+) -> Result<Json<BlocklistEntries>, Error> {
+    admin?;
+
+    let entries = normalize_blocklist_entries(entries.into_inner())?;
+    let updated = blocklist.add(entries).context("could not save blocklist")?;
+    save_blocklist(persistence, &updated).await?;
+
+    Ok(Json(updated))
+}
+
+/// Unblocks GitHub accounts and tokens.
+#[delete("/v1/admin/blocklist", data = "<entries>")]
+async fn remove_from_blocklist(
+    blocklist: &State<Blocklist>,
+    persistence: &State<Arc<dyn Persistence>>,
+    admin: Result<AdminAccess, Error>,
+    entries: Json<BlocklistEntries>,
+) -> Result<Json<BlocklistEntries>, Error> {
+    admin?;
+
+    let entries = normalize_blocklist_entries(entries.into_inner())?;
+    let updated = blocklist
+        .remove(&entries)
+        .context("could not save blocklist")?;
+    save_blocklist(persistence, &updated).await?;
+
+    Ok(Json(updated))
+}
+
+/// The change has already taken effect by the time it's saved, so a failure
+/// here means it won't survive a restart rather than that it didn't happen.
+async fn save_blocklist(
+    persistence: &Arc<dyn Persistence>,
+    entries: &BlocklistEntries,
+) -> Result<(), Error> {
+    persistence
+        .save_blocklist(entries)
+        .await
+        .context("the blocklist was changed, but could not be saved")
+        .status(Status::InternalServerError)
+        .code("blocklist_not_saved")
+}
+
+/// Token hashes are compared as lowercase hex, however they were written.
+fn normalize_blocklist_entries(entries: BlocklistEntries) -> Result<BlocklistEntries, Error> {
+    let token_hashes = entries
+        .token_hashes
+        .into_iter()
+        .map(|hash| {
+            let hash = hash.to_ascii_lowercase();
+
+            if hash.len() == 64 && hash.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+                Ok(hash)
+            } else {
+                Err(format_err!("{:?} is not a hex-encoded SHA-256 hash", hash)
+                    .status(Status::BadRequest)
+                    .code("invalid_token_hash"))
+            }
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(BlocklistEntries {
+        user_ids: entries.user_ids,
+        token_hashes,
+    })
+}
+
+/// Re-reads a published version from storage and checks that it still has
+/// the SHA-256 hash recorded when it was published, to catch corruption in
+/// storage.
+#[get("/v1/admin/verify/<scope>/<name>/<version>")]
+async fn verify_package(
     storage: &State<Box<dyn StorageBackend>>,
-    _read: Result<ReadAccess, Error>,
+    indexes: &State<Indexes>,
+    admin: Result<AdminAccess, Error>,
     scope: String,
     name: String,
     version: String,
-    _cli_version: Result<WallyVersion, Error>,
-) -> Result<(ContentType, ReaderStream![StorageOutput]), Error> {
-    _read?;
-    _cli_version?;
+) -> Result<Json<serde_json::Value>, Error> {
+    admin?;
+    let package_id = parse_package_id(scope, name, version)?;
+    let index = indexes.for_scope(package_id.name().scope())?;
 
-    let package_name = PackageName::new(scope, name)
-        .context("error parsing package name")
-        .status(Status::BadRequest)?;
-    let version: Version = version
-        .parse()
-        .context("error parsing version")
-        .status(Status::BadRequest)?;
-    let package_id = PackageId::new(package_name, version);
+    let metadata = index
+        .get_package_metadata(package_id.name())
+        .status(Status::NotFound)
+        .code("package_not_found")?;
 
-    match storage.read(&package_id).await.map(ReaderStream::one) {
-        Ok(stream) => Ok((ContentType::GZIP, stream)),
-        Err(e) => Err(e).status(Status::NotFound),
+    if !metadata
+        .versions
+        .iter()
+        .any(|manifest| &manifest.package.version == package_id.version())
+    {
+        return Err(format_err!("{} does not exist", package_id)
+            .status(Status::NotFound)
+            .code("version_not_found"));
     }
-}
 
-#[get("/v1/package-metadata/<scope>/<name>")]
-async fn package_info(
-    index: &State<PackageIndex>,
-    _read: Result<ReadAccess, Error>,
-    scope: String,
-    name: String,
-) -> Result<Json<serde_json::Value>, Error> {
-    _read?;
+    let expected = match metadata.sha256(package_id.version()) {
+        Some(expected) => expected,
+        None => {
+            return Err(format_err!(
+                "{} was published before SHA-256 hashes were recorded, so it can't be verified",
+                package_id
+            )
+            .status(Status::NotFound)
+            .code("checksum_not_recorded"))
+        }
+    };
 
-    let package_name = PackageName::new(scope, name)
-        .context("error parsing package name")
-        .status(Status::BadRequest)?;
+    let mut package = storage
+        .read(&package_id)
+        .await
+        .context("could not read package from storage backend")?;
 
-    let metadata = &*index.get_package_metadata(&package_name)?;
+    // Hashed a chunk at a time, so that big packages aren't held in memory.
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
 
-    Ok(Json(serde_json::to_value(metadata)?))
-}
+    loop {
+        let read = package
+            .contents
+            .read(&mut buffer)
+            .await
+            .context("could not read package from storage backend")?;
 
-#[get("/v1/package-search?<query>")]
-async fn package_search(
-    search_backend: &State<RwLock<SearchBackend>>,
-    _read: Result<ReadAccess, Error>,
-    query: String,
-) -> Result<Json<serde_json::Value>, Error> {
-    _read?;
+        if read == 0 {
+            break;
+        }
 
-    if let Ok(search_backend) = search_backend.read() {
-        let result = search_backend.search(&query)?;
-        Ok(Json(serde_json::to_value(result)?))
-    } else {
-        Err(
-            format_err!("Unexpected error during search. Try again later.")
-                .status(Status::InternalServerError),
-        )
+        hasher.update(&buffer[..read]);
     }
+
+    let actual = hex::encode(hasher.finalize());
+
+    Ok(Json(json!({
+        "package": package_id,
+        "expected": expected,
+        "actual": actual,
+        "intact": actual == expected,
+    })))
 }
 
-#[post("/v1/publish", data = "<data>")]
-async fn publish(
+/// Cross-checks every version in the indexes against storage, reporting
+/// versions whose archive is missing and archives no index lists.
+#[get("/v1/admin/consistency")]
+async fn index_consistency(
     storage: &State<Box<dyn StorageBackend>>,
-    search_backend: &State<RwLock<SearchBackend>>,
-    index: &State<PackageIndex>,
-    authorization: Result<WriteAccess, Error>,
-    _cli_version: Result<WallyVersion, Error>,
-    data: Data<'_>,
+    indexes: &State<Indexes>,
+    scope_locks: &State<ScopeLocks>,
+    admin: Result<AdminAccess, Error>,
+) -> Result<Json<ConsistencyReport>, Error> {
+    admin?;
+
+    let report = check_consistency(
+        indexes.inner().clone(),
+        storage.inner().as_ref(),
+        scope_locks,
+        false,
+    )
+    .await?;
+
+    Ok(Json(report))
+}
+
+/// Like `index_consistency`, but also deletes the archives no index lists.
+#[post("/v1/admin/consistency/prune")]
+async fn prune_orphaned_archives(
+    storage: &State<Box<dyn StorageBackend>>,
+    indexes: &State<Indexes>,
+    scope_locks: &State<ScopeLocks>,
+    admin: Result<AdminAccess, Error>,
+) -> Result<Json<ConsistencyReport>, Error> {
+    admin?;
+
+    let report = check_consistency(
+        indexes.inner().clone(),
+        storage.inner().as_ref(),
+        scope_locks,
+        true,
+    )
+    .await?;
+
+    for id in &report.pruned {
+        tracing::info!(%id, "pruned orphaned archive");
+    }
+
+    Ok(Json(report))
+}
+
+/// Reports scopes with no owners or teams, and, with `github`, owners whose
+/// GitHub accounts are gone. Nothing is changed.
+#[get("/v1/admin/scope-owners?<github>")]
+async fn audit_owners(
+    config: &State<Config>,
+    indexes: &State<Indexes>,
+    github_client: &State<GithubClient>,
+    admin: Result<AdminAccess, Error>,
+    github: Option<bool>,
+) -> Result<Json<OwnerAudit>, Error> {
+    admin?;
+
+    let audit = audit_scope_owners(
+        indexes.inner().clone(),
+        config,
+        github_client,
+        github.unwrap_or(false),
+    )
+    .await?;
+
+    Ok(Json(audit))
+}
+
+/// Refreshes the package index from its remote right away, like when the
+/// remote index's own webhook reports a change. The webhook's payload can be
+/// sent as the body: pushes to other branches are ignored, and a push to the
+/// commit the index is already at needs no fetch. Only the packages that
+/// changed are read again, unless `full` is set. Federated indexes are
+/// refreshed too, and listed separately.
+#[post("/v1/refresh-index?<full>", data = "<event>")]
+async fn refresh_index_now(
+    indexes: &State<Indexes>,
+    metadata_cache: &State<Arc<MetadataCache>>,
+    admin: Result<AdminAccess, Error>,
+    full: Option<bool>,
+    event: Option<Json<PushEvent>>,
 ) -> Result<Json<serde_json::Value>, Error> {
-    _cli_version?;
-    let authorization = authorization?;
+    admin?;
+
+    let event = event.map(Json::into_inner).unwrap_or_default();
+
+    if event.is_other_branch() {
+        return Ok(Json(json!({
+            "message": "Push wasn't to the index's branch, so nothing was refreshed",
+        })));
+    }
+
+    let full = full.unwrap_or(false);
+    let refresh = |index: &Arc<PackageIndex>| {
+        refresh_index(Arc::clone(index), metadata_cache, full, event.after.clone())
+    };
+
+    let main = refresh(indexes.main())
+        .await
+        .status(Status::BadGateway)
+        .code("index_refresh_failed")?;
+    let mut federated = Vec::new();
+
+    for index in indexes.federated() {
+        let refreshed = refresh(index)
+            .await
+            .with_context(|| format!("could not refresh federated index {}", index.url()))
+            .status(Status::BadGateway)
+            .code("index_refresh_failed")?;
+
+        federated.push(json!({
+            "url": index.url(),
+            "head": refreshed.head,
+            "full": refreshed.full,
+            "updated": refreshed.updated,
+        }));
+    }
+
+    Ok(Json(json!({
+        "message": "Package index refreshed",
+        "head": main.head,
+        "full": main.full,
+        "updated": main.updated,
+        "federated": federated,
+    })))
+}
 
+/// Reads an uploaded package. Reading stops at the registry's size limit, so
+/// oversized uploads are never held in full.
+async fn read_upload(config: &Config, data: Data<'_>) -> Result<Vec<u8>, Error> {
+    let limit = config.max_package_size.bytes();
     let contents = data
-        .open(2.mebibytes())
+        .open(limit)
         .into_bytes()
         .await
         .context("could not read request body")?;
 
     if !contents.is_complete() {
-        return Err(format_err!("request body too large").status(Status::BadRequest));
+        return Err(
+            format_err!("package is larger than the registry's limit of {}", limit)
+                .status(Status::PayloadTooLarge)
+                .code("payload_too_large"),
+        );
     }
 
-    let contents = Cursor::new(contents.value);
-    let mut archive = ZipArchive::new(contents)
+    Ok(contents.value)
+}
+
+/// Opens a package archive and reads its manifest, rejecting archives that
+/// wouldn't be safe to extract.
+fn open_package_archive(
+    contents: Vec<u8>,
+) -> Result<(ZipArchive<Cursor<Vec<u8>>>, Manifest), Error> {
+    let mut archive = ZipArchive::new(Cursor::new(contents))
         .context("could not read ZIP archive")
-        .status(Status::BadRequest)?;
+        .status(Status::BadRequest)
+        .code("invalid_archive")?;
 
-    index.update()?;
+    check_archive_paths(&mut archive)
+        .status(Status::BadRequest)
+        .code("unsafe_archive_path")?;
 
-    let manifest = get_manifest(&mut archive).status(Status::BadRequest)?;
-    let package_id = manifest.package_id();
+    let manifest_contents = read_manifest_file(&mut archive)
+        .status(Status::BadRequest)
+        .code("invalid_manifest")?;
+    let manifest = parse_manifest(&manifest_contents)?;
 
-    if !authorization.can_write_package(&package_id, &index)? {
-        return Err(format_err!(
-            "you do not have permission to write in scope {}",
-            package_id.name().scope()
-        )
-        .status(Status::Unauthorized));
-    }
+    Ok((archive, manifest))
+}
 
-    // If a user can write but isn't in the scope owner file then we should add them!
-    if let WriteAccess::Github(github_info) = authorization {
-        let user_id = github_info.id();
-        let scope = package_id.name().scope();
+/// Parses a manifest, first checking that its version is written exactly the
+/// way semver writes it. Otherwise `01.0.0` could be published next to `1.0.0`,
+/// and range resolution would have to guess which one was meant. Build
+/// metadata doesn't change which version a package is, so it's dropped.
+fn parse_manifest(contents: &[u8]) -> Result<Manifest, Error> {
+    let version = canonical_version(contents)?;
 
-        if !index.is_scope_owner(scope, user_id)? {
-            index.add_scope_owner(scope, user_id)?;
-        }
+    let mut manifest = Manifest::from_slice(contents)
+        .status(Status::BadRequest)
+        .code("invalid_manifest")?;
+
+    if let Some(version) = version {
+        manifest.package.version = version;
     }
 
-    let package_metadata = index.get_package_metadata(manifest.package_id().name());
+    Ok(manifest)
+}
+
+/// The manifest's version, checked and normalized, or `None` if the manifest
+/// doesn't declare one as a string, which parsing it will report.
+fn canonical_version(contents: &[u8]) -> Result<Option<Version>, Error> {
+    let declared = toml::from_slice::<toml::Value>(contents)
+        .ok()
+        .and_then(|manifest| {
+            manifest
+                .get("package")?
+                .get("version")?
+                .as_str()
+                .map(str::to_owned)
+        });
 
-    if let Ok(metadata) = package_metadata {
-        if metadata.versions.iter().any(|published_manifest| {
-            published_manifest.package.version == manifest.package.version
-        }) {
-            return Err(format_err!("package already exists in index").status(Status::Conflict));
-        }
+    let declared = match declared {
+        Some(declared) => declared,
+        None => return Ok(None),
+    };
+
+    let mut version = Version::parse(&declared)
+        .with_context(|| format!("{:?} is not a valid semver version", declared))
+        .status(Status::BadRequest)
+        .code("invalid_version")?;
+
+    if version.to_string() != declared {
+        return Err(format_err!(
+            "{:?} is not written the way semver writes versions; write it as {:?}",
+            declared,
+            version.to_string()
+        )
+        .status(Status::BadRequest)
+        .code("invalid_version"));
     }
 
-    storage
-        .write(&manifest.package_id(), &archive.into_inner().into_inner())
-        .await
-        .context("could not write package to storage backend")?;
+    version.build.clear();
+    Ok(Some(version))
+}
 
-    index
-        .publish(&manifest)
-        .context("could not publish package to index")?;
+/// Unix file mode bits saying what kind of file an entry is.
+const UNIX_FILE_TYPE: u32 = 0o170000;
+const UNIX_SYMLINK: u32 = 0o120000;
 
-    if let Ok(mut search_backend) = search_backend.try_write() {
-        // TODO: Recrawling the whole index for each publish is very wasteful!
-        // Eventually this will get too expensive and we should only add the new package.
-        search_backend.crawl_packages(&index)?;
+/// Checks that every entry in the archive would land inside the package if
+/// it were extracted: no absolute paths, no `..`, nothing that only looks
+/// relative on Windows, and no symlinks that could point anywhere. On Unix a
+/// name like `C:foo` is a normal component, but on Windows it's relative to
+/// wherever drive C's current directory is, so colons aren't allowed at all.
+fn check_archive_paths<R: Read + Seek>(archive: &mut ZipArchive<R>) -> anyhow::Result<()> {
+    for index in 0..archive.len() {
+        let entry = archive.by_index(index)?;
+        let name = entry.name();
+
+        let normalized = !name.is_empty()
+            && !name.contains('\\')
+            && !name.contains(':')
+            && !name.contains('\0')
+            && Path::new(name)
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+
+        if !normalized {
+            bail!("archive contains an entry with an unsafe path: {:?}", name);
+        }
+
+        if let Some(mode) = entry.unix_mode() {
+            if mode & UNIX_FILE_TYPE == UNIX_SYMLINK {
+                bail!(
+                    "archive contains a symlink, which isn't allowed: {:?}",
+                    name
+                );
+            }
+        }
     }
 
-    Ok(Json(json!({
-        "message": "Package published successfully!"
-    })))
+    Ok(())
 }
 
-fn get_manifest<R: Read + Seek>(archive: &mut ZipArchive<R>) -> anyhow::Result<Manifest> {
+fn read_manifest_file<R: Read + Seek>(archive: &mut ZipArchive<R>) -> anyhow::Result<Vec<u8>> {
     let mut manifest_file = archive
         .by_name(MANIFEST_FILE_NAME)
         .context("could not find manifest file")?;
@@ -218,15 +3504,98 @@ fn get_manifest<R: Read + Seek>(archive: &mut ZipArchive<R>) -> anyhow::Result<M
         .read_to_end(&mut manifest_contents)
         .context("could not read manifest file")?;
 
-    let manifest = Manifest::from_slice(&manifest_contents)?;
+    Ok(manifest_contents)
+}
+
+/// Clones an index repository, taking `lock` while writing to it if it's set.
+fn open_index(
+    url: &Url,
+    github_token: Option<String>,
+    lock: Option<&IndexLockConfig>,
+) -> Arc<PackageIndex> {
+    let mut index = PackageIndex::new_temp(url, github_token)
+        .unwrap_or_else(|err| panic!("could not clone index {}: {:?}", url, err));
 
-    Ok(manifest)
+    if let Some(lock) = lock {
+        println!("Using index lock for {}: {}", url, lock.path.display());
+        index.set_write_lock(IndexLock::new(
+            &lock.path,
+            Duration::from_secs(lock.timeout),
+            Duration::from_secs(lock.stale_after),
+        ));
+    }
+
+    Arc::new(index)
 }
 
 pub fn server(figment: Figment) -> rocket::Rocket<Build> {
-    let config: Config = figment.extract().expect("could not read configuration");
+    let config = Config::from_figment(&figment).expect("could not read configuration");
+    config.validate().expect("invalid configuration");
 
-    println!("Using authentication mode: {:?}", config.auth);
+    for setting in config.unused_settings() {
+        tracing::warn!(
+            %setting,
+            "setting only applies to GitHub auth, which isn't used"
+        );
+    }
+
+    if config.read_auth.is_none() && config.write_auth.is_none() {
+        println!("Using authentication mode: {:?}", config.auth);
+    } else {
+        println!(
+            "Using authentication mode: {:?} for reads, {:?} for writes",
+            config.read_auth(),
+            config.write_auth()
+        );
+    }
+
+    let maintenance = config.maintenance;
+    let auth_cache_ttl = Duration::from_secs(config.auth_cache_ttl);
+    let rate_limits = config.rate_limits;
+    let auth_throttle = config.auth_throttle;
+    let cors_origins = config.cors_origins.clone();
+    let compression = config.compression.clone();
+    if maintenance {
+        println!("Starting in read-only maintenance mode");
+    }
+
+    println!("Using minimum TLS version: {}", config.min_tls_version);
+    let github_client =
+        GithubClient::new(&config).expect("could not create HTTP client with minimum TLS version");
+
+    let blocklist = match &config.blocklist_path {
+        Some(path) => {
+            println!("Using blocklist: {}", path.display());
+            Blocklist::load(path.clone()).expect("could not load blocklist")
+        }
+        None => Blocklist::new(),
+    };
+
+    println!("Using persistence: {:?}", config.persistence);
+    let (stats, persistence) =
+        persistence::open(&config.persistence).expect("could not set up persistence");
+
+    let audit_log = match &config.audit_log {
+        Some(sink) => {
+            println!("Writing audit log to: {:?}", sink);
+            AuditLog::new(sink).expect("could not open audit log")
+        }
+        None => AuditLog::disabled(),
+    };
+    let audit_log = audit_log.persist_to(persistence.clone());
+
+    let webhooks = match &config.webhooks {
+        Some(webhooks) => {
+            println!("Sending publish webhooks to: {:?}", webhooks.urls);
+            Webhooks::new(webhooks, config.min_tls_version).expect("could not set up webhooks")
+        }
+        None => Webhooks::disabled(),
+    };
+
+    let github_app = config.github_app.as_ref().map(|app| {
+        println!("Authenticating to GitHub as app: {}", app.app_id);
+        GithubApp::new(app).expect("could not load GitHub App private key")
+    });
 
     println!("Using storage backend: {:?}", config.storage);
     let storage_backend: Box<dyn StorageBackend> = match config.storage {
@@ -235,34 +3604,215 @@ pub fn server(figment: Figment) -> rocket::Rocket<Build> {
             Box::new(configure_gcs(bucket, cache_size).unwrap())
         }
         #[cfg(feature = "s3-storage")]
-        StorageMode::S3 { bucket, cache_size } => {
-            Box::new(configure_s3(bucket, cache_size).unwrap())
-        }
+        StorageMode::S3 {
+            bucket,
+            cache_size,
+            region,
+            endpoint,
+            credentials,
+        } => Box::new(configure_s3(bucket, cache_size, region, endpoint, credentials).unwrap()),
     };
 
     println!("Cloning package index repository...");
-    let package_index = PackageIndex::new_temp(&config.index_url, config.github_token).unwrap();
+    let package_index = open_index(
+        &config.index_url,
+        config.github_token.clone(),
+        config.index_lock.as_ref(),
+    );
+    let mut indexes = Indexes::new(package_index, config.index_scopes.clone());
+
+    for federated in &config.federated_indexes {
+        println!(
+            "Serving scopes {} from federated index: {}",
+            federated.scopes.join(", "),
+            federated.url
+        );
+        let index = open_index(
+            &federated.url,
+            config.github_token.clone(),
+            federated.lock.as_ref(),
+        );
+        indexes.federate(index, federated.scopes.clone());
+    }
 
     println!("Initializing search backend...");
-    let search_backend = SearchBackend::new(&package_index).unwrap();
+    let search_backend = match SearchBackend::new(&indexes) {
+        Ok(search_backend) => Some(search_backend),
+        Err(err) => {
+            tracing::warn!(error = ?err, "search will be unavailable");
+            None
+        }
+    };
 
-    rocket::custom(figment)
+    let metrics = Arc::new(Metrics::new());
+    let metadata_cache = Arc::new(MetadataCache::new(config.metadata_cache, metrics.clone()));
+
+    let mut rocket = rocket::custom(figment)
         .mount(
             "/",
-            routes![
-                root,
-                package_contents,
-                publish,
-                package_info,
-                package_search,
-                cors_options,
-            ],
+            with_timeouts(
+                routes![
+                    root,
+                    api_info,
+                    healthz,
+                    health,
+                    package_contents,
+                    package_integrity,
+                    package_manifest,
+                    publish,
+                    publish_version,
+                    lint_manifest,
+                    package_info,
+                    package_info_batch,
+                    whoami,
+                    package_search,
+                    search_packages,
+                    recent,
+                    yank_versions,
+                    unyank_versions,
+                    yank_version,
+                    unyank_version,
+                    deprecate_package,
+                    deprecate_version,
+                    unpublish_version,
+                    scope_activity,
+                    scope_owner_list,
+                    scope_package_list,
+                    scope_owners,
+                    scope_signing_key_list,
+                    set_scope_signing_keys,
+                    issue_read_token,
+                    can_publish,
+                    package_stats,
+                    set_maintenance,
+                    list_blocklist,
+                    add_to_blocklist,
+                    remove_from_blocklist,
+                    verify_package,
+                    index_consistency,
+                    prune_orphaned_archives,
+                    audit_owners,
+                    refresh_index_now,
+                    cors_options,
+                ],
+                config.request_timeouts,
+            ),
         )
         .manage(storage_backend)
-        .manage(package_index)
+        .manage(indexes.clone())
+        .manage(metadata_cache.clone())
+        .manage(Cursors::new(config.cursor_secret.as_deref()))
+        .manage(ScopeLocks::new())
+        .manage(stats)
+        .manage(persistence.clone())
+        .manage(audit_log)
+        .manage(webhooks)
+        .manage(github_app)
+        .manage(MaintenanceMode::new(maintenance))
+        .manage(TokenCache::new(auth_cache_ttl))
+        .manage(GithubHealth::new())
+        .manage(GithubLogins::new())
+        .manage(IssuerKeys::new())
+        .manage(github_client)
+        .manage(GithubRateLimit::new())
+        .manage(blocklist)
+        .manage(RateLimiter::new(rate_limits))
+        .manage(AuthThrottle::new(auth_throttle))
         .manage(RwLock::new(search_backend))
+        .manage(metrics.clone())
         .attach(AdHoc::config::<Config>())
-        .attach(Cors)
+        .attach(RequestIds::new())
+        .attach(RequestMetrics(metrics.clone()))
+        .attach(Cors::new(cors_origins))
+        .attach(ResponseCompression::new(compression))
+        .attach(AdHoc::try_on_ignite("Persistence", move |rocket| {
+            Box::pin(async move {
+                match prepare_persistence(&rocket, persistence.as_ref()).await {
+                    Ok(()) => Ok(rocket),
+                    Err(err) => {
+                        tracing::error!(error = ?err, "could not set up persistence");
+                        Err(rocket)
+                    }
+                }
+            })
+        }));
+
+    if config.storage_self_test {
+        rocket = rocket.attach(AdHoc::try_on_ignite("Storage self-test", |rocket| {
+            Box::pin(async move {
+                let result = match rocket.state::<Box<dyn StorageBackend>>() {
+                    Some(backend) => self_test(backend.as_ref()).await,
+                    None => Ok(()),
+                };
+
+                match result {
+                    Ok(()) => Ok(rocket),
+                    Err(err) => {
+                        tracing::error!(error = ?err, "storage backend failed its self-test");
+                        Err(rocket)
+                    }
+                }
+            })
+        }));
+    } else {
+        println!("Skipping storage self-test");
+    }
+
+    if let Some(interval) = config.index_refresh_interval {
+        println!("Refreshing package index every {} seconds", interval);
+        let interval = Duration::from_secs(interval);
+        rocket = rocket.attach(AdHoc::on_liftoff("Index refresh", move |_| {
+            Box::pin(async move {
+                rocket::tokio::spawn(poll_index(indexes, metadata_cache, interval));
+            })
+        }));
+    }
+
+    match config.metrics_address {
+        Some(address) => {
+            println!("Serving metrics on: {}", address);
+            rocket = rocket.attach(AdHoc::on_liftoff("Metrics server", move |_| {
+                Box::pin(launch_metrics_server(address, metrics))
+            }));
+        }
+        None => rocket = rocket.mount("/", routes![prometheus_metrics]),
+    }
+
+    rocket
+}
+
+/// Brings the persistence store up to date and adds the blocklist it kept to
+/// the one being served.
+async fn prepare_persistence(
+    rocket: &rocket::Rocket<Build>,
+    persistence: &dyn Persistence,
+) -> anyhow::Result<()> {
+    persistence.migrate().await?;
+
+    let saved = persistence.load_blocklist().await?;
+    rocket
+        .state::<Blocklist>()
+        .expect("Blocklist was not configured")
+        .add(saved)?;
+
+    Ok(())
+}
+
+/// Serve `/metrics` on its own address, alongside the main server.
+async fn launch_metrics_server(address: SocketAddr, metrics: Arc<Metrics>) {
+    let figment = Figment::from(rocket::Config::default())
+        .merge(("address", address.ip()))
+        .merge(("port", address.port()));
+
+    let server = rocket::custom(figment)
+        .manage(metrics)
+        .mount("/", routes![prometheus_metrics]);
+
+    rocket::tokio::spawn(async move {
+        if let Err(err) = server.launch().await {
+            tracing::error!(error = ?err, "metrics server failed");
+        }
+    });
 }
 
 fn configure_gcs(bucket: String, cache_size: Option<u64>) -> anyhow::Result<GcsStorage> {
@@ -284,48 +3834,51 @@ fn configure_gcs(bucket: String, cache_size: Option<u64>) -> anyhow::Result<GcsS
 }
 
 #[cfg(feature = "s3-storage")]
-fn configure_s3(bucket: String, cache_size: Option<u64>) -> anyhow::Result<S3Storage> {
+fn configure_s3(
+    bucket: String,
+    cache_size: Option<u64>,
+    region: Option<String>,
+    endpoint: Option<String>,
+    credentials: Option<S3Credentials>,
+) -> anyhow::Result<S3Storage> {
     use std::env;
 
-    use rusoto_core::{credential::ChainProvider, request::HttpClient, Region};
+    use rusoto_core::{
+        credential::{ChainProvider, StaticProvider},
+        request::HttpClient,
+    };
 
     use rusoto_s3::S3Client;
 
-    let client = S3Client::new_with(
-        HttpClient::new()?,
-        ChainProvider::new(),
-        Region::Custom {
-            name: env::var("AWS_REGION_NAME").unwrap_or_else(|_| "us-east-1".to_string()),
-            endpoint: env::var("AWS_REGION_ENDPOINT")?,
-        },
-    );
-
-    Ok(S3Storage::new(client, bucket, cache_size))
-}
+    let region = s3_region(
+        region.or_else(|| env::var("AWS_REGION_NAME").ok()),
+        endpoint.or_else(|| env::var("AWS_REGION_ENDPOINT").ok()),
+    )?;
 
-struct Cors;
+    // Pre-signing download URLs happens locally, so the storage needs the
+    // same region and credentials as the client.
+    let storage = match credentials {
+        Some(credentials) => {
+            let provider = StaticProvider::new_minimal(
+                credentials.access_key_id,
+                credentials.secret_access_key,
+            );
+            let client = S3Client::new_with(HttpClient::new()?, provider.clone(), region.clone());
 
-#[rocket::async_trait]
-impl Fairing for Cors {
-    fn info(&self) -> Info {
-        Info {
-            name: "Add CORS headers to responses",
-            kind: Kind::Response,
+            S3Storage::new(client, bucket, cache_size).with_presigning(region, provider)
         }
-    }
+        None => {
+            let provider = ChainProvider::new();
+            let client = S3Client::new_with(HttpClient::new()?, provider.clone(), region.clone());
 
-    async fn on_response<'r>(&self, _request: &'r Request<'_>, response: &mut Response<'r>) {
-        response.set_header(Header::new("Access-Control-Allow-Origin", "*"));
-        response.set_header(Header::new("Access-Control-Allow-Methods", "GET"));
-        response.set_header(Header::new("Access-Control-Allow-Headers", "*"));
-    }
-}
+            S3Storage::new(client, bucket, cache_size).with_presigning(region, provider)
+        }
+    };
 
-#[options("/<_..>")]
-fn cors_options() -> Result<(), Error> {
-    Ok(())
+    Ok(storage)
 }
 
+/// Turns away publishes from clients older than `minimum_wally_version`.
 struct WallyVersion;
 
 #[rocket::async_trait]
@@ -338,43 +3891,84 @@ impl<'r> FromRequest<'r> for WallyVersion {
             .await
             .expect("Failed to load config");
 
-        let minimum_version = match &config.minimum_wally_version {
-            Some(version) => version,
-            None => return Outcome::Success(WallyVersion),
-        };
+        match check_wally_version(config, request) {
+            Ok(()) => Outcome::Success(WallyVersion),
+            Err(err) => err.into(),
+        }
+    }
+}
 
-        let version = match request.headers().get_one("Wally-Version") {
-            Some(version) => version,
-            None => {
-                return format_err!(
-                    "Wally version header required. Try upgrading your wally installation."
-                )
-                .status(Status::UpgradeRequired)
-                .into();
-            }
-        };
+/// Like `WallyVersion`, but for downloads, which are only turned away when
+/// `minimum_wally_version_reads` is on.
+struct ReadWallyVersion;
 
-        let version = match Version::parse(version) {
-            Ok(version) => version,
-            Err(err) => {
-                return format_err!("Failed to parse wally version header: {}", err)
-                    .status(Status::BadRequest)
-                    .into();
-            }
-        };
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ReadWallyVersion {
+    type Error = Error;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let config = request
+            .guard::<&State<Config>>()
+            .await
+            .expect("Failed to load config");
+
+        if !config.minimum_wally_version_reads {
+            return Outcome::Success(ReadWallyVersion);
+        }
+
+        match check_wally_version(config, request) {
+            Ok(()) => Outcome::Success(ReadWallyVersion),
+            Err(err) => err.into(),
+        }
+    }
+}
+
+/// Checks the version of Wally a request says it was sent by against
+/// `minimum_wally_version`, if there is one.
+fn check_wally_version(config: &Config, request: &Request<'_>) -> Result<(), Error> {
+    let minimum_version = match &config.minimum_wally_version {
+        Some(version) => version,
+        None => return Ok(()),
+    };
+
+    let allow_unknown = config.unknown_wally_version == UnknownWallyVersion::Allow;
 
-        if &version < minimum_version {
-            format_err!(
-                "This registry requires Wally {} (you are using {})",
-                minimum_version,
-                version
+    let version = match request.headers().get_one("Wally-Version") {
+        Some(version) => version,
+        None if allow_unknown => return Ok(()),
+        None => {
+            return Err(format_err!(
+                "This registry requires Wally {} or newer, but your client didn't say which \
+                 version it is. Update Wally to keep using this registry.",
+                minimum_version
             )
             .status(Status::UpgradeRequired)
-            .into()
-        } else {
-            Outcome::Success(WallyVersion)
+            .code("wally_version_required"));
+        }
+    };
+
+    let version = match Version::parse(version) {
+        Ok(version) => version,
+        Err(_) if allow_unknown => return Ok(()),
+        Err(err) => {
+            return Err(format_err!("Failed to parse wally version header: {}", err)
+                .status(Status::BadRequest)
+                .code("invalid_wally_version"));
         }
+    };
+
+    if &version < minimum_version {
+        return Err(format_err!(
+            "This registry requires Wally {} or newer, but you are using {}. Update Wally to \
+             keep using this registry.",
+            minimum_version,
+            version
+        )
+        .status(Status::UpgradeRequired)
+        .code("wally_version_too_old"));
     }
+
+    Ok(())
 }
 
 #[launch]
@@ -383,5 +3977,10 @@ fn rocket() -> _ {
         .merge(Toml::file("Rocket.toml").nested())
         .merge(Env::prefixed("WALLY_").global());
 
+    // Logging is set up here rather than in `server` so that tests, which
+    // build many servers, don't each try to install a logger.
+    let config = Config::from_figment(&figment).expect("could not read configuration");
+    logging::init(&config.log).expect("could not set up logging");
+
     server(figment)
 }
@@ -0,0 +1,27 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether the registry is in read-only maintenance mode, like during an index
+/// migration. While it's on, anything that changes the registry is turned away
+/// and everything else keeps working, including reads that need write access.
+///
+/// This lives in Rocket's managed state so that it can be toggled through the
+/// admin API without restarting the registry.
+pub struct MaintenanceMode {
+    enabled: AtomicBool,
+}
+
+impl MaintenanceMode {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled: AtomicBool::new(enabled),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,227 @@
+//! Keeps encoded package metadata and manifests for a short time, since the
+//! same popular packages are asked for over and over and each response would
+//! otherwise be encoded and hashed again from the index.
+//!
+//! Cached responses are served as-is while they're fresh. Once they're stale
+//! they're still served for a while, but the first request to find one starts
+//! encoding it again in the background, so no request waits on the index for
+//! a package that's in the cache. Publishing, yanking, deprecating, and
+//! refreshing the index drop what they change.
+//!
+//! Responses are cached without the identity of who asked, since metadata is
+//! the same for everyone who can read it. Access to the package's scope is
+//! checked before the cache is looked at.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use libwally::package_index::IndexRefresh;
+use libwally::package_name::PackageName;
+use semver::Version;
+
+use crate::config::MetadataCacheConfig;
+use crate::error::Error;
+use crate::format::{Encoded, Format};
+use crate::metrics::Metrics;
+use crate::token_cache::{Clock, SystemClock};
+
+/// Which response is cached: a package's metadata, or with `version`, one
+/// version's manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    name: PackageName,
+    version: Option<Version>,
+    format: Format,
+}
+
+impl CacheKey {
+    pub fn metadata(name: PackageName, format: Format) -> Self {
+        Self {
+            name,
+            version: None,
+            format,
+        }
+    }
+
+    pub fn manifest(name: PackageName, version: Version, format: Format) -> Self {
+        Self {
+            name,
+            version: Some(version),
+            format,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CachedResponse {
+    pub body: Encoded,
+    pub etag: Option<String>,
+}
+
+struct Entry {
+    inserted: Instant,
+    response: CachedResponse,
+
+    /// Whether a request has already started encoding this response again.
+    refreshing: bool,
+}
+
+pub struct MetadataCache {
+    fresh_for: Duration,
+    stale_for: Duration,
+    metrics: Arc<Metrics>,
+    clock: Arc<dyn Clock>,
+
+    /// Goes up every time entries are dropped, so that a response encoded
+    /// from the index before then isn't cached after it.
+    generation: AtomicU64,
+    entries: Mutex<HashMap<CacheKey, Entry>>,
+}
+
+impl MetadataCache {
+    pub fn new(config: MetadataCacheConfig, metrics: Arc<Metrics>) -> Self {
+        Self::with_clock(config, metrics, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(
+        config: MetadataCacheConfig,
+        metrics: Arc<Metrics>,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Self {
+            fresh_for: Duration::from_secs(config.ttl),
+            stale_for: Duration::from_secs(config.stale),
+            metrics,
+            clock,
+            generation: AtomicU64::new(0),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The cached response for `key`, or the one `encode` makes if there
+    /// isn't one. Errors from `encode` aren't cached.
+    pub fn get<F>(self: &Arc<Self>, key: CacheKey, encode: F) -> Result<CachedResponse, Error>
+    where
+        F: FnOnce() -> Result<CachedResponse, Error> + Send + 'static,
+    {
+        if self.fresh_for.is_zero() {
+            return encode();
+        }
+
+        let generation = self.generation.load(Ordering::SeqCst);
+
+        // The cache is only an optimization, so if it's broken we just don't
+        // use it.
+        let cached = self.entries.lock().ok().and_then(|mut entries| {
+            let entry = entries.get_mut(&key)?;
+            let age = self.clock.now().saturating_duration_since(entry.inserted);
+
+            if age < self.fresh_for {
+                return Some((entry.response.clone(), false));
+            }
+
+            if age < self.fresh_for + self.stale_for {
+                let refresh = !entry.refreshing;
+                entry.refreshing = true;
+                return Some((entry.response.clone(), refresh));
+            }
+
+            entries.remove(&key);
+            None
+        });
+
+        match cached {
+            Some((response, refresh)) => {
+                if refresh {
+                    self.metrics.record_metadata_cache("stale");
+                    self.refresh(key, encode, generation);
+                } else {
+                    self.metrics.record_metadata_cache("hit");
+                }
+
+                Ok(response)
+            }
+            None => {
+                self.metrics.record_metadata_cache("miss");
+
+                let response = encode()?;
+                self.insert(key, response.clone(), generation);
+                Ok(response)
+            }
+        }
+    }
+
+    /// Drops every cached response for a package.
+    pub fn invalidate(&self, name: &PackageName) {
+        if let Ok(mut entries) = self.entries.lock() {
+            self.generation.fetch_add(1, Ordering::SeqCst);
+            entries.retain(|key, _| &key.name != name);
+        }
+    }
+
+    /// Drops the cached responses of the packages an index refresh read
+    /// again, or every response after a full refresh.
+    pub fn refreshed(&self, refresh: &IndexRefresh) {
+        if !refresh.full {
+            for name in &refresh.updated {
+                self.invalidate(name);
+            }
+
+            return;
+        }
+
+        if let Ok(mut entries) = self.entries.lock() {
+            self.generation.fetch_add(1, Ordering::SeqCst);
+            entries.clear();
+        }
+    }
+
+    /// Encodes a stale response again on a thread of its own, since reading
+    /// the index can block.
+    fn refresh<F>(self: &Arc<Self>, key: CacheKey, encode: F, generation: u64)
+    where
+        F: FnOnce() -> Result<CachedResponse, Error> + Send + 'static,
+    {
+        let cache = Arc::clone(self);
+
+        rocket::tokio::task::spawn_blocking(move || match encode() {
+            Ok(response) => cache.insert(key, response, generation),
+
+            // The next request finds nothing cached and reports the error
+            // itself.
+            Err(_) => {
+                if let Ok(mut entries) = cache.entries.lock() {
+                    entries.remove(&key);
+                }
+            }
+        });
+    }
+
+    fn insert(&self, key: CacheKey, response: CachedResponse, generation: u64) {
+        if let Ok(mut entries) = self.entries.lock() {
+            // Something was invalidated while this was being encoded, so it
+            // may already be out of date. The next request tries again.
+            if self.generation.load(Ordering::SeqCst) != generation {
+                if let Some(entry) = entries.get_mut(&key) {
+                    entry.refreshing = false;
+                }
+
+                return;
+            }
+
+            let now = self.clock.now();
+            let max_age = self.fresh_for + self.stale_for;
+            entries.retain(|_, entry| now.saturating_duration_since(entry.inserted) < max_age);
+            entries.insert(
+                key,
+                Entry {
+                    inserted: now,
+                    response,
+                    refreshing: false,
+                },
+            );
+        }
+    }
+}
@@ -0,0 +1,302 @@
+//! Counters and histograms describing what the registry is doing, served in
+//! Prometheus' text format from `/metrics`.
+//!
+//! Metrics are kept in memory and reset when the registry restarts, which
+//! Prometheus handles on its own.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    Request, Response,
+};
+
+/// The upper bounds, in seconds, of the buckets GitHub API call durations are
+/// sorted into.
+const DURATION_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+#[derive(Default)]
+pub struct Metrics {
+    state: Mutex<MetricsState>,
+}
+
+#[derive(Default)]
+struct MetricsState {
+    requests: BTreeMap<(String, u16), u64>,
+    auth: BTreeMap<(&'static str, &'static str), u64>,
+    github_calls: BTreeMap<&'static str, Histogram>,
+    github_rate_limit: BTreeMap<&'static str, u64>,
+    publishes: u64,
+    downloads: u64,
+    metadata_cache: BTreeMap<&'static str, u64>,
+}
+
+struct Histogram {
+    /// How many observations fell into each of `DURATION_BUCKETS`. These
+    /// aren't cumulative; that's worked out when rendering.
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: vec![0; DURATION_BUCKETS.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(index) = DURATION_BUCKETS.iter().position(|&bound| seconds <= bound) {
+            self.buckets[index] += 1;
+        }
+
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_request(&self, route: &str, status: u16) {
+        self.update(|state| {
+            *state
+                .requests
+                .entry((route.to_owned(), status))
+                .or_default() += 1;
+        });
+    }
+
+    /// Count the result of authenticating a request. `mode` is the kind of
+    /// `AuthMode` the registry uses, and `outcome` is what happened, like
+    /// "success".
+    pub fn record_auth(&self, mode: &'static str, outcome: &'static str) {
+        self.update(|state| *state.auth.entry((mode, outcome)).or_default() += 1);
+    }
+
+    pub fn observe_github_call(&self, call: &'static str, duration: Duration) {
+        self.update(|state| {
+            state
+                .github_calls
+                .entry(call)
+                .or_default()
+                .observe(duration.as_secs_f64())
+        });
+    }
+
+    /// Set how many calls GitHub says are left of the registry's rate limit
+    /// for `call`.
+    pub fn set_github_rate_limit_remaining(&self, call: &'static str, remaining: u64) {
+        self.update(|state| {
+            state.github_rate_limit.insert(call, remaining);
+        });
+    }
+
+    pub fn record_publish(&self) {
+        self.update(|state| state.publishes += 1);
+    }
+
+    pub fn record_download(&self) {
+        self.update(|state| state.downloads += 1);
+    }
+
+    /// Count a lookup in the metadata cache. `outcome` is "hit", "stale" for
+    /// a stale response that's being refreshed, or "miss".
+    pub fn record_metadata_cache(&self, outcome: &'static str) {
+        self.update(|state| *state.metadata_cache.entry(outcome).or_default() += 1);
+    }
+
+    /// Render every metric in Prometheus' text exposition format.
+    pub fn render(&self) -> String {
+        let state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return String::new(),
+        };
+
+        let mut output = String::new();
+
+        // Writing to a String can't fail.
+        let _ = render_state(&state, &mut output);
+
+        output
+    }
+
+    // Metrics are auxiliary, so if the lock is poisoned they're dropped rather
+    // than failing the request being measured.
+    fn update(&self, update: impl FnOnce(&mut MetricsState)) {
+        if let Ok(mut state) = self.state.lock() {
+            update(&mut state);
+        }
+    }
+}
+
+fn render_state(state: &MetricsState, output: &mut String) -> std::fmt::Result {
+    writeln!(
+        output,
+        "# HELP wally_requests_total Requests handled, by route and status."
+    )?;
+    writeln!(output, "# TYPE wally_requests_total counter")?;
+    for ((route, status), count) in &state.requests {
+        writeln!(
+            output,
+            "wally_requests_total{{route=\"{}\",status=\"{}\"}} {}",
+            escape_label(route),
+            status,
+            count
+        )?;
+    }
+
+    writeln!(
+        output,
+        "# HELP wally_auth_total Authentication attempts, by auth mode and outcome."
+    )?;
+    writeln!(output, "# TYPE wally_auth_total counter")?;
+    for ((mode, outcome), count) in &state.auth {
+        writeln!(
+            output,
+            "wally_auth_total{{mode=\"{}\",outcome=\"{}\"}} {}",
+            mode, outcome, count
+        )?;
+    }
+
+    writeln!(
+        output,
+        "# HELP wally_github_request_duration_seconds Time spent waiting on the GitHub API."
+    )?;
+    writeln!(
+        output,
+        "# TYPE wally_github_request_duration_seconds histogram"
+    )?;
+    for (call, histogram) in &state.github_calls {
+        let mut cumulative = 0;
+
+        for (bound, count) in DURATION_BUCKETS.iter().zip(&histogram.buckets) {
+            cumulative += count;
+            writeln!(
+                output,
+                "wally_github_request_duration_seconds_bucket{{call=\"{}\",le=\"{}\"}} {}",
+                call, bound, cumulative
+            )?;
+        }
+
+        writeln!(
+            output,
+            "wally_github_request_duration_seconds_bucket{{call=\"{}\",le=\"+Inf\"}} {}",
+            call, histogram.count
+        )?;
+        writeln!(
+            output,
+            "wally_github_request_duration_seconds_sum{{call=\"{}\"}} {}",
+            call, histogram.sum
+        )?;
+        writeln!(
+            output,
+            "wally_github_request_duration_seconds_count{{call=\"{}\"}} {}",
+            call, histogram.count
+        )?;
+    }
+
+    writeln!(
+        output,
+        "# HELP wally_github_rate_limit_remaining GitHub API calls left before the registry's \
+         rate limit resets, by call."
+    )?;
+    writeln!(output, "# TYPE wally_github_rate_limit_remaining gauge")?;
+    for (call, remaining) in &state.github_rate_limit {
+        writeln!(
+            output,
+            "wally_github_rate_limit_remaining{{call=\"{}\"}} {}",
+            call, remaining
+        )?;
+    }
+
+    writeln!(output, "# HELP wally_publishes_total Packages published.")?;
+    writeln!(output, "# TYPE wally_publishes_total counter")?;
+    writeln!(output, "wally_publishes_total {}", state.publishes)?;
+
+    writeln!(output, "# HELP wally_downloads_total Packages downloaded.")?;
+    writeln!(output, "# TYPE wally_downloads_total counter")?;
+    writeln!(output, "wally_downloads_total {}", state.downloads)?;
+
+    writeln!(
+        output,
+        "# HELP wally_metadata_cache_total Package metadata and manifest lookups in the \
+         response cache, by outcome."
+    )?;
+    writeln!(output, "# TYPE wally_metadata_cache_total counter")?;
+    for (outcome, count) in &state.metadata_cache {
+        writeln!(
+            output,
+            "wally_metadata_cache_total{{outcome=\"{}\"}} {}",
+            outcome, count
+        )?;
+    }
+
+    Ok(())
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Time a call to the GitHub API, recording how long it took if the registry
+/// is collecting metrics, and log what GitHub answered.
+pub async fn time_github_call<F>(request: &Request<'_>, call: &'static str, future: F) -> F::Output
+where
+    F: std::future::Future<Output = reqwest::Result<reqwest::Response>>,
+{
+    let start = Instant::now();
+    let output = future.await;
+    let elapsed = start.elapsed();
+
+    if let Some(metrics) = request.rocket().state::<Arc<Metrics>>() {
+        metrics.observe_github_call(call, elapsed);
+    }
+
+    match &output {
+        Ok(response) => tracing::debug!(
+            call,
+            status = response.status().as_u16(),
+            elapsed_ms = elapsed.as_millis() as u64,
+            "called GitHub"
+        ),
+        Err(err) => tracing::debug!(call, error = %err, "GitHub call failed"),
+    }
+
+    output
+}
+
+/// Counts every response by the route that handled it and its status.
+pub struct RequestMetrics(pub Arc<Metrics>);
+
+#[rocket::async_trait]
+impl Fairing for RequestMetrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "Count requests for metrics",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let route = request
+            .route()
+            .and_then(|route| route.name.as_deref())
+            .unwrap_or("unmatched");
+
+        self.0.record_request(route, response.status().code);
+    }
+}
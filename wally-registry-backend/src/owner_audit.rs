@@ -0,0 +1,176 @@
+//! Checks the scope owners recorded in the indexes, for after importing
+//! packages or editing owner files by hand. Scopes nobody can manage anymore
+//! and owners whose GitHub accounts are gone are reported, but nothing is
+//! changed, since fixing them needs someone to decide who should own what.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::Context;
+use futures::{stream, StreamExt};
+use libwally::package_index::OwnerId;
+use reqwest::StatusCode;
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::github_client::GithubClient;
+use crate::indexes::Indexes;
+
+/// How many owners are looked up on GitHub at once. Kept small so that an
+/// audit doesn't use up the registry's GitHub rate limit in one go.
+const CONCURRENCY: usize = 8;
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct OwnerAudit {
+    /// How many scopes the indexes have.
+    pub scopes: usize,
+
+    /// Scopes with neither an owner nor a team, which only the API key, or
+    /// someone allowed to claim them, can change.
+    pub ownerless_scopes: Vec<String>,
+
+    /// Owners whose accounts GitHub doesn't know, or `None` if owners weren't
+    /// looked up.
+    pub stale_owners: Option<Vec<ScopeOwner>>,
+
+    /// Owners that couldn't be looked up, like when GitHub was down, or
+    /// `None` if owners weren't looked up.
+    pub unverified_owners: Option<Vec<ScopeOwner>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ScopeOwner {
+    pub scope: String,
+    pub owner: OwnerId,
+    pub reason: String,
+}
+
+enum Lookup {
+    Found,
+    Missing(String),
+    Failed(String),
+}
+
+/// Reads the owners of every scope. With `check_github`, each owner is also
+/// looked up on the GitHub host they're from, once however many scopes they
+/// own.
+pub async fn audit_scope_owners(
+    indexes: Indexes,
+    config: &Config,
+    github: &GithubClient,
+    check_github: bool,
+) -> anyhow::Result<OwnerAudit> {
+    let scopes = scope_owners(indexes).await?;
+
+    let mut audit = OwnerAudit {
+        scopes: scopes.len(),
+        ..Default::default()
+    };
+
+    let mut scopes_by_owner: BTreeMap<&OwnerId, Vec<&str>> = BTreeMap::new();
+
+    for (scope, (owners, teams)) in &scopes {
+        if owners.is_empty() && teams.is_empty() {
+            audit.ownerless_scopes.push(scope.clone());
+        }
+
+        for owner in owners {
+            scopes_by_owner.entry(owner).or_default().push(scope);
+        }
+    }
+
+    if !check_github {
+        return Ok(audit);
+    }
+
+    let lookups: Vec<_> = stream::iter(scopes_by_owner)
+        .map(|(owner, scopes)| async move { (owner, scopes, look_up(config, github, owner).await) })
+        .buffer_unordered(CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut stale_owners = Vec::new();
+    let mut unverified_owners = Vec::new();
+
+    for (owner, scopes, lookup) in lookups {
+        let (list, reason) = match lookup {
+            Lookup::Found => continue,
+            Lookup::Missing(reason) => (&mut stale_owners, reason),
+            Lookup::Failed(reason) => (&mut unverified_owners, reason),
+        };
+
+        list.extend(scopes.into_iter().map(|scope| ScopeOwner {
+            scope: scope.to_owned(),
+            owner: owner.clone(),
+            reason: reason.clone(),
+        }));
+    }
+
+    // Lookups finish in any order, so the report is sorted to stay the same
+    // from one audit to the next.
+    for list in [&mut stale_owners, &mut unverified_owners] {
+        list.sort_by(|a, b| a.scope.cmp(&b.scope).then_with(|| a.owner.cmp(&b.owner)));
+    }
+
+    audit.stale_owners = Some(stale_owners);
+    audit.unverified_owners = Some(unverified_owners);
+
+    Ok(audit)
+}
+
+/// The owners and teams of every scope the registry serves. Reading an index
+/// is blocking, so it's done on a thread of its own.
+async fn scope_owners(
+    indexes: Indexes,
+) -> anyhow::Result<BTreeMap<String, (Vec<OwnerId>, Vec<String>)>> {
+    rocket::tokio::task::spawn_blocking(move || {
+        let mut owners = BTreeMap::new();
+
+        for index in indexes.all() {
+            for scope in index.scope_names()? {
+                // A scope routed to another index is managed there.
+                if !matches!(indexes.lookup(&scope), Some(served) if Arc::ptr_eq(served, index)) {
+                    continue;
+                }
+
+                let scope_owners = index.get_scope_owners(&scope)?;
+                let teams = index.get_scope_teams(&scope)?;
+                owners.insert(scope, (scope_owners, teams));
+            }
+        }
+
+        Ok(owners)
+    })
+    .await
+    .context("owner audit was interrupted")?
+}
+
+async fn look_up(config: &Config, github: &GithubClient, owner: &OwnerId) -> Lookup {
+    let api_base = match config.owner_api_base(owner) {
+        Some(api_base) => api_base,
+        None => return Lookup::Missing(String::from("their GitHub host isn't configured")),
+    };
+
+    let mut request = github
+        .identity()
+        .get(format!("{}/user/{}", api_base, owner.id()))
+        .header("accept", "application/json");
+
+    // The registry's token is only good on the main host.
+    if let (None, Some(token)) = (owner.provider(), &config.github_token) {
+        request = request.bearer_auth(token);
+    }
+
+    match request.send().await {
+        Ok(response) if response.status() == StatusCode::NOT_FOUND => {
+            Lookup::Missing(String::from("GitHub has no user with this id"))
+        }
+        Ok(response) => match response.error_for_status() {
+            Ok(_) => Lookup::Found,
+            Err(err) => Lookup::Failed(err.to_string()),
+        },
+        Err(err) => Lookup::Failed(err.to_string()),
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use libwally::{package_id::PackageId, package_index::OwnerId, package_name::PackageName};
+use semver::Version;
+use sqlx::postgres::{PgPool, PgPoolOptions};
+
+use super::Persistence;
+use crate::audit::{AuditAction, AuditEvent, AuditOutcome, StoredAuditEvent};
+use crate::blocklist::BlocklistEntries;
+use crate::stats::StatsStore;
+
+/// An allowed audit event as it's read back: its id, timestamp, action, actor,
+/// user id and provider, package, and permission.
+type AuditRow = (
+    i64,
+    i64,
+    String,
+    String,
+    Option<i64>,
+    Option<String>,
+    String,
+    Option<String>,
+);
+
+/// Postgres has no unsigned integers, so ids and counts are stored as `BIGINT`
+/// and converted on the way in and out.
+pub struct PostgresPersistence {
+    pool: PgPool,
+}
+
+impl PostgresPersistence {
+    /// Connections are only opened once they're needed, so a database that's
+    /// still starting up doesn't stop the registry from being built. Starting
+    /// it runs the migrations, which does need the database.
+    pub fn new(url: &str, max_connections: u32) -> anyhow::Result<Self> {
+        let pool = PgPoolOptions::new()
+            .max_connections(max_connections)
+            .connect_lazy(url)
+            .context("invalid Postgres connection string")?;
+
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl StatsStore for PostgresPersistence {
+    async fn record_download(&self, package: &PackageId) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT INTO package_downloads (scope, name, version, downloads)
+             VALUES ($1, $2, $3, 1)
+             ON CONFLICT (scope, name, version)
+             DO UPDATE SET downloads = package_downloads.downloads + 1",
+        )
+        .bind(package.name().scope())
+        .bind(package.name().name())
+        .bind(package.version().to_string())
+        .execute(&self.pool)
+        .await
+        .context("could not record download")?;
+
+        Ok(())
+    }
+
+    async fn package_downloads(
+        &self,
+        name: &PackageName,
+    ) -> anyhow::Result<BTreeMap<Version, u64>> {
+        let rows: Vec<(String, i64)> = sqlx::query_as(
+            "SELECT version, downloads FROM package_downloads WHERE scope = $1 AND name = $2",
+        )
+        .bind(name.scope())
+        .bind(name.name())
+        .fetch_all(&self.pool)
+        .await
+        .context("could not read download stats")?;
+
+        rows.into_iter()
+            .map(|(version, downloads)| {
+                let version = Version::parse(&version)
+                    .with_context(|| format!("invalid version {:?} in download stats", version))?;
+                Ok((version, downloads as u64))
+            })
+            .collect()
+    }
+
+    async fn scope_downloads(&self, scope: &str) -> anyhow::Result<BTreeMap<PackageId, u64>> {
+        let rows: Vec<(String, String, i64)> = sqlx::query_as(
+            "SELECT name, version, downloads FROM package_downloads WHERE scope = $1",
+        )
+        .bind(scope)
+        .fetch_all(&self.pool)
+        .await
+        .context("could not read download stats")?;
+
+        rows.into_iter()
+            .map(|(name, version, downloads)| {
+                let package: PackageId = format!("{}/{}@{}", scope, name, version)
+                    .parse()
+                    .with_context(|| {
+                        format!(
+                            "invalid package {}/{}@{} in download stats",
+                            scope, name, version
+                        )
+                    })?;
+                Ok((package, downloads as u64))
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Persistence for PostgresPersistence {
+    async fn migrate(&self) -> anyhow::Result<()> {
+        sqlx::migrate!()
+            .run(&self.pool)
+            .await
+            .context("could not run Postgres migrations")?;
+
+        Ok(())
+    }
+
+    async fn record_audit(&self, event: &AuditEvent) -> anyhow::Result<()> {
+        let outcome = match event.outcome {
+            AuditOutcome::Allowed => "allowed",
+            AuditOutcome::Denied => "denied",
+        };
+        let permission = event
+            .permission
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
+
+        sqlx::query(
+            "INSERT INTO audit_events
+                (timestamp, outcome, actor, user_id, user_provider, package, scope, permission,
+                 reason, action)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+        )
+        .bind(event.timestamp as i64)
+        .bind(outcome)
+        .bind(&event.actor)
+        .bind(event.user_id.as_ref().map(|id| id.id() as i64))
+        .bind(event.user_id.as_ref().and_then(OwnerId::provider))
+        .bind(event.package.to_string())
+        .bind(&event.scope)
+        .bind(permission)
+        .bind(event.reason.as_deref())
+        .bind(event.action.as_str())
+        .execute(&self.pool)
+        .await
+        .context("could not record audit event")?;
+
+        Ok(())
+    }
+
+    async fn scope_audit_events(
+        &self,
+        scope: &str,
+        before: Option<u64>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<StoredAuditEvent>> {
+        let rows: Vec<AuditRow> = sqlx::query_as(
+            "SELECT id, timestamp, action, actor, user_id, user_provider, package, permission
+             FROM audit_events
+             WHERE scope = $1 AND outcome = 'allowed' AND ($2::BIGINT IS NULL OR id < $2)
+             ORDER BY id DESC
+             LIMIT $3",
+        )
+        .bind(scope)
+        .bind(before.map(|id| id as i64))
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await
+        .context("could not read audit events")?;
+
+        rows.into_iter()
+            .map(|row| {
+                let (id, timestamp, action, actor, user_id, user_provider, package, permission) =
+                    row;
+
+                let event = AuditEvent {
+                    timestamp: timestamp as u64,
+                    action: AuditAction::parse(&action)
+                        .with_context(|| format!("invalid audit action {:?}", action))?,
+                    outcome: AuditOutcome::Allowed,
+                    actor,
+                    user_id: user_id.map(|id| match user_provider {
+                        Some(provider) => OwnerId::with_provider(&provider, id as u64),
+                        None => OwnerId::new(id as u64),
+                    }),
+                    package: package.parse().with_context(|| {
+                        format!("invalid package {:?} in audit events", package)
+                    })?,
+                    scope: scope.to_owned(),
+                    permission: permission
+                        .map(|permission| serde_json::from_str(&permission))
+                        .transpose()?,
+                    reason: None,
+                };
+
+                Ok(StoredAuditEvent {
+                    id: id as u64,
+                    event,
+                })
+            })
+            .collect()
+    }
+
+    async fn load_blocklist(&self) -> anyhow::Result<BlocklistEntries> {
+        let user_ids: Vec<(i64, Option<String>)> =
+            sqlx::query_as("SELECT user_id, user_provider FROM blocked_users")
+                .fetch_all(&self.pool)
+                .await
+                .context("could not read blocked users")?;
+        let token_hashes: Vec<(String,)> = sqlx::query_as("SELECT token_hash FROM blocked_tokens")
+            .fetch_all(&self.pool)
+            .await
+            .context("could not read blocked tokens")?;
+
+        Ok(BlocklistEntries {
+            user_ids: user_ids
+                .into_iter()
+                .map(|(id, provider)| match provider {
+                    Some(provider) => OwnerId::with_provider(&provider, id as u64),
+                    None => OwnerId::new(id as u64),
+                })
+                .collect(),
+            token_hashes: token_hashes.into_iter().map(|(hash,)| hash).collect(),
+        })
+    }
+
+    /// The whole blocklist is replaced in one transaction, so a failed save
+    /// leaves the old one in place.
+    async fn save_blocklist(&self, entries: &BlocklistEntries) -> anyhow::Result<()> {
+        let mut transaction = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM blocked_users")
+            .execute(&mut transaction)
+            .await?;
+        for id in &entries.user_ids {
+            sqlx::query("INSERT INTO blocked_users (user_id, user_provider) VALUES ($1, $2)")
+                .bind(id.id() as i64)
+                .bind(id.provider())
+                .execute(&mut transaction)
+                .await?;
+        }
+
+        sqlx::query("DELETE FROM blocked_tokens")
+            .execute(&mut transaction)
+            .await?;
+        for hash in &entries.token_hashes {
+            sqlx::query("INSERT INTO blocked_tokens (token_hash) VALUES ($1)")
+                .bind(hash)
+                .execute(&mut transaction)
+                .await?;
+        }
+
+        transaction
+            .commit()
+            .await
+            .context("could not save blocklist")?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+//! Range requests, so that clients can resume a package download that was cut
+//! off instead of starting it again.
+
+use std::convert::Infallible;
+
+use anyhow::format_err;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+
+use crate::error::{ApiErrorStatus, Error};
+use crate::storage::ByteRange;
+
+/// The `Range` and `If-Range` headers of a request, if it had them.
+pub struct RangeHeader {
+    range: Option<String>,
+    if_range: Option<String>,
+}
+
+impl RangeHeader {
+    pub fn is_requested(&self) -> bool {
+        self.range.is_some()
+    }
+
+    /// Works out which bytes of a `size`-byte package to send, or `None` to
+    /// send all of them. That's when no range was asked for, when `If-Range`
+    /// says the client's copy is out of date, and when several ranges were
+    /// asked for, since clients resuming a download only ever ask for one.
+    pub fn resolve(&self, size: u64, etag: Option<&str>) -> Result<Option<ByteRange>, Error> {
+        let header = match &self.range {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+
+        // Only strong tags are compared for `If-Range`, so a weak tag never
+        // matches.
+        if let Some(if_range) = &self.if_range {
+            if etag != Some(if_range.trim()) {
+                return Ok(None);
+            }
+        }
+
+        let spec = match header.trim().strip_prefix("bytes=") {
+            Some(spec) => spec.trim(),
+            None => return Err(unsatisfiable(size, "only byte ranges are supported")),
+        };
+
+        if spec.contains(',') {
+            return Ok(None);
+        }
+
+        let (start, end) = spec
+            .split_once('-')
+            .ok_or_else(|| unsatisfiable(size, "the range is malformed"))?;
+        let (start, end) = (start.trim(), end.trim());
+
+        let parse = |value: &str| {
+            value
+                .parse::<u64>()
+                .map_err(|_| unsatisfiable(size, "the range is malformed"))
+        };
+
+        let range = match (start.is_empty(), end.is_empty()) {
+            // `bytes=-500` is the last 500 bytes.
+            (true, false) => {
+                let length = parse(end)?;
+                ByteRange {
+                    start: size.saturating_sub(length),
+                    end: size,
+                }
+            }
+            (false, true) => ByteRange {
+                start: parse(start)?,
+                end: size,
+            },
+            (false, false) => {
+                let (start, last) = (parse(start)?, parse(end)?);
+
+                if last < start {
+                    return Err(unsatisfiable(size, "the range ends before it starts"));
+                }
+
+                ByteRange {
+                    start,
+                    end: last.saturating_add(1).min(size),
+                }
+            }
+            (true, true) => return Err(unsatisfiable(size, "the range is malformed")),
+        };
+
+        if range.start >= range.end {
+            return Err(unsatisfiable(
+                size,
+                &format!("the package is only {} bytes long", size),
+            ));
+        }
+
+        Ok(Some(range))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RangeHeader {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let headers = request.headers();
+
+        Outcome::Success(RangeHeader {
+            range: headers.get_one("Range").map(str::to_owned),
+            if_range: headers.get_one("If-Range").map(str::to_owned),
+        })
+    }
+}
+
+/// Tells the client how big the package really is, so it can ask again.
+fn unsatisfiable(size: u64, reason: &str) -> Error {
+    format_err!("can't send the requested range: {}", reason)
+        .status(Status::RangeNotSatisfiable)
+        .code("range_not_satisfiable")
+        .header("Content-Range", format!("bytes */{}", size))
+}
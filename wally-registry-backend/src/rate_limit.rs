@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::format_err;
+use libwally::package_index::OwnerId;
+use rocket::{http::Status, request::Outcome, Request, State};
+
+use crate::config::{RateLimit, RateLimits};
+use crate::error::{ApiErrorStatus, Error};
+use crate::token_cache::{Clock, SystemClock};
+
+/// Once this many buckets are being tracked, full ones are forgotten, since
+/// they're no different from a bucket that was never used.
+const MAX_BUCKETS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// Who a request is counted against.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Identity {
+    /// Someone signed in with GitHub or GitLab, by user id.
+    User(OwnerId),
+
+    /// A client using an API key, by the key's hash so that clients sharing a
+    /// registry with several keys don't share a bucket.
+    ApiKey([u8; 32]),
+
+    /// Anyone who didn't need to sign in, by IP address.
+    Ip(Option<IpAddr>),
+}
+
+impl Identity {
+    pub fn api_key(request: &Request<'_>) -> Self {
+        let token = request
+            .headers()
+            .get_one("authorization")
+            .unwrap_or_default();
+
+        Identity::ApiKey(*blake3::hash(token.as_bytes()).as_bytes())
+    }
+
+    pub fn ip(request: &Request<'_>) -> Self {
+        Identity::Ip(request.client_ip())
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: std::time::Instant,
+}
+
+/// Token buckets limiting how often each identity can read and write, so
+/// that one misbehaving client can't starve everyone else or use up the
+/// registry's GitHub API budget.
+pub struct RateLimiter {
+    limits: RateLimits,
+    clock: Arc<dyn Clock>,
+    buckets: Mutex<HashMap<(Identity, AccessKind), Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        Self::with_clock(limits, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(limits: RateLimits, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            limits,
+            clock,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a token from the identity's bucket, or say how long to wait until
+    /// one is available.
+    pub fn check(&self, identity: Identity, kind: AccessKind) -> Result<(), Duration> {
+        let limit = match kind {
+            AccessKind::Read => self.limits.read,
+            AccessKind::Write => self.limits.write,
+        };
+
+        let limit = match limit {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+
+        let capacity = f64::from(limit.requests);
+        let refill_per_second = refill_rate(limit);
+        let now = self.clock.now();
+
+        // Rate limiting shouldn't take the registry down with it.
+        let mut buckets = match self.buckets.lock() {
+            Ok(buckets) => buckets,
+            Err(_) => return Ok(()),
+        };
+
+        if buckets.len() >= MAX_BUCKETS {
+            let limits = &self.limits;
+            buckets.retain(|(_, kind), bucket| {
+                let limit = match kind {
+                    AccessKind::Read => limits.read,
+                    AccessKind::Write => limits.write,
+                };
+
+                match limit {
+                    Some(limit) => {
+                        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+                        bucket.tokens + elapsed * refill_rate(limit) < f64::from(limit.requests)
+                    }
+                    None => false,
+                }
+            });
+        }
+
+        let bucket = buckets.entry((identity, kind)).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * refill_per_second).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - bucket.tokens) / refill_per_second;
+            Err(Duration::from_secs_f64(wait))
+        }
+    }
+}
+
+fn refill_rate(limit: RateLimit) -> f64 {
+    f64::from(limit.requests) / limit.window.max(1) as f64
+}
+
+/// Count a request that passed authentication against its identity's limit,
+/// turning it away with 429 Too Many Requests if the limit's been reached.
+pub async fn rate_limit<T>(
+    request: &Request<'_>,
+    outcome: Outcome<T, Error>,
+    kind: AccessKind,
+    identity: impl FnOnce(&T) -> Identity,
+) -> Outcome<T, Error> {
+    let access = match outcome {
+        Outcome::Success(access) => access,
+        outcome => return outcome,
+    };
+
+    let limiter = request
+        .guard::<&State<RateLimiter>>()
+        .await
+        .expect("RateLimiter was not configured");
+
+    match limiter.check(identity(&access), kind) {
+        Ok(()) => Outcome::Success(access),
+        Err(wait) => {
+            let retry_after = wait.as_secs_f64().ceil() as u64;
+
+            format_err!("Too many requests. Try again in {} second(s).", retry_after)
+                .status(Status::TooManyRequests)
+                .code("rate_limited")
+                .retry_after(retry_after)
+                .into()
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Read tokens issued by the registry itself: JWTs signed with a secret only
+//! the registry knows, which grant read access to a few scopes until they
+//! expire. They let read access be handed to third parties without a GitHub
+//! account or one of the registry's API keys.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use jsonwebtoken::{errors::ErrorKind, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+
+/// Set as the token's audience, so that JWTs signed with the same secret for
+/// anything else aren't taken for read tokens.
+const AUDIENCE: &str = "wally-read";
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ReadTokenConfig {
+    /// The secret tokens are signed with. Changing it revokes every token
+    /// issued so far.
+    pub secret: String,
+
+    /// The longest a token can last, in seconds. Tokens asked for without a
+    /// lifetime get this one.
+    #[serde(default = "default_max_ttl")]
+    pub max_ttl: u64,
+}
+
+fn default_max_ttl() -> u64 {
+    30 * 24 * 60 * 60
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ReadTokenClaims {
+    /// Who the token was issued to, like the name of a partner or a CI job,
+    /// for telling tokens apart.
+    pub sub: String,
+
+    /// The only scopes the token can read.
+    pub scopes: Vec<String>,
+
+    pub aud: String,
+
+    /// When the token was issued and when it expires, in seconds since the
+    /// Unix epoch.
+    pub iat: u64,
+    pub exp: u64,
+}
+
+impl ReadTokenClaims {
+    pub fn new(subject: String, scopes: Vec<String>, ttl: u64) -> anyhow::Result<Self> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+        Ok(Self {
+            sub: subject,
+            scopes,
+            aud: AUDIENCE.to_owned(),
+            iat: now,
+            exp: now + ttl,
+        })
+    }
+
+    pub fn encode(&self, secret: &str) -> anyhow::Result<String> {
+        Ok(jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            self,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )?)
+    }
+}
+
+/// Why a token wasn't accepted.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReadTokenError {
+    /// The token isn't a JWT at all, so it's some other kind of credential.
+    NotAToken,
+    Expired,
+    Invalid,
+}
+
+/// Checks a token's signature, expiry, and audience, returning its claims if
+/// they're all good.
+pub fn verify(secret: &str, token: &str) -> Result<ReadTokenClaims, ReadTokenError> {
+    if jsonwebtoken::decode_header(token).is_err() {
+        return Err(ReadTokenError::NotAToken);
+    }
+
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.leeway = 0;
+    validation.set_audience(&[AUDIENCE]);
+
+    match jsonwebtoken::decode(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &validation,
+    ) {
+        Ok(data) => Ok(data.claims),
+        Err(err) if matches!(err.kind(), ErrorKind::ExpiredSignature) => {
+            Err(ReadTokenError::Expired)
+        }
+        Err(_) => Err(ReadTokenError::Invalid),
+    }
+}
@@ -0,0 +1,120 @@
+//! Gives every request an id, which is logged with everything done for it and
+//! sent back in the `X-Request-Id` header, so that a failed request reported
+//! by a user can be found in the logs.
+
+use std::convert::Infallible;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Data, Request, Response};
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Ids passed in by a proxy in front of the registry are kept, as long as
+/// they're short and made of characters that are safe to log.
+const MAX_INCOMING_LENGTH: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// The id of `request`, given to it by the `RequestIds` fairing.
+    pub fn of<'r>(request: &'r Request<'_>) -> &'r RequestId {
+        request.local_cache(|| RequestId("unknown".to_owned()))
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestId {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(RequestId::of(request).clone())
+    }
+}
+
+/// Hands out request ids and logs each request once it's been answered.
+pub struct RequestIds {
+    /// Ids start with when the registry started, so that ids from different
+    /// runs of the registry don't collide.
+    prefix: String,
+    next: AtomicU64,
+}
+
+impl RequestIds {
+    pub fn new() -> Self {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis())
+            .unwrap_or_default();
+
+        Self {
+            prefix: format!("{:x}", started),
+            next: AtomicU64::new(0),
+        }
+    }
+
+    fn generate(&self) -> String {
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        format!("{}-{}", self.prefix, id)
+    }
+}
+
+impl Default for RequestIds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn is_valid_incoming(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_INCOMING_LENGTH
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+#[rocket::async_trait]
+impl Fairing for RequestIds {
+    fn info(&self) -> Info {
+        Info {
+            name: "Assign request ids",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+        let incoming = request
+            .headers()
+            .get_one(REQUEST_ID_HEADER)
+            .filter(|id| is_valid_incoming(id))
+            .map(str::to_owned);
+
+        request.local_cache(|| RequestId(incoming.unwrap_or_else(|| self.generate())));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let request_id = RequestId::of(request);
+
+        // Only the path is logged, since query strings can hold anything.
+        tracing::info!(
+            request_id = %request_id,
+            method = %request.method(),
+            path = %request.uri().path(),
+            status = response.status().code,
+            "answered request"
+        );
+
+        response.set_header(Header::new(REQUEST_ID_HEADER, request_id.to_string()));
+    }
+}
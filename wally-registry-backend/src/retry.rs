@@ -0,0 +1,64 @@
+//! Retries calls to GitHub that failed for reasons that are likely to go away
+//! on their own, so that a brief GitHub outage doesn't fail publishes outright.
+
+use std::future::Future;
+use std::time::Instant;
+
+use reqwest::{Response, StatusCode};
+
+use crate::config::GithubRetries;
+
+/// Retries GitHub calls with exponential backoff. All calls made through one
+/// instance share a single time budget, so a request making several calls
+/// can't keep retrying for minutes.
+pub struct GithubRetry {
+    config: GithubRetries,
+    deadline: Instant,
+}
+
+impl GithubRetry {
+    pub fn new(config: GithubRetries) -> Self {
+        Self {
+            config,
+            deadline: Instant::now() + config.budget(),
+        }
+    }
+
+    /// Calls `send` until it gives a response that isn't a transient failure,
+    /// runs out of attempts, or would run past the budget.
+    pub async fn send<F, Fut>(&self, mut send: F) -> reqwest::Result<Response>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = reqwest::Result<Response>>,
+    {
+        let mut delay = self.config.base_delay();
+        let mut attempt = 1;
+
+        loop {
+            let result = send().await;
+
+            if !is_transient(&result)
+                || attempt >= self.config.max_attempts
+                || Instant::now() + delay > self.deadline
+            {
+                return result;
+            }
+
+            rocket::tokio::time::sleep(delay).await;
+            delay *= 2;
+            attempt += 1;
+        }
+    }
+}
+
+/// Failures worth trying again. Anything else, like a 401 or 422 for a bad
+/// token, would just fail the same way again.
+fn is_transient(result: &reqwest::Result<Response>) -> bool {
+    match result {
+        Ok(response) => matches!(
+            response.status(),
+            StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+        ),
+        Err(err) => err.is_connect(),
+    }
+}
@@ -0,0 +1,32 @@
+//! Serializes publishes to the same scope. Publishing reads the scope's owners
+//! and the package's versions from the index, then writes them back, so two
+//! publishes to one scope at once could each miss the other's change.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use rocket::tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+/// One lock per scope, made the first time the scope is published to.
+/// Publishes to different scopes don't wait on each other.
+#[derive(Default)]
+pub struct ScopeLocks {
+    locks: Mutex<HashMap<String, Arc<AsyncMutex<()>>>>,
+}
+
+impl ScopeLocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Waits until no other publish holds `scope`'s lock, then holds it until
+    /// the returned guard is dropped.
+    pub async fn lock(&self, scope: &str) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap();
+            Arc::clone(locks.entry(scope.to_owned()).or_default())
+        };
+
+        lock.lock_owned().await
+    }
+}
@@ -1,15 +1,16 @@
 use std::time::Instant;
 
-use libwally::package_index::PackageIndex;
-use libwally::package_name::PackageName;
+use libwally::{manifest::Manifest, package_index::PackageMetadata, package_name::PackageName};
 use tantivy::collector::TopDocs;
 use tantivy::query::QueryParser;
 
+use semver::Version;
 use serde::{Deserialize, Serialize};
 use tantivy::tokenizer::{LowerCaser, NgramTokenizer, TextAnalyzer};
 use tantivy::{schema::*, IndexReader, ReloadPolicy};
 use tantivy::{Index, IndexWriter};
-use walkdir::{DirEntry, WalkDir};
+
+use crate::indexes::Indexes;
 
 static DOC_LIMIT: usize = 100;
 
@@ -21,7 +22,7 @@ pub struct SearchBackend {
 }
 
 impl SearchBackend {
-    pub fn new(package_index: &PackageIndex) -> anyhow::Result<Self> {
+    pub fn new(indexes: &Indexes) -> anyhow::Result<Self> {
         let mut schema_builder = Schema::builder();
 
         let text_options = TextOptions::default()
@@ -66,11 +67,11 @@ impl SearchBackend {
             query_parser,
         };
 
-        backend.crawl_packages(package_index)?;
+        backend.crawl_packages(indexes)?;
         Ok(backend)
     }
 
-    pub fn crawl_packages(&mut self, package_index: &PackageIndex) -> anyhow::Result<()> {
+    pub fn crawl_packages(&mut self, indexes: &Indexes) -> anyhow::Result<()> {
         let scope = self.schema.get_field("scope").unwrap();
         let name = self.schema.get_field("name").unwrap();
         let versions = self.schema.get_field("versions").unwrap();
@@ -80,29 +81,8 @@ impl SearchBackend {
         let now = Instant::now();
         self.writer.delete_all_documents()?;
 
-        for entry in WalkDir::new(package_index.path())
-            .min_depth(1)
-            .into_iter()
-            .filter_entry(|e| !is_config(e))
-        {
-            let entry = entry?;
-            let path = entry.path();
-
-            if path.is_dir() {
-                continue;
-            }
-
-            let package_scope = path
-                .parent()
-                .unwrap()
-                .file_name()
-                .unwrap()
-                .to_str()
-                .unwrap();
-            let package_name = path.file_name().unwrap().to_str().unwrap();
-
-            let metadata = package_index
-                .get_package_metadata(&PackageName::new(package_scope, package_name)?)?;
+        for (package_name, package_index) in indexes.package_names()? {
+            let metadata = package_index.get_package_metadata(&package_name)?;
 
             let mut doc = Document::default();
 
@@ -158,14 +138,6 @@ impl SearchBackend {
     }
 }
 
-fn is_config(entry: &DirEntry) -> bool {
-    entry
-        .file_name()
-        .to_str()
-        .map(|s| s.ends_with(".json") || s.ends_with(".toml") || s.starts_with('.'))
-        .unwrap_or(false)
-}
-
 #[derive(Serialize, Deserialize)]
 struct NativeDocResult {
     scope: Vec<String>,
@@ -181,3 +153,122 @@ pub struct DocResult {
     versions: Vec<String>,
     description: Option<String>,
 }
+
+impl DocResult {
+    pub fn scope(&self) -> &str {
+        &self.scope
+    }
+}
+
+/// A package found by `find_packages`.
+#[derive(Debug, Serialize)]
+pub struct PackageMatch {
+    pub scope: String,
+    pub name: String,
+
+    /// The newest version that hasn't been yanked, preferring stable versions.
+    pub version: Version,
+
+    pub description: Option<String>,
+
+    #[serde(skip)]
+    rank: MatchRank,
+}
+
+impl PackageMatch {
+    /// Where this match is among the others, for picking a search up after
+    /// it.
+    pub fn position(&self) -> SearchPosition {
+        SearchPosition {
+            rank: self.rank,
+            scope: self.scope.clone(),
+            name: self.name.clone(),
+        }
+    }
+}
+
+/// A place in the order `find_packages` returns matches in.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct SearchPosition {
+    rank: MatchRank,
+    scope: String,
+    name: String,
+}
+
+/// How closely a package matched a query. Earlier variants rank first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum MatchRank {
+    ExactName,
+    Name,
+    Description,
+}
+
+/// The newest version of a package that hasn't been yanked, preferring stable
+/// versions over prereleases.
+pub fn latest_version(metadata: &PackageMetadata) -> Option<&Manifest> {
+    metadata
+        .versions
+        .iter()
+        .filter(|manifest| !metadata.is_yanked(&manifest.package.version))
+        .max_by_key(|manifest| {
+            let version = &manifest.package.version;
+            (!version.is_prerelease(), version.clone())
+        })
+}
+
+/// Finds packages whose name or description contains `query`, ignoring case.
+/// Unlike `SearchBackend`, this reads the index directly, so it's always up to
+/// date and available even when the search index couldn't be built.
+///
+/// Packages whose name is exactly the query come first, then other name
+/// matches, then description matches, each in alphabetical order.
+pub fn find_packages(indexes: &Indexes, query: &str) -> anyhow::Result<Vec<PackageMatch>> {
+    let query = query.trim().to_lowercase();
+    let mut matches = Vec::new();
+
+    for (package_name, package_index) in indexes.package_names()? {
+        let metadata = package_index.get_package_metadata(&package_name)?;
+
+        // Every version has been yanked, so there's nothing to offer.
+        let latest = match latest_version(&metadata) {
+            Some(latest) => latest,
+            None => continue,
+        };
+
+        let description = latest.package.description.clone();
+        let rank = match rank_match(&package_name, description.as_deref(), &query) {
+            Some(rank) => rank,
+            None => continue,
+        };
+
+        matches.push(PackageMatch {
+            scope: package_name.scope().to_owned(),
+            name: package_name.name().to_owned(),
+            version: latest.package.version.clone(),
+            description,
+            rank,
+        });
+    }
+
+    matches.sort_by_key(PackageMatch::position);
+
+    Ok(matches)
+}
+
+fn rank_match(name: &PackageName, description: Option<&str>, query: &str) -> Option<MatchRank> {
+    let full_name = name.to_string().to_lowercase();
+    let short_name = name.name().to_lowercase();
+
+    if full_name == query || short_name == query {
+        Some(MatchRank::ExactName)
+    } else if full_name.contains(query) {
+        Some(MatchRank::Name)
+    } else if description.map_or(false, |description| {
+        description.to_lowercase().contains(query)
+    }) {
+        Some(MatchRank::Description)
+    } else {
+        None
+    }
+}
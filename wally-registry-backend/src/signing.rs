@@ -0,0 +1,208 @@
+//! Minisign signatures of published packages. Publishers sign the archive
+//! they upload with `minisign -S`, and send the `.minisig` file, in base64,
+//! in the `Wally-Signature` header. The signature has to be from one of the
+//! keys the scope's owners registered, and it's kept in the index next to the
+//! version so that anyone can check it later.
+//!
+//! Minisign signs with Ed25519. Signatures made the default way sign the
+//! BLAKE2b-512 hash of the file rather than the file itself, and older ones
+//! sign the file.
+
+use std::convert::{Infallible, TryInto};
+
+use anyhow::format_err;
+use blake2::{Blake2b512, Digest};
+use ed25519_dalek::{Signature, VerifyingKey};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+
+use crate::error::{ApiErrorStatus, Error};
+
+/// Minisign's name for Ed25519 signatures of the file itself.
+const LEGACY_ALGORITHM: &[u8] = b"Ed";
+
+/// Minisign's name for Ed25519 signatures of the file's BLAKE2b-512 hash.
+const PREHASHED_ALGORITHM: &[u8] = b"ED";
+
+const UNTRUSTED_COMMENT: &str = "untrusted comment:";
+const TRUSTED_COMMENT: &str = "trusted comment: ";
+
+/// A minisign public key.
+pub struct PublicKey {
+    key_id: [u8; 8],
+    key: [u8; 32],
+}
+
+impl PublicKey {
+    /// Reads a public key from the contents of a minisign `.pub` file, or
+    /// just its second line.
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let encoded = text
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with(UNTRUSTED_COMMENT))
+            .ok_or_else(|| format_err!("the public key is empty"))?;
+
+        let bytes = base64::decode(encoded)
+            .map_err(|_| format_err!("the public key isn't valid base64"))?;
+
+        if bytes.len() != 42 || &bytes[..2] != LEGACY_ALGORITHM {
+            anyhow::bail!("this isn't a minisign Ed25519 public key");
+        }
+
+        Ok(Self {
+            key_id: bytes[2..10].try_into().unwrap(),
+            key: bytes[10..].try_into().unwrap(),
+        })
+    }
+
+    /// The key as the second line of a `.pub` file, which is how keys are
+    /// stored in the index.
+    pub fn encode(&self) -> String {
+        let mut bytes = LEGACY_ALGORITHM.to_vec();
+        bytes.extend_from_slice(&self.key_id);
+        bytes.extend_from_slice(&self.key);
+
+        base64::encode(bytes)
+    }
+
+    /// The key id the way minisign prints it.
+    pub fn key_id(&self) -> String {
+        format_key_id(&self.key_id)
+    }
+}
+
+/// A signature sent with a package, along with what it's expected to sign.
+pub struct SignedArchive {
+    /// The `.minisig` file as it was sent, to store in the index.
+    pub text: String,
+
+    key_id: [u8; 8],
+    signature: [u8; 64],
+    trusted_comment: String,
+    global_signature: [u8; 64],
+
+    /// The archive, or its hash for signatures of the hash.
+    message: Vec<u8>,
+}
+
+impl SignedArchive {
+    /// Reads a `.minisig` file sent with `archive`.
+    pub fn new(text: String, archive: &[u8]) -> Result<Self, Error> {
+        let invalid = |reason: &str| {
+            format_err!("the package signature is invalid: {}", reason)
+                .status(Status::BadRequest)
+                .code("invalid_signature")
+        };
+
+        let mut lines = text.lines().map(|line| line.trim_end_matches('\r'));
+
+        let signature = match (lines.next(), lines.next()) {
+            (Some(comment), Some(signature)) if comment.starts_with(UNTRUSTED_COMMENT) => {
+                base64::decode(signature).map_err(|_| invalid("the signature isn't base64"))?
+            }
+            _ => return Err(invalid("it isn't a minisign signature file")),
+        };
+
+        let trusted_comment = lines
+            .next()
+            .and_then(|line| line.strip_prefix(TRUSTED_COMMENT))
+            .ok_or_else(|| invalid("the trusted comment is missing"))?
+            .to_owned();
+
+        let global_signature = lines
+            .next()
+            .and_then(|line| base64::decode(line).ok())
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| invalid("the trusted comment's signature is missing"))?;
+
+        if signature.len() != 74 {
+            return Err(invalid("the signature is the wrong length"));
+        }
+
+        let message = match &signature[..2] {
+            PREHASHED_ALGORITHM => Blake2b512::digest(archive).to_vec(),
+            LEGACY_ALGORITHM => archive.to_vec(),
+            _ => return Err(invalid("it isn't an Ed25519 signature")),
+        };
+
+        Ok(Self {
+            key_id: signature[2..10].try_into().unwrap(),
+            signature: signature[10..].try_into().unwrap(),
+            trusted_comment,
+            global_signature,
+            message,
+            text,
+        })
+    }
+
+    /// Checks that the archive was signed by one of `keys`, and that the
+    /// trusted comment wasn't changed since.
+    pub fn verify(&self, keys: &[PublicKey]) -> Result<(), Error> {
+        let key = keys
+            .iter()
+            .find(|key| key.key_id == self.key_id)
+            .ok_or_else(|| {
+                format_err!(
+                    "the package was signed with key {}, which isn't one of the scope's signing \
+                     keys",
+                    format_key_id(&self.key_id)
+                )
+                .status(Status::BadRequest)
+                .code("unknown_signing_key")
+            })?;
+
+        let mut global_message = self.signature.to_vec();
+        global_message.extend_from_slice(self.trusted_comment.as_bytes());
+
+        if !verify_ed25519(&key.key, &self.message, &self.signature)
+            || !verify_ed25519(&key.key, &global_message, &self.global_signature)
+        {
+            return Err(format_err!(
+                "the package signature doesn't match the archive. Sign the archive being \
+                 uploaded with key {}.",
+                key.key_id()
+            )
+            .status(Status::BadRequest)
+            .code("invalid_signature"));
+        }
+
+        Ok(())
+    }
+}
+
+/// The signature sent with a publish in the `Wally-Signature` header, if
+/// there is one.
+pub struct PublishSignature(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for PublishSignature {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        // A header that isn't base64 is passed along as it is, so that it's
+        // rejected as an invalid signature instead of being ignored.
+        let signature = request.headers().get_one("Wally-Signature").map(|header| {
+            match base64::decode(header.trim()) {
+                Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+                Err(_) => header.to_owned(),
+            }
+        });
+
+        Outcome::Success(PublishSignature(signature))
+    }
+}
+
+fn format_key_id(key_id: &[u8; 8]) -> String {
+    format!("{:016X}", u64::from_le_bytes(*key_id))
+}
+
+fn verify_ed25519(key: &[u8; 32], message: &[u8], signature: &[u8; 64]) -> bool {
+    match VerifyingKey::from_bytes(key) {
+        Ok(key) => key
+            .verify_strict(message, &Signature::from_bytes(signature))
+            .is_ok(),
+        Err(_) => false,
+    }
+}
@@ -0,0 +1,95 @@
+//! Counts how many times each package version has been downloaded, so that
+//! package authors can see how much their releases are used.
+//!
+//! Stores are behind a trait so that counts can be kept somewhere shared
+//! between instances later on. The only store for now keeps them in memory,
+//! so counts are lost when the registry restarts.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+
+use anyhow::format_err;
+use async_trait::async_trait;
+use libwally::{package_id::PackageId, package_name::PackageName};
+use semver::Version;
+
+use crate::audit::StoredAuditEvent;
+
+#[async_trait]
+pub trait StatsStore: Send + Sync + 'static {
+    /// Count one download of a package version.
+    async fn record_download(&self, package: &PackageId) -> anyhow::Result<()>;
+
+    /// The number of downloads of each version of a package. Versions that
+    /// have never been downloaded may be left out.
+    async fn package_downloads(&self, name: &PackageName)
+        -> anyhow::Result<BTreeMap<Version, u64>>;
+
+    /// The number of downloads of each package version in a scope that's been
+    /// downloaded at all.
+    async fn scope_downloads(&self, scope: &str) -> anyhow::Result<BTreeMap<PackageId, u64>>;
+}
+
+#[derive(Default)]
+pub struct MemoryStats {
+    downloads: Mutex<HashMap<PackageName, BTreeMap<Version, u64>>>,
+
+    /// Recent audit events, for showing scopes' activity, when they aren't
+    /// kept anywhere else. See `Persistence for MemoryStats`.
+    pub(crate) audit_events: Mutex<VecDeque<StoredAuditEvent>>,
+}
+
+impl MemoryStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl StatsStore for MemoryStats {
+    async fn record_download(&self, package: &PackageId) -> anyhow::Result<()> {
+        let mut downloads = self
+            .downloads
+            .lock()
+            .map_err(|_| format_err!("download stats are unavailable"))?;
+
+        *downloads
+            .entry(package.name().clone())
+            .or_default()
+            .entry(package.version().clone())
+            .or_default() += 1;
+
+        Ok(())
+    }
+
+    async fn package_downloads(
+        &self,
+        name: &PackageName,
+    ) -> anyhow::Result<BTreeMap<Version, u64>> {
+        let downloads = self
+            .downloads
+            .lock()
+            .map_err(|_| format_err!("download stats are unavailable"))?;
+
+        Ok(downloads.get(name).cloned().unwrap_or_default())
+    }
+
+    async fn scope_downloads(&self, scope: &str) -> anyhow::Result<BTreeMap<PackageId, u64>> {
+        let downloads = self
+            .downloads
+            .lock()
+            .map_err(|_| format_err!("download stats are unavailable"))?;
+
+        let downloads = downloads
+            .iter()
+            .filter(|(name, _)| name.scope() == scope)
+            .flat_map(|(name, versions)| {
+                versions.iter().map(move |(version, count)| {
+                    (PackageId::new(name.clone(), version.clone()), *count)
+                })
+            })
+            .collect();
+
+        Ok(downloads)
+    }
+}
@@ -1,5 +1,4 @@
 use std::convert::Infallible;
-use std::io::Cursor;
 
 use async_trait::async_trait;
 use cloud_storage_lite::client::{BucketClient, GcsBucketClient};
@@ -7,7 +6,7 @@ use futures::TryStreamExt;
 use libwally::package_id::PackageId;
 use moka::sync::Cache;
 
-use super::{StorageBackend, StorageOutput};
+use super::{integrity_name, StorageBackend, StoredPackage};
 
 pub struct GcsStorage {
     client: GcsBucketClient,
@@ -29,10 +28,10 @@ impl GcsStorage {
 
 #[async_trait]
 impl StorageBackend for GcsStorage {
-    async fn read(&self, key: &PackageId) -> anyhow::Result<StorageOutput> {
+    async fn read(&self, key: &PackageId) -> anyhow::Result<StoredPackage> {
         if let Some(cache) = &self.cache {
             if cache.contains_key(key) {
-                return Ok(Box::new(Cursor::new(cache.get(key).unwrap())));
+                return Ok(StoredPackage::from_buffer(cache.get(key).unwrap()));
             }
         }
 
@@ -44,7 +43,7 @@ impl StorageBackend for GcsStorage {
             cache.insert(key.clone(), data.clone());
         }
 
-        Ok(Box::new(Cursor::new(data)))
+        Ok(StoredPackage::from_buffer(data))
     }
 
     async fn write(&self, id: &PackageId, contents: &[u8]) -> anyhow::Result<()> {
@@ -59,4 +58,37 @@ impl StorageBackend for GcsStorage {
 
         Ok(())
     }
+
+    async fn read_integrity(&self, id: &PackageId) -> anyhow::Result<Vec<u8>> {
+        let stream = self.client.download_object(&integrity_name(id)).await?;
+        let data = stream.map_ok(|chunk| chunk.to_vec()).try_concat().await?;
+
+        Ok(data)
+    }
+
+    async fn write_integrity(&self, id: &PackageId, contents: &[u8]) -> anyhow::Result<()> {
+        let contents = contents.to_vec();
+        self.client
+            .create_object(
+                &integrity_name(id),
+                futures::stream::once(futures::future::ok::<_, Infallible>(contents)),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: &PackageId) -> anyhow::Result<()> {
+        if let Some(cache) = &self.cache {
+            cache.invalidate(id);
+        }
+
+        self.client.delete_object(&id.to_string()).await?;
+
+        // Versions published before integrity documents existed don't have
+        // one, so failing to delete it isn't a problem.
+        self.client.delete_object(&integrity_name(id)).await.ok();
+
+        Ok(())
+    }
 }
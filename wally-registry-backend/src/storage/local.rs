@@ -4,9 +4,10 @@ use anyhow::Context;
 use async_trait::async_trait;
 use libwally::package_id::PackageId;
 use tokio::fs::{create_dir_all, File, OpenOptions};
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom};
+use walkdir::WalkDir;
 
-use super::{StorageBackend, StorageOutput};
+use super::{ByteRange, StorageBackend, StoredPackage};
 
 pub struct LocalStorage {
     path: Option<PathBuf>,
@@ -20,40 +21,201 @@ impl LocalStorage {
 
 #[async_trait]
 impl StorageBackend for LocalStorage {
-    async fn read(&self, id: &PackageId) -> anyhow::Result<StorageOutput> {
+    /// Creates the packages directory if it doesn't exist yet, and checks
+    /// that it's a directory that can be written to.
+    async fn check(&self) -> anyhow::Result<()> {
+        let base_path = self
+            .path
+            .as_deref()
+            .unwrap_or_else(|| Path::new("packages"));
+
+        create_dir_all(base_path).await.with_context(|| {
+            format!(
+                "could not create packages directory {}",
+                base_path.display()
+            )
+        })?;
+
+        let metadata = tokio::fs::metadata(base_path)
+            .await
+            .with_context(|| format!("could not read metadata of {}", base_path.display()))?;
+
+        anyhow::ensure!(
+            metadata.is_dir(),
+            "packages directory {} is not a directory",
+            base_path.display()
+        );
+        anyhow::ensure!(
+            !metadata.permissions().readonly(),
+            "packages directory {} is read-only",
+            base_path.display()
+        );
+
+        Ok(())
+    }
+
+    async fn read(&self, id: &PackageId) -> anyhow::Result<StoredPackage> {
         let path = package_path(self.path.as_deref(), id)?;
         let file = File::open(&path)
             .await
             .with_context(|| format!("could not open path for reading {}", path.display()))?;
+        let metadata = file
+            .metadata()
+            .await
+            .with_context(|| format!("could not read metadata of {}", path.display()))?;
 
-        Ok(Box::new(file))
+        Ok(StoredPackage {
+            contents: Box::new(file),
+            size: Some(metadata.len()),
+        })
+    }
+
+    async fn read_range(&self, id: &PackageId, range: ByteRange) -> anyhow::Result<StoredPackage> {
+        let path = package_path(self.path.as_deref(), id)?;
+        let mut file = File::open(&path)
+            .await
+            .with_context(|| format!("could not open path for reading {}", path.display()))?;
+        file.seek(SeekFrom::Start(range.start))
+            .await
+            .with_context(|| format!("could not seek in {}", path.display()))?;
+
+        Ok(StoredPackage {
+            contents: Box::new(file.take(range.length())),
+            size: Some(range.length()),
+        })
+    }
+
+    async fn size(&self, id: &PackageId) -> anyhow::Result<u64> {
+        let path = package_path(self.path.as_deref(), id)?;
+        let metadata = tokio::fs::metadata(&path)
+            .await
+            .with_context(|| format!("could not read metadata of {}", path.display()))?;
+
+        Ok(metadata.len())
     }
 
     async fn write(&self, id: &PackageId, contents: &[u8]) -> anyhow::Result<()> {
         let path = package_path(self.path.as_deref(), id)?;
-        let directory = path.parent().unwrap();
+        write_new(&path, contents).await
+    }
+
+    async fn exists(&self, id: &PackageId) -> anyhow::Result<bool> {
+        let path = package_path(self.path.as_deref(), id)?;
 
-        create_dir_all(&directory)
+        match tokio::fs::metadata(&path).await {
+            Ok(_) => Ok(true),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(err) => {
+                Err(err).with_context(|| format!("could not check for package {}", path.display()))
+            }
+        }
+    }
+
+    async fn read_integrity(&self, id: &PackageId) -> anyhow::Result<Vec<u8>> {
+        let path = integrity_path(self.path.as_deref(), id)?;
+        let contents = tokio::fs::read(&path)
             .await
-            .with_context(|| format!("could not create directory {}", directory.display()))?;
+            .with_context(|| format!("could not read integrity document {}", path.display()))?;
+
+        Ok(contents)
+    }
+
+    async fn write_integrity(&self, id: &PackageId, contents: &[u8]) -> anyhow::Result<()> {
+        let path = integrity_path(self.path.as_deref(), id)?;
+        write_new(&path, contents).await
+    }
+
+    async fn list(&self) -> anyhow::Result<Option<Vec<PackageId>>> {
+        let base_path = self
+            .path
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("packages"));
 
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create_new(true)
-            .open(&path)
+        let ids = tokio::task::spawn_blocking(move || list_packages(&base_path)).await??;
+        Ok(Some(ids))
+    }
+
+    async fn delete(&self, id: &PackageId) -> anyhow::Result<()> {
+        let path = package_path(self.path.as_deref(), id)?;
+        tokio::fs::remove_file(&path)
             .await
-            .with_context(|| format!("could not open path for writing {}", path.display()))?;
+            .with_context(|| format!("could not delete {}", path.display()))?;
 
-        file.write_all(contents).await?;
-        Ok(())
+        let path = integrity_path(self.path.as_deref(), id)?;
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err).with_context(|| format!("could not delete {}", path.display())),
+        }
     }
 }
 
+/// Finds the archives laid out the way `package_path` puts them, as
+/// `scope/name/version.zip`.
+fn list_packages(base_path: &Path) -> anyhow::Result<Vec<PackageId>> {
+    if !base_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut ids = Vec::new();
+
+    for entry in WalkDir::new(base_path).min_depth(3).max_depth(3) {
+        let entry =
+            entry.with_context(|| format!("could not list packages in {}", base_path.display()))?;
+        let path = entry.path();
+
+        if !entry.file_type().is_file() || path.extension() != Some("zip".as_ref()) {
+            continue;
+        }
+
+        let relative = path.strip_prefix(base_path)?;
+        let parts: Vec<_> = relative.iter().map(|part| part.to_string_lossy()).collect();
+        let version = parts[2].trim_end_matches(".zip");
+
+        // Anything else in the directory isn't a package to report.
+        if let Ok(id) = format!("{}/{}@{}", parts[0], parts[1], version).parse() {
+            ids.push(id);
+        }
+    }
+
+    Ok(ids)
+}
+
+async fn write_new(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    let directory = path.parent().unwrap();
+
+    create_dir_all(&directory)
+        .await
+        .with_context(|| format!("could not create directory {}", directory.display()))?;
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .await
+        .with_context(|| format!("could not open path for writing {}", path.display()))?;
+
+    file.write_all(contents).await?;
+    Ok(())
+}
+
 fn package_path(package_directory: Option<&Path>, id: &PackageId) -> anyhow::Result<PathBuf> {
+    version_path(package_directory, id, "zip")
+}
+
+fn integrity_path(package_directory: Option<&Path>, id: &PackageId) -> anyhow::Result<PathBuf> {
+    version_path(package_directory, id, "integrity.json")
+}
+
+fn version_path(
+    package_directory: Option<&Path>,
+    id: &PackageId,
+    extension: &str,
+) -> anyhow::Result<PathBuf> {
     let base_path = package_directory.unwrap_or_else(|| Path::new("packages"));
     let mut path = base_path.join(id.name().scope());
     path.push(id.name().name());
-    path.push(format!("{}.zip", id.version()));
+    path.push(format!("{}.{}", id.version(), extension));
 
     anyhow::ensure!(
         path.starts_with(base_path),
@@ -5,17 +5,19 @@ mod local;
 mod s3;
 
 use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use anyhow::Context;
 use async_trait::async_trait;
 use libwally::package_id::PackageId;
 use serde::{Deserialize, Serialize};
-use tokio::io::AsyncRead;
+use tokio::io::{AsyncRead, AsyncReadExt};
 
 pub use gcs::GcsStorage;
 pub use local::LocalStorage;
 
 #[cfg(feature = "s3-storage")]
-pub use s3::S3Storage;
+pub use s3::{s3_region, S3Storage};
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(tag = "type", rename_all = "kebab-case")]
@@ -30,18 +32,206 @@ pub enum StorageMode {
         cache_size: Option<u64>,
     },
     #[cfg(feature = "s3-storage")]
-    #[cfg(feature = "s3-storage")]
     #[serde(rename_all = "kebab-case")]
     S3 {
         bucket: String,
         cache_size: Option<u64>,
+
+        /// The AWS region the bucket is in, like `us-east-1`. Defaults to the
+        /// `AWS_REGION_NAME` environment variable, then `us-east-1`.
+        region: Option<String>,
+
+        /// The URL of an S3-compatible service to use instead of AWS, like a
+        /// MinIO server. Defaults to the `AWS_REGION_ENDPOINT` environment
+        /// variable.
+        endpoint: Option<String>,
+
+        /// Credentials to use instead of the usual AWS credential chain of
+        /// environment variables, profile files, and instance metadata.
+        credentials: Option<S3Credentials>,
     },
 }
 
+#[cfg(feature = "s3-storage")]
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct S3Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+}
+
+// The storage config is printed on startup, which shouldn't leak the secret.
+#[cfg(feature = "s3-storage")]
+impl std::fmt::Debug for S3Credentials {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter
+            .debug_struct("S3Credentials")
+            .field("access_key_id", &self.access_key_id)
+            .field("secret_access_key", &"<redacted>")
+            .finish()
+    }
+}
+
 pub type StorageOutput = Box<dyn AsyncRead + Unpin + Send + Sync + 'static>;
 
+/// A package archive being read from storage. Backends stream the archive
+/// where they can, so that large packages aren't held in memory.
+pub struct StoredPackage {
+    pub contents: StorageOutput,
+
+    /// The size of the archive in bytes, if the backend knows it up front.
+    pub size: Option<u64>,
+}
+
+impl StoredPackage {
+    fn from_buffer(data: Vec<u8>) -> Self {
+        Self {
+            size: Some(data.len() as u64),
+            contents: Box::new(std::io::Cursor::new(data)),
+        }
+    }
+}
+
+/// The bytes of a package archive from `start` up to, but not including,
+/// `end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn length(&self) -> u64 {
+        self.end - self.start
+    }
+}
+
 #[async_trait]
 pub trait StorageBackend: Send + Sync + 'static {
-    async fn read(&self, id: &PackageId) -> anyhow::Result<StorageOutput>;
+    /// Check that the backend is set up to be used, before the startup
+    /// self-test writes to it. Backends with something to check without
+    /// writing, like a directory, should override this.
+    async fn check(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn read(&self, id: &PackageId) -> anyhow::Result<StoredPackage>;
+
+    /// Read part of a package archive, for resuming a download. Backends that
+    /// can read part of a file without reading what comes before it should
+    /// override this.
+    async fn read_range(&self, id: &PackageId, range: ByteRange) -> anyhow::Result<StoredPackage> {
+        let mut contents = self.read(id).await?.contents;
+        tokio::io::copy(
+            &mut (&mut contents).take(range.start),
+            &mut tokio::io::sink(),
+        )
+        .await?;
+
+        Ok(StoredPackage {
+            contents: Box::new(contents.take(range.length())),
+            size: Some(range.length()),
+        })
+    }
+
+    /// The size of a package archive in bytes. Backends that can find it
+    /// without reading the archive should override this.
+    async fn size(&self, id: &PackageId) -> anyhow::Result<u64> {
+        let package = self.read(id).await?;
+
+        match package.size {
+            Some(size) => Ok(size),
+            None => {
+                let mut contents = package.contents;
+                Ok(tokio::io::copy(&mut contents, &mut tokio::io::sink()).await?)
+            }
+        }
+    }
     async fn write(&self, id: &PackageId, contents: &[u8]) -> anyhow::Result<()>;
+
+    /// Check whether a package version has been stored, so that a failed read
+    /// can be told apart from a missing package. Backends that can check this
+    /// without downloading the package should override it.
+    async fn exists(&self, id: &PackageId) -> anyhow::Result<bool> {
+        Ok(self.read(id).await.is_ok())
+    }
+
+    /// Read the integrity document stored for a package version.
+    async fn read_integrity(&self, id: &PackageId) -> anyhow::Result<Vec<u8>>;
+
+    /// Store the integrity document for a package version.
+    async fn write_integrity(&self, id: &PackageId, contents: &[u8]) -> anyhow::Result<()>;
+
+    /// Delete a package version's archive and its integrity document, if it
+    /// has one. Used when a version is unpublished.
+    async fn delete(&self, id: &PackageId) -> anyhow::Result<()>;
+
+    /// Every package version that has an archive in storage, for finding
+    /// archives the index doesn't list. Backends that can't list what they
+    /// hold return `None`.
+    async fn list(&self) -> anyhow::Result<Option<Vec<PackageId>>> {
+        Ok(None)
+    }
+
+    /// A URL that a package archive can be downloaded from directly for the
+    /// next `ttl`, without going through the registry. Backends that can't
+    /// make one return `None`, and the archive is streamed instead.
+    async fn presigned_url(
+        &self,
+        _id: &PackageId,
+        _ttl: Duration,
+    ) -> anyhow::Result<Option<String>> {
+        Ok(None)
+    }
+}
+
+/// The contents of the probe object written by `self_test`.
+const PROBE_CONTENTS: &[u8] = b"wally storage self-test";
+
+/// Writes a tiny probe object to `backend`, reads it back, and deletes it,
+/// failing with the step that went wrong. The probe is a prerelease named
+/// after the current time, so it won't clash with a real package.
+pub async fn self_test(backend: &dyn StorageBackend) -> anyhow::Result<()> {
+    backend.check().await?;
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let id: PackageId = format!("wally/storage-self-test@0.0.0-probe.{}", timestamp).parse()?;
+
+    backend
+        .write(&id, PROBE_CONTENTS)
+        .await
+        .with_context(|| format!("could not write probe object {}", id))?;
+
+    let mut contents = Vec::new();
+    let read = async {
+        let mut package = backend.read(&id).await?;
+        package.contents.read_to_end(&mut contents).await?;
+        Ok::<_, anyhow::Error>(())
+    };
+    let read = read
+        .await
+        .with_context(|| format!("could not read back probe object {}", id));
+
+    // Clean up even if the probe couldn't be read, so it isn't left behind.
+    let delete = backend
+        .delete(&id)
+        .await
+        .with_context(|| format!("could not delete probe object {}", id));
+
+    read?;
+    anyhow::ensure!(
+        contents == PROBE_CONTENTS,
+        "probe object {} read back with different contents than were written",
+        id
+    );
+    delete
+}
+
+/// The name integrity documents are stored under, next to the package
+/// archive itself.
+fn integrity_name(id: &PackageId) -> String {
+    format!("{}.integrity.json", id)
 }
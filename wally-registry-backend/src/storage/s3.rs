@@ -1,18 +1,32 @@
-use std::io::Cursor;
+use std::time::Duration;
 
+use anyhow::Context;
 use async_trait::async_trait;
 use futures::TryStreamExt;
 use libwally::package_id::PackageId;
 use moka::sync::Cache;
 
-use rusoto_s3::{GetObjectRequest, PutObjectRequest, S3Client, S3};
+use rusoto_core::credential::ProvideAwsCredentials;
+use rusoto_core::{Region, RusotoError};
+use rusoto_s3::util::{PreSignedRequest, PreSignedRequestOption};
+use rusoto_s3::{
+    DeleteObjectRequest, GetObjectRequest, HeadObjectError, HeadObjectRequest,
+    ListObjectsV2Request, PutObjectRequest, S3Client, S3,
+};
 
-use super::{StorageBackend, StorageOutput};
+use super::{integrity_name, ByteRange, StorageBackend, StoredPackage};
 
 pub struct S3Storage {
     client: S3Client,
     bucket: String,
     cache: Option<Cache<PackageId, Vec<u8>>>,
+    presigning: Option<Presigning>,
+}
+
+/// What's needed to pre-sign URLs, which the client doesn't give access to.
+struct Presigning {
+    region: Region,
+    credentials: Box<dyn ProvideAwsCredentials + Send + Sync>,
 }
 
 impl S3Storage {
@@ -25,16 +39,30 @@ impl S3Storage {
             client,
             bucket,
             cache: cache_size.map(Cache::new),
+            presigning: None,
         }
     }
+
+    /// Lets the storage hand out pre-signed URLs for downloading packages
+    /// straight from the bucket.
+    pub fn with_presigning<P>(mut self, region: Region, credentials: P) -> Self
+    where
+        P: ProvideAwsCredentials + Send + Sync + 'static,
+    {
+        self.presigning = Some(Presigning {
+            region,
+            credentials: Box::new(credentials),
+        });
+        self
+    }
 }
 
 #[async_trait]
 impl StorageBackend for S3Storage {
-    async fn read(&self, key: &PackageId) -> anyhow::Result<StorageOutput> {
+    async fn read(&self, key: &PackageId) -> anyhow::Result<StoredPackage> {
         if let Some(cache) = &self.cache {
             if cache.contains_key(key) {
-                return Ok(Box::new(Cursor::new(cache.get(key).unwrap())));
+                return Ok(StoredPackage::from_buffer(cache.get(key).unwrap()));
             }
         }
 
@@ -50,13 +78,68 @@ impl StorageBackend for S3Storage {
             .await?;
 
         let stream = result.body.unwrap();
-        let data = stream.map_ok(|chunk| chunk.to_vec()).try_concat().await?;
 
+        // The cache needs the whole package, but otherwise it can be streamed
+        // straight from S3 to the client.
         if let Some(cache) = &self.cache {
+            let data = stream.map_ok(|chunk| chunk.to_vec()).try_concat().await?;
             cache.insert(key.clone(), data.clone());
+
+            return Ok(StoredPackage::from_buffer(data));
         }
 
-        Ok(Box::new(Cursor::new(data)))
+        Ok(StoredPackage {
+            contents: Box::new(stream.into_async_read()),
+            size: result.content_length.map(|length| length as u64),
+        })
+    }
+
+    async fn read_range(&self, key: &PackageId, range: ByteRange) -> anyhow::Result<StoredPackage> {
+        if let Some(data) = self.cache.as_ref().and_then(|cache| cache.get(key)) {
+            let data = data
+                .get(range.start as usize..range.end as usize)
+                .context("range is outside of the package")?;
+            return Ok(StoredPackage::from_buffer(data.to_vec()));
+        }
+
+        // HTTP ranges include their last byte.
+        let result = self
+            .client
+            .get_object(GetObjectRequest {
+                bucket: self.bucket.to_owned(),
+                key: key.to_string(),
+                range: Some(format!("bytes={}-{}", range.start, range.end - 1)),
+                ..Default::default()
+            })
+            .await?;
+
+        let stream = result.body.context("S3 sent no body")?;
+
+        Ok(StoredPackage {
+            contents: Box::new(stream.into_async_read()),
+            size: Some(range.length()),
+        })
+    }
+
+    async fn size(&self, key: &PackageId) -> anyhow::Result<u64> {
+        if let Some(data) = self.cache.as_ref().and_then(|cache| cache.get(key)) {
+            return Ok(data.len() as u64);
+        }
+
+        let result = self
+            .client
+            .head_object(HeadObjectRequest {
+                bucket: self.bucket.to_owned(),
+                key: key.to_string(),
+                ..Default::default()
+            })
+            .await?;
+
+        let length = result
+            .content_length
+            .context("S3 didn't say how big the package is")?;
+
+        Ok(length as u64)
     }
 
     async fn write(&self, id: &PackageId, contents: &[u8]) -> anyhow::Result<()> {
@@ -74,4 +157,146 @@ impl StorageBackend for S3Storage {
 
         Ok(())
     }
+
+    async fn exists(&self, id: &PackageId) -> anyhow::Result<bool> {
+        let result = self
+            .client
+            .head_object(HeadObjectRequest {
+                bucket: self.bucket.to_owned(),
+                key: id.to_string(),
+                ..Default::default()
+            })
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(RusotoError::Service(HeadObjectError::NoSuchKey(_))) => Ok(false),
+            // HEAD responses have no body, so a missing object usually comes
+            // back as a bare 404 rather than a NoSuchKey error.
+            Err(RusotoError::Unknown(response)) if response.status.as_u16() == 404 => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn read_integrity(&self, id: &PackageId) -> anyhow::Result<Vec<u8>> {
+        let result = self
+            .client
+            .get_object(GetObjectRequest {
+                bucket: self.bucket.to_owned(),
+                key: integrity_name(id),
+                ..Default::default()
+            })
+            .await?;
+
+        let stream = result.body.unwrap();
+        let data = stream.map_ok(|chunk| chunk.to_vec()).try_concat().await?;
+
+        Ok(data)
+    }
+
+    async fn write_integrity(&self, id: &PackageId, contents: &[u8]) -> anyhow::Result<()> {
+        let contents = contents.to_vec();
+
+        self.client
+            .put_object(PutObjectRequest {
+                bucket: self.bucket.to_owned(),
+                key: integrity_name(id),
+                body: Some(contents.into()),
+                ..Default::default()
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    async fn list(&self) -> anyhow::Result<Option<Vec<PackageId>>> {
+        let mut ids = Vec::new();
+        let mut continuation_token = None;
+
+        loop {
+            let output = self
+                .client
+                .list_objects_v2(ListObjectsV2Request {
+                    bucket: self.bucket.to_owned(),
+                    continuation_token,
+                    ..Default::default()
+                })
+                .await?;
+
+            // Archives are stored under the package ID itself, so anything
+            // that doesn't parse as one, like an integrity document, is
+            // something else.
+            for object in output.contents.unwrap_or_default() {
+                if let Some(id) = object.key.and_then(|key| key.parse().ok()) {
+                    ids.push(id);
+                }
+            }
+
+            continuation_token = output.next_continuation_token;
+
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(Some(ids))
+    }
+
+    async fn presigned_url(&self, id: &PackageId, ttl: Duration) -> anyhow::Result<Option<String>> {
+        let presigning = match &self.presigning {
+            Some(presigning) => presigning,
+            None => return Ok(None),
+        };
+
+        let credentials = presigning
+            .credentials
+            .credentials()
+            .await
+            .context("could not load AWS credentials to pre-sign with")?;
+
+        let request = GetObjectRequest {
+            bucket: self.bucket.to_owned(),
+            key: id.to_string(),
+            ..Default::default()
+        };
+
+        Ok(Some(request.get_presigned_url(
+            &presigning.region,
+            &credentials,
+            &PreSignedRequestOption { expires_in: ttl },
+        )))
+    }
+
+    async fn delete(&self, id: &PackageId) -> anyhow::Result<()> {
+        if let Some(cache) = &self.cache {
+            cache.invalidate(id);
+        }
+
+        // S3 treats deleting a missing object as a success, so this is fine
+        // for versions without an integrity document too.
+        for key in [id.to_string(), integrity_name(id)] {
+            self.client
+                .delete_object(DeleteObjectRequest {
+                    bucket: self.bucket.to_owned(),
+                    key,
+                    ..Default::default()
+                })
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Pick the region to connect to. An endpoint means an S3-compatible service
+/// other than AWS, which rusoto treats as a custom region.
+pub fn s3_region(region: Option<String>, endpoint: Option<String>) -> anyhow::Result<Region> {
+    let name = region.unwrap_or_else(|| "us-east-1".to_owned());
+
+    match endpoint {
+        Some(endpoint) => Ok(Region::Custom { name, endpoint }),
+        None => name
+            .parse()
+            .map_err(|_| anyhow::format_err!("unknown AWS region {}", name)),
+    }
 }
@@ -0,0 +1,75 @@
+use anyhow::{bail, format_err};
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::github_client::{GithubClient, TimedClient};
+
+/// Answers whether a GitHub user is in a team, for scopes that are owned by
+/// GitHub teams instead of, or as well as, individual users.
+#[async_trait]
+pub trait TeamMembership: Send + Sync {
+    /// Whether `login` is an active member of `team`, given as
+    /// `org/team-slug`.
+    async fn is_member(&self, team: &str, login: &str) -> anyhow::Result<bool>;
+}
+
+#[derive(Deserialize)]
+struct GithubTeamMembership {
+    state: String,
+}
+
+/// Checks team membership with GitHub's teams API, using the registry's own
+/// GitHub token, which needs the `read:org` scope.
+pub struct GithubTeams<'a> {
+    client: TimedClient,
+    api_base: String,
+    token: Option<&'a str>,
+}
+
+impl<'a> GithubTeams<'a> {
+    pub fn new(config: &'a Config, github: &GithubClient) -> Self {
+        Self {
+            client: github.permission(),
+            api_base: config.github_api_base(),
+            token: config.github_token.as_deref(),
+        }
+    }
+}
+
+#[async_trait]
+impl TeamMembership for GithubTeams<'_> {
+    async fn is_member(&self, team: &str, login: &str) -> anyhow::Result<bool> {
+        let (org, team_slug) = team
+            .split_once('/')
+            .ok_or_else(|| format_err!("team {} should be written as org/team-slug", team))?;
+
+        let token = match self.token {
+            Some(token) => token,
+            None => bail!("the registry needs a GitHub token to check team membership"),
+        };
+
+        let response = self
+            .client
+            .get(format!(
+                "{}/orgs/{}/teams/{}/memberships/{}",
+                self.api_base, org, team_slug, login
+            ))
+            .header("accept", "application/json")
+            .bearer_auth(token)
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::OK => {
+                let membership = response.json::<GithubTeamMembership>().await?;
+
+                // Invitations that haven't been accepted yet are "pending".
+                Ok(membership.state == "active")
+            }
+            StatusCode::NOT_FOUND => Ok(false),
+            status => bail!("GitHub team membership check failed because: {}", status),
+        }
+    }
+}
@@ -1,13 +1,45 @@
 use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use figment::{providers::Serialized, Figment};
-use libwally::test_package::PackageBuilder;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use libwally::{
+    manifest::Realm,
+    package_contents::PackageContents,
+    package_index::{OwnerId, PackageIndex},
+    package_integrity::PackageIntegrity,
+    test_package::PackageBuilder,
+};
 use rocket::{
     http::{Accept, ContentType, Header, Status},
-    local::blocking::{Client, LocalResponse},
+    local::blocking::{Client, LocalRequest, LocalResponse},
 };
+use sha2::{Digest, Sha256};
 
-use crate::{auth::AuthMode, config::Config, server, storage::StorageMode};
+use crate::{
+    audit::{AuditEvent, AuditLog, AuditOutcome, AuditSink},
+    auth::{ApiKeys, AuthMode, GithubInfo, WriteAccess, WritePermission},
+    auth_throttle::AuthThrottle,
+    config::{
+        AuthThrottleConfig, Config, FederatedIndexConfig, GithubProvider, GithubRetries,
+        MetadataCacheConfig, RateLimit, RateLimits, RequestTimeouts, TrustedPublisher,
+    },
+    format::Format,
+    github_app::{AppClaims, GithubApp, GithubAppConfig, InstallationToken},
+    indexes::ANY_SCOPE,
+    metadata_cache::{CacheKey, CachedResponse, MetadataCache},
+    persistence::PersistenceMode,
+    rate_limit::{AccessKind, Identity, RateLimiter},
+    read_tokens::{ReadTokenClaims, ReadTokenConfig},
+    retry::GithubRetry,
+    server,
+    storage::StorageMode,
+    teams::TeamMembership,
+    timeout::with_timeouts,
+    token_cache::{CachedToken, CachedUser, Clock, TokenCache},
+    webhook::{sign, PublishEvent, WebhookConfig, Webhooks, SIGNATURE_HEADER},
+};
 
 fn init_test_index_remote() -> anyhow::Result<url::Url> {
     let temp_dir = tempfile::tempdir()?;
@@ -49,18 +81,92 @@ fn new_client(auth: AuthMode) -> Client {
 }
 
 fn new_client_with_remote(auth: AuthMode, index_url: url::Url) -> Client {
+    new_client_with_config(test_config(auth, index_url))
+}
+
+fn test_config(auth: AuthMode, index_url: url::Url) -> Config {
     let package_path = tempfile::tempdir().unwrap().into_path();
     add_test_packages(&package_path).unwrap();
 
-    let figment = Figment::from(rocket::Config::default()).merge(Serialized::globals(Config {
+    Config {
         index_url,
+        index_scopes: vec![ANY_SCOPE.to_owned()],
+        federated_indexes: Vec::new(),
         storage: StorageMode::Local {
             path: Some(package_path),
         },
+        presigned_url_ttl: None,
+        storage_self_test: true,
         auth,
+        read_auth: None,
+        write_auth: None,
         github_token: None,
+        github_app: None,
+        github_api_url: None,
+        github_providers: Vec::new(),
+        trusted_publishers: Vec::new(),
         minimum_wally_version: None,
-    }));
+        minimum_wally_version_reads: true,
+        unknown_wally_version: Default::default(),
+        allowed_email_domains: None,
+        minimum_index_permission: Default::default(),
+        read_index_permission: None,
+        write_index_permission: None,
+        min_tls_version: Default::default(),
+        github_timeouts: Default::default(),
+        github_retries: Default::default(),
+        bootstrap: Default::default(),
+        auth_cache_ttl: 60,
+        rate_limits: Default::default(),
+        auth_throttle: Default::default(),
+        audit_log: None,
+        persistence: Default::default(),
+        metrics_address: None,
+        index_lock: None,
+        index_refresh_interval: None,
+        request_timeouts: Default::default(),
+        metadata_cache: Default::default(),
+        unpublish_window: 3600,
+        republish_window: 0,
+        max_package_size: 50 * 1024 * 1024,
+        max_versions_per_package: None,
+        prune_prereleases: false,
+        check_dependencies: true,
+        check_dependency_realms: true,
+        reject_confusable_names: true,
+        // Most tests publish bare packages, so these are only turned on by the
+        // tests for them.
+        require_description: false,
+        require_license: false,
+        require_signatures: false,
+        allowed_scopes: Vec::new(),
+        denied_scopes: Vec::new(),
+        max_metadata_batch: 100,
+        log: Default::default(),
+        maintenance: false,
+        admin_key: None,
+        blocklist_path: None,
+        cors_origins: Vec::new(),
+        compression: Default::default(),
+        webhooks: None,
+        read_tokens: None,
+        cursor_secret: None,
+    }
+}
+
+/// A client for a registry with the write key `hello` and the admin key
+/// `admin`.
+fn new_admin_client() -> Client {
+    let mut config = test_config(
+        AuthMode::ApiKey("hello".into()),
+        init_test_index_remote().unwrap(),
+    );
+    config.admin_key = Some(String::from("admin"));
+    new_client_with_config(config)
+}
+
+fn new_client_with_config(config: Config) -> Client {
+    let figment = Figment::from(rocket::Config::default()).merge(Serialized::globals(config));
 
     Client::tracked(server(figment)).expect("valid rocket instance")
 }
@@ -118,6 +224,45 @@ fn root() {
     .assert(response);
 }
 
+#[test]
+fn root_describes_service() {
+    let client = new_client(AuthMode::Unauthenticated);
+    let response = client.get("/").dispatch();
+
+    assert!(response.headers().get_one("Cache-Control").is_some());
+
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+    assert!(body["docs"].is_string());
+}
+
+#[test]
+fn api_info_hides_secrets() {
+    let client = new_client_with_config(github_oauth_config());
+    let response = client.get("/v1/api-info").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let text = response.into_string().unwrap();
+    assert!(!text.contains("client-secret"), "{}", text);
+
+    let body: serde_json::Value = serde_json::from_str(&text).unwrap();
+    assert_eq!(body["api-version"], 1);
+    assert_eq!(body["auth"]["write"]["type"], "github-oauth");
+    assert_eq!(body["auth"]["write"]["client-id"], "client-id");
+    assert!(body["features"]
+        .as_array()
+        .unwrap()
+        .contains(&serde_json::json!("package-search")));
+
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    let body: serde_json::Value = client.get("/v1/api-info").dispatch().into_json().unwrap();
+    assert_eq!(
+        body["auth"]["read"],
+        serde_json::json!({ "type": "api-key" })
+    );
+    assert!(!body.to_string().contains("hello"));
+}
+
 #[test]
 fn read_minimal() {
     let client = new_client(AuthMode::Unauthenticated);
@@ -127,11 +272,107 @@ fn read_minimal() {
 
     Expectation {
         status: Status::Ok,
-        content_type: ContentType::GZIP,
+        content_type: ContentType::Binary,
+    }
+    .assert(response);
+}
+
+#[test]
+fn presigned_downloads_fall_back_to_streaming() {
+    let mut config = test_config(AuthMode::Unauthenticated, init_test_index_remote().unwrap());
+    config.presigned_url_ttl = Some(300);
+    let client = new_client_with_config(config);
+
+    // Local storage can't pre-sign URLs, so the package is sent as usual.
+    let response = client
+        .get("/v1/package-contents/biff/minimal/0.1.0")
+        .dispatch();
+
+    Expectation {
+        status: Status::Ok,
+        content_type: ContentType::Binary,
     }
     .assert(response);
 }
 
+#[rocket::async_test]
+async fn storage_self_test() {
+    use rocket::local::asynchronous::Client;
+
+    use crate::storage::{self_test, LocalStorage, StorageBackend};
+
+    let package_path = tempfile::tempdir().unwrap().into_path().join("packages");
+    let storage = LocalStorage::new(Some(package_path.clone()));
+    self_test(&storage).await.unwrap();
+
+    // The directory is created, and the probe doesn't leave an archive behind.
+    assert!(package_path.is_dir());
+    let listed = storage.list().await.unwrap().unwrap();
+    assert!(listed.is_empty());
+
+    // A registry whose storage can't be written to shouldn't launch.
+    let not_a_directory = tempfile::NamedTempFile::new().unwrap();
+    let mut config = test_config(AuthMode::Unauthenticated, init_test_index_remote().unwrap());
+    config.storage = StorageMode::Local {
+        path: Some(not_a_directory.path().to_owned()),
+    };
+    let figment = Figment::from(rocket::Config::default()).merge(Serialized::globals(&config));
+    assert!(Client::tracked(server(figment)).await.is_err());
+
+    config.storage_self_test = false;
+    let figment = Figment::from(rocket::Config::default()).merge(Serialized::globals(&config));
+    assert!(Client::tracked(server(figment)).await.is_ok());
+}
+
+#[test]
+fn read_large_package() {
+    use std::io::Read;
+
+    let config = test_config(AuthMode::Unauthenticated, init_test_index_remote().unwrap());
+    let package_path = match &config.storage {
+        StorageMode::Local { path } => path.clone().unwrap(),
+        _ => unreachable!(),
+    };
+
+    let size = 8 * 1024 * 1024;
+    let data: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+    fs_err::create_dir_all(package_path.join("biff/large")).unwrap();
+    fs_err::write(package_path.join("biff/large/1.0.0.zip"), &data).unwrap();
+    let expected_hash = blake3::hash(&data);
+    drop(data);
+
+    let client = new_client_with_config(config);
+    let mut response = client
+        .get("/v1/package-contents/biff/large/1.0.0")
+        .dispatch();
+
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::Binary));
+    assert_eq!(
+        response.headers().get_one("Content-Length"),
+        Some(size.to_string().as_str())
+    );
+
+    // Read the body a chunk at a time, the way a client saving it to disk
+    // would.
+    let mut hasher = blake3::Hasher::new();
+    let mut received = 0;
+    let mut chunk = [0; 64 * 1024];
+
+    loop {
+        let read = response.read(&mut chunk).unwrap();
+        if read == 0 {
+            break;
+        }
+
+        hasher.update(&chunk[..read]);
+        received += read;
+    }
+
+    assert_eq!(received, size);
+    assert_eq!(hasher.finalize(), expected_hash);
+}
+
 #[test]
 fn read_404() {
     let client = new_client(AuthMode::Unauthenticated);
@@ -146,6 +387,128 @@ fn read_404() {
     .assert(response);
 }
 
+#[test]
+fn package_integrity() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    publish_versions(&client, "biff/hello", &["1.0.0"]);
+
+    let contents = client
+        .get("/v1/package-contents/biff/hello/1.0.0")
+        .dispatch()
+        .into_bytes()
+        .unwrap();
+    let contents = PackageContents::from_buffer(contents);
+
+    let response = client
+        .get("/v1/package-integrity/biff/hello/1.0.0")
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let integrity: PackageIntegrity = response.into_json().unwrap();
+    integrity.verify_archive(&contents).unwrap();
+    assert!(integrity.files.contains_key("wally.toml"));
+}
+
+#[test]
+fn package_manifest() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    let contents = PackageBuilder::new("biff/hello@1.0.0")
+        .with_description("Says hello")
+        .contents();
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .body(contents.data())
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let manifest = |path: &'static str, accept: Option<&'static str>| {
+        let mut request = client
+            .get(path)
+            .header(Header::new("Authorization", "Bearer hello"));
+
+        if let Some(accept) = accept {
+            request = request.header(Header::new("Accept", accept));
+        }
+
+        request.dispatch()
+    };
+
+    let response = manifest("/v1/package/biff/hello/1.0.0/manifest", None);
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::JSON));
+    let parsed: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(parsed["package"]["name"], "biff/hello");
+    assert_eq!(parsed["package"]["description"], "Says hello");
+
+    // TOML comes straight from the archive, as it was published.
+    let response = manifest(
+        "/v1/package/biff/hello/1.0.0/manifest",
+        Some("text/toml, application/json;q=0.5"),
+    );
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        response.content_type(),
+        Some(ContentType::new("text", "toml"))
+    );
+    assert_eq!(response.headers().get_one("Vary"), Some("Accept"));
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(contents.data().to_vec())).unwrap();
+    assert_eq!(
+        response.into_bytes().unwrap(),
+        crate::read_manifest_file(&mut archive).unwrap()
+    );
+
+    for path in &[
+        "/v1/package/biff/hello/2.0.0/manifest",
+        "/v1/package/biff/missing/1.0.0/manifest",
+    ] {
+        let response = manifest(path, Some("text/toml"));
+        assert_eq!(response.status(), Status::NotFound, "{}", path);
+        let body: serde_json::Value = response.into_json().unwrap();
+        assert_eq!(body["code"], "package_not_found");
+    }
+}
+
+#[test]
+fn package_integrity_404() {
+    // Packages published before integrity documents existed don't have one.
+    let client = new_client(AuthMode::Unauthenticated);
+    let response = client
+        .get("/v1/package-integrity/biff/minimal/0.1.0")
+        .dispatch();
+
+    Expectation {
+        status: Status::NotFound,
+        content_type: ContentType::JSON,
+    }
+    .assert(response);
+}
+
+#[test]
+fn path_traversal_400() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+
+    let urls = [
+        "/v1/package-metadata/..%2F..%2Fetc/passwd",
+        "/v1/package-metadata/%2E%2E/minimal",
+        "/v1/package-metadata/biff/..%5Cminimal",
+        "/v1/package-contents/biff/minimal%00/0.1.0",
+        "/v1/package-contents/..%2Fbiff/minimal/0.1.0",
+        "/v1/scope/..%2F..%2Fetc/activity",
+        "/v1/scope/bi%00ff/activity",
+    ];
+
+    for url in &urls {
+        let response = client
+            .get(*url)
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch();
+
+        assert_eq!(response.status(), Status::BadRequest, "{}", url);
+    }
+}
+
 #[test]
 fn publish_unauthenticated_401() {
     let client = new_client(AuthMode::Unauthenticated);
@@ -158,11 +521,346 @@ fn publish_unauthenticated_401() {
     .assert(response);
 }
 
+#[test]
+fn max_versions_per_package() {
+    let publish = |client: &Client, package: &str| {
+        let response = client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .header(Header::new("Authorization", "Bearer hello"))
+            .body(PackageBuilder::new(package).contents().data())
+            .dispatch();
+        let status = response.status();
+        (status, response.into_json::<serde_json::Value>().unwrap())
+    };
+    let versions = |client: &Client| {
+        let response = client
+            .get("/v1/package-metadata/biff/hello")
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch();
+        let body: serde_json::Value = response.into_json().unwrap();
+        body["versions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|manifest| manifest["package"]["version"].as_str().unwrap().to_owned())
+            .collect::<Vec<_>>()
+    };
+
+    let client_with = |prune_prereleases: bool| {
+        let mut config = test_config(
+            AuthMode::ApiKey("hello".into()),
+            init_test_index_remote().unwrap(),
+        );
+        config.max_versions_per_package = Some(2);
+        config.prune_prereleases = prune_prereleases;
+        new_client_with_config(config)
+    };
+
+    let client = client_with(false);
+
+    assert_eq!(publish(&client, "biff/hello@1.0.0").0, Status::Ok);
+    assert_eq!(publish(&client, "biff/hello@1.1.0-rc.1").0, Status::Ok);
+    let (status, body) = publish(&client, "biff/hello@1.1.0");
+    assert_eq!(status, Status::Forbidden);
+    assert_eq!(body["code"], "too_many_versions");
+
+    let client = client_with(true);
+
+    assert_eq!(publish(&client, "biff/hello@1.0.0-rc.1").0, Status::Ok);
+    assert_eq!(publish(&client, "biff/hello@1.0.0-rc.2").0, Status::Ok);
+    let (status, body) = publish(&client, "biff/hello@1.0.0");
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["pruned"], serde_json::json!(["1.0.0-rc.1"]));
+    assert_eq!(versions(&client), vec!["1.0.0", "1.0.0-rc.2"]);
+
+    // Releases are never pruned, so once there's no prerelease left to
+    // delete, publishing is refused again.
+    assert_eq!(publish(&client, "biff/hello@1.0.1").0, Status::Ok);
+    let (status, body) = publish(&client, "biff/hello@1.0.2");
+    assert_eq!(status, Status::Forbidden);
+    assert_eq!(body["code"], "too_many_versions");
+    assert_eq!(versions(&client), vec!["1.0.1", "1.0.0"]);
+}
+
+#[test]
+fn publish_too_large_413() {
+    let index_url = init_test_index_remote().unwrap();
+    let mut config = test_config(AuthMode::ApiKey("hello".into()), index_url);
+    config.max_package_size = 1024;
+    let client = new_client_with_config(config);
+
+    let publish = |size: usize| {
+        client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .header(Header::new("Authorization", "Bearer hello"))
+            .body(vec![0; size])
+            .dispatch()
+    };
+
+    // A body right at the limit is read, and only then found not to be a ZIP.
+    assert_eq!(publish(1024).status(), Status::BadRequest);
+
+    let response = publish(1025);
+    assert_eq!(response.status(), Status::PayloadTooLarge);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["code"], "payload_too_large");
+    assert!(body["message"].as_str().unwrap().contains("limit"));
+}
+
+/// Marks the archive entry called `name` as a symlink, which `ZipWriter`
+/// can't write on its own, by setting its Unix mode in the central directory.
+fn mark_as_symlink(archive: &mut [u8], name: &str) {
+    const CENTRAL_HEADER: &[u8] = &[0x50, 0x4b, 0x01, 0x02];
+
+    let header = (0..archive.len() - 46)
+        .find(|&offset| {
+            archive[offset..].starts_with(CENTRAL_HEADER)
+                && archive[offset + 46..].starts_with(name.as_bytes())
+        })
+        .unwrap();
+
+    let mode: u32 = 0o120777 << 16;
+    archive[header + 38..header + 42].copy_from_slice(&mode.to_le_bytes());
+}
+
+#[test]
+fn publish_unsafe_paths_400() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+
+    let publish = |archive: Vec<u8>| {
+        let response = client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .header(Header::new("Authorization", "Bearer hello"))
+            .body(archive)
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        let body: serde_json::Value = response.into_json().unwrap();
+        assert_eq!(body["code"], "unsafe_archive_path");
+    };
+
+    for path in &[
+        "../../etc/passwd",
+        "/etc/passwd",
+        "src/../../init.lua",
+        "..\\init.lua",
+        "C:foo",
+    ] {
+        let contents = PackageBuilder::new("biff/hello@1.0.0")
+            .with_file(*path, "return nil")
+            .contents();
+        publish(contents.data().to_vec());
+    }
+
+    let mut archive = PackageBuilder::new("biff/hello@1.0.0")
+        .with_file("link", "/etc/passwd")
+        .contents()
+        .data()
+        .to_vec();
+    mark_as_symlink(&mut archive, "link");
+    publish(archive);
+
+    // Nothing rejected made it into the index.
+    let response = client
+        .get("/v1/package-metadata/biff/hello")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+fn publish_keeps_assets() {
+    use std::io::Read;
+
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+
+    let contents = PackageBuilder::new("biff/assets@1.0.0")
+        .with_file("src/init.lua", "return nil")
+        .with_file("assets/logo.png", "PNG")
+        .with_file("assets/data.json", "{}")
+        .with_file("LICENSE", "MIT")
+        .contents();
+
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .header(Header::new("Authorization", "Bearer hello"))
+        .body(contents.data().to_vec())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = client
+        .get("/v1/package-contents/biff/assets/1.0.0")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let mut archive =
+        zip::ZipArchive::new(std::io::Cursor::new(response.into_bytes().unwrap())).unwrap();
+    let mut data = String::new();
+    archive
+        .by_name("assets/data.json")
+        .unwrap()
+        .read_to_string(&mut data)
+        .unwrap();
+    assert_eq!(data, "{}");
+    assert!(archive.by_name("assets/logo.png").is_ok());
+}
+
+/// Archives a package whose manifest declares `version` exactly as given,
+/// which `PackageBuilder` can't do since it only takes valid versions.
+fn archive_with_version(version: &str) -> Vec<u8> {
+    use std::io::Write;
+    use zip::write::{FileOptions, ZipWriter};
+
+    let manifest = PackageBuilder::new("biff/hello@1.0.0").into_manifest();
+    let manifest = toml::to_string_pretty(&manifest)
+        .unwrap()
+        .replace(r#"version = "1.0.0""#, &format!("version = {:?}", version));
+
+    let mut archive = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    archive
+        .start_file("wally.toml", FileOptions::default())
+        .unwrap();
+    archive.write_all(manifest.as_bytes()).unwrap();
+    archive.finish().unwrap().into_inner()
+}
+
+#[test]
+fn publish_normalizes_versions() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+
+    let publish = |archive: Vec<u8>| {
+        client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .header(Header::new("Authorization", "Bearer hello"))
+            .body(archive)
+            .dispatch()
+    };
+
+    for version in &["1.0", "01.0.0"] {
+        let response = publish(archive_with_version(version));
+        assert_eq!(response.status(), Status::BadRequest, "{}", version);
+        let body: serde_json::Value = response.into_json().unwrap();
+        assert_eq!(body["code"], "invalid_version", "{}", version);
+    }
+
+    // Build metadata doesn't make it a different version, so it isn't stored.
+    let response = publish(archive_with_version("1.0.0+build"));
+    assert_eq!(response.status(), Status::Ok);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["prerelease"], false);
+
+    let response = client
+        .get("/v1/package-metadata/biff/hello")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["versions"][0]["package"]["version"], "1.0.0");
+
+    let response = publish(
+        PackageBuilder::new("biff/hello@1.1.0-rc.1")
+            .contents()
+            .data()
+            .to_vec(),
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["prerelease"], true);
+
+    // Ranges only match prereleases that they ask for.
+    let depends_on = |req: &str| {
+        publish(
+            PackageBuilder::new("biff/dependent@1.0.0")
+                .with_dep("Hello", req)
+                .contents()
+                .data()
+                .to_vec(),
+        )
+        .status()
+    };
+    assert_eq!(depends_on("biff/hello@1.0.1"), Status::BadRequest);
+    assert_eq!(depends_on("biff/hello@1.1.0-rc.1"), Status::Ok);
+}
+
+#[test]
+fn lint_manifests() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+
+    let lint = |body: Vec<u8>| {
+        let response = client
+            .post("/v1/lint")
+            .header(Header::new("Authorization", "Bearer hello"))
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let body: serde_json::Value = response.into_json().unwrap();
+        let rules: Vec<String> = body["diagnostics"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|diagnostic| diagnostic["rule"].as_str().unwrap().to_owned())
+            .collect();
+        rules
+    };
+
+    let contents = PackageBuilder::new("biff/hello@1.0.0")
+        .with_description("Says hello")
+        .with_dep("Minimal", "biff/minimal@*")
+        .contents();
+    assert_eq!(
+        lint(contents.data().to_vec()),
+        vec!["missing_license", "unbounded_version_range"]
+    );
+
+    let manifest = r#"
+        [package]
+        name = "biff/hello"
+        version = "1.0.0"
+        registry = "https://github.com/UpliftGames/wally-test-index"
+        realm = "shared"
+        license = "MIT"
+        private = true
+
+        [dependencies]
+        TestEZ = "roblox/testez@0.4.1"
+
+        [dev-dependencies]
+        TestEZ = "roblox/testez@0.4"
+    "#;
+    assert_eq!(
+        lint(manifest.as_bytes().to_vec()),
+        vec![
+            "missing_description",
+            "dev_dependency_shipped",
+            "private_package"
+        ]
+    );
+
+    // Nothing was published.
+    let response = client
+        .get("/v1/package-metadata/biff/hello")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    let response = client
+        .post("/v1/lint")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .body("not a manifest")
+        .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
 #[test]
 fn publish() {
     let contents = PackageBuilder::new("biff/hello@1.0.0").contents();
 
-    let client = new_client(AuthMode::ApiKey(String::from("hello")));
+    let client = new_client(AuthMode::ApiKey("hello".into()));
     let response = client
         .post("/v1/publish")
         .header(Accept::JSON)
@@ -177,11 +875,44 @@ fn publish() {
     .assert(response);
 }
 
+#[test]
+fn whoami() {
+    let client = new_client(AuthMode::DoubleApiKey {
+        read: Some("read".into()),
+        write: "write".into(),
+        read_scopes: None,
+    });
+
+    let whoami = |uri: &str, key: &str| {
+        client
+            .get(uri.to_owned())
+            .header(Header::new("Authorization", format!("Bearer {}", key)))
+            .dispatch()
+    };
+
+    let response = whoami("/v1/whoami", "read");
+    assert_eq!(response.status(), Status::Ok);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["type"], "api-key");
+    assert!(!body.to_string().contains("read"));
+
+    // The read key is only good for reads.
+    let response = whoami("/v1/whoami?write=true", "read");
+    assert_eq!(response.status(), Status::Forbidden);
+    let response = whoami("/v1/whoami?write=true", "write");
+    assert_eq!(response.status(), Status::Ok);
+
+    let client = new_client(AuthMode::Unauthenticated);
+    let body: serde_json::Value = client.get("/v1/whoami").dispatch().into_json().unwrap();
+    assert_eq!(body["type"], "anonymous");
+}
+
 #[test]
 fn read_write_double_key() {
     let client = new_client(AuthMode::DoubleApiKey {
         read: None,
-        write: String::from("A write key"),
+        write: "A write key".into(),
+        read_scopes: None,
     });
 
     // We can read with no API key
@@ -191,7 +922,7 @@ fn read_write_double_key() {
 
     Expectation {
         status: Status::Ok,
-        content_type: ContentType::GZIP,
+        content_type: ContentType::Binary,
     }
     .assert(response);
 
@@ -225,36 +956,208 @@ fn read_write_double_key() {
 }
 
 #[test]
-fn publish_duplicate() {
-    let contents = PackageBuilder::new("biff/hello@0.1.0").contents();
-    let client = new_client(AuthMode::ApiKey(String::from("hello")));
-    let send_request = || {
-        client
-            .post("/v1/publish")
-            .header(Accept::JSON)
-            .body(contents.data())
-            .header(Header::new("Authorization", "Bearer hello"))
-            .dispatch()
-    };
+fn gitlab_requires_token() {
+    let client = new_client(AuthMode::GitLab {
+        client_id: String::from("client-id"),
+        client_secret: String::from("client-secret"),
+        instance_url: "https://gitlab.example.com".parse().unwrap(),
+        private: true,
+    });
+
+    let response = client
+        .get("/v1/package-contents/biff/minimal/0.1.0")
+        .dispatch();
 
     Expectation {
-        status: Status::Ok,
+        status: Status::Unauthorized,
         content_type: ContentType::JSON,
     }
-    .assert(send_request());
+    .assert(response);
+
+    let contents = PackageBuilder::new("biff/hello@1.0.0").contents();
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .body(contents.data())
+        .dispatch();
 
     Expectation {
-        status: Status::Conflict,
+        status: Status::Unauthorized,
         content_type: ContentType::JSON,
     }
-    .assert(send_request());
+    .assert(response);
 }
 
 #[test]
-fn publish_updates_git_remote() {
-    let remote = init_test_index_remote().unwrap();
-    let repo = git2::Repository::open(remote.to_file_path().unwrap()).unwrap();
-    let client = new_client_with_remote(AuthMode::ApiKey(String::from("hello")), remote);
+fn gitlab_project_id() {
+    let project_id = |instance: &str, index: &str| {
+        crate::auth::gitlab_project_id(&instance.parse().unwrap(), &index.parse().unwrap())
+    };
+
+    assert_eq!(
+        project_id("https://gitlab.com", "https://gitlab.com/wally/index"),
+        Some(String::from("wally%2Findex"))
+    );
+    assert_eq!(
+        project_id(
+            "https://git.example.com/gitlab/",
+            "https://git.example.com/gitlab/games/registry/index.git"
+        ),
+        Some(String::from("games%2Fregistry%2Findex"))
+    );
+    assert_eq!(
+        project_id("https://gitlab.com", "https://github.com/wally/index"),
+        None
+    );
+    assert_eq!(
+        project_id("https://gitlab.com", "https://gitlab.com/index"),
+        None
+    );
+}
+
+#[test]
+fn rotate_api_keys() {
+    let client = new_client(AuthMode::DoubleApiKey {
+        read: Some(ApiKeys::Many(vec![
+            String::from("old read key"),
+            String::from("new read key"),
+        ])),
+        write: ApiKeys::Many(vec![
+            String::from("old write key"),
+            String::from("new write key"),
+        ]),
+        read_scopes: None,
+    });
+
+    for key in ["old read key", "new read key"] {
+        let response = client
+            .get("/v1/package-contents/biff/minimal/0.1.0")
+            .header(Header::new("Authorization", format!("Bearer {}", key)))
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok, "{}", key);
+    }
+
+    for (key, version) in [("old write key", "1.0.0"), ("new write key", "1.0.1")] {
+        let contents = PackageBuilder::new(format!("biff/hello@{}", version)).contents();
+        let response = client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .body(contents.data())
+            .header(Header::new("Authorization", format!("Bearer {}", key)))
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok, "{}", key);
+    }
+
+    // Read keys still can't write.
+    let contents = PackageBuilder::new("biff/hello@1.0.2").contents();
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .body(contents.data())
+        .header(Header::new("Authorization", "Bearer new read key"))
+        .dispatch();
+
+    assert_eq!(response.status(), Status::Forbidden);
+}
+
+#[test]
+fn api_keys_config() {
+    let keys: ApiKeys = serde_json::from_str(r#""a key""#).unwrap();
+    assert_eq!(keys.as_slice(), ["a key"]);
+
+    let keys: ApiKeys = serde_json::from_str(r#"["a key", "another key"]"#).unwrap();
+    assert_eq!(keys.as_slice(), ["a key", "another key"]);
+}
+
+#[test]
+fn separate_read_and_write_auth() {
+    let index_url = init_test_index_remote().unwrap();
+    let mut config = test_config(AuthMode::Unauthenticated, index_url);
+    config.write_auth = Some(AuthMode::ApiKey("hello".into()));
+    let client = new_client_with_config(config);
+
+    publish_versions(&client, "biff/hello", &["1.0.0"]);
+
+    // Reads still go by `auth`, so they don't need a key.
+    let response = client
+        .get("/v1/package-contents/biff/hello/1.0.0")
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let contents = PackageBuilder::new("biff/hello@1.0.1").contents();
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .body(contents.data())
+        .dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn concurrent_publishes_to_one_scope() {
+    use rocket::local::asynchronous::Client;
+
+    let index_url = init_test_index_remote().unwrap();
+    let config = test_config(AuthMode::ApiKey("hello".into()), index_url);
+    let figment = Figment::from(rocket::Config::default()).merge(Serialized::globals(config));
+    let client = Client::tracked(server(figment)).await.unwrap();
+
+    let publish = |id: &str| {
+        let contents = PackageBuilder::new(id).contents();
+        client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .header(Header::new("Authorization", "Bearer hello"))
+            .body(contents.data().to_vec())
+            .dispatch()
+    };
+
+    let (first, second) = futures::join!(publish("biff/hello@1.0.0"), publish("biff/hello@1.1.0"));
+    assert_eq!(first.status(), Status::Ok);
+    assert_eq!(second.status(), Status::Ok);
+
+    let response = client
+        .get("/v1/package-metadata/biff/hello")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch()
+        .await;
+    let metadata: serde_json::Value = response.into_json().await.unwrap();
+    assert_eq!(metadata["versions"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn publish_duplicate() {
+    let contents = PackageBuilder::new("biff/hello@0.1.0").contents();
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    let send_request = || {
+        client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .body(contents.data())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch()
+    };
+
+    Expectation {
+        status: Status::Ok,
+        content_type: ContentType::JSON,
+    }
+    .assert(send_request());
+
+    Expectation {
+        status: Status::Conflict,
+        content_type: ContentType::JSON,
+    }
+    .assert(send_request());
+}
+
+#[test]
+fn publish_updates_git_remote() {
+    let remote = init_test_index_remote().unwrap();
+    let repo = git2::Repository::open(remote.to_file_path().unwrap()).unwrap();
+    let client = new_client_with_remote(AuthMode::ApiKey("hello".into()), remote);
 
     let commit = repo.head().unwrap().peel_to_commit().unwrap();
     assert_eq!(commit.message().unwrap(), "Initial commit");
@@ -276,8 +1179,8 @@ fn publish_updates_git_remote() {
 #[test]
 fn publish_then_publish_elsewhere() {
     let remote = init_test_index_remote().unwrap();
-    let client1 = new_client_with_remote(AuthMode::ApiKey(String::from("hello")), remote.clone());
-    let client2 = new_client_with_remote(AuthMode::ApiKey(String::from("hello")), remote);
+    let client1 = new_client_with_remote(AuthMode::ApiKey("hello".into()), remote.clone());
+    let client2 = new_client_with_remote(AuthMode::ApiKey("hello".into()), remote);
 
     let contents = PackageBuilder::new("biff/hello@1.0.0").contents();
     let response = client1
@@ -308,7 +1211,5216 @@ fn publish_then_publish_elsewhere() {
     .assert(response);
 }
 
-// TODO: Implement yanking
+fn publish_versions(client: &Client, name: &str, versions: &[&str]) {
+    for version in versions {
+        let contents = PackageBuilder::new(format!("{}@{}", name, version)).contents();
+        let response = client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .body(contents.data())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch();
+
+        Expectation {
+            status: Status::Ok,
+            content_type: ContentType::JSON,
+        }
+        .assert(response);
+    }
+}
+
+#[test]
+fn minimum_wally_version() {
+    use crate::config::UnknownWallyVersion;
+
+    let new_client = |reads: bool, unknown: UnknownWallyVersion| {
+        let index_url = init_test_index_remote().unwrap();
+        let mut config = test_config(AuthMode::ApiKey("hello".into()), index_url);
+        config.minimum_wally_version = Some("0.3.0".parse().unwrap());
+        config.minimum_wally_version_reads = reads;
+        config.unknown_wally_version = unknown;
+        new_client_with_config(config)
+    };
+
+    let publish = |client: &Client, id: &str, version: Option<&str>| {
+        let contents = PackageBuilder::new(id).contents();
+        let mut request = client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .header(Header::new("Authorization", "Bearer hello"))
+            .body(contents.data());
+
+        if let Some(version) = version {
+            request = request.header(Header::new("Wally-Version", version.to_owned()));
+        }
+
+        let response = request.dispatch();
+        let status = response.status();
+        let body: serde_json::Value = response.into_json().unwrap();
+        (status, body["code"].as_str().map(str::to_owned))
+    };
+
+    let download = |client: &Client, version: &str| {
+        client
+            .get("/v1/package-contents/biff/minimal/0.1.0")
+            .header(Header::new("Authorization", "Bearer hello"))
+            .header(Header::new("Wally-Version", version.to_owned()))
+            .dispatch()
+            .status()
+    };
+
+    let client = new_client(true, UnknownWallyVersion::Deny);
+    assert_eq!(
+        publish(&client, "biff/hello@1.0.0", Some("0.2.9")),
+        (
+            Status::UpgradeRequired,
+            Some("wally_version_too_old".to_owned())
+        )
+    );
+    assert_eq!(
+        publish(&client, "biff/hello@1.0.0", None),
+        (
+            Status::UpgradeRequired,
+            Some("wally_version_required".to_owned())
+        )
+    );
+    assert_eq!(
+        publish(&client, "biff/hello@1.0.0", Some("0.3.0")).0,
+        Status::Ok
+    );
+    assert_eq!(download(&client, "0.2.9"), Status::UpgradeRequired);
+    assert_eq!(download(&client, "0.3.1"), Status::Ok);
+
+    // Reads can be left alone, and clients that don't say which version they
+    // are can be let through.
+    let client = new_client(false, UnknownWallyVersion::Allow);
+    assert_eq!(download(&client, "0.2.9"), Status::Ok);
+    assert_eq!(
+        publish(&client, "biff/hello@1.0.0", Some("0.2.9")).0,
+        Status::UpgradeRequired
+    );
+    assert_eq!(publish(&client, "biff/hello@1.0.0", None).0, Status::Ok);
+    assert_eq!(
+        publish(&client, "biff/hello@1.0.1", Some("not a version")).0,
+        Status::Ok
+    );
+}
+
+#[test]
+fn recent_versions() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    publish_versions(&client, "biff/hello", &["1.0.0", "1.1.0"]);
+    publish_versions(&client, "zoop/tools", &["0.1.0"]);
+
+    let recent = |query: &str| {
+        let response = client
+            .get(format!("/v1/recent{}", query))
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let body: serde_json::Value = response.into_json().unwrap();
+        body["versions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|version| {
+                assert_eq!(version["publisher"], "api-key");
+                assert!(version["published-at"].as_u64().unwrap() > 0);
+                version["package"].as_str().unwrap().to_owned()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut all = recent("");
+    all.sort();
+    assert_eq!(
+        all,
+        ["biff/hello@1.0.0", "biff/hello@1.1.0", "zoop/tools@0.1.0"]
+    );
+    assert_eq!(recent("?limit=2").len(), 2);
+    assert_eq!(
+        recent("?scope=biff"),
+        ["biff/hello@1.1.0", "biff/hello@1.0.0"]
+    );
+
+    let response = client
+        .get("/v1/recent?scope=zoop")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .header(Header::new("Accept", "application/atom+xml"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        response.content_type(),
+        Some(ContentType::new("application", "atom+xml"))
+    );
+    let feed = response.into_string().unwrap();
+    assert!(feed.contains("<title>zoop/tools 0.1.0</title>"));
+    assert!(feed.contains("<author><name>api-key</name></author>"));
+    assert!(!feed.contains("biff/hello"));
+
+    let response = client
+        .get("/v1/recent?scope=nobody")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+fn yank() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    publish_versions(&client, "biff/hello", &["1.0.0", "1.0.1"]);
+
+    let send_request = |endpoint: &str, version: &str| {
+        client
+            .post(format!("/v1/{}/biff/hello/{}", endpoint, version))
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch()
+    };
+
+    let response = send_request("package-yank", "1.0.1");
+    assert_eq!(response.status(), Status::Ok);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["yanked"], serde_json::json!(["1.0.1"]));
+
+    let download = |version: &str| {
+        client
+            .get(format!("/v1/package-contents/biff/hello/{}", version))
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch()
+    };
+
+    // Yanked versions can still be downloaded, so that lockfiles keep working.
+    let response = download("1.0.1");
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("Wally-Yanked"), Some("true"));
+
+    let response = download("1.0.0");
+    assert_eq!(response.headers().get_one("Wally-Yanked"), Some("false"));
+
+    let response = client
+        .get("/v1/package-metadata/biff/hello")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    let metadata: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(metadata["yanked"], serde_json::json!(["1.0.1"]));
+
+    Expectation {
+        status: Status::NotFound,
+        content_type: ContentType::JSON,
+    }
+    .assert(send_request("package-yank", "3.0.0"));
+
+    let response = send_request("package-unyank", "1.0.1");
+    assert_eq!(response.status(), Status::Ok);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["unyanked"], serde_json::json!(["1.0.1"]));
+
+    let response = download("1.0.1");
+    assert_eq!(response.headers().get_one("Wally-Yanked"), Some("false"));
+}
+
+#[test]
+fn deprecate() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    publish_versions(&client, "biff/hello", &["1.0.0", "1.0.1"]);
+
+    let deprecate = |path: &str, message: &str| {
+        client
+            .post(format!("/v1/package-deprecate/{}", path))
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", "Bearer hello"))
+            .body(serde_json::json!({ "message": message }).to_string())
+            .dispatch()
+    };
+
+    let deprecated = || {
+        let metadata: serde_json::Value = client
+            .get("/v1/package-metadata/biff/hello")
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch()
+            .into_json()
+            .unwrap();
+
+        metadata["deprecated"].clone()
+    };
+
+    let response = deprecate("biff/hello/1.0.0", "Use 1.0.1 instead");
+    assert_eq!(response.status(), Status::Ok);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["versions"], serde_json::json!(["1.0.0"]));
+    assert_eq!(
+        deprecated(),
+        serde_json::json!({ "1.0.0": "Use 1.0.1 instead" })
+    );
+
+    let response = deprecate("biff/hello", "Use biff/goodbye instead");
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        deprecated(),
+        serde_json::json!({
+            "1.0.0": "Use biff/goodbye instead",
+            "1.0.1": "Use biff/goodbye instead",
+        })
+    );
+
+    // An empty message clears the deprecation.
+    let response = deprecate("biff/hello", "");
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["versions"], serde_json::json!(["1.0.0", "1.0.1"]));
+    assert_eq!(deprecated(), serde_json::json!({}));
+
+    Expectation {
+        status: Status::NotFound,
+        content_type: ContentType::JSON,
+    }
+    .assert(deprecate("biff/hello/3.0.0", "Gone"));
+
+    let response = deprecate("biff/hello/1.0.0", &"x".repeat(1001));
+    assert_eq!(response.status(), Status::BadRequest);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["code"], "deprecation_message_too_long");
+}
+
+#[test]
+fn bulk_yank() {
+    let client = new_admin_client();
+    publish_versions(&client, "biff/hello", &["1.0.0", "1.0.1", "2.0.0"]);
+
+    let response = client
+        .post("/v1/package-yank/biff/hello")
+        .header(ContentType::JSON)
+        .header(Header::new("Authorization", "Bearer admin"))
+        .body(r#"{ "range": "^1.0.0" }"#)
+        .dispatch();
+
+    assert_eq!(response.status(), Status::Ok);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["yanked"], serde_json::json!(["1.0.0", "1.0.1"]));
+
+    let response = client
+        .get("/v1/package-metadata/biff/hello")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    let metadata: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(metadata["yanked"], serde_json::json!(["1.0.0", "1.0.1"]));
+}
+
+#[test]
+fn bulk_yank_every_version_requires_force() {
+    let client = new_admin_client();
+    publish_versions(&client, "biff/hello", &["1.0.0", "1.0.1"]);
+
+    let send_request = |body: &str| {
+        client
+            .post("/v1/package-yank/biff/hello")
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", "Bearer admin"))
+            .body(body.to_owned())
+            .dispatch()
+    };
+
+    Expectation {
+        status: Status::BadRequest,
+        content_type: ContentType::JSON,
+    }
+    .assert(send_request(r#"{ "range": "*" }"#));
+
+    Expectation {
+        status: Status::Ok,
+        content_type: ContentType::JSON,
+    }
+    .assert(send_request(r#"{ "range": "*", "force": true }"#));
+}
+
+#[test]
+fn bulk_yank_requires_admin() {
+    let client = new_admin_client();
+    publish_versions(&client, "biff/hello", &["1.0.0", "1.0.1"]);
+
+    let send_request = |key: &str| {
+        client
+            .post("/v1/package-yank/biff/hello")
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", format!("Bearer {}", key)))
+            .body(r#"{ "versions": ["1.0.0"] }"#)
+            .dispatch()
+    };
+
+    // Being able to write to the scope isn't enough.
+    assert_eq!(send_request("hello").status(), Status::Unauthorized);
+
+    let response = client
+        .put("/v1/admin/maintenance")
+        .header(ContentType::JSON)
+        .header(Header::new("Authorization", "Bearer admin"))
+        .body(r#"{ "enabled": true }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = send_request("admin");
+    assert_eq!(response.status(), Status::ServiceUnavailable);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["code"], "maintenance");
+}
+
+#[test]
+fn bulk_yank_unknown_version_404() {
+    let client = new_admin_client();
+    publish_versions(&client, "biff/hello", &["1.0.0", "1.0.1"]);
+
+    let response = client
+        .post("/v1/package-yank/biff/hello")
+        .header(ContentType::JSON)
+        .header(Header::new("Authorization", "Bearer admin"))
+        .body(r#"{ "versions": ["1.0.0", "3.0.0"] }"#)
+        .dispatch();
+
+    Expectation {
+        status: Status::NotFound,
+        content_type: ContentType::JSON,
+    }
+    .assert(response);
+}
+
+#[test]
+fn unyank() {
+    let client = new_admin_client();
+    publish_versions(&client, "biff/hello", &["1.0.0", "1.0.1"]);
+
+    let send_request = |endpoint: &str| {
+        client
+            .post(format!("/v1/{}/biff/hello", endpoint))
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", "Bearer admin"))
+            .body(r#"{ "versions": ["1.0.0"] }"#)
+            .dispatch()
+    };
+
+    assert_eq!(send_request("package-yank").status(), Status::Ok);
+
+    let response = send_request("package-unyank");
+    assert_eq!(response.status(), Status::Ok);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["unyanked"], serde_json::json!(["1.0.0"]));
+
+    let response = client
+        .get("/v1/package-metadata/biff/hello")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    let metadata: serde_json::Value = response.into_json().unwrap();
+    assert!(metadata.get("yanked").is_none());
+}
+
 #[test]
-#[ignore]
-fn yank() {}
+fn unpublish() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    publish_versions(&client, "biff/hello", &["1.0.0", "1.0.1"]);
+
+    let unpublish = |version: &str, key: &str| {
+        client
+            .delete(format!("/v1/package/biff/hello/{}", version))
+            .header(Header::new("Authorization", format!("Bearer {}", key)))
+            .dispatch()
+    };
+
+    assert_eq!(unpublish("1.0.1", "wrong").status(), Status::Unauthorized);
+    assert_eq!(unpublish("2.0.0", "hello").status(), Status::NotFound);
+    assert_eq!(unpublish("1.0.1", "hello").status(), Status::Ok);
+
+    let response = client
+        .get("/v1/package-contents/biff/hello/1.0.1")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    let response = client
+        .get("/v1/package-metadata/biff/hello")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    let metadata: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(metadata["versions"].as_array().unwrap().len(), 1);
+
+    // The version number is free again.
+    publish_versions(&client, "biff/hello", &["1.0.1"]);
+}
+
+#[test]
+fn failed_unpublish_keeps_version_downloadable() {
+    let index_url = init_test_index_remote().unwrap();
+    let remote_path = index_url.to_file_path().unwrap();
+    let client = new_client_with_config(test_config(AuthMode::ApiKey("hello".into()), index_url));
+    publish_versions(&client, "biff/hello", &["1.0.0"]);
+
+    // The index can't be pushed to, so the version stays listed, and its
+    // archive has to stay in storage.
+    std::fs::write(remote_path.join("refs/heads/main.lock"), b"").unwrap();
+
+    let response = client
+        .delete("/v1/package/biff/hello/1.0.0")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::InternalServerError);
+
+    let response = client
+        .get("/v1/package-contents/biff/hello/1.0.0")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[test]
+fn republish_identical_archive() {
+    let index_url = init_test_index_remote().unwrap();
+    let mut config = test_config(AuthMode::ApiKey("hello".into()), index_url);
+    config.republish_window = 60;
+    let client = new_client_with_config(config);
+
+    let publish = |contents: &PackageContents| {
+        client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .body(contents.data())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch()
+    };
+
+    let contents = PackageBuilder::new("biff/hello@1.0.0").contents();
+    assert_eq!(publish(&contents).status(), Status::Ok);
+
+    // The same archive again is a retry, and succeeds.
+    let response = publish(&contents);
+    assert_eq!(response.status(), Status::Ok);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["republished"], true);
+
+    let different = PackageBuilder::new("biff/hello@1.0.0")
+        .with_description("Something else")
+        .contents();
+    let response = publish(&different);
+    assert_eq!(response.status(), Status::Conflict);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["code"], "version_exists");
+
+    let response = client
+        .get("/v1/package-metadata/biff/hello")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["versions"].as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn republish_is_refused_by_default() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    let contents = PackageBuilder::new("biff/hello@1.0.0").contents();
+
+    for status in [Status::Ok, Status::Conflict] {
+        let response = client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .body(contents.data())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch();
+        assert_eq!(response.status(), status);
+    }
+}
+
+/// The key pair from the first Ed25519 test of RFC 8032, as a minisign key
+/// with key id 0807060504030201.
+const SIGNING_KEY_ID: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+const SIGNING_SEED: &str = "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+const SIGNING_PUBLIC_KEY: &str = "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a";
+
+fn minisign_public_key() -> String {
+    let mut bytes = b"Ed".to_vec();
+    bytes.extend_from_slice(&SIGNING_KEY_ID);
+    bytes.extend_from_slice(&hex::decode(SIGNING_PUBLIC_KEY).unwrap());
+
+    format!(
+        "untrusted comment: minisign public key 0807060504030201\n{}\n",
+        base64::encode(bytes)
+    )
+}
+
+/// Signs `data` the way `minisign -S` does, giving the `Wally-Signature`
+/// header to publish it with.
+fn minisign_signature(data: &[u8]) -> Header<'static> {
+    use blake2::{Blake2b512, Digest};
+    use ed25519_dalek::{Signer, SigningKey};
+    use std::convert::TryInto;
+
+    let seed = hex::decode(SIGNING_SEED).unwrap();
+    let key = SigningKey::from_bytes(seed.as_slice().try_into().unwrap());
+    let sign = |message: &[u8]| key.sign(message).to_bytes().to_vec();
+
+    let trusted_comment = "timestamp:1700000000\tfile:hello.zip";
+    let signature = sign(&Blake2b512::digest(data));
+    let mut global_message = signature.clone();
+    global_message.extend_from_slice(trusted_comment.as_bytes());
+
+    let mut signature_line = b"ED".to_vec();
+    signature_line.extend_from_slice(&SIGNING_KEY_ID);
+    signature_line.extend_from_slice(&signature);
+
+    let minisig = format!(
+        "untrusted comment: signature from minisign secret key\n{}\ntrusted comment: {}\n{}\n",
+        base64::encode(signature_line),
+        trusted_comment,
+        base64::encode(sign(&global_message))
+    );
+
+    Header::new("Wally-Signature", base64::encode(minisig))
+}
+
+fn set_signing_keys<'c>(client: &'c Client, keys: &[String]) -> LocalResponse<'c> {
+    client
+        .put("/v1/scope/biff/signing-keys")
+        .header(ContentType::JSON)
+        .header(Header::new("Authorization", "Bearer hello"))
+        .body(serde_json::json!({ "keys": keys }).to_string())
+        .dispatch()
+}
+
+/// A key and signatures of a file containing `test`, made by `minisign -S`,
+/// from the `minisign-verify` crate's tests.
+const MINISIGN_PUBLIC_KEY: &str = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3";
+const MINISIGN_PREHASHED_SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RUQf6LRCGA9i559r3g7V1qNyJDApGip8MfqcadIgT9CuhV3EMhHoN1mGTkUidF/z7SrlQgXdy8ofjb7bNJJylDOocrCo8KLzZwo=
+trusted comment: timestamp:1556193335\tfile:test
+y/rUw2y8/hOUYjZU71eHp/Wo1KZ40fGy2VJEDl34XMJM+TX48Ss/17u3IvIfbVR1FkZZSNCisQbuQY+bHwhEBg==
+";
+const MINISIGN_LEGACY_SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RWQf6LRCGA9i59SLOFxz6NxvASXDJeRtuZykwQepbDEGt87ig1BNpWaVWuNrm73YiIiJbq71Wi+dP9eKL8OC351vwIasSSbXxwA=
+trusted comment: timestamp:1555779966\tfile:test
+QtKMXWyYcwdpZAlPF7tE2ENJkRd1ujvKjlj1m9RtHTBnZPa5WKU5uWRs5GoP5M/VqE81QFuMKI5k/SfNQUaOAA==
+";
+
+#[test]
+fn minisign_test_vectors() {
+    use crate::signing::{PublicKey, SignedArchive};
+
+    let key = PublicKey::parse(MINISIGN_PUBLIC_KEY).unwrap();
+    assert_eq!(key.key_id(), "E7620F1842B4E81F");
+    let keys = [key];
+
+    let verify = |signature: &str, archive: &[u8]| {
+        SignedArchive::new(signature.to_owned(), archive)
+            .and_then(|signed| signed.verify(&keys))
+            .is_ok()
+    };
+
+    assert!(verify(MINISIGN_PREHASHED_SIGNATURE, b"test"));
+    assert!(verify(MINISIGN_LEGACY_SIGNATURE, b"test"));
+    assert!(!verify(MINISIGN_PREHASHED_SIGNATURE, b"tset"));
+    assert!(!verify(MINISIGN_LEGACY_SIGNATURE, b"tset"));
+
+    // The trusted comment is signed too, so it can't be changed.
+    let tampered = MINISIGN_PREHASHED_SIGNATURE.replace("file:test", "file:else");
+    assert!(!verify(&tampered, b"test"));
+}
+
+#[test]
+fn scope_signing_keys() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+
+    let response = set_signing_keys(&client, &[minisign_public_key()]);
+    assert_eq!(response.status(), Status::Ok);
+
+    let body: serde_json::Value = client
+        .get("/v1/scope/biff/signing-keys")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch()
+        .into_json()
+        .unwrap();
+    assert_eq!(body["keys"][0]["id"], "0807060504030201");
+    assert_eq!(
+        body["keys"][0]["key"],
+        minisign_public_key().lines().nth(1).unwrap()
+    );
+
+    let response = set_signing_keys(&client, &["not a key".to_owned()]);
+    assert_eq!(response.status(), Status::BadRequest);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["code"], "invalid_signing_key");
+}
+
+#[test]
+fn signed_publish() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    set_signing_keys(&client, &[minisign_public_key()]);
+
+    let contents = PackageBuilder::new("biff/hello@1.0.0").contents();
+    let signature = minisign_signature(contents.data());
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .body(contents.data())
+        .header(Header::new("Authorization", "Bearer hello"))
+        .header(signature.clone())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let expected = String::from_utf8(base64::decode(signature.value()).unwrap()).unwrap();
+
+    let metadata: serde_json::Value = client
+        .get("/v1/package-metadata/biff/hello")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch()
+        .into_json()
+        .unwrap();
+    assert_eq!(metadata["signatures"]["1.0.0"], expected.as_str());
+
+    let response = client
+        .get("/v1/package-contents/biff/hello/1.0.0")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(
+        response.headers().get_one("Wally-Signature"),
+        Some(signature.value())
+    );
+}
+
+#[test]
+fn publish_rejects_bad_signatures() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    let contents = PackageBuilder::new("biff/hello@1.0.0").contents();
+
+    let publish = |signature: Header<'static>| {
+        let response = client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .body(contents.data())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .header(signature)
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        let body: serde_json::Value = response.into_json().unwrap();
+        body["code"].as_str().unwrap().to_owned()
+    };
+
+    // The scope has no keys to check the signature with yet.
+    assert_eq!(
+        publish(minisign_signature(contents.data())),
+        "unknown_signing_key"
+    );
+
+    set_signing_keys(&client, &[minisign_public_key()]);
+
+    assert_eq!(
+        publish(minisign_signature(b"something else")),
+        "invalid_signature"
+    );
+    assert_eq!(
+        publish(Header::new("Wally-Signature", "not a signature")),
+        "invalid_signature"
+    );
+}
+
+#[test]
+fn publish_requires_signatures() {
+    let index_url = init_test_index_remote().unwrap();
+    let mut config = test_config(AuthMode::ApiKey("hello".into()), index_url);
+    config.require_signatures = true;
+    let client = new_client_with_config(config);
+
+    let contents = PackageBuilder::new("biff/hello@1.0.0").contents();
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .body(contents.data())
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["code"], "signature_required");
+}
+
+#[test]
+fn unpublish_window_passed() {
+    let index_url = init_test_index_remote().unwrap();
+    let mut config = test_config(AuthMode::ApiKey("hello".into()), index_url);
+    config.unpublish_window = 0;
+    let client = new_client_with_config(config);
+    publish_versions(&client, "biff/hello", &["1.0.0"]);
+
+    let response = client
+        .delete("/v1/package/biff/hello/1.0.0")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["code"], "unpublish_window_passed");
+}
+
+#[test]
+fn range_requests() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+
+    let contents = PackageBuilder::new("biff/hello@1.0.0")
+        .with_file("src/init.lua", "return 'hello'")
+        .contents();
+    let data = contents.data().to_vec();
+    let size = data.len();
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .body(&data)
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let download = |headers: &[(&'static str, String)]| {
+        let mut request = client
+            .get("/v1/package-contents/biff/hello/1.0.0")
+            .header(Header::new("Authorization", "Bearer hello"));
+
+        for (name, value) in headers {
+            request = request.header(Header::new(*name, value.clone()));
+        }
+
+        request.dispatch()
+    };
+
+    let response = download(&[]);
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("Accept-Ranges"), Some("bytes"));
+    let etag = response.headers().get_one("ETag").unwrap().to_owned();
+
+    // A download resumed partway through the file.
+    let response = download(&[("Range", String::from("bytes=10-19"))]);
+    assert_eq!(response.status(), Status::PartialContent);
+    assert_eq!(
+        response.headers().get_one("Content-Range"),
+        Some(format!("bytes 10-19/{}", size).as_str())
+    );
+    assert_eq!(response.headers().get_one("Content-Length"), Some("10"));
+    assert_eq!(response.into_bytes().unwrap(), &data[10..20]);
+
+    let response = download(&[("Range", String::from("bytes=-5"))]);
+    assert_eq!(response.status(), Status::PartialContent);
+    assert_eq!(response.into_bytes().unwrap(), &data[size - 5..]);
+
+    // A range running past the end is cut short.
+    let response = download(&[("Range", format!("bytes={}-{}", size - 3, size + 100))]);
+    assert_eq!(response.status(), Status::PartialContent);
+    assert_eq!(response.into_bytes().unwrap(), &data[size - 3..]);
+
+    // Ranges starting past the end, and malformed ones, can't be satisfied.
+    for range in &[
+        format!("bytes={}-", size),
+        String::from("bytes=20-10"),
+        String::from("bytes=ten-twenty"),
+        String::from("lines=1-2"),
+    ] {
+        let response = download(&[("Range", range.clone())]);
+        assert_eq!(response.status(), Status::RangeNotSatisfiable, "{}", range);
+        assert_eq!(
+            response.headers().get_one("Content-Range"),
+            Some(format!("bytes */{}", size).as_str()),
+            "{}",
+            range
+        );
+    }
+
+    // The whole package is sent if it changed since the client started
+    // downloading it.
+    let if_range = |tag: String| {
+        download(&[("Range", String::from("bytes=10-19")), ("If-Range", tag)]).status()
+    };
+    assert_eq!(if_range(etag), Status::PartialContent);
+    assert_eq!(if_range(String::from("\"something-else\"")), Status::Ok);
+}
+
+#[test]
+fn package_checksums() {
+    let mut config = test_config(
+        AuthMode::ApiKey("hello".into()),
+        init_test_index_remote().unwrap(),
+    );
+    config.admin_key = Some(String::from("admin"));
+    let package_path = match &config.storage {
+        StorageMode::Local { path } => path.clone().unwrap(),
+        _ => unreachable!(),
+    };
+    let client = new_client_with_config(config);
+
+    let contents = PackageBuilder::new("biff/hello@1.0.0").contents();
+    let sha256 = hex::encode(Sha256::digest(contents.data()));
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .body(contents.data())
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = client
+        .get("/v1/package-contents/biff/hello/1.0.0")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(
+        response.headers().get_one("X-Wally-Checksum"),
+        Some(sha256.as_str())
+    );
+    assert!(response
+        .headers()
+        .get_one("Content-Digest")
+        .unwrap()
+        .starts_with("sha-256=:"));
+
+    let metadata: serde_json::Value = client
+        .get("/v1/package-metadata/biff/hello")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch()
+        .into_json()
+        .unwrap();
+    assert_eq!(metadata["sha256"]["1.0.0"], sha256.as_str());
+
+    let verify = || {
+        client
+            .get("/v1/admin/verify/biff/hello/1.0.0")
+            .header(Header::new("Authorization", "Bearer admin"))
+            .dispatch()
+            .into_json::<serde_json::Value>()
+            .unwrap()
+    };
+    assert_eq!(verify()["intact"], true);
+
+    std::fs::write(package_path.join("biff/hello/1.0.0.zip"), b"corrupted").unwrap();
+    let body = verify();
+    assert_eq!(body["intact"], false);
+    assert_eq!(body["expected"], sha256.as_str());
+}
+
+#[test]
+fn failed_publish_leaves_nothing_behind() {
+    let index_url = init_test_index_remote().unwrap();
+    let remote_path = index_url.to_file_path().unwrap();
+    let config = test_config(AuthMode::ApiKey("hello".into()), index_url);
+    let package_path = match &config.storage {
+        StorageMode::Local { path } => path.clone().unwrap(),
+        _ => unreachable!(),
+    };
+    let client = new_client_with_config(config);
+
+    let publish = || {
+        client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .header(Header::new("Authorization", "Bearer hello"))
+            .body(PackageBuilder::new("biff/hello@1.0.0").contents().data())
+            .dispatch()
+            .status()
+    };
+
+    // Locking the remote's branch lets the commit be built and sent, but
+    // fails the push right at the end, when the branch would be updated.
+    let branch_lock = remote_path.join("refs/heads/main.lock");
+    std::fs::write(&branch_lock, b"").unwrap();
+
+    assert_eq!(publish(), Status::InternalServerError);
+
+    let remote = git2::Repository::open_bare(&remote_path).unwrap();
+    let head = remote.find_reference("refs/heads/main").unwrap();
+    assert_eq!(
+        head.peel_to_commit().unwrap().message(),
+        Some("Initial commit")
+    );
+
+    let response = client
+        .get("/v1/package-metadata/biff/hello")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    assert!(!package_path.join("biff/hello/1.0.0.zip").exists());
+
+    // Once the remote can be written to again, nothing is in the way of
+    // publishing the same version.
+    std::fs::remove_file(&branch_lock).unwrap();
+    assert_eq!(publish(), Status::Ok);
+}
+
+#[test]
+fn failed_publish_keeps_pruned_versions() {
+    let index_url = init_test_index_remote().unwrap();
+    let remote_path = index_url.to_file_path().unwrap();
+    let mut config = test_config(AuthMode::ApiKey("hello".into()), index_url);
+    config.max_versions_per_package = Some(2);
+    config.prune_prereleases = true;
+    let package_path = match &config.storage {
+        StorageMode::Local { path } => path.clone().unwrap(),
+        _ => unreachable!(),
+    };
+    let client = new_client_with_config(config);
+    publish_versions(&client, "biff/hello", &["1.0.0-rc.1", "1.0.0-rc.2"]);
+
+    let publish = || {
+        client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .header(Header::new("Authorization", "Bearer hello"))
+            .body(PackageBuilder::new("biff/hello@1.0.0").contents().data())
+            .dispatch()
+            .status()
+    };
+
+    let branch_lock = remote_path.join("refs/heads/main.lock");
+    std::fs::write(&branch_lock, b"").unwrap();
+    assert_eq!(publish(), Status::InternalServerError);
+
+    let response = client
+        .get("/v1/package-metadata/biff/hello")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["versions"].as_array().unwrap().len(), 2);
+    assert!(package_path.join("biff/hello/1.0.0-rc.1.zip").exists());
+
+    // The version is pruned in the same commit that publishes the new one.
+    std::fs::remove_file(&branch_lock).unwrap();
+    assert_eq!(publish(), Status::Ok);
+
+    let remote = git2::Repository::open_bare(&remote_path).unwrap();
+    let head = remote.find_reference("refs/heads/main").unwrap();
+    assert_eq!(
+        head.peel_to_commit().unwrap().message(),
+        Some("Publish biff/hello@1.0.0, pruning 1.0.0-rc.1")
+    );
+    assert!(!package_path.join("biff/hello/1.0.0-rc.1.zip").exists());
+}
+
+#[test]
+fn index_consistency() {
+    let mut config = test_config(
+        AuthMode::ApiKey("hello".into()),
+        init_test_index_remote().unwrap(),
+    );
+    config.admin_key = Some(String::from("admin"));
+    let package_path = match &config.storage {
+        StorageMode::Local { path } => path.clone().unwrap(),
+        _ => unreachable!(),
+    };
+    let client = new_client_with_config(config);
+    publish_versions(&client, "biff/hello", &["1.0.0", "1.1.0"]);
+
+    let check = |path: &'static str| {
+        let request = match path {
+            "/v1/admin/consistency" => client.get(path),
+            _ => client.post(path),
+        };
+        let response = request
+            .header(Header::new("Authorization", "Bearer admin"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        response.into_json::<serde_json::Value>().unwrap()
+    };
+    let lists = |report: &serde_json::Value, field: &str, id: &str| {
+        report[field]
+            .as_array()
+            .unwrap()
+            .contains(&serde_json::json!(id))
+    };
+
+    let report = check("/v1/admin/consistency");
+    assert!(!lists(&report, "missing-archives", "biff/hello@1.0.0"));
+    assert!(!lists(&report, "orphaned-archives", "biff/hello@1.0.0"));
+
+    std::fs::remove_file(package_path.join("biff/hello/1.0.0.zip")).unwrap();
+    let orphan = package_path.join("biff/orphan/2.0.0.zip");
+    std::fs::create_dir_all(orphan.parent().unwrap()).unwrap();
+    std::fs::write(&orphan, b"orphan").unwrap();
+
+    // Only reported, until pruning is asked for.
+    let report = check("/v1/admin/consistency");
+    assert!(lists(&report, "missing-archives", "biff/hello@1.0.0"));
+    assert!(!lists(&report, "missing-archives", "biff/hello@1.1.0"));
+    assert!(lists(&report, "orphaned-archives", "biff/orphan@2.0.0"));
+    assert_eq!(report["pruned"], serde_json::json!([]));
+    assert!(orphan.exists());
+
+    let report = check("/v1/admin/consistency/prune");
+    assert!(lists(&report, "pruned", "biff/orphan@2.0.0"));
+    assert!(!orphan.exists());
+    assert!(package_path.join("biff/hello/1.1.0.zip").exists());
+
+    let response = client
+        .get("/v1/admin/consistency")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+#[test]
+fn owner_audit() {
+    let (github_url, github_server) = mock_server(vec![404, 500]);
+    let mut config = test_config(
+        AuthMode::ApiKey("hello".into()),
+        init_test_index_remote().unwrap(),
+    );
+    config.admin_key = Some(String::from("admin"));
+    config.github_api_url = Some(github_url);
+    let client = new_client_with_config(config);
+    publish_versions(&client, "biff/hello", &["1.0.0"]);
+
+    let response = client
+        .post("/v1/scope-owners")
+        .header(ContentType::JSON)
+        .header(Header::new("Authorization", "Bearer hello"))
+        .body(r#"{ "scope": "owned", "add": [2] }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let audit = |path: &str| {
+        let response = client
+            .get(path)
+            .header(Header::new("Authorization", "Bearer admin"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        response.into_json::<serde_json::Value>().unwrap()
+    };
+
+    // Owners are only looked up on GitHub when asked.
+    let report = audit("/v1/admin/scope-owners");
+    assert_eq!(report["scopes"], 2);
+    assert_eq!(report["ownerless-scopes"], serde_json::json!(["biff"]));
+    assert_eq!(report["stale-owners"], serde_json::Value::Null);
+
+    let report = audit("/v1/admin/scope-owners?github=true");
+    assert_eq!(report["stale-owners"][0]["scope"], "owned");
+    assert_eq!(report["stale-owners"][0]["owner"], 2);
+    assert_eq!(report["unverified-owners"], serde_json::json!([]));
+
+    // An owner GitHub couldn't answer for isn't called stale.
+    let report = audit("/v1/admin/scope-owners?github=true");
+    assert_eq!(report["stale-owners"], serde_json::json!([]));
+    assert_eq!(report["unverified-owners"][0]["owner"], 2);
+
+    let requests = github_server.join().unwrap();
+    assert!(requests[0].0.starts_with("GET /user/2 HTTP/1.1"));
+
+    let response = client
+        .get("/v1/admin/scope-owners")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+#[test]
+fn scope_activity() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    publish_versions(&client, "biff/hello", &["1.0.0", "1.0.1"]);
+
+    let response = client
+        .post("/v1/package-yank/biff/hello/1.0.0")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = client
+        .get("/v1/package-contents/biff/hello/1.0.1")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    // Downloads are counted in the background, so give them a moment.
+    let mut body = serde_json::Value::Null;
+    for _ in 0..50 {
+        let response = client
+            .get("/v1/scope/biff/activity")
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        body = response.into_json().unwrap();
+
+        if body["downloads"]["biff/hello@1.0.1"] == 1 {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+
+    let events = body["events"].as_array().unwrap();
+    let kinds: Vec<&str> = events
+        .iter()
+        .map(|event| event["type"].as_str().unwrap())
+        .collect();
+
+    assert_eq!(kinds, ["yank", "publish", "publish"]);
+    assert_eq!(events[0]["package"], "biff/hello@1.0.0");
+    assert_eq!(events[0]["actor"], "api-key");
+    assert_eq!(body["downloads"]["biff/hello@1.0.1"], 1);
+    assert!(body["next"].is_null());
+}
+
+#[test]
+fn scope_activity_paginates() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    publish_versions(&client, "biff/hello", &["1.0.0", "1.0.1"]);
+
+    let get_page = |url: String| -> serde_json::Value {
+        client
+            .get(url)
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch()
+            .into_json()
+            .unwrap()
+    };
+
+    let first = get_page("/v1/scope/biff/activity?limit=1".to_owned());
+    assert_eq!(first["events"][0]["package"], "biff/hello@1.0.1");
+
+    let next = first["next"].as_u64().unwrap();
+    let second = get_page(format!("/v1/scope/biff/activity?limit=1&before={}", next));
+    assert_eq!(second["events"][0]["package"], "biff/hello@1.0.0");
+}
+
+#[test]
+fn scope_activity_unauthenticated_401() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    let response = client.get("/v1/scope/biff/activity").dispatch();
+
+    Expectation {
+        status: Status::Unauthorized,
+        content_type: ContentType::JSON,
+    }
+    .assert(response);
+}
+
+#[test]
+fn broken_persistence_does_not_break_downloads() {
+    let mut config = test_config(AuthMode::Unauthenticated, init_test_index_remote().unwrap());
+    config.persistence = PersistenceMode::Unavailable;
+    let client = new_client_with_config(config);
+
+    let response = client
+        .get("/v1/package-contents/biff/minimal/0.1.0")
+        .dispatch();
+
+    Expectation {
+        status: Status::Ok,
+        content_type: ContentType::Binary,
+    }
+    .assert(response);
+}
+
+#[test]
+fn broken_persistence_makes_activity_unavailable() {
+    let mut config = test_config(
+        AuthMode::ApiKey("hello".into()),
+        init_test_index_remote().unwrap(),
+    );
+    config.persistence = PersistenceMode::Unavailable;
+    let client = new_client_with_config(config);
+
+    // Publishing still works, without its audit event being kept.
+    publish_versions(&client, "biff/hello", &["1.0.0"]);
+
+    let response = client
+        .get("/v1/scope/biff/activity")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+
+    Expectation {
+        status: Status::ServiceUnavailable,
+        content_type: ContentType::JSON,
+    }
+    .assert(response);
+}
+
+#[test]
+fn maintenance_rejects_writes() {
+    let mut config = test_config(
+        AuthMode::ApiKey("hello".into()),
+        init_test_index_remote().unwrap(),
+    );
+    config.maintenance = true;
+    let client = new_client_with_config(config);
+
+    let contents = PackageBuilder::new("biff/hello@1.0.0").contents();
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .body(contents.data())
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+
+    Expectation {
+        status: Status::ServiceUnavailable,
+        content_type: ContentType::JSON,
+    }
+    .assert(response);
+
+    let response = client
+        .get("/v1/package-contents/biff/minimal/0.1.0")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+
+    Expectation {
+        status: Status::Ok,
+        content_type: ContentType::Binary,
+    }
+    .assert(response);
+
+    // Reads that need write access keep working too.
+    let response = client
+        .get("/v1/scope/biff/activity")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let health: serde_json::Value = client.get("/healthz").dispatch().into_json().unwrap();
+    assert_eq!(health["maintenance"], true);
+}
+
+#[test]
+fn toggle_maintenance() {
+    let mut config = test_config(AuthMode::Unauthenticated, init_test_index_remote().unwrap());
+    config.admin_key = Some(String::from("admin"));
+    let client = new_client_with_config(config);
+
+    let set_maintenance = |key: &str, enabled: bool| {
+        client
+            .put("/v1/admin/maintenance")
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", format!("Bearer {}", key)))
+            .body(format!(r#"{{ "enabled": {} }}"#, enabled))
+            .dispatch()
+    };
+
+    Expectation {
+        status: Status::Unauthorized,
+        content_type: ContentType::JSON,
+    }
+    .assert(set_maintenance("not the admin key", true));
+
+    assert_eq!(set_maintenance("admin", true).status(), Status::Ok);
+    let health: serde_json::Value = client.get("/healthz").dispatch().into_json().unwrap();
+    assert_eq!(health["maintenance"], true);
+
+    assert_eq!(set_maintenance("admin", false).status(), Status::Ok);
+    let health: serde_json::Value = client.get("/healthz").dispatch().into_json().unwrap();
+    assert_eq!(health["maintenance"], false);
+}
+
+fn change_blocklist(client: &Client, add: bool, entries: serde_json::Value) -> LocalResponse<'_> {
+    let request = match add {
+        true => client.post("/v1/admin/blocklist"),
+        false => client.delete("/v1/admin/blocklist"),
+    };
+
+    request
+        .header(ContentType::JSON)
+        .header(Header::new("Authorization", "Bearer admin"))
+        .body(entries.to_string())
+        .dispatch()
+}
+
+#[test]
+fn blocklisted_api_keys() {
+    use crate::blocklist::token_hash;
+
+    let mut config = test_config(
+        AuthMode::ApiKey("hello".into()),
+        init_test_index_remote().unwrap(),
+    );
+    config.admin_key = Some(String::from("admin"));
+    let client = new_client_with_config(config);
+
+    let whoami = || {
+        client
+            .get("/v1/whoami")
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch()
+    };
+    assert_eq!(whoami().status(), Status::Ok);
+
+    let hash = token_hash("hello").to_ascii_uppercase();
+    let response = change_blocklist(&client, true, serde_json::json!({ "token_hashes": [hash] }));
+    assert_eq!(response.status(), Status::Ok);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(
+        body["token_hashes"],
+        serde_json::json!([token_hash("hello")])
+    );
+
+    let response = whoami();
+    assert_eq!(response.status(), Status::Forbidden);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["code"], "blocked");
+
+    // The admin key is never blocked, so a block can always be undone.
+    let response = change_blocklist(
+        &client,
+        true,
+        serde_json::json!({ "token_hashes": [token_hash("admin")] }),
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let entries: serde_json::Value = client
+        .get("/v1/admin/blocklist")
+        .header(Header::new("Authorization", "Bearer admin"))
+        .dispatch()
+        .into_json()
+        .unwrap();
+    assert_eq!(entries["token_hashes"].as_array().unwrap().len(), 2);
+
+    let response = change_blocklist(
+        &client,
+        true,
+        serde_json::json!({ "token_hashes": ["hello"] }),
+    );
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let response = change_blocklist(
+        &client,
+        false,
+        serde_json::json!({ "token_hashes": [token_hash("hello")] }),
+    );
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(whoami().status(), Status::Ok);
+}
+
+/// Makes `token` belong to the given GitHub user without asking GitHub, by
+/// putting it in the token cache as if it had just been checked for writing.
+fn cache_github_user(client: &Client, token: &str, login: &str, id: u64) {
+    let info: GithubInfo = serde_json::from_value(serde_json::json!({
+        "login": login,
+        "id": id,
+    }))
+    .unwrap();
+
+    client.rocket().state::<TokenCache>().unwrap().insert(
+        token,
+        true,
+        false,
+        CachedToken {
+            user: CachedUser::Github(info),
+            permission: None,
+        },
+    );
+}
+
+fn github_oauth_config() -> Config {
+    test_config(
+        AuthMode::GithubOAuth {
+            client_id: String::from("client-id"),
+            client_secret: String::from("client-secret"),
+        },
+        init_test_index_remote().unwrap(),
+    )
+}
+
+#[test]
+fn blocklisted_github_users() {
+    let mut config = github_oauth_config();
+    config.admin_key = Some(String::from("admin"));
+    let client = new_client_with_config(config);
+
+    // The account has been renamed since it was blocked.
+    cache_github_user(&client, "gho_token", "biff-renamed", 42);
+
+    let whoami = || {
+        client
+            .get("/v1/whoami?write=true")
+            .header(Header::new("Authorization", "Bearer gho_token"))
+            .dispatch()
+    };
+    assert_eq!(whoami().status(), Status::Ok);
+
+    let response = change_blocklist(&client, true, serde_json::json!({ "user_ids": [42] }));
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = whoami();
+    assert_eq!(response.status(), Status::Forbidden);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["code"], "blocked");
+
+    let response = change_blocklist(&client, false, serde_json::json!({ "user_ids": [42] }));
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(whoami().status(), Status::Ok);
+}
+
+#[test]
+fn gitlab_tokens_are_cached_and_blocklisted() {
+    // Only enough answers for one check, so a second would fail to connect.
+    let (instance_url, gitlab) = mock_server_responses(vec![
+        (
+            200,
+            vec![("content-type", "application/json")],
+            String::from(r#"{"username": "biff", "id": 42}"#),
+        ),
+        (
+            200,
+            vec![("content-type", "application/json")],
+            String::from(r#"{"application": {"uid": "client-id"}}"#),
+        ),
+    ]);
+
+    let mut config = test_config(
+        AuthMode::GitLab {
+            client_id: String::from("client-id"),
+            client_secret: String::from("client-secret"),
+            instance_url,
+            private: false,
+        },
+        init_test_index_remote().unwrap(),
+    );
+    config.admin_key = Some(String::from("admin"));
+    let client = new_client_with_config(config);
+
+    let whoami = || {
+        client
+            .get("/v1/whoami?write=true")
+            .header(Header::new("Authorization", "Bearer glpat_token"))
+            .dispatch()
+    };
+    assert_eq!(whoami().status(), Status::Ok);
+    assert_eq!(gitlab.join().unwrap().len(), 2);
+    assert_eq!(whoami().status(), Status::Ok);
+
+    let response = change_blocklist(&client, true, serde_json::json!({ "user_ids": [42] }));
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = whoami();
+    assert_eq!(response.status(), Status::Forbidden);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["code"], "blocked");
+}
+
+#[test]
+fn github_write_denied_403() {
+    let client = new_client_with_config(github_oauth_config());
+    cache_github_user(&client, "gho_token", "mallory", 99);
+
+    let as_user = |request: LocalRequest<'_>| {
+        request
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", "Bearer gho_token"))
+            .dispatch()
+            .status()
+    };
+
+    // The token is fine, but mallory doesn't own the scope.
+    let contents = PackageBuilder::new("biff/hello@1.0.0").contents();
+    assert_eq!(
+        as_user(client.post("/v1/publish").body(contents.data())),
+        Status::Forbidden
+    );
+    assert_eq!(
+        as_user(client.post("/v1/package-yank/biff/minimal/0.1.0")),
+        Status::Forbidden
+    );
+    assert_eq!(
+        as_user(client.get("/v1/scope/biff/activity")),
+        Status::Forbidden
+    );
+    assert_eq!(
+        as_user(
+            client
+                .post("/v1/scope-owners")
+                .body(r#"{ "scope": "biff", "add": [99] }"#)
+        ),
+        Status::Forbidden
+    );
+
+    // Without a token, or with a malformed one, the user should log in again.
+    let response = client.post("/v1/publish").body(contents.data()).dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    let response = client
+        .post("/v1/publish")
+        .header(Header::new("Authorization", "gho_token"))
+        .body(contents.data())
+        .dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+#[test]
+fn can_publish_checks_scope() {
+    let client = new_client_with_config(github_oauth_config());
+    cache_github_user(&client, "gho_token", "mallory", 99);
+
+    let can_publish = |scope: &str| {
+        let response = client
+            .get(format!("/v1/can-publish/{}", scope))
+            .header(Header::new("Authorization", "Bearer gho_token"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        response.into_json::<serde_json::Value>().unwrap()
+    };
+
+    let body = can_publish("biff");
+    assert_eq!(body["allowed"], false);
+    assert_eq!(body["reason"], "denied");
+
+    // Nobody owns the mallory scope yet, so publishing would claim it.
+    let body = can_publish("mallory");
+    assert_eq!(body["allowed"], true);
+    assert_eq!(body["reason"], "bootstrap");
+
+    let response = client
+        .post("/v1/scope-owners")
+        .header(ContentType::JSON)
+        .header(Header::new("Authorization", "Bearer gho_token"))
+        .body(r#"{ "scope": "mallory" }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let body = can_publish("mallory");
+    assert_eq!(body["allowed"], true);
+    assert_eq!(body["reason"], "owner");
+
+    let response = client.get("/v1/can-publish/mallory").dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+#[test]
+fn scope_allow_and_deny_lists() {
+    let mut config = test_config(
+        AuthMode::ApiKey("hello".into()),
+        init_test_index_remote().unwrap(),
+    );
+    config.allowed_scopes = vec![String::from("biff"), String::from("wally")];
+    config.denied_scopes = vec![String::from("wally")];
+    let client = new_client_with_config(config);
+
+    let publish = |package: &str| {
+        let response = client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .header(Header::new("Authorization", "Bearer hello"))
+            .body(PackageBuilder::new(package).contents().data())
+            .dispatch();
+        let status = response.status();
+        let body: serde_json::Value = response.into_json().unwrap();
+        (status, body["code"].clone())
+    };
+
+    assert_eq!(publish("biff/hello@1.0.0").0, Status::Ok);
+    assert_eq!(
+        publish("other/hello@1.0.0"),
+        (Status::Forbidden, serde_json::json!("scope_not_allowed"))
+    );
+
+    // Denying a scope wins over allowing it.
+    assert_eq!(
+        publish("wally/hello@1.0.0"),
+        (Status::Forbidden, serde_json::json!("scope_reserved"))
+    );
+}
+
+#[test]
+fn denied_scopes_cant_be_claimed() {
+    let mut config = github_oauth_config();
+    config.denied_scopes = vec![String::from("wally")];
+    let client = new_client_with_config(config);
+    cache_github_user(&client, "gho_token", "Wally", 5);
+
+    let response = client
+        .post("/v1/scope-owners")
+        .header(ContentType::JSON)
+        .header(Header::new("Authorization", "Bearer gho_token"))
+        .body(r#"{ "scope": "wally" }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["code"], "scope_reserved");
+
+    let response = client
+        .get("/v1/can-publish/wally")
+        .header(Header::new("Authorization", "Bearer gho_token"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+}
+
+#[test]
+fn blocklist_is_saved() {
+    use crate::blocklist::{Blocklist, BlocklistEntries};
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("blocklist.json");
+
+    let blocklist = Blocklist::load(path.clone()).unwrap();
+    assert_eq!(blocklist.entries(), BlocklistEntries::default());
+
+    blocklist
+        .add(BlocklistEntries {
+            user_ids: vec![OwnerId::new(42), OwnerId::new(43)]
+                .into_iter()
+                .collect(),
+            token_hashes: Default::default(),
+        })
+        .unwrap();
+    blocklist
+        .remove(&BlocklistEntries {
+            user_ids: vec![OwnerId::new(43)].into_iter().collect(),
+            token_hashes: Default::default(),
+        })
+        .unwrap();
+
+    let reloaded = Blocklist::load(path).unwrap();
+    assert!(reloaded.is_user_blocked(&OwnerId::new(42)));
+    assert!(!reloaded.is_user_blocked(&OwnerId::new(43)));
+}
+
+#[test]
+fn federated_indexes() {
+    let internal_url = init_test_index_remote().unwrap();
+    let community_url = init_test_index_remote().unwrap();
+
+    let mut config = test_config(AuthMode::ApiKey("hello".into()), internal_url.clone());
+    config.admin_key = Some(String::from("admin"));
+    config.index_scopes = vec!["studio".to_owned()];
+    config.federated_indexes = vec![FederatedIndexConfig {
+        url: community_url.clone(),
+        scopes: vec!["biff".to_owned(), "other".to_owned()],
+        lock: None,
+    }];
+    let client = new_client_with_config(config);
+
+    publish_versions(&client, "biff/hello", &["1.0.0"]);
+    publish_versions(&client, "studio/tools", &["1.0.0"]);
+
+    // Each publish lands in the index its scope is served from.
+    let internal = PackageIndex::new_temp(&internal_url, None).unwrap();
+    let community = PackageIndex::new_temp(&community_url, None).unwrap();
+    let biff_hello = "biff/hello".parse().unwrap();
+    let studio_tools = "studio/tools".parse().unwrap();
+    assert!(community.package_exists(&biff_hello).unwrap());
+    assert!(!internal.package_exists(&biff_hello).unwrap());
+    assert!(internal.package_exists(&studio_tools).unwrap());
+    assert!(!community.package_exists(&studio_tools).unwrap());
+
+    for uri in [
+        "/v1/package-metadata/biff/hello",
+        "/v1/package-metadata/studio/tools",
+        "/v1/package-contents/biff/hello/1.0.0",
+    ] {
+        let response = client.get(uri).dispatch();
+        assert_eq!(response.status(), Status::Ok, "{}", uri);
+    }
+
+    // A dependency is resolved in the index its own scope is served from.
+    let contents = PackageBuilder::new("studio/app@1.0.0")
+        .with_dep("Hello", "biff/hello@1.0.0")
+        .contents();
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .header(Header::new("Authorization", "Bearer hello"))
+        .body(contents.data())
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    // Scopes that no index serves don't exist here at all.
+    let contents = PackageBuilder::new("elsewhere/hello@1.0.0").contents();
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .header(Header::new("Authorization", "Bearer hello"))
+        .body(contents.data())
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["code"], "scope_not_served");
+
+    for uri in [
+        "/v1/package-metadata/elsewhere/hello",
+        "/v1/scope/elsewhere/owners",
+        "/v1/scope/elsewhere/packages",
+    ] {
+        let response = client.get(uri).dispatch();
+        assert_eq!(response.status(), Status::NotFound, "{}", uri);
+    }
+
+    // A package in the community index whose scope is served from the
+    // internal one isn't served at all.
+    community
+        .publish(PackageBuilder::new("studio/hello@1.0.0").manifest())
+        .unwrap();
+
+    let response = client
+        .post("/v1/refresh-index")
+        .header(Header::new("Authorization", "Bearer admin"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["federated"][0]["url"], community_url.as_str());
+
+    let response = client.get("/v1/search?q=hello").dispatch();
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["total"], 1);
+    assert_eq!(body["results"][0]["scope"], "biff");
+
+    let response = client.get("/v1/package-metadata/studio/hello").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    let mut config = test_config(AuthMode::Unauthenticated, internal_url.clone());
+    config.federated_indexes = vec![FederatedIndexConfig {
+        url: internal_url,
+        scopes: vec!["biff".to_owned()],
+        lock: None,
+    }];
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn refresh_index() {
+    let index_url = init_test_index_remote().unwrap();
+    let mut config = test_config(AuthMode::ApiKey("hello".into()), index_url.clone());
+    config.admin_key = Some(String::from("admin"));
+    let client = new_client_with_config(config);
+    publish_versions(&client, "biff/hello", &["1.0.0"]);
+
+    let versions = || {
+        let metadata: serde_json::Value = client
+            .get("/v1/package-metadata/biff/hello")
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch()
+            .into_json()
+            .unwrap();
+
+        metadata["versions"].as_array().unwrap().len()
+    };
+    assert_eq!(versions(), 1);
+
+    // A version published straight to the remote index isn't seen until the
+    // registry refreshes its copy.
+    let other = PackageIndex::new_temp(&index_url, None).unwrap();
+    other
+        .publish(PackageBuilder::new("biff/hello@1.1.0").manifest())
+        .unwrap();
+    assert_eq!(versions(), 1);
+
+    let refresh = |key: &str| {
+        client
+            .post("/v1/refresh-index")
+            .header(Header::new("Authorization", format!("Bearer {}", key)))
+            .dispatch()
+    };
+
+    Expectation {
+        status: Status::Unauthorized,
+        content_type: ContentType::JSON,
+    }
+    .assert(refresh("hello"));
+    assert_eq!(versions(), 1);
+
+    Expectation {
+        status: Status::Ok,
+        content_type: ContentType::JSON,
+    }
+    .assert(refresh("admin"));
+    assert_eq!(versions(), 2);
+}
+
+#[test]
+fn refresh_index_incrementally() {
+    let index_url = init_test_index_remote().unwrap();
+    let mut config = test_config(AuthMode::ApiKey("hello".into()), index_url.clone());
+    config.admin_key = Some(String::from("admin"));
+    let client = new_client_with_config(config);
+    publish_versions(&client, "biff/hello", &["1.0.0"]);
+    publish_versions(&client, "biff/other", &["1.0.0"]);
+
+    let versions = |name: &str| {
+        let metadata: serde_json::Value = client
+            .get(format!("/v1/package-metadata/{}", name))
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch()
+            .into_json()
+            .unwrap();
+
+        metadata["versions"].as_array().unwrap().len()
+    };
+    assert_eq!(versions("biff/hello"), 1);
+    assert_eq!(versions("biff/other"), 1);
+
+    let other = PackageIndex::new_temp(&index_url, None).unwrap();
+    other
+        .publish(PackageBuilder::new("biff/hello@1.1.0").manifest())
+        .unwrap();
+
+    let refresh = |uri: &str, event: Option<serde_json::Value>| -> serde_json::Value {
+        let mut request = client
+            .post(uri.to_owned())
+            .header(Header::new("Authorization", "Bearer admin"));
+
+        if let Some(event) = event {
+            request = request.header(ContentType::JSON).body(event.to_string());
+        }
+
+        let response = request.dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        response.into_json().unwrap()
+    };
+
+    // Pushes to other branches don't touch the index.
+    let ignored = refresh(
+        "/v1/refresh-index",
+        Some(serde_json::json!({ "ref": "refs/heads/docs" })),
+    );
+    assert!(ignored.get("head").is_none());
+    assert_eq!(versions("biff/hello"), 1);
+
+    // Only the package that changed is read again.
+    let refreshed = refresh("/v1/refresh-index", None);
+    assert_eq!(refreshed["full"], false);
+    assert_eq!(refreshed["updated"], serde_json::json!(["biff/hello"]));
+    assert_eq!(versions("biff/hello"), 2);
+    assert_eq!(versions("biff/other"), 1);
+
+    // A push to the commit the index is already at needs no fetch.
+    let head = refreshed["head"].as_str().unwrap();
+    let unchanged = refresh(
+        "/v1/refresh-index",
+        Some(serde_json::json!({ "ref": "refs/heads/main", "after": head })),
+    );
+    assert_eq!(unchanged["head"], head);
+    assert_eq!(unchanged["updated"], serde_json::json!([]));
+
+    let full = refresh("/v1/refresh-index?full=true", None);
+    assert_eq!(full["full"], true);
+    assert_eq!(versions("biff/hello"), 2);
+}
+
+struct FakeClock(Mutex<Instant>);
+
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        *self.0.lock().unwrap()
+    }
+}
+
+#[test]
+fn token_cache_expires() {
+    let clock = Arc::new(FakeClock(Mutex::new(Instant::now())));
+    let cache = TokenCache::with_clock(Duration::from_secs(60), clock.clone());
+
+    let info: GithubInfo = serde_json::from_value(serde_json::json!({
+        "login": "biff",
+        "id": 1,
+    }))
+    .unwrap();
+    cache.insert(
+        "a token",
+        true,
+        false,
+        CachedToken {
+            user: CachedUser::Github(info),
+            permission: None,
+        },
+    );
+
+    assert!(cache.get("a token", true, false).is_some());
+    assert!(cache.get("a token", false, false).is_none());
+    assert!(cache.get("a token", true, true).is_none());
+    assert!(cache.get("another token", true, false).is_none());
+
+    *clock.0.lock().unwrap() += Duration::from_secs(61);
+    assert!(cache.get("a token", true, false).is_none());
+}
+
+#[test]
+fn metadata_cache() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    publish_versions(&client, "biff/hello", &["1.0.0", "1.0.1"]);
+
+    let get = |path: &str, accept: &str| {
+        client
+            .get(path.to_owned())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .header(Header::new("Accept", accept.to_owned()))
+            .dispatch()
+    };
+    let metadata = || -> serde_json::Value {
+        get("/v1/package-metadata/biff/hello", "application/json")
+            .into_json()
+            .unwrap()
+    };
+
+    let first = metadata();
+    assert_eq!(metadata(), first);
+
+    // Yanking drops the cached metadata, so the yank shows up right away.
+    let response = client
+        .post("/v1/package-yank/biff/hello/1.0.1")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(metadata()["yanked"], serde_json::json!(["1.0.1"]));
+
+    // Each format is cached on its own.
+    let response = get("/v1/package-metadata/biff/hello", "application/msgpack");
+    assert_eq!(response.content_type(), Some(ContentType::MsgPack));
+
+    for _ in 0..2 {
+        let response = get("/v1/package/biff/hello/1.0.0/manifest", "application/json");
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    let response = get("/v1/package/biff/hello/2.0.0/manifest", "application/json");
+    assert_eq!(response.status(), Status::NotFound);
+
+    let metrics = client.get("/metrics").dispatch().into_string().unwrap();
+
+    for line in [
+        "wally_metadata_cache_total{outcome=\"hit\"} 2",
+        "wally_metadata_cache_total{outcome=\"miss\"} 5",
+    ] {
+        assert!(
+            metrics.lines().any(|l| l == line),
+            "missing {}:\n{}",
+            line,
+            metrics
+        );
+    }
+}
+
+#[rocket::async_test]
+async fn metadata_cache_serves_stale_responses() {
+    let clock = Arc::new(FakeClock(Mutex::new(Instant::now())));
+    let cache = Arc::new(MetadataCache::with_clock(
+        MetadataCacheConfig { ttl: 30, stale: 60 },
+        Arc::new(crate::metrics::Metrics::new()),
+        clock.clone(),
+    ));
+
+    let name: libwally::package_name::PackageName = "biff/hello".parse().unwrap();
+    let key = || CacheKey::metadata(name.clone(), Format::Json);
+    let get = |value: u32| {
+        cache
+            .get(key(), move || {
+                Ok(CachedResponse {
+                    body: Format::Json.encode(&value)?,
+                    etag: None,
+                })
+            })
+            .unwrap()
+            .body
+            .body()
+            .to_vec()
+    };
+
+    assert_eq!(get(1), b"1");
+    assert_eq!(get(2), b"1");
+
+    // A stale response is served while it's encoded again in the background.
+    *clock.0.lock().unwrap() += Duration::from_secs(31);
+    assert_eq!(get(3), b"1");
+
+    for _ in 0..100 {
+        if get(4) == b"3" {
+            break;
+        }
+
+        rocket::tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(get(4), b"3");
+
+    // Past the stale window, the response is encoded again before it's served.
+    *clock.0.lock().unwrap() += Duration::from_secs(91);
+    assert_eq!(get(5), b"5");
+
+    cache.invalidate(&name);
+    assert_eq!(get(6), b"6");
+}
+
+#[test]
+fn rate_limit_writes() {
+    let mut config = test_config(
+        AuthMode::ApiKey("hello".into()),
+        init_test_index_remote().unwrap(),
+    );
+    config.rate_limits = RateLimits {
+        read: None,
+        write: Some(RateLimit {
+            requests: 2,
+            window: 60,
+        }),
+    };
+    let client = new_client_with_config(config);
+
+    let publish = |version: &str| {
+        let contents = PackageBuilder::new(format!("biff/hello@{}", version)).contents();
+        client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .body(contents.data())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch()
+    };
+
+    assert_eq!(publish("1.0.0").status(), Status::Ok);
+    assert_eq!(publish("1.0.1").status(), Status::Ok);
+
+    let response = publish("1.0.2");
+    assert_eq!(response.status(), Status::TooManyRequests);
+    let retry_after: u64 = response
+        .headers()
+        .get_one("Retry-After")
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0 && retry_after <= 30);
+
+    // Reads have their own limit, which isn't set.
+    let response = client
+        .get("/v1/package-contents/biff/minimal/0.1.0")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[test]
+fn rate_limit_refills() {
+    let clock = Arc::new(FakeClock(Mutex::new(Instant::now())));
+    let limiter = RateLimiter::with_clock(
+        RateLimits {
+            read: Some(RateLimit {
+                requests: 2,
+                window: 10,
+            }),
+            write: None,
+        },
+        clock.clone(),
+    );
+
+    let ip = Identity::Ip(None);
+    limiter.check(ip.clone(), AccessKind::Read).unwrap();
+    limiter.check(ip.clone(), AccessKind::Read).unwrap();
+    assert_eq!(
+        limiter.check(ip.clone(), AccessKind::Read),
+        Err(Duration::from_secs(5))
+    );
+
+    // Other identities and writes are counted separately.
+    limiter
+        .check(Identity::User(OwnerId::new(1)), AccessKind::Read)
+        .unwrap();
+    limiter.check(ip.clone(), AccessKind::Write).unwrap();
+
+    *clock.0.lock().unwrap() += Duration::from_secs(5);
+    limiter.check(ip.clone(), AccessKind::Read).unwrap();
+    limiter.check(ip, AccessKind::Read).unwrap_err();
+}
+
+#[test]
+fn auth_throttle_after_failures() {
+    let mut config = test_config(
+        AuthMode::ApiKey(ApiKeys::Many(vec![
+            String::from("old key"),
+            String::from("new key"),
+        ])),
+        init_test_index_remote().unwrap(),
+    );
+    config.auth_throttle = AuthThrottleConfig {
+        max_failures: 3,
+        window: 60,
+    };
+    let client = new_client_with_config(config);
+
+    let whoami = |key: &str| {
+        client
+            .get("/v1/whoami")
+            .header(Header::new("Authorization", format!("Bearer {}", key)))
+            .dispatch()
+    };
+
+    assert_eq!(whoami("old key").status(), Status::Ok);
+
+    for key in ["guess 1", "guess 2", "guess 3"] {
+        assert_eq!(whoami(key).status(), Status::Unauthorized);
+    }
+
+    // A valid key is turned away too, since it can't be told apart from a
+    // lucky guess.
+    let response = whoami("new key");
+    assert_eq!(response.status(), Status::TooManyRequests);
+    let retry_after: u64 = response
+        .headers()
+        .get_one("Retry-After")
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0 && retry_after <= 60);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["code"], "auth_throttled");
+
+    // Keys that already signed in keep working.
+    assert_eq!(whoami("old key").status(), Status::Ok);
+}
+
+#[test]
+fn auth_throttle_decays() {
+    let clock = Arc::new(FakeClock(Mutex::new(Instant::now())));
+    let throttle = AuthThrottle::with_clock(
+        AuthThrottleConfig {
+            max_failures: 2,
+            window: 10,
+        },
+        clock.clone(),
+    );
+
+    throttle.record_failure(None, "gho_aaaa1");
+    throttle.record_failure(None, "gho_aaaa2");
+    assert_eq!(
+        throttle.check(None, "a valid key"),
+        Err(Duration::from_secs(5))
+    );
+
+    // Failures from elsewhere count against the token prefix they share.
+    let elsewhere = Some("10.0.0.1".parse().unwrap());
+    throttle.check(elsewhere, "a valid key").unwrap();
+    throttle.check(elsewhere, "gho_aaaa3").unwrap_err();
+
+    *clock.0.lock().unwrap() += Duration::from_secs(5);
+    throttle.check(None, "a valid key").unwrap();
+
+    throttle.record_failure(None, "guess 4");
+    throttle.check(None, "a valid key").unwrap_err();
+
+    throttle.record_success(None, "a valid key");
+    throttle.check(None, "another key").unwrap();
+}
+
+#[test]
+fn github_token_for_other_app() {
+    use crate::auth::ValidatedGithubInfo;
+
+    // A trimmed down response from GitHub's "check a token" endpoint.
+    let response = |client_id: &str| -> ValidatedGithubInfo {
+        serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "token": "gho_abc123",
+            "app": {
+                "client_id": client_id,
+                "name": "Wally",
+                "url": "https://wally.run",
+            },
+        }))
+        .unwrap()
+    };
+
+    response("our-client-id")
+        .check_app("our-client-id")
+        .unwrap();
+
+    let err = response("their-client-id")
+        .check_app("our-client-id")
+        .unwrap_err();
+    let err = format!("{:?}", err);
+    assert!(err.contains("401"), "{}", err);
+    assert!(err.contains("different application"), "{}", err);
+}
+
+#[test]
+fn github_check_token_responses() {
+    use crate::auth::ValidatedGithubInfo;
+
+    // Both of the shapes GitHub documents for its "check a token" endpoint.
+    for fixture in &[
+        "src/tests/github/check-token-oauth-app.json",
+        "src/tests/github/check-token-github-app.json",
+    ] {
+        let body = fs_err::read(fixture).unwrap();
+        let response: ValidatedGithubInfo = serde_json::from_slice(&body).unwrap();
+        response.check_app("our-client-id").unwrap();
+    }
+
+    // Without an app, there's no way to tell who the token was issued for.
+    let response: ValidatedGithubInfo =
+        serde_json::from_value(serde_json::json!({ "id": 1, "app": null })).unwrap();
+    let err = format!("{:?}", response.check_app("our-client-id").unwrap_err());
+    assert!(err.contains("502"), "{}", err);
+    assert!(err.contains("github_unexpected_response"), "{}", err);
+}
+
+#[test]
+fn extract_github_owner_repo() {
+    use crate::auth::{extract_github_owner_repo, github_api_base};
+
+    let expected = Some((
+        String::from("github.com"),
+        String::from("UpliftGames"),
+        String::from("wally-index"),
+    ));
+
+    for url in [
+        "https://github.com/UpliftGames/wally-index",
+        "https://github.com/UpliftGames/wally-index/",
+        "https://github.com/UpliftGames/wally-index.git",
+        "https://github.com/UpliftGames/wally-index.git/",
+        "http://github.com/UpliftGames/wally-index",
+        "ssh://git@github.com/UpliftGames/wally-index.git",
+        "ssh://git@github.com:22/UpliftGames/wally-index",
+        "git@github.com:UpliftGames/wally-index.git",
+        "git@github.com:UpliftGames/wally-index/",
+    ] {
+        assert_eq!(extract_github_owner_repo(url), expected, "{}", url);
+    }
+
+    let (host, owner, repo) =
+        extract_github_owner_repo("https://github.mycorp.com/games/index.git").unwrap();
+    assert_eq!(
+        (host.as_str(), owner.as_str(), repo.as_str()),
+        ("github.mycorp.com", "games", "index")
+    );
+    assert_eq!(github_api_base(&host), "https://github.mycorp.com/api/v3");
+    assert_eq!(github_api_base("github.com"), "https://api.github.com");
+
+    assert_eq!(
+        extract_github_owner_repo("https://github.com/UpliftGames"),
+        None
+    );
+    assert_eq!(extract_github_owner_repo("not a url"), None);
+}
+
+#[test]
+fn validate_private_github_config() {
+    let github_index: url::Url = "https://github.com/UpliftGames/wally-index"
+        .parse()
+        .unwrap();
+    let private_auth = || AuthMode::GithubOAuthPrivate {
+        client_id: String::from("client-id"),
+        client_secret: String::from("client-secret"),
+    };
+
+    let config = test_config(private_auth(), init_test_index_remote().unwrap());
+    let err = config.validate().unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("index_url must be a GitHub repository URL"),
+        "{}",
+        err
+    );
+
+    let config = test_config(private_auth(), github_index.clone());
+    let err = config.validate().unwrap_err();
+    assert!(err.to_string().contains("needs github_token"), "{}", err);
+
+    let mut config = test_config(private_auth(), github_index);
+    config.github_token = Some(String::from("token"));
+    config.validate().unwrap();
+}
+
+#[test]
+fn config_errors_explain_auth_modes() {
+    let config = test_config(AuthMode::Unauthenticated, init_test_index_remote().unwrap());
+    let figment = Figment::from(Serialized::globals(config)).merge(Serialized::global(
+        "auth",
+        serde_json::json!({ "type": "apikey", "value": "hello" }),
+    ));
+
+    let err = Config::from_figment(&figment).err().unwrap().to_string();
+    assert!(err.contains("apikey"), "{}", err);
+    assert!(err.contains("auth"), "{}", err);
+    assert!(
+        err.contains(r#"{ type = "api-key", value = "SECRET-KEY" }"#),
+        "{}",
+        err
+    );
+
+    // The help is only shown when it's the auth mode that's wrong.
+    let config = test_config(AuthMode::Unauthenticated, init_test_index_remote().unwrap());
+    let figment = Figment::from(Serialized::globals(config))
+        .merge(Serialized::global("max_package_size", "big"));
+    let err = Config::from_figment(&figment).err().unwrap().to_string();
+    assert!(err.contains("max_package_size"), "{}", err);
+    assert!(!err.contains("Auth modes are tables"), "{}", err);
+}
+
+#[test]
+fn unused_github_settings() {
+    let mut config = test_config(
+        AuthMode::ApiKey("hello".into()),
+        init_test_index_remote().unwrap(),
+    );
+    config.github_token = Some(String::from("token"));
+    config.allowed_email_domains = Some(vec![String::from("example.com")]);
+    config.bootstrap.require_verified_email = true;
+
+    assert_eq!(
+        config.unused_settings(),
+        vec!["allowed_email_domains", "bootstrap"]
+    );
+
+    config.write_auth = Some(AuthMode::GithubOAuth {
+        client_id: String::from("client-id"),
+        client_secret: String::from("client-secret"),
+    });
+    assert!(config.unused_settings().is_empty());
+}
+
+#[test]
+fn validate_private_gitlab_config() {
+    let gitlab_auth = |private| AuthMode::GitLab {
+        client_id: String::from("client-id"),
+        client_secret: String::from("client-secret"),
+        instance_url: "https://gitlab.example.com".parse().unwrap(),
+        private,
+    };
+    let github_index: url::Url = "https://github.com/UpliftGames/wally-index"
+        .parse()
+        .unwrap();
+
+    let config = test_config(gitlab_auth(true), github_index.clone());
+    let err = config.validate().unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("index_url must be a project on https://gitlab.example.com"),
+        "{}",
+        err
+    );
+
+    // Public GitLab auth never looks at the index project.
+    test_config(gitlab_auth(false), github_index)
+        .validate()
+        .unwrap();
+
+    let gitlab_index = "https://gitlab.example.com/games/index".parse().unwrap();
+    test_config(gitlab_auth(true), gitlab_index)
+        .validate()
+        .unwrap();
+}
+
+struct FakeTeams(&'static [(&'static str, &'static str)]);
+
+#[rocket::async_trait]
+impl TeamMembership for FakeTeams {
+    async fn is_member(&self, team: &str, login: &str) -> anyhow::Result<bool> {
+        Ok(self.0.iter().any(|&(t, l)| t == team && l == login))
+    }
+}
+
+#[test]
+fn team_members_can_write_scope() {
+    let remote = init_test_index_remote().unwrap();
+    let index = PackageIndex::new_temp(&remote, None).unwrap();
+    index.add_scope_team("biff", "biff-org/publishers").unwrap();
+
+    let github_user = |login: &str, id: u64| WriteAccess::Github {
+        info: serde_json::from_value(serde_json::json!({ "login": login, "id": id })).unwrap(),
+        permission: None,
+    };
+    let teams = FakeTeams(&[("biff-org/publishers", "team-member")]);
+
+    // In the team but not an individual owner of the scope.
+    let member = github_user("team-member", 1);
+    assert!(!index.is_scope_owner("biff", &OwnerId::new(1)).unwrap());
+    assert!(futures::executor::block_on(member.can_write_scope(
+        "biff",
+        &index,
+        &teams,
+        &Default::default()
+    ))
+    .unwrap());
+
+    let outsider = github_user("outsider", 2);
+    assert!(!futures::executor::block_on(outsider.can_write_scope(
+        "biff",
+        &index,
+        &teams,
+        &Default::default()
+    ))
+    .unwrap());
+
+    // A scope owned by a team can't be claimed by a user with the same name.
+    let namesake = github_user("biff", 3);
+    assert!(!futures::executor::block_on(namesake.can_write_scope(
+        "biff",
+        &index,
+        &teams,
+        &Default::default()
+    ))
+    .unwrap());
+}
+
+#[test]
+fn bootstrap_policy() {
+    use crate::config::BootstrapPolicy;
+
+    let remote = init_test_index_remote().unwrap();
+    let index = PackageIndex::new_temp(&remote, None).unwrap();
+    index.add_scope_owner("owned", &OwnerId::new(2)).unwrap();
+
+    let github_user = |login: &str, id: u64, verified_email: Option<bool>| WriteAccess::Github {
+        info: serde_json::from_value(serde_json::json!({
+            "login": login,
+            "id": id,
+            "created_at": "2008-01-14T04:33:35Z",
+            "verified_email": verified_email,
+        }))
+        .unwrap(),
+        permission: None,
+    };
+    let permission = |user: &WriteAccess, scope: &str, policy: &BootstrapPolicy| {
+        futures::executor::block_on(user.write_permission(scope, &index, &FakeTeams(&[]), policy))
+    };
+
+    let old_enough = BootstrapPolicy {
+        min_account_age_days: Some(30),
+        require_verified_email: true,
+    };
+    let user = github_user("biff", 1, Some(true));
+    assert_eq!(
+        permission(&user, "biff", &old_enough).unwrap(),
+        Some(WritePermission::Bootstrap)
+    );
+
+    // No GitHub account is a million years old.
+    let too_new = BootstrapPolicy {
+        min_account_age_days: Some(365 * 1000 * 1000),
+        require_verified_email: false,
+    };
+    let err = format!("{:?}", permission(&user, "biff", &too_new).unwrap_err());
+    assert!(err.contains("403"), "{}", err);
+    assert!(err.contains("bootstrap_not_allowed"), "{}", err);
+
+    let unverified = github_user("biff", 1, Some(false));
+    let err = format!(
+        "{:?}",
+        permission(&unverified, "biff", &old_enough).unwrap_err()
+    );
+    assert!(err.contains("verified email"), "{}", err);
+
+    // Explicit owners aren't held to the policy.
+    let owner = github_user("someone-else", 2, None);
+    assert_eq!(
+        permission(&owner, "owned", &too_new).unwrap(),
+        Some(WritePermission::Owner)
+    );
+}
+
+#[test]
+fn github_permission() {
+    let github_user = |permission: Option<&str>| WriteAccess::Github {
+        info: serde_json::from_value(serde_json::json!({ "login": "biff", "id": 1 })).unwrap(),
+        permission: permission.map(str::to_owned),
+    };
+
+    assert_eq!(
+        github_user(Some("admin")).github_permission(),
+        Some("admin")
+    );
+    assert_eq!(github_user(None).github_permission(), None);
+    assert_eq!(WriteAccess::ApiKey.github_permission(), None);
+}
+
+#[test]
+fn audit_log() {
+    let audit_path = tempfile::tempdir().unwrap().into_path().join("audit.jsonl");
+
+    let mut config = test_config(
+        AuthMode::ApiKey("hello".into()),
+        init_test_index_remote().unwrap(),
+    );
+    config.audit_log = Some(AuditSink::File {
+        path: audit_path.clone(),
+    });
+    let client = new_client_with_config(config);
+
+    let contents = PackageBuilder::new("biff/hello@1.0.0").contents();
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .body(contents.data())
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    // Denied attempts come from users, which the test client can't sign in as,
+    // so write one directly.
+    let github_user = WriteAccess::Github {
+        info: serde_json::from_value(serde_json::json!({ "login": "intruder", "id": 7 })).unwrap(),
+        permission: None,
+    };
+    let audit = AuditLog::new(&AuditSink::File {
+        path: audit_path.clone(),
+    })
+    .unwrap();
+    futures::executor::block_on(audit.record(&AuditEvent::denied(
+        &github_user,
+        &"biff/hello@1.0.1".parse().unwrap(),
+        "you do not have permission to write in scope biff",
+    )))
+    .unwrap();
+
+    let log = fs_err::read_to_string(&audit_path).unwrap();
+    let events: Vec<AuditEvent> = log
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(events.len(), 2);
+
+    assert_eq!(events[0].outcome, AuditOutcome::Allowed);
+    assert_eq!(events[0].actor, "api-key");
+    assert_eq!(events[0].user_id, None);
+    assert_eq!(events[0].package.to_string(), "biff/hello@1.0.0");
+    assert_eq!(events[0].scope, "biff");
+    assert_eq!(events[0].permission, Some(WritePermission::ApiKey));
+
+    assert_eq!(events[1].outcome, AuditOutcome::Denied);
+    assert_eq!(events[1].actor, "intruder");
+    assert_eq!(events[1].user_id, Some(OwnerId::new(7)));
+    assert_eq!(events[1].permission, None);
+    assert!(events[1].reason.as_ref().unwrap().contains("permission"));
+}
+
+/// A store whose database can't be reached, for `PersistenceMode::Unavailable`.
+pub struct UnavailablePersistence;
+
+#[async_trait::async_trait]
+impl crate::stats::StatsStore for UnavailablePersistence {
+    async fn record_download(
+        &self,
+        _package: &libwally::package_id::PackageId,
+    ) -> anyhow::Result<()> {
+        anyhow::bail!("the database is unavailable")
+    }
+
+    async fn package_downloads(
+        &self,
+        _name: &libwally::package_name::PackageName,
+    ) -> anyhow::Result<std::collections::BTreeMap<semver::Version, u64>> {
+        anyhow::bail!("the database is unavailable")
+    }
+
+    async fn scope_downloads(
+        &self,
+        _scope: &str,
+    ) -> anyhow::Result<std::collections::BTreeMap<libwally::package_id::PackageId, u64>> {
+        anyhow::bail!("the database is unavailable")
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::persistence::Persistence for UnavailablePersistence {
+    async fn record_audit(&self, _event: &AuditEvent) -> anyhow::Result<()> {
+        anyhow::bail!("the database is unavailable")
+    }
+
+    async fn scope_audit_events(
+        &self,
+        _scope: &str,
+        _before: Option<u64>,
+        _limit: usize,
+    ) -> anyhow::Result<Vec<crate::audit::StoredAuditEvent>> {
+        anyhow::bail!("the database is unavailable")
+    }
+
+    // The registry loads the blocklist on startup, and doesn't start if it
+    // can't, so this one works.
+    async fn load_blocklist(&self) -> anyhow::Result<crate::blocklist::BlocklistEntries> {
+        Ok(Default::default())
+    }
+
+    async fn save_blocklist(
+        &self,
+        _entries: &crate::blocklist::BlocklistEntries,
+    ) -> anyhow::Result<()> {
+        anyhow::bail!("the database is unavailable")
+    }
+}
+
+/// Keeps audit events in memory, to check what reaches persistence.
+#[derive(Default)]
+struct FakePersistence {
+    events: Mutex<Vec<AuditEvent>>,
+}
+
+#[async_trait::async_trait]
+impl crate::stats::StatsStore for FakePersistence {
+    async fn record_download(
+        &self,
+        _package: &libwally::package_id::PackageId,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn package_downloads(
+        &self,
+        _name: &libwally::package_name::PackageName,
+    ) -> anyhow::Result<std::collections::BTreeMap<semver::Version, u64>> {
+        Ok(Default::default())
+    }
+
+    async fn scope_downloads(
+        &self,
+        _scope: &str,
+    ) -> anyhow::Result<std::collections::BTreeMap<libwally::package_id::PackageId, u64>> {
+        Ok(Default::default())
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::persistence::Persistence for FakePersistence {
+    async fn record_audit(&self, event: &AuditEvent) -> anyhow::Result<()> {
+        self.events.lock().unwrap().push(event.clone());
+        Ok(())
+    }
+
+    async fn scope_audit_events(
+        &self,
+        _scope: &str,
+        _before: Option<u64>,
+        _limit: usize,
+    ) -> anyhow::Result<Vec<crate::audit::StoredAuditEvent>> {
+        Ok(Vec::new())
+    }
+
+    async fn load_blocklist(&self) -> anyhow::Result<crate::blocklist::BlocklistEntries> {
+        Ok(Default::default())
+    }
+
+    async fn save_blocklist(
+        &self,
+        _entries: &crate::blocklist::BlocklistEntries,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[rocket::async_test]
+async fn audit_events_are_persisted() {
+    let persistence = Arc::new(FakePersistence::default());
+
+    // Events are persisted even when the audit log itself is disabled, and
+    // before recording them returns.
+    let audit = AuditLog::disabled().persist_to(persistence.clone());
+    audit
+        .record(&AuditEvent::allowed(
+            &WriteAccess::ApiKey,
+            &"biff/hello@1.0.0".parse().unwrap(),
+            WritePermission::ApiKey,
+        ))
+        .await
+        .unwrap();
+
+    let events = persistence.events.lock().unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].package.to_string(), "biff/hello@1.0.0");
+    assert_eq!(events[0].outcome, AuditOutcome::Allowed);
+}
+
+#[test]
+fn scope_owners() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+
+    let change_owners = |body: serde_json::Value| {
+        client
+            .post("/v1/scope-owners")
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", "Bearer hello"))
+            .body(body.to_string())
+            .dispatch()
+    };
+
+    let response = change_owners(serde_json::json!({ "scope": "biff", "add": [1, 2] }));
+    assert_eq!(response.status(), Status::Ok);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["owners"], serde_json::json!([1, 2]));
+
+    let response = change_owners(serde_json::json!({ "scope": "biff", "remove": [1, 2] }));
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let response = change_owners(serde_json::json!({ "scope": "biff", "add": [3], "remove": [1] }));
+    assert_eq!(response.status(), Status::Ok);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["owners"], serde_json::json!([2, 3]));
+
+    let response = change_owners(serde_json::json!({ "scope": "Not A Scope", "add": [1] }));
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[test]
+fn list_scope_owners() {
+    let index_url = init_test_index_remote().unwrap();
+    let index = PackageIndex::new_temp(&index_url, None).unwrap();
+    index.add_scope_owner("biff", &OwnerId::new(1)).unwrap();
+    index.add_scope_owner("biff", &OwnerId::new(2)).unwrap();
+
+    let client = new_client_with_remote(AuthMode::ApiKey("hello".into()), index_url);
+    let list_owners = |scope: &str| {
+        client
+            .get(format!("/v1/scope/{}/owners", scope))
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch()
+    };
+
+    // Logins are only looked up when users sign in with GitHub.
+    let response = list_owners("Biff");
+    assert_eq!(response.status(), Status::Ok);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(
+        body["owners"],
+        serde_json::json!([{ "id": 1, "login": null }, { "id": 2, "login": null }])
+    );
+
+    // Nobody has claimed this scope yet.
+    let response = list_owners("unclaimed");
+    assert_eq!(response.status(), Status::Ok);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["owners"], serde_json::json!([]));
+
+    assert_eq!(list_owners("Not A Scope").status(), Status::BadRequest);
+}
+
+#[test]
+fn list_scope_packages() {
+    let index_url = init_test_index_remote().unwrap();
+    let index = PackageIndex::new_temp(&index_url, None).unwrap();
+    index.add_scope_owner("empty", &OwnerId::new(1)).unwrap();
+
+    let client = new_client_with_remote(AuthMode::ApiKey("hello".into()), index_url);
+    publish_versions(&client, "biff/hello", &["1.0.0", "1.1.0"]);
+    publish_versions(&client, "biff/minimal", &["0.1.0"]);
+    publish_versions(&client, "other/hello", &["2.0.0"]);
+
+    let list_packages = |path: &str| {
+        client
+            .get(format!("/v1/scope/{}", path))
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch()
+    };
+
+    let response = list_packages("Biff/packages");
+    assert_eq!(response.status(), Status::Ok);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["total"], 2);
+    assert_eq!(
+        body["packages"],
+        serde_json::json!([
+            { "name": "hello", "version": "1.1.0" },
+            { "name": "minimal", "version": "0.1.0" },
+        ])
+    );
+
+    let body: serde_json::Value = list_packages("biff/packages?limit=1&offset=1")
+        .into_json()
+        .unwrap();
+    assert_eq!(body["total"], 2);
+    assert_eq!(
+        body["packages"],
+        serde_json::json!([{ "name": "minimal", "version": "0.1.0" }])
+    );
+
+    // A scope that's been claimed but has nothing published is just empty.
+    let response = list_packages("empty/packages");
+    assert_eq!(response.status(), Status::Ok);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["packages"], serde_json::json!([]));
+
+    let response = list_packages("nobody/packages");
+    assert_eq!(response.status(), Status::NotFound);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["code"], "scope_not_found");
+}
+
+#[test]
+fn scopes_ignore_case() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    publish_versions(&client, "foobar/hello", &["1.0.0"]);
+
+    let change_owners = |body: serde_json::Value| {
+        let response = client
+            .post("/v1/scope-owners")
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", "Bearer hello"))
+            .body(body.to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let body: serde_json::Value = response.into_json().unwrap();
+        body["owners"].clone()
+    };
+
+    assert_eq!(
+        change_owners(serde_json::json!({ "scope": "FooBar", "add": [1] })),
+        serde_json::json!([1])
+    );
+    assert_eq!(
+        change_owners(serde_json::json!({ "scope": "foobar", "add": [2] })),
+        serde_json::json!([1, 2])
+    );
+
+    let get_metadata = |path: &str| {
+        client
+            .get(path.to_owned())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch()
+    };
+    assert_eq!(
+        get_metadata("/v1/package-metadata/FooBar/Hello").status(),
+        Status::Ok
+    );
+
+    let response = get_metadata("/v1/package-metadata/foo_bar/hello");
+    assert_eq!(response.status(), Status::BadRequest);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["code"], "invalid_package_name");
+
+    let remote = init_test_index_remote().unwrap();
+    let index = PackageIndex::new_temp(&remote, None).unwrap();
+    index.add_scope_owner("FooBar", &OwnerId::new(1)).unwrap();
+    assert_eq!(
+        index.get_scope_owners("foobar").unwrap(),
+        vec![OwnerId::new(1)]
+    );
+    assert_eq!(
+        index.get_scope_owners("FooBar").unwrap(),
+        index.get_scope_owners("foobar").unwrap()
+    );
+    assert!(index.is_scope_owner("FOOBAR", &OwnerId::new(1)).unwrap());
+
+    // A login only has to match an unowned scope when both are lowercased.
+    let github_user = WriteAccess::Github {
+        info: serde_json::from_value(serde_json::json!({ "login": "Biff", "id": 3 })).unwrap(),
+        permission: None,
+    };
+    assert!(futures::executor::block_on(github_user.can_write_scope(
+        "biff",
+        &index,
+        &FakeTeams(&[]),
+        &Default::default()
+    ))
+    .unwrap());
+}
+
+#[test]
+fn change_scope_owners() {
+    use crate::change_scope_owners;
+
+    let ids = |ids: &[u64]| -> Vec<OwnerId> { ids.iter().copied().map(OwnerId::new).collect() };
+    let user = OwnerId::new(1);
+
+    // Owners can hand the scope over to someone else.
+    let owners = change_scope_owners(
+        "biff",
+        ids(&[1]),
+        Some(WritePermission::Owner),
+        Some(&user),
+        &ids(&[2]),
+        &ids(&[1]),
+    );
+    assert_eq!(owners.unwrap(), ids(&[2]));
+
+    // Someone claiming an empty scope becomes its first owner.
+    let owners = change_scope_owners(
+        "biff",
+        ids(&[]),
+        Some(WritePermission::Bootstrap),
+        Some(&user),
+        &ids(&[2]),
+        &ids(&[]),
+    );
+    assert_eq!(owners.unwrap(), ids(&[1, 2]));
+
+    // But not if someone else claimed it first.
+    change_scope_owners(
+        "biff",
+        ids(&[3]),
+        Some(WritePermission::Bootstrap),
+        Some(&user),
+        &ids(&[]),
+        &ids(&[]),
+    )
+    .unwrap_err();
+
+    // Team members can publish, but not change who owns the scope.
+    change_scope_owners(
+        "biff",
+        ids(&[3]),
+        Some(WritePermission::Team(String::from("biff-org/publishers"))),
+        Some(&user),
+        &ids(&[1]),
+        &ids(&[]),
+    )
+    .unwrap_err();
+
+    change_scope_owners("biff", ids(&[3]), None, Some(&user), &ids(&[1]), &ids(&[])).unwrap_err();
+
+    // A user with the same id on another GitHub host is someone else.
+    change_scope_owners(
+        "biff",
+        vec![OwnerId::with_provider("enterprise", 1)],
+        Some(WritePermission::Owner),
+        Some(&user),
+        &ids(&[2]),
+        &ids(&[]),
+    )
+    .unwrap_err();
+}
+
+#[cfg(feature = "s3-storage")]
+#[test]
+fn s3_storage_config() {
+    use rusoto_core::Region;
+
+    use crate::storage::s3_region;
+
+    let storage: StorageMode = serde_json::from_value(serde_json::json!({
+        "type": "s3",
+        "bucket": "wally",
+        "endpoint": "http://localhost:9000",
+        "credentials": {
+            "access-key-id": "minio",
+            "secret-access-key": "minio-secret",
+        },
+    }))
+    .unwrap();
+
+    let debug = format!("{:?}", storage);
+    assert!(debug.contains("minio"));
+    assert!(!debug.contains("minio-secret"));
+
+    assert_eq!(
+        s3_region(None, Some(String::from("http://localhost:9000"))).unwrap(),
+        Region::Custom {
+            name: String::from("us-east-1"),
+            endpoint: String::from("http://localhost:9000"),
+        }
+    );
+    assert_eq!(
+        s3_region(Some(String::from("eu-west-2")), None).unwrap(),
+        Region::EuWest2
+    );
+    s3_region(Some(String::from("not-a-region")), None).unwrap_err();
+}
+
+#[cfg(feature = "s3-storage")]
+#[test]
+fn s3_presigned_urls() {
+    use rusoto_core::{credential::StaticProvider, request::HttpClient, Region};
+    use rusoto_s3::S3Client;
+
+    use crate::storage::{S3Storage, StorageBackend};
+
+    let region = Region::Custom {
+        name: String::from("us-east-1"),
+        endpoint: String::from("http://localhost:9000"),
+    };
+    let provider = StaticProvider::new_minimal(String::from("minio"), String::from("secret"));
+    let client = S3Client::new_with(HttpClient::new().unwrap(), provider.clone(), region.clone());
+    let storage = S3Storage::new(client, String::from("wally"), None);
+    let package_id = "biff/hello@1.0.0".parse().unwrap();
+    let ttl = Duration::from_secs(300);
+
+    assert_eq!(
+        futures::executor::block_on(storage.presigned_url(&package_id, ttl)).unwrap(),
+        None
+    );
+
+    let storage = storage.with_presigning(region, provider);
+    let url = futures::executor::block_on(storage.presigned_url(&package_id, ttl))
+        .unwrap()
+        .unwrap();
+
+    assert!(
+        url.starts_with("http://localhost:9000/wally/biff/hello"),
+        "{}",
+        url
+    );
+    assert!(url.contains("X-Amz-Expires=300"), "{}", url);
+    assert!(!url.contains("secret"), "{}", url);
+}
+
+fn search_request(client: &Client, query: &str) -> (Status, serde_json::Value) {
+    let response = client.get(format!("/v1/search?{}", query)).dispatch();
+    let status = response.status();
+
+    (status, response.into_json().unwrap())
+}
+
+fn publish_described(client: &Client, id: &str, description: &str) {
+    let contents = PackageBuilder::new(id)
+        .with_description(description)
+        .contents();
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .body(contents.data())
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[test]
+fn search_packages() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    publish_described(&client, "biff/roact@1.0.0", "A declarative UI library");
+    publish_described(&client, "biff/roact@1.1.0", "A declarative UI library");
+    publish_described(&client, "biff/roact-hooks@0.1.0", "Hooks for Roact");
+    publish_described(
+        &client,
+        "biff/fusion@0.2.0",
+        "Another UI library, unlike roact",
+    );
+
+    let (status, body) = search_request(&client, "q=ROACT");
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["total"], 3);
+
+    let results = body["results"].as_array().unwrap();
+    let names: Vec<&str> = results
+        .iter()
+        .map(|result| result["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, vec!["roact", "roact-hooks", "fusion"]);
+    assert_eq!(results[0]["scope"], "biff");
+    assert_eq!(results[0]["version"], "1.1.0");
+    assert_eq!(results[0]["description"], "A declarative UI library");
+
+    let (status, body) = search_request(&client, "q=nothing-like-this");
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["total"], 0);
+    assert_eq!(body["results"], serde_json::json!([]));
+}
+
+#[test]
+fn search_requires_query() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+
+    let (status, _) = search_request(&client, "q=");
+    assert_eq!(status, Status::BadRequest);
+
+    let (status, _) = search_request(&client, "q=%20%20");
+    assert_eq!(status, Status::BadRequest);
+
+    let (status, _) = search_request(&client, "limit=5");
+    assert_eq!(status, Status::BadRequest);
+}
+
+#[test]
+fn search_pagination() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    for name in ["alpha", "bravo", "charlie"] {
+        publish_described(&client, &format!("biff/{}@1.0.0", name), "a test package");
+    }
+
+    let page = |query: &str| {
+        let (status, body) = search_request(&client, query);
+        assert_eq!(status, Status::Ok);
+        assert_eq!(body["total"], 3);
+
+        body["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| result["name"].as_str().unwrap().to_owned())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(page("q=test&limit=2"), vec!["alpha", "bravo"]);
+    assert_eq!(page("q=test&limit=2&offset=2"), vec!["charlie"]);
+    assert_eq!(page("q=test&offset=3"), Vec::<String>::new());
+    assert_eq!(page("q=test&offset=100"), Vec::<String>::new());
+
+    // A limit of 0 still returns at least one result.
+    assert_eq!(page("q=test&limit=0"), vec!["alpha"]);
+}
+
+#[test]
+fn search_cursors() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    for name in ["alpha", "charlie", "delta"] {
+        publish_described(&client, &format!("biff/{}@1.0.0", name), "a test package");
+    }
+
+    let (status, body) = search_request(&client, "q=test&limit=2");
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["results"].as_array().unwrap().len(), 2);
+    let cursor = body["next_cursor"].as_str().unwrap().to_owned();
+
+    // Packages published before the cursor's position don't shift the next
+    // page, the way they would with an offset.
+    publish_described(&client, "biff/bravo@1.0.0", "a test package");
+
+    let (status, body) = search_request(&client, &format!("q=test&limit=2&cursor={}", cursor));
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["total"], 4);
+    assert_eq!(body["results"].as_array().unwrap().len(), 1);
+    assert_eq!(body["results"][0]["name"], "delta");
+    assert_eq!(body["next_cursor"], serde_json::Value::Null);
+
+    // The cursor is only good for the search it came from.
+    let (status, body) = search_request(&client, &format!("q=package&cursor={}", cursor));
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(body["code"], "invalid_cursor");
+
+    let (payload, signature) = cursor.split_once('.').unwrap();
+    let tampered = format!("{}.{}", payload, &signature[1..]);
+    for cursor in [tampered.as_str(), "not-a-cursor", "bm90.anNvbg"] {
+        let (status, body) = search_request(&client, &format!("q=test&cursor={}", cursor));
+        assert_eq!(status, Status::BadRequest);
+        assert_eq!(body["code"], "invalid_cursor");
+    }
+
+    let (status, _) = search_request(&client, &format!("q=test&offset=1&cursor={}", cursor));
+    assert_eq!(status, Status::BadRequest);
+}
+
+#[test]
+fn stream_listings() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    for name in ["alpha", "bravo", "charlie"] {
+        publish_described(&client, &format!("biff/{}@1.0.0", name), "a test package");
+    }
+
+    let stream = |path: &str| {
+        let response = client
+            .get(path.to_owned())
+            .header(Header::new("Accept", "application/x-ndjson"))
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.content_type(),
+            Some(ContentType::new("application", "x-ndjson"))
+        );
+
+        response
+            .into_string()
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect::<Vec<serde_json::Value>>()
+    };
+
+    // Streams aren't limited to a page unless a limit is given.
+    let results = stream("/v1/search?q=test");
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0]["name"], "alpha");
+
+    let packages = stream("/v1/scope/biff/packages?limit=2");
+    assert_eq!(
+        packages,
+        vec![
+            serde_json::json!({ "name": "alpha", "version": "1.0.0" }),
+            serde_json::json!({ "name": "bravo", "version": "1.0.0" }),
+        ]
+    );
+
+    let body: serde_json::Value = client
+        .get("/v1/scope/biff/packages?limit=1")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch()
+        .into_json()
+        .unwrap();
+    let cursor = body["next_cursor"].as_str().unwrap();
+
+    let packages = stream(&format!("/v1/scope/biff/packages?cursor={}", cursor));
+    assert_eq!(packages.len(), 2);
+    assert_eq!(packages[0]["name"], "bravo");
+}
+
+#[test]
+fn metrics() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    publish_versions(&client, "biff/hello", &["1.0.0"]);
+
+    let response = client
+        .get("/v1/package-contents/biff/hello/1.0.0")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = client
+        .get("/v1/package-contents/biff/hello/1.0.0")
+        .header(Header::new("Authorization", "Bearer wrong"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    let response = client.get("/metrics").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let metrics = response.into_string().unwrap();
+
+    for line in [
+        "wally_requests_total{route=\"publish\",status=\"200\"} 1",
+        "wally_requests_total{route=\"package_contents\",status=\"200\"} 1",
+        "wally_requests_total{route=\"package_contents\",status=\"401\"} 1",
+        "wally_auth_total{mode=\"api-key\",outcome=\"success\"} 2",
+        "wally_auth_total{mode=\"api-key\",outcome=\"failure\"} 1",
+        "wally_publishes_total 1",
+        "wally_downloads_total 1",
+    ] {
+        assert!(
+            metrics.lines().any(|l| l == line),
+            "missing {}:\n{}",
+            line,
+            metrics
+        );
+    }
+}
+
+#[test]
+fn metrics_on_separate_address() {
+    let mut config = test_config(AuthMode::Unauthenticated, init_test_index_remote().unwrap());
+    config.metrics_address = Some("127.0.0.1:0".parse().unwrap());
+    let client = new_client_with_config(config);
+
+    let response = client.get("/metrics").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+fn github_call_histogram() {
+    use crate::metrics::Metrics;
+
+    let metrics = Metrics::new();
+    metrics.observe_github_call("user", Duration::from_millis(80));
+    metrics.observe_github_call("user", Duration::from_secs(20));
+
+    let rendered = metrics.render();
+    for line in [
+        "wally_github_request_duration_seconds_bucket{call=\"user\",le=\"0.05\"} 0",
+        "wally_github_request_duration_seconds_bucket{call=\"user\",le=\"0.1\"} 1",
+        "wally_github_request_duration_seconds_bucket{call=\"user\",le=\"10\"} 1",
+        "wally_github_request_duration_seconds_bucket{call=\"user\",le=\"+Inf\"} 2",
+        "wally_github_request_duration_seconds_count{call=\"user\"} 2",
+    ] {
+        assert!(
+            rendered.lines().any(|l| l == line),
+            "missing {}:\n{}",
+            line,
+            rendered
+        );
+    }
+}
+
+#[test]
+fn health() {
+    let remote = init_test_index_remote().unwrap();
+    let client = new_client_with_remote(AuthMode::Unauthenticated, remote.clone());
+
+    let response = client.get("/health").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["status"], "ok");
+    assert_eq!(body["checks"]["index"]["status"], "ok");
+    assert_eq!(body["checks"]["github"]["status"], "skipped");
+
+    // Losing the index's remote makes the registry unhealthy.
+    fs_err::remove_dir_all(remote.to_file_path().unwrap()).unwrap();
+
+    let response = client.get("/health").dispatch();
+    assert_eq!(response.status(), Status::ServiceUnavailable);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["status"], "unavailable");
+    assert_eq!(body["checks"]["index"]["status"], "failed");
+}
+
+#[test]
+fn github_health_is_cached() {
+    use crate::health::{CheckStatus, GithubHealth};
+
+    let clock = Arc::new(FakeClock(Mutex::new(Instant::now())));
+    let github = GithubHealth::with_clock(Duration::from_secs(30), clock.clone());
+    assert_eq!(github.cached(), None);
+
+    github.store(CheckStatus::Failed(String::from("GitHub is down")));
+    assert_eq!(
+        github.cached(),
+        Some(CheckStatus::Failed(String::from("GitHub is down")))
+    );
+
+    *clock.0.lock().unwrap() += Duration::from_secs(31);
+    assert_eq!(github.cached(), None);
+}
+
+#[test]
+fn publish_version_checks_manifest() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+
+    let publish = |url: &str, package: &str| {
+        let contents = PackageBuilder::new(package).contents();
+        client
+            .post(url.to_owned())
+            .header(Accept::JSON)
+            .body(contents.data())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch()
+    };
+
+    let response = publish("/v1/publish/biff/hello/1.0.0", "biff/goodbye@1.0.0");
+    assert_eq!(response.status(), Status::BadRequest);
+    let message = response.into_string().unwrap();
+    assert!(message.contains("declares package biff/goodbye, but biff/hello"));
+
+    let response = publish("/v1/publish/biff/hello/1.0.0", "biff/hello@1.0.1");
+    assert_eq!(response.status(), Status::BadRequest);
+    let message = response.into_string().unwrap();
+    assert!(message.contains("declares version 1.0.1 of biff/hello, but version 1.0.0"));
+
+    // Nothing should have been published by the rejected uploads.
+    let response = client
+        .get("/v1/package-contents/biff/hello/1.0.0")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    let response = publish("/v1/publish/biff/hello/1.0.0", "biff/hello@1.0.0");
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = publish("/v1/publish/biff/hello/1.0.0", "biff/hello@1.0.0");
+    assert_eq!(response.status(), Status::Conflict);
+}
+
+#[test]
+fn error_codes() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    publish_versions(&client, "biff/hello", &["1.0.0"]);
+
+    let error_code = |response: LocalResponse<'_>| {
+        let body: serde_json::Value = response.into_json().unwrap();
+        body["code"].as_str().unwrap().to_owned()
+    };
+
+    let publish = |package: &str, key: &str| {
+        let contents = PackageBuilder::new(package).contents();
+        client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .body(contents.data())
+            .header(Header::new("Authorization", format!("Bearer {}", key)))
+            .dispatch()
+    };
+
+    assert_eq!(
+        error_code(publish("biff/hello@1.0.0", "hello")),
+        "version_exists"
+    );
+    assert_eq!(
+        error_code(publish("biff/hello@1.0.1", "wrong")),
+        "invalid_api_key"
+    );
+
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .body("not a zip")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(error_code(response), "invalid_archive");
+
+    let response = client
+        .get("/v1/package-metadata/biff/missing")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(error_code(response), "package_not_found");
+
+    let response = client.get("/v1/package-metadata/biff/hello").dispatch();
+    assert_eq!(error_code(response), "auth_required");
+}
+
+#[test]
+fn request_ids() {
+    let client = new_client(AuthMode::Unauthenticated);
+
+    let request_id = |incoming: Option<&str>| {
+        let mut request = client.get("/");
+        if let Some(incoming) = incoming {
+            request = request.header(Header::new("X-Request-Id", incoming.to_owned()));
+        }
+        let response = request.dispatch();
+        response
+            .headers()
+            .get_one("X-Request-Id")
+            .unwrap()
+            .to_owned()
+    };
+
+    let first = request_id(None);
+    assert_ne!(first, request_id(None));
+
+    // Ids from a proxy are kept, unless they aren't safe to log.
+    assert_eq!(request_id(Some("lb-1234")), "lb-1234");
+    assert_ne!(request_id(Some("has spaces")), "has spaces");
+}
+
+#[test]
+fn invalid_log_level() {
+    let index_url = init_test_index_remote().unwrap();
+    let mut config = test_config(AuthMode::Unauthenticated, index_url);
+    config.log.level = "wally=verbose".to_owned();
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn cors() {
+    let mut config = test_config(AuthMode::Unauthenticated, init_test_index_remote().unwrap());
+    config.cors_origins = vec![String::from("https://wally.example.com")];
+    let client = new_client_with_config(config);
+
+    let response = client
+        .options("/v1/publish")
+        .header(Header::new("Origin", "https://wally.example.com"))
+        .header(Header::new("Access-Control-Request-Method", "POST"))
+        .dispatch();
+    assert_eq!(response.status(), Status::NoContent);
+
+    let headers = response.headers();
+    assert_eq!(
+        headers.get_one("Access-Control-Allow-Origin"),
+        Some("https://wally.example.com")
+    );
+    assert_eq!(
+        headers.get_one("Access-Control-Allow-Credentials"),
+        Some("true")
+    );
+    assert!(headers
+        .get_one("Access-Control-Allow-Methods")
+        .unwrap()
+        .contains("POST"));
+    assert!(headers
+        .get_one("Access-Control-Allow-Headers")
+        .unwrap()
+        .contains("Authorization"));
+
+    // Origins are matched exactly, never reflected.
+    for origin in &[
+        "https://evil.example.com",
+        "https://wally.example.com.evil.com",
+        "http://wally.example.com",
+    ] {
+        let response = client
+            .get("/")
+            .header(Header::new("Origin", origin.to_string()))
+            .dispatch();
+        assert_eq!(
+            response.headers().get_one("Access-Control-Allow-Origin"),
+            None
+        );
+    }
+}
+
+#[test]
+fn cors_any_origin() {
+    let mut config = test_config(AuthMode::Unauthenticated, init_test_index_remote().unwrap());
+    config.cors_origins = vec![String::from("*")];
+    let client = new_client_with_config(config);
+
+    let response = client
+        .get("/")
+        .header(Header::new("Origin", "https://anywhere.example.com"))
+        .dispatch();
+    let headers = response.headers();
+    assert_eq!(headers.get_one("Access-Control-Allow-Origin"), Some("*"));
+    assert_eq!(headers.get_one("Access-Control-Allow-Credentials"), None);
+}
+
+#[test]
+fn cors_disabled_by_default() {
+    let client = new_client(AuthMode::Unauthenticated);
+
+    let response = client
+        .get("/")
+        .header(Header::new("Origin", "https://wally.example.com"))
+        .dispatch();
+    assert_eq!(
+        response.headers().get_one("Access-Control-Allow-Origin"),
+        None
+    );
+}
+
+#[test]
+fn cors_origins_are_validated() {
+    let mut config = test_config(AuthMode::Unauthenticated, init_test_index_remote().unwrap());
+
+    config.cors_origins = vec![String::from("https://wally.example.com/")];
+    assert!(config.validate().is_err());
+
+    config.cors_origins = vec![
+        String::from("https://wally.example.com"),
+        String::from("http://localhost:3000"),
+        String::from("*"),
+    ];
+    config.validate().unwrap();
+}
+
+/// Answers one HTTP request for each of `statuses`, in order, and returns the
+/// head and body of every request it received.
+fn mock_server(statuses: Vec<u16>) -> (url::Url, std::thread::JoinHandle<Vec<(String, Vec<u8>)>>) {
+    let responses = statuses
+        .into_iter()
+        .map(|status| (status, Vec::new(), String::new()))
+        .collect();
+
+    mock_server_responses(responses)
+}
+
+/// Like `mock_server`, but answers with the given status, headers, and body.
+fn mock_server_responses(
+    responses: Vec<(u16, Vec<(&'static str, &'static str)>, String)>,
+) -> (url::Url, std::thread::JoinHandle<Vec<(String, Vec<u8>)>>) {
+    use std::io::{Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap())
+        .parse()
+        .unwrap();
+
+    let handle = std::thread::spawn(move || {
+        let mut requests = Vec::new();
+
+        for (status, headers, body) in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            let mut buffer = [0; 4096];
+
+            let head_end = loop {
+                let read = stream.read(&mut buffer).unwrap();
+                received.extend_from_slice(&buffer[..read]);
+
+                if let Some(end) = received.windows(4).position(|window| window == b"\r\n\r\n") {
+                    break end + 4;
+                }
+            };
+
+            let head = String::from_utf8(received[..head_end].to_vec()).unwrap();
+            let length: usize = head
+                .lines()
+                .find_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    name.eq_ignore_ascii_case("content-length")
+                        .then(|| value.trim().parse().unwrap())
+                })
+                .unwrap_or(0);
+
+            while received.len() < head_end + length {
+                let read = stream.read(&mut buffer).unwrap();
+                received.extend_from_slice(&buffer[..read]);
+            }
+
+            let headers: String = headers
+                .iter()
+                .map(|(name, value)| format!("{}: {}\r\n", name, value))
+                .collect();
+
+            write!(
+                stream,
+                "HTTP/1.1 {} Webhook\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                headers,
+                body.len(),
+                body
+            )
+            .unwrap();
+
+            requests.push((head, received[head_end..].to_vec()));
+        }
+
+        requests
+    });
+
+    (url, handle)
+}
+
+fn publish_event() -> PublishEvent {
+    let contents = PackageBuilder::new("biff/hello@1.0.0").contents();
+    PublishEvent::new(&WriteAccess::ApiKey, &contents.manifest().unwrap())
+}
+
+#[test]
+fn webhook_signature() {
+    // Test case 2 from RFC 4231.
+    assert_eq!(
+        sign("Jefe", b"what do ya want for nothing?"),
+        "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+}
+
+#[test]
+fn webhook_retries() {
+    let (url, receiver) = mock_server(vec![500, 503, 200]);
+    let webhooks = Webhooks::with_backoff(
+        &WebhookConfig {
+            urls: vec![url],
+            secret: Some(String::from("webhook-secret")),
+        },
+        Default::default(),
+        Duration::from_millis(10),
+    )
+    .unwrap();
+
+    let runtime = rocket::tokio::runtime::Runtime::new().unwrap();
+    let results = runtime.block_on(webhooks.deliver_all(&publish_event()));
+    assert_eq!(results.len(), 1);
+    results[0].as_ref().unwrap();
+
+    let requests = receiver.join().unwrap();
+    assert_eq!(requests.len(), 3);
+
+    let (head, body) = &requests[2];
+    let event: PublishEvent = serde_json::from_slice(body).unwrap();
+    assert_eq!(event.event, "publish");
+    assert_eq!(event.package.to_string(), "biff/hello@1.0.0");
+    assert_eq!(event.actor, "api-key");
+    assert_eq!(event.user_id, None);
+
+    let signature = format!(
+        "{}: {}",
+        SIGNATURE_HEADER.to_lowercase(),
+        sign("webhook-secret", body)
+    );
+    assert!(head.to_lowercase().contains(&signature));
+}
+
+#[test]
+fn webhook_gives_up() {
+    let (url, receiver) = mock_server(vec![500, 500, 500]);
+    let webhooks = Webhooks::with_backoff(
+        &WebhookConfig {
+            urls: vec![url],
+            secret: None,
+        },
+        Default::default(),
+        Duration::from_millis(10),
+    )
+    .unwrap();
+
+    let runtime = rocket::tokio::runtime::Runtime::new().unwrap();
+    let results = runtime.block_on(webhooks.deliver_all(&publish_event()));
+    assert!(results[0].is_err());
+
+    let requests = receiver.join().unwrap();
+    assert_eq!(requests.len(), 3);
+    assert!(!requests[0]
+        .0
+        .to_lowercase()
+        .contains(&SIGNATURE_HEADER.to_lowercase()));
+}
+
+#[test]
+fn github_api_url() {
+    let mut config = test_config(
+        AuthMode::Unauthenticated,
+        "https://github.mycorp.com/games/index".parse().unwrap(),
+    );
+    assert_eq!(config.github_api_base(), "https://github.mycorp.com/api/v3");
+
+    config.index_url = "https://github.com/UpliftGames/wally-index"
+        .parse()
+        .unwrap();
+    assert_eq!(config.github_api_base(), "https://api.github.com");
+
+    config.github_api_url = Some("https://github.example.com/api/v3/".parse().unwrap());
+    assert_eq!(
+        config.github_api_base(),
+        "https://github.example.com/api/v3"
+    );
+}
+
+#[test]
+fn github_calls_use_api_url() {
+    let (url, server) = mock_server(vec![200]);
+    let mut config = test_config(
+        AuthMode::GithubOAuth {
+            client_id: String::from("client-id"),
+            client_secret: String::from("client-secret"),
+        },
+        init_test_index_remote().unwrap(),
+    );
+    config.github_api_url = Some(url.join("api/v3/").unwrap());
+    let client = new_client_with_config(config);
+
+    // The mock answers with an empty body, which isn't a GitHub user.
+    let response = client
+        .get("/v1/whoami?write=true")
+        .header(Header::new("Authorization", "Bearer gho_token"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    let requests = server.join().unwrap();
+    assert!(
+        requests[0].0.starts_with("GET /api/v3/user HTTP/1.1"),
+        "{}",
+        requests[0].0
+    );
+}
+
+/// Users who sign in through another host still have their permissions
+/// checked on the index's repository, on the main host.
+#[test]
+fn github_permission_checks_use_main_host() {
+    use crate::auth::index_repo_api;
+
+    let mut config = test_config(
+        AuthMode::GithubOAuthPrivate {
+            client_id: String::from("client-id"),
+            client_secret: String::from("client-secret"),
+        },
+        "https://github.mycorp.com/games/index".parse().unwrap(),
+    );
+    config.github_token = Some(String::from("token"));
+    config.github_providers = vec![GithubProvider {
+        name: String::from("enterprise"),
+        api_url: "https://github.example.com/api/v3/".parse().unwrap(),
+        client_id: String::from("enterprise-client"),
+        client_secret: String::from("enterprise-secret"),
+    }];
+
+    let (api_base, repo_api) = index_repo_api(&config).unwrap();
+    assert_eq!(api_base, "https://github.mycorp.com/api/v3");
+    assert_eq!(
+        repo_api,
+        "https://github.mycorp.com/api/v3/repos/games/index"
+    );
+    assert!(!repo_api.contains("github.example.com"));
+}
+
+#[test]
+fn github_providers() {
+    let enterprise = |url: url::Url, client_id: &str| GithubProvider {
+        name: String::from("enterprise"),
+        api_url: url.join("api/v3/").unwrap(),
+        client_id: client_id.to_owned(),
+        client_secret: String::from("enterprise-secret"),
+    };
+
+    // The token isn't one github.com knows, so it's tried on the next host.
+    let (main_url, main_server) = mock_server(vec![401]);
+    let (enterprise_url, enterprise_server) = mock_server_responses(vec![
+        (
+            200,
+            Vec::new(),
+            String::from(r#"{ "login": "biff", "id": 1 }"#),
+        ),
+        (
+            200,
+            Vec::new(),
+            String::from(r#"{ "id": 1, "app": { "client_id": "enterprise-client" } }"#),
+        ),
+    ]);
+
+    let mut config = test_config(
+        AuthMode::GithubOAuth {
+            client_id: String::from("client-id"),
+            client_secret: String::from("client-secret"),
+        },
+        init_test_index_remote().unwrap(),
+    );
+    config.github_api_url = Some(main_url);
+    config.github_providers = vec![enterprise(enterprise_url, "enterprise-client")];
+    config.validate().unwrap();
+    let client = new_client_with_config(config);
+
+    let response = client
+        .get("/v1/whoami?write=true")
+        .header(Header::new("Authorization", "Bearer ghu_token"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["login"], "biff");
+    assert_eq!(body["id"], 1);
+    assert_eq!(body["provider"], "enterprise");
+
+    assert_eq!(main_server.join().unwrap().len(), 1);
+    let requests = enterprise_server.join().unwrap();
+    assert!(requests[0].0.starts_with("GET /api/v3/user HTTP/1.1"));
+    assert!(requests[1]
+        .0
+        .starts_with("POST /api/v3/applications/enterprise-client/token HTTP/1.1"));
+
+    // Provider names end up in the index, so they have to be unambiguous.
+    let mut config = test_config(
+        AuthMode::GithubOAuth {
+            client_id: String::from("client-id"),
+            client_secret: String::from("client-secret"),
+        },
+        init_test_index_remote().unwrap(),
+    );
+    let url: url::Url = "https://github.example.com".parse().unwrap();
+    config.github_providers = vec![
+        enterprise(url.clone(), "one"),
+        enterprise(url.clone(), "two"),
+    ];
+    let err = config.validate().unwrap_err();
+    assert!(err.to_string().contains("more than once"), "{}", err);
+
+    // Private registries check permissions on the main host, which users
+    // from other hosts can't have.
+    let mut config = test_config(
+        AuthMode::GithubOAuthPrivate {
+            client_id: String::from("client-id"),
+            client_secret: String::from("client-secret"),
+        },
+        "https://github.com/UpliftGames/wally-index"
+            .parse()
+            .unwrap(),
+    );
+    config.github_token = Some(String::from("token"));
+    config.github_providers = vec![enterprise(url, "one")];
+    let err = config.validate().unwrap_err();
+    assert!(err.to_string().contains("github-oauth-private"), "{}", err);
+}
+
+/// The public half of `src/tests/github-app/private-key.pem`, as a JWK.
+const OIDC_KEYS: &str = r#"{ "keys": [{ "kty": "RSA", "kid": "test", "alg": "RS256", "use": "sig", "e": "AQAB", "n": "3Y4zLcZDoNQsYv9E9FeRGcNCU8xP6ADFSNVxQ_5NoJJi7Ct0PDxlrP_d0VN4GDhWyD3dBRzm-XC8xs4rpuygqrHf7TsGNxbtmV0W8nDGoBb_WjlOpzqGDBMLMEcGZm6c5IcF0mUdGwdZCkDCv6OVfmRFY4bkJqQWGY-T_Ti_evPEoQWHAunziBkMPmhrYc_IA7TYC5Z2_CvxGGcG8ki9u0DtI1NHyJrJptl6I7oBkrwIpmA_z1Bwwh2fQsK-uB3mgHkVlRlDV6Pw4SVm4OMgHcOmTIqqcOBkLixekcYwLHKuipr3dH_TfcIuidz_SwkiDaJBHHeMVJWKDs_Jv5C3Tw" }] }"#;
+
+const GITHUB_ACTIONS_ISSUER: &str = "https://token.actions.githubusercontent.com";
+
+/// An OIDC token like GitHub Actions issues, for a job on `git_ref`.
+fn oidc_token(audience: &str, git_ref: &str) -> String {
+    oidc_token_with_kid(audience, git_ref, "test")
+}
+
+/// An OIDC token that says it was signed with the key `kid`, though it's
+/// always signed with the key in `OIDC_KEYS`.
+fn oidc_token_with_kid(audience: &str, git_ref: &str, kid: &str) -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let claims = serde_json::json!({
+        "iss": GITHUB_ACTIONS_ISSUER,
+        "aud": audience,
+        "sub": format!("repo:biff/hello:ref:{}", git_ref),
+        "repository": "biff/hello",
+        "ref": git_ref,
+        "iat": now,
+        "exp": now + 300,
+    });
+
+    let mut header = jsonwebtoken::Header::new(Algorithm::RS256);
+    header.kid = Some(String::from(kid));
+    let private_key = fs_err::read("src/tests/github-app/private-key.pem").unwrap();
+
+    jsonwebtoken::encode(
+        &header,
+        &claims,
+        &jsonwebtoken::EncodingKey::from_rsa_pem(&private_key).unwrap(),
+    )
+    .unwrap()
+}
+
+#[test]
+fn trusted_publishing() {
+    let (keys_url, keys_server) =
+        mock_server_responses(vec![(200, Vec::new(), String::from(OIDC_KEYS))]);
+
+    let mut config = test_config(
+        AuthMode::TrustedPublishing {
+            audience: String::from("wally"),
+            api_key: None,
+        },
+        init_test_index_remote().unwrap(),
+    );
+    config.trusted_publishers = vec![TrustedPublisher {
+        issuer: GITHUB_ACTIONS_ISSUER.parse().unwrap(),
+        jwks_url: Some(keys_url.join("jwks").unwrap()),
+        repository: String::from("biff/hello"),
+        git_ref: Some(String::from("refs/heads/main")),
+        scopes: vec![String::from("biff")],
+    }];
+    config.validate().unwrap();
+    let client = new_client_with_config(config);
+
+    let publish = |name: &str, token: &str| {
+        let contents = PackageBuilder::new(format!("{}@1.0.0", name)).contents();
+        client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .header(Header::new("Authorization", format!("Bearer {}", token)))
+            .body(contents.data())
+            .dispatch()
+    };
+
+    let token = oidc_token("wally", "refs/heads/main");
+    let response = client
+        .get("/v1/whoami?write=true")
+        .header(Header::new("Authorization", format!("Bearer {}", token)))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["type"], "trusted-publisher");
+    assert_eq!(body["repository"], "biff/hello");
+    assert_eq!(body["scopes"], serde_json::json!(["biff"]));
+
+    assert_eq!(publish("biff/hello", &token).status(), Status::Ok);
+
+    // The repository is only trusted with its own scopes.
+    assert_eq!(publish("mallory/hello", &token).status(), Status::Forbidden);
+
+    // Jobs on other branches aren't trusted.
+    let response = publish("biff/hello", &oidc_token("wally", "refs/heads/feature"));
+    assert_eq!(response.status(), Status::Forbidden);
+
+    // Nor are tokens meant for someone else.
+    let response = publish(
+        "biff/hello",
+        &oidc_token("somewhere-else", "refs/heads/main"),
+    );
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    // Without an `api-key`, there's nothing else to publish with.
+    assert_eq!(
+        publish("biff/hello", "hello").status(),
+        Status::Unauthorized
+    );
+
+    // The keys were just fetched, so a key id that isn't among them is
+    // rejected without fetching them again. The mock server only answers
+    // once, so fetching again would fail with 502 instead.
+    for _ in 0..3 {
+        let token = oidc_token_with_kid("wally", "refs/heads/main", "made-up");
+        let response = publish("biff/hello", &token);
+        assert_eq!(response.status(), Status::Unauthorized);
+        let body: serde_json::Value = response.into_json().unwrap();
+        assert_eq!(body["code"], "invalid_oidc_token");
+    }
+
+    // The keys were fetched once and then reused.
+    let requests = keys_server.join().unwrap();
+    assert_eq!(requests.len(), 1);
+    assert!(requests[0].0.starts_with("GET /jwks HTTP/1.1"));
+
+    // A trust policy needs something to trust.
+    let mut config = test_config(
+        AuthMode::TrustedPublishing {
+            audience: String::from("wally"),
+            api_key: None,
+        },
+        init_test_index_remote().unwrap(),
+    );
+    let err = config.validate().unwrap_err();
+    assert!(err.to_string().contains("trusted_publishers"), "{}", err);
+
+    config.trusted_publishers = vec![TrustedPublisher {
+        issuer: GITHUB_ACTIONS_ISSUER.parse().unwrap(),
+        jwks_url: None,
+        repository: String::from("biff/hello"),
+        git_ref: None,
+        scopes: Vec::new(),
+    }];
+    let err = config.validate().unwrap_err();
+    assert!(err.to_string().contains("at least one scope"), "{}", err);
+}
+
+#[test]
+fn github_rate_limit_backs_off() {
+    use crate::github_rate_limit::GithubRateLimit;
+    use crate::metrics::Metrics;
+    use reqwest::header::{HeaderMap, HeaderValue};
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let headers = |remaining: u64, reset: u64| {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-remaining", HeaderValue::from(remaining));
+        headers.insert("x-ratelimit-reset", HeaderValue::from(reset));
+        headers
+    };
+
+    let rate_limit = GithubRateLimit::new();
+    assert!(rate_limit.check().is_ok());
+
+    assert_eq!(rate_limit.observe("check-token", &HeaderMap::new()), None);
+    assert_eq!(
+        rate_limit.observe("check-token", &headers(4000, now + 600)),
+        Some(4000)
+    );
+    assert!(rate_limit.check().is_ok());
+
+    rate_limit.observe("check-token", &headers(3, now + 600));
+    let err = format!("{:?}", rate_limit.check().unwrap_err());
+    assert!(err.contains("github_rate_limited"), "{}", err);
+    assert!(err.contains("503"), "{}", err);
+
+    // Once the limit has reset, there's no reason to hold back.
+    rate_limit.observe("check-token", &headers(0, now - 1));
+    assert!(rate_limit.check().is_ok());
+
+    let metrics = Metrics::new();
+    metrics.set_github_rate_limit_remaining("check-token", 42);
+    assert!(metrics
+        .render()
+        .contains("wally_github_rate_limit_remaining{call=\"check-token\"} 42"));
+}
+
+#[test]
+fn github_secondary_rate_limit() {
+    let limited = r#"{"message": "You have exceeded a secondary rate limit. Please wait a few minutes before you try again."}"#;
+    let (url, server) = mock_server_responses(vec![
+        (403, vec![("Retry-After", "30")], String::new()),
+        (403, Vec::new(), limited.to_owned()),
+        (403, Vec::new(), r#"{"message": "Forbidden"}"#.to_owned()),
+    ]);
+
+    let mut config = test_config(
+        AuthMode::GithubOAuth {
+            client_id: String::from("client-id"),
+            client_secret: String::from("client-secret"),
+        },
+        init_test_index_remote().unwrap(),
+    );
+    config.github_api_url = Some(url.join("api/v3/").unwrap());
+    let client = new_client_with_config(config);
+
+    let whoami = || {
+        client
+            .get("/v1/whoami?write=true")
+            .header(Header::new("Authorization", "Bearer gho_token"))
+            .dispatch()
+    };
+
+    // GitHub's own wait is passed on, or a minute if it didn't give one.
+    for retry_after in &["30", "60"] {
+        let response = whoami();
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+        assert_eq!(
+            response.headers().get_one("Retry-After"),
+            Some(*retry_after)
+        );
+
+        let body: serde_json::Value = response.into_json().unwrap();
+        assert_eq!(body["code"], "github_rate_limited");
+    }
+
+    // A 403 that isn't a rate limit is still a refusal.
+    let response = whoami();
+    assert_eq!(response.status(), Status::Unauthorized);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["code"], "github_auth_failed");
+
+    assert_eq!(server.join().unwrap().len(), 3);
+}
+
+#[rocket::async_test]
+async fn github_calls_share_one_client() {
+    use crate::github_client::GithubClient;
+
+    let (url, server) = mock_server(vec![200, 200]);
+    let config = test_config(AuthMode::Unauthenticated, init_test_index_remote().unwrap());
+    let github = GithubClient::new(&config).unwrap();
+
+    github.identity().get(url.clone()).send().await.unwrap();
+    github.permission().get(url).send().await.unwrap();
+
+    let user_agent = format!("user-agent: wally-registry/{}", env!("CARGO_PKG_VERSION"));
+    for (head, _) in server.join().unwrap() {
+        assert!(head.to_ascii_lowercase().contains(&user_agent), "{}", head);
+    }
+}
+
+#[test]
+fn github_collaborator_fallback() {
+    use crate::auth::is_github_collaborator;
+    use reqwest::StatusCode;
+
+    assert!(is_github_collaborator(StatusCode::NO_CONTENT).unwrap());
+    assert!(!is_github_collaborator(StatusCode::NOT_FOUND).unwrap());
+
+    // A token that can't check collaborators is the registry's problem, not
+    // the user's, so it mustn't look like they were denied.
+    for status in &[StatusCode::FORBIDDEN, StatusCode::BAD_GATEWAY] {
+        let err = format!("{:?}", is_github_collaborator(*status).unwrap_err());
+        assert!(err.contains("500"), "{}", err);
+    }
+}
+
+#[test]
+fn email_domains() {
+    use crate::auth::email_has_domain;
+
+    let allowed = vec![String::from("example.com")];
+
+    assert!(email_has_domain("biff@Example.COM", &allowed));
+    assert!(!email_has_domain("biff@mail.example.com", &allowed));
+    assert!(!email_has_domain("biff.example.com", &allowed));
+}
+
+#[test]
+fn unverified_emails_are_not_allowed() {
+    let (github_url, github) = mock_server_responses(vec![
+        (
+            200,
+            Vec::new(),
+            String::from(r#"{ "login": "biff", "id": 1 }"#),
+        ),
+        (
+            200,
+            Vec::new(),
+            String::from(r#"{ "id": 1, "app": { "client_id": "client-id" } }"#),
+        ),
+        (
+            200,
+            Vec::new(),
+            String::from(
+                r#"[
+                    { "email": "biff@example.com", "verified": false },
+                    { "email": "biff@gmail.com", "verified": true }
+                ]"#,
+            ),
+        ),
+    ]);
+
+    let mut config = github_oauth_config();
+    config.github_api_url = Some(github_url);
+    config.allowed_email_domains = Some(vec![String::from("example.com")]);
+    let client = new_client_with_config(config);
+
+    let response = client
+        .get("/v1/whoami?write=true")
+        .header(Header::new("Authorization", "Bearer gho_token"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["code"], "email_not_allowed");
+
+    let requests = github.join().unwrap();
+    assert!(requests[2].0.starts_with("GET /user/emails HTTP/1.1"));
+}
+
+#[test]
+fn gitlab_membership_statuses() {
+    use crate::auth::gitlab_membership_error;
+    use reqwest::StatusCode;
+
+    assert!(gitlab_membership_error(StatusCode::OK).is_none());
+
+    let err = format!(
+        "{:?}",
+        gitlab_membership_error(StatusCode::NOT_FOUND).unwrap()
+    );
+    assert!(err.contains("403"), "{}", err);
+
+    // The user's token already identified them, so being refused here is the
+    // registry's problem and mustn't be read as a membership.
+    for status in &[
+        StatusCode::UNAUTHORIZED,
+        StatusCode::FORBIDDEN,
+        StatusCode::BAD_GATEWAY,
+    ] {
+        let err = format!("{:?}", gitlab_membership_error(*status).unwrap());
+        assert!(err.contains("500"), "{}", err);
+    }
+}
+
+#[test]
+fn github_index_permission_levels() {
+    use crate::auth::{GithubPermission, GithubPermissionInfo};
+
+    let level = |permission: &str, role_name: Option<&str>| {
+        serde_json::from_value::<GithubPermissionInfo>(serde_json::json!({
+            "permission": permission,
+            "role_name": role_name,
+        }))
+        .unwrap()
+        .level()
+    };
+
+    assert_eq!(level("admin", Some("admin")), Some(GithubPermission::Admin));
+    assert_eq!(
+        level("write", Some("maintain")),
+        Some(GithubPermission::Maintain)
+    );
+    assert_eq!(
+        level("read", Some("triage")),
+        Some(GithubPermission::Triage)
+    );
+
+    // Custom roles, and older responses without a role, go by the permission.
+    assert_eq!(
+        level("write", Some("deployer")),
+        Some(GithubPermission::Write)
+    );
+    assert_eq!(level("read", None), Some(GithubPermission::Read));
+    assert_eq!(level("none", None), None);
+
+    assert!(GithubPermission::Admin > GithubPermission::Maintain);
+    assert!(GithubPermission::Maintain > GithubPermission::Write);
+    assert!(GithubPermission::Write > GithubPermission::Triage);
+    assert!(GithubPermission::Triage > GithubPermission::Read);
+
+    let mut config = test_config(AuthMode::Unauthenticated, init_test_index_remote().unwrap());
+    assert_eq!(
+        config.minimum_index_permission(false),
+        GithubPermission::Read
+    );
+    assert_eq!(
+        config.minimum_index_permission(true),
+        GithubPermission::Read
+    );
+
+    config.minimum_index_permission = GithubPermission::Write;
+    config.write_index_permission = Some(GithubPermission::Maintain);
+    assert_eq!(
+        config.minimum_index_permission(false),
+        GithubPermission::Write
+    );
+    assert_eq!(
+        config.minimum_index_permission(true),
+        GithubPermission::Maintain
+    );
+}
+
+#[test]
+fn publish_checks_dependencies() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    publish_versions(&client, "biff/shared", &["1.0.0"]);
+
+    let publish = |builder: PackageBuilder| {
+        client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .body(builder.contents().data())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch()
+    };
+
+    let response = publish(PackageBuilder::new("biff/server@1.0.0").with_realm(Realm::Server));
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = publish(
+        PackageBuilder::new("biff/hello@1.0.0")
+            .with_dep("Shared", "biff/shared@1.0.0")
+            .with_dep("Future", "biff/shared@2.0.0")
+            .with_server_dep("Missing", "biff/missing@0.1.0"),
+    );
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["code"], "unresolvable_dependencies");
+    let message = body["message"].as_str().unwrap();
+    assert!(message.contains("2.0.0"));
+    assert!(message.contains("biff/missing"));
+    // Only the dependency on a version that doesn't exist is listed.
+    assert_eq!(message.matches("biff/shared").count(), 1);
+}
+
+#[test]
+fn publish_checks_dependency_realms() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    publish_versions(&client, "biff/shared", &["1.0.0"]);
+
+    let publish = |builder: PackageBuilder| {
+        client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .body(builder.contents().data())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch()
+    };
+
+    let response = publish(PackageBuilder::new("biff/dev@1.0.0").with_realm(Realm::Dev));
+    assert_eq!(response.status(), Status::BadRequest);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["code"], "invalid_realm");
+
+    let response = publish(PackageBuilder::new("biff/server@1.0.0").with_realm(Realm::Server));
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = publish(
+        PackageBuilder::new("biff/hello@1.0.0")
+            .with_dep("Shared", "biff/shared@1.0.0")
+            .with_dep("Server", "biff/server@1.0.0"),
+    );
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["code"], "cross_realm_dependencies");
+    let message = body["message"].as_str().unwrap();
+    assert!(message.contains("Server (biff/server@1.0.0)"));
+    assert!(!message.contains("biff/shared"));
+
+    // Server dependencies can be from either realm.
+    let response = publish(
+        PackageBuilder::new("biff/hello@1.0.0")
+            .with_dep("Shared", "biff/shared@1.0.0")
+            .with_server_dep("Server", "biff/server@1.0.0"),
+    );
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[test]
+fn publish_allows_cross_realm_dependencies() {
+    let index_url = init_test_index_remote().unwrap();
+    let mut config = test_config(AuthMode::ApiKey("hello".into()), index_url);
+    config.check_dependency_realms = false;
+    let client = new_client_with_config(config);
+
+    let publish = |builder: PackageBuilder| {
+        client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .body(builder.contents().data())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch()
+    };
+
+    let response = publish(PackageBuilder::new("biff/server@1.0.0").with_realm(Realm::Server));
+    assert_eq!(response.status(), Status::Ok);
+
+    let response =
+        publish(PackageBuilder::new("biff/hello@1.0.0").with_dep("Server", "biff/server@1.0.0"));
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[test]
+fn confusable_skeletons() {
+    use crate::confusables::skeleton;
+
+    assert_eq!(skeleton("roblox"), skeleton("r0blox"));
+    assert_eq!(skeleton("modules"), skeleton("rnodules"));
+    assert_eq!(skeleton("foo-bar"), skeleton("foobar"));
+    assert_eq!(skeleton("lib"), skeleton("l1b"));
+    assert_ne!(skeleton("hello"), skeleton("hello2"));
+}
+
+#[test]
+fn publish_rejects_confusable_names() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    publish_versions(&client, "biff/hello", &["1.0.0"]);
+
+    let publish = |id: &str| {
+        client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .body(PackageBuilder::new(id).contents().data())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch()
+    };
+
+    for id in &["biff/he1lo@1.0.0", "biff/hel-lo@1.0.0", "b1ff/hello@1.0.0"] {
+        let response = publish(id);
+        assert_eq!(response.status(), Status::Conflict, "{}", id);
+
+        let body: serde_json::Value = response.into_json().unwrap();
+        assert_eq!(body["code"], "confusable_name");
+        assert!(body["message"].as_str().unwrap().contains("biff/hello"));
+    }
+
+    // New versions of the package itself, and names that don't look alike,
+    // are fine.
+    assert_eq!(publish("biff/hello@1.1.0").status(), Status::Ok);
+    assert_eq!(publish("biff/hello-world@1.0.0").status(), Status::Ok);
+
+    let index_url = init_test_index_remote().unwrap();
+    let mut config = test_config(AuthMode::ApiKey("hello".into()), index_url);
+    config.reject_confusable_names = false;
+    let client = new_client_with_config(config);
+    publish_versions(&client, "biff/hello", &["1.0.0"]);
+    publish_versions(&client, "biff/he1lo", &["1.0.0"]);
+}
+
+#[test]
+fn spdx_license_expressions() {
+    use crate::spdx::is_valid_license;
+
+    assert!(is_valid_license("MIT"));
+    assert!(is_valid_license("mit"));
+    assert!(is_valid_license("MIT OR Apache-2.0"));
+    assert!(is_valid_license("(MIT OR Apache-2.0) AND BSD-3-Clause"));
+    assert!(is_valid_license(
+        "GPL-2.0-or-later WITH Classpath-exception-2.0"
+    ));
+    assert!(is_valid_license("LGPL-2.1+"));
+    assert!(is_valid_license("LicenseRef-my-studio"));
+
+    assert!(!is_valid_license(""));
+    assert!(!is_valid_license("MIT License"));
+    assert!(!is_valid_license("Proprietary"));
+    assert!(!is_valid_license("MIT or Apache-2.0"));
+    assert!(!is_valid_license("MIT OR"));
+    assert!(!is_valid_license("(MIT OR Apache-2.0"));
+    assert!(!is_valid_license("MIT WITH Classpath-exception"));
+    assert!(!is_valid_license("LicenseRef-"));
+}
+
+#[test]
+fn publish_requires_description_and_license() {
+    let index_url = init_test_index_remote().unwrap();
+    let mut config = test_config(AuthMode::ApiKey("hello".into()), index_url);
+    config.require_description = true;
+    config.require_license = true;
+    let client = new_client_with_config(config);
+
+    let publish = |package: PackageBuilder| {
+        let response = client
+            .post("/v1/publish")
+            .header(Accept::JSON)
+            .body(package.contents().data())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch();
+        let status = response.status();
+        let body = response.into_json::<serde_json::Value>().unwrap();
+
+        (status, body["code"].as_str().map(str::to_owned))
+    };
+
+    let rejected = |code: &str| (Status::BadRequest, Some(code.to_owned()));
+
+    assert_eq!(
+        publish(PackageBuilder::new("biff/hello@1.0.0").with_license("MIT")),
+        rejected("missing_description")
+    );
+    assert_eq!(
+        publish(PackageBuilder::new("biff/hello@1.0.0").with_description("   ")),
+        rejected("missing_description")
+    );
+    assert_eq!(
+        publish(PackageBuilder::new("biff/hello@1.0.0").with_description("Says hello")),
+        rejected("missing_license")
+    );
+    assert_eq!(
+        publish(
+            PackageBuilder::new("biff/hello@1.0.0")
+                .with_description("Says hello")
+                .with_license("MIT License")
+        ),
+        rejected("invalid_license")
+    );
+    assert_eq!(
+        publish(
+            PackageBuilder::new("biff/hello@1.0.0")
+                .with_description("Says hello")
+                .with_license("MIT OR Apache-2.0")
+        )
+        .0,
+        Status::Ok
+    );
+}
+
+#[test]
+fn publish_rejects_local_dependencies() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    let contents = PackageBuilder::new("biff/hello@1.0.0")
+        .with_path_dep("Utils", "../utils")
+        .contents();
+
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .body(contents.data())
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["code"], "local_dependency");
+    assert!(body["message"].as_str().unwrap().contains("Utils"));
+}
+
+#[test]
+fn publish_allows_unresolved_dependencies() {
+    let index_url = init_test_index_remote().unwrap();
+    let mut config = test_config(AuthMode::ApiKey("hello".into()), index_url);
+    config.check_dependencies = false;
+    let client = new_client_with_config(config);
+
+    let contents = PackageBuilder::new("biff/hello@1.0.0")
+        .with_dep("Missing", "biff/missing@0.1.0")
+        .contents();
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .body(contents.data())
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[test]
+fn package_metadata_batch() {
+    let index_url = init_test_index_remote().unwrap();
+    let mut config = test_config(AuthMode::ApiKey("hello".into()), index_url);
+    config.max_metadata_batch = 3;
+    let client = new_client_with_config(config);
+    publish_versions(&client, "biff/hello", &["1.0.0", "1.1.0"]);
+
+    let batch = |body: serde_json::Value| {
+        client
+            .post("/v1/metadata-batch")
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", "Bearer hello"))
+            .body(body.to_string())
+            .dispatch()
+    };
+
+    let response = batch(serde_json::json!([
+        { "scope": "biff", "name": "Hello" },
+        { "scope": "biff", "name": "missing" },
+    ]));
+    assert_eq!(response.status(), Status::Ok);
+
+    let body: serde_json::Value = response.into_json().unwrap();
+    let packages = &body["packages"];
+    assert_eq!(
+        packages["biff/hello"]["versions"].as_array().unwrap().len(),
+        2
+    );
+    assert!(packages["biff/missing"].is_null());
+
+    let too_many = serde_json::json!([
+        { "scope": "biff", "name": "a" },
+        { "scope": "biff", "name": "b" },
+        { "scope": "biff", "name": "c" },
+        { "scope": "biff", "name": "d" },
+    ]);
+    let response = batch(too_many);
+    assert_eq!(response.status(), Status::BadRequest);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["code"], "batch_too_large");
+}
+
+#[test]
+fn package_metadata() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    publish_versions(&client, "biff/hello", &["1.0.0"]);
+    publish_versions(&client, "biff/minimal", &["0.1.0"]);
+
+    let contents = PackageBuilder::new("biff/hello@1.1.0")
+        .with_dep("Minimal", "biff/minimal@0.1.0")
+        .contents();
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .body(contents.data())
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = client
+        .get("/v1/package-metadata/biff/hello")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let metadata: serde_json::Value = response.into_json().unwrap();
+    let versions: Vec<_> = metadata["versions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|manifest| manifest["package"]["version"].as_str().unwrap())
+        .collect();
+    assert_eq!(versions, vec!["1.1.0", "1.0.0"]);
+    assert!(metadata["versions"][0]["dependencies"]["Minimal"]
+        .as_str()
+        .unwrap()
+        .starts_with("biff/minimal@"));
+    assert_eq!(metadata["yanked"], serde_json::json!([]));
+
+    Expectation {
+        status: Status::NotFound,
+        content_type: ContentType::JSON,
+    }
+    .assert(
+        client
+            .get("/v1/package-metadata/biff/doesnt-exist")
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch(),
+    );
+}
+
+fn github_retries(max_attempts: u32, budget: u64) -> GithubRetries {
+    GithubRetries {
+        max_attempts,
+        base_delay_ms: 10,
+        budget,
+    }
+}
+
+#[test]
+fn github_retries_transient_errors() {
+    let (url, server) = mock_server(vec![503, 503, 200]);
+    let retry = GithubRetry::new(github_retries(3, 10));
+    let client = reqwest::Client::new();
+
+    let runtime = rocket::tokio::runtime::Runtime::new().unwrap();
+    let response = runtime
+        .block_on(retry.send(|| client.get(url.clone()).send()))
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert_eq!(server.join().unwrap().len(), 3);
+}
+
+#[test]
+fn github_retries_skip_auth_failures() {
+    let (url, server) = mock_server(vec![401]);
+    let retry = GithubRetry::new(github_retries(3, 10));
+    let client = reqwest::Client::new();
+
+    let runtime = rocket::tokio::runtime::Runtime::new().unwrap();
+    let response = runtime
+        .block_on(retry.send(|| client.get(url.clone()).send()))
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+    assert_eq!(server.join().unwrap().len(), 1);
+}
+
+#[test]
+fn github_retries_share_a_budget() {
+    let (url, server) = mock_server(vec![503, 503]);
+    let client = reqwest::Client::new();
+
+    // With no budget left, nothing is retried, however many attempts remain.
+    let retry = GithubRetry::new(github_retries(5, 0));
+
+    let runtime = rocket::tokio::runtime::Runtime::new().unwrap();
+    for _ in 0..2 {
+        let response = runtime
+            .block_on(retry.send(|| client.get(url.clone()).send()))
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+    }
+    assert_eq!(server.join().unwrap().len(), 2);
+}
+
+fn github_app_config(private_key_path: &str) -> GithubAppConfig {
+    GithubAppConfig {
+        app_id: 1234,
+        private_key_path: private_key_path.into(),
+        installation_id: None,
+    }
+}
+
+#[test]
+fn github_app_jwt() {
+    let app = GithubApp::new(&github_app_config("src/tests/github-app/private-key.pem")).unwrap();
+    let jwt = app.jwt().unwrap();
+
+    let public_key = fs_err::read("src/tests/github-app/public-key.pem").unwrap();
+    let claims = jsonwebtoken::decode::<AppClaims>(
+        &jwt,
+        &DecodingKey::from_rsa_pem(&public_key).unwrap(),
+        &Validation::new(Algorithm::RS256),
+    )
+    .unwrap()
+    .claims;
+
+    assert_eq!(claims.iss, "1234");
+    assert!(claims.exp - claims.iat <= 600, "{:?}", claims);
+
+    // Only RSA keys can sign app JWTs.
+    GithubApp::new(&github_app_config("src/tests/github-app/public-key.pem")).unwrap_err();
+}
+
+#[test]
+fn github_app_token_refresh() {
+    let minted = Instant::now();
+    let token = InstallationToken::new(String::from("token"), minted);
+
+    assert!(token.is_fresh(minted));
+    assert!(token.is_fresh(minted + Duration::from_secs(50 * 60)));
+    assert!(!token.is_fresh(minted + Duration::from_secs(56 * 60)));
+    assert!(!token.is_fresh(minted + Duration::from_secs(2 * 60 * 60)));
+}
+
+#[test]
+fn download_stats() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    publish_versions(&client, "biff/hello", &["1.0.0", "1.0.1", "2.0.0"]);
+
+    let download = |version: &str| {
+        let response = client
+            .get(format!("/v1/package-contents/biff/hello/{}", version))
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+    };
+
+    download("1.0.0");
+    download("1.0.0");
+    download("1.0.1");
+
+    // Checking a package with HEAD doesn't download it.
+    let response = client
+        .head("/v1/package-contents/biff/hello/2.0.0")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let expected = serde_json::json!({
+        "package": "biff/hello",
+        "total": 3,
+        "versions": { "1.0.0": 2, "1.0.1": 1, "2.0.0": 0 },
+    });
+
+    // Downloads are counted in the background, so give them a moment.
+    let mut stats = serde_json::Value::Null;
+    for _ in 0..50 {
+        let response = client
+            .get("/v1/stats/biff/hello")
+            .header(Header::new("Authorization", "Bearer hello"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        stats = response.into_json().unwrap();
+
+        if stats == expected {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!(stats, expected);
+
+    let response = client
+        .get("/v1/stats/biff/doesnt-exist")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+fn conditional_get() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    publish_versions(&client, "biff/hello", &["1.0.0"]);
+
+    let get = |url: &str, if_none_match: Option<&str>| {
+        let mut request = client
+            .get(url.to_owned())
+            .header(Header::new("Authorization", "Bearer hello"));
+
+        if let Some(etag) = if_none_match {
+            request = request.header(Header::new("If-None-Match", etag.to_owned()));
+        }
+
+        request.dispatch()
+    };
+
+    for url in &[
+        "/v1/package-contents/biff/hello/1.0.0",
+        "/v1/package-metadata/biff/hello",
+    ] {
+        let response = get(url, None);
+        assert_eq!(response.status(), Status::Ok);
+        let etag = response.headers().get_one("ETag").unwrap().to_owned();
+
+        let response = get(url, Some(&etag));
+        assert_eq!(response.status(), Status::NotModified);
+        assert_eq!(response.headers().get_one("ETag"), Some(etag.as_str()));
+        assert_eq!(response.into_bytes().unwrap_or_default(), Vec::<u8>::new());
+
+        let response = get(url, Some("\"something-else\""));
+        assert_eq!(response.status(), Status::Ok);
+
+        // The client has to be allowed to read the package before it finds
+        // out whether it has changed.
+        let response = client
+            .get(url.to_string())
+            .header(Header::new("If-None-Match", etag))
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    // Publishing a new version changes the metadata.
+    let response = get("/v1/package-metadata/biff/hello", None);
+    let etag = response.headers().get_one("ETag").unwrap().to_owned();
+    publish_versions(&client, "biff/hello", &["1.0.1"]);
+
+    let response = get("/v1/package-metadata/biff/hello", Some(&etag));
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[test]
+fn compressed_responses() {
+    use std::io::Read;
+
+    let mut config = test_config(
+        AuthMode::ApiKey("hello".into()),
+        init_test_index_remote().unwrap(),
+    );
+    config.compression.min_size = 0;
+    let client = new_client_with_config(config);
+    publish_versions(&client, "biff/hello", &["1.0.0"]);
+
+    let get = |url: &str, accept_encoding: &str| {
+        client
+            .get(url.to_owned())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .header(Header::new("Accept-Encoding", accept_encoding.to_owned()))
+            .dispatch()
+    };
+
+    let response = get("/v1/package-metadata/biff/hello", "identity");
+    assert_eq!(response.headers().get_one("Content-Encoding"), None);
+    let etag = response.headers().get_one("ETag").unwrap().to_owned();
+    let uncompressed = response.into_bytes().unwrap();
+
+    let response = get("/v1/package-metadata/biff/hello", "br, gzip");
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("Content-Encoding"), Some("gzip"));
+    assert_eq!(
+        response.headers().get_one("ETag"),
+        Some(format!("W/{}", etag).as_str())
+    );
+    assert!(response
+        .headers()
+        .get_all("Vary")
+        .any(|vary| vary == "Accept-Encoding"));
+
+    let mut decompressed = Vec::new();
+    flate2::read::GzDecoder::new(response.into_bytes().unwrap().as_slice())
+        .read_to_end(&mut decompressed)
+        .unwrap();
+    assert_eq!(decompressed, uncompressed);
+
+    let response = get("/v1/search?q=hello", "deflate");
+    assert_eq!(
+        response.headers().get_one("Content-Encoding"),
+        Some("deflate")
+    );
+
+    // The ETag still belongs to the uncompressed metadata.
+    let response = client
+        .get("/v1/package-metadata/biff/hello")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .header(Header::new("Accept-Encoding", "gzip"))
+        .header(Header::new("If-None-Match", etag))
+        .dispatch();
+    assert_eq!(response.status(), Status::NotModified);
+
+    // Packages are already compressed.
+    let response = get("/v1/package-contents/biff/hello/1.0.0", "gzip");
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("Content-Encoding"), None);
+}
+
+#[test]
+fn msgpack_responses() {
+    let mut config = test_config(
+        AuthMode::ApiKey("hello".into()),
+        init_test_index_remote().unwrap(),
+    );
+    config.compression.min_size = 0;
+    let client = new_client_with_config(config);
+    publish_versions(&client, "biff/hello", &["1.0.0"]);
+
+    let get = |url: &str, accept: &str| {
+        client
+            .get(url.to_owned())
+            .header(Header::new("Authorization", "Bearer hello"))
+            .header(Header::new("Accept", accept.to_owned()))
+            .dispatch()
+    };
+
+    for url in &["/v1/package-metadata/biff/hello", "/v1/search?q=hello"] {
+        let response = get(url, "application/json");
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        let json_etag = response.headers().get_one("ETag").map(str::to_owned);
+        let json: serde_json::Value = response.into_json().unwrap();
+
+        let response = get(url, "application/msgpack, application/json;q=0.5");
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::MsgPack));
+        assert!(response
+            .headers()
+            .get_all("Vary")
+            .any(|vary| vary == "Accept"));
+
+        // The tag depends on the format, so a client switching formats
+        // doesn't get a 304 for a body it can't parse.
+        let msgpack_etag = response.headers().get_one("ETag").map(str::to_owned);
+        if json_etag.is_some() {
+            assert_ne!(msgpack_etag, json_etag, "{}", url);
+        }
+
+        let msgpack: serde_json::Value =
+            rmp_serde::from_slice(&response.into_bytes().unwrap()).unwrap();
+        assert_eq!(msgpack, json, "{}", url);
+    }
+
+    let response = client
+        .post("/v1/metadata-batch")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .header(Header::new("Accept", "application/msgpack"))
+        .header(ContentType::JSON)
+        .body(r#"[{ "scope": "biff", "name": "hello" }]"#)
+        .dispatch();
+    assert_eq!(response.content_type(), Some(ContentType::MsgPack));
+    let batch: serde_json::Value = rmp_serde::from_slice(&response.into_bytes().unwrap()).unwrap();
+    assert!(batch["packages"]["biff/hello"].is_object());
+
+    // MessagePack is compressed like JSON.
+    let response = client
+        .get("/v1/package-metadata/biff/hello")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .header(Header::new("Accept", "application/msgpack"))
+        .header(Header::new("Accept-Encoding", "gzip"))
+        .dispatch();
+    assert_eq!(response.headers().get_one("Content-Encoding"), Some("gzip"));
+}
+
+#[test]
+fn format_negotiation() {
+    let negotiate = Format::negotiate;
+
+    assert_eq!(negotiate("application/msgpack"), Format::MsgPack);
+    assert_eq!(negotiate("application/x-msgpack"), Format::MsgPack);
+    assert_eq!(
+        negotiate("application/msgpack;q=0.9, application/json"),
+        Format::Json
+    );
+    assert_eq!(negotiate("application/msgpack;q=0"), Format::Json);
+    assert_eq!(negotiate("*/*"), Format::Json);
+    assert_eq!(negotiate("text/html, application/msgpack"), Format::MsgPack);
+}
+
+#[test]
+fn small_responses_are_not_compressed() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+
+    let response = client
+        .get("/v1/whoami")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .header(Header::new("Accept-Encoding", "gzip"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("Content-Encoding"), None);
+}
+
+#[test]
+fn negotiate_encodings() {
+    use crate::compression::{negotiate, Encoding};
+
+    let both = [Encoding::Gzip, Encoding::Deflate];
+
+    assert_eq!(negotiate("gzip, deflate, br", &both), Some(Encoding::Gzip));
+    assert_eq!(negotiate("deflate", &both), Some(Encoding::Deflate));
+    assert_eq!(
+        negotiate("gzip;q=0, deflate", &both),
+        Some(Encoding::Deflate)
+    );
+    assert_eq!(negotiate("*", &both), Some(Encoding::Gzip));
+    assert_eq!(negotiate("*, gzip;q=0", &both), Some(Encoding::Deflate));
+    assert_eq!(negotiate("br, identity", &both), None);
+    assert_eq!(negotiate("GZIP", &[Encoding::Deflate]), None);
+}
+
+#[test]
+fn publish_dry_run() {
+    let client = new_client(AuthMode::ApiKey("hello".into()));
+    publish_versions(&client, "biff/hello", &["1.0.0"]);
+
+    let publish = |url: &str, package: &str, token: &str| {
+        let contents = PackageBuilder::new(package).contents();
+        client
+            .post(url.to_owned())
+            .header(Accept::JSON)
+            .body(contents.data())
+            .header(Header::new("Authorization", format!("Bearer {}", token)))
+            .dispatch()
+    };
+
+    let response = publish("/v1/publish?dry-run=true", "biff/hello@1.1.0", "hello");
+    assert_eq!(response.status(), Status::Ok);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["dry-run"], true);
+    assert_eq!(body["package"], "biff/hello@1.1.0");
+
+    // Nothing was written.
+    let response = client
+        .get("/v1/package-contents/biff/hello/1.1.0")
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    // Dry runs fail the same way real publishes do.
+    let response = publish("/v1/publish?dry-run=true", "biff/hello@1.0.0", "hello");
+    assert_eq!(response.status(), Status::Conflict);
+
+    let response = publish(
+        "/v1/publish/biff/hello/1.2.0?dry-run=true",
+        "biff/hello@1.1.0",
+        "hello",
+    );
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let response = publish("/v1/publish?dry-run=true", "biff/hello@1.1.0", "wrong");
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    // The version can still be published for real afterwards.
+    let response = publish("/v1/publish", "biff/hello@1.1.0", "hello");
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[test]
+fn read_key_scopes() {
+    let client = new_client(AuthMode::DoubleApiKey {
+        read: Some("partner key".into()),
+        write: "write key".into(),
+        read_scopes: Some(vec![String::from("biff")]),
+    });
+
+    let contents = PackageBuilder::new("secret/thing@1.0.0").contents();
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .body(contents.data())
+        .header(Header::new("Authorization", "Bearer write key"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let read = |url: &str| {
+        client
+            .get(url.to_owned())
+            .header(Header::new("Authorization", "Bearer partner key"))
+            .dispatch()
+    };
+
+    assert_eq!(
+        read("/v1/package-contents/biff/minimal/0.1.0").status(),
+        Status::Ok
+    );
+
+    for url in &[
+        "/v1/package-contents/secret/thing/1.0.0",
+        "/v1/package-metadata/secret/thing",
+        "/v1/package-integrity/secret/thing/1.0.0",
+        "/v1/stats/secret/thing",
+    ] {
+        assert_eq!(read(url).status(), Status::Forbidden, "{}", url);
+    }
+
+    let response = read("/v1/search?q=thing");
+    assert_eq!(response.status(), Status::Ok);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["total"], 0);
+}
+
+#[test]
+fn scoped_read_tokens() {
+    let mut config = test_config(
+        AuthMode::DoubleApiKey {
+            read: Some("read key".into()),
+            write: "write key".into(),
+            read_scopes: None,
+        },
+        init_test_index_remote().unwrap(),
+    );
+    config.read_tokens = Some(ReadTokenConfig {
+        secret: String::from("token secret"),
+        max_ttl: 3600,
+    });
+    let client = new_client_with_config(config);
+
+    let contents = PackageBuilder::new("secret/thing@1.0.0").contents();
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .body(contents.data())
+        .header(Header::new("Authorization", "Bearer write key"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let issue = |key: &str, body: serde_json::Value| {
+        client
+            .post("/v1/read-tokens")
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", format!("Bearer {}", key)))
+            .body(body.to_string())
+            .dispatch()
+    };
+
+    let response = issue(
+        "write key",
+        serde_json::json!({ "subject": "partner", "scopes": ["Biff"], "ttl": 60 }),
+    );
+    assert_eq!(response.status(), Status::Ok);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["scopes"], serde_json::json!(["biff"]));
+    let token = body["token"].as_str().unwrap().to_owned();
+
+    let read = |token: &str, url: &str| {
+        client
+            .get(url.to_owned())
+            .header(Header::new("Authorization", format!("Bearer {}", token)))
+            .dispatch()
+    };
+
+    assert_eq!(
+        read(&token, "/v1/package-contents/biff/minimal/0.1.0").status(),
+        Status::Ok
+    );
+    assert_eq!(
+        read(&token, "/v1/package-contents/secret/thing/1.0.0").status(),
+        Status::Forbidden
+    );
+
+    let body: serde_json::Value = read(&token, "/v1/whoami").into_json().unwrap();
+    assert_eq!(body["type"], "read-token");
+    assert_eq!(body["subject"], "partner");
+
+    // The registry's own read key keeps working alongside tokens.
+    assert_eq!(
+        read("read key", "/v1/package-contents/secret/thing/1.0.0").status(),
+        Status::Ok
+    );
+
+    let mut expired =
+        ReadTokenClaims::new(String::from("partner"), vec!["biff".into()], 60).unwrap();
+    expired.exp = expired.iat - 10;
+    let response = read(
+        &expired.encode("token secret").unwrap(),
+        "/v1/package-metadata/biff/minimal",
+    );
+    assert_eq!(response.status(), Status::Unauthorized);
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["code"], "read_token_expired");
+
+    let forged = ReadTokenClaims::new(String::from("partner"), vec!["secret".into()], 60)
+        .unwrap()
+        .encode("wrong secret")
+        .unwrap();
+    let response = read(&forged, "/v1/package-metadata/secret/thing");
+    assert_eq!(response.status(), Status::Unauthorized);
+
+    // Read tokens can't be used to issue more of them, and tokens can't
+    // outlive the registry's limit.
+    let request = serde_json::json!({ "subject": "partner", "scopes": ["biff"] });
+    assert_eq!(
+        issue(&token, request.clone()).status(),
+        Status::Unauthorized
+    );
+    assert_eq!(issue("read key", request).status(), Status::Forbidden);
+
+    let request = serde_json::json!({ "subject": "partner", "scopes": ["biff"], "ttl": 7200 });
+    assert_eq!(issue("write key", request).status(), Status::BadRequest);
+}
+
+#[test]
+fn read_scopes_need_read_keys() {
+    let config = test_config(
+        AuthMode::DoubleApiKey {
+            read: None,
+            write: "write key".into(),
+            read_scopes: Some(vec![String::from("biff")]),
+        },
+        init_test_index_remote().unwrap(),
+    );
+
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn slow_requests_time_out() {
+    #[rocket::get("/slow")]
+    async fn slow() -> &'static str {
+        rocket::tokio::time::sleep(Duration::from_secs(10)).await;
+        "finally"
+    }
+
+    #[rocket::get("/fast")]
+    fn fast() -> &'static str {
+        "done"
+    }
+
+    let timeouts = RequestTimeouts { read: 1, write: 60 };
+    let rocket = rocket::build().mount("/", with_timeouts(rocket::routes![slow, fast], timeouts));
+    let client = Client::tracked(rocket).unwrap();
+
+    let started = Instant::now();
+    let response = client.get("/slow").dispatch();
+    assert_eq!(response.status(), Status::GatewayTimeout);
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    let body: serde_json::Value = response.into_json().unwrap();
+    assert_eq!(body["code"], "request_timeout");
+
+    let response = client.get("/fast").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.into_string().unwrap(), "done");
+}
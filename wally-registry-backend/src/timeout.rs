@@ -0,0 +1,70 @@
+//! Gives every request a deadline, so that a slow index operation or a hung
+//! storage backend can't tie up workers forever. Requests that run out of time
+//! are abandoned with a 504.
+//!
+//! Fairings can't stop a handler once it's running, so the deadline is kept by
+//! wrapping the handler of every route instead. Abandoning a request drops
+//! whatever it was waiting on, but blocking work already handed to another
+//! thread, like a Git push, still runs to the end.
+
+use std::time::Duration;
+
+use anyhow::format_err;
+use rocket::http::{Method, Status};
+use rocket::route::{Handler, Outcome, Route};
+use rocket::{Data, Request};
+
+use crate::config::RequestTimeouts;
+use crate::error::ApiErrorStatus;
+
+#[derive(Clone)]
+struct Deadline {
+    handler: Box<dyn Handler>,
+    budget: Duration,
+}
+
+#[rocket::async_trait]
+impl Handler for Deadline {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> Outcome<'r> {
+        match rocket::tokio::time::timeout(self.budget, self.handler.handle(request, data)).await {
+            Ok(outcome) => outcome,
+            Err(_) => {
+                tracing::warn!(
+                    method = %request.method(),
+                    uri = %request.uri(),
+                    budget_secs = self.budget.as_secs(),
+                    "request timed out"
+                );
+
+                let error = format_err!(
+                    "the request took longer than {} seconds, try again later",
+                    self.budget.as_secs()
+                )
+                .status(Status::GatewayTimeout)
+                .code("request_timeout");
+
+                Outcome::from(request, error)
+            }
+        }
+    }
+}
+
+/// Wraps the handler of every route in the deadline for its method: `read`
+/// for GET and HEAD, and `write` for everything else.
+pub fn with_timeouts(routes: Vec<Route>, timeouts: RequestTimeouts) -> Vec<Route> {
+    routes
+        .into_iter()
+        .map(|mut route| {
+            let budget = match route.method {
+                Method::Get | Method::Head => timeouts.read(),
+                _ => timeouts.write(),
+            };
+
+            route.handler = Box::new(Deadline {
+                handler: route.handler,
+                budget,
+            });
+            route
+        })
+        .collect()
+}
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::auth::{GitLabInfo, GithubInfo};
+
+/// Where the token cache gets the time from, so tests can move it forward.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// What GitHub or GitLab told us about a token the last time we checked it.
+#[derive(Clone)]
+pub struct CachedToken {
+    pub user: CachedUser,
+
+    /// The user's permission on the index repository, if it was checked.
+    pub permission: Option<String>,
+}
+
+/// Who a cached token belongs to.
+#[derive(Clone)]
+pub enum CachedUser {
+    Github(GithubInfo),
+    GitLab(GitLabInfo),
+}
+
+/// Remembers recently validated OAuth tokens for a short time, so that bursts
+/// of requests with the same token don't each make several calls to GitHub or GitLab.
+///
+/// Tokens are stored by their hash, never as-is, so lookups don't compare the
+/// token itself. Entries are dropped once they're older than the TTL, which
+/// bounds how long a revoked token keeps working.
+pub struct TokenCache {
+    ttl: Duration,
+    clock: Arc<dyn Clock>,
+    entries: Mutex<HashMap<CacheKey, (Instant, CachedToken)>>,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+struct CacheKey {
+    token_hash: [u8; 32],
+
+    /// Write access does extra checks, so it's never answered from a read.
+    write: bool,
+    index_access_required: bool,
+}
+
+impl TokenCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self::with_clock(ttl, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(ttl: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            ttl,
+            clock,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(
+        &self,
+        token: &str,
+        write: bool,
+        index_access_required: bool,
+    ) -> Option<CachedToken> {
+        let key = CacheKey::new(token, write, index_access_required);
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().ok()?;
+
+        match entries.get(&key) {
+            Some((inserted, cached)) if now.duration_since(*inserted) < self.ttl => {
+                Some(cached.clone())
+            }
+            Some(_) => {
+                entries.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(
+        &self,
+        token: &str,
+        write: bool,
+        index_access_required: bool,
+        cached: CachedToken,
+    ) {
+        if self.ttl.is_zero() {
+            return;
+        }
+
+        let now = self.clock.now();
+
+        // The cache is only an optimization, so if it's broken we just don't
+        // use it.
+        if let Ok(mut entries) = self.entries.lock() {
+            let ttl = self.ttl;
+            entries.retain(|_, (inserted, _)| now.duration_since(*inserted) < ttl);
+            entries.insert(
+                CacheKey::new(token, write, index_access_required),
+                (now, cached),
+            );
+        }
+    }
+}
+
+impl CacheKey {
+    fn new(token: &str, write: bool, index_access_required: bool) -> Self {
+        Self {
+            token_hash: *blake3::hash(token.as_bytes()).as_bytes(),
+            write,
+            index_access_required,
+        }
+    }
+}
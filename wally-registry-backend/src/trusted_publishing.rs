@@ -0,0 +1,282 @@
+//! Publishing from CI without a stored secret. CI providers like GitHub
+//! Actions and GitLab CI give each job an OIDC token, a JWT signed by the
+//! provider that says which repository the job is running for. Tokens are
+//! checked against the signing keys the provider publishes, and
+//! `trusted_publishers` in the config says which repositories can write to
+//! which scopes.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::format_err;
+use jsonwebtoken::{errors::ErrorKind, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use moka::sync::Cache;
+use rocket::http::Status;
+use serde::Deserialize;
+
+use crate::config::{Config, TrustedPublisher};
+use crate::error::{ApiErrorStatus, Error};
+use crate::github_client::GithubClient;
+
+/// Providers rotate their signing keys now and then, announcing new ones
+/// ahead of time, so keys are fetched again every so often.
+const KEYS_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// How long to wait before fetching an issuer's keys again for a key id that
+/// isn't among them. Anyone can send a token with a made-up key id, so they're
+/// only looked for this often.
+const REFETCH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The claims the registry looks at. GitHub Actions calls the repository
+/// `repository`, and GitLab CI calls the project `project_path`.
+#[derive(Debug, Deserialize)]
+struct OidcClaims {
+    sub: String,
+    #[serde(default)]
+    repository: Option<String>,
+    #[serde(default)]
+    project_path: Option<String>,
+    #[serde(default, rename = "ref")]
+    git_ref: Option<String>,
+}
+
+/// The part of a token that's read before its signature can be checked, to
+/// find out whose keys to check it with.
+#[derive(Deserialize)]
+struct UnverifiedClaims {
+    iss: String,
+}
+
+#[derive(Deserialize)]
+struct OpenIdConfiguration {
+    jwks_uri: String,
+}
+
+/// A CI job that presented a valid OIDC token from a trusted repository.
+#[derive(Debug, Clone)]
+pub struct TrustedPublisherInfo {
+    pub issuer: String,
+    pub repository: String,
+
+    /// The token's subject, which says more precisely what the job ran for,
+    /// like `repo:biff/hello:ref:refs/heads/main`.
+    pub subject: String,
+
+    /// The scopes the repository can write to.
+    pub scopes: Vec<String>,
+}
+
+impl TrustedPublisherInfo {
+    pub fn can_write_scope(&self, scope: &str) -> bool {
+        self.scopes
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(scope))
+    }
+}
+
+/// The signing keys of each issuer, by the URL they were fetched from.
+pub struct IssuerKeys {
+    cache: Cache<String, Arc<JwkSet>>,
+
+    /// Where each issuer said its keys are, by the issuer's URL.
+    discovered: Cache<String, String>,
+
+    /// The URLs keys were fetched from in the last `REFETCH_INTERVAL`.
+    recently_fetched: Cache<String, ()>,
+}
+
+impl IssuerKeys {
+    pub fn new() -> Self {
+        Self {
+            cache: Cache::builder().time_to_live(KEYS_TTL).build(),
+            discovered: Cache::builder().time_to_live(KEYS_TTL).build(),
+            recently_fetched: Cache::builder().time_to_live(REFETCH_INTERVAL).build(),
+        }
+    }
+
+    /// Where the publisher's issuer keeps its keys, found through the
+    /// issuer's OpenID configuration unless the publisher says.
+    async fn jwks_url(
+        &self,
+        github: &GithubClient,
+        publisher: &TrustedPublisher,
+    ) -> anyhow::Result<String> {
+        if let Some(jwks_url) = &publisher.jwks_url {
+            return Ok(jwks_url.to_string());
+        }
+
+        let issuer = publisher.issuer.as_str().trim_end_matches('/');
+
+        if let Some(jwks_url) = self.discovered.get(issuer) {
+            return Ok(jwks_url);
+        }
+
+        let configuration: OpenIdConfiguration = github
+            .identity()
+            .get(format!("{}/.well-known/openid-configuration", issuer))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        self.discovered
+            .insert(issuer.to_owned(), configuration.jwks_uri.clone());
+        Ok(configuration.jwks_uri)
+    }
+
+    /// The key with id `kid` from the keys at `jwks_url`. If it isn't there,
+    /// keys are fetched again in case the provider just started using a new
+    /// one, but at most once every `REFETCH_INTERVAL`. In between, unknown key
+    /// ids are rejected from the cache.
+    async fn key(
+        &self,
+        github: &GithubClient,
+        jwks_url: &str,
+        kid: &str,
+    ) -> anyhow::Result<Option<DecodingKey>> {
+        if let Some(keys) = self.cache.get(jwks_url) {
+            if let Some(jwk) = keys.find(kid) {
+                return Ok(Some(DecodingKey::from_jwk(jwk)?));
+            }
+
+            if self.recently_fetched.contains_key(jwks_url) {
+                return Ok(None);
+            }
+        }
+
+        // Marked before fetching, so that a burst of tokens with unknown key
+        // ids doesn't fetch the keys once each.
+        self.recently_fetched.insert(jwks_url.to_owned(), ());
+
+        let keys: JwkSet = github
+            .identity()
+            .get(jwks_url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let key = keys.find(kid).map(DecodingKey::from_jwk).transpose()?;
+
+        self.cache.insert(jwks_url.to_owned(), Arc::new(keys));
+        Ok(key)
+    }
+}
+
+impl Default for IssuerKeys {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether a token looks like a JWT, rather than an API key.
+pub fn is_oidc_token(token: &str) -> bool {
+    jsonwebtoken::decode_header(token).is_ok()
+}
+
+/// Checks an OIDC token's signature, expiry, and `audience`, then which of
+/// the `trusted_publishers` it matches.
+pub async fn verify(
+    config: &Config,
+    github: &GithubClient,
+    keys: &IssuerKeys,
+    audience: &str,
+    token: &str,
+) -> Result<TrustedPublisherInfo, Error> {
+    let invalid = || {
+        format_err!("Invalid OIDC token")
+            .status(Status::Unauthorized)
+            .code("invalid_oidc_token")
+    };
+
+    let header = jsonwebtoken::decode_header(token).map_err(|_| invalid())?;
+    let issuer = unverified_issuer(token).ok_or_else(invalid)?;
+
+    let publishers: Vec<&TrustedPublisher> = config
+        .trusted_publishers
+        .iter()
+        .filter(|publisher| publisher.issuer_matches(&issuer))
+        .collect();
+
+    if publishers.is_empty() {
+        return Err(format_err!(
+            "OIDC tokens from {} aren't trusted by this registry",
+            issuer
+        )
+        .status(Status::Forbidden)
+        .code("untrusted_publisher"));
+    }
+
+    let kid = header.kid.ok_or_else(invalid)?;
+    let key = async {
+        let jwks_url = keys.jwks_url(github, publishers[0]).await?;
+        keys.key(github, &jwks_url, &kid).await
+    };
+
+    let key = match key.await {
+        Ok(Some(key)) => key,
+        Ok(None) => return Err(invalid()),
+        Err(err) => {
+            return Err(err
+                .context(format!("could not fetch the signing keys of {}", issuer))
+                .status(Status::BadGateway)
+                .code("oidc_keys_unavailable"))
+        }
+    };
+
+    // Both GitHub and GitLab sign with RS256. Only allowing it means a token
+    // can't pick a weaker algorithm for itself.
+    let mut validation = Validation::new(Algorithm::RS256);
+    validation.set_audience(&[audience]);
+    validation.set_issuer(&[&issuer]);
+
+    let claims = match jsonwebtoken::decode::<OidcClaims>(token, &key, &validation) {
+        Ok(data) => data.claims,
+        Err(err) if matches!(err.kind(), ErrorKind::ExpiredSignature) => {
+            return Err(format_err!("This OIDC token has expired")
+                .status(Status::Unauthorized)
+                .code("oidc_token_expired"))
+        }
+        Err(_) => return Err(invalid()),
+    };
+
+    let repository = match claims.repository.or(claims.project_path) {
+        Some(repository) => repository,
+        None => return Err(invalid()),
+    };
+
+    let mut scopes = Vec::new();
+
+    for publisher in publishers {
+        if publisher.trusts(&repository, claims.git_ref.as_deref()) {
+            scopes.extend(publisher.scopes.iter().cloned());
+        }
+    }
+
+    if scopes.is_empty() {
+        return Err(format_err!(
+            "the repository {} isn't a trusted publisher, or can't publish from this ref",
+            repository
+        )
+        .status(Status::Forbidden)
+        .code("untrusted_publisher"));
+    }
+
+    Ok(TrustedPublisherInfo {
+        issuer,
+        repository,
+        subject: claims.sub,
+        scopes,
+    })
+}
+
+/// Reads the issuer out of a token without checking it. It's only used to
+/// pick which keys to check the token with, and which publishers it could be.
+fn unverified_issuer(token: &str) -> Option<String> {
+    let payload = token.split('.').nth(1)?;
+    let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+    let claims: UnverifiedClaims = serde_json::from_slice(&payload).ok()?;
+
+    Some(claims.iss)
+}
@@ -0,0 +1,223 @@
+//! Tells other services, like CI or chat bots, when a package is published.
+//!
+//! Each configured URL is sent a JSON description of the publish. Delivery
+//! happens in the background and is retried a few times, so a slow or broken
+//! receiver never holds up the publish itself.
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use hmac::{Hmac, Mac, NewMac};
+use libwally::{
+    http_client::TlsVersion, manifest::Manifest, package_id::PackageId, package_index::OwnerId,
+};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use url::Url;
+
+use crate::auth::WriteAccess;
+
+/// Each webhook is tried this many times before giving up.
+const MAX_ATTEMPTS: u32 = 3;
+
+/// How long to wait before the first retry. Each later retry waits twice as
+/// long as the one before.
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// How long a single delivery attempt can take.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The header carrying the signature of the request body, in the form
+/// `sha256=<hex>`.
+pub const SIGNATURE_HEADER: &str = "Wally-Signature-256";
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct WebhookConfig {
+    /// Where to send events.
+    pub urls: Vec<Url>,
+
+    /// Signs each request body with HMAC-SHA256 so that receivers can check
+    /// it came from this registry.
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PublishEvent {
+    /// Always "publish", so receivers can tell events apart if more are
+    /// added later.
+    pub event: String,
+
+    /// When the package was published, in seconds since the Unix epoch.
+    pub timestamp: u64,
+
+    pub package: PackageId,
+
+    /// Who published the package: a GitHub or GitLab login, or "api-key".
+    pub actor: String,
+
+    /// The id of the user who published the package, if it was a user.
+    pub user_id: Option<OwnerId>,
+
+    /// The manifest of the published package.
+    pub metadata: Manifest,
+}
+
+impl PublishEvent {
+    pub fn new(authorization: &WriteAccess, manifest: &Manifest) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+
+        Self {
+            event: String::from("publish"),
+            timestamp,
+            package: manifest.package_id(),
+            actor: authorization.actor().to_owned(),
+            user_id: authorization.user_id(),
+            metadata: manifest.clone(),
+        }
+    }
+}
+
+pub struct Webhooks {
+    delivery: Option<Arc<Delivery>>,
+}
+
+struct Delivery {
+    urls: Vec<Url>,
+    secret: Option<String>,
+    client: Client,
+    backoff: Duration,
+}
+
+impl Webhooks {
+    /// Webhooks that don't send anything.
+    pub fn disabled() -> Self {
+        Self { delivery: None }
+    }
+
+    pub fn new(config: &WebhookConfig, min_tls_version: TlsVersion) -> anyhow::Result<Self> {
+        Self::with_backoff(config, min_tls_version, RETRY_BACKOFF)
+    }
+
+    pub fn with_backoff(
+        config: &WebhookConfig,
+        min_tls_version: TlsVersion,
+        backoff: Duration,
+    ) -> anyhow::Result<Self> {
+        let client = Client::builder()
+            .min_tls_version(min_tls_version.into())
+            .timeout(DELIVERY_TIMEOUT)
+            .build()
+            .context("could not create HTTP client for webhooks")?;
+
+        Ok(Self {
+            delivery: Some(Arc::new(Delivery {
+                urls: config.urls.clone(),
+                secret: config.secret.clone(),
+                client,
+                backoff,
+            })),
+        })
+    }
+
+    /// Sends `event` to every webhook in the background. Failures are logged
+    /// rather than returned, since the publish has already happened.
+    pub fn notify(&self, event: &PublishEvent) {
+        let delivery = match &self.delivery {
+            Some(delivery) => delivery,
+            None => return,
+        };
+
+        let body = match serde_json::to_vec(event) {
+            Ok(body) => body,
+            Err(err) => {
+                tracing::error!(error = ?err, "could not serialize webhook event");
+                return;
+            }
+        };
+
+        for url in &delivery.urls {
+            let delivery = Arc::clone(delivery);
+            let url = url.clone();
+            let body = body.clone();
+
+            rocket::tokio::spawn(async move {
+                if let Err(err) = delivery.deliver(&url, &body).await {
+                    tracing::warn!(%url, error = ?err, "could not deliver webhook");
+                }
+            });
+        }
+    }
+
+    /// Sends `event` to every webhook and waits for the deliveries to finish,
+    /// returning the result for each URL.
+    #[cfg(test)]
+    pub async fn deliver_all(&self, event: &PublishEvent) -> Vec<anyhow::Result<()>> {
+        let delivery = match &self.delivery {
+            Some(delivery) => delivery,
+            None => return Vec::new(),
+        };
+
+        let body = serde_json::to_vec(event).unwrap();
+        let mut results = Vec::new();
+        for url in &delivery.urls {
+            results.push(delivery.deliver(url, &body).await);
+        }
+
+        results
+    }
+}
+
+impl Delivery {
+    async fn deliver(&self, url: &Url, body: &[u8]) -> anyhow::Result<()> {
+        let mut backoff = self.backoff;
+        let mut attempt = 1;
+
+        loop {
+            match self.send(url, body).await {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt >= MAX_ATTEMPTS => {
+                    return Err(err.context(format!("gave up after {} attempts", attempt)))
+                }
+                Err(_) => {
+                    rocket::tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    async fn send(&self, url: &Url, body: &[u8]) -> anyhow::Result<()> {
+        let mut request = self
+            .client
+            .post(url.clone())
+            .header("Content-Type", "application/json")
+            .header("Wally-Event", "publish")
+            .body(body.to_vec());
+
+        if let Some(secret) = &self.secret {
+            request = request.header(SIGNATURE_HEADER, sign(secret, body));
+        }
+
+        request.send().await?.error_for_status()?;
+
+        Ok(())
+    }
+}
+
+/// The value of the signature header for `body`: an HMAC-SHA256 of the body,
+/// keyed with the webhook secret.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}